@@ -38,10 +38,7 @@
 #include "src/popover.h"
 #include "src/submission.h"
 
-#define LIBFEEDBACK_USE_UNSTABLE_API
-#include <libfeedback.h>
-
-#define SQUEEKBOARD_APP_ID "sm.puri.squeekboard"
+#include <canberra-gtk.h>
 
 typedef struct _EekGtkKeyboardPrivate
 {
@@ -56,9 +53,10 @@ typedef struct _EekGtkKeyboardPrivate
     LevelKeyboard *keyboard; // unowned reference; it's kept in server-context
 
     GdkEventSequence *sequence; // unowned reference
-    LfbEvent *event;
+    GdkEventSequence *chord_sequence; // unowned reference, holds a modifier
 
     gulong kb_signal;
+    guint tick_id; // 0 when no animation runs
 } EekGtkKeyboardPrivate;
 
 G_DEFINE_TYPE_WITH_PRIVATE (EekGtkKeyboard, eek_gtk_keyboard, GTK_TYPE_DRAWING_AREA)
@@ -86,6 +84,13 @@ static void set_allocation_size(EekGtkKeyboard *gtk_keyboard,
         eek_gtk_keyboard_get_instance_private (gtk_keyboard);
     priv->render_geometry = eek_render_geometry_from_allocation_size(
         layout, width, height);
+    // Shells want to know where the rows ended up.
+    if (priv->state_manager) {
+        squeek_layout_report_rows(layout,
+            priv->render_geometry.widget_to_layout, priv->state_manager);
+    }
+    squeek_layout_set_input_region(layout,
+        priv->render_geometry.widget_to_layout, gtk_keyboard);
 }
 
 static gboolean
@@ -137,20 +142,22 @@ eek_gtk_keyboard_real_size_allocate (GtkWidget     *self,
         size_allocate (self, allocation);
 }
 
-static void
-on_event_triggered (LfbEvent      *event,
-                    GAsyncResult  *res,
-                    gpointer      unused)
+/// Redraws on every frame until the layout's animations are over.
+static gboolean on_tick(GtkWidget *widget, GdkFrameClock *clock, gpointer user_data)
 {
-    (void)unused;
-    g_autoptr (GError) err = NULL;
-
-    if (!lfb_event_trigger_feedback_finish (event, res, &err)) {
-        g_warning ("Failed to trigger feedback for '%s': %s",
-                   lfb_event_get_event (event), err->message);
+    (void)clock;
+    (void)user_data;
+    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (EEK_GTK_KEYBOARD (widget));
+    gtk_widget_queue_draw (widget);
+    if (!priv->keyboard || !squeek_layout_is_animating(priv->keyboard->layout)) {
+        priv->tick_id = 0;
+        return G_SOURCE_REMOVE;
     }
+    return G_SOURCE_CONTINUE;
 }
 
+static void update_geometry(EekGtkKeyboard *self);
+
 static void depress(EekGtkKeyboard *self,
                     gdouble x, gdouble y, guint32 time)
 {
@@ -160,7 +167,61 @@ static void depress(EekGtkKeyboard *self,
     }
     squeek_layout_depress(priv->keyboard->layout,
                           priv->submission,
-                          x, y, priv->render_geometry.widget_to_layout, time, self);
+                          x, y, priv->render_geometry.widget_to_layout, time,
+                          priv->popover, priv->state_manager, self);
+    // View switching keys act on press.
+    update_geometry (self);
+    if (!priv->tick_id && squeek_layout_is_animating(priv->keyboard->layout)) {
+        priv->tick_id = gtk_widget_add_tick_callback (GTK_WIDGET (self), on_tick, NULL, NULL);
+    }
+}
+
+/// Views differ in size, so the geometry follows the current view.
+static void update_geometry(EekGtkKeyboard *self)
+{
+    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
+    GtkAllocation allocation;
+    gtk_widget_get_allocation (GTK_WIDGET (self), &allocation);
+    set_allocation_size (self, priv->keyboard->layout,
+        allocation.width, allocation.height);
+}
+
+/// Switches the view from outside of the keyboard, like from the popover.
+void eek_gtk_keyboard_set_view(EekGtkKeyboard *self, const char *view)
+{
+    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
+    if (!priv->keyboard) {
+        return;
+    }
+    squeek_layout_set_view(priv->keyboard->layout, view, priv->state_manager);
+    update_geometry (self);
+    gtk_widget_queue_draw (GTK_WIDGET (self));
+}
+
+/// Touches outside of the bounds go to the surface below.
+/// Without bounds, the whole keyboard takes them.
+void eek_gtk_keyboard_set_input_region(EekGtkKeyboard *self, const EekBounds *bounds, guint count)
+{
+    GtkWidget *toplevel = gtk_widget_get_toplevel (GTK_WIDGET (self));
+    if (!gtk_widget_is_toplevel (toplevel)) {
+        return;
+    }
+    if (!bounds) {
+        gtk_widget_input_shape_combine_region (toplevel, NULL);
+        return;
+    }
+    cairo_region_t *region = cairo_region_create ();
+    for (guint i = 0; i < count; i++) {
+        cairo_rectangle_int_t rect = {
+            .x = floor (bounds[i].x),
+            .y = floor (bounds[i].y),
+            .width = ceil (bounds[i].x + bounds[i].width) - floor (bounds[i].x),
+            .height = ceil (bounds[i].y + bounds[i].height) - floor (bounds[i].y),
+        };
+        cairo_region_union_rectangle (region, &rect);
+    }
+    gtk_widget_input_shape_combine_region (toplevel, region);
+    cairo_region_destroy (region);
 }
 
 static void drag(EekGtkKeyboard *self,
@@ -174,6 +235,8 @@ static void drag(EekGtkKeyboard *self,
                        priv->submission,
                        x, y, priv->render_geometry.widget_to_layout, time,
                        priv->popover, priv->state_manager, self);
+    // Sliding off a key releases it, which may switch the view.
+    update_geometry (self);
 }
 
 static void release(EekGtkKeyboard *self, guint32 time)
@@ -185,6 +248,17 @@ static void release(EekGtkKeyboard *self, guint32 time)
     squeek_layout_release(eekboard_context_service_get_keyboard(priv->eekboard_context)->layout,
                           priv->submission, priv->render_geometry.widget_to_layout, time,
                           priv->popover, priv->state_manager, self);
+    update_geometry (self);
+}
+
+static void end_chord(EekGtkKeyboard *self, guint32 time)
+{
+    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
+    priv->chord_sequence = NULL;
+    if (!priv->keyboard) {
+        return;
+    }
+    squeek_layout_end_chord(priv->keyboard->layout, priv->submission, time, self);
 }
 
 static gboolean
@@ -231,7 +305,32 @@ eek_gtk_keyboard_real_motion_notify_event (GtkWidget      *self,
     return TRUE;
 }
 
-// Only one touch stream at a time allowed. Others will be completely ignored.
+/// In floating layouts, every touch presses keys of its own,
+/// so that buttons of a game controller work together.
+static gboolean
+handle_floating_touch_event (EekGtkKeyboard *self,
+                             GdkEventTouch  *event)
+{
+    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
+    struct squeek_layout *layout = priv->keyboard->layout;
+
+    if (event->type == GDK_TOUCH_BEGIN || event->type == GDK_TOUCH_UPDATE) {
+        squeek_layout_move_touch(layout, priv->submission, event->sequence,
+                                 event->x, event->y,
+                                 priv->render_geometry.widget_to_layout,
+                                 event->time, priv->popover,
+                                 priv->state_manager, self);
+    } else if (event->type == GDK_TOUCH_END || event->type == GDK_TOUCH_CANCEL) {
+        squeek_layout_release_touch(layout, priv->submission, event->sequence,
+                                    priv->render_geometry.widget_to_layout,
+                                    event->time, priv->popover,
+                                    priv->state_manager, self);
+    }
+    return TRUE;
+}
+
+// Only one touch stream at a time allowed. Others will be completely ignored,
+// except for one holding down a modifier.
 static gboolean
 handle_touch_event (GtkWidget     *widget,
                     GdkEventTouch *event)
@@ -239,10 +338,20 @@ handle_touch_event (GtkWidget     *widget,
     EekGtkKeyboard        *self = EEK_GTK_KEYBOARD (widget);
     EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (self);
 
+    if (priv->keyboard && squeek_layout_is_floating(priv->keyboard->layout)) {
+        return handle_floating_touch_event (self, event);
+    }
+
     /* For each new touch, release the previous one and record the new event
-       sequence. */
+       sequence. A modifier held by the previous touch stays down instead. */
     if (event->type == GDK_TOUCH_BEGIN) {
-        release(self, event->time);
+        if (priv->sequence && !priv->chord_sequence && priv->keyboard
+                && squeek_layout_begin_chord(priv->keyboard->layout,
+                                             priv->submission, event->time)) {
+            priv->chord_sequence = priv->sequence;
+        } else {
+            release(self, event->time);
+        }
         priv->sequence = event->sequence;
         depress(self, event->x, event->y, event->time);
         return TRUE;
@@ -259,6 +368,8 @@ handle_touch_event (GtkWidget     *widget,
         if (event->sequence == priv->sequence) {
             release(self, event->time);
             priv->sequence = NULL;
+        } else if (event->sequence == priv->chord_sequence) {
+            end_chord(self, event->time);
         }
     }
     return TRUE;
@@ -276,6 +387,7 @@ eek_gtk_keyboard_real_unmap (GtkWidget *self)
             priv->submission,
             gdk_event_get_time(NULL));
     }
+    priv->chord_sequence = NULL;
 
     GTK_WIDGET_CLASS (eek_gtk_keyboard_parent_class)->unmap (self);
 }
@@ -307,6 +419,11 @@ eek_gtk_keyboard_dispose (GObject *object)
         priv->kb_signal = 0;
     }
 
+    if (priv->tick_id != 0) {
+        gtk_widget_remove_tick_callback (GTK_WIDGET (self), priv->tick_id);
+        priv->tick_id = 0;
+    }
+
     if (priv->renderer) {
         eek_renderer_free(priv->renderer);
         priv->renderer = NULL;
@@ -320,11 +437,6 @@ eek_gtk_keyboard_dispose (GObject *object)
         priv->keyboard = NULL;
     }
 
-    if (priv->event) {
-        g_clear_object (&priv->event);
-        lfb_uninit ();
-    }
-
     G_OBJECT_CLASS (eek_gtk_keyboard_parent_class)->dispose (object);
 }
 
@@ -356,15 +468,7 @@ eek_gtk_keyboard_class_init (EekGtkKeyboardClass *klass)
 static void
 eek_gtk_keyboard_init (EekGtkKeyboard *self)
 {
-    EekGtkKeyboardPrivate *priv = eek_gtk_keyboard_get_instance_private (EEK_GTK_KEYBOARD (self));
-    g_autoptr(GError) err = NULL;
-
-    if (lfb_init(SQUEEKBOARD_APP_ID, &err)) {
-        priv->event = lfb_event_new ("button-pressed");
-    } else {
-        g_warning ("Failed to init libfeedback: %s", err->message);
-    }
-
+    (void)self;
     GtkIconTheme *theme = gtk_icon_theme_get_default ();
 
     gtk_icon_theme_add_resource_path (theme, "/sm/puri/squeekboard/icons");
@@ -436,22 +540,39 @@ eek_gtk_keyboard_new (EekboardContextService *eekservice,
 }
 
 /**
- * eek_gtk_keyboard_emit_feedback:
+ * eek_gtk_keyboard_play_sound:
+ * @event_id: name of the sound in the freedesktop sound theme
+ * @description: human-readable description of the event
+ * @volume: in decibels, as understood by libcanberra
  *
- * Emit button press haptic feedback via libfeedack.
+ * Play a key press sound, unless event sounds are off in the desktop settings.
  */
 void
-eek_gtk_keyboard_emit_feedback (EekGtkKeyboard *self)
+eek_gtk_keyboard_play_sound (EekGtkKeyboard *self,
+                             const char     *event_id,
+                             const char     *description,
+                             const char     *volume)
 {
-    EekGtkKeyboardPrivate *priv;
+    gboolean enabled = TRUE;
+    int ret;
 
     g_return_if_fail (EEK_IS_GTK_KEYBOARD (self));
 
-    priv = eek_gtk_keyboard_get_instance_private (EEK_GTK_KEYBOARD (self));
-    if (priv->event) {
-        lfb_event_trigger_feedback_async (priv->event,
-                                          NULL,
-                                          (GAsyncReadyCallback)on_event_triggered,
-                                          NULL);
+    g_object_get (gtk_widget_get_settings (GTK_WIDGET (self)),
+                  "gtk-enable-event-sounds", &enabled,
+                  NULL);
+    if (!enabled) {
+        return;
+    }
+
+    ret = ca_gtk_play_for_widget (GTK_WIDGET (self), 0,
+                                  CA_PROP_EVENT_ID, event_id,
+                                  CA_PROP_EVENT_DESCRIPTION, description,
+                                  CA_PROP_CANBERRA_VOLUME, volume,
+                                  CA_PROP_CANBERRA_CACHE_CONTROL, "permanent",
+                                  NULL);
+    // Themes without the sound are fine, and there's one per press.
+    if (ret != CA_SUCCESS) {
+        g_debug ("Failed to play sound '%s': %s", event_id, ca_strerror (ret));
     }
 }
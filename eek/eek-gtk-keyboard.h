@@ -51,7 +51,9 @@ struct _EekGtkKeyboardClass
 };
 
 GtkWidget *eek_gtk_keyboard_new       (EekboardContextService *eekservice, struct submission *submission, struct squeek_state_manager *state_manager, struct squeek_popover *popover);
-void       eek_gtk_keyboard_emit_feedback (EekGtkKeyboard *self);
+void       eek_gtk_keyboard_play_sound (EekGtkKeyboard *self, const char *event_id, const char *description, const char *volume);
+void       eek_gtk_keyboard_set_view (EekGtkKeyboard *self, const char *view);
+void       eek_gtk_keyboard_set_input_region (EekGtkKeyboard *self, const EekBounds *bounds, guint count);
 
 G_END_DECLS
 #endif  /* EEK_GTK_KEYBOARD_H */
@@ -33,10 +33,32 @@
 static void render_button_label (cairo_t *cr, GtkStyleContext *ctx,
                                                 const gchar *label, EekBounds bounds);
 
+/* Radii go clockwise from the top left corner. */
+static void
+clip_rounded (cairo_t *cr, EekBounds bounds, const double *radii)
+{
+    double max = MIN (bounds.width, bounds.height) / 2;
+    double r[4];
+    for (int i = 0; i < 4; i++) {
+        r[i] = CLAMP (radii[i], 0, max);
+    }
+    double right = bounds.x + bounds.width;
+    double bottom = bounds.y + bounds.height;
+    cairo_new_sub_path (cr);
+    cairo_arc (cr, right - r[1], bounds.y + r[1], r[1], -M_PI / 2, 0);
+    cairo_arc (cr, right - r[2], bottom - r[2], r[2], 0, M_PI / 2);
+    cairo_arc (cr, bounds.x + r[3], bottom - r[3], r[3], M_PI / 2, M_PI);
+    cairo_arc (cr, bounds.x + r[0], bounds.y + r[0], r[0], M_PI, 3 * M_PI / 2);
+    cairo_close_path (cr);
+    cairo_clip (cr);
+}
+
+/* The radii come from the layout, and win over the theme's corners. */
 static void
 render_outline (cairo_t     *cr,
                 GtkStyleContext *ctx,
-                EekBounds bounds)
+                EekBounds bounds,
+                const double *radii)
 {
     GtkBorder margin, border;
     gtk_style_context_get_margin(ctx, GTK_STATE_FLAG_NORMAL, &margin);
@@ -50,10 +72,17 @@ render_outline (cairo_t     *cr,
         .width = bounds.width - x - (margin.right + border.right),
         .height = bounds.height - y - (margin.bottom + border.bottom),
     };
+    if (radii) {
+        cairo_save (cr);
+        clip_rounded (cr, position, radii);
+    }
     gtk_render_background (ctx, cr,
         position.x, position.y, position.width, position.height);
     gtk_render_frame (ctx, cr,
         position.x, position.y, position.width, position.height);
+    if (radii) {
+        cairo_restore (cr);
+    }
 }
 
 float get_scale(cairo_t *cr) {
@@ -69,12 +98,13 @@ void eek_render_button_in_context(uint32_t scale_factor,
                                      GtkStyleContext *ctx,
                                      EekBounds bounds,
                                      const char *icon_name,
-                                     const gchar *label) {
+                                     const gchar *label,
+                                     const double *radii) {
     /* blank background */
     cairo_set_source_rgba (cr, 0.0, 0.0, 0.0, 0.0);
     cairo_paint (cr);
 
-    render_outline (cr, ctx, bounds);
+    render_outline (cr, ctx, bounds, radii);
     cairo_paint (cr);
 
     /* render icon (if any) */
@@ -211,11 +241,21 @@ eek_renderer_render_keyboard (EekRenderer *self,
     g_return_if_fail (geometry.allocation_width > 0.0);
     g_return_if_fail (geometry.allocation_height > 0.0);
 
-    /* Paint the background covering the entire widget area */
-    gtk_render_background (self->view_context,
-                           cr,
-                           0, 0,
-                           geometry.allocation_width, geometry.allocation_height);
+    gboolean floating = squeek_layout_is_floating (keyboard->layout);
+    if (floating) {
+        /* The application stays visible between the buttons */
+        cairo_save (cr);
+        cairo_set_operator (cr, CAIRO_OPERATOR_CLEAR);
+        cairo_paint (cr);
+        cairo_restore (cr);
+        cairo_push_group (cr);
+    } else {
+        /* Paint the background covering the entire widget area */
+        gtk_render_background (self->view_context,
+                               cr,
+                               0, 0,
+                               geometry.allocation_width, geometry.allocation_height);
+    }
 
     cairo_save(cr);
     cairo_translate (cr, geometry.widget_to_layout.origin_x, geometry.widget_to_layout.origin_y);
@@ -224,6 +264,11 @@ eek_renderer_render_keyboard (EekRenderer *self,
     squeek_draw_layout_base_view(keyboard->layout, self, cr);
     squeek_layout_draw_all_changed(keyboard->layout, self, cr, submission);
     cairo_restore (cr);
+
+    if (floating) {
+        cairo_pop_group_to_source (cr);
+        cairo_paint_with_alpha (cr, squeek_layout_get_opacity (keyboard->layout));
+    }
 }
 
 void
@@ -332,8 +377,15 @@ eek_renderer_new (LevelKeyboard  *keyboard,
     renderer->view_context = gtk_style_context_new();
     gtk_style_context_set_path(renderer->view_context, path);
     gtk_widget_path_unref(path);
-    if (squeek_layout_get_kind(keyboard->layout) == ARRANGEMENT_KIND_WIDE) {
+    switch (squeek_layout_get_kind(keyboard->layout)) {
+    case ARRANGEMENT_KIND_WIDE:
         gtk_style_context_add_class(renderer->view_context, "wide");
+        break;
+    case ARRANGEMENT_KIND_COMPACT:
+        gtk_style_context_add_class(renderer->view_context, "compact");
+        break;
+    default:
+        break;
     }
     gtk_style_context_add_class(renderer->view_context, (char*)&keyboard->style_name);
     gtk_style_context_add_provider (renderer->view_context,
@@ -343,8 +395,15 @@ eek_renderer_new (LevelKeyboard  *keyboard,
     /* Create a style context for the buttons */
     path = gtk_widget_path_new();
     gtk_widget_path_append_type(path, view_type());
-    if (squeek_layout_get_kind(keyboard->layout) == ARRANGEMENT_KIND_WIDE) {
+    switch (squeek_layout_get_kind(keyboard->layout)) {
+    case ARRANGEMENT_KIND_WIDE:
         gtk_widget_path_iter_add_class(path, -1, "wide");
+        break;
+    case ARRANGEMENT_KIND_COMPACT:
+        gtk_widget_path_iter_add_class(path, -1, "compact");
+        break;
+    default:
+        break;
     }
     /* Add style classes based on purpose */
     switch (squeek_layout_get_purpose (keyboard->layout)) {
@@ -130,6 +130,12 @@ void eekboard_context_service_set_layout(EekboardContextService *context, char *
     LevelKeyboard *keyboard = level_keyboard_new(style_name, layout);
     // set as current
     LevelKeyboard *previous_keyboard = context->keyboard;
+    // Release whatever is still held down before the keymap changes,
+    // otherwise the releases would go to the wrong layout.
+    if (previous_keyboard && context->submission) {
+        squeek_layout_hand_over(previous_keyboard->layout, keyboard->layout,
+                                context->submission, timestamp);
+    }
     context->keyboard = keyboard;
     // Update the keymap if necessary.
     // TODO: Update submission on change event
@@ -0,0 +1,15 @@
+extern crate rs;
+
+use rs::golden::check_builtin_layouts;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+fn main() -> () {
+    let dir = PathBuf::from(env::args().nth(1).expect("No golden directory given"));
+    let bless = env::var_os("SQUEEKBOARD_BLESS").is_some();
+    if !check_builtin_layouts(&dir, bless) {
+        eprintln!("Layouts differ from golden files, or have none. Set SQUEEKBOARD_BLESS=1 to accept the changes.");
+        process::exit(1);
+    }
+}
@@ -2,15 +2,20 @@
 
 use std::ffi::CString;
 
+use ::morse;
+
+// traits
+use serde::{ Deserialize, Serialize };
+
 /// Name of the keysym
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeySym(pub String);
 
 /// Use to switch views
 type View = String;
 
 /// Use to send modified keypresses
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Modifier {
     /// Control and Alt are the only modifiers
     /// which doesn't interfere with levels,
@@ -18,10 +23,13 @@ pub enum Modifier {
     Control,
     Alt,
     Mod4,
+    /// Turns cursor movement into selection.
+    /// Letters are entered by switching views, not with this.
+    Shift,
 }
 
 /// Action to perform on the keypress and, in reverse, on keyrelease
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Action {
     /// Switch to this view
     SetView(View),
@@ -42,13 +50,68 @@ pub enum Action {
     Submit {
         /// Text to submit with input-method.
         /// If None, then keys are to be submitted instead.
+        #[serde(with = "::util::cstring::option")]
         text: Option<CString>,
         /// The key events this symbol submits when submitting text is not possible
         keys: Vec<KeySym>,
     },
+    /// Submit text generated from the template on release.
+    /// Needs the input method.
+    SubmitTemplate(String),
+    /// Press keys while holding modifiers, like Ctrl+C
+    Shortcut {
+        modifiers: Vec<Modifier>,
+        keys: Vec<KeySym>,
+    },
     /// Erase a position behind the cursor
     Erase,
     ShowPreferences,
+    /// Show a layout on top of the current one, like emoji
+    ShowOverlay(String),
+    /// Start or stop entering text by speaking
+    VoiceInput,
+    /// Draw text to be recognized, instead of pressing
+    Handwriting,
+    /// Replace the recognized text with the next guess
+    NextCandidate,
+    /// Replace the misspelled word with the first correction
+    AcceptCorrection,
+    /// Show symbols whose names match the word before the cursor
+    SearchSymbols,
+    /// Commit the result of the calculator's expression
+    InsertResult,
+    /// Capture the screen through the desktop portal
+    Screenshot,
+    /// A key of the stenotype, like "S-".
+    /// The stroke gets typed when all keys are released.
+    Steno(String),
+    /// Part of a Morse code sequence, typed after a pause
+    Morse(morse::Signal),
+    /// A dot of a Braille cell, 1 to 6, or 0 for the blank cell.
+    /// The cell gets typed when all dots are released.
+    Braille(u8),
+    /// A key standing for any of its letters, like "abc".
+    /// The word list decides which word the presses mean.
+    T9(String),
+    /// Replace the word guessed from the letter groups with the next guess
+    T9Next,
+    /// Continue a compose sequence with its last keysym.
+    /// If the sequence is complete, the result gets committed.
+    ComposeStep {
+        sequence: Vec<String>,
+        #[serde(with = "::util::cstring::option")]
+        result: Option<CString>,
+    },
+    /// Leave the compose picker
+    ComposeCancel,
+    /// Ignore touches for a while, to let the screen get wiped
+    StartCleaning,
+    /// Draw the usage statistics, showing the next chart when pressed
+    Statistics,
+    /// Return in a text field which sends messages.
+    /// Tapped, it presses Return on release. Held, it types a new line.
+    /// Only made from Return keys when the layout gets loaded.
+    SendOrNewline,
 }
 
 impl Action {
@@ -68,6 +131,23 @@ impl Action {
             _ => false,
         }
     }
+    pub fn switches_view(&self) -> bool {
+        match self {
+            Action::SetView(_) | Action::LockView { .. } => true,
+            _ => false,
+        }
+    }
+    /// Submits Return, whatever the text field makes of it
+    pub fn is_return(&self) -> bool {
+        match self {
+            Action::Submit { text: None, keys } => match keys.as_slice() {
+                [KeySym(name)] => name == "Return",
+                _ => false,
+            },
+            Action::SendOrNewline => true,
+            _ => false,
+        }
+    }
     pub fn is_active(&self, view_name: &str) -> bool {
         match self {
             Action::SetView(view) => view == view_name,
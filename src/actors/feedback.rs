@@ -0,0 +1,139 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Haptic feedback, by feedbackd.
+ *
+ * Events go to org.sigxcpu.Feedback on the session bus,
+ * from a thread of their own, so that a slow call doesn't delay typing.
+ * The "quiet" profile is requested for all of them,
+ * because key sounds come from the sound theme instead.
+ *
+ * Without the service, events get dropped,
+ * and another connection is tried only after a while.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{ Duration, Instant };
+use zbus::Connection;
+
+use crate::logging;
+
+// Traits
+use crate::logging::Warn;
+
+
+const APP_ID: &str = "sm.puri.squeekboard";
+const DESTINATION: &str = "org.sigxcpu.Feedback";
+const PATH: &str = "/org/sigxcpu/Feedback";
+/// Between attempts to reach a missing service
+const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Events from the feedbackd event naming spec
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Press,
+    /// The press can't do what the key is meant to
+    Error,
+}
+
+impl Event {
+    fn get_name(&self) -> &'static str {
+        match self {
+            Event::Press => "button-pressed",
+            Event::Error => "dialog-error",
+        }
+    }
+}
+
+type Error = Box<dyn std::error::Error>;
+
+fn trigger_on(connection: &Connection, event: Event) -> Result<(), Error> {
+    let mut hints: HashMap<&str, zvariant::Value> = HashMap::new();
+    hints.insert("profile", "quiet".into());
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(DESTINATION),
+        "TriggerFeedback",
+        &(
+            APP_ID,
+            event.get_name(),
+            hints,
+            -1i32, // as long as the theme says
+        ),
+    )?;
+    Ok(())
+}
+
+/// The actor's side
+struct Link {
+    connection: Option<Connection>,
+    /// When the service was last found missing
+    failed_at: Option<Instant>,
+    /// Complaining once is enough.
+    reported: bool,
+}
+
+impl Link {
+    fn trigger(&mut self, event: Event, now: Instant) {
+        if let Some(failed_at) = self.failed_at {
+            if now.saturating_duration_since(failed_at) < RETRY_AFTER {
+                return;
+            }
+        }
+        let result = match &self.connection {
+            Some(connection) => trigger_on(connection, event),
+            None => Connection::new_session()
+                .map_err(Error::from)
+                .and_then(|connection| {
+                    let result = trigger_on(&connection, event);
+                    self.connection = Some(connection);
+                    result
+                }),
+        };
+        match result {
+            Ok(()) => self.failed_at = None,
+            Err(e) => {
+                self.connection = None;
+                self.failed_at = Some(now);
+                if !self.reported {
+                    self.reported = true;
+                    log_print!(
+                        logging::Level::Surprise,
+                        "No haptic feedback, feedbackd unavailable: {}", e,
+                    );
+                }
+            },
+        }
+    }
+}
+
+fn run(receiver: mpsc::Receiver<Event>) {
+    let mut link = Link { connection: None, failed_at: None, reported: false };
+    for event in receiver {
+        link.trigger(event, Instant::now());
+    }
+}
+
+thread_local! {
+    /// Started on the first event
+    static ACTOR: RefCell<Option<mpsc::Sender<Event>>> = RefCell::new(None);
+}
+
+/// Doesn't wait for the buzz.
+pub fn trigger(event: Event) {
+    ACTOR.with(|actor| {
+        let mut actor = actor.borrow_mut();
+        let sender = actor.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || run(receiver));
+            sender
+        });
+        sender.send(event)
+            .or_print(logging::Problem::Bug, "Haptic feedback thread is gone");
+    });
+}
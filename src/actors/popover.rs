@@ -10,8 +10,18 @@ but it cannot get the user-selected overlay, because it's stored in state.
 
 To solve this, overlay will be cached in the popover actor,
 and updated by main state every time it changes.
+
+The contents of the menu are decided here, in `Menu`,
+without touching GTK, so that switching layouts can be tested headlessly.
+Only drawing the popover and reading system settings remain in `crate::popover`.
 */
 
+use crate::layout::MenuEntry;
+use crate::layout::c::{ Bounds, EekGtkKeyboard };
+use crate::popover;
+use crate::popover::LayoutId;
+use crate::receiver;
+
 pub mod c {
     use super::*;
     use crate::util::c::Wrapped;
@@ -37,4 +47,132 @@ pub fn set_overlay(
     let actor = actor.clone_ref();
     let mut actor = actor.borrow_mut();
     actor.overlay = overlay;
-}
\ No newline at end of file
+}
+
+/// What the popover offers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu {
+    /// Display names and layouts, in the order of preference
+    pub system_layouts: Vec<(String, LayoutId)>,
+    /// Built into Squeekboard
+    pub overlays: Vec<LayoutId>,
+    /// From the current layout
+    pub entries: Vec<MenuEntry>,
+    /// Shown as selected
+    pub current: Option<LayoutId>,
+}
+
+impl Menu {
+    /// Takes into account first any overlays, then system layouts from the list
+    pub fn new(
+        state: &State,
+        system_layouts: Vec<(String, LayoutId)>,
+        overlay_names: Vec<String>,
+        entries: Vec<MenuEntry>,
+    ) -> Self {
+        let current = match &state.overlay {
+            Some(name) => Some(LayoutId::Local(name.clone())),
+            None => system_layouts.get(0).map(|(_, id)| id.clone()),
+        };
+        Menu {
+            system_layouts,
+            overlays: overlay_names.into_iter().map(LayoutId::Local).collect(),
+            entries,
+            current,
+        }
+    }
+
+    pub fn get_current_name(&self) -> Option<String> {
+        self.current.as_ref().map(|id| id.get_name().into())
+    }
+
+    /// System layouts take precedence over overlays with the same name.
+    pub fn find_layout(&self, name: &str) -> Option<&LayoutId> {
+        self.system_layouts.iter().map(|(_, id)| id)
+            .chain(self.overlays.iter())
+            .find(|id| id.get_name() == name)
+    }
+}
+
+/// Opens the popover pointing at `bounds`, in widget coordinates.
+pub fn show_at(
+    keyboard: EekGtkKeyboard,
+    bounds: Bounds,
+    actor: &State,
+    app_state: receiver::State,
+    entries: &[MenuEntry],
+) {
+    let menu = Menu::new(
+        actor,
+        popover::get_system_layouts(),
+        popover::get_overlay_names(),
+        entries.to_vec(),
+    );
+    popover::present(keyboard, bounds, menu, app_state);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Instant;
+    use crate::action::Action;
+    use crate::state;
+
+    fn system(name: &str) -> (String, LayoutId) {
+        (
+            name.to_uppercase(),
+            LayoutId::System { kind: "xkb".into(), name: name.into() },
+        )
+    }
+
+    fn make_menu(overlay: Option<&str>) -> Menu {
+        Menu::new(
+            &State { overlay: overlay.map(String::from) },
+            vec![system("us"), system("de")],
+            vec!["emoji".into(), "terminal".into()],
+            vec![MenuEntry {
+                label: "Numbers".into(),
+                action: Action::SetView("numbers".into()),
+            }],
+        )
+    }
+
+    #[test]
+    fn current_layout() {
+        assert_eq!(make_menu(None).get_current_name(), Some("us".into()));
+        assert_eq!(
+            make_menu(Some("emoji")).get_current_name(),
+            Some("emoji".into()),
+        );
+        let empty = Menu::new(&State::new(), Vec::new(), Vec::new(), Vec::new());
+        assert_eq!(empty.get_current_name(), None);
+    }
+
+    /// Choosing an overlay and then a system layout in the menu
+    #[test]
+    fn switch_layouts() {
+        let start = Instant::now();
+        let menu = make_menu(None);
+        let emoji = menu.find_layout("emoji").unwrap().clone();
+        assert_eq!(emoji, LayoutId::Local("emoji".into()));
+        let app = state::test::application_with_fake_output(start)
+            .apply_event(state::Event::OverlayChanged(emoji.clone()), start);
+        assert_eq!(app.overlay_layout, Some(emoji));
+
+        // The main state tells the actor, and the next menu shows the overlay
+        let actor = State { overlay: Some("emoji".into()) };
+        let menu = Menu::new(
+            &actor,
+            menu.system_layouts.clone(),
+            vec!["emoji".into()],
+            Vec::new(),
+        );
+        assert_eq!(menu.get_current_name(), Some("emoji".into()));
+        assert_eq!(
+            menu.find_layout("de"),
+            Some(&LayoutId::System { kind: "xkb".into(), name: "de".into() }),
+        );
+        assert_eq!(menu.find_layout("fr"), None);
+    }
+}
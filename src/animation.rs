@@ -6,14 +6,24 @@
 
 use std::time::Duration;
 
+use crate::data::parsing::BottomRow;
 use crate::imservice::ContentPurpose;
-use crate::layout::ArrangementKind;
+use crate::layout::{ ArrangementKind, LetterCase };
 use crate::outputs::OutputId;
 use crate::panel::PixelSize;
+use crate::send_key::ReturnKey;
+use crate::text_field;
 
 /// The keyboard should hide after this has elapsed to prevent flickering.
 pub const HIDING_TIMEOUT: Duration = Duration::from_millis(200);
 
+/// Someone who pressed a key this recently is likely to keep typing.
+pub const RECENT_TYPING: Duration = Duration::from_secs(3);
+
+/// Hiding timeout after recent typing,
+/// long enough to move on to the next text field.
+pub const TYPING_HIDING_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Description of parameters which influence panel contents
 #[derive(PartialEq, Clone, Debug)]
 pub struct Contents {
@@ -21,6 +31,26 @@ pub struct Contents {
     pub kind: ArrangementKind,
     pub overlay_name: Option<String>,
     pub purpose: ContentPurpose,
+    /// Layout definition to show instead of the named one
+    pub preview: Option<String>,
+    /// Changes whenever the layout must be loaded again,
+    /// even if nothing else changed.
+    pub generation: u32,
+    /// Place PIN digits randomly instead of using the layout file
+    pub scramble: bool,
+    pub bottom_row: BottomRow,
+    /// Search in the symbols picker
+    pub symbol_query: Option<String>,
+    /// Typing goes into the keyboard's own text field
+    pub text_field: Option<text_field::Purpose>,
+    /// Keysyms pressed in the compose picker
+    pub compose_sequence: Vec<String>,
+    /// View to start in, instead of the one from the previous layout
+    pub letter_case: Option<LetterCase>,
+    /// The text field holds secrets
+    pub sensitive: bool,
+    /// What the text field makes of Return
+    pub return_key: ReturnKey,
 }
 
 /// The outwardly visible state of visibility
@@ -29,6 +59,8 @@ pub enum Outcome {
     Visible {
         output: OutputId,
         height: PixelSize,
+        /// Over the whole output, instead of taking the bottom
+        floating: bool,
         contents: Contents,
     },
     Hidden,
@@ -0,0 +1,161 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Tells screen reader users what the keyboard switched to.
+ *
+ * View and layout changes are spoken through the ATK "announcement" signal,
+ * which reaches AT-SPI listeners like Orca.
+ *
+ * Changes coming in quick succession, like tapping Shift twice,
+ * only announce the last one.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::time::{ Duration, Instant };
+
+use gtk::prelude::*;
+
+use crate::clock::{ Clock, System };
+use crate::labels;
+use crate::layout::LatchedState;
+use crate::locale;
+use crate::logging;
+
+// Traits
+use crate::logging::Warn;
+
+
+/// Minimal pause between announcements
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+thread_local! {
+    /// Must match the default setting
+    static ENABLED: Cell<bool> = Cell::new(true);
+    static LAST: Cell<Option<Instant>> = Cell::new(None);
+    /// Waiting for the pause to end
+    static PENDING: RefCell<Option<String>> = RefCell::new(None);
+    static LAYOUT: RefCell<String> = RefCell::new(String::new());
+    /// The signal is missing in older ATK. Complaining once is enough.
+    static UNSUPPORTED: Cell<bool> = Cell::new(false);
+}
+
+/// Follows the user's settings.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+    if !enabled {
+        PENDING.with(|p| *p.borrow_mut() = None);
+    }
+}
+
+/// How the view sounds to the user
+fn describe_view(view: &str, latched: &LatchedState) -> String {
+    let view = match (view, latched) {
+        ("upper", LatchedState::FromView(_)) => "upper_once",
+        (other, _) => other,
+    };
+    labels::get_view_name(view)
+}
+
+fn describe_layout(name: &str, overlay: Option<&str>) -> String {
+    let name = match overlay {
+        Some(overlay) => overlay.into(),
+        None => locale::XkbInfo::new().get_display_name(name)
+            .or_print(
+                logging::Problem::Surprise,
+                &format!("No display name for xkb layout {}", name),
+            )
+            .unwrap_or_else(|| name.into()),
+    };
+    format!("{} keyboard", name)
+}
+
+/// Called after switching views, or when the latch on the view changed.
+pub fn view_changed(view: &str, latched: &LatchedState) {
+    say(describe_view(view, latched));
+}
+
+/// Called for every layout loaded. Reloading the same one stays silent.
+pub fn layout_changed(name: &str, overlay: Option<&str>) {
+    let id = overlay.unwrap_or(name).to_string();
+    if LAYOUT.with(|layout| layout.replace(id.clone())) == id {
+        return;
+    }
+    say(describe_layout(name, overlay));
+}
+
+fn say(text: String) {
+    // Tests switch views without GTK.
+    if !ENABLED.with(|e| e.get()) || !gtk::is_initialized() {
+        return;
+    }
+    let now = System.now();
+    let wait = LAST.with(|last| last.get())
+        .map(|last| last + MIN_INTERVAL)
+        .filter(|next| *next > now)
+        .map(|next| next - now);
+    match wait {
+        None => emit(&text, now),
+        Some(wait) => {
+            // A timer is already running if something was pending.
+            if PENDING.with(|p| p.replace(Some(text))).is_none() {
+                schedule(wait);
+            }
+        },
+    }
+}
+
+fn schedule(wait: Duration) {
+    System.schedule(wait, Box::new(|| {
+        if let Some(text) = PENDING.with(|p| p.borrow_mut().take()) {
+            emit(&text, System.now());
+        }
+        false
+    }));
+}
+
+fn emit(text: &str, now: Instant) {
+    LAST.with(|last| last.set(Some(now)));
+    // Usually only the panel. The layout menu may be up too, and speaks just as well.
+    let window = gtk::Window::list_toplevels().into_iter()
+        .find(|widget| widget.is_visible());
+    let window = match window {
+        Some(window) => window,
+        None => return,
+    };
+    #[cfg(feature = "glib_v0_14")]
+    let accessible = window.accessible();
+    #[cfg(not(feature = "glib_v0_14"))]
+    let accessible = window.get_accessible();
+    let accessible = match accessible {
+        Some(accessible) => accessible,
+        None => return,
+    };
+
+    #[cfg(feature = "glib_v0_14")]
+    let result = accessible.emit_by_name("announcement", &[&text]);
+    #[cfg(not(feature = "glib_v0_14"))]
+    let result = accessible.emit("announcement", &[&text]);
+
+    if result.is_err() && !UNSUPPORTED.with(|u| u.replace(true)) {
+        result.or_print(
+            logging::Problem::Warning,
+            "Can't announce changes, ATK may be too old",
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shift() {
+        assert_eq!(
+            describe_view("upper", &LatchedState::FromView("base".into())),
+            "Capital letter",
+        );
+        assert_eq!(describe_view("upper", &LatchedState::Not), "Capital letters");
+        assert_eq!(describe_view("accents", &LatchedState::Not), "accents");
+    }
+}
@@ -0,0 +1,184 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Safeguards for typing which doesn't come from touching the panel,
+ * like from the remote keyboard or the command socket.
+ *
+ * Each interface limits how long a text can be,
+ * and how fast it can be typed, so that a runaway script or a large paste
+ * doesn't flood the text field.
+ *
+ * While something types that way, the panel shows it with a banner.
+ * Touching the panel then stops it: the touch doesn't press anything,
+ * and typing from elsewhere is refused for a while.
+ */
+
+use std::cell::Cell;
+use std::time::{ Duration, Instant };
+use cairo;
+
+use crate::clock::{ Clock, System };
+use crate::drawing;
+use crate::layout::Layout;
+use crate::logging;
+
+
+/// Longest text in a single command, in characters
+pub const MAX_TEXT: usize = 1000;
+/// Characters per second, after the first `MAX_TEXT`. A very fast typist.
+const RATE: f64 = 50.0;
+/// The banner stays up this long after the last typed character
+const SHOWN_FOR: Duration = Duration::from_secs(2);
+/// After the user stops it, typing from elsewhere is refused this long
+const STOPPED_FOR: Duration = Duration::from_secs(30);
+
+/// Lets through a burst of characters, and then a steady trickle.
+/// Each interface has its own.
+#[derive(Debug)]
+pub struct Limiter {
+    allowance: f64,
+    last: Option<Instant>,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter {
+            allowance: MAX_TEXT as f64,
+            last: None,
+        }
+    }
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Limiter::default()
+    }
+
+    /// Uses up the allowance for `count` characters or keys.
+    /// If too few are left, takes nothing,
+    /// and returns how long until there are enough.
+    pub fn take(&mut self, count: usize, now: Instant) -> Result<(), Duration> {
+        let refilled = self.last
+            .map(|last| now.saturating_duration_since(last).as_secs_f64() * RATE)
+            .unwrap_or(0.0);
+        self.allowance = (self.allowance + refilled).min(MAX_TEXT as f64);
+        self.last = Some(now);
+        let count = count as f64;
+        if count <= self.allowance {
+            self.allowance -= count;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((count - self.allowance) / RATE))
+        }
+    }
+}
+
+/// Turns the wait into an answer for the client.
+pub fn describe_wait(wait: Duration) -> String {
+    format!("Typing too fast, try again in {} ms", wait.as_millis().max(1))
+}
+
+thread_local! {
+    static LAST_TYPED: Cell<Option<Instant>> = Cell::new(None);
+    static STOPPED: Cell<Option<Instant>> = Cell::new(None);
+    /// The touch which stopped typing is still down
+    static STOPPING: Cell<bool> = Cell::new(false);
+}
+
+/// Call on the main loop before typing anything from elsewhere.
+/// Refuses while the user keeps it stopped.
+pub fn check(now: Instant) -> Result<(), String> {
+    let stopped = STOPPED.with(|s| s.get())
+        .filter(|since| now.saturating_duration_since(*since) < STOPPED_FOR);
+    match stopped {
+        Some(_) => Err("Stopped by the user".into()),
+        None => Ok(()),
+    }
+}
+
+/// Call on the main loop after typing from elsewhere, to show the banner.
+pub fn typed(now: Instant) {
+    let was_shown = is_typing(now);
+    LAST_TYPED.with(|l| l.set(Some(now)));
+    if !was_shown {
+        drawing::queue_redraw_all();
+    }
+    // Checked again when the time is up, in case something got typed since.
+    System.schedule(SHOWN_FOR, Box::new(|| {
+        if !is_typing(System.now()) {
+            drawing::queue_redraw_all();
+        }
+        false
+    }));
+}
+
+/// Whether the banner is up
+pub fn is_typing(now: Instant) -> bool {
+    LAST_TYPED.with(|l| l.get())
+        .map(|last| now.saturating_duration_since(last) < SHOWN_FOR)
+        .unwrap_or(false)
+}
+
+/// The emergency stop, when the panel gets touched while the banner is up.
+/// The touch shouldn't press anything until it's lifted.
+pub fn stop(now: Instant) {
+    log_print!(logging::Level::Info, "Typing from elsewhere stopped by the user");
+    STOPPED.with(|s| s.set(Some(now)));
+    STOPPING.with(|s| s.set(true));
+    LAST_TYPED.with(|l| l.set(None));
+}
+
+pub fn is_stopping() -> bool {
+    STOPPING.with(|s| s.get())
+}
+
+/// Call when a touch is lifted. Tells whether it was the stopping one.
+pub fn finish_stop() -> bool {
+    STOPPING.with(|s| s.replace(false))
+}
+
+/// Draws the banner over the top of the view.
+pub fn render(cr: &cairo::Context, layout: &Layout) {
+    if !is_typing(System.now()) {
+        return;
+    }
+    let (offset, view) = layout.get_current_view_position();
+    let size = view.get_size();
+    let height = size.height / 6.0;
+    cr.save();
+    cr.set_source_rgba(0.8, 0.3, 0.0, 0.85);
+    cr.rectangle(offset.x, offset.y, size.width, height);
+    cr.fill();
+
+    let text = "Typing from elsewhere. Touch to stop.";
+    let font_size = height * 0.6;
+    // Letters are roughly this wide in most fonts
+    let width = text.len() as f64 * font_size * 0.5;
+    cr.set_font_size(font_size);
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.95);
+    cr.move_to(
+        offset.x + (size.width - width).max(0.0) / 2.0,
+        offset.y + (height + font_size * 0.7) / 2.0,
+    );
+    cr.show_text(text);
+    cr.restore();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let start = Instant::now();
+        let mut limiter = Limiter::new();
+        assert_eq!(limiter.take(MAX_TEXT, start), Ok(()));
+        // Nothing left, and nothing gets taken either.
+        let wait = limiter.take(10, start).unwrap_err();
+        assert!(wait > Duration::from_millis(190) && wait <= Duration::from_millis(200));
+        let wait = limiter.take(10, start + Duration::from_millis(100)).unwrap_err();
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+        assert_eq!(limiter.take(10, start + Duration::from_millis(250)), Ok(()));
+    }
+}
@@ -0,0 +1,158 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Timing of the code between a touch and a key press.
+ *
+ * Slow phone CPUs feel every millisecond spent there.
+ * The benchmarks are ignored tests,
+ * because benchmark crates like criterion are not packaged
+ * for the distributions Squeekboard gets built on. Run them with:
+ *
+ * ```
+ * cargo.sh test --release benches -- --ignored --nocapture --test-threads 1
+ * ```
+ *
+ * and compare the printed times before and after a change.
+ *
+ * Each benchmark fails above a time limit.
+ * The limits are generous enough for a phone,
+ * so they only catch slowdowns by orders of magnitude,
+ * like a lookup turning quadratic.
+ */
+
+use crate::data::parsing;
+use crate::imservice::ContentPurpose;
+use crate::layout::{ ArrangementKind, Layout, Size };
+use crate::layout::c::Point;
+use crate::logging;
+use std::char;
+use std::ptr;
+use std::mem;
+use std::time::{ Duration, Instant };
+
+
+/// A layout bigger than any built in one
+const ROWS: u32 = 10;
+const COLUMNS: u32 = 12;
+
+/// Keeps the optimizer from throwing the result away.
+fn black_box<T>(value: T) -> T {
+    unsafe {
+        let ret = ptr::read_volatile(&value);
+        mem::forget(value);
+        ret
+    }
+}
+
+/// Runs `f` repeatedly after a warm-up, and prints the average time.
+/// Fails if the average goes above `limit`.
+fn measure<T, F: FnMut() -> T>(
+    name: &str,
+    iterations: u32,
+    limit: Duration,
+    mut f: F,
+) -> Duration {
+    for _ in 0..iterations / 10 {
+        black_box(f());
+    }
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    let per_iteration = start.elapsed() / iterations;
+    println!("{}: {:?} per iteration", name, per_iteration);
+    assert!(
+        per_iteration <= limit,
+        "{} took {:?}, above the limit of {:?}", name, per_iteration, limit,
+    );
+    per_iteration
+}
+
+/// Every button gets its own letter, so that the keymap is as big as it gets.
+fn make_yaml() -> String {
+    let rows: Vec<String> = (0..ROWS)
+        .map(|row| {
+            let names: Vec<String> = (0..COLUMNS)
+                .map(|column| char::from_u32(0xc0 + row * COLUMNS + column).unwrap())
+                .map(|c| c.to_string())
+                .collect();
+            format!("        - \"{}\"", names.join(" "))
+        })
+        .collect();
+    format!(
+        "outlines:\n    default: {{ width: 35, height: 50 }}\nviews:\n    base:\n{}\n",
+        rows.join("\n"),
+    )
+}
+
+fn build(parsed: parsing::Layout) -> Layout {
+    let data = parsed.build(logging::Print).0.unwrap();
+    Layout::new(data, ArrangementKind::Base, ContentPurpose::Normal)
+}
+
+fn make_layout() -> Layout {
+    build(parsing::Layout::from_yaml_str(&make_yaml()).unwrap())
+}
+
+/// Touches spread over the whole panel, some outside of the buttons
+fn make_touches() -> Vec<Point> {
+    (0..16).flat_map(|y| (0..16).map(move |x| Point {
+        x: x as f64 * 48.0,
+        y: y as f64 * 20.0,
+    })).collect()
+}
+
+#[test]
+#[ignore]
+fn find_button() {
+    let layout = make_layout();
+    let transformation = layout.calculate_transformation(
+        Size { width: 720.0, height: 300.0 },
+    );
+    let touches = make_touches();
+    measure(
+        "find_button_by_position, 256 touches",
+        10_000,
+        Duration::from_millis(5),
+        || {
+            touches.iter()
+                .filter(|point| {
+                    let point = transformation.forward((*point).clone());
+                    layout.find_button_by_position(point).is_some()
+                })
+                .count()
+        },
+    );
+}
+
+#[test]
+#[ignore]
+fn build_layout() {
+    let yaml = make_yaml();
+    // Building consumes the parsed layout, so it can't be timed alone.
+    measure(
+        "parse layout",
+        200,
+        Duration::from_millis(50),
+        || parsing::Layout::from_yaml_str(&yaml).unwrap(),
+    );
+    measure(
+        "parse and build layout with 120 buttons",
+        200,
+        Duration::from_millis(250),
+        || build(parsing::Layout::from_yaml_str(&yaml).unwrap()),
+    );
+}
+
+#[test]
+#[ignore]
+fn transformation() {
+    let layout = make_layout();
+    measure(
+        "calculate_transformation",
+        100_000,
+        Duration::from_millis(1),
+        || layout.calculate_transformation(Size { width: 720.0, height: 300.0 }),
+    );
+}
@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate clap;
+extern crate rs;
+
+use rs::data::compiled::compile_file;
+use std::path::Path;
+use std::process;
+
+fn main() -> () {
+    let matches = clap_app!(compile_layouts =>
+        (name: "squeekboard-compile-layouts")
+        (about: "Build yaml keyboard layouts ahead of time, storing the result next to each file. Squeekboard loads the compiled form while it's newer than the yaml file.")
+        (@arg INPUT: +required +multiple "Yaml keyboard layout files to compile")
+    ).get_matches();
+    let mut failed = false;
+    for input in matches.values_of("INPUT").unwrap() {
+        match compile_file(Path::new(input)) {
+            Ok(path) => println!("{}: {}", input, path.display()),
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
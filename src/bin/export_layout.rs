@@ -0,0 +1,36 @@
+#[macro_use]
+extern crate clap;
+extern crate rs;
+
+use rs::data::expansion::Context;
+use rs::data::export::export_file;
+use std::path::Path;
+use std::process;
+
+fn main() -> () {
+    let matches = clap_app!(export_layout =>
+        (name: "squeekboard-export-layout")
+        (about: "Print the keyboard layout as Squeekboard would show it, in JSON, with the positions and actions of all buttons.")
+        (@arg arrangement: -a --arrangement +takes_value "base, wide or compact (default: base)")
+        (@arg purpose: -p --purpose +takes_value "Purpose of the text field, e.g. terminal (default: normal)")
+        (@arg INPUT: +required "Yaml keyboard layout file to export")
+    ).get_matches();
+    let context = Context::from_names(
+        matches.value_of("arrangement").unwrap_or("base"),
+        matches.value_of("purpose").unwrap_or("normal"),
+    );
+    let context = match context {
+        Some(context) => context,
+        None => {
+            eprintln!("Unknown arrangement or purpose");
+            process::exit(1);
+        },
+    };
+    match export_file(Path::new(matches.value_of("INPUT").unwrap()), &context) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,19 @@
+#[macro_use]
+extern crate clap;
+extern crate rs;
+
+use rs::trace::print_replay;
+use std::path::Path;
+use std::process;
+
+fn main() -> () {
+    let matches = clap_app!(replay_trace =>
+        (name: "squeekboard-replay-trace")
+        (about: "Feed events recorded with --trace to the state machine again, printing how the panel changes.")
+        (@arg INPUT: +required "Trace file")
+    ).get_matches();
+    if let Err(e) = print_replay(Path::new(matches.value_of("INPUT").unwrap())) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
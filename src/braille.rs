@@ -0,0 +1,169 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Typing Braille cells as chords of six dot keys.
+ *
+ * All dot keys pressed until the last one goes up make a cell,
+ * which gets translated as uncontracted (grade 1) English Braille.
+ * The number sign turns the letters a to j into digits until the next space,
+ * and the capital sign makes the next letter uppercase.
+ * Cells without a translation get typed as Unicode Braille patterns.
+ *
+ * The blank cell key types a space. It's a dot key numbered 0,
+ * so that it ends the number mode, which a plain space key couldn't.
+ */
+
+use std::ffi::CString;
+
+use crate::logging;
+use crate::submission::Submission;
+
+
+const NUMBER_SIGN: &str = "3456";
+const CAPITAL_SIGN: &str = "6";
+
+const LETTERS: [&str; 26] = [
+    "1", "12", "14", "145", "15", "124", "1245", "125", "24", "245",
+    "13", "123", "134", "1345", "135", "1234", "12345", "1235", "234", "2345",
+    "136", "1236", "2456", "1346", "13456", "1356",
+];
+
+const PUNCTUATION: [(&str, char); 8] = [
+    ("2", ','), ("23", ';'), ("25", ':'), ("256", '.'),
+    ("235", '!'), ("236", '?'), ("3", '\''), ("36", '-'),
+];
+
+/// Bits 0 to 5 stand for dots 1 to 6.
+fn to_mask(dots: &str) -> u8 {
+    dots.chars()
+        .filter_map(|dot| dot.to_digit(10))
+        .fold(0, |mask, dot| mask | (1 << (dot - 1)))
+}
+
+pub fn is_dot(dot: u8) -> bool {
+    dot <= 6
+}
+
+/// Collects a cell, and remembers the indicators in effect.
+#[derive(Default)]
+pub struct Translator {
+    dots: u8,
+    /// The blank cell key took part
+    blank: bool,
+    numbers: bool,
+    capital: bool,
+}
+
+impl Translator {
+    pub fn press(&mut self, dot: u8) {
+        match dot {
+            0 => self.blank = true,
+            dot => self.dots |= 1 << (dot - 1),
+        }
+    }
+
+    /// Text for the cell, if it types any.
+    fn resolve(&mut self) -> Option<String> {
+        let dots = self.dots;
+        let blank = self.blank;
+        self.dots = 0;
+        self.blank = false;
+
+        if dots == 0 {
+            if blank {
+                self.numbers = false;
+                self.capital = false;
+                return Some(" ".into());
+            }
+            return None;
+        }
+        if dots == to_mask(NUMBER_SIGN) {
+            self.numbers = true;
+            return None;
+        }
+        if dots == to_mask(CAPITAL_SIGN) {
+            self.capital = true;
+            return None;
+        }
+
+        let letter = LETTERS.iter().position(|pattern| to_mask(pattern) == dots);
+        match letter {
+            Some(idx) if self.numbers && idx < 10 => {
+                // j comes after i, like 0 comes after 9 on the keyboard.
+                return Some(((idx + 1) % 10).to_string());
+            },
+            Some(idx) => {
+                self.numbers = false;
+                let letter = (b'a' + idx as u8) as char;
+                let capital = std::mem::replace(&mut self.capital, false);
+                return Some(match capital {
+                    true => letter.to_ascii_uppercase(),
+                    false => letter,
+                }.to_string());
+            },
+            None => {},
+        }
+
+        self.numbers = false;
+        self.capital = false;
+        let punctuation = PUNCTUATION.iter()
+            .find(|(pattern, _)| to_mask(pattern) == dots)
+            .map(|(_, character)| *character);
+        let character = punctuation.unwrap_or_else(|| {
+            std::char::from_u32(0x2800 + dots as u32)
+                .expect("Braille pattern out of range")
+        });
+        Some(character.to_string())
+    }
+
+    /// Call when the last dot key goes up.
+    pub fn finish(&mut self, submission: &mut Submission) {
+        if let Some(text) = self.resolve() {
+            let text = CString::new(text).expect("Braille table contains a null byte");
+            if submission.commit_text(&text).is_err() {
+                log_print!(
+                    logging::Level::Warning,
+                    "No text field to receive the Braille cell",
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn type_cells(translator: &mut Translator, cells: &[&str]) -> String {
+        cells.iter()
+            .filter_map(|cell| {
+                for dot in cell.chars().filter_map(|dot| dot.to_digit(10)) {
+                    translator.press(dot as u8);
+                }
+                translator.resolve()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn letters() {
+        let mut translator = Translator::default();
+        assert_eq!(type_cells(&mut translator, &["6", "125", "24", "256"]), "Hi.");
+    }
+
+    #[test]
+    fn numbers() {
+        let mut translator = Translator::default();
+        assert_eq!(
+            type_cells(&mut translator, &["3456", "1", "245", "0", "1"]),
+            "10 a",
+        );
+    }
+
+    #[test]
+    fn unknown_pattern() {
+        let mut translator = Translator::default();
+        assert_eq!(type_cells(&mut translator, &["123456"]), "⠿");
+    }
+}
@@ -0,0 +1,208 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! The calculator overlay.
+ *
+ * Expressions get typed into the keyboard's own text field,
+ * and the result is shown next to them as they change.
+ * The `insert_result` action commits the result into the application.
+ *
+ * Only the four basic operations and parentheses are understood.
+ */
+
+use std::ffi::CString;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::logging;
+use crate::submission::Submission;
+
+
+/// The name under which the calculator is shown as an overlay
+pub const OVERLAY_NAME: &str = "calculator";
+
+/// Digits after the decimal point, enough to hide rounding errors
+const PRECISION: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Position in characters where the expression stopped making sense
+    Syntax(usize),
+    DivisionByZero,
+    OutOfRange,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        while let Some(c) = self.chars.peek().cloned() {
+            if !c.is_whitespace() {
+                return Some(c);
+            }
+            self.next();
+        }
+        None
+    }
+
+    fn next(&mut self) {
+        self.chars.next();
+        self.position += 1;
+    }
+
+    /// Terms joined by addition and subtraction
+    fn parse_sum(&mut self) -> Result<f64, Error> {
+        let mut value = self.parse_product()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.next();
+                    value += self.parse_product()?;
+                },
+                Some('-') | Some('−') => {
+                    self.next();
+                    value -= self.parse_product()?;
+                },
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// Factors joined by multiplication and division
+    fn parse_product(&mut self) -> Result<f64, Error> {
+        let mut value = self.parse_factor()?;
+        loop {
+            match self.peek() {
+                Some('*') | Some('×') => {
+                    self.next();
+                    value *= self.parse_factor()?;
+                },
+                Some('/') | Some('÷') => {
+                    self.next();
+                    let divisor = self.parse_factor()?;
+                    if divisor == 0.0 {
+                        return Err(Error::DivisionByZero);
+                    }
+                    value /= divisor;
+                },
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// A number, a negated factor, or a sum in parentheses
+    fn parse_factor(&mut self) -> Result<f64, Error> {
+        match self.peek() {
+            Some('-') | Some('−') => {
+                self.next();
+                self.parse_factor().map(|value| -value)
+            },
+            Some('(') => {
+                self.next();
+                let value = self.parse_sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.next();
+                        Ok(value)
+                    },
+                    _ => Err(Error::Syntax(self.position)),
+                }
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => self.parse_number(),
+            _ => Err(Error::Syntax(self.position)),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<f64, Error> {
+        let start = self.position;
+        let mut number = String::new();
+        while let Some(c) = self.chars.peek().cloned() {
+            if !(c.is_ascii_digit() || c == '.') {
+                break;
+            }
+            number.push(c);
+            self.next();
+        }
+        number.parse().map_err(|_| Error::Syntax(start))
+    }
+}
+
+pub fn evaluate(expression: &str) -> Result<f64, Error> {
+    let mut parser = Parser { chars: expression.chars().peekable(), position: 0 };
+    let value = parser.parse_sum()?;
+    if parser.peek().is_some() {
+        return Err(Error::Syntax(parser.position));
+    }
+    match value.is_finite() {
+        true => Ok(value),
+        false => Err(Error::OutOfRange),
+    }
+}
+
+/// Without trailing zeros, and without the point for whole numbers
+pub fn format(value: f64) -> String {
+    let text = format!("{:.*}", PRECISION, value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    match text {
+        "-0" => "0".into(),
+        text => text.into(),
+    }
+}
+
+/// Commits the result of the expression in the field into the application.
+pub fn insert_result(submission: &mut Submission) {
+    let expression = match submission.get_redirected_text() {
+        Some(expression) => expression,
+        None => {
+            log_print!(logging::Level::Bug, "Calculator has no text field");
+            return;
+        },
+    };
+    let result = match evaluate(&expression) {
+        Ok(value) => format(value),
+        Err(e) => {
+            log_print!(logging::Level::Info, "No result for {}: {:?}", expression, e);
+            return;
+        },
+    };
+    let result = CString::new(result).expect("Number contains a null byte");
+    if submission.commit_to_application(&result).is_err() {
+        log_print!(
+            logging::Level::Warning,
+            "No text field to receive the result",
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) × 3"), Ok(9.0));
+        assert_eq!(evaluate("-2 - -3"), Ok(1.0));
+        assert_eq!(evaluate("8 ÷ 4 / 2"), Ok(1.0));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(evaluate("1 / 0"), Err(Error::DivisionByZero));
+        assert_eq!(evaluate("2 +"), Err(Error::Syntax(3)));
+        assert_eq!(evaluate("(2"), Err(Error::Syntax(2)));
+        assert_eq!(evaluate("1.2.3"), Err(Error::Syntax(0)));
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format(evaluate("0.1 + 0.2").unwrap()), "0.3");
+        assert_eq!(format(42.0), "42");
+        assert_eq!(format(-0.00000000001), "0");
+        assert_eq!(format(-1.5), "-1.5");
+    }
+}
@@ -0,0 +1,148 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! The time, and wakeups after some of it passes.
+ *
+ * Timers and the state loop ask a `Clock` rather than `Instant::now()`,
+ * so that tests can use a `Manual` clock and move time forward themselves.
+ * Everything scheduled on it then runs in order, without sleeping,
+ * and the same test always sees the same times.
+ */
+
+#[cfg(test)]
+use std::cell::{ Cell, RefCell };
+#[cfg(test)]
+use std::rc::Rc;
+use std::time::{ Duration, Instant };
+
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+    /// Calls `f` after the delay,
+    /// and again after each next delay, for as long as it returns true.
+    fn schedule(&self, after: Duration, f: Box<dyn FnMut() -> bool>);
+}
+
+/// The real time. Wakeups come on the glib main loop of the current thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct System;
+
+impl Clock for System {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn schedule(&self, after: Duration, mut f: Box<dyn FnMut() -> bool>) {
+        let f = move || glib::Continue(f());
+        #[cfg(feature = "glib_v0_14")]
+        glib::timeout_add_local(after, f);
+        #[cfg(not(feature = "glib_v0_14"))]
+        glib::timeout_add_local(after.as_millis() as u32, f);
+    }
+}
+
+#[cfg(test)]
+struct Wakeup {
+    when: Instant,
+    interval: Duration,
+    f: Box<dyn FnMut() -> bool>,
+}
+
+#[cfg(test)]
+struct Timeline {
+    now: Cell<Instant>,
+    /// In the order of scheduling
+    wakeups: RefCell<Vec<Wakeup>>,
+}
+
+/// Time which only passes when the test says so.
+/// Clones share the time.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct Manual(Rc<Timeline>);
+
+#[cfg(test)]
+impl Manual {
+    /// Only the time passed since the start matters.
+    pub fn new() -> Manual {
+        Manual(Rc::new(Timeline {
+            now: Cell::new(Instant::now()),
+            wakeups: RefCell::new(Vec::new()),
+        }))
+    }
+
+    /// Runs the wakeups due until then, the earliest first.
+    /// Wakeups at the same time run in the order they were scheduled.
+    pub fn advance(&self, by: Duration) {
+        let end = self.0.now.get() + by;
+        loop {
+            let next = self.0.wakeups.borrow().iter()
+                .enumerate()
+                .filter(|(_, wakeup)| wakeup.when <= end)
+                .min_by_key(|(idx, wakeup)| (wakeup.when, *idx))
+                .map(|(idx, _)| idx);
+            let mut wakeup = match next {
+                Some(idx) => self.0.wakeups.borrow_mut().remove(idx),
+                None => break,
+            };
+            self.0.now.set(wakeup.when);
+            // The callback may schedule more.
+            if (wakeup.f)() {
+                wakeup.when += wakeup.interval;
+                self.0.wakeups.borrow_mut().push(wakeup);
+            }
+        }
+        self.0.now.set(end);
+    }
+}
+
+#[cfg(test)]
+impl Clock for Manual {
+    fn now(&self) -> Instant {
+        self.0.now.get()
+    }
+
+    fn schedule(&self, after: Duration, f: Box<dyn FnMut() -> bool>) {
+        self.0.wakeups.borrow_mut().push(Wakeup {
+            when: self.now() + after,
+            interval: after,
+            f,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn manual() {
+        let clock = Manual::new();
+        let start = clock.now();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let (repeating, calls_repeating) = (clock.clone(), calls.clone());
+        clock.schedule(Duration::from_millis(100), Box::new(move || {
+            calls_repeating.borrow_mut().push(repeating.now() - start);
+            calls_repeating.borrow().len() < 3
+        }));
+        let (once, calls_once) = (clock.clone(), calls.clone());
+        clock.schedule(Duration::from_millis(150), Box::new(move || {
+            calls_once.borrow_mut().push(once.now() - start);
+            false
+        }));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(150),
+                Duration::from_millis(200),
+            ],
+        );
+        // Nothing repeats any more.
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(calls.borrow().len(), 3);
+        assert_eq!(clock.now() - start, Duration::from_millis(1250));
+    }
+}
@@ -0,0 +1,383 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Typing on behalf of scripts, like dotool or ydotool.
+ *
+ * While enabled in the settings, a Unix socket in the runtime dir,
+ * `$XDG_RUNTIME_DIR/squeekboard/commands`, takes one command per line:
+ *
+ * - `type TEXT` types the text,
+ * - `key COMBO...` taps keys, each like `ctrl+shift+Left`.
+ *
+ * Every command gets answered with `ok`, or with `error: ` and the reason.
+ *
+ * Text goes through the input method.
+ * Without a text field, its characters get pressed on the current layout,
+ * which only works if they are all there.
+ * Keys are named by keysyms, and also need to be on the current layout,
+ * because there's no keymap with any other keys.
+ * Clients get served one at a time,
+ * within the limits of length and speed in `automation`.
+ */
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{ UnixListener, UnixStream };
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+use std::sync::{ mpsc, Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+use std::time::{ Duration, Instant };
+use glib::{ Continue, MainContext, PRIORITY_DEFAULT };
+
+use crate::action::{ Action, KeySym, Modifier };
+use crate::automation;
+use crate::keyboard::{ KeyCode, KeyStateId };
+use crate::layout::Layout;
+use crate::logging;
+use crate::main;
+use crate::main::Commands;
+use crate::submission::{ Submission, SubmitData, Timestamp };
+use crate::xdg;
+
+// Traits
+use crate::logging::Warn;
+
+
+/// How often the listener checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Lets the next client in if the current one goes quiet
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// The main loop is busy for longer only if something's broken
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn get_path() -> Option<PathBuf> {
+    xdg::runtime_path("squeekboard/commands")
+}
+
+type Error = Box<dyn std::error::Error>;
+
+/// A key of the current layout, ready to be pressed on the main loop
+struct Key {
+    id: KeyStateId,
+    keycodes: Vec<KeyCode>,
+}
+
+enum Request {
+    Type {
+        text: CString,
+        /// Pressed when there's no input method. Empty if any are missing.
+        keys: Vec<Key>,
+    },
+    Tap {
+        key: Key,
+        modifiers: Vec<Modifier>,
+    },
+}
+
+impl Request {
+    /// Characters or keys, for the speed limit
+    fn get_size(&self) -> usize {
+        match self {
+            Request::Type { text, keys: _ } => text.to_str()
+                .map(|text| text.chars().count())
+                .unwrap_or_else(|_| text.as_bytes().len()),
+            Request::Tap { .. } => 1,
+        }
+    }
+}
+
+/// Sent to the main loop
+struct Message {
+    request: Request,
+    reply: mpsc::Sender<Result<(), String>>,
+}
+
+fn submit(submission: &mut Submission, request: Request, time: Timestamp)
+    -> Result<(), String>
+{
+    match request {
+        Request::Type { text, keys } => {
+            if submission.commit_text(&text).is_ok() {
+                Ok(())
+            } else if keys.is_empty() {
+                Err("No text field, and not all characters are on the layout".into())
+            } else {
+                for key in keys {
+                    submission.handle_press(key.id, SubmitData::Keycodes, &key.keycodes, time);
+                    submission.handle_release(key.id, time);
+                }
+                Ok(())
+            }
+        },
+        Request::Tap { key, modifiers } => {
+            submission.handle_shortcut(key.id, &modifiers, &key.keycodes, time);
+            submission.handle_release(key.id, time);
+            Ok(())
+        },
+    }
+}
+
+fn find_key<F: Fn(&Action) -> bool>(layout: &Layout, matches: F) -> Option<Key> {
+    layout.keys.iter()
+        .find(|(_id, key)| matches(&key.action))
+        .map(|(id, key)| Key { id, keycodes: key.keycodes.clone() })
+}
+
+fn find_character(layout: &Layout, character: char) -> Option<Key> {
+    let text = character.to_string();
+    find_key(layout, |action| match action {
+        Action::Submit { text: Some(t), keys: _ } => t.to_str() == Ok(text.as_str()),
+        _ => false,
+    })
+}
+
+fn find_keysym(layout: &Layout, name: &str) -> Option<Key> {
+    find_key(layout, |action| match action {
+        Action::Submit { text: _, keys } => match keys.as_slice() {
+            [KeySym(sym)] => sym == name,
+            _ => false,
+        },
+        Action::Erase => name == "BackSpace",
+        Action::SendOrNewline => name == "Return",
+        _ => false,
+    })
+}
+
+fn parse_modifier(name: &str) -> Option<Modifier> {
+    match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => Some(Modifier::Control),
+        "alt" => Some(Modifier::Alt),
+        "super" | "mod4" | "meta" => Some(Modifier::Mod4),
+        "shift" => Some(Modifier::Shift),
+        _ => None,
+    }
+}
+
+/// Takes a combo like `ctrl+c`. The last part is the keysym.
+fn parse_combo(layout: &Layout, combo: &str) -> Result<Request, String> {
+    let mut parts: Vec<&str> = combo.split('+').collect();
+    let name = parts.pop().filter(|name| !name.is_empty())
+        .ok_or_else(|| format!("No key in {}", combo))?;
+    let modifiers = parts.into_iter()
+        .map(|m| parse_modifier(m).ok_or_else(|| format!("Unknown modifier {}", m)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = find_keysym(layout, name)
+        .ok_or_else(|| format!("No {} key on the current layout", name))?;
+    Ok(Request::Tap { key, modifiers })
+}
+
+/// Turns the line into requests for the main loop.
+fn parse(layout: Option<&Layout>, line: &str) -> Result<Vec<Request>, String> {
+    let (command, argument) = match line.find(' ') {
+        Some(idx) => (&line[..idx], &line[idx + 1..]),
+        None => (line, ""),
+    };
+    match command {
+        "type" => {
+            if argument.chars().count() > automation::MAX_TEXT {
+                return Err(format!("Text longer than {} characters", automation::MAX_TEXT));
+            }
+            let text = CString::new(argument).map_err(|e| e.to_string())?;
+            let keys = layout.and_then(|layout| {
+                argument.chars()
+                    .map(|c| find_character(layout, c))
+                    .collect::<Option<Vec<_>>>()
+            });
+            Ok(vec![Request::Type { text, keys: keys.unwrap_or_default() }])
+        },
+        "key" => {
+            let layout = layout.ok_or("No layout shown")?;
+            argument.split_whitespace()
+                .map(|combo| parse_combo(layout, combo))
+                .collect()
+        },
+        other => Err(format!("Unknown command {}", other)),
+    }
+}
+
+struct Listener {
+    layout: main::LayoutCopy,
+    /// The listener stops when this stops being `current`
+    generation: Arc<AtomicUsize>,
+    current: usize,
+    requests: glib::Sender<Message>,
+    limiter: automation::Limiter,
+}
+
+impl Listener {
+    fn run(mut self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Left over after a crash
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+
+        while self.generation.load(Ordering::SeqCst) == self.current {
+            match listener.accept() {
+                Ok((stream, _address)) => {
+                    self.serve(stream)
+                        .or_print(logging::Problem::Warning, "Command socket client failed");
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn serve(&mut self, stream: UnixStream) -> Result<(), Error> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+        let mut writer = &stream;
+        for line in BufReader::new(&stream).lines() {
+            let line = line?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            match self.execute(line) {
+                Ok(()) => writeln!(writer, "ok")?,
+                Err(e) => writeln!(writer, "error: {}", e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, line: &str) -> Result<(), String> {
+        let requests = parse(self.layout.get().map(|layout| &*layout), line)?;
+        let size = requests.iter().map(Request::get_size).sum();
+        self.limiter.take(size, Instant::now())
+            .map_err(automation::describe_wait)?;
+        for request in requests {
+            let (reply, replies) = mpsc::channel();
+            self.requests.send(Message { request, reply })
+                .map_err(|e| e.to_string())?;
+            replies.recv_timeout(REPLY_TIMEOUT).map_err(|e| e.to_string())??;
+        }
+        Ok(())
+    }
+}
+
+/// Opens and closes the socket as the settings change,
+/// and keeps track of the layout shown.
+fn watch(commands: mpsc::Receiver<Commands>, requests: glib::Sender<Message>) {
+    let path = match get_path() {
+        Some(path) => path,
+        None => {
+            log_print!(logging::Level::Warning, "No runtime dir for the command socket");
+            return;
+        },
+    };
+    let shown = Arc::new(Mutex::new(None));
+    let generation = Arc::new(AtomicUsize::new(0));
+    let mut listener: Option<thread::JoinHandle<()>> = None;
+    for commands in commands.iter() {
+        if let Some(main::commands::SetLayout { description }) = commands.layout_selection {
+            *shown.lock().unwrap() = Some(description);
+        }
+        if let Some(enabled) = commands.command_socket {
+            let current = generation.fetch_add(1, Ordering::SeqCst) + 1;
+            // The new listener needs the path.
+            if let Some(handle) = listener.take() {
+                if handle.join().is_err() {
+                    log_print!(logging::Level::Bug, "Command socket listener panicked");
+                }
+            }
+            if enabled {
+                let (shown, generation, requests) = (shown.clone(), generation.clone(), requests.clone());
+                let path = path.clone();
+                listener = Some(thread::spawn(move || {
+                    // Layouts stay on the thread which loaded them.
+                    let new = Listener {
+                        layout: main::LayoutCopy::new(shown),
+                        generation,
+                        current,
+                        requests,
+                        limiter: automation::Limiter::new(),
+                    };
+                    new.run(&path)
+                        .or_print(logging::Problem::Warning, "Command socket closed");
+                }));
+            }
+        }
+    }
+}
+
+/// Call on the main loop, before the state loop starts.
+pub fn init(commands: mpsc::Receiver<Commands>, submission: Rc<RefCell<Submission>>) {
+    let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    let start = Instant::now();
+    receiver.attach(
+        Some(&MainContext::default()),
+        move |Message { request, reply }: Message| {
+            let time = Timestamp(start.elapsed().as_millis() as u32);
+            let now = Instant::now();
+            let result = automation::check(now)
+                .and_then(|()| submit(&mut submission.borrow_mut(), request, time));
+            if result.is_ok() {
+                automation::typed(now);
+            }
+            // The client may have given up waiting.
+            let _ = reply.send(result);
+            Continue(true)
+        },
+    );
+    thread::spawn(move || watch(commands, sender));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::layout_dsl;
+    use ::layout_dsl::{ key, KeySpec };
+
+    fn letter(name: &str) -> KeySpec {
+        key(name).action(Action::Submit {
+            text: Some(CString::new(name).unwrap()),
+            keys: vec![KeySym(name.into())],
+        })
+    }
+
+    #[test]
+    fn commands() {
+        let layout = layout_dsl::layout(vec![
+            ("base", view![row![letter("a"), letter("b")]]),
+        ]);
+        match parse(Some(&layout), "type ab").unwrap().as_slice() {
+            [Request::Type { text, keys }] => {
+                assert_eq!(text.to_str(), Ok("ab"));
+                assert_eq!(keys.len(), 2);
+            },
+            _ => panic!("Expected typing"),
+        }
+        match parse(Some(&layout), "type abc").unwrap().as_slice() {
+            [Request::Type { text: _, keys }] => assert!(keys.is_empty()),
+            _ => panic!("Expected typing"),
+        }
+        match parse(Some(&layout), "key ctrl+a b").unwrap().as_slice() {
+            [Request::Tap { key: _, modifiers }, Request::Tap { key: _, modifiers: none }] => {
+                assert_eq!(modifiers, &vec![Modifier::Control]);
+                assert!(none.is_empty());
+            },
+            _ => panic!("Expected two taps"),
+        }
+        assert!(parse(Some(&layout), "key hyper+a").is_err());
+        assert!(parse(Some(&layout), "key c").is_err());
+        assert!(parse(Some(&layout), "click 1").is_err());
+        let long = format!("type {}", "a".repeat(automation::MAX_TEXT + 1));
+        assert!(parse(Some(&layout), &long).is_err());
+    }
+}
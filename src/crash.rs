@@ -0,0 +1,181 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Surviving panics, and leaving something to report.
+ *
+ * A panic anywhere used to take the keyboard down,
+ * leaving the user without a way to type.
+ * Now the panic hook writes a report with the panic message,
+ * the last events reaching the state machine, and the layout being shown,
+ * and the loops catch the panic and bring the panel back.
+ *
+ * The backtrace goes to the log, like with the default hook.
+ */
+
+use crate::logging;
+use crate::state::Event;
+use crate::xdg;
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, MutexGuard, TryLockError };
+use std::thread;
+use std::time::{ Instant, SystemTime, UNIX_EPOCH };
+
+// Traits
+use std::fmt::Write;
+
+
+/// Events kept for the report
+const EVENTS_KEPT: usize = 50;
+
+struct Data {
+    /// Oldest first
+    events: VecDeque<(Instant, Event)>,
+    layout: Option<String>,
+}
+
+/// What happened recently, for the report.
+/// Shared by the panic hook and the loops.
+#[derive(Clone)]
+pub struct Journal {
+    data: Arc<Mutex<Data>>,
+    /// Off where events could reveal what's typed in public
+    keep_events: bool,
+}
+
+impl Journal {
+    fn new(keep_events: bool) -> Journal {
+        Journal {
+            data: Arc::new(Mutex::new(Data {
+                events: VecDeque::new(),
+                layout: None,
+            })),
+            keep_events,
+        }
+    }
+
+    /// The panic may have happened while holding the lock,
+    /// so this must not wait, and must not mind poisoning.
+    fn lock(&self) -> Option<MutexGuard<Data>> {
+        match self.data.try_lock() {
+            Ok(data) => Some(data),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    pub fn record(&self, events: &[Event], now: Instant) {
+        if !self.keep_events {
+            return;
+        }
+        if let Some(mut data) = self.lock() {
+            for event in events {
+                if data.events.len() >= EVENTS_KEPT {
+                    data.events.pop_front();
+                }
+                data.events.push_back((now, event.clone()));
+            }
+        }
+    }
+
+    pub fn set_layout(&self, name: String) {
+        if let Some(mut data) = self.lock() {
+            data.layout = Some(name);
+        }
+    }
+
+    fn describe(&self, info: &panic::PanicInfo) -> String {
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "Squeekboard {} panicked in thread {}",
+            env!("CARGO_PKG_VERSION"),
+            thread::current().name().unwrap_or("<unnamed>"),
+        );
+        let _ = writeln!(report, "{}", info);
+        match self.lock() {
+            None => {
+                let _ = writeln!(report, "Recent events unavailable");
+            },
+            Some(data) => {
+                let _ = writeln!(report, "Layout: {:?}", data.layout);
+                let now = Instant::now();
+                let _ = writeln!(report, "Events, latest last:");
+                for (when, event) in data.events.iter() {
+                    let _ = writeln!(report, "-{:?}: {:?}", now - *when, event);
+                }
+            },
+        }
+        report
+    }
+}
+
+fn get_path() -> Option<PathBuf> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    xdg::state_path(format!("squeekboard/crash-{}.txt", seconds))
+}
+
+fn write(path: &Path, report: &str) -> Result<(), std::io::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, report)
+}
+
+/// Replaces the panic hook with one writing a report before the usual output.
+/// Call before any threads start.
+pub fn install(keep_events: bool) -> Journal {
+    // The default hook prints the backtrace only when asked to.
+    if env::var_os("RUST_BACKTRACE").is_none() {
+        env::set_var("RUST_BACKTRACE", "1");
+    }
+    let journal = Journal::new(keep_events);
+    let default_hook = panic::take_hook();
+    let hook_journal = journal.clone();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let report = hook_journal.describe(info);
+        match get_path() {
+            Some(path) => match write(&path, &report) {
+                Ok(()) => log_print!(
+                    logging::Level::Error,
+                    "Crash report written to {:?}", path,
+                ),
+                Err(e) => log_print!(
+                    logging::Level::Error,
+                    "Can't write crash report to {:?}: {}\n{}", path, e, report,
+                ),
+            },
+            None => log_print!(logging::Level::Error, "{}", report),
+        }
+    }));
+    journal
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn events_bounded() {
+        let journal = Journal::new(true);
+        let now = Instant::now();
+        for _ in 0..EVENTS_KEPT + 5 {
+            journal.record(&[Event::KeyPressed], now);
+        }
+        assert_eq!(journal.lock().unwrap().events.len(), EVENTS_KEPT);
+    }
+
+    #[test]
+    fn private() {
+        let journal = Journal::new(false);
+        journal.record(&[Event::KeyPressed], Instant::now());
+        assert!(journal.lock().unwrap().events.is_empty());
+    }
+}
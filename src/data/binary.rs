@@ -0,0 +1,711 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! A compact binary encoding for serde.
+ *
+ * Made for storing built layouts,
+ * because binary serde crates are not packaged
+ * for the distributions Squeekboard gets built on.
+ *
+ * The data is not self-describing: reading it back needs the same types.
+ * Numbers are little endian, lengths and strings are prefixed with a u64,
+ * structs and tuples are their fields in order,
+ * and enum variants are a u32 index followed by the contents.
+ */
+
+use serde::{ de, ser };
+use serde::de::IntoDeserializer;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+pub fn to_vec<T: ser::Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut serializer = Serializer { output: Vec::new() };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+/// Fails if anything is left over.
+pub fn from_slice<'de, T: de::Deserialize<'de>>(input: &'de [u8])
+    -> Result<T, Error>
+{
+    let mut deserializer = Deserializer { input };
+    let value = T::deserialize(&mut deserializer)?;
+    match deserializer.input.len() {
+        0 => Ok(value),
+        n => Err(Error(format!("{} bytes left over", n))),
+    }
+}
+
+struct Serializer {
+    output: Vec<u8>,
+}
+
+impl Serializer {
+    fn write_len(&mut self, len: Option<usize>) -> Result<(), Error> {
+        let len = len.ok_or_else(|| Error("Unknown length".into()))?;
+        self.output.extend_from_slice(&(len as u64).to_le_bytes());
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.output.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write_len(Some(v.len()))?;
+        self.output.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.serialize_bool(false)
+    }
+
+    fn serialize_some<T: ?Sized + ser::Serialize>(self, value: &T)
+        -> Result<(), Error>
+    {
+        self.serialize_bool(true)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + ser::Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + ser::Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.serialize_u32(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, Error> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self, Error>
+    {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.serialize_u32(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self, Error> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self, Error>
+    {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, Error> {
+        self.serialize_u32(variant_index)?;
+        Ok(self)
+    }
+}
+
+impl<'a> ser::SerializeSeq for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: ?Sized + ser::Serialize>(&mut self, value: &T)
+        -> Result<(), Error>
+    {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTuple for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: ?Sized + ser::Serialize>(&mut self, value: &T)
+        -> Result<(), Error>
+    {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ?Sized + ser::Serialize>(&mut self, value: &T)
+        -> Result<(), Error>
+    {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ?Sized + ser::Serialize>(&mut self, value: &T)
+        -> Result<(), Error>
+    {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeMap for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_key<T: ?Sized + ser::Serialize>(&mut self, key: &T)
+        -> Result<(), Error>
+    {
+        key.serialize(&mut **self)
+    }
+    fn serialize_value<T: ?Sized + ser::Serialize>(&mut self, value: &T)
+        -> Result<(), Error>
+    {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStruct for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ?Sized + ser::Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: ?Sized + ser::Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> Deserializer<'de> {
+    fn take(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        if self.input.len() < len {
+            return Err(Error("Unexpected end of data".into()));
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
+    }
+
+    fn take_array<A>(&mut self) -> Result<A, Error>
+        where for<'a> A: TryFrom<&'a [u8]>
+    {
+        let len = std::mem::size_of::<A>();
+        A::try_from(self.take(len)?)
+            .map_err(|_| Error("Bad array size".into()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        self.take_array().map(u32::from_le_bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize, Error> {
+        let len = self.take_array().map(u64::from_le_bytes)?;
+        // Anything bigger than the data would fail anyway,
+        // but would first try to allocate all that memory.
+        match len <= self.input.len() as u64 {
+            true => Ok(len as usize),
+            false => Err(Error(format!("Length {} exceeds the data", len))),
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<&'de [u8], Error> {
+        let len = self.read_len()?;
+        self.take(len)
+    }
+
+    fn read_str(&mut self) -> Result<&'de str, Error> {
+        str::from_utf8(self.read_bytes()?)
+            .map_err(|e| Error(e.to_string()))
+    }
+
+    fn read_bool(&mut self) -> Result<bool, Error> {
+        match self.take(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(Error(format!("Bad bool {}", other))),
+        }
+    }
+}
+
+macro_rules! deserialize_number {
+    ($method:ident, $visit:ident, $type:ty) => {
+        fn $method<V: de::Visitor<'de>>(self, visitor: V)
+            -> Result<V::Value, Error>
+        {
+            visitor.$visit(<$type>::from_le_bytes(self.take_array()?))
+        }
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V)
+        -> Result<V::Value, Error>
+    {
+        Err(Error("The format is not self-describing".into()))
+    }
+
+    fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        visitor.visit_bool(self.read_bool()?)
+    }
+
+    deserialize_number!(deserialize_i8, visit_i8, i8);
+    deserialize_number!(deserialize_i16, visit_i16, i16);
+    deserialize_number!(deserialize_i32, visit_i32, i32);
+    deserialize_number!(deserialize_i64, visit_i64, i64);
+    deserialize_number!(deserialize_u8, visit_u8, u8);
+    deserialize_number!(deserialize_u16, visit_u16, u16);
+    deserialize_number!(deserialize_u32, visit_u32, u32);
+    deserialize_number!(deserialize_u64, visit_u64, u64);
+    deserialize_number!(deserialize_f32, visit_f32, f32);
+    deserialize_number!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        let code = self.read_u32()?;
+        std::char::from_u32(code)
+            .ok_or_else(|| Error(format!("Bad char {}", code)))
+            .and_then(|c| visitor.visit_char(c))
+    }
+
+    fn deserialize_str<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        visitor.visit_borrowed_str(self.read_str()?)
+    }
+
+    fn deserialize_string<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        visitor.visit_borrowed_bytes(self.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        match self.read_bool()? {
+            false => visitor.visit_none(),
+            true => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        let len = self.read_len()?;
+        visitor.visit_seq(Sequence { deserializer: self, remaining: len })
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(self, len: usize, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        visitor.visit_seq(Sequence { deserializer: self, remaining: len })
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        let len = self.read_len()?;
+        visitor.visit_map(Sequence { deserializer: self, remaining: len })
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        visitor.visit_u32(self.read_u32()?)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, _visitor: V)
+        -> Result<V::Value, Error>
+    {
+        Err(Error("Can't skip data in a format which is not self-describing".into()))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Elements of sequences, tuples, structs, and maps.
+/// Map entries count as one element.
+struct Sequence<'a, 'de: 'a> {
+    deserializer: &'a mut Deserializer<'de>,
+    remaining: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Sequence<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T)
+        -> Result<Option<T::Value>, Error>
+    {
+        match self.remaining {
+            0 => Ok(None),
+            _ => {
+                self.remaining -= 1;
+                seed.deserialize(&mut *self.deserializer).map(Some)
+            },
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for Sequence<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K)
+        -> Result<Option<K::Value>, Error>
+    {
+        match self.remaining {
+            0 => Ok(None),
+            _ => {
+                self.remaining -= 1;
+                seed.deserialize(&mut *self.deserializer).map(Some)
+            },
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V)
+        -> Result<V::Value, Error>
+    {
+        seed.deserialize(&mut *self.deserializer)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for &'a mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V)
+        -> Result<(V::Value, Self), Error>
+    {
+        let index = self.read_u32()?;
+        let index: de::value::U32Deserializer<Error> = index.into_deserializer();
+        let value = seed.deserialize(index)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for &'a mut Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T)
+        -> Result<T::Value, Error>
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, len: usize, visitor: V)
+        -> Result<V::Value, Error>
+    {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::{ Deserialize, Serialize };
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rectangle { width: u32, height: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Data {
+        name: String,
+        shapes: Vec<Shape>,
+        tags: HashMap<String, (bool, char)>,
+        comment: Option<String>,
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = Data {
+            name: "shapes".into(),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(2.5),
+                Shape::Rectangle { width: 3, height: 4 },
+            ],
+            tags: hashmap!{ "ß".into() => (true, 'ł') },
+            comment: None,
+        };
+        let bytes = to_vec(&data).unwrap();
+        assert_eq!(from_slice::<Data>(&bytes), Ok(data));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = to_vec(&vec![1u32, 2, 3]).unwrap();
+        assert!(from_slice::<Vec<u32>>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(from_slice::<Vec<u32>>(&bytes[..4]).is_err());
+        assert!(from_slice::<u8>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Layouts built ahead of time.
+ *
+ * Parsing the YAML and generating keymaps takes long on slow phones,
+ * so the built layout can be stored next to the layout file.
+ * The compiled file gets picked up instead of the YAML
+ * as long as it's not older than the YAML.
+ * Otherwise, the YAML gets loaded, and the compiled file gets replaced.
+ *
+ * Layouts which extend or include others are never compiled,
+ * because the compiled file wouldn't know when the others change.
+ * Neither are layouts with conditions, which differ by arrangement and purpose.
+ *
+ * The bottom row settings change the built layout,
+ * so every combination gets its own compiled file.
+ * Compiled files are read through a memory mapping,
+ * and only ever make sense to the same version of Squeekboard.
+ */
+
+use glib;
+use glib_sys;
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{ Path, PathBuf };
+use std::ptr;
+use std::slice;
+
+use super::binary;
+use super::parsing::{ self, BottomRow };
+
+use crate::layout::LayoutData;
+use crate::logging;
+
+// Traits
+use glib::translate::FromGlibPtrFull;
+use std::io::Write;
+
+
+const MAGIC: &[u8; 4] = b"SQKL";
+
+/// Must change whenever anything stored in `LayoutData` changes.
+const VERSION: u32 = 17;
+
+const EXTENSION: &str = "bin";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Map(glib::Error),
+    /// Not a compiled layout, or from another version
+    Header,
+    Data(binary::Error),
+    /// The compiled file is older than the source
+    Stale,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO: {}", e),
+            Error::Map(e) => write!(f, "Mapping: {}", e),
+            Error::Header => write!(f, "Not a compiled layout of this version"),
+            Error::Data(e) => write!(f, "Bad data: {}", e),
+            Error::Stale => write!(f, "Older than the source"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<binary::Error> for Error {
+    fn from(e: binary::Error) -> Self {
+        Error::Data(e)
+    }
+}
+
+/// A letter for every enabled setting, like "lp" for the default.
+fn get_bottom_row_tag(bottom_row: &BottomRow) -> String {
+    [
+        (bottom_row.emoji, 'e'),
+        (bottom_row.voice, 'v'),
+        (bottom_row.language, 'l'),
+        (bottom_row.comma, 'c'),
+        (bottom_row.period, 'p'),
+    ].iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, letter)| *letter)
+        .collect()
+}
+
+/// Where the compiled form of a layout file goes,
+/// e.g. "us.lp.bin" for "us.yaml".
+pub fn get_compiled_path(source: &Path, bottom_row: &BottomRow) -> PathBuf {
+    match get_bottom_row_tag(bottom_row).as_str() {
+        "" => source.with_extension(EXTENSION),
+        tag => source.with_extension(format!("{}.{}", tag, EXTENSION)),
+    }
+}
+
+fn to_bytes(layout: &LayoutData) -> Result<Vec<u8>, binary::Error> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend(binary::to_vec(layout)?);
+    Ok(bytes)
+}
+
+fn from_bytes(bytes: &[u8]) -> Result<LayoutData, Error> {
+    let header_len = MAGIC.len() + 4;
+    if bytes.len() < header_len
+        || bytes[..MAGIC.len()] != MAGIC[..]
+        || bytes[MAGIC.len()..header_len] != VERSION.to_le_bytes()
+    {
+        return Err(Error::Header);
+    }
+    binary::from_slice(&bytes[header_len..]).map_err(Error::Data)
+}
+
+/// A read-only memory mapping of a whole file
+struct MappedFile(*mut glib_sys::GMappedFile);
+
+impl MappedFile {
+    fn open(path: &Path) -> Result<MappedFile, Error> {
+        let name = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        let mut error = ptr::null_mut();
+        let file = unsafe {
+            glib_sys::g_mapped_file_new(name.as_ptr(), glib_sys::GFALSE, &mut error)
+        };
+        match file.is_null() {
+            true => Err(Error::Map(unsafe { glib::Error::from_glib_full(error) })),
+            false => Ok(MappedFile(file)),
+        }
+    }
+
+    fn get_contents(&self) -> &[u8] {
+        unsafe {
+            let length = glib_sys::g_mapped_file_get_length(self.0);
+            // Empty files have no contents pointer
+            match length {
+                0 => &[],
+                length => slice::from_raw_parts(
+                    glib_sys::g_mapped_file_get_contents(self.0) as *const u8,
+                    length,
+                ),
+            }
+        }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe { glib_sys::g_mapped_file_unref(self.0) }
+    }
+}
+
+/// Loads the compiled form of the layout file, if it's up to date.
+pub fn load(source: &Path, bottom_row: &BottomRow)
+    -> Result<LayoutData, Error>
+{
+    let path = get_compiled_path(source, bottom_row);
+    let compiled_time = fs::metadata(&path)?.modified()?;
+    if compiled_time < fs::metadata(source)?.modified()? {
+        return Err(Error::Stale);
+    }
+    let file = MappedFile::open(&path)?;
+    from_bytes(file.get_contents())
+}
+
+/// Stores the built layout next to its source.
+/// The file appears all at once, so that a concurrent load never sees half.
+pub fn store(source: &Path, layout: &LayoutData, bottom_row: &BottomRow)
+    -> Result<PathBuf, Error>
+{
+    let path = get_compiled_path(source, bottom_row);
+    let temporary = path.with_extension(format!("{}.tmp", EXTENSION));
+    let bytes = to_bytes(layout)?;
+    fs::File::create(&temporary)?.write_all(&bytes)?;
+    fs::rename(&temporary, &path)?;
+    Ok(path)
+}
+
+/// Builds the layout file with the default bottom row,
+/// and stores the result next to it.
+/// For compiling ahead of time.
+pub fn compile_file(source: &Path) -> Result<PathBuf, String> {
+    let bottom_row = BottomRow::default();
+    let layout = parsing::Layout::from_file(source.into())
+        .map_err(|e| e.to_string())?;
+    if !layout.can_compile() {
+        return Err("Layouts made of several files or with conditions can't be compiled".into());
+    }
+    let layout = layout
+        .with_bottom_row(&bottom_row)
+        .build(logging::Print).0
+        .map_err(|e| format!("Bad key map: {}", e))?;
+    store(source, &layout, &bottom_row)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build() -> LayoutData {
+        parsing::Layout::from_yaml_str(
+            "outlines:\n    default: { width: 40, height: 50 }\nviews:\n    base:\n        - \"a b c\"\n",
+        )
+            .unwrap()
+            .build(logging::ProblemPanic).0
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = to_bytes(&build()).unwrap();
+        let layout = from_bytes(&bytes).unwrap();
+        assert_eq!(to_bytes(&layout).unwrap(), bytes);
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = to_bytes(&build()).unwrap();
+        assert_matches!(from_bytes(&bytes[..2]), Err(Error::Header));
+        bytes[MAGIC.len()] ^= 1;
+        assert_matches!(from_bytes(&bytes), Err(Error::Header));
+    }
+
+    #[test]
+    fn paths() {
+        let source = Path::new("keyboards/terminal/us.yaml");
+        assert_eq!(
+            get_compiled_path(source, &BottomRow::default()),
+            PathBuf::from("keyboards/terminal/us.lp.bin"),
+        );
+        let bottom_row = BottomRow {
+            emoji: true,
+            language: false,
+            period: false,
+            ..BottomRow::default()
+        };
+        assert_eq!(
+            get_compiled_path(source, &bottom_row),
+            PathBuf::from("keyboards/terminal/us.e.bin"),
+        );
+    }
+}
@@ -0,0 +1,300 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Compose sequences, for the compose picker.
+ *
+ * The sequences come from the same files as for X11 and GTK:
+ * `$XCOMPOSEFILE`, or `~/.XCompose`, or the one for the current locale.
+ * Only sequences starting with `<Multi_key>` are used,
+ * and the picker shows what can follow what was already pressed.
+ */
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+
+use ::logging;
+use ::memory;
+use ::xdg;
+
+
+/// The name under which the picker is shown as an overlay
+pub const OVERLAY_NAME: &str = "compose";
+
+const SYSTEM_DIR: &str = "/usr/share/X11/locale";
+
+/// Continuations in a single row of the picker
+pub const ROW_SIZE: usize = 8;
+/// Continuations on a single page of the picker
+pub const PAGE_SIZE: usize = ROW_SIZE * 4;
+
+/// Includes referring to each other in a loop must end somewhere
+const MAX_INCLUDE_DEPTH: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequence {
+    /// Keysym names, without the leading Multi_key
+    pub keysyms: Vec<String>,
+    pub result: String,
+}
+
+/// What can be pressed next
+#[derive(Clone, Debug, PartialEq)]
+pub struct Continuation {
+    pub keysym: String,
+    /// The text, if the sequence ends here
+    pub result: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Line {
+    Sequence(Sequence),
+    Include(String),
+}
+
+/// Reads the part inside quotes, resolving escapes.
+fn parse_quoted(text: &str) -> Option<String> {
+    let mut chars = text.trim_start().chars();
+    if chars.next() != Some('"') {
+        return None;
+    }
+    let mut out = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(out),
+            '\\' => out.push(chars.next()?),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+/// Parses a line like `<Multi_key> <a> <e> : "æ" ae # comment`
+fn parse_line(line: &str) -> Option<Line> {
+    let line = line.trim();
+    if line.starts_with("include") {
+        return parse_quoted(&line["include".len()..]).map(Line::Include);
+    }
+    let separator = line.find(':')?;
+    let keysyms: Option<Vec<String>> = line[..separator].split_whitespace()
+        .map(|token| {
+            if token.starts_with('<') && token.ends_with('>') && token.len() > 2 {
+                Some(token[1..token.len() - 1].to_string())
+            } else {
+                // Modifiers are not supported
+                None
+            }
+        })
+        .collect();
+    let mut keysyms = keysyms?;
+    if keysyms.len() < 2 || keysyms[0] != "Multi_key" {
+        return None;
+    }
+    keysyms.remove(0);
+    parse_quoted(&line[separator + 1..])
+        .map(|result| Line::Sequence(Sequence { keysyms, result }))
+}
+
+fn get_locale() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Finds the file for the locale in the `compose.dir` index,
+/// with lines like `en_US.UTF-8/Compose:		en_US.UTF-8`.
+fn find_in_index(index: &str, locale: &str) -> Option<String> {
+    index.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let separator = line.find(':')?;
+            Some((&line[..separator], line[separator + 1..].trim()))
+        })
+        .find(|(_, name)| *name == locale)
+        .map(|(file, _)| file.to_string())
+}
+
+fn get_system_file() -> Option<PathBuf> {
+    let index = fs::read_to_string(Path::new(SYSTEM_DIR).join("compose.dir")).ok()?;
+    let locale = get_locale().unwrap_or_else(|| "C".into());
+    find_in_index(&index, &locale)
+        .or_else(|| find_in_index(&index, "en_US.UTF-8"))
+        .map(|file| Path::new(SYSTEM_DIR).join(file))
+}
+
+fn expand_include(path: &str) -> Option<PathBuf> {
+    if path == "%L" {
+        return get_system_file();
+    }
+    let expanded = if path.starts_with("%H") {
+        xdg::home_dir()?.join(path["%H".len()..].trim_start_matches('/'))
+    } else if path.starts_with("%S") {
+        Path::new(SYSTEM_DIR).join(path["%S".len()..].trim_start_matches('/'))
+    } else {
+        PathBuf::from(path)
+    };
+    Some(expanded)
+}
+
+fn read_file(path: &Path, depth: u32, sequences: &mut Vec<Sequence>) {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) => {
+            log_print!(
+                logging::Level::Warning,
+                "Can't read compose file {:?}: {}", path, e,
+            );
+            return;
+        },
+    };
+    for line in data.lines().filter_map(parse_line) {
+        match line {
+            Line::Sequence(sequence) => sequences.push(sequence),
+            Line::Include(include) => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    log_print!(
+                        logging::Level::Warning,
+                        "Compose files nested too deep at {:?}", path,
+                    );
+                } else if let Some(include) = expand_include(&include) {
+                    read_file(&include, depth + 1, sequences);
+                }
+            },
+        }
+    }
+}
+
+fn load_sequences() -> Vec<Sequence> {
+    let path = env::var_os("XCOMPOSEFILE").map(PathBuf::from)
+        .or_else(|| {
+            xdg::home_dir()
+                .map(|home| home.join(".XCompose"))
+                .filter(|path| path.exists())
+        })
+        .or_else(get_system_file);
+    let mut sequences = Vec::new();
+    match path {
+        Some(path) => read_file(&path, 0, &mut sequences),
+        None => log_print!(logging::Level::Warning, "No compose file found"),
+    };
+    sequences
+}
+
+thread_local! {
+    /// Compose files are large, so they are read just once.
+    static SEQUENCES: RefCell<Option<Rc<Vec<Sequence>>>> = RefCell::new(None);
+}
+
+fn get_sequences() -> Rc<Vec<Sequence>> {
+    SEQUENCES.with(|sequences| {
+        sequences.borrow_mut()
+            .get_or_insert_with(|| {
+                let loaded = load_sequences();
+                memory::set_size(memory::Cache::Compose, loaded.len());
+                Rc::new(loaded)
+            })
+            .clone()
+    })
+}
+
+/// Frees the sequences until next needed.
+pub fn drop_cache() {
+    SEQUENCES.with(|sequences| sequences.borrow_mut().take());
+    memory::set_size(memory::Cache::Compose, 0);
+}
+
+fn find_continuations(sequences: &[Sequence], prefix: &[String])
+    -> Vec<Continuation>
+{
+    let mut out: Vec<Continuation> = Vec::new();
+    let matching = sequences.iter()
+        .filter(|s| s.keysyms.len() > prefix.len() && s.keysyms.starts_with(prefix));
+    for sequence in matching {
+        let keysym = &sequence.keysyms[prefix.len()];
+        let continuation = Continuation {
+            keysym: keysym.clone(),
+            result: match sequence.keysyms.len() == prefix.len() + 1 {
+                true => Some(sequence.result.clone()),
+                false => None,
+            },
+        };
+        // Later definitions override earlier ones, e.g. from includes.
+        match out.iter().position(|c| &c.keysym == keysym) {
+            Some(index) => out[index] = continuation,
+            None => out.push(continuation),
+        }
+    }
+    out
+}
+
+/// Returns what can follow the keysyms pressed so far.
+pub fn get_continuations(prefix: &[String]) -> Vec<Continuation> {
+    find_continuations(&get_sequences(), prefix)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn seq(keysyms: &[&str], result: &str) -> Sequence {
+        Sequence {
+            keysyms: keysyms.iter().map(|s| s.to_string()).collect(),
+            result: result.into(),
+        }
+    }
+
+    #[test]
+    fn parse_sequence() {
+        assert_eq!(
+            parse_line("<Multi_key> <a> <e> : \"æ\" ae # LATIN SMALL LETTER AE"),
+            Some(Line::Sequence(seq(&["a", "e"], "æ"))),
+        );
+        assert_eq!(
+            parse_line("<Multi_key> <quotedbl> <backslash> : \"\\\"\\\\\""),
+            Some(Line::Sequence(seq(&["quotedbl", "backslash"], "\"\\"))),
+        );
+        assert_eq!(parse_line("<dead_acute> <a> : \"á\""), None);
+        assert_eq!(parse_line("# <Multi_key> <a> : \"b\""), None);
+        assert_eq!(
+            parse_line("include \"%L\""),
+            Some(Line::Include("%L".into())),
+        );
+    }
+
+    #[test]
+    fn index() {
+        let index = "# comment
+en_US.UTF-8/Compose:		en_US.UTF-8
+pl_PL.UTF-8/Compose:		pl_PL.UTF-8
+";
+        assert_eq!(find_in_index(index, "pl_PL.UTF-8"), Some("pl_PL.UTF-8/Compose".into()));
+        assert_eq!(find_in_index(index, "de_DE.UTF-8"), None);
+    }
+
+    #[test]
+    fn continuations() {
+        let sequences = vec![
+            seq(&["a", "e"], "æ"),
+            seq(&["a", "apostrophe"], "á"),
+            seq(&["o", "c"], "©"),
+            seq(&["a", "e"], "override"),
+        ];
+        assert_eq!(
+            find_continuations(&sequences, &[]),
+            vec![
+                Continuation { keysym: "a".into(), result: None },
+                Continuation { keysym: "o".into(), result: None },
+            ],
+        );
+        assert_eq!(
+            find_continuations(&sequences, &["a".to_string()]),
+            vec![
+                Continuation { keysym: "e".into(), result: Some("override".into()) },
+                Continuation { keysym: "apostrophe".into(), result: Some("á".into()) },
+            ],
+        );
+    }
+}
@@ -0,0 +1,370 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Variables and conditionals in layout files.
+ *
+ * Layout files get expanded before they are parsed,
+ * so that one file can serve several arrangements and purposes.
+ *
+ * The "variables" section gives names to numbers, text, or booleans.
+ * Any text in the layout can refer to them as `${name}`.
+ * Text consisting only of a reference takes on the variable's type,
+ * so that `width: "${key}"` gets a number.
+ *
+ * A mapping entry with a key like `if arrangement == wide`
+ * holds a mapping, whose entries get added when the condition holds.
+ * Later entries win, so conditionals go after the defaults.
+ * In a list, an item like `if purpose != terminal: [...]`
+ * stands for the items inside, or for nothing.
+ * Conditions compare `arrangement` or `purpose` to a value,
+ * with `==` or `!=`, and can be joined with `and`.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_yaml::{ Mapping, Value };
+
+use ::imservice::ContentPurpose;
+use ::layout::ArrangementKind;
+
+
+const ARRANGEMENTS: &[(&str, ArrangementKind)] = &[
+    ("base", ArrangementKind::Base),
+    ("wide", ArrangementKind::Wide),
+    ("compact", ArrangementKind::Compact),
+];
+
+const PURPOSES: &[(&str, ContentPurpose)] = &[
+    ("normal", ContentPurpose::Normal),
+    ("alpha", ContentPurpose::Alpha),
+    ("digits", ContentPurpose::Digits),
+    ("number", ContentPurpose::Number),
+    ("phone", ContentPurpose::Phone),
+    ("url", ContentPurpose::Url),
+    ("email", ContentPurpose::Email),
+    ("name", ContentPurpose::Name),
+    ("password", ContentPurpose::Password),
+    ("pin", ContentPurpose::Pin),
+    ("date", ContentPurpose::Date),
+    ("time", ContentPurpose::Time),
+    ("datetime", ContentPurpose::Datetime),
+    ("terminal", ContentPurpose::Terminal),
+];
+
+const VARIABLES: &str = "variables";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnknownVariable(String),
+    /// Variables must be numbers, text, or booleans
+    BadVariable(String),
+    BadCondition(String),
+    /// The conditional entry of a mapping doesn't hold a mapping
+    NotMapping(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownVariable(name) => write!(f, "Unknown variable {}", name),
+            Error::BadVariable(name) => write!(f, "Variable {} is not a number, text, or boolean", name),
+            Error::BadCondition(condition) => write!(f, "Bad condition \"{}\"", condition),
+            Error::NotMapping(condition) => write!(f, "\"{}\" must hold a mapping", condition),
+        }
+    }
+}
+
+/// What the conditions get evaluated against
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    /// The arrangement asked for, even if the file isn't named after it
+    pub arrangement: ArrangementKind,
+    pub purpose: ContentPurpose,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context {
+            arrangement: ArrangementKind::Base,
+            purpose: ContentPurpose::Normal,
+        }
+    }
+}
+
+fn get_name<T: PartialEq>(names: &[(&'static str, T)], value: &T) -> &'static str {
+    names.iter()
+        .find(|(_, v)| v == value)
+        .map(|(name, _)| *name)
+        .expect("Value missing from the names")
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (arrangement, purpose) = self.get_names();
+        write!(f, "arrangement {}, purpose {}", arrangement, purpose)
+    }
+}
+
+impl Context {
+    /// Takes the names used in conditions.
+    pub fn from_names(arrangement: &str, purpose: &str) -> Option<Context> {
+        let arrangement = ARRANGEMENTS.iter().find(|(name, _)| *name == arrangement)?.1;
+        let purpose = PURPOSES.iter().find(|(name, _)| *name == purpose)?.1;
+        Some(Context { arrangement, purpose })
+    }
+
+    /// The names used in conditions.
+    pub fn get_names(&self) -> (&'static str, &'static str) {
+        (
+            get_name(ARRANGEMENTS, &self.arrangement),
+            get_name(PURPOSES, &self.purpose),
+        )
+    }
+
+    /// Every combination, for checking layouts.
+    pub fn all() -> Vec<Context> {
+        ARRANGEMENTS.iter()
+            .flat_map(|(_, arrangement)| {
+                PURPOSES.iter().map(move |(_, purpose)| Context {
+                    arrangement: *arrangement,
+                    purpose: *purpose,
+                })
+            })
+            .collect()
+    }
+
+    /// Takes the text after "if".
+    fn evaluate(&self, condition: &str) -> Result<bool, Error> {
+        let bad = || Error::BadCondition(condition.into());
+        let mut holds = true;
+        for clause in condition.split(" and ") {
+            let parts: Vec<&str> = clause.split_whitespace().collect();
+            let (name, operator, value) = match parts.as_slice() {
+                [name, operator, value] => (*name, *operator, *value),
+                _ => return Err(bad()),
+            };
+            let is_equal = match name {
+                "arrangement" => {
+                    ARRANGEMENTS.iter().find(|(n, _)| *n == value).ok_or_else(bad)?.1
+                        == self.arrangement
+                },
+                "purpose" => {
+                    PURPOSES.iter().find(|(n, _)| *n == value).ok_or_else(bad)?.1
+                        == self.purpose
+                },
+                _ => return Err(bad()),
+            };
+            holds &= match operator {
+                "==" => is_equal,
+                "!=" => !is_equal,
+                _ => return Err(bad()),
+            };
+        }
+        Ok(holds)
+    }
+}
+
+/// The condition, if the key makes the entry conditional.
+fn get_condition(key: &Value) -> Option<&str> {
+    match key {
+        Value::String(key) if key.starts_with("if ") => Some(&key[3..]),
+        _ => None,
+    }
+}
+
+fn to_text(value: &Value, name: &str) -> Result<String, Error> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(Error::BadVariable(name.into())),
+    }
+}
+
+struct Expander<'a> {
+    context: &'a Context,
+    variables: HashMap<String, Value>,
+    /// Some condition was found
+    conditional: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn get_variable(&self, name: &str) -> Result<&Value, Error> {
+        self.variables.get(name)
+            .ok_or_else(|| Error::UnknownVariable(name.into()))
+    }
+
+    fn substitute(&self, text: &str) -> Result<Value, Error> {
+        // Takes on the type of the variable
+        if text.starts_with("${") && text.ends_with('}') && text.matches("${").count() == 1 {
+            return self.get_variable(&text[2..text.len() - 1]).map(Value::clone);
+        }
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("${") {
+            let end = rest[start..].find('}')
+                .ok_or_else(|| Error::UnknownVariable(rest[start..].into()))?;
+            let name = &rest[start + 2..start + end];
+            result.push_str(&rest[..start]);
+            result.push_str(&to_text(self.get_variable(name)?, name)?);
+            rest = &rest[start + end + 1..];
+        }
+        result.push_str(rest);
+        Ok(Value::String(result))
+    }
+
+    fn expand_mapping(&mut self, mapping: Mapping) -> Result<Mapping, Error> {
+        let mut expanded = Mapping::new();
+        for (key, value) in mapping {
+            if let Some(condition) = get_condition(&key) {
+                self.conditional = true;
+                if self.context.evaluate(condition)? {
+                    match value {
+                        Value::Mapping(entries) => {
+                            for (key, value) in self.expand_mapping(entries)? {
+                                expanded.insert(key, value);
+                            }
+                        },
+                        _ => return Err(Error::NotMapping(condition.into())),
+                    }
+                }
+            } else {
+                let key = self.expand(key)?;
+                let value = self.expand(value)?;
+                expanded.insert(key, value);
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn expand_sequence(&mut self, items: Vec<Value>) -> Result<Vec<Value>, Error> {
+        let mut expanded = Vec::new();
+        for item in items {
+            let conditional = match &item {
+                Value::Mapping(mapping) if mapping.len() == 1 => {
+                    mapping.iter()
+                        .next()
+                        .and_then(|(key, value)| {
+                            get_condition(key).map(|c| (c.to_string(), value.clone()))
+                        })
+                },
+                _ => None,
+            };
+            match conditional {
+                Some((condition, value)) => {
+                    self.conditional = true;
+                    if self.context.evaluate(&condition)? {
+                        match value {
+                            Value::Sequence(items) => expanded.extend(self.expand_sequence(items)?),
+                            value => expanded.push(self.expand(value)?),
+                        }
+                    }
+                },
+                None => expanded.push(self.expand(item)?),
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn expand(&mut self, value: Value) -> Result<Value, Error> {
+        match value {
+            Value::Mapping(mapping) => self.expand_mapping(mapping).map(Value::Mapping),
+            Value::Sequence(items) => self.expand_sequence(items).map(Value::Sequence),
+            Value::String(text) => self.substitute(&text),
+            other => Ok(other),
+        }
+    }
+}
+
+/// Takes out the variables, and applies them and the conditions to the rest.
+/// Also tells whether there were any conditions.
+pub fn expand(document: Value, context: &Context) -> Result<(Value, bool), Error> {
+    let mut document = match document {
+        Value::Mapping(mapping) => mapping,
+        // Not a layout, but that's for the parser to tell
+        other => return Ok((other, false)),
+    };
+    let mut expander = Expander {
+        context,
+        variables: HashMap::new(),
+        conditional: false,
+    };
+    let variables = document.remove(&Value::String(VARIABLES.into()));
+    if let Some(Value::Mapping(variables)) = variables {
+        for (name, value) in expander.expand_mapping(variables)? {
+            let name = to_text(&name, "")?;
+            to_text(&value, &name)?;
+            expander.variables.insert(name, value);
+        }
+    }
+    let document = expander.expand_mapping(document)?;
+    Ok((Value::Mapping(document), expander.conditional))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expand_str(yaml: &str, context: &Context) -> Result<Value, Error> {
+        expand(serde_yaml::from_str(yaml).unwrap(), context).map(|(value, _)| value)
+    }
+
+    const LAYOUT: &str = "
+variables:
+    width: 48
+    if arrangement == wide:
+        width: 64
+outlines:
+    default: { width: \"${width}\", height: 52 }
+views:
+    base:
+        - \"a b c\"
+        - if purpose == terminal: [\"Ctrl Tab\"]
+        - \"x${width}\"
+";
+
+    #[test]
+    fn variants() {
+        let wide = Context {
+            arrangement: ArrangementKind::Wide,
+            purpose: ContentPurpose::Terminal,
+        };
+        assert_eq!(
+            expand_str(LAYOUT, &wide),
+            Ok(serde_yaml::from_str("
+outlines:
+    default: { width: 64, height: 52 }
+views:
+    base: [\"a b c\", \"Ctrl Tab\", \"x64\"]
+").unwrap()),
+        );
+        assert_eq!(
+            expand_str(LAYOUT, &Context::default()),
+            Ok(serde_yaml::from_str("
+outlines:
+    default: { width: 48, height: 52 }
+views:
+    base: [\"a b c\", \"x48\"]
+").unwrap()),
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let context = Context::default();
+        assert_eq!(
+            expand_str("a: \"${b}\"", &context),
+            Err(Error::UnknownVariable("b".into())),
+        );
+        assert_eq!(
+            expand_str("if arrangement = wide: {}", &context),
+            Err(Error::BadCondition("arrangement = wide".into())),
+        );
+        assert_eq!(
+            expand_str("if purpose == tv: {}", &context),
+            Err(Error::BadCondition("purpose == tv".into())),
+        );
+    }
+}
@@ -0,0 +1,222 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Describing a loaded layout in JSON, for other programs.
+ *
+ * Layout editors, screenshot tools and the like
+ * get the layout the way it's shown, after all the merging and expanding,
+ * rather than having to interpret the layout files again.
+ *
+ * Positions and sizes are in the layout's own units, like in the files.
+ * Every view gets described, positioned within the whole layout,
+ * so a button's place is where the panel would draw it.
+ */
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_yaml::{ Mapping, Number, Value };
+
+use super::expansion::Context;
+use super::parsing;
+use ::layout::{ Label, Layout };
+use ::layout::c::Point;
+use ::logging;
+
+
+fn field<K: Into<String>>(map: &mut Mapping, key: K, value: Value) {
+    map.insert(Value::String(key.into()), value);
+}
+
+fn float(f: f64) -> Value {
+    Value::Number(Number::from(f))
+}
+
+fn place(map: &mut Mapping, position: &Point, width: f64, height: f64) {
+    field(map, "x", float(position.x));
+    field(map, "y", float(position.y));
+    field(map, "width", float(width));
+    field(map, "height", float(height));
+}
+
+/// Builds all views, to describe them too.
+pub fn describe(layout: &mut Layout) -> Value {
+    for (_offset, view) in layout.views.values_mut() {
+        view.finalize();
+    }
+
+    let mut views = BTreeMap::new();
+    for (name, (view_offset, view)) in &layout.views {
+        let view = view.get().expect("View not built");
+        let rows = view.get_rows().iter()
+            .map(|(row_offset, row)| {
+                let row_offset = view_offset + row_offset.clone();
+                let buttons = row.get_buttons().iter()
+                    .map(|(x_offset, button)| {
+                        let mut out = Mapping::new();
+                        field(&mut out, "name", Value::String(
+                            button.name.to_string_lossy().into_owned()
+                        ));
+                        match &button.label {
+                            Label::Text(text) => field(&mut out, "label", Value::String(
+                                text.to_string_lossy().into_owned()
+                            )),
+                            Label::IconName(icon) => field(&mut out, "icon", Value::String(
+                                icon.to_string_lossy().into_owned()
+                            )),
+                        }
+                        let position = &row_offset + Point { x: *x_offset, y: 0.0 };
+                        place(&mut out, &position, button.size.width, button.size.height);
+                        let action = serde_yaml::to_value(&layout.keys.get(button.key).action)
+                            .unwrap_or(Value::Null);
+                        field(&mut out, "action", action);
+                        Value::Mapping(out)
+                    })
+                    .collect();
+                let size = row.get_size();
+                let mut out = Mapping::new();
+                place(&mut out, &row_offset, size.width, size.height);
+                field(&mut out, "buttons", Value::Sequence(buttons));
+                Value::Mapping(out)
+            })
+            .collect();
+        let size = view.get_size();
+        let mut out = Mapping::new();
+        place(&mut out, view_offset, size.width, size.height);
+        field(&mut out, "rows", Value::Sequence(rows));
+        views.insert(name.clone(), Value::Mapping(out));
+    }
+
+    let (arrangement, purpose) = Context {
+        arrangement: layout.kind,
+        purpose: layout.purpose,
+    }.get_names();
+    let mut margins = Mapping::new();
+    field(&mut margins, "top", float(layout.margins.top));
+    field(&mut margins, "bottom", float(layout.margins.bottom));
+    field(&mut margins, "left", float(layout.margins.left));
+    field(&mut margins, "right", float(layout.margins.right));
+
+    let mut out = Mapping::new();
+    field(&mut out, "arrangement", Value::String(arrangement.into()));
+    field(&mut out, "purpose", Value::String(purpose.into()));
+    field(&mut out, "current_view", Value::String(layout.current_view.clone()));
+    field(&mut out, "margins", Value::Mapping(margins));
+    field(&mut out, "views", Value::Mapping(
+        views.into_iter().map(|(name, view)| (Value::String(name), view)).collect()
+    ));
+    Value::Mapping(out)
+}
+
+fn write_string(text: &str, out: &mut String) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Starts a line at the depth, when indenting.
+fn new_line(indent: Option<usize>, out: &mut String) {
+    if let Some(depth) = indent {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+}
+
+/// Only values built like in `describe` need to work.
+/// Their mapping keys are text, so others only get a readable stand-in.
+/// Without the indent, everything ends up on one line.
+fn write_value(value: &Value, out: &mut String, indent: Option<usize>) {
+    let inner = indent.map(|depth| depth + 1);
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::Number(n) => match n.as_f64() {
+            // JSON has no infinity
+            Some(f) if !f.is_finite() => out.push_str("null"),
+            _ => out.push_str(&n.to_string()),
+        },
+        Value::String(text) => write_string(text, out),
+        Value::Sequence(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                new_line(inner, out);
+                write_value(item, out, inner);
+            }
+            if !items.is_empty() {
+                new_line(indent, out);
+            }
+            out.push(']');
+        },
+        Value::Mapping(map) => {
+            out.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                new_line(inner, out);
+                match key {
+                    Value::String(key) => write_string(key, out),
+                    other => write_string(&format!("{:?}", other), out),
+                }
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                write_value(value, out, inner);
+            }
+            if !map.is_empty() {
+                new_line(indent, out);
+            }
+            out.push('}');
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::layout_dsl;
+    use ::layout_dsl::{ key, ViewSpec };
+
+    #[test]
+    fn escaping() {
+        let mut out = String::new();
+        write_string("a\"\\\n\t", &mut out);
+        assert_eq!(out, "\"a\\\"\\\\\\n\\u0009\"");
+    }
+
+    #[test]
+    fn pretty() {
+        let value: Value = serde_yaml::from_str("{a: [1, 2], b: {}}").unwrap();
+        assert_eq!(value_to_json(&value), "{\"a\":[1,2],\"b\":{}}");
+        assert_eq!(
+            value_to_json_pretty(&value),
+            "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": {}\n}\n",
+        );
+    }
+
+    #[test]
+    fn views() {
+        // The view! macro is not available here yet.
+        let mut layout = layout_dsl::layout(vec![
+            ("base", ViewSpec { rows: vec![vec![key("a"), key("b")]] }),
+        ]);
+        let json = to_json(&mut layout);
+        assert!(json.starts_with("{\"arrangement\":\"base\",\"purpose\":\"normal\""));
+        assert!(json.contains(
+            "{\"name\":\"b\",\"label\":\"b\",\"x\":1.0,\"y\":0.0,\"width\":1.0,\"height\":1.0,"
+        ));
+    }
+}
@@ -4,21 +4,40 @@
 
 /*! Loading layout files */
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
 use std::env;
 use std::fmt;
+use std::io;
 use std::path::PathBuf;
 
 use super::{ Error, LoadError };
+use super::compiled;
+use super::compose;
+use super::expansion::Context;
 use super::parsing;
+use super::symbols;
 
 use crate::layout;
-use crate::layout::ArrangementKind;
+use crate::layout::{ ArrangementKind, LetterCase };
 use crate::logging;
+use crate::resources;
 use crate::xdg;
-use crate::imservice::ContentPurpose;
+use crate::imservice::{ ContentHint, ContentPurpose };
 
+// Traits
+use std::hash::{ BuildHasher, Hasher };
 
-const FALLBACK_LAYOUT_NAME: &str = "us";
+
+pub const FALLBACK_LAYOUT_NAME: &str = "us";
+
+/// Layouts whose letters are not Latin.
+/// Variants count too, unless listed with their variant.
+const NON_LATIN_LAYOUTS: &[&str] = &[
+    "am", "ara", "bg", "by", "ge", "gr", "il", "in", "ir", "jp+kana",
+    "kz", "mk", "ru", "th", "ua",
+];
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -46,17 +65,19 @@ impl fmt::Display for DataSource {
 
 /// Returns ordered names treating `name` as the base name,
 /// ignoring any `+` inside.
+/// Compact arrangements fall back to wide ones,
+/// which fit the same screens better than the base.
 fn _get_arrangement_names(name: &str, arrangement: ArrangementKind)
     -> Vec<(ArrangementKind, String)>
 {
-    let name_with_arrangement = match arrangement {    
-        ArrangementKind::Base => name.into(),
-        ArrangementKind::Wide => format!("{}_wide", name),
-    };
-    
     let mut ret = Vec::new();
-    if name_with_arrangement != name {
-        ret.push((arrangement, name_with_arrangement));
+    if let ArrangementKind::Compact = arrangement {
+        ret.push((arrangement, format!("{}_compact", name)));
+    }
+    match arrangement {
+        ArrangementKind::Wide | ArrangementKind::Compact
+            => ret.push((ArrangementKind::Wide, format!("{}_wide", name))),
+        ArrangementKind::Base => {},
     }
     ret.push((ArrangementKind::Base, name.into()));
     ret
@@ -119,9 +140,9 @@ fn get_directory_string(
     let layout_purpose = match overlay {
         None => match content_purpose {
             ContentPurpose::Email => Special("email"),
-            ContentPurpose::Digits => Special("number"),
+            ContentPurpose::Digits => Special("phone"),
             ContentPurpose::Number => Special("number"),
-            ContentPurpose::Phone => Special("number"),
+            ContentPurpose::Phone => Special("phone"),
             ContentPurpose::Pin => Special("pin"),
             ContentPurpose::Terminal => Special("terminal"),
             ContentPurpose::Url => Special("url"),
@@ -140,18 +161,64 @@ fn get_directory_string(
     }
 }
 
+/// Returns whether the layout can type Latin letters.
+pub fn is_latin(name: &str) -> bool {
+    let base = name.splitn(2, '+').next().unwrap_or(name);
+    !(NON_LATIN_LAYOUTS.contains(&name) || NON_LATIN_LAYOUTS.contains(&base))
+}
+
+/// Returns the name of the layout to show in a text field with the hint.
+pub fn get_hinted_name(name: &str, hint: ContentHint) -> &str {
+    if hint.contains(ContentHint::LATIN) && !is_latin(name) {
+        FALLBACK_LAYOUT_NAME
+    } else {
+        name
+    }
+}
+
+/// Returns the letter case to start with in a text field with the hint.
+pub fn get_letter_case(hint: ContentHint) -> Option<LetterCase> {
+    if hint.contains(ContentHint::UPPERCASE) {
+        Some(LetterCase::Upper)
+    } else if hint.contains(ContentHint::TITLECASE) {
+        Some(LetterCase::Title)
+    } else if hint.contains(ContentHint::LOWERCASE) {
+        Some(LetterCase::Lower)
+    } else {
+        None
+    }
+}
+
+/// Keypads are interchangeable:
+/// a phone pad is better than a full keyboard when entering numbers,
+/// even if someone's custom layouts only include a calculator.
+fn get_fallback_directory(content_purpose: ContentPurpose, overlay: Option<&str>)
+    -> Option<String>
+{
+    match (overlay, content_purpose) {
+        (None, ContentPurpose::Digits)
+            | (None, ContentPurpose::Phone)
+        => Some("number/".into()),
+        _ => None,
+    }
+}
+
 /// Returns an iterator over all fallback paths.
 fn to_layout_paths(
     name_fallbacks: Vec<(ArrangementKind, String)>,
     content_purpose: ContentPurpose,
     overlay: Option<&str>,
 ) -> impl Iterator<Item=(ArrangementKind, LayoutPath)> {
-    let prepend_directory = get_directory_string(content_purpose, overlay);
-
-    name_fallbacks.into_iter()
-        .map(move |(arrangement, name)|
-            (arrangement, format!("{}{}", prepend_directory, name))
-        )
+    let mut directories = vec![get_directory_string(content_purpose, overlay)];
+    directories.extend(get_fallback_directory(content_purpose, overlay));
+
+    directories.into_iter()
+        .flat_map(move |directory| {
+            name_fallbacks.clone().into_iter()
+                .map(move |(arrangement, name)|
+                    (arrangement, format!("{}{}", directory, name))
+                )
+        })
 }
 
 type LayoutSource = (ArrangementKind, DataSource);
@@ -191,41 +258,139 @@ fn iter_layout_sources(
     to_layout_sources(paths, layout_storage)
 }
 
-fn load_layout_data(source: DataSource)
-    -> Result<::layout::LayoutData, LoadError>
-{
-    let handler = logging::Print {};
+/// Returns the built-in layout to use when there are no layout files,
+/// together with its arrangement.
+pub fn find_builtin(
+    name: &str,
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+) -> Option<(ArrangementKind, String)> {
+    iter_layout_sources(name, kind, purpose, overlay, None)
+        .filter_map(|(kind, source)| match source {
+            DataSource::Resource(name) => Some((kind, name)),
+            DataSource::File(_) => None,
+        })
+        .find(|(_kind, name)| resources::get_keyboard(name).is_some())
+}
+
+/// The context holds the requested arrangement,
+/// for the conditions in layouts found for another one.
+fn load_layout_data(
+    source: DataSource,
+    context: &Context,
+    bottom_row: &parsing::BottomRow,
+) -> Result<::layout::LayoutData, LoadError> {
     match source {
         DataSource::File(path) => {
-            parsing::Layout::from_file(path.clone())
-                .map_err(LoadError::BadData)
-                .and_then(|layout|
-                    layout.build(handler).0.map_err(LoadError::BadKeyMap)
-                )
+            // Extra keys come in too many combinations to store each.
+            let compile = bottom_row.extra_keys.is_empty();
+            if compile {
+                match compiled::load(&path, bottom_row) {
+                    Ok(layout) => return Ok(layout),
+                    Err(compiled::Error::Io(ref e))
+                        if e.kind() == io::ErrorKind::NotFound
+                    => {},
+                    Err(e) => log_print!(
+                        logging::Level::Debug,
+                        "Not using the compiled form of {:?}: {}",
+                        path, e,
+                    ),
+                }
+            }
+            let layout = parsing::Layout::from_file_for(path.clone(), context)
+                .map_err(LoadError::BadData)?;
+            // The compiled form would miss changes to the parent layout
+            // or the fragments, and would serve every context the same.
+            if !compile || !layout.can_compile() {
+                return layout.with_bottom_row(bottom_row)
+                    .build_lazily().map_err(LoadError::BadKeyMap);
+            }
+            // Every view gets built, because all get stored
+            let layout = layout.with_bottom_row(bottom_row)
+                .build(logging::Print).0
+                .map_err(LoadError::BadKeyMap)?;
+            // Next time will be faster
+            if let Err(e) = compiled::store(&path, &layout, bottom_row) {
+                log_print!(
+                    logging::Level::Debug,
+                    "Can't store the compiled form of {:?}: {}",
+                    path, e,
+                );
+            }
+            Ok(layout)
         },
         DataSource::Resource(name) => {
-            parsing::Layout::from_resource(&name)
+            parsing::Layout::from_resource_for(&name, context)
                 .and_then(|layout|
-                    layout.build(handler).0.map_err(LoadError::BadKeyMap)
+                    layout.with_bottom_row(bottom_row)
+                        .build_lazily().map_err(LoadError::BadKeyMap)
                 )
         },
     }
 }
 
+/// Where the user can place own layouts
+pub fn get_layouts_dir() -> Option<PathBuf> {
+    env::var_os("SQUEEKBOARD_KEYBOARDSDIR")
+        .map(PathBuf::from)
+        .or_else(|| xdg::data_path("squeekboard/keyboards"))
+}
+
+/// The requested layout has no definition,
+/// so the default one got loaded in its place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitute {
+    pub requested: String,
+}
+
+/// Returns the layout name without the directory and arrangement suffix.
+fn get_source_name(source: &DataSource) -> Option<String> {
+    let name = match source {
+        DataSource::File(path) => path.file_stem()?.to_str()?,
+        DataSource::Resource(name) => name.rsplit('/').next()?,
+    };
+    let name = ["_wide", "_compact"].iter()
+        .find(|suffix| name.ends_with(*suffix))
+        .map(|suffix| &name[..name.len() - suffix.len()])
+        .unwrap_or(name);
+    Some(name.into())
+}
+
+/// Only cares about text layouts.
+/// Special purposes fall back to the default as a matter of course.
+fn get_substitute(
+    requested: &str,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+    found: &DataSource,
+) -> Option<Substitute> {
+    let base = requested.splitn(2, '+').next().unwrap_or(requested);
+    let is_text = get_directory_string(purpose, overlay) == "";
+    let is_fallback
+        = get_source_name(found).as_ref().map(String::as_str)
+            == Some(FALLBACK_LAYOUT_NAME);
+    if is_text && is_fallback && base != FALLBACK_LAYOUT_NAME {
+        Some(Substitute { requested: requested.into() })
+    } else {
+        None
+    }
+}
+
 fn load_layout_data_with_fallback(
     name: &str,
     kind: ArrangementKind,
     purpose: ContentPurpose,
     overlay: Option<&str>,
-) -> (ArrangementKind, layout::LayoutData) {
+    bottom_row: &parsing::BottomRow,
+) -> (ArrangementKind, layout::LayoutData, DataSource) {
 
     // Build the path to the right keyboard layout subdirectory
-    let path = env::var_os("SQUEEKBOARD_KEYBOARDSDIR")
-        .map(PathBuf::from)
-        .or_else(|| xdg::data_path("squeekboard/keyboards"));
+    let path = get_layouts_dir();
+    let context = Context { arrangement: kind, purpose };
 
     for (kind, source) in iter_layout_sources(&name, kind, purpose, overlay, path) {
-        let layout = load_layout_data(source.clone());
+        let layout = load_layout_data(source.clone(), &context, bottom_row);
         match layout {
             Err(e) => match (e, source) {
                 (
@@ -244,7 +409,7 @@ fn load_layout_data_with_fallback(
             },
             Ok(layout) => {
                 log_print!(logging::Level::Info, "Loaded layout {}", source);
-                return (kind, layout);
+                return (kind, layout, source);
             }
         }
     }
@@ -252,16 +417,164 @@ fn load_layout_data_with_fallback(
     panic!("No useful layout found!");
 }
 
+/// Also reports if the layout had to be replaced by the default one.
 pub fn load_layout(
     name: &String,
     kind: ArrangementKind,
     variant: ContentPurpose,
     overlay: &Option<String>,
-) -> layout::Layout {
+    bottom_row: &parsing::BottomRow,
+) -> (layout::Layout, Option<Substitute>) {
     let overlay = overlay.as_ref().map(String::as_str);
-    let (found_kind, layout)
-        = load_layout_data_with_fallback(name, kind, variant, overlay);
-    layout::Layout::new(layout, found_kind, variant)
+    let (found_kind, layout, source)
+        = load_layout_data_with_fallback(name, kind, variant, overlay, bottom_row);
+    let substitute = get_substitute(name, variant, overlay, &source);
+    (layout::Layout::new(layout, found_kind, variant), substitute)
+}
+
+/// What the panel height depends on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shape {
+    /// The arrangement actually found, which may differ from the requested
+    pub kind: ArrangementKind,
+    pub rows: u32,
+    /// Height divided by width
+    pub aspect_ratio: f64,
+    /// Shortest button side divided by the height,
+    /// or None if the view has no buttons
+    pub smallest_key: Option<f64>,
+    /// Covers the whole output, whatever the other values
+    pub floating: bool,
+}
+
+type ShapeKey = (String, ArrangementKind, ContentPurpose, Option<String>, String);
+
+thread_local! {
+    /// The shape is needed every time the state changes,
+    /// and loading layouts takes a while.
+    static SHAPES: RefCell<HashMap<ShapeKey, Option<Shape>>>
+        = RefCell::new(HashMap::new());
+}
+
+fn find_shape(
+    name: &str,
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+    view: &str,
+) -> Option<Shape> {
+    let context = Context { arrangement: kind, purpose };
+    iter_layout_sources(name, kind, purpose, overlay, get_layouts_dir())
+        .filter_map(|(kind, source)|
+            // The bottom row only changes button widths.
+            load_layout_data(source, &context, &parsing::BottomRow::default()).ok()
+                .map(|data| layout::Layout::new(data, kind, purpose))
+        )
+        .next()
+        .and_then(|mut layout| {
+            // Like when the view can't be carried over from another layout
+            let view = match layout.views.contains_key(view) {
+                true => view,
+                false => "base",
+            };
+            Some(Shape {
+                kind: layout.kind,
+                rows: layout.get_row_count(view)? as u32,
+                aspect_ratio: layout.get_aspect_ratio(view)?,
+                smallest_key: layout.get_smallest_button(view),
+                floating: layout.floating.is_some(),
+            })
+        })
+}
+
+/// Returns the shape of the view in the layout which `load_layout` would pick.
+/// Layouts not coming from files, like the symbols picker, have no shape.
+pub fn get_shape(
+    name: &str,
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+    view: &str,
+) -> Option<Shape> {
+    let key = (name.into(), kind, purpose, overlay.map(String::from), view.into());
+    SHAPES.with(|shapes| {
+        *shapes.borrow_mut()
+            .entry(key)
+            .or_insert_with(|| find_shape(name, kind, purpose, overlay, view))
+    })
+}
+
+/// Drops the shapes found so far, for when the layout files may have changed.
+pub fn forget_shapes() {
+    SHAPES.with(|shapes| shapes.borrow_mut().clear());
+}
+
+/// Returns the digits in random order.
+/// The randomness comes from the standard library's hash seeds,
+/// which is plenty against someone looking over the shoulder.
+fn get_shuffled_digits() -> [char; 10] {
+    let mut digits = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'];
+    let mut seed = RandomState::new().build_hasher().finish();
+    // Fisher-Yates, with a xorshift generator
+    for i in (1..digits.len()).rev() {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let j = (seed % (i as u64 + 1)) as usize;
+        digits.swap(i, j);
+    }
+    digits
+}
+
+/// Builds a PIN layout with the digits scattered randomly,
+/// ignoring any layout files.
+pub fn load_scrambled_pin(kind: ArrangementKind) -> layout::Layout {
+    let data = parsing::Layout::pin_pad(&get_shuffled_digits())
+        .build(logging::Print {}).0
+        .expect("Generated PIN layout is broken");
+    layout::Layout::new(data, kind, ContentPurpose::Pin)
+}
+
+/// Builds the symbols picker, ignoring any layout files.
+pub fn load_symbols(
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    query: Option<&str>,
+) -> layout::Layout {
+    let data = parsing::Layout::symbol_picker(&symbols::get_groups(query))
+        .build_lazily()
+        .expect("Generated symbols layout is broken");
+    layout::Layout::new(data, kind, purpose)
+}
+
+/// Builds the compose picker for the keysyms pressed so far.
+pub fn load_compose(
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    sequence: &[String],
+) -> layout::Layout {
+    let continuations = compose::get_continuations(sequence);
+    let data = parsing::Layout::compose_picker(sequence, &continuations)
+        .build_lazily()
+        .expect("Generated compose layout is broken");
+    layout::Layout::new(data, kind, purpose)
+}
+
+/// Builds a layout which doesn't come from any file,
+/// e.g. one sent by a layout editor.
+pub fn load_layout_from_str(
+    data: &str,
+    kind: ArrangementKind,
+    variant: ContentPurpose,
+    bottom_row: &parsing::BottomRow,
+) -> Result<layout::Layout, LoadError> {
+    parsing::Layout::from_yaml_str(data)
+        .map_err(LoadError::BadData)
+        .and_then(|layout|
+            layout.with_bottom_row(bottom_row)
+                .build(logging::Print {}).0.map_err(LoadError::BadKeyMap)
+        )
+        .map(|layout| layout::Layout::new(layout, kind, variant))
 }
 
 #[cfg(test)]
@@ -335,6 +648,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preferences_order_compact() {
+        let sources = iter_layout_sources("us", ArrangementKind::Compact, ContentPurpose::Normal, None, None);
+
+        assert_eq!(
+            sources.collect::<Vec<_>>()[..3].to_vec(),
+            vec!(
+                (ArrangementKind::Compact, DataSource::Resource("us_compact".into())),
+                (ArrangementKind::Wide, DataSource::Resource("us_wide".into())),
+                (ArrangementKind::Base, DataSource::Resource("us".into())),
+            )
+        );
+    }
+
     #[test]
     fn test_preferences_order_arrangement() {
         let sources = iter_layout_sources("nb", ArrangementKind::Wide, ContentPurpose::Normal, None, None);
@@ -387,4 +714,92 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn latin_hint() {
+        assert_eq!(get_hinted_name("ru", ContentHint::LATIN), "us");
+        assert_eq!(get_hinted_name("ru", ContentHint::NONE), "ru");
+        assert_eq!(get_hinted_name("jp+kana", ContentHint::LATIN), "us");
+        assert_eq!(get_hinted_name("de", ContentHint::LATIN), "de");
+        assert_eq!(get_hinted_name("us+colemak", ContentHint::LATIN), "us+colemak");
+    }
+
+    #[test]
+    fn case_hints() {
+        assert_eq!(get_letter_case(ContentHint::NONE), None);
+        assert_eq!(get_letter_case(ContentHint::LOWERCASE), Some(LetterCase::Lower));
+        assert_eq!(get_letter_case(ContentHint::TITLECASE), Some(LetterCase::Title));
+        assert_eq!(
+            get_letter_case(ContentHint::UPPERCASE | ContentHint::LOWERCASE),
+            Some(LetterCase::Upper),
+        );
+    }
+
+    #[test]
+    fn substitute_missing() {
+        assert_eq!(
+            get_substitute(
+                "xx+aliens",
+                ContentPurpose::Normal,
+                None,
+                &DataSource::File("./us_wide.yaml".into()),
+            ),
+            Some(Substitute { requested: "xx+aliens".into() }),
+        );
+        assert_eq!(
+            get_substitute(
+                "nb",
+                ContentPurpose::Normal,
+                None,
+                &DataSource::Resource("nb".into()),
+            ),
+            None,
+        );
+        assert_eq!(
+            get_substitute(
+                "us+colemak",
+                ContentPurpose::Normal,
+                None,
+                &DataSource::Resource("us".into()),
+            ),
+            None,
+        );
+    }
+
+    /// Phone pads fall back to the calculator, and not to text
+    #[test]
+    fn test_fallback_phone() {
+        let sources = iter_layout_sources("nb", ArrangementKind::Base, ContentPurpose::Phone, None, None);
+
+        assert_eq!(
+            sources.collect::<Vec<_>>(),
+            vec!(
+                (ArrangementKind::Base, DataSource::Resource("phone/nb".into())),
+                (ArrangementKind::Base, DataSource::Resource("phone/us".into())),
+                (ArrangementKind::Base, DataSource::Resource("number/nb".into())),
+                (ArrangementKind::Base, DataSource::Resource("number/us".into())),
+            )
+        );
+    }
+
+    #[test]
+    fn shuffled_digits_complete() {
+        let mut digits = get_shuffled_digits();
+        digits.sort();
+        assert_eq!(digits, ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9']);
+    }
+
+    /// Number layouts are shared between languages
+    #[test]
+    fn substitute_special() {
+        assert_eq!(
+            get_substitute(
+                "nb",
+                ContentPurpose::Number,
+                None,
+                &DataSource::Resource("number/us".into()),
+            ),
+            None,
+        );
+    }
 }
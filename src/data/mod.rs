@@ -4,8 +4,14 @@
 
 /*! Combined module for dealing with layout files */
 
+mod binary;
+pub mod compiled;
+pub mod compose;
+pub mod expansion;
+pub mod export;
 pub mod loading;
 pub mod parsing;
+pub mod symbols;
 
 use std::io;
 use std::fmt;
@@ -21,6 +27,14 @@ pub enum Error {
     /// It's distinct from Io in order to make it matchable
     /// without calling io::Error::kind()
     Missing(io::Error),
+    /// No layout or fragment with the name given in `extends` or `include`
+    MissingPart(String),
+    /// No named row for a "$name" in a view
+    MissingRow(String),
+    /// The layouts extend or include each other in a loop
+    Cycle(Vec<String>),
+    /// The variables or conditions are wrong
+    Expansion(expansion::Error),
 }
 
 impl fmt::Display for Error {
@@ -29,6 +43,10 @@ impl fmt::Display for Error {
             Error::Yaml(e) => write!(f, "YAML: {}", e),
             Error::Io(e) => write!(f, "IO: {}", e),
             Error::Missing(e) => write!(f, "Missing: {}", e),
+            Error::MissingPart(name) => write!(f, "Missing layout or fragment {}", name),
+            Error::MissingRow(name) => write!(f, "Missing row {}", name),
+            Error::Cycle(names) => write!(f, "Layouts include each other: {}", names.join(" -> ")),
+            Error::Expansion(e) => write!(f, "Expansion: {}", e),
         }
     }
 }
@@ -4,27 +4,39 @@
 
 /*! Parsing of the data files containing layouts */
 
-use std::cell::RefCell;
-use std::collections::{ HashMap, HashSet };
+use std::collections::{ BTreeMap, HashMap, HashSet };
 use std::ffi::CString;
 use std::fs;
+use std::mem;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
 use std::vec::Vec;
 
 use xkbcommon::xkb;
 
 use super::{ Error, LoadError };
+use super::loading;
+use super::compose;
+use super::expansion;
+use super::expansion::Context;
+use super::symbols;
 
 use ::action;
+use ::braille;
 use ::keyboard::{
-    KeyState, PressType,
+    Behavior, KeyState, KeyStateId, KeyStore, PressType,
     generate_keymaps, generate_keycodes, KeyCode, FormattingError
 };
+use ::labels;
 use ::layout;
 use ::logging;
-use ::util::hash_map_map;
+use ::util::{ find_max_double, hash_map_map, Interner };
+use ::morse;
 use ::resources;
+use ::steno;
+use ::t9;
+use ::template;
 
 // traits, derives
 use serde::Deserialize;
@@ -35,15 +47,75 @@ use ::logging::Warn;
 // TODO: find a nice way to make sure non-positive sizes don't break layouts
 
 /// The root element describing an entire keyboard
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Default, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Layout {
+    /// Layout to take views, buttons, outlines, rows and margins from,
+    /// where they are not given here, e.g. "us" or "terminal/us"
+    #[serde(default)]
+    extends: Option<String>,
+    /// Fragments to take the same from, before the parent layout
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    margins: Option<Margins>,
+    /// Offsets of the rows from the left, in the order of rows in views.
+    /// Rows without one get centered.
+    #[serde(default)]
+    stagger: Option<Vec<f64>>,
     #[serde(default)]
-    margins: Margins,
     views: HashMap<String, Vec<ButtonIds>>,
+    /// Rows which views refer to as "$name".
+    /// They can be replaced without replacing the views.
+    #[serde(default)]
+    rows: HashMap<String, ButtonIds>,
     #[serde(default)] 
     buttons: HashMap<String, ButtonMeta>,
-    outlines: HashMap<String, Outline>
+    #[serde(default)]
+    outlines: HashMap<String, Outline>,
+    /// Extra entries for the popover
+    #[serde(default)]
+    menu: Vec<MenuEntry>,
+    /// Views covering the whole screen, with buttons in the corners
+    #[serde(default)]
+    floating: Option<Floating>,
+    /// Something came from other files
+    #[serde(skip)]
+    has_parts: bool,
+    /// Something depends on the arrangement or purpose
+    #[serde(skip)]
+    conditional: bool,
+}
+
+/// Applies the variables and conditions before parsing.
+fn parse_value(value: serde_yaml::Value, context: &Context) -> Result<Layout, Error> {
+    let (value, conditional) = expansion::expand(value, context)
+        .map_err(Error::Expansion)?;
+    let mut layout: Layout = serde_yaml::from_value(value).map_err(Error::Yaml)?;
+    layout.conditional = conditional;
+    Ok(layout)
+}
+
+fn parse_str(data: &str, context: &Context) -> Result<Layout, Error> {
+    parse_value(serde_yaml::from_str(data).map_err(Error::Yaml)?, context)
+}
+
+/// Finds a layout or fragment named in `extends` or `include`,
+/// preferring the user's files over the built-in ones.
+fn load_part(name: &str, context: &Context) -> Result<Layout, Error> {
+    let file = loading::get_layouts_dir()
+        .and_then(|dir| fs::File::open(dir.join(name).with_extension("yaml")).ok());
+    match file {
+        Some(file) => parse_value(
+            serde_yaml::from_reader(BufReader::new(file)).map_err(Error::Yaml)?,
+            context,
+        ),
+        None => {
+            let data = resources::get_keyboard(name)
+                .ok_or_else(|| Error::MissingPart(name.into()))?;
+            parse_str(data, context)
+        },
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Default)]
@@ -54,12 +126,69 @@ struct Margins {
     side: f64,
 }
 
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct MenuEntry {
+    label: String,
+    /// Only switching views and showing overlays make sense here
+    action: Action,
+}
+
 /// Buttons are embedded in a single string
 type ButtonIds = String;
 
+/// Views over the whole screen, like a game controller,
+/// with the application visible between the buttons
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Floating {
+    /// Of the buttons, from 0 to 1
+    #[serde(default = "Floating::default_opacity")]
+    opacity: f64,
+    /// Clusters of buttons, taking the place of rows
+    views: HashMap<String, Vec<Cluster>>,
+}
+
+impl Floating {
+    fn default_opacity() -> f64 {
+        0.5
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Cluster {
+    corner: Corner,
+    /// Distance from both edges at the corner
+    #[serde(default)]
+    margin: f64,
+    /// Offsets of the buttons from the top left of the cluster
+    buttons: BTreeMap<String, [f64; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl From<Corner> for layout::Corner {
+    fn from(corner: Corner) -> Self {
+        match corner {
+            Corner::TopLeft => layout::Corner::TopLeft,
+            Corner::TopRight => layout::Corner::TopRight,
+            Corner::BottomLeft => layout::Corner::BottomLeft,
+            Corner::BottomRight => layout::Corner::BottomRight,
+        }
+    }
+}
+
 /// All info about a single button
 /// Buttons can have multiple instances though.
-#[derive(Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 struct ButtonMeta {
     // TODO: structure (action, keysym, text, modifier) as an enum
@@ -76,12 +205,42 @@ struct ButtonMeta {
     /// The modifier to apply while the key is locked
     /// Conflicts with action, keysym, text
     modifier: Option<Modifier>,
+    /// Modifiers held down while the keysym gets pressed.
+    /// Only used together with keysym.
+    #[serde(default)]
+    modifiers: Vec<Modifier>,
     /// If not present, will be derived from text or the button ID
     label: Option<String>,
+    /// A label known to Squeekboard, like "letters", translated on load.
+    /// Conflicts with label and icon
+    label_name: Option<String>,
     /// Conflicts with label
     icon: Option<String>,
     /// The name of the outline. If not present, will be "default"
     outline: Option<String>,
+    /// Whether typing repeats while the button is held.
+    /// If not present, buttons with `long_press` don't repeat, and others do.
+    repeatable: Option<bool>,
+    /// Text offered in a popover after holding the button
+    #[serde(default)]
+    long_press: Vec<String>,
+    /// Milliseconds after which the latched view goes back.
+    /// Only used together with the "locking" action.
+    latch_timeout: Option<u64>,
+    /// Share of the extra width when the keyboard is wider than the layout.
+    /// If not present, the button keeps its width.
+    growth: Option<f64>,
+    /// Holding the button opens the layout chooser.
+    /// Only set for the space bar, when the language key is gone.
+    #[serde(skip)]
+    hold_for_preferences: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+enum MorseSignal {
+    Dot,
+    Dash,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -99,6 +258,54 @@ enum Action {
     SetView(String),
     #[serde(rename="show_prefs")]
     ShowPrefs,
+    /// Show a layout on top of the current one, like "emoji"
+    #[serde(rename="show_overlay")]
+    ShowOverlay(String),
+    #[serde(rename="voice_input")]
+    VoiceInput,
+    #[serde(rename="handwriting")]
+    Handwriting,
+    #[serde(rename="next_candidate")]
+    NextCandidate,
+    #[serde(rename="accept_correction")]
+    AcceptCorrection,
+    /// Search the symbols picker for the word before the cursor
+    #[serde(rename="search_symbols")]
+    SearchSymbols,
+    /// Type text generated from placeholders like `{date}`
+    #[serde(rename="template")]
+    SubmitTemplate(String),
+    /// Type the result of the expression in the calculator
+    #[serde(rename="insert_result")]
+    InsertResult,
+    #[serde(rename="screenshot")]
+    Screenshot,
+    /// Part of a chord typing a whole word
+    #[serde(rename="steno")]
+    Steno(String),
+    /// "dot" or "dash"
+    #[serde(rename="morse")]
+    Morse(MorseSignal),
+    /// Dot number of a Braille cell
+    #[serde(rename="braille")]
+    Braille(u8),
+    /// Letters typed by the key, for guessing words
+    #[serde(rename="t9")]
+    T9(String),
+    #[serde(rename="t9_next")]
+    T9Next,
+    #[serde(rename="cleaning")]
+    Cleaning,
+    #[serde(rename="statistics")]
+    Statistics,
+    /// Only in the generated compose picker
+    #[serde(skip_deserializing)]
+    ComposeStep {
+        sequence: Vec<String>,
+        result: Option<String>,
+    },
+    #[serde(skip_deserializing)]
+    ComposeCancel,
     /// Remove last character
     #[serde(rename="erase")]
     Erase,
@@ -123,6 +330,103 @@ enum Modifier {
 struct Outline {
     width: f64,
     height: f64,
+    /// Overrides the corners from the theme
+    #[serde(default)]
+    radius: Option<Radius>,
+}
+
+/// Either the same for all corners, or one for each corner,
+/// going clockwise from the top left.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum Radius {
+    All(f64),
+    Each([f64; 4]),
+}
+
+impl Radius {
+    fn get_corners(&self) -> [f64; 4] {
+        match self {
+            Radius::All(radius) => [*radius; 4],
+            Radius::Each(radii) => *radii,
+        }
+    }
+}
+
+/// Which utility keys go next to the space bar.
+/// Keys not mentioned here stay as the layout defines them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BottomRow {
+    /// Add a key showing the emoji layout
+    pub emoji: bool,
+    /// Add a key for speech recognition
+    pub voice: bool,
+    /// Keep the key for choosing layouts
+    pub language: bool,
+    /// Add a comma if there's none
+    pub comma: bool,
+    /// Keep the period
+    pub period: bool,
+    /// Keys for a row added on top of text layouts.
+    /// Keysym names like "Left" press that key, anything else is typed.
+    pub extra_keys: Vec<String>,
+}
+
+impl Default for BottomRow {
+    /// Leaves layouts as they are
+    fn default() -> Self {
+        BottomRow {
+            emoji: false,
+            voice: false,
+            language: true,
+            comma: false,
+            period: true,
+            extra_keys: Vec::new(),
+        }
+    }
+}
+
+const SPACE: &str = "space";
+const EMOJI_KEY: &str = "bottom_row_emoji";
+const VOICE_KEY: &str = "bottom_row_voice";
+const COMMA_KEY: &str = "bottom_row_comma";
+const EXTRA_KEY_PREFIX: &str = "extra_row_";
+
+/// Keysyms of more than one letter get pressed, the rest gets typed.
+fn get_extra_key_meta(entry: &str) -> ButtonMeta {
+    let is_keysym = entry.chars().count() > 1
+        && xkb::keysym_from_name(entry, xkb::KEYSYM_NO_FLAGS) != xkb::KEY_NoSymbol;
+    if is_keysym {
+        let label = match entry {
+            "Left" => "←".into(),
+            "Right" => "→".into(),
+            "Up" => "↑".into(),
+            "Down" => "↓".into(),
+            other => get_keysym_label(other),
+        };
+        ButtonMeta {
+            keysym: Some(entry.into()),
+            label: Some(label),
+            ..ButtonMeta::default()
+        }
+    } else {
+        ButtonMeta {
+            text: Some(entry.into()),
+            ..ButtonMeta::default()
+        }
+    }
+}
+
+/// The text the keysym types, or the name if it types nothing visible.
+fn get_keysym_label(name: &str) -> String {
+    let keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+    let text = xkb::keysym_to_utf8(keysym);
+    let text = text.trim_end_matches('\0');
+    if text.trim().is_empty() || text.chars().any(char::is_control) {
+        name.into()
+    } else {
+        text.into()
+    }
 }
 
 pub fn add_offsets<'a, I: 'a, T, F: 'a>(iterator: I, get_size: F)
@@ -141,24 +445,599 @@ pub fn add_offsets<'a, I: 'a, T, F: 'a>(iterator: I, get_size: F)
 
 impl Layout {
     pub fn from_resource(name: &str) -> Result<Layout, LoadError> {
+        Layout::from_resource_for(name, &Context::default())
+    }
+
+    /// Evaluates the conditions in the layout for the context.
+    pub fn from_resource_for(name: &str, context: &Context)
+        -> Result<Layout, LoadError>
+    {
         let data = resources::get_keyboard(name)
                     .ok_or(LoadError::MissingResource)?;
-        serde_yaml::from_str(data)
-                    .map_err(LoadError::BadResource)
+        let value = serde_yaml::from_str(data)
+                    .map_err(LoadError::BadResource)?;
+        parse_value(value, context)
+            .and_then(|layout| layout.resolve(&mut |name| load_part(name, context)))
+            .map_err(LoadError::BadData)
+    }
+
+    pub fn from_yaml_str(data: &str) -> Result<Layout, Error> {
+        let context = Context::default();
+        parse_str(data, &context)?
+            .resolve(&mut |name| load_part(name, &context))
+    }
+
+    /// The layout can't tell when another file it's made of changes.
+    pub fn has_parts(&self) -> bool {
+        self.has_parts
+    }
+
+    /// Another arrangement or purpose would give a different layout.
+    pub fn is_conditional(&self) -> bool {
+        self.conditional
+    }
+
+    /// The compiled form is only good for layouts
+    /// which come out the same every time their file is the same.
+    pub fn can_compile(&self) -> bool {
+        !self.has_parts && !self.conditional
+    }
+
+    /// Merges in the fragments and the parent layout, recursively,
+    /// and puts the named rows into the views.
+    fn resolve<F>(self, load: &mut F) -> Result<Layout, Error>
+        where F: FnMut(&str) -> Result<Layout, Error>
+    {
+        self.resolve_parts(load, &mut Vec::new())?
+            .expand_rows()
+    }
+
+    /// `chain` holds the names of the layouts being resolved,
+    /// to find one which comes back.
+    fn resolve_parts<F>(mut self, load: &mut F, chain: &mut Vec<String>)
+        -> Result<Layout, Error>
+        where F: FnMut(&str) -> Result<Layout, Error>
+    {
+        let include = mem::replace(&mut self.include, Vec::new());
+        let parent = self.extends.take();
+        for name in include.into_iter().chain(parent) {
+            if chain.contains(&name) {
+                let mut cycle = chain.clone();
+                cycle.push(name);
+                return Err(Error::Cycle(cycle));
+            }
+            chain.push(name.clone());
+            let part = load(&name)?.resolve_parts(load, chain)?;
+            chain.pop();
+            self = self.inherit(part);
+        }
+        Ok(self)
+    }
+
+    /// Takes what's not given here from the other layout.
+    fn inherit(mut self, other: Layout) -> Layout {
+        fn merge<V>(into: &mut HashMap<String, V>, from: HashMap<String, V>) {
+            for (name, value) in from {
+                into.entry(name).or_insert(value);
+            }
+        }
+        merge(&mut self.views, other.views);
+        merge(&mut self.rows, other.rows);
+        merge(&mut self.buttons, other.buttons);
+        merge(&mut self.outlines, other.outlines);
+        self.margins = self.margins.or(other.margins);
+        self.stagger = self.stagger.or(other.stagger);
+        self.floating = self.floating.or(other.floating);
+        let mut menu = other.menu;
+        menu.extend(self.menu);
+        self.menu = menu;
+        self.has_parts = true;
+        self.conditional |= other.conditional;
+        self
+    }
+
+    fn expand_rows(mut self) -> Result<Layout, Error> {
+        for rows in self.views.values_mut() {
+            for row in rows.iter_mut() {
+                let reference = row.trim();
+                if !reference.starts_with('$') {
+                    continue;
+                }
+                let name = &reference[1..];
+                let expanded = self.rows.get(name)
+                    .ok_or_else(|| Error::MissingRow(name.into()))?
+                    .clone();
+                *row = expanded;
+            }
+        }
+        Ok(self)
+    }
+
+    /// A PIN pad with digits in the given order, top to bottom.
+    /// The last digit goes between erase and enter, like on phones.
+    pub fn pin_pad(digits: &[char; 10]) -> Layout {
+        let names: Vec<String> = digits.iter().map(char::to_string).collect();
+        let rows = vec![
+            names[0..3].join(" "),
+            names[3..6].join(" "),
+            names[6..9].join(" "),
+            format!("BackSpace {} Return", names[9]),
+        ];
+        Layout {
+            margins: Some(Margins { top: 4.0, bottom: 4.0, side: 0.0 }),
+            views: HashMap::from_iter(vec![("base".into(), rows)]),
+            buttons: HashMap::from_iter(vec![
+                ("BackSpace".into(), ButtonMeta {
+                    icon: Some("edit-clear-symbolic".into()),
+                    action: Some(Action::Erase),
+                    ..ButtonMeta::default()
+                }),
+                ("Return".into(), ButtonMeta {
+                    icon: Some("key-enter".into()),
+                    keysym: Some("Return".into()),
+                    ..ButtonMeta::default()
+                }),
+            ]),
+            outlines: HashMap::from_iter(vec![
+                ("default".into(), Outline { width: 120.0, height: 52.0, radius: None }),
+            ]),
+            menu: Vec::new(),
+            ..Layout::default()
+        }
+    }
+
+    /// The symbols picker, with a view for every page.
+    /// The first page of the first group is also the base view.
+    pub fn symbol_picker(groups: &[symbols::Group]) -> Layout {
+        let nav = |meta: ButtonMeta| ButtonMeta {
+            outline: Some("nav".into()),
+            ..meta
+        };
+        let mut buttons: HashMap<String, ButtonMeta> = HashMap::from_iter(vec![
+            ("preferences".into(), nav(ButtonMeta {
+                action: Some(Action::ShowPrefs),
+                icon: Some("keyboard-mode-symbolic".into()),
+                ..ButtonMeta::default()
+            })),
+            ("search".into(), nav(ButtonMeta {
+                action: Some(Action::SearchSymbols),
+                icon: Some("edit-find-symbolic".into()),
+                ..ButtonMeta::default()
+            })),
+            ("BackSpace".into(), nav(ButtonMeta {
+                action: Some(Action::Erase),
+                icon: Some("edit-clear-symbolic".into()),
+                ..ButtonMeta::default()
+            })),
+            ("blank".into(), nav(ButtonMeta {
+                text: Some("".into()),
+                ..ButtonMeta::default()
+            })),
+            ("no_matches".into(), ButtonMeta {
+                text: Some("".into()),
+                label: Some("—".into()),
+                outline: Some("wide".into()),
+                ..ButtonMeta::default()
+            }),
+        ]);
+        for category in symbols::CATEGORIES {
+            buttons.insert(format!("category_{}", category.name), nav(ButtonMeta {
+                action: Some(Action::SetView(format!("{}_1", category.name))),
+                label: Some(category.label.into()),
+                ..ButtonMeta::default()
+            }));
+        }
+        let categories: Vec<String> = symbols::CATEGORIES.iter()
+            .map(|category| format!("category_{}", category.name))
+            .collect();
+
+        let mut views = HashMap::new();
+        for group in groups {
+            for (index, page) in group.pages.iter().enumerate() {
+                let mut rows = Vec::new();
+                for row in page.chunks(symbols::ROW_SIZE) {
+                    let mut names = Vec::new();
+                    for c in row {
+                        let name = format!("U{:04X}", *c as u32);
+                        buttons.insert(name.clone(), ButtonMeta {
+                            text: Some(c.to_string()),
+                            ..ButtonMeta::default()
+                        });
+                        names.push(name);
+                    }
+                    rows.push(names.join(" "));
+                }
+                if page.is_empty() {
+                    rows.push("no_matches".into());
+                }
+
+                let mut add_switch = |direction: &str, label: &str, page: usize| {
+                    let view = group.get_view_name(page);
+                    let name = format!("{}_{}", direction, view);
+                    buttons.insert(name.clone(), nav(ButtonMeta {
+                        action: Some(Action::SetView(view)),
+                        label: Some(label.into()),
+                        ..ButtonMeta::default()
+                    }));
+                    name
+                };
+                let previous = match index {
+                    0 => "blank".into(),
+                    index => add_switch("previous", "‹", index - 1),
+                };
+                let next = match index + 1 < group.pages.len() {
+                    true => add_switch("next", "›", index + 1),
+                    false => "blank".into(),
+                };
+                rows.push(format!(
+                    "preferences {} {} search {} BackSpace",
+                    previous,
+                    categories.join(" "),
+                    next,
+                ));
+                views.insert(group.get_view_name(index), rows);
+            }
+        }
+        let base = groups.get(0)
+            .and_then(|group| views.get(&group.get_view_name(0)))
+            .cloned()
+            .unwrap_or_else(|| vec!["no_matches".into()]);
+        views.insert("base".into(), base);
+
+        Layout {
+            margins: Some(Margins { top: 4.0, bottom: 4.0, side: 0.0 }),
+            views,
+            buttons,
+            outlines: HashMap::from_iter(vec![
+                ("default".into(), Outline { width: 45.0, height: 52.0, radius: None }),
+                ("nav".into(), Outline { width: 40.0, height: 52.0, radius: None }),
+                ("wide".into(), Outline { width: 360.0, height: 52.0, radius: None }),
+            ]),
+            menu: Vec::new(),
+            ..Layout::default()
+        }
+    }
+
+    /// The compose picker, showing what can follow the keysyms pressed so far.
+    /// Continuations which don't fit on the base view go to further views.
+    pub fn compose_picker(
+        prefix: &[String],
+        continuations: &[compose::Continuation],
+    ) -> Layout {
+        let nav = |meta: ButtonMeta| ButtonMeta {
+            outline: Some("nav".into()),
+            ..meta
+        };
+        let pressed: String = prefix.iter()
+            .map(|keysym| get_keysym_label(keysym))
+            .collect();
+        let mut buttons: HashMap<String, ButtonMeta> = HashMap::from_iter(vec![
+            ("preferences".into(), nav(ButtonMeta {
+                action: Some(Action::ShowPrefs),
+                icon: Some("keyboard-mode-symbolic".into()),
+                ..ButtonMeta::default()
+            })),
+            ("cancel".into(), nav(ButtonMeta {
+                action: Some(Action::ComposeCancel),
+                icon: Some("window-close-symbolic".into()),
+                ..ButtonMeta::default()
+            })),
+            ("BackSpace".into(), nav(ButtonMeta {
+                action: Some(Action::Erase),
+                icon: Some("edit-clear-symbolic".into()),
+                ..ButtonMeta::default()
+            })),
+            ("blank".into(), nav(ButtonMeta {
+                text: Some("".into()),
+                ..ButtonMeta::default()
+            })),
+            ("pressed".into(), ButtonMeta {
+                text: Some("".into()),
+                label: Some(match pressed.is_empty() {
+                    true => "⎄".into(),
+                    false => pressed,
+                }),
+                outline: Some("pressed".into()),
+                ..ButtonMeta::default()
+            }),
+            ("no_matches".into(), ButtonMeta {
+                text: Some("".into()),
+                label: Some("—".into()),
+                outline: Some("wide".into()),
+                ..ButtonMeta::default()
+            }),
+        ]);
+
+        let mut names = Vec::new();
+        for (index, continuation) in continuations.iter().enumerate() {
+            let name = format!("compose_{}", index);
+            let mut sequence = prefix.to_vec();
+            sequence.push(continuation.keysym.clone());
+            buttons.insert(name.clone(), ButtonMeta {
+                action: Some(Action::ComposeStep {
+                    sequence,
+                    result: continuation.result.clone(),
+                }),
+                // Show what comes out, if it's the last step
+                label: Some(continuation.result.clone().unwrap_or_else(||
+                    get_keysym_label(&continuation.keysym)
+                )),
+                ..ButtonMeta::default()
+            });
+            names.push(name);
+        }
+
+        let pages: Vec<&[String]> = match names.is_empty() {
+            true => vec![&names[..]],
+            false => names.chunks(compose::PAGE_SIZE).collect(),
+        };
+        let get_view_name = |index: usize| match index {
+            0 => "base".to_string(),
+            index => format!("page_{}", index + 1),
+        };
+        let mut views = HashMap::new();
+        for (index, page) in pages.iter().enumerate() {
+            let mut rows: Vec<String> = page.chunks(compose::ROW_SIZE)
+                .map(|row| row.join(" "))
+                .collect();
+            if page.is_empty() {
+                rows.push("no_matches".into());
+            }
+            let mut add_switch = |direction: &str, label: &str, page: usize| {
+                let view = get_view_name(page);
+                let name = format!("{}_{}", direction, view);
+                buttons.insert(name.clone(), nav(ButtonMeta {
+                    action: Some(Action::SetView(view)),
+                    label: Some(label.into()),
+                    ..ButtonMeta::default()
+                }));
+                name
+            };
+            let previous = match index {
+                0 => "blank".into(),
+                index => add_switch("previous", "‹", index - 1),
+            };
+            let next = match index + 1 < pages.len() {
+                true => add_switch("next", "›", index + 1),
+                false => "blank".into(),
+            };
+            rows.push(format!(
+                "preferences cancel {} pressed {} BackSpace",
+                previous,
+                next,
+            ));
+            views.insert(get_view_name(index), rows);
+        }
+
+        Layout {
+            margins: Some(Margins { top: 4.0, bottom: 4.0, side: 0.0 }),
+            views,
+            buttons,
+            outlines: HashMap::from_iter(vec![
+                ("default".into(), Outline { width: 45.0, height: 52.0, radius: None }),
+                ("nav".into(), Outline { width: 40.0, height: 52.0, radius: None }),
+                ("pressed".into(), Outline { width: 160.0, height: 52.0, radius: None }),
+                ("wide".into(), Outline { width: 360.0, height: 52.0, radius: None }),
+            ]),
+            menu: Vec::new(),
+            ..Layout::default()
+        }
+    }
+
+    /// Returns the text the button submits, if any.
+    fn get_text<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        match self.buttons.get(name) {
+            Some(ButtonMeta { text: Some(text), .. }) => Some(text.as_str()),
+            Some(ButtonMeta { action: None, keysym: None, modifier: None, .. })
+                | None
+            => Some(name),
+            _ => None,
+        }
+    }
+
+    fn is_language_key(&self, name: &str) -> bool {
+        match self.buttons.get(name) {
+            Some(ButtonMeta { action: Some(Action::ShowPrefs), .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn get_outline_width(&self, name: &str) -> f64 {
+        let outline = self.buttons.get(name)
+            .and_then(|meta| meta.outline.as_ref())
+            .map(String::as_str)
+            .unwrap_or("default");
+        self.outlines.get(outline)
+            .map(|outline| outline.width)
+            .unwrap_or(0.0)
+    }
+
+    /// Adds and removes utility keys from the row containing the space bar,
+    /// which then takes up the width difference.
+    /// Only the last row of each view gets modified,
+    /// and only if it contains the space bar.
+    /// The same views get the row of extra keys on top.
+    pub fn with_bottom_row(mut self, config: &BottomRow) -> Layout {
+        let language_outline = self.buttons.iter()
+            .find(|(name, _)| self.is_language_key(name))
+            .and_then(|(_, meta)| meta.outline.clone());
+        let space_meta = self.buttons.get(SPACE)
+            .cloned()
+            .unwrap_or_else(|| ButtonMeta {
+                text: Some(" ".into()),
+                ..ButtonMeta::default()
+            });
+        // Without the language key, holding the space bar chooses layouts
+        let space_meta = ButtonMeta {
+            hold_for_preferences: !config.language,
+            ..space_meta
+        };
+        if space_meta.hold_for_preferences {
+            self.buttons.insert(SPACE.into(), space_meta.clone());
+        }
+        let space_width = self.get_outline_width(SPACE);
+
+        self.buttons.insert(EMOJI_KEY.into(), ButtonMeta {
+            action: Some(Action::ShowOverlay("emoji".into())),
+            label: Some("☺".into()),
+            outline: language_outline.clone(),
+            ..ButtonMeta::default()
+        });
+        self.buttons.insert(VOICE_KEY.into(), ButtonMeta {
+            action: Some(Action::VoiceInput),
+            icon: Some("audio-input-microphone-symbolic".into()),
+            outline: language_outline.clone(),
+            ..ButtonMeta::default()
+        });
+        self.buttons.insert(COMMA_KEY.into(), ButtonMeta {
+            text: Some(",".into()),
+            outline: language_outline,
+            ..ButtonMeta::default()
+        });
+
+        let extra_row: Vec<String> = config.extra_keys.iter()
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .enumerate()
+            .map(|(index, entry)| {
+                let name = format!("{}{}", EXTRA_KEY_PREFIX, index);
+                self.buttons.insert(name.clone(), get_extra_key_meta(entry));
+                name
+            })
+            .collect();
+
+        let mut new_views = HashMap::new();
+        for (view_name, mut rows) in self.views.clone() {
+            let last = match rows.last() {
+                Some(row) => row.clone(),
+                None => {
+                    new_views.insert(view_name, rows);
+                    continue;
+                },
+            };
+            let mut buttons: Vec<String> = last.split_ascii_whitespace()
+                .map(String::from)
+                .collect();
+            let space_idx = match buttons.iter().position(|b| b == SPACE) {
+                Some(idx) => idx,
+                None => {
+                    new_views.insert(view_name, rows);
+                    continue;
+                },
+            };
+
+            let width_before: f64 = buttons.iter()
+                .map(|b| self.get_outline_width(b))
+                .sum();
+
+            if config.comma
+                && !buttons.iter().any(|b| self.get_text(b) == Some(","))
+            {
+                buttons.insert(space_idx + 1, COMMA_KEY.into());
+            }
+            if config.voice {
+                buttons.insert(space_idx, VOICE_KEY.into());
+            }
+            if config.emoji {
+                buttons.insert(space_idx, EMOJI_KEY.into());
+            }
+            buttons.retain(|b|
+                (config.language || !self.is_language_key(b))
+                && (config.period || self.get_text(b) != Some("."))
+            );
+
+            let width_after: f64 = buttons.iter()
+                .map(|b| self.get_outline_width(b))
+                .sum();
+            let new_space_width = f64::max(
+                space_width + width_before - width_after,
+                self.get_outline_width(COMMA_KEY),
+            );
+            if new_space_width != space_width {
+                // Every view may need a different width
+                let name = format!("{}_{}", SPACE, view_name);
+                let space_outline = self.outlines.get(
+                    space_meta.outline.as_ref().map(String::as_str)
+                        .unwrap_or("default")
+                );
+                self.outlines.insert(name.clone(), Outline {
+                    width: new_space_width,
+                    height: space_outline.map(|o| o.height).unwrap_or(0.0),
+                    radius: space_outline.and_then(|o| o.radius.clone()),
+                });
+                self.buttons.insert(name.clone(), ButtonMeta {
+                    outline: Some(name.clone()),
+                    ..space_meta.clone()
+                });
+                for button in buttons.iter_mut() {
+                    if button == SPACE {
+                        *button = name.clone();
+                    }
+                }
+            }
+
+            let rows_count = rows.len();
+            rows[rows_count - 1] = buttons.join(" ");
+            if !extra_row.is_empty() {
+                rows.insert(0, extra_row.join(" "));
+            }
+            new_views.insert(view_name, rows);
+        }
+        self.views = new_views;
+        self
     }
 
     pub fn from_file(path: PathBuf) -> Result<Layout, Error> {
+        Layout::from_file_for(path, &Context::default())
+    }
+
+    /// Evaluates the conditions in the layout for the context.
+    pub fn from_file_for(path: PathBuf, context: &Context) -> Result<Layout, Error> {
         let infile = BufReader::new(
             fs::OpenOptions::new()
                 .read(true)
                 .open(&path)?
         );
-        serde_yaml::from_reader(infile).map_err(Error::Yaml)
+        let value = serde_yaml::from_reader(infile).map_err(Error::Yaml)?;
+        parse_value(value, context)?
+            .resolve(&mut |name| load_part(name, context))
     }
 
-    pub fn build<H: logging::Handler>(self, mut warning_handler: H)
+    pub fn build<H: logging::Handler>(self, warning_handler: H)
         -> (Result<::layout::LayoutData, FormattingError>, H)
     {
+        self.build_views(warning_handler, false)
+    }
+
+    /// Like `build`, but only the base view gets created right away.
+    /// Problems in other views get printed when they are first shown.
+    pub fn build_lazily(self) -> Result<::layout::LayoutData, FormattingError> {
+        self.build_views(logging::Print, true).0
+    }
+
+    fn build_views<H: logging::Handler>(mut self, mut warning_handler: H, lazily: bool)
+        -> (Result<::layout::LayoutData, FormattingError>, H)
+    {
+        // Buttons of floating views are found and given actions
+        // like the ones in rows.
+        if let Some(floating) = &self.floating {
+            for name in self.views.keys() {
+                if !floating.views.contains_key(name) {
+                    warning_handler.handle(
+                        logging::Level::Warning,
+                        &format!("View {} doesn't float, unlike the layout", name),
+                    );
+                }
+            }
+            for (name, clusters) in &floating.views {
+                let buttons: Vec<&str> = clusters.iter()
+                    .flat_map(|cluster| cluster.buttons.keys())
+                    .map(String::as_str)
+                    .collect();
+                self.views.insert(name.clone(), vec![buttons.join(" ")]);
+            }
+        }
+
         let button_names = self.views.values()
             .flat_map(|rows| {
                 rows.iter()
@@ -186,7 +1065,9 @@ impl Layout {
         let button_states = HashMap::<String, KeyState>::from_iter(
             button_actions.into_iter().map(|(name, action)| {
                 let keycodes = match &action {
-                    ::action::Action::Submit { text: _, keys } => {
+                    ::action::Action::Submit { text: _, keys }
+                        | ::action::Action::Shortcut { modifiers: _, keys }
+                    => {
                         keys.iter().map(|named_keysym| {
                             symbolmap.get(named_keysym.0.as_str())
                                 .expect(
@@ -206,12 +1087,19 @@ impl Layout {
                     ],
                     _ => Vec::new(),
                 };
+                let behavior = create_behavior(
+                    self.buttons.get(name),
+                    &action,
+                    name,
+                    &mut warning_handler,
+                );
                 (
                     name.into(),
                     KeyState {
                         pressed: PressType::Released,
                         keycodes,
                         action,
+                        behavior,
                     }
                 )
             })
@@ -222,76 +1110,139 @@ impl Layout {
             Ok(v) => v,
         };
 
-        let button_states_cache = hash_map_map(
+        let mut keys = KeyStore::new();
+        let button_ids = hash_map_map(
             button_states,
-            |name, state| {(
-                name,
-                Rc::new(RefCell::new(state))
-            )}
+            |name, state| (name, keys.add(state)),
         );
 
-        let views: Vec<_> = self.views.iter()
-            .map(|(name, view)| {
-                let rows = view.iter().map(|row| {
-                    let buttons = row.split_ascii_whitespace()
-                        .map(|name| {
-                            Box::new(create_button(
-                                &self.buttons,
-                                &self.outlines,
-                                name,
-                                button_states_cache.get(name.into())
-                                    .expect("Button state not created")
-                                    .clone(),
-                                &mut warning_handler,
-                            ))
-                        });
-                    layout::Row::new(
-                        add_offsets(
-                            buttons,
-                            |button| button.size.width,
-                        ).collect()
-                    )
-                });
-                let rows = add_offsets(rows, |row| row.get_size().height)
-                    .collect();
-                (
-                    name.clone(),
-                    layout::View::new(rows)
-                )
+        let Layout { views, buttons, outlines, menu, margins, stagger, floating, .. } = self;
+        let clusters = floating.as_ref().map(|floating| &floating.views);
+        let margins = margins.unwrap_or_default();
+        let stagger = Arc::new(stagger.unwrap_or_default());
+        // Shared with the views created later
+        let buttons = Arc::new(buttons);
+        let outlines = Arc::new(outlines);
+        let button_ids = Arc::new(button_ids);
+        // Buttons repeat the same strings, also across views
+        let strings = Arc::new(Mutex::new(Interner::new()));
+
+        let views: Vec<_> = views.into_iter()
+            .map(|(name, rows)| {
+                if let Some(clusters) = clusters.and_then(|clusters| clusters.get(&name)) {
+                    let view = create_floating_view(
+                        &buttons,
+                        &outlines,
+                        &button_ids,
+                        clusters,
+                        &mut strings.lock().unwrap(),
+                        &mut warning_handler,
+                    );
+                    return (name, layout::LazyView::Built(view));
+                }
+                let view = match lazily && name != "base" {
+                    false => layout::LazyView::Built(create_view(
+                        &buttons,
+                        &outlines,
+                        &button_ids,
+                        &rows,
+                        &stagger,
+                        &mut strings.lock().unwrap(),
+                        &mut warning_handler,
+                    )),
+                    true => {
+                        let buttons = buttons.clone();
+                        let outlines = outlines.clone();
+                        let button_ids = button_ids.clone();
+                        let strings = strings.clone();
+                        let stagger = stagger.clone();
+                        layout::LazyView::Pending {
+                            size: calculate_view_size(&buttons, &outlines, &rows, &stagger),
+                            row_count: rows.len(),
+                            build: Box::new(move || create_view(
+                                &buttons,
+                                &outlines,
+                                &button_ids,
+                                &rows,
+                                &stagger,
+                                &mut strings.lock().unwrap(),
+                                &mut logging::Print,
+                            )),
+                        }
+                    },
+                };
+                (name, view)
             }).collect();
 
         // Center views on the same point.
+        // Floating views all cover the screen instead.
         let views = {
             let total_size = layout::View::calculate_super_size(
-                views.iter().map(|(_name, view)| view).collect()
+                views.iter().map(|(_name, view)| view.get_size()).collect()
             );
 
             HashMap::from_iter(views.into_iter().map(|(name, view)| (
                 name,
                 (
-                    layout::c::Point {
-                        x: (total_size.width - view.get_size().width) / 2.0,
-                        y: (total_size.height - view.get_size().height) / 2.0,
+                    match floating {
+                        Some(_) => layout::c::Point { x: 0.0, y: 0.0 },
+                        None => layout::c::Point {
+                            x: (total_size.width - view.get_size().width) / 2.0,
+                            y: (total_size.height - view.get_size().height) / 2.0,
+                        },
                     },
                     view,
                 ),
             )))
         };
 
+        let view_names: Vec<&String> = views.keys().collect();
+        let menu = menu.iter()
+            .filter_map(|entry| {
+                let action = match &entry.action {
+                    Action::SetView(view) if view_names.contains(&view) => {
+                        Some(::action::Action::SetView(view.clone()))
+                    },
+                    Action::ShowOverlay(name) => {
+                        Some(::action::Action::ShowOverlay(name.clone()))
+                    },
+                    other => {
+                        warning_handler.handle(
+                            logging::Level::Warning,
+                            &format!(
+                                "Menu entry {} can't use action {:?}",
+                                entry.label, other,
+                            ),
+                        );
+                        None
+                    },
+                };
+                action.map(|action| layout::MenuEntry {
+                    label: entry.label.clone(),
+                    action,
+                })
+            })
+            .collect();
+
         (
             Ok(::layout::LayoutData {
                 views: views,
+                keys,
+                menu,
                 keymaps: keymaps.into_iter().map(|keymap_str|
                     CString::new(keymap_str)
                         .expect("Invalid keymap string generated")
                 ).collect(),
                 // FIXME: use a dedicated field
                 margins: layout::Margins {
-                    top: self.margins.top,
-                    left: self.margins.side,
-                    bottom: self.margins.bottom,
-                    right: self.margins.side,
+                    top: margins.top,
+                    left: margins.side,
+                    bottom: margins.bottom,
+                    right: margins.side,
                 },
+                floating: floating.map(|floating| layout::Floating {
+                    opacity: floating.opacity.max(0.0).min(1.0),
+                }),
             }),
             warning_handler,
         )
@@ -311,6 +1262,27 @@ fn create_action<H: logging::Handler>(
     fn keysym_valid(name: &str) -> bool {
         xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS) != xkb::KEY_NoSymbol
     }
+
+    fn convert_modifier<H: logging::Handler>(
+        modifier: &Modifier,
+        warning_handler: &mut H,
+    ) -> Option<action::Modifier> {
+        match modifier {
+            Modifier::Control => Some(action::Modifier::Control),
+            Modifier::Alt => Some(action::Modifier::Alt),
+            Modifier::Mod4 => Some(action::Modifier::Mod4),
+            Modifier::Shift => Some(action::Modifier::Shift),
+            unsupported_modifier => {
+                warning_handler.handle(
+                    logging::Level::Bug,
+                    &format!(
+                        "Modifier {:?} unsupported", unsupported_modifier,
+                    ),
+                );
+                None
+            },
+        }
+    }
     
     enum SubmitData {
         Action(Action),
@@ -396,10 +1368,78 @@ fn create_action<H: logging::Handler>(
         SubmitData::Action(
             Action::ShowPrefs
         ) => ::action::Action::ShowPreferences,
+        SubmitData::Action(
+            Action::ShowOverlay(name)
+        ) => ::action::Action::ShowOverlay(name),
+        SubmitData::Action(Action::VoiceInput) => action::Action::VoiceInput,
+        SubmitData::Action(Action::Handwriting) => action::Action::Handwriting,
+        SubmitData::Action(Action::NextCandidate) => action::Action::NextCandidate,
+        SubmitData::Action(Action::AcceptCorrection) => action::Action::AcceptCorrection,
+        SubmitData::Action(Action::SearchSymbols) => action::Action::SearchSymbols,
+        SubmitData::Action(Action::InsertResult) => action::Action::InsertResult,
+        SubmitData::Action(Action::Screenshot) => action::Action::Screenshot,
+        SubmitData::Action(Action::Morse(MorseSignal::Dot))
+            => action::Action::Morse(morse::Signal::Dot),
+        SubmitData::Action(Action::Morse(MorseSignal::Dash))
+            => action::Action::Morse(morse::Signal::Dash),
+        SubmitData::Action(Action::Braille(dot)) => match braille::is_dot(dot) {
+            true => action::Action::Braille(dot),
+            false => {
+                warning_handler.handle(
+                    logging::Level::Warning,
+                    &format!("Button {} has Braille dot {} out of 0 to 6", name, dot),
+                );
+                action::Action::Submit { text: None, keys: Vec::new() }
+            },
+        },
+        SubmitData::Action(Action::T9(letters)) => match t9::is_group(&letters) {
+            true => action::Action::T9(letters),
+            false => {
+                warning_handler.handle(
+                    logging::Level::Warning,
+                    &format!("Button {} has a T9 group which is empty or has capitals", name),
+                );
+                action::Action::Submit { text: None, keys: Vec::new() }
+            },
+        },
+        SubmitData::Action(Action::T9Next) => action::Action::T9Next,
+        SubmitData::Action(Action::Steno(key)) => match steno::is_key(&key) {
+            true => action::Action::Steno(key),
+            false => {
+                warning_handler.handle(
+                    logging::Level::Warning,
+                    &format!("Button {} has unknown steno key {}", name, key),
+                );
+                action::Action::Submit { text: None, keys: Vec::new() }
+            },
+        },
+        SubmitData::Action(Action::SubmitTemplate(text)) => match template::check(&text) {
+            Ok(()) => action::Action::SubmitTemplate(text),
+            Err(e) => {
+                warning_handler.handle(
+                    logging::Level::Warning,
+                    &format!("Button {} has a broken template: {}", name, e),
+                );
+                action::Action::Submit { text: None, keys: Vec::new() }
+            },
+        },
+        SubmitData::Action(Action::ComposeStep { sequence, result })
+        => ::action::Action::ComposeStep {
+            sequence,
+            result: result.and_then(|result|
+                CString::new(result.clone()).or_warn(
+                    warning_handler,
+                    logging::Problem::Warning,
+                    &format!("Compose result {} contains problems", result),
+                )
+            ),
+        },
+        SubmitData::Action(Action::ComposeCancel) => action::Action::ComposeCancel,
+        SubmitData::Action(Action::Cleaning) => action::Action::StartCleaning,
+        SubmitData::Action(Action::Statistics) => action::Action::Statistics,
         SubmitData::Action(Action::Erase) => action::Action::Erase,
-        SubmitData::Keysym(keysym) => ::action::Action::Submit {
-            text: None,
-            keys: vec!(::action::KeySym(
+        SubmitData::Keysym(keysym) => {
+            let keys = vec!(::action::KeySym(
                 match keysym_valid(keysym.as_str()) {
                     true => keysym.clone(),
                     false => {
@@ -413,7 +1453,16 @@ fn create_action<H: logging::Handler>(
                         "space".into() // placeholder
                     },
                 }
-            )),
+            ));
+            match symbol_meta.modifiers.is_empty() {
+                true => ::action::Action::Submit { text: None, keys },
+                false => ::action::Action::Shortcut {
+                    modifiers: symbol_meta.modifiers.iter()
+                        .filter_map(|m| convert_modifier(m, warning_handler))
+                        .collect(),
+                    keys,
+                },
+            }
         },
         SubmitData::Text(text) => ::action::Action::Submit {
             text: CString::new(text.clone()).or_warn(
@@ -429,63 +1478,232 @@ fn create_action<H: logging::Handler>(
                 })
             }).collect(),
         },
-        SubmitData::Modifier(modifier) => match modifier {
-            Modifier::Control => action::Action::ApplyModifier(
-                action::Modifier::Control,
-            ),
-            Modifier::Alt => action::Action::ApplyModifier(
-                action::Modifier::Alt,
-            ),
-            Modifier::Mod4 => action::Action::ApplyModifier(
-                action::Modifier::Mod4,
-            ),
-            unsupported_modifier => {
-                warning_handler.handle(
-                    logging::Level::Bug,
-                    &format!(
-                        "Modifier {:?} unsupported", unsupported_modifier,
-                    ),
-                );
-                action::Action::Submit {
+        SubmitData::Modifier(modifier) => {
+            match convert_modifier(&modifier, warning_handler) {
+                Some(modifier) => action::Action::ApplyModifier(modifier),
+                None => action::Action::Submit {
                     text: None,
                     keys: Vec::new(),
-                }
-            },
+                },
+            }
         },
     }
 }
 
+fn create_view<H: logging::Handler>(
+    button_info: &HashMap<String, ButtonMeta>,
+    outlines: &HashMap<String, Outline>,
+    button_ids: &HashMap<String, KeyStateId>,
+    rows: &[String],
+    stagger: &[f64],
+    strings: &mut Interner,
+    warning_handler: &mut H,
+) -> layout::View {
+    let rows = rows.iter().map(|row| {
+        let buttons = row.split_ascii_whitespace()
+            .map(|name| {
+                Box::new(create_button(
+                    button_info,
+                    outlines,
+                    name,
+                    *button_ids.get(name)
+                        .expect("Button state not created"),
+                    strings,
+                    warning_handler,
+                ))
+            });
+        layout::Row::new(
+            add_offsets(
+                buttons,
+                |button| button.size.width,
+            ).collect()
+        )
+    });
+    let rows = add_offsets(rows, |row| row.get_size().height)
+        .collect();
+    layout::View::new(rows).with_stagger(stagger.into())
+}
+
+/// Gives every button of the clusters a row of its own,
+/// anchored to the cluster's corner.
+fn create_floating_view<H: logging::Handler>(
+    button_info: &HashMap<String, ButtonMeta>,
+    outlines: &HashMap<String, Outline>,
+    button_ids: &HashMap<String, KeyStateId>,
+    clusters: &[Cluster],
+    strings: &mut Interner,
+    warning_handler: &mut H,
+) -> layout::View {
+    let mut rows = Vec::new();
+    for cluster in clusters {
+        let buttons: Vec<(layout::c::Point, layout::Button)> = cluster.buttons.iter()
+            .map(|(name, [x, y])| (
+                layout::c::Point { x: *x, y: *y },
+                create_button(
+                    button_info,
+                    outlines,
+                    name,
+                    *button_ids.get(name)
+                        .expect("Button state not created"),
+                    strings,
+                    warning_handler,
+                ),
+            ))
+            .collect();
+        let size = layout::Size {
+            width: find_max_double(
+                buttons.iter(),
+                |(offset, button)| offset.x + button.size.width,
+            ),
+            height: find_max_double(
+                buttons.iter(),
+                |(offset, button)| offset.y + button.size.height,
+            ),
+        };
+        for (offset, button) in buttons {
+            rows.push((
+                layout::Anchor {
+                    corner: cluster.corner.into(),
+                    margin: cluster.margin,
+                    cluster: size.clone(),
+                    offset,
+                },
+                layout::Row::new(vec![(0.0, Box::new(button))]),
+            ));
+        }
+    }
+    layout::View::floating(rows)
+}
+
+/// The size `create_view` will arrive at, without creating any buttons
+fn calculate_view_size(
+    button_info: &HashMap<String, ButtonMeta>,
+    outlines: &HashMap<String, Outline>,
+    rows: &[String],
+    stagger: &[f64],
+) -> layout::Size {
+    let row_sizes: Vec<layout::Size> = rows.iter()
+        .map(|row| {
+            let outlines: Vec<Outline> = row.split_ascii_whitespace()
+                .map(|name| get_outline(button_info, outlines, name))
+                .collect();
+            layout::Size {
+                width: outlines.iter().map(|outline| outline.width).sum(),
+                height: find_max_double(outlines.iter(), |outline| outline.height),
+            }
+        })
+        .collect();
+    layout::Size {
+        width: find_max_double(
+            row_sizes.iter().enumerate(),
+            |(index, size)| stagger.get(*index).cloned().unwrap_or(0.0) + size.width,
+        ),
+        height: row_sizes.iter().map(|size| size.height).sum(),
+    }
+}
+
+/// Finds the outline like `create_button` does, but quietly.
+fn get_outline(
+    button_info: &HashMap<String, ButtonMeta>,
+    outlines: &HashMap<String, Outline>,
+    name: &str,
+) -> Outline {
+    let outline_name = button_info.get(name)
+        .and_then(|meta| meta.outline.as_ref())
+        .filter(|outline| outlines.contains_key(*outline))
+        .map(String::as_str)
+        .unwrap_or("default");
+    outlines.get(outline_name)
+        .cloned()
+        .unwrap_or(Outline { width: 1f64, height: 1f64, radius: None })
+}
+
 /// TODO: Since this will receive user-provided data,
 /// all .expect() on them should be turned into soft fails
+fn create_behavior<H: logging::Handler>(
+    button_meta: Option<&ButtonMeta>,
+    action: &action::Action,
+    name: &str,
+    warning_handler: &mut H,
+) -> Behavior {
+    let button_meta = match button_meta {
+        Some(meta) => meta,
+        None => return Behavior::default(),
+    };
+    let long_press = match action {
+        action::Action::Submit { text: Some(_), .. } => button_meta.long_press.clone(),
+        _ => {
+            if !button_meta.long_press.is_empty() {
+                warning_handler.handle(
+                    logging::Level::Warning,
+                    &format!("Button {} has long_press, but types no text", name),
+                );
+            }
+            Vec::new()
+        },
+    };
+    let latch_timeout = match action {
+        action::Action::LockView { latches: true, .. } => button_meta.latch_timeout,
+        _ => {
+            if button_meta.latch_timeout.is_some() {
+                warning_handler.handle(
+                    logging::Level::Warning,
+                    &format!("Button {} has latch_timeout, but doesn't latch", name),
+                );
+            }
+            None
+        },
+    };
+    Behavior {
+        repeatable: button_meta.repeatable,
+        long_press,
+        latch_timeout: latch_timeout.map(Duration::from_millis),
+        hold_for_preferences: button_meta.hold_for_preferences,
+    }
+}
+
 fn create_button<H: logging::Handler>(
     button_info: &HashMap<String, ButtonMeta>,
     outlines: &HashMap<String, Outline>,
     name: &str,
-    state: Rc<RefCell<KeyState>>,
+    key: KeyStateId,
+    strings: &mut Interner,
     warning_handler: &mut H,
 ) -> ::layout::Button {
-    let cname = CString::new(name.clone())
+    let cname = strings.get(name)
         .expect("Bad name");
     // don't remove, because multiple buttons with the same name are allowed
     let default_meta = ButtonMeta::default();
     let button_meta = button_info.get(name)
         .unwrap_or(&default_meta);
 
+    let named = button_meta.label_name.as_ref()
+        .map(|name| (name, labels::get_text(name).or_warn(
+            warning_handler,
+            logging::Problem::Warning,
+            &format!("No label named {}, using the name", name),
+        )));
+    // Only known names get translated
+    let label_name = match (&button_meta.label, named) {
+        (None, Some((name, Some(_)))) => strings.get(name).ok(),
+        _ => None,
+    };
+    let named = named.map(|(name, text)| text.unwrap_or(name.as_str()));
     // TODO: move conversion to the C/Rust boundary
-    let label = if let Some(label) = &button_meta.label {
-        ::layout::Label::Text(CString::new(label.as_str())
+    let label = if let Some(label) = button_meta.label.as_ref().map(String::as_str).or(named) {
+        ::layout::Label::Text(strings.get(label)
             .expect("Bad label"))
     } else if let Some(icon) = &button_meta.icon {
-        ::layout::Label::IconName(CString::new(icon.as_str())
+        ::layout::Label::IconName(strings.get(icon)
             .expect("Bad icon"))
     } else if let Some(text) = &button_meta.text {
         ::layout::Label::Text(
-            CString::new(text.as_str())
+            strings.get(text)
                 .or_warn(
                     warning_handler,
                     logging::Problem::Warning,
                     &format!("Text {} is invalid", text),
-                ).unwrap_or_else(|| CString::new("").unwrap())
+                ).unwrap_or_else(|| strings.get("").unwrap())
         )
     } else {
         ::layout::Label::Text(cname.clone())
@@ -512,18 +1730,33 @@ fn create_button<H: logging::Handler>(
             warning_handler,
             logging::Problem::Warning,
             "No default outline defined! Using 1x1!",
-        ).unwrap_or(Outline { width: 1f64, height: 1f64 });
+        ).unwrap_or(Outline { width: 1f64, height: 1f64, radius: None });
+
+    let growth = match button_meta.growth {
+        Some(growth) if !(growth >= 0.0) => {
+            warning_handler.handle(
+                logging::Level::Warning,
+                &format!("Growth of button {} is negative, ignoring", name),
+            );
+            0.0
+        },
+        Some(growth) => growth,
+        None => 0.0,
+    };
 
     layout::Button {
         name: cname,
-        outline_name: CString::new(outline_name).expect("Bad outline"),
+        outline_name: strings.get(&outline_name).expect("Bad outline"),
         // TODO: do layout before creating buttons
         size: layout::Size {
             width: outline.width,
             height: outline.height,
         },
+        corners: outline.radius.as_ref().map(Radius::get_corners),
+        growth,
         label: label,
-        state: state,
+        label_name,
+        key,
     }
 }
 
@@ -536,6 +1769,9 @@ fn extract_symbol_names<'a>(actions: &'a [(&str, action::Action)])
                 action::Action::Submit {
                     text: _, keys,
                 } => Some(keys.clone()),
+                action::Action::Shortcut {
+                    modifiers: _, keys,
+                } => Some(keys.clone()),
                 action::Action::Erase => Some(vec!(action::KeySym("BackSpace".into()))),
                 _ => None,
             }
@@ -552,6 +1788,7 @@ mod tests {
     use std::env;
     
     use ::logging::ProblemPanic;
+    use ::util::Interned;
 
     fn path_from_root(file: &'static str) -> PathBuf {
         let source_dir = env::var("SOURCE_DIR")
@@ -575,7 +1812,7 @@ mod tests {
         assert_eq!(
             Layout::from_file(path_from_root("tests/layout.yaml")).unwrap(),
             Layout {
-                margins: Margins { top: 0f64, bottom: 0f64, side: 0f64 },
+                margins: None,
                 views: hashmap!(
                     "base".into() => vec!("test".into()),
                 ),
@@ -586,17 +1823,102 @@ mod tests {
                         action: None,
                         text: None,
                         modifier: None,
+                        modifiers: Vec::new(),
                         label: Some("test".into()),
+                        label_name: None,
                         outline: None,
+                        repeatable: None,
+                        long_press: Vec::new(),
+                        latch_timeout: None,
+                        growth: None,
                     }
                 },
                 outlines: hashmap!{
-                    "default".into() => Outline { width: 0f64, height: 0f64 }, 
+                    "default".into() => Outline { width: 0f64, height: 0f64, radius: None }, 
                 },
+                menu: Vec::new(),
+                ..Layout::default()
             }
         );
     }
 
+    fn load_from<'a>(parts: &'a HashMap<&str, &str>)
+        -> impl FnMut(&str) -> Result<Layout, Error> + 'a
+    {
+        move |name| {
+            let data = parts.get(name).ok_or_else(|| Error::MissingPart(name.into()))?;
+            parse_str(data, &Context::default())
+        }
+    }
+
+    #[test]
+    fn inheritance() {
+        let parts = hashmap!{
+            "parent" => "
+views:
+    base: [\"a b\", \"$bottom\"]
+    upper: [\"A B\", \"$bottom\"]
+rows:
+    bottom: \"space\"
+outlines:
+    default: { width: 1, height: 1 }
+",
+            "fragment" => "
+rows:
+    bottom: \"comma space period\"
+buttons:
+    comma: { text: \",\" }
+",
+        };
+        let layout: Layout = serde_yaml::from_str("
+extends: parent
+include: [fragment]
+views:
+    upper: [\"C D\", \"$bottom\"]
+").unwrap();
+        let layout = layout.resolve(&mut load_from(&parts)).unwrap();
+        assert_eq!(
+            layout.views["base"],
+            vec!["a b".to_string(), "comma space period".into()],
+        );
+        assert_eq!(layout.views["upper"][0], "C D");
+        assert!(layout.buttons.contains_key("comma"));
+        assert!(layout.has_parts());
+        assert!(!layout.can_compile());
+    }
+
+    #[test]
+    fn conditional_part() {
+        let parts = hashmap!{
+            "parent" => "
+views:
+    base: [\"a b\"]
+    if purpose == terminal:
+        base: [\"a b\", \"Tab\"]
+outlines:
+    default: { width: 1, height: 1 }
+",
+        };
+        let layout = parse_str("extends: parent", &Context::default()).unwrap()
+            .resolve(&mut load_from(&parts))
+            .unwrap();
+        assert!(layout.is_conditional());
+        assert_eq!(layout.views["base"], vec!["a b".to_string()]);
+    }
+
+    #[test]
+    fn inheritance_cycle() {
+        let parts = hashmap!{
+            "first" => "extends: second",
+            "second" => "include: [first]",
+        };
+        let layout: Layout = serde_yaml::from_str("extends: first").unwrap();
+        match layout.resolve(&mut load_from(&parts)) {
+            Err(Error::Cycle(chain)) => assert_eq!(chain, vec!["first", "second", "first"]),
+            other => panic!("Cycle not found: {:?}", other),
+        }
+    }
+
     /// Check if the default protection works
     #[test]
     fn test_empty_views() {
@@ -643,11 +1965,11 @@ mod tests {
             .build(ProblemPanic).0
             .unwrap();
         assert_eq!(
-            out.views["base"].1
+            out.views["base"].1.get().unwrap()
                 .get_rows()[0].1
                 .get_buttons()[0].1
                 .label,
-            ::layout::Label::Text(CString::new("test").unwrap())
+            ::layout::Label::Text(Interned::new("test").unwrap())
         );
     }
 
@@ -658,11 +1980,11 @@ mod tests {
             .build(ProblemPanic).0
             .unwrap();
         assert_eq!(
-            out.views["base"].1
+            out.views["base"].1.get().unwrap()
                 .get_rows()[0].1
                 .get_buttons()[0].1
                 .label,
-            ::layout::Label::Text(CString::new("test").unwrap())
+            ::layout::Label::Text(Interned::new("test").unwrap())
         );
     }
 
@@ -673,16 +1995,50 @@ mod tests {
             .unwrap()
             .build(ProblemPanic).0
             .unwrap();
+        let key = out.views["base"].1.get().unwrap()
+            .get_rows()[0].1
+            .get_buttons()[0].1
+            .key;
         assert_eq!(
-            out.views["base"].1
-                .get_rows()[0].1
-                .get_buttons()[0].1
-                .state.borrow()
-                .keycodes.len(),
+            out.keys.get(key).keycodes.len(),
             2
         );
     }
 
+    /// Built layouts can be stored and read back
+    #[test]
+    fn store_built_layout() {
+        let out = Layout::from_file(path_from_root("tests/layout_key3.yaml"))
+            .unwrap()
+            .build(ProblemPanic).0
+            .unwrap();
+        let stored = serde_yaml::to_string(&out).unwrap();
+        let read: ::layout::LayoutData = serde_yaml::from_str(&stored).unwrap();
+        assert_eq!(serde_yaml::to_string(&read).unwrap(), stored);
+        assert_eq!(read.keymaps, out.keymaps);
+        let key = read.views["base"].1.get().unwrap()
+            .get_rows()[0].1
+            .get_buttons()[0].1
+            .key;
+        assert_eq!(read.keys.get(key).keycodes.len(), 2);
+    }
+
+    /// Views created later turn out as big as promised up front
+    #[test]
+    fn lazy_views() {
+        let yaml = "outlines:\n    default: { width: 10, height: 20 }\n    wide: { width: 25, height: 30 }\nbuttons:\n    big: { outline: wide }\nviews:\n    base:\n        - \"a\"\n    other:\n        - \"a b\"\n        - \"big c\"\n";
+        let mut out = Layout::from_yaml_str(yaml).unwrap()
+            .build_lazily()
+            .unwrap();
+        assert!(out.views["base"].1.get().is_some());
+        let (_offset, other) = out.views.get_mut("other").unwrap();
+        assert!(other.get().is_none());
+        let size = other.get_size();
+        assert_eq!(size, layout::Size { width: 35.0, height: 50.0 });
+        assert_eq!(other.get_row_count(), 2);
+        assert_eq!(other.finalize().get_size(), size);
+    }
+
     /// Test if erase yields a useable keycode
     #[test]
     fn test_layout_erase() {
@@ -690,16 +2046,39 @@ mod tests {
             .unwrap()
             .build(ProblemPanic).0
             .unwrap();
+        let key = out.views["base"].1.get().unwrap()
+            .get_rows()[0].1
+            .get_buttons()[0].1
+            .key;
         assert_eq!(
-            out.views["base"].1
-                .get_rows()[0].1
-                .get_buttons()[0].1
-                .state.borrow()
-                .keycodes.len(),
+            out.keys.get(key).keycodes.len(),
             1
         );
     }
 
+    #[test]
+    fn test_layout_behavior() {
+        let out = Layout::from_file(path_from_root("tests/layout_behavior.yaml"))
+            .unwrap()
+            .build(ProblemPanic).0
+            .unwrap();
+        let get_key = |view: &str, index: usize| {
+            let key = out.views[view].1.get().unwrap()
+                .get_rows()[0].1
+                .get_buttons()[index].1
+                .key;
+            out.keys.get(key)
+        };
+        let e = get_key("base", 0);
+        assert_eq!(e.behavior.long_press.len(), 3);
+        assert!(!e.repeats());
+        assert!(get_key("upper", 0).repeats());
+        assert_eq!(
+            get_key("base", 1).behavior.latch_timeout,
+            Some(Duration::from_millis(2000)),
+        );
+    }
+
     #[test]
     fn unicode_keysym() {
         let keysym = xkb::keysym_from_name(
@@ -721,8 +2100,14 @@ mod tests {
                         text: None,
                         action: None,
                         modifier: None,
+                        modifiers: Vec::new(),
                         label: Some("test".into()),
+                        label_name: None,
                         outline: None,
+                        repeatable: None,
+                        long_press: Vec::new(),
+                        latch_timeout: None,
+                        growth: None,
                     }
                 },
                 ".",
@@ -736,6 +2121,269 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_key_shortcut() {
+        assert_eq!(
+            create_action(
+                &hashmap!{
+                    "Copy".into() => ButtonMeta {
+                        keysym: Some("c".into()),
+                        modifiers: vec![Modifier::Control],
+                        ..ButtonMeta::default()
+                    }
+                },
+                "Copy",
+                Vec::new(),
+                &mut ProblemPanic,
+            ),
+            ::action::Action::Shortcut {
+                modifiers: vec![::action::Modifier::Control],
+                keys: vec!(::action::KeySym("c".into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_pin_pad() {
+        let digits = ['5', '0', '9', '1', '8', '2', '7', '3', '6', '4'];
+        let layout = Layout::pin_pad(&digits);
+        assert_eq!(layout.views["base"][0], "5 0 9");
+        assert_eq!(layout.views["base"][3], "BackSpace 4 Return");
+        let out = layout.build(ProblemPanic).0.unwrap();
+        assert_eq!(
+            out.views["base"].1.get().unwrap()
+                .get_rows()[3].1
+                .get_buttons()[1].1
+                .label,
+            ::layout::Label::Text(Interned::new("4").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_symbol_picker() {
+        let mut groups: Vec<symbols::Group> = symbols::CATEGORIES.iter()
+            .map(|category| symbols::Group {
+                name: category.name.into(),
+                pages: vec![vec!['∀'; symbols::PAGE_SIZE], vec!['∂']],
+            })
+            .collect();
+        groups.insert(0, symbols::Group {
+            name: symbols::RESULTS.into(),
+            pages: vec![Vec::new()],
+        });
+        let layout = Layout::symbol_picker(&groups);
+        assert_eq!(layout.views["base"], vec![
+            "no_matches".to_string(),
+            "preferences blank category_math category_currency \
+category_arrows category_box search blank BackSpace".to_string(),
+        ]);
+        assert_eq!(layout.views["math_1"].len(), 4);
+        assert_eq!(
+            layout.views["math_2"][1],
+            "preferences previous_math_1 category_math category_currency \
+category_arrows category_box search blank BackSpace",
+        );
+        layout.build(ProblemPanic).0.unwrap();
+    }
+
+    #[test]
+    fn test_compose_picker() {
+        let continuations: Vec<compose::Continuation> = (0..compose::PAGE_SIZE + 1)
+            .map(|i| compose::Continuation {
+                keysym: if i == 0 { "a".into() } else { "e".into() },
+                result: if i == 0 { None } else { Some("é".into()) },
+            })
+            .collect();
+        let layout = Layout::compose_picker(&["apostrophe".into()], &continuations);
+        assert_eq!(
+            layout.buttons["compose_0"].action,
+            Some(Action::ComposeStep {
+                sequence: vec!["apostrophe".into(), "a".into()],
+                result: None,
+            }),
+        );
+        assert_eq!(layout.buttons["compose_1"].label, Some("é".into()));
+        assert_eq!(layout.buttons["compose_0"].label, Some("a".into()));
+        assert_eq!(layout.buttons["pressed"].label, Some("'".into()));
+        assert_eq!(
+            layout.views["page_2"],
+            vec![
+                format!("compose_{}", compose::PAGE_SIZE),
+                "preferences cancel previous_base pressed blank BackSpace".into(),
+            ],
+        );
+        layout.build(ProblemPanic).0.unwrap();
+    }
+
+    const BOTTOM_ROW_LAYOUT: &str = "
+outlines:
+    default: { width: 10, height: 10 }
+    wide: { width: 20, height: 10 }
+    spaceline: { width: 100, height: 10 }
+views:
+    base:
+        - \"a b\"
+        - \"preferences space period Return\"
+buttons:
+    preferences: { action: show_prefs }
+    space: { outline: spaceline, text: \" \" }
+    period: { text: \".\" }
+    Return: { outline: wide, keysym: Return }
+";
+
+    #[test]
+    fn bottom_row_unchanged() {
+        let layout = Layout::from_yaml_str(BOTTOM_ROW_LAYOUT).unwrap()
+            .with_bottom_row(&BottomRow::default());
+        assert_eq!(
+            layout.views["base"][1],
+            "preferences space period Return",
+        );
+    }
+
+    #[test]
+    fn bottom_row_swap() {
+        let layout = Layout::from_yaml_str(BOTTOM_ROW_LAYOUT).unwrap()
+            .with_bottom_row(&BottomRow {
+                emoji: true,
+                voice: false,
+                language: false,
+                comma: true,
+                period: false,
+                extra_keys: Vec::new(),
+            });
+        assert_eq!(
+            layout.views["base"][1],
+            "bottom_row_emoji space bottom_row_comma Return",
+        );
+        // As many keys came as went, so the space bar stays the same.
+        assert_eq!(layout.get_outline_width("space"), 100.0);
+        layout.build(ProblemPanic).0.unwrap();
+    }
+
+    #[test]
+    fn space_holds_preferences() {
+        let layout = Layout::from_yaml_str(BOTTOM_ROW_LAYOUT).unwrap()
+            .with_bottom_row(&BottomRow {
+                language: false,
+                ..BottomRow::default()
+            });
+        assert_eq!(layout.views["base"][1], "space_base period Return");
+        assert!(layout.buttons["space_base"].hold_for_preferences);
+
+        let layout = Layout::from_yaml_str(BOTTOM_ROW_LAYOUT).unwrap()
+            .with_bottom_row(&BottomRow::default());
+        assert!(!layout.buttons["space"].hold_for_preferences);
+    }
+
+    #[test]
+    fn bottom_row_space_grows() {
+        let layout = Layout::from_yaml_str(BOTTOM_ROW_LAYOUT).unwrap()
+            .with_bottom_row(&BottomRow {
+                period: false,
+                ..BottomRow::default()
+            });
+        assert_eq!(layout.views["base"][1], "preferences space_base Return");
+        assert_eq!(layout.get_outline_width("space_base"), 110.0);
+    }
+
+    #[test]
+    fn extra_row() {
+        let layout = Layout::from_yaml_str(BOTTOM_ROW_LAYOUT).unwrap()
+            .with_bottom_row(&BottomRow {
+                extra_keys: vec!["/".into(), " ".into(), "Left".into()],
+                ..BottomRow::default()
+            });
+        assert_eq!(layout.views["base"][0], "extra_row_0 extra_row_1");
+        assert_eq!(layout.buttons["extra_row_0"].text, Some("/".into()));
+        assert_eq!(layout.buttons["extra_row_1"].keysym, Some("Left".into()));
+        assert_eq!(layout.buttons["extra_row_1"].label, Some("←".into()));
+        layout.build(ProblemPanic).0.unwrap();
+    }
+
+    #[test]
+    fn named_label() {
+        let out = Layout::from_yaml_str("
+outlines:
+    default: { width: 1, height: 1 }
+views:
+    base: [\"letters\"]
+buttons:
+    letters: { label_name: numbers_symbols, action: { set_view: base } }
+").unwrap()
+            .build(ProblemPanic).0.unwrap();
+        assert_eq!(
+            out.views["base"].1.get().unwrap()
+                .get_rows()[0].1
+                .get_buttons()[0].1
+                .label,
+            ::layout::Label::Text(Interned::new("?123").unwrap())
+        );
+        assert_eq!(
+            out.views["base"].1.get().unwrap()
+                .get_rows()[0].1
+                .get_buttons()[0].1
+                .label_name,
+            Some(Interned::new("numbers_symbols").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_menu() {
+        let layout = Layout::from_yaml_str("
+outlines:
+    default: { width: 10, height: 10 }
+views:
+    base: [\"a\"]
+    greek: [\"a\"]
+menu:
+    - { label: Greek, action: { set_view: greek } }
+    - { label: Emoji, action: { show_overlay: emoji } }
+").unwrap();
+        let out = layout.build(ProblemPanic).0.unwrap();
+        assert_eq!(
+            out.menu,
+            vec![
+                layout::MenuEntry {
+                    label: "Greek".into(),
+                    action: ::action::Action::SetView("greek".into()),
+                },
+                layout::MenuEntry {
+                    label: "Emoji".into(),
+                    action: ::action::Action::ShowOverlay("emoji".into()),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn test_floating() {
+        let layout = Layout::from_yaml_str("
+outlines:
+    default: { width: 10, height: 10 }
+floating:
+    views:
+        base:
+            - corner: top_left
+              buttons: { a: [0, 0], b: [20, 0] }
+            - corner: bottom_right
+              margin: 5
+              buttons: { c: [0, 0] }
+").unwrap();
+        let out = layout.build(ProblemPanic).0.unwrap();
+        assert_eq!(out.floating, Some(layout::Floating { opacity: 0.5 }));
+        let (offset, view) = &out.views["base"];
+        assert_eq!(offset, &layout::c::Point { x: 0.0, y: 0.0 });
+        let view = view.get().unwrap();
+        assert!(view.is_floating());
+        assert_eq!(view.get_rows().len(), 3);
+        // Only "c" has a margin.
+        assert_eq!(
+            view.get_size(),
+            layout::Size { width: 30.0 + 15.0, height: 10.0 + 15.0 },
+        );
+    }
+
     #[test]
     fn test_layout_margins() {
         let out = Layout::from_file(path_from_root("tests/layout_margins.yaml"))
@@ -753,6 +2401,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn outline_radius() {
+        let outlines: HashMap<String, Outline> = serde_yaml::from_str("
+round: { width: 1, height: 1, radius: 4 }
+tab: { width: 1, height: 1, radius: [4, 4, 0, 0.5] }
+").unwrap();
+        assert_eq!(
+            outlines["round"].radius.as_ref().map(Radius::get_corners),
+            Some([4.0; 4]),
+        );
+        assert_eq!(
+            outlines["tab"].radius.as_ref().map(Radius::get_corners),
+            Some([4.0, 4.0, 0.0, 0.5]),
+        );
+    }
+
     #[test]
     fn test_extract_symbols() {
         let actions = [(
@@ -0,0 +1,294 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Contents of the symbols picker.
+ *
+ * The picker isn't described by a layout file.
+ * Instead, its pages get generated on first use,
+ * from the character ranges of each category.
+ * Character names come from the Unicode database installed on the system,
+ * and without it, the categories still work, but search finds nothing.
+ *
+ * The search query is the word in front of the text cursor.
+ * Without one, the query gets typed on the text layout
+ * into the keyboard's own text field, and Return searches.
+ */
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{ BufRead, BufReader };
+use std::rc::Rc;
+
+use ::logging;
+use ::memory;
+
+
+/// The name under which the picker is shown as an overlay
+pub const OVERLAY_NAME: &str = "symbols";
+
+/// The group holding search results
+pub const RESULTS: &str = "results";
+
+/// Symbols in a single row
+pub const ROW_SIZE: usize = 8;
+/// Symbols on a single page
+pub const PAGE_SIZE: usize = ROW_SIZE * 3;
+
+const UNICODE_DATA_PATHS: &[&str] = &[
+    "/usr/share/unicode/UnicodeData.txt",
+    "/usr/share/unicode-data/UnicodeData.txt",
+];
+
+pub struct Category {
+    pub name: &'static str,
+    /// Shown on the key switching to the category
+    pub label: &'static str,
+    /// Inclusive ranges of code points
+    ranges: &'static [(u32, u32)],
+}
+
+pub const CATEGORIES: &[Category] = &[
+    Category {
+        name: "math",
+        label: "∑",
+        ranges: &[(0x2200, 0x22ff)],
+    },
+    Category {
+        name: "currency",
+        label: "€",
+        ranges: &[(0x24, 0x24), (0xa2, 0xa5), (0x20a0, 0x20c0)],
+    },
+    Category {
+        name: "arrows",
+        label: "→",
+        ranges: &[(0x2190, 0x21ff)],
+    },
+    Category {
+        name: "box",
+        label: "┼",
+        ranges: &[(0x2500, 0x257f)],
+    },
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub character: char,
+    /// Upper case, like in the Unicode database. May be empty.
+    pub name: String,
+    /// Index into CATEGORIES
+    category: usize,
+}
+
+/// Pages to flip through, e.g. a category
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub pages: Vec<Vec<char>>,
+}
+
+impl Group {
+    /// There's always at least one page, even if empty.
+    fn new(name: &str, symbols: Vec<char>) -> Group {
+        let pages: Vec<Vec<char>> = symbols.chunks(PAGE_SIZE)
+            .map(|page| page.to_vec())
+            .collect();
+        Group {
+            name: name.into(),
+            pages: match pages.is_empty() {
+                true => vec![Vec::new()],
+                false => pages,
+            },
+        }
+    }
+
+    /// Name of the view showing the page
+    pub fn get_view_name(&self, page: usize) -> String {
+        format!("{}_{}", self.name, page + 1)
+    }
+}
+
+fn parse_code(code: &str) -> Option<char> {
+    u32::from_str_radix(code, 16).ok().and_then(std::char::from_u32)
+}
+
+/// Parses a line from UnicodeData.txt,
+/// e.g. `2200;FOR ALL;Sm;0;ON;;;;;N;;;;;`
+fn parse_line(line: &str) -> Option<(char, &str)> {
+    let mut fields = line.split(';');
+    let character = fields.next().and_then(parse_code)?;
+    let name = fields.next()?;
+    // Ranges and control characters have no real names
+    if name.starts_with('<') {
+        None
+    } else {
+        Some((character, name))
+    }
+}
+
+fn find_category(character: char) -> Option<usize> {
+    let code = character as u32;
+    CATEGORIES.iter().position(|category|
+        category.ranges.iter().any(|(start, end)| *start <= code && code <= *end)
+    )
+}
+
+fn read_unicode_data<R: BufRead>(reader: R) -> Vec<Symbol> {
+    reader.lines()
+        .filter_map(|line| line.ok())
+        .filter_map(|line| {
+            let (character, name) = parse_line(&line)?;
+            find_category(character).map(|category| Symbol {
+                character,
+                name: name.into(),
+                category,
+            })
+        })
+        .collect()
+}
+
+/// Every code point in the ranges, without names
+fn get_unnamed_symbols() -> Vec<Symbol> {
+    CATEGORIES.iter().enumerate()
+        .flat_map(|(category, c)| c.ranges.iter().map(move |range| (category, range)))
+        .flat_map(|(category, (start, end))|
+            (*start..=*end)
+                .filter_map(std::char::from_u32)
+                .map(move |character| Symbol {
+                    character,
+                    name: String::new(),
+                    category,
+                })
+        )
+        .collect()
+}
+
+fn load_symbols() -> Vec<Symbol> {
+    let file = UNICODE_DATA_PATHS.iter()
+        .filter_map(|path| File::open(path).ok())
+        .next();
+    match file {
+        Some(file) => read_unicode_data(BufReader::new(file)),
+        None => {
+            log_print!(
+                logging::Level::Warning,
+                "No Unicode database, symbol search won't work",
+            );
+            get_unnamed_symbols()
+        },
+    }
+}
+
+thread_local! {
+    /// Reading the database takes a while, so do it just once.
+    static SYMBOLS: RefCell<Option<Rc<Vec<Symbol>>>> = RefCell::new(None);
+}
+
+fn get_symbols() -> Rc<Vec<Symbol>> {
+    SYMBOLS.with(|symbols| {
+        symbols.borrow_mut()
+            .get_or_insert_with(|| {
+                let loaded = load_symbols();
+                memory::set_size(memory::Cache::Symbols, loaded.len());
+                Rc::new(loaded)
+            })
+            .clone()
+    })
+}
+
+/// Frees the database until next needed.
+pub fn drop_cache() {
+    SYMBOLS.with(|symbols| symbols.borrow_mut().take());
+    memory::set_size(memory::Cache::Symbols, 0);
+}
+
+/// Returns symbols whose names contain all words of the query.
+pub fn search<'a>(symbols: &'a [Symbol], query: &str) -> Vec<&'a Symbol> {
+    let words: Vec<String> = query.split_whitespace()
+        .map(str::to_uppercase)
+        .collect();
+    if words.is_empty() {
+        return Vec::new();
+    }
+    symbols.iter()
+        .filter(|symbol| words.iter().all(|word| symbol.name.contains(word.as_str())))
+        .collect()
+}
+
+fn get_groups_from(symbols: &[Symbol], query: Option<&str>) -> Vec<Group> {
+    let results = query.map(|query| Group::new(
+        RESULTS,
+        search(symbols, query).into_iter().map(|s| s.character).collect(),
+    ));
+    let categories = CATEGORIES.iter().enumerate()
+        .map(|(index, category)| Group::new(
+            category.name,
+            symbols.iter()
+                .filter(|symbol| symbol.category == index)
+                .map(|symbol| symbol.character)
+                .collect(),
+        ));
+    results.into_iter().chain(categories).collect()
+}
+
+/// Returns the groups of pages to show in the picker.
+/// Results of the search come first, if there was a query.
+pub fn get_groups(query: Option<&str>) -> Vec<Group> {
+    get_groups_from(&get_symbols(), query)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DATA: &str = "0041;LATIN CAPITAL LETTER A;Lu;0;L;;;;;N;;;;0061;
+20AC;EURO SIGN;Sc;0;ET;;;;;N;;;;;
+2190;LEFTWARDS ARROW;Sm;0;ON;;;;;N;LEFT ARROW;;;;
+2192;RIGHTWARDS ARROW;Sm;0;ON;;;;;N;RIGHT ARROW;;;;
+21D2;RIGHTWARDS DOUBLE ARROW;Sm;0;ON;;;;;N;RIGHT DOUBLE ARROW;;;;
+";
+
+    fn symbols() -> Vec<Symbol> {
+        read_unicode_data(DATA.as_bytes())
+    }
+
+    #[test]
+    fn only_categories() {
+        let symbols = symbols();
+        let chars: Vec<char> = symbols.iter().map(|s| s.character).collect();
+        assert_eq!(chars, vec!['€', '←', '→', '⇒']);
+    }
+
+    #[test]
+    fn search_words() {
+        let symbols = symbols();
+        let found: Vec<char> = search(&symbols, "right arrow")
+            .into_iter().map(|s| s.character).collect();
+        assert_eq!(found, vec!['→', '⇒']);
+        assert_eq!(search(&symbols, "  ").len(), 0);
+    }
+
+    #[test]
+    fn results_first() {
+        let groups = get_groups_from(&symbols(), Some("euro"));
+        assert_eq!(groups[0], Group { name: RESULTS.into(), pages: vec![vec!['€']] });
+        assert_eq!(groups.len(), CATEGORIES.len() + 1);
+    }
+
+    #[test]
+    fn nothing_found() {
+        let groups = get_groups_from(&symbols(), Some("xyzzy"));
+        assert_eq!(groups[0].pages, vec![Vec::<char>::new()]);
+    }
+
+    #[test]
+    fn paged() {
+        let group = Group::new("box", get_unnamed_symbols().iter()
+            .filter(|s| s.category == 3)
+            .map(|s| s.character)
+            .collect()
+        );
+        assert_eq!(group.pages.len(), 128 / PAGE_SIZE + 1);
+        assert_eq!(group.get_view_name(0), "box_1");
+    }
+}
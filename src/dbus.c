@@ -20,6 +20,7 @@
 
 #include "dbus.h"
 #include "main.h"
+#include "wayland.h"
 
 #include <inttypes.h>
 #include <stdio.h>
@@ -49,11 +50,27 @@ dbus_handler_destroy(DBusHandler *service)
     free(service);
 }
 
+/// Returns TRUE if the call got refused
+static gboolean
+refuse_in_kiosk(GDBusMethodInvocation *invocation) {
+    if (!squeek_kiosk_is_enabled()) {
+        return FALSE;
+    }
+    g_dbus_method_invocation_return_error(invocation, G_DBUS_ERROR,
+                                          G_DBUS_ERROR_ACCESS_DENIED,
+                                          "Not allowed in kiosk mode");
+    return TRUE;
+}
+
 static gboolean
 handle_set_visible(SmPuriOSK0 *object, GDBusMethodInvocation *invocation,
                    gboolean arg_visible, gpointer user_data) {
     DBusHandler *service = user_data;
 
+    if (refuse_in_kiosk(invocation)) {
+        return TRUE;
+    }
+
     if (arg_visible) {
         squeek_state_send_force_visible (service->state_manager);
     } else {
@@ -64,6 +81,36 @@ handle_set_visible(SmPuriOSK0 *object, GDBusMethodInvocation *invocation,
     return TRUE;
 }
 
+static gboolean
+handle_app_request_visible(SmPuriOSK0 *object, GDBusMethodInvocation *invocation,
+                           gboolean arg_visible, gpointer user_data) {
+    DBusHandler *service = user_data;
+
+    if (refuse_in_kiosk(invocation)) {
+        return TRUE;
+    }
+
+    squeek_state_send_app_visibility(service->state_manager, arg_visible);
+
+    sm_puri_osk0_complete_app_request_visible(object, invocation);
+    return TRUE;
+}
+
+static gboolean
+handle_start_cleaning(SmPuriOSK0 *object, GDBusMethodInvocation *invocation,
+                      gpointer user_data) {
+    DBusHandler *service = user_data;
+
+    if (refuse_in_kiosk(invocation)) {
+        return TRUE;
+    }
+
+    squeek_state_send_start_cleaning(service->state_manager);
+
+    sm_puri_osk0_complete_start_cleaning(object, invocation);
+    return TRUE;
+}
+
 DBusHandler *
 dbus_handler_new (GDBusConnection *connection,
                       const gchar     *object_path,
@@ -77,6 +124,13 @@ dbus_handler_new (GDBusConnection *connection,
     self->dbus_interface = sm_puri_osk0_skeleton_new();
     g_signal_connect(self->dbus_interface, "handle-set-visible",
                      G_CALLBACK(handle_set_visible), self);
+    g_signal_connect(self->dbus_interface, "handle-app-request-visible",
+                     G_CALLBACK(handle_app_request_visible), self);
+    g_signal_connect(self->dbus_interface, "handle-start-cleaning",
+                     G_CALLBACK(handle_start_cleaning), self);
+
+    struct squeek_rect nothing = {0};
+    dbus_handler_set_geometry(self, &nothing, NULL, 0);
 
     if (self->connection && self->object_path) {
         GError *error = NULL;
@@ -99,3 +153,43 @@ void dbus_handler_set_visible(DBusHandler *service,
 {
     sm_puri_osk0_set_visible(service->dbus_interface, visible);
 }
+
+/// Lists the protocols available for typing.
+void dbus_handler_set_capabilities(DBusHandler *service,
+                                   const struct squeek_wayland *wayland)
+{
+    const gchar *capabilities[4] = {0};
+    size_t count = 0;
+    if (wayland->input_method) {
+        capabilities[count++] = "input-method-v2";
+    }
+    if (wayland->virtual_keyboard) {
+        capabilities[count++] = "virtual-keyboard-v1";
+    }
+    if (wayland->text_input_manager) {
+        capabilities[count++] = "text-input-v3";
+    }
+    sm_puri_osk0_set_capabilities(service->dbus_interface, capabilities);
+}
+
+static GVariant *
+rect_to_variant(const struct squeek_rect *rect)
+{
+    return g_variant_new("(iiii)", rect->x, rect->y, rect->width, rect->height);
+}
+
+// Exported to Rust
+void dbus_handler_set_geometry(DBusHandler *service,
+                               const struct squeek_rect *panel,
+                               const struct squeek_rect *rows,
+                               size_t row_count)
+{
+    GVariantBuilder builder;
+    g_variant_builder_init(&builder, G_VARIANT_TYPE("a(iiii)"));
+    for (size_t i = 0; i < row_count; i++) {
+        g_variant_builder_add_value(&builder, rect_to_variant(&rows[i]));
+    }
+    sm_puri_osk0_set_bounds(service->dbus_interface, rect_to_variant(panel));
+    sm_puri_osk0_set_row_bounds(service->dbus_interface,
+                                g_variant_builder_end(&builder));
+}
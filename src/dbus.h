@@ -19,13 +19,25 @@
 #ifndef DBUS_H_
 #define DBUS_H_ 1
 
+#include <stddef.h>
+#include <stdint.h>
 #include "sm.puri.OSK0.h"
 
 // From main.h
 struct squeek_state_manager;
+// From wayland.h
+struct squeek_wayland;
 
 G_BEGIN_DECLS
 
+/// In logical pixels of the output. See panel.rs
+struct squeek_rect {
+    int32_t x;
+    int32_t y;
+    int32_t width;
+    int32_t height;
+};
+
 #define DBUS_SERVICE_PATH "/sm/puri/OSK0"
 #define DBUS_SERVICE_INTERFACE "sm.puri.OSK0"
 
@@ -50,5 +62,11 @@ DBusHandler * dbus_handler_new      (GDBusConnection *connection,
                                      struct squeek_state_manager *state_manager);
 
 void dbus_handler_destroy(DBusHandler*);
+void dbus_handler_set_capabilities(DBusHandler *service,
+                                   const struct squeek_wayland *wayland);
+void dbus_handler_set_geometry(DBusHandler *service,
+                               const struct squeek_rect *panel,
+                               const struct squeek_rect *rows,
+                               size_t row_count);
 G_END_DECLS
 #endif  /* DBUS_H_ */
@@ -3,11 +3,24 @@
  *
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
+use std::collections::HashMap;
+use std::sync::{ mpsc, Arc, Mutex };
 use std::thread;
 use zbus::{Connection, ObjectServer, dbus_interface, fdo};
 
+use crate::animation;
+use crate::data::{ export, loading, parsing };
 use crate::event_loop;
+use crate::imservice::ContentPurpose;
+use crate::inspect::Inspector;
+use crate::layout::ArrangementKind;
+use crate::main;
+use crate::main::Commands;
+use crate::memory;
 use crate::state;
+use crate::statistics;
+use crate::stuck_keys;
+use crate::submission;
 
 
 use std::convert::TryInto;
@@ -17,6 +30,11 @@ use std::convert::TryInto;
 struct Manager {
     sender: event_loop::driver::Threaded,
     enabled: bool,
+    submission_journal: submission::Journal,
+    statistics_clearing: statistics::Clearing,
+    inspector: Inspector,
+    /// What the panel was last told to show
+    shown: Arc<Mutex<Option<animation::Contents>>>,
 }
 
 #[dbus_interface(name = "sm.puri.SqueekDebug")]
@@ -35,6 +53,92 @@ impl Manager {
             ))
             .unwrap();
     }
+
+    /// Entries held in each of the caches dropped while hidden
+    #[dbus_interface(property, name = "CacheSizes")]
+    fn get_cache_sizes(&self) -> HashMap<String, u64> {
+        memory::get_sizes()
+    }
+
+    /// Keys released after staying pressed for too long
+    #[dbus_interface(property, name = "StuckKeysReleased")]
+    fn get_stuck_keys_released(&self) -> u64 {
+        stuck_keys::get_released_count()
+    }
+
+    /// Whether the operations submitting text get recorded
+    #[dbus_interface(property, name = "SubmissionJournal")]
+    fn get_submission_journal(&self) -> bool {
+        self.submission_journal.is_enabled()
+    }
+    #[dbus_interface(property, name = "SubmissionJournal")]
+    fn set_submission_journal(&mut self, enabled: bool) {
+        self.submission_journal.set_enabled(enabled);
+    }
+
+    /// The recorded operations, oldest first.
+    /// Empty unless `SubmissionJournal` is on.
+    fn dump_submission_journal(&self) -> Vec<String> {
+        self.submission_journal.dump()
+    }
+
+    /// Shows the layout defined in the YAML string,
+    /// until `ReloadLayout` is called.
+    /// For editors to preview the layout without saving it.
+    fn load_layout_from_string(&self, yaml: &str) -> fdo::Result<()> {
+        // Check early, to let the editor know what's wrong.
+        loading::load_layout_from_str(
+            yaml,
+            ArrangementKind::Base,
+            ContentPurpose::Normal,
+            &parsing::BottomRow::default(),
+        )
+            .map_err(|e| fdo::Error::InvalidArgs(format!("{}", e)))?;
+        self.sender
+            .send(state::Event::PreviewLayout(yaml.into()))
+            .map_err(|e| fdo::Error::Failed(format!("{}", e)))
+    }
+
+    /// Describes the layout being shown as JSON,
+    /// with the positions and actions of the buttons in all its views.
+    fn export_layout(&self) -> fdo::Result<String> {
+        let description = self.shown.lock().unwrap().clone()
+            .ok_or_else(|| fdo::Error::Failed("No layout shown yet".into()))?;
+        // The order must stay secret.
+        if description.scramble {
+            return Err(fdo::Error::AccessDenied("The digits are scrambled".into()));
+        }
+        // Loaded again here, because the shown one belongs to the UI thread.
+        let (mut layout, _substitute) = main::load_layout(&description);
+        Ok(export::to_json(&mut layout))
+    }
+
+    /// Describes the state, the layout shown, and the keys held down, as JSON.
+    /// The layout is missing while its digits are scrambled.
+    fn dump_state(&self) -> String {
+        let description = self.shown.lock().unwrap().clone()
+            .filter(|description| !description.scramble);
+        match description {
+            Some(description) => {
+                let (mut layout, _substitute) = main::load_layout(&description);
+                self.inspector.dump(Some(&mut layout))
+            },
+            None => self.inspector.dump(None),
+        }
+    }
+
+    /// Forgets the usage statistics, also on the disk.
+    fn clear_statistics(&self) -> fdo::Result<()> {
+        self.statistics_clearing.request()
+            .map_err(fdo::Error::Failed)
+    }
+
+    /// Loads the current layout again from its file.
+    fn reload_layout(&self) -> fdo::Result<()> {
+        self.sender
+            .send(state::Event::ReloadLayout)
+            .map_err(|e| fdo::Error::Failed(format!("{}", e)))
+    }
 }
 
 fn start(mgr: Manager) -> Result<(), Box<dyn std::error::Error>> {
@@ -54,11 +158,37 @@ fn start(mgr: Manager) -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-pub fn init(sender: event_loop::driver::Threaded) {
+/// Keeps track of the layout shown, using the commands to the UI.
+fn watch_layouts(
+    commands: mpsc::Receiver<Commands>,
+    shown: Arc<Mutex<Option<animation::Contents>>>,
+) {
+    for commands in commands.iter() {
+        if let Some(main::commands::SetLayout { description }) = commands.layout_selection {
+            *shown.lock().unwrap() = Some(description);
+        }
+    }
+}
+
+pub fn init(
+    sender: event_loop::driver::Threaded,
+    submission_journal: submission::Journal,
+    statistics_clearing: statistics::Clearing,
+    inspector: Inspector,
+    commands: mpsc::Receiver<Commands>,
+) {
+    let shown = Arc::new(Mutex::new(None));
     let mgr = Manager {
         sender,
         enabled: false,
+        submission_journal,
+        statistics_clearing,
+        inspector,
+        shown: shown.clone(),
     };
+    thread::spawn(move || {
+        watch_layouts(commands, shown);
+    });
     thread::spawn(move || {
         start(mgr).unwrap();
     });
@@ -1,13 +1,20 @@
 /*! Drawing the UI */
 
 use cairo;
-use std::cell::RefCell;
 
 use ::action::{ Action, Modifier };
+use ::automation;
+use ::clock::{ Clock, System };
+use ::flash;
+use ::handwriting;
 use ::keyboard;
+use ::layout;
 use ::layout::{ Button, Label, LatchedState, Layout };
 use ::layout::c::{ Bounds, EekGtkKeyboard, Point };
+use ::rearrange;
+use ::statistics;
 use ::submission::c::Submission as CSubmission;
+use ::voice;
 
 use glib::translate::FromGlibPtrNone;
 use gtk::prelude::WidgetExt;
@@ -15,6 +22,7 @@ use gtk::prelude::WidgetExt;
 use std::collections::HashSet;
 use std::ffi::CStr;
 use std::ptr;
+use std::sync::atomic::{ AtomicBool, Ordering };
 
 mod c {
     use super::*;
@@ -50,6 +58,7 @@ mod c {
             bounds: Bounds,
             icon_name: *const c_char,
             label: *const c_char,
+            radii: *const f64,
         );
 
         #[allow(improper_ctypes)]
@@ -85,7 +94,7 @@ mod c {
         let active_modifiers = submission.get_active_modifiers();
 
         layout.foreach_visible_button(|offset, button| {
-            let state = RefCell::borrow(&button.state).clone();
+            let state = layout.keys.get(button.key);
 
             let locked = LockedStyle::from_action(
                 &state.action,
@@ -103,7 +112,36 @@ mod c {
                     state.pressed, locked,
                 );
             }
-        })
+        });
+
+        render_ripples(&cr, layout);
+
+        handwriting::render(&cr);
+
+        rearrange::render(&cr, layout);
+
+        automation::render(&cr, layout);
+
+        flash::render_error(&cr, layout);
+
+        layout.foreach_visible_button(|offset, button| {
+            if let Action::Statistics = layout.keys.get(button.key).action {
+                statistics::render(&cr, &Bounds {
+                    x: offset.x,
+                    y: offset.y,
+                    width: button.size.width,
+                    height: button.size.height,
+                });
+            }
+        });
+
+        if let Some(seconds) = layout::get_cleaning() {
+            render_countdown(&cr, layout, seconds);
+        }
+
+        if DEBUG_OVERLAY.load(Ordering::Relaxed) {
+            render_debug_overlay(&cr, layout);
+        }
     }
     
     #[no_mangle]
@@ -128,6 +166,17 @@ mod c {
     }
 }
 
+/// Whether to draw debug information on top of the keyboard.
+/// Changed from the main loop when debug mode gets toggled.
+static DEBUG_OVERLAY: AtomicBool = AtomicBool::new(false);
+
+/// Redraws the keyboard when the setting changes.
+pub fn set_debug_overlay(enabled: bool) {
+    if DEBUG_OVERLAY.swap(enabled, Ordering::Relaxed) != enabled {
+        queue_redraw_all();
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum LockedStyle {
     Free,
@@ -144,6 +193,7 @@ impl LockedStyle {
     ) -> LockedStyle {
         let active_mod = match action {
             Action::ApplyModifier(m) => mods.contains(m),
+            Action::VoiceInput => voice::is_listening(),
             _ => false,
         };
         
@@ -195,6 +245,10 @@ fn render_button_at_position(
         },
     };
 
+    let radii = button.corners.as_ref()
+        .map(|corners| corners.as_ptr())
+        .unwrap_or(ptr::null());
+
     with_button_context(
         renderer,
         button,
@@ -210,6 +264,7 @@ fn render_button_at_position(
                 bounds,
                 icon_name_c,
                 label_c,
+                radii,
             )
         }
     );
@@ -217,6 +272,109 @@ fn render_button_at_position(
     cr.restore();
 }
 
+/// Draws the press animations, and forgets the finished ones.
+fn render_ripples(cr: &cairo::Context, layout: &mut Layout) {
+    let now = System.now();
+    layout.ripples.retain(|ripple| ripple.get_progress(now).is_some());
+    for ripple in &layout.ripples {
+        if ripple.view != layout.current_view {
+            continue;
+        }
+        let progress = match ripple.get_progress(now) {
+            Some(progress) => progress,
+            None => continue,
+        };
+        if flash::is_enabled() {
+            flash::render_press(cr, &ripple.bounds, progress);
+            continue;
+        }
+        let Bounds { x, y, width, height } = ripple.bounds;
+        // Reaches the farthest corner just as it fades out
+        let reach = width.hypot(height);
+        cr.save();
+        cr.rectangle(x, y, width, height);
+        cr.clip();
+        cr.arc(
+            ripple.center.x, ripple.center.y,
+            reach * progress,
+            0.0, 2.0 * std::f64::consts::PI,
+        );
+        cr.set_source_rgba(1.0, 1.0, 1.0, 0.3 * (1.0 - progress));
+        cr.fill();
+        cr.restore();
+    }
+}
+
+/// Outlines the hit boxes of the visible buttons with their key IDs,
+/// marks the last touch point,
+/// and prints the view state in the top left corner.
+fn render_debug_overlay(cr: &cairo::Context, layout: &Layout) {
+    const LINE_HEIGHT: f64 = 12.0;
+    cr.save();
+    cr.set_line_width(1.0);
+    cr.set_font_size(10.0);
+    cr.set_source_rgba(1.0, 0.0, 0.0, 0.8);
+    layout.foreach_visible_button(|offset, button| {
+        cr.rectangle(
+            offset.x, offset.y,
+            button.size.width, button.size.height,
+        );
+        cr.stroke();
+        cr.move_to(offset.x + 2.0, offset.y + LINE_HEIGHT);
+        cr.show_text(&button.key.to_string());
+    });
+
+    if let Some(Point { x, y }) = layout.last_touch {
+        const ARM: f64 = 6.0;
+        cr.set_source_rgba(0.0, 0.0, 1.0, 0.8);
+        cr.move_to(x - ARM, y);
+        cr.line_to(x + ARM, y);
+        cr.move_to(x, y - ARM);
+        cr.line_to(x, y + ARM);
+        cr.stroke();
+    }
+
+    let mut pressed: Vec<_> = layout.pressed_keys.iter()
+        .map(|key| key.to_string())
+        .collect();
+    // Shorter numbers are smaller.
+    pressed.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+    let lines = [
+        format!("view: {}", layout.current_view),
+        format!("latched: {:?}", layout.get_view_latched()),
+        format!("pressed: {}", pressed.join(", ")),
+    ];
+    cr.set_source_rgba(0.0, 0.0, 0.0, 0.9);
+    for (i, line) in lines.iter().enumerate() {
+        cr.move_to(2.0, LINE_HEIGHT * (i + 1) as f64);
+        cr.show_text(line);
+    }
+    cr.restore();
+}
+
+/// Dims the keyboard, and shows how long it stays unresponsive.
+fn render_countdown(cr: &cairo::Context, layout: &Layout, seconds: u64) {
+    let (offset, view) = layout.get_current_view_position();
+    let size = view.get_size();
+    cr.save();
+    cr.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+    cr.rectangle(offset.x, offset.y, size.width, size.height);
+    cr.fill();
+
+    let text = seconds.to_string();
+    let font_size = size.height / 3.0;
+    // Digits are roughly this wide in most fonts
+    let width = text.len() as f64 * font_size * 0.6;
+    cr.set_font_size(font_size);
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+    cr.move_to(
+        offset.x + (size.width - width) / 2.0,
+        offset.y + (size.height + font_size * 0.7) / 2.0,
+    );
+    cr.show_text(&text);
+    cr.restore();
+}
+
 fn with_button_context<R, F: FnOnce(&c::GtkStyleContext) -> R>(
     renderer: c::EekRenderer,
     button: &Button,
@@ -263,6 +421,17 @@ pub fn queue_redraw(keyboard: EekGtkKeyboard) {
     widget.queue_draw();
 }
 
+/// For when the panel widget is out of reach.
+/// Does nothing without GTK, like in tests.
+pub fn queue_redraw_all() {
+    if !gtk::is_initialized() {
+        return;
+    }
+    for window in gtk::Window::list_toplevels() {
+        window.queue_draw();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,196 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Deleting more at a time while the erase key is held.
+ *
+ * At first, a held erase key repeats like any other key,
+ * with the application doing the repetition.
+ * After a while, deletion moves on to whole words, then to whole lines,
+ * with a haptic tick at every step up.
+ * Those deletions are driven by a timer here.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::rc::Rc;
+use std::time::{ Duration, Instant };
+
+use crate::keyboard::KeyStateId;
+use crate::layout;
+use crate::layout::c::EekGtkKeyboard;
+use crate::sound::Sound;
+use crate::submission::{ Submission, Timestamp };
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    Character,
+    Word,
+    Line,
+}
+
+/// Holding for this long starts deleting words
+const WORD_AFTER: Duration = Duration::from_millis(1500);
+/// Holding for this long starts deleting lines
+const LINE_AFTER: Duration = Duration::from_millis(4000);
+/// How often the timer checks on the key
+const TICK: Duration = Duration::from_millis(50);
+
+thread_local! {
+    /// Counts presses which started a repeat.
+    /// A timer belongs to the press with its number,
+    /// so a quick release and press doesn't leave an old timer going.
+    static PRESS: Cell<u64> = Cell::new(0);
+}
+
+fn next_press() -> u64 {
+    PRESS.with(|p| {
+        let press = p.get().wrapping_add(1);
+        p.set(press);
+        press
+    })
+}
+
+fn is_current(press: u64) -> bool {
+    PRESS.with(|p| p.get() == press)
+}
+
+/// Returns what gets deleted after holding the key for the duration.
+pub fn get_unit(held: Duration) -> Unit {
+    if held >= LINE_AFTER {
+        Unit::Line
+    } else if held >= WORD_AFTER {
+        Unit::Word
+    } else {
+        Unit::Character
+    }
+}
+
+/// Larger units go slower, to give a chance to let go in time.
+fn get_interval(unit: Unit) -> Duration {
+    match unit {
+        Unit::Character => Duration::from_millis(0),
+        Unit::Word => Duration::from_millis(300),
+        Unit::Line => Duration::from_millis(600),
+    }
+}
+
+/// Returns the byte offset where the deletion should start,
+/// given the cursor byte offset.
+/// Gives back the cursor when it's not on a character boundary.
+pub fn find_start(text: &str, cursor: usize, unit: Unit) -> usize {
+    if !text.is_char_boundary(cursor) {
+        return cursor;
+    }
+    let before = &text[..cursor];
+    match unit {
+        Unit::Character => before.char_indices()
+            .next_back()
+            .map(|(i, _)| i)
+            .unwrap_or(0),
+        // Whitespace in front of the cursor goes together with the word.
+        Unit::Word => {
+            let trimmed = before.trim_end();
+            trimmed.char_indices()
+                .rev()
+                .find(|(_, c)| c.is_whitespace())
+                .map(|(i, c)| i + c.len_utf8())
+                .unwrap_or(0)
+        },
+        // At the start of a line, only the line break goes.
+        Unit::Line => match before.rfind('\n') {
+            Some(i) if i + 1 == cursor => i,
+            Some(i) => i + 1,
+            None => 0,
+        },
+    }
+}
+
+/// Keeps escalating the deletion until the key gets released.
+pub fn start_repeat(
+    submission: Rc<RefCell<Submission>>,
+    key_id: KeyStateId,
+    ui_keyboard: EekGtkKeyboard,
+    time: Timestamp,
+) {
+    let start = Instant::now();
+    let press = next_press();
+    let mut unit = Unit::Character;
+    let mut last_step: Option<Instant> = None;
+
+    let tick = move || {
+        let mut submission = submission.borrow_mut();
+        if !is_current(press) || !submission.is_pressed(&key_id) {
+            return glib::Continue(false);
+        }
+        let now = Instant::now();
+        let held = now.duration_since(start);
+        let new_unit = get_unit(held);
+        if new_unit != unit {
+            unit = new_unit;
+            last_step = None;
+            layout::emit_feedback(ui_keyboard, Sound::Delete);
+        }
+        let is_due = match last_step {
+            Some(last) => now >= last + get_interval(unit),
+            None => true,
+        };
+        if unit != Unit::Character && is_due {
+            let elapsed_ms = held.as_millis() as u32;
+            submission.erase(
+                &key_id,
+                unit,
+                Timestamp(time.0.wrapping_add(elapsed_ms)),
+            );
+            last_step = Some(now);
+        }
+        glib::Continue(true)
+    };
+
+    #[cfg(feature = "glib_v0_14")]
+    glib::timeout_add_local(TICK, tick);
+    #[cfg(not(feature = "glib_v0_14"))]
+    glib::timeout_add_local(TICK.as_millis() as u32, tick);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stages() {
+        assert_eq!(get_unit(Duration::from_millis(100)), Unit::Character);
+        assert_eq!(get_unit(WORD_AFTER), Unit::Word);
+        assert_eq!(get_unit(Duration::from_secs(60)), Unit::Line);
+    }
+
+    #[test]
+    fn new_press_takes_over() {
+        let first = next_press();
+        assert!(is_current(first));
+        let second = next_press();
+        assert!(!is_current(first));
+        assert!(is_current(second));
+    }
+
+    #[test]
+    fn word_start() {
+        assert_eq!(find_start("hello world", 11, Unit::Word), 6);
+        assert_eq!(find_start("hello world  ", 13, Unit::Word), 6);
+        assert_eq!(find_start("hello", 5, Unit::Word), 0);
+        assert_eq!(find_start("zażółć gęślą", 19, Unit::Word), 11);
+    }
+
+    #[test]
+    fn line_start() {
+        assert_eq!(find_start("one\ntwo three", 13, Unit::Line), 4);
+        assert_eq!(find_start("one\n", 4, Unit::Line), 3);
+        assert_eq!(find_start("one two", 7, Unit::Line), 0);
+    }
+
+    #[test]
+    fn not_on_boundary() {
+        assert_eq!(find_start("ż", 1, Unit::Word), 1);
+        assert_eq!(find_start("ż", 2, Unit::Character), 0);
+    }
+}
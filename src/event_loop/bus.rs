@@ -0,0 +1,113 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Hands the commands from the state loop out to everyone interested.
+ *
+ * The UI main loop used to be the only receiver,
+ * so anything reacting to state changes had to hook into it.
+ * With the bus, a subsystem subscribes once, gets its own channel,
+ * and receives every batch of commands the loop produces.
+ *
+ * Commands only carry changes,
+ * so subscribing must happen before the loop starts,
+ * or the subscriber misses the initial state.
+ */
+
+use crate::logging;
+use crate::main::Commands;
+use glib;
+use std::sync::{ mpsc, Arc, Mutex };
+
+
+/// Where commands get delivered
+pub trait Subscriber: Send {
+    /// Returns false when the receiving end is gone.
+    fn deliver(&self, commands: Commands) -> bool;
+}
+
+impl Subscriber for mpsc::Sender<Commands> {
+    fn deliver(&self, commands: Commands) -> bool {
+        self.send(commands).is_ok()
+    }
+}
+
+impl Subscriber for glib::Sender<Commands> {
+    fn deliver(&self, commands: Commands) -> bool {
+        self.send(commands).is_ok()
+    }
+}
+
+/// Shared between the state loop and the subscribers
+#[derive(Clone)]
+pub struct Bus {
+    subscribers: Arc<Mutex<Vec<Box<dyn Subscriber>>>>,
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus {
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn add<S: Subscriber + 'static>(&self, subscriber: S) {
+        self.subscribers.lock().unwrap().push(Box::new(subscriber));
+    }
+
+    /// For subsystems running their own thread.
+    pub fn subscribe(&self) -> mpsc::Receiver<Commands> {
+        let (sender, receiver) = mpsc::channel();
+        self.add(sender);
+        receiver
+    }
+
+    /// For subsystems living in the glib main loop.
+    pub fn subscribe_main(&self) -> glib::Receiver<Commands> {
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        self.add(sender);
+        receiver
+    }
+
+    /// Delivers to every subscriber,
+    /// and forgets the ones which are gone.
+    pub fn publish(&self, commands: Commands) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let count = subscribers.len();
+        subscribers.retain(|subscriber| subscriber.deliver(commands.clone()));
+        if subscribers.len() != count {
+            log_print!(
+                logging::Level::Debug,
+                "{} command subscribers gone", count - subscribers.len(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deliver_to_all() {
+        let bus = Bus::new();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        bus.publish(Commands {
+            spell_check: Some(true),
+            ..Commands::default()
+        });
+        assert_eq!(first.try_recv().unwrap().spell_check, Some(true));
+        assert_eq!(second.try_recv().unwrap().spell_check, Some(true));
+    }
+
+    #[test]
+    fn forget_gone() {
+        let bus = Bus::new();
+        drop(bus.subscribe());
+        let remaining = bus.subscribe();
+        bus.publish(Commands::default());
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+        assert!(remaining.try_recv().is_ok());
+    }
+}
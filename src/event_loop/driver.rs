@@ -16,11 +16,19 @@
  * and no longer unit-testable.
  */
 
+use crate::animation;
+use crate::clock::{ Clock, System };
+use crate::crash;
 use crate::event_loop;
+use crate::event_loop::bus::Bus;
+use crate::inspect::Inspector;
 use crate::logging;
-use crate::main::Commands;
+use crate::main::commands::SetLayout;
+use crate::persist;
+use crate::state;
 use crate::state::{ Application, Event };
-use glib;
+use crate::trace;
+use std::panic;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Instant;
@@ -31,15 +39,13 @@ use crate::logging::Warn;
 
 /// Type of the sender that waits for external events
 type Sender = mpsc::Sender<Event>;
-/// Type of the sender that waits for internal state changes
-type UISender = glib::Sender<Commands>;
 
 /// This loop driver spawns a new thread which updates the state in a loop,
 /// in response to incoming events.
-/// It sends outcomes to the glib main loop using a channel.
-/// The outcomes are applied by the UI end of the channel in the `main` module.
+/// It publishes outcomes on the command bus.
+/// The glib main loop subscribes to apply them in the `main` module.
 // This could still be reasonably tested,
-/// by creating a glib::Sender and checking what messages it receives.
+/// by subscribing to the bus and checking what messages it receives.
 // This can/should be abstracted over Event and Commands,
 // so that the C call-ins can be thrown away from here and defined near events.
 #[derive(Clone)]
@@ -48,15 +54,53 @@ pub struct Threaded {
 }
 
 impl Threaded {
-    pub fn new(ui: UISender, initial_state: Application) -> Self {
+    pub fn new(
+        bus: Bus,
+        initial_state: Application,
+        mut recorder: Option<trace::Recorder>,
+        journal: crash::Journal,
+        inspector: Inspector,
+    ) -> Self {
         let (sender, receiver) = mpsc::channel();
         let saved_sender = sender.clone();
         thread::spawn(move || {
-            let mut state = event_loop::State::new(initial_state, Instant::now());
+            let clock = System;
+            inspector.set_application(&initial_state, clock.now());
+            let mut saved = persist::Snapshot::new(&initial_state);
+            let mut state = event_loop::State::new(initial_state, clock.now());
             loop {
                 match receiver.recv() {
                     Ok(event) => {
-                        state = Self::handle_loop_event(&sender, state, event, &ui);
+                        // Whatever else arrived in the meantime
+                        // gets handled in one go.
+                        let events: Vec<Event> = Some(event).into_iter()
+                            .chain(receiver.try_iter())
+                            .collect();
+                        let now = clock.now();
+                        if let Some(recorder) = &mut recorder {
+                            recorder.record(&events, now);
+                        }
+                        journal.record(&events, now);
+                        let previous = state.clone();
+                        let handled = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                            Self::handle_loop_events(&sender, state, events, now, &bus, &journal)
+                        }));
+                        state = match handled {
+                            Ok(state) => state,
+                            // The events which caused it are lost,
+                            // but the panel can come back as it was.
+                            Err(_) => {
+                                Self::restart_panel(&bus, &previous, now);
+                                previous
+                            },
+                        };
+                        inspector.set_application(&state.state, now);
+                        // Only touch the disk when something worth saving changed.
+                        let snapshot = persist::Snapshot::new(&state.state);
+                        if snapshot != saved {
+                            persist::save(&snapshot);
+                            saved = snapshot;
+                        }
                     },
                     Err(e) => {
                         logging::print(logging::Level::Bug, &format!("Senders hung up, aborting: {}", e));
@@ -75,15 +119,23 @@ impl Threaded {
         self.thread.send(event)
     }
     
-    fn handle_loop_event(loop_sender: &Sender, state: event_loop::State, event: Event, ui: &UISender)
-        -> event_loop::State
-    {
-        let now = Instant::now();
+    fn handle_loop_events(
+        loop_sender: &Sender,
+        state: event_loop::State,
+        events: Vec<Event>,
+        now: Instant,
+        bus: &Bus,
+        journal: &crash::Journal,
+    ) -> event_loop::State {
+        let (new_state, commands) = event_loop::handle_events(state.clone(), events, now);
 
-        let (new_state, commands) = event_loop::handle_event(state.clone(), event, now);
-
-        ui.send(commands)
-            .or_warn(&mut logging::Print, logging::Problem::Bug, "Can't send to UI");
+        if let Some(SetLayout { description }) = &commands.layout_selection {
+            journal.set_layout(
+                description.overlay_name.clone()
+                    .unwrap_or_else(|| description.name.clone())
+            );
+        }
+        bus.publish(commands);
 
         if new_state.scheduled_wakeup != state.scheduled_wakeup {
             if let Some(when) = new_state.scheduled_wakeup {
@@ -94,11 +146,24 @@ impl Threaded {
         new_state
     }
 
+    /// Hides the panel and shows it again,
+    /// so that everything gets created anew.
+    fn restart_panel(bus: &Bus, loop_state: &event_loop::State, now: Instant) {
+        log_print!(logging::Level::Warning, "Restarting the panel after a crash");
+        let outcome = loop_state.state.get_outcome(now);
+        let hidden = state::Outcome {
+            panel: animation::Outcome::Hidden,
+            ..outcome.clone()
+        };
+        bus.publish(outcome.get_commands_to_reach(&hidden));
+        bus.publish(hidden.get_commands_to_reach(&outcome));
+    }
+
     fn schedule_timeout_wake(loop_sender: &Sender, when: Instant) {
         let sender = loop_sender.clone();
         thread::spawn(move || {
-            let now = Instant::now();
-            thread::sleep(when - now);
+            let now = System.now();
+            thread::sleep(when.saturating_duration_since(now));
             sender.send(Event::TimeoutReached(when))
                 .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't wake visibility manager");
         });
@@ -109,6 +174,7 @@ impl Threaded {
 mod c {
     use super::*;
 
+    use crate::repeat::RepeatInfo;
     use crate::state::Presence;
     use crate::state::LayoutChoice;
     use crate::state::visibility;
@@ -134,6 +200,30 @@ mod c {
             .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
     }
 
+    /// The user wants to wipe the screen without typing.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_state_send_start_cleaning(sender: Wrapped<Threaded>) {
+        let sender = sender.clone_ref();
+        let sender = sender.borrow();
+        sender.send(Event::StartCleaning)
+            .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
+    }
+
+    /// An application asked to show or hide the panel.
+    /// Unlike the `force` calls, this is not the user's choice.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_state_send_app_visibility(sender: Wrapped<Threaded>, visible: u32) {
+        let sender = sender.clone_ref();
+        let sender = sender.borrow();
+        let request =
+            if visible == 0 { visibility::AppRequest::Hide }
+            else { visibility::AppRequest::Show };
+        sender.send(Event::AppRequestedVisibility(request))
+            .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
+    }
+
     #[no_mangle]
     pub extern "C"
     fn squeek_state_send_keyboard_present(sender: Wrapped<Threaded>, present: u32) {
@@ -146,6 +236,16 @@ mod c {
             .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
     }
     
+    /// From `wl_keyboard.repeat_info`
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_state_send_repeat_info(sender: Wrapped<Threaded>, rate: i32, delay: i32) {
+        let sender = sender.clone_ref();
+        let sender = sender.borrow();
+        sender.send(Event::RepeatInfo(RepeatInfo::from_wayland(rate, delay)))
+            .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
+    }
+    
     #[no_mangle]
     pub extern "C"
     fn squeek_state_send_layout_set(
@@ -36,6 +36,7 @@
  * The loop iteration is kept as a pure function to stay testable.
  */
 
+pub mod bus;
 pub mod driver;
 
 // This module is tightly coupled to the shape of data passed around in this project.
@@ -73,10 +74,22 @@ impl State {
 /// - determines next scheduled animation wakeup,
 /// and because this is a pure function, it's easily testable.
 /// It returns the new state, and the message to send onwards.
+#[cfg(test)]
 fn handle_event(
-    mut loop_state: State,
+    loop_state: State,
     event: Event,
     now: Instant,
+) -> (State, Commands) {
+    handle_events(loop_state, vec![event], now)
+}
+
+/// Like `handle_event`, but for all events which arrived in one wakeup.
+/// Only the final outcome turns into commands,
+/// so a burst like hide-show doesn't make the panel blink.
+fn handle_events(
+    mut loop_state: State,
+    events: Vec<Event>,
+    now: Instant,
 ) -> (State, Commands) {
     // Calculate changes to send to the consumer,
     // based on publicly visible state.
@@ -84,37 +97,38 @@ fn handle_event(
     // so the resulting changes may be no-ops.
     let old_state = loop_state.state.clone();
     let last_update = loop_state.last_update;
-    loop_state.state = loop_state.state.apply_event(event.clone(), now);
+    // Timeout events are special: they affect the scheduled timeout.
+    let scheduled_wakeup = events.iter()
+        .fold(loop_state.scheduled_wakeup, |scheduled, event| match event {
+            Event::TimeoutReached(when) => {
+                if *when > now {
+                    // Special handling for scheduled events coming in early.
+                    // Wait at least 10 ms to avoid Zeno's paradox.
+                    // This is probably not needed though,
+                    // if the `now` contains the desired time of the event.
+                    // But then what about time "reversing"?
+                    Some(cmp::max(
+                        *when,
+                        now + Duration::from_millis(10),
+                    ))
+                } else {
+                    // There's only one timeout in flight, and it's this one.
+                    // It's about to complete, and then the tracker can be cleared.
+                    // I'm not sure if this is strictly necessary.
+                    None
+                }
+            },
+            _ => scheduled,
+        });
+    loop_state.state = loop_state.state.apply_events(events, now);
     loop_state.last_update = now;
+    loop_state.scheduled_wakeup = scheduled_wakeup;
 
     let new_outcome = loop_state.state.get_outcome(now);
 
     let commands = old_state.get_outcome(last_update)
         .get_commands_to_reach(&new_outcome);
     
-    // Timeout events are special: they affect the scheduled timeout.
-    loop_state.scheduled_wakeup = match event {
-        Event::TimeoutReached(when) => {
-            if when > now {
-                // Special handling for scheduled events coming in early.
-                // Wait at least 10 ms to avoid Zeno's paradox.
-                // This is probably not needed though,
-                // if the `now` contains the desired time of the event.
-                // But then what about time "reversing"?
-                Some(cmp::max(
-                    when,
-                    now + Duration::from_millis(10),
-                ))
-            } else {
-                // There's only one timeout in flight, and it's this one.
-                // It's about to complete, and then the tracker can be cleared.
-                // I'm not sure if this is strictly necessary.
-                None
-            }
-        },
-        _ => loop_state.scheduled_wakeup.clone(),
-    };
-    
     // Reschedule timeout if the new state calls for it.
     let scheduled = &loop_state.scheduled_wakeup;
     let desired = loop_state.state.get_next_wake(now);
@@ -150,6 +164,7 @@ fn handle_event(
 mod test {
     use super::*;
     use crate::animation;
+    use crate::clock::{ Clock, Manual };
     use crate::imservice::{ ContentHint, ContentPurpose };
     use crate::panel;
     use crate::state::{ Application, InputMethod, InputMethodDetails, Presence, visibility };
@@ -164,25 +179,64 @@ mod test {
 
     #[test]
     fn schedule_hide() {
-        let start = Instant::now(); // doesn't matter when. It would be better to have a reproducible value though
-        let mut now = start;
+        let clock = Manual::new();
+        let now = clock.now();
 
         let state = Application {
             im: InputMethod::Active(imdetails_new()),
-            physical_keyboard: Presence::Missing,
-            visibility_override: visibility::State::NotForced,
-            ..application_with_fake_output(start)
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(now)
         };
         
         let l = State::new(state, now);
         let (l, commands) = handle_event(l, InputMethod::InactiveSince(now).into(), now);
-        assert_matches!(commands.panel_visibility, Some(panel::Command::Show{..}));
+        // Still shown until the timeout
+        assert_eq!(commands.panel_visibility, None);
         assert_eq!(l.scheduled_wakeup, Some(now + animation::HIDING_TIMEOUT));
         
-        now += animation::HIDING_TIMEOUT;
+        clock.advance(animation::HIDING_TIMEOUT);
+        let now = clock.now();
         
         let (l, commands) = handle_event(l, Event::TimeoutReached(now), now);
         assert_eq!(commands.panel_visibility, Some(panel::Command::Hide));
         assert_eq!(l.scheduled_wakeup, None);
     }
+
+    /// Hiding and showing again in one wakeup leaves the panel alone
+    #[test]
+    fn coalesce_burst() {
+        let now = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            ..application_with_fake_output(now)
+        };
+
+        let l = State::new(state, now);
+        let (l, commands) = handle_events(
+            l,
+            vec![
+                Event::Visibility(visibility::Event::ForceHidden),
+                Event::Visibility(visibility::Event::ForceVisible),
+            ],
+            now,
+        );
+        assert_eq!(commands.panel_visibility, None);
+
+        // disable, enable, disable
+        let (l, commands) = handle_events(
+            l,
+            vec![
+                InputMethod::InactiveSince(now).into(),
+                InputMethod::Active(imdetails_new()).into(),
+                InputMethod::InactiveSince(now).into(),
+            ],
+            now,
+        );
+        assert_eq!(commands.panel_visibility, None);
+        assert_eq!(l.scheduled_wakeup, Some(now + animation::HIDING_TIMEOUT));
+    }
 }
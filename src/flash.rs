@@ -0,0 +1,96 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Feedback which can be seen rather than heard or felt.
+ *
+ * When enabled, pressed keys light up whole instead of rippling,
+ * and a press which can't work flashes a border around the keyboard.
+ * It's independent of the sound and haptic feedback,
+ * so that either or both can be on.
+ */
+
+use std::cell::Cell;
+use std::time::{ Duration, Instant };
+use cairo;
+
+use crate::clock::{ Clock, System };
+use crate::drawing;
+use crate::layout::Layout;
+use crate::layout::c::Bounds;
+
+
+/// The border stays this long
+const ERROR_FOR: Duration = Duration::from_millis(400);
+
+thread_local! {
+    /// Must match the default setting
+    static ENABLED: Cell<bool> = Cell::new(false);
+    static ERROR: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// Follows the user's settings.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+    if !enabled {
+        ERROR.with(|e| e.set(None));
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(|e| e.get())
+}
+
+/// Flashes the border, when a press does nothing.
+pub fn error(now: Instant) {
+    if !is_enabled() {
+        return;
+    }
+    ERROR.with(|e| e.set(Some(now)));
+    drawing::queue_redraw_all();
+    System.schedule(ERROR_FOR, Box::new(|| {
+        drawing::queue_redraw_all();
+        false
+    }));
+}
+
+fn is_error_shown(now: Instant) -> bool {
+    ERROR.with(|e| e.get())
+        .map(|since| now.saturating_duration_since(since) < ERROR_FOR)
+        .unwrap_or(false)
+}
+
+/// Takes the place of the ripple on the pressed button.
+/// `progress` goes from 0 to 1 over the ripple's time.
+pub fn render_press(cr: &cairo::Context, bounds: &Bounds, progress: f64) {
+    let Bounds { x, y, width, height } = *bounds;
+    cr.save();
+    cr.rectangle(x, y, width, height);
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.8 * (1.0 - progress));
+    cr.fill_preserve();
+    cr.set_line_width(3.0);
+    cr.set_source_rgba(0.0, 0.0, 0.0, 1.0 - progress);
+    cr.stroke();
+    cr.restore();
+}
+
+/// Draws the border around the view, if there was an error just now.
+pub fn render_error(cr: &cairo::Context, layout: &Layout) {
+    if !is_enabled() || !is_error_shown(System.now()) {
+        return;
+    }
+    let (offset, view) = layout.get_current_view_position();
+    let size = view.get_size();
+    let width = 6.0;
+    cr.save();
+    cr.set_line_width(width);
+    cr.set_source_rgba(0.9, 0.1, 0.1, 0.9);
+    cr.rectangle(
+        offset.x + width / 2.0,
+        offset.y + width / 2.0,
+        size.width - width,
+        size.height - width,
+    );
+    cr.stroke();
+    cr.restore();
+}
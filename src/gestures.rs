@@ -0,0 +1,242 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Swiping across the keyboard to erase, type a space, or hide it.
+ *
+ * A touch which quickly moves away from where it started,
+ * in a direction with a command in the settings, becomes a swipe.
+ * The keys it crosses don't get pressed,
+ * and the command runs when the finger lifts.
+ * Directions do nothing by default,
+ * so that sliding to a neighbouring key works as usual.
+ *
+ * The key where the swipe started got pressed before anything was known.
+ * If it typed through the input method, the character gets taken back.
+ * Otherwise, it stays typed.
+ */
+
+use std::cell::Cell;
+use std::time::{ Duration, Instant };
+
+use crate::action::Action;
+use crate::layout::c::Point;
+use crate::layout::Size;
+
+
+/// Slower touches are sliding between keys
+const SWIPE_WITHIN: Duration = Duration::from_millis(250);
+/// How far a swipe must move in that time, as a share of the view's size
+const SWIPE_START: f64 = 0.15;
+/// How far a swipe must move in total
+const SWIPE_DISTANCE: f64 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// What a swipe does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Like the erase key
+    Erase,
+    /// Deletes the word before the cursor
+    EraseWord,
+    /// Like the space bar
+    Space,
+    /// Like the Return key
+    Return,
+    Hide,
+}
+
+const COMMAND_NAMES: &[(&str, Command)] = &[
+    ("backspace", Command::Erase),
+    ("erase-word", Command::EraseWord),
+    ("space", Command::Space),
+    ("return", Command::Return),
+    ("hide", Command::Hide),
+];
+
+impl Command {
+    /// Takes the names used in the settings. "none" gives nothing.
+    pub fn from_name(name: &str) -> Option<Command> {
+        COMMAND_NAMES.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, command)| *command)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        COMMAND_NAMES.iter()
+            .find(|(_, command)| command == self)
+            .map(|(name, _)| *name)
+            .expect("Command missing from the names")
+    }
+
+    /// Whether the action belongs to the key the command stands for.
+    /// The erase key also provides the keycodes to erase words with.
+    pub fn is_done_by(&self, action: &Action) -> bool {
+        match (self, action) {
+            (Command::Erase, Action::Erase)
+                | (Command::EraseWord, Action::Erase) => true,
+            (Command::Space, Action::Submit { text: Some(text), keys: _ })
+                => text.as_bytes() == b" ",
+            (Command::Return, action) => action.is_return(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Mapping {
+    pub left: Option<Command>,
+    pub right: Option<Command>,
+    pub up: Option<Command>,
+    pub down: Option<Command>,
+}
+
+impl Mapping {
+    pub fn get(&self, direction: Direction) -> Option<Command> {
+        match direction {
+            Direction::Left => self.left,
+            Direction::Right => self.right,
+            Direction::Up => self.up,
+            Direction::Down => self.down,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Mapping::default()
+    }
+}
+
+thread_local! {
+    /// Follows the user's settings
+    static MAPPING: Cell<Mapping> = Cell::new(Mapping::default());
+}
+
+pub fn set_mapping(mapping: Mapping) {
+    MAPPING.with(|m| m.set(mapping));
+}
+
+/// Returns the direction, if the move is longer than the share of the size.
+fn get_direction(start: &Point, end: &Point, size: &Size, share: f64)
+    -> Option<Direction>
+{
+    let dx = (end.x - start.x) / size.width;
+    let dy = (end.y - start.y) / size.height;
+    if dx.abs() >= dy.abs() {
+        match dx {
+            dx if dx >= share => Some(Direction::Right),
+            dx if dx <= -share => Some(Direction::Left),
+            _ => None,
+        }
+    } else {
+        match dy {
+            dy if dy >= share => Some(Direction::Down),
+            dy if dy <= -share => Some(Direction::Up),
+            _ => None,
+        }
+    }
+}
+
+/// A touch which may turn out to be a swipe
+#[derive(Clone, Debug)]
+pub struct Touch {
+    start: Point,
+    started: Instant,
+    mapping: Mapping,
+    swiping: bool,
+}
+
+impl Touch {
+    /// Nothing to track without any commands.
+    pub fn start(start: Point, now: Instant) -> Option<Touch> {
+        let mapping = MAPPING.with(|m| m.get());
+        match mapping.is_empty() {
+            true => None,
+            false => Some(Touch {
+                start,
+                started: now,
+                mapping,
+                swiping: false,
+            }),
+        }
+    }
+
+    /// Returns whether the touch is a swipe,
+    /// which stays so once it's found to be one.
+    pub fn update(&mut self, point: &Point, now: Instant, size: &Size) -> bool {
+        if !self.swiping && now - self.started <= SWIPE_WITHIN {
+            self.swiping = get_direction(&self.start, point, size, SWIPE_START)
+                .and_then(|direction| self.mapping.get(direction))
+                .is_some();
+        }
+        self.swiping
+    }
+
+    /// Returns what the swipe does, given where it ended.
+    pub fn finish(&self, end: &Point, size: &Size) -> Option<Command> {
+        match self.swiping {
+            true => get_direction(&self.start, end, size, SWIPE_DISTANCE)
+                .and_then(|direction| self.mapping.get(direction)),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SIZE: Size = Size { width: 100.0, height: 40.0 };
+
+    fn point(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn directions() {
+        let start = point(50.0, 20.0);
+        assert_eq!(
+            get_direction(&start, &point(10.0, 25.0), &SIZE, SWIPE_DISTANCE),
+            Some(Direction::Left),
+        );
+        assert_eq!(
+            get_direction(&start, &point(55.0, 35.0), &SIZE, SWIPE_DISTANCE),
+            Some(Direction::Down),
+        );
+        assert_eq!(
+            get_direction(&start, &point(60.0, 20.0), &SIZE, SWIPE_DISTANCE),
+            None,
+        );
+    }
+
+    #[test]
+    fn swipe() {
+        set_mapping(Mapping { left: Some(Command::EraseWord), ..Mapping::default() });
+        let now = Instant::now();
+        let mut touch = Touch::start(point(50.0, 20.0), now).unwrap();
+        // Moving right does nothing here
+        assert!(!touch.update(&point(70.0, 20.0), now, &SIZE));
+        assert!(touch.update(&point(30.0, 20.0), now, &SIZE));
+        assert!(touch.update(&point(45.0, 20.0), now + SWIPE_WITHIN * 2, &SIZE));
+        assert_eq!(touch.finish(&point(10.0, 20.0), &SIZE), Some(Command::EraseWord));
+        assert_eq!(touch.finish(&point(45.0, 20.0), &SIZE), None);
+
+        set_mapping(Mapping::default());
+        assert!(Touch::start(point(50.0, 20.0), now).is_none());
+    }
+
+    #[test]
+    fn command_names() {
+        for (name, command) in COMMAND_NAMES {
+            assert_eq!(Command::from_name(name), Some(*command));
+            assert_eq!(command.get_name(), *name);
+        }
+        assert_eq!(Command::from_name("none"), None);
+    }
+}
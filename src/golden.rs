@@ -0,0 +1,234 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Golden files for the built-in layouts.
+ *
+ * Every built-in layout gets built with all its views,
+ * and its geometry and keysyms get written down in a summary.
+ * The summary is compared with the one stored in `tests/golden`,
+ * so that a change to the parser which moves buttons around,
+ * or drops keysyms, shows up as a difference.
+ *
+ * Which layout gets picked for each language, arrangement and purpose
+ * is stored in the same way, in `tests/golden/choices.json`.
+ *
+ * A missing summary counts as a difference.
+ * After an intended change, or to add a layout,
+ * set `SQUEEKBOARD_BLESS` to write the summaries,
+ * and review the difference with git.
+ *
+ * The summaries are JSON with one value on each line,
+ * so that git shows which numbers changed.
+ */
+
+use crate::data::export;
+use crate::data::loading;
+use crate::data::parsing::Layout;
+use crate::imservice::ContentPurpose;
+use crate::layout::{ ArrangementKind, LayoutData };
+use crate::logging;
+use crate::resources;
+use serde::Serialize;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fs;
+use std::io;
+use std::path::Path;
+use xkbcommon::xkb;
+
+
+const KINDS: [ArrangementKind; 3] = [
+    ArrangementKind::Base,
+    ArrangementKind::Wide,
+    ArrangementKind::Compact,
+];
+
+const PURPOSES: [ContentPurpose; 14] = [
+    ContentPurpose::Normal,
+    ContentPurpose::Alpha,
+    ContentPurpose::Digits,
+    ContentPurpose::Number,
+    ContentPurpose::Phone,
+    ContentPurpose::Url,
+    ContentPurpose::Email,
+    ContentPurpose::Name,
+    ContentPurpose::Password,
+    ContentPurpose::Pin,
+    ContentPurpose::Date,
+    ContentPurpose::Time,
+    ContentPurpose::Datetime,
+    ContentPurpose::Terminal,
+];
+
+/// Not a layout, to record what the fallback is
+const UNKNOWN_LANGUAGE: &str = "unknown";
+
+#[derive(Serialize)]
+struct ViewSummary {
+    offset: (f64, f64),
+    width: f64,
+    height: f64,
+    buttons_in_rows: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    views: BTreeMap<String, ViewSummary>,
+    /// Top, bottom, left, right
+    margins: (f64, f64, f64, f64),
+    /// Names of all keysyms in each keymap
+    keymaps: Vec<BTreeSet<String>>,
+}
+
+fn get_keysym_names(keymap: &str) -> BTreeSet<String> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_string(
+        &context,
+        keymap.into(),
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    ).expect("Failed to create keymap");
+    let state = xkb::State::new(&keymap);
+    (keymap.min_keycode()..=keymap.max_keycode())
+        .map(|code| state.key_get_one_sym(code))
+        .filter(|sym| *sym != xkb::KEY_NoSymbol)
+        .map(xkb::keysym_get_name)
+        .collect()
+}
+
+fn summarize(layout: &LayoutData) -> Summary {
+    let views = layout.views.iter()
+        .map(|(name, (offset, view))| {
+            let view = view.get().expect("View not built");
+            let size = view.get_size();
+            (name.clone(), ViewSummary {
+                offset: (offset.x, offset.y),
+                width: size.width,
+                height: size.height,
+                buttons_in_rows: view.get_rows().iter()
+                    .map(|(_offset, row)| row.get_buttons().len())
+                    .collect(),
+            })
+        })
+        .collect();
+    let margins = &layout.margins;
+    Summary {
+        views,
+        margins: (margins.top, margins.bottom, margins.left, margins.right),
+        keymaps: layout.keymaps.iter()
+            .map(|keymap| get_keysym_names(
+                keymap.to_str().expect("Keymap not UTF-8")
+            ))
+            .collect(),
+    }
+}
+
+fn to_json<T: Serialize>(summary: &T) -> String {
+    let value = serde_yaml::to_value(summary).expect("Summary not serializable");
+    export::value_to_json_pretty(&value)
+}
+
+/// The languages as they are requested, without shapes or overlays
+fn get_languages() -> Vec<&'static str> {
+    resources::get_keyboard_names().into_iter()
+        .filter(|name| !name.contains('/'))
+        .filter(|name| !name.ends_with("_wide") && !name.ends_with("_compact"))
+        .collect()
+}
+
+/// Language, then arrangement, then purpose, to the layout chosen
+type Choices = BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>;
+
+fn get_choices() -> Choices {
+    let overlays = resources::get_overlays();
+    let requests = get_languages().into_iter()
+        .chain(Some(UNKNOWN_LANGUAGE))
+        .map(|name| (name.to_string(), name, None))
+        .chain(overlays.into_iter().map(|overlay| (
+            format!("{}:{}", overlay, loading::FALLBACK_LAYOUT_NAME),
+            loading::FALLBACK_LAYOUT_NAME,
+            Some(overlay),
+        )));
+
+    requests.map(|(key, name, overlay)| {
+        let kinds = KINDS.iter().map(|kind| {
+            let purposes = PURPOSES.iter().map(|purpose| {
+                let choice = loading::find_builtin(name, *kind, *purpose, overlay)
+                    .map(|(kind, name)| format!("{} ({:?})", name, kind))
+                    .unwrap_or_else(|| "none".into());
+                (format!("{:?}", purpose), choice)
+            }).collect();
+            (format!("{:?}", kind), purposes)
+        }).collect();
+        (key, kinds)
+    }).collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Same,
+    Written,
+    Different,
+    Missing,
+}
+
+fn compare(path: &Path, summary: &str, bless: bool) -> Result<Outcome, io::Error> {
+    let golden = match fs::read_to_string(path) {
+        Ok(golden) => Some(golden),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    match golden {
+        Some(ref golden) if golden == summary => return Ok(Outcome::Same),
+        Some(_) if !bless => return Ok(Outcome::Different),
+        None if !bless => return Ok(Outcome::Missing),
+        _ => {},
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, summary)?;
+    Ok(Outcome::Written)
+}
+
+fn report(name: &str, outcome: Outcome) -> bool {
+    match outcome {
+        Outcome::Same => true,
+        Outcome::Written => {
+            log_print!(logging::Level::Info, "Wrote golden file for {}", name);
+            true
+        },
+        Outcome::Different => {
+            log_print!(logging::Level::Error, "{} differs from its golden file", name);
+            false
+        },
+        Outcome::Missing => {
+            log_print!(logging::Level::Error, "{} has no golden file", name);
+            false
+        },
+    }
+}
+
+/// Compares every built-in layout and the layout choices
+/// with the golden files in `dir`.
+/// Returns false if anything differs or is missing.
+pub fn check_builtin_layouts(dir: &Path, bless: bool) -> bool {
+    let mut same = true;
+    for name in resources::get_keyboard_names() {
+        let layout = Layout::from_resource(name)
+            .expect("Invalid layout data")
+            .build(logging::Print).0
+            .expect("Layout broken");
+        let summary = to_json(&summarize(&layout));
+        let path = dir.join(format!("{}.json", name));
+        let outcome = compare(&path, &summary, bless)
+            .expect("Failed to access golden file");
+        same &= report(name, outcome);
+    }
+
+    let choices = to_json(&get_choices());
+    let outcome = compare(&dir.join("choices.json"), &choices, bless)
+        .expect("Failed to access golden file");
+    same &= report("Layout choice", outcome);
+    same
+}
@@ -0,0 +1,326 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Entering text by drawing it.
+ *
+ * A button with the `handwriting` action works as a canvas:
+ * touches starting there draw strokes instead of pressing buttons.
+ * After a pause in drawing, the strokes go to a recognizer,
+ * and the best guess gets committed.
+ * The other guesses are shown next to the text cursor,
+ * and the `next_candidate` action replaces the committed text
+ * with the next one of them.
+ *
+ * Recognizers hide behind the `Recognizer` trait.
+ * The default one asks a `sm.puri.Handwriting1` service on the session bus,
+ * at the `/sm/puri/Handwriting1` path,
+ * calling the `Recognize(aa(dd) strokes, d width, d height) -> as` method.
+ * Coordinates are relative to the top left corner of the canvas.
+ */
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+use cairo;
+use glib::{ Continue, MainContext, PRIORITY_DEFAULT };
+use zbus::Connection;
+
+use crate::drawing;
+use crate::layout::c::{ Bounds, EekGtkKeyboard, Point };
+use crate::logging;
+use crate::submission::Submission;
+
+// Traits
+use crate::logging::Warn;
+
+
+/// How long to wait for another stroke before recognizing
+const PAUSE: Duration = Duration::from_millis(700);
+
+/// Points relative to the canvas
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke(pub Vec<(f64, f64)>);
+
+type Error = Box<dyn std::error::Error>;
+
+/// Turns drawings into text.
+pub trait Recognizer {
+    /// Returns guesses, best first.
+    fn recognize(&self, strokes: &[Stroke], width: f64, height: f64)
+        -> Result<Vec<String>, Error>;
+}
+
+/// Uses a service on D-Bus
+pub struct DBusRecognizer;
+
+impl Recognizer for DBusRecognizer {
+    fn recognize(&self, strokes: &[Stroke], width: f64, height: f64)
+        -> Result<Vec<String>, Error>
+    {
+        let connection = Connection::new_session()?;
+        let strokes: Vec<Vec<(f64, f64)>> = strokes.iter()
+            .map(|stroke| stroke.0.clone())
+            .collect();
+        let reply = connection.call_method(
+            Some("sm.puri.Handwriting1"),
+            "/sm/puri/Handwriting1",
+            Some("sm.puri.Handwriting1"),
+            "Recognize",
+            &(strokes, width, height),
+        )?;
+        Ok(reply.body()?)
+    }
+}
+
+/// Drawing in progress
+struct Canvas {
+    bounds: Bounds,
+    strokes: Vec<Stroke>,
+    /// The finger is down
+    drawing: bool,
+    /// Changes with every stroke, so that a late timer knows it's stale
+    serial: u32,
+    submission: Rc<RefCell<Submission>>,
+    ui_keyboard: EekGtkKeyboard,
+}
+
+/// Guesses for the last recognized drawing
+#[derive(Debug, PartialEq)]
+struct Candidates {
+    list: Vec<String>,
+    /// The one which got committed
+    current: usize,
+}
+
+impl Candidates {
+    /// Returns the committed candidate and the next one.
+    fn advance(&mut self) -> Option<(String, String)> {
+        if self.list.len() < 2 {
+            return None;
+        }
+        let old = self.list[self.current].clone();
+        self.current = (self.current + 1) % self.list.len();
+        Some((old, self.list[self.current].clone()))
+    }
+
+    /// Text for the popup, with the committed one marked
+    fn describe(&self) -> String {
+        self.list.iter().enumerate()
+            .map(|(i, text)| match i == self.current {
+                true => format!("[{}]", text),
+                false => text.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+}
+
+thread_local! {
+    static CANVAS: RefCell<Option<Canvas>> = RefCell::new(None);
+    static CANDIDATES: RefCell<Option<Candidates>> = RefCell::new(None);
+}
+
+/// Whether touches are going to the canvas instead of buttons
+pub fn is_drawing() -> bool {
+    CANVAS.with(|canvas| match &*canvas.borrow() {
+        Some(canvas) => canvas.drawing,
+        None => false,
+    })
+}
+
+/// Starts a stroke on the canvas with the given bounds, in layout coordinates.
+pub fn begin_stroke(
+    bounds: Bounds,
+    point: Point,
+    submission: Rc<RefCell<Submission>>,
+    ui_keyboard: EekGtkKeyboard,
+) {
+    CANVAS.with(|canvas| {
+        let mut canvas = canvas.borrow_mut();
+        // Another canvas means another drawing
+        let is_same = match &*canvas {
+            Some(c) => c.bounds == bounds,
+            None => false,
+        };
+        if !is_same {
+            *canvas = Some(Canvas {
+                bounds,
+                strokes: Vec::new(),
+                drawing: false,
+                serial: 0,
+                submission,
+                ui_keyboard,
+            });
+        }
+        if let Some(canvas) = &mut *canvas {
+            let position = (point.x - canvas.bounds.x, point.y - canvas.bounds.y);
+            canvas.strokes.push(Stroke(vec![position]));
+            canvas.drawing = true;
+            canvas.serial = canvas.serial.wrapping_add(1);
+        }
+    });
+    drawing::queue_redraw(ui_keyboard);
+}
+
+pub fn add_point(point: Point) {
+    CANVAS.with(|canvas| {
+        if let Some(canvas) = &mut *canvas.borrow_mut() {
+            let position = (point.x - canvas.bounds.x, point.y - canvas.bounds.y);
+            if let Some(stroke) = canvas.strokes.last_mut() {
+                stroke.0.push(position);
+            }
+            drawing::queue_redraw(canvas.ui_keyboard);
+        }
+    });
+}
+
+/// Finishes the stroke, and recognizes the drawing
+/// unless another stroke starts soon.
+pub fn end_stroke() {
+    let serial = CANVAS.with(|canvas| {
+        canvas.borrow_mut().as_mut().map(|canvas| {
+            canvas.drawing = false;
+            canvas.serial
+        })
+    });
+    let serial = match serial {
+        Some(serial) => serial,
+        None => return,
+    };
+
+    let on_pause = move || {
+        let canvas = CANVAS.with(|canvas| {
+            let mut canvas = canvas.borrow_mut();
+            let is_current = match &*canvas {
+                Some(c) => c.serial == serial && !c.drawing,
+                None => false,
+            };
+            match is_current {
+                true => canvas.take(),
+                false => None,
+            }
+        });
+        if let Some(canvas) = canvas {
+            recognize(canvas, DBusRecognizer);
+        }
+        Continue(false)
+    };
+    #[cfg(feature = "glib_v0_14")]
+    glib::timeout_add_local(PAUSE, on_pause);
+    #[cfg(not(feature = "glib_v0_14"))]
+    glib::timeout_add_local(PAUSE.as_millis() as u32, on_pause);
+}
+
+/// Recognizes on a separate thread,
+/// and commits the best guess once it's ready.
+fn recognize<R: Recognizer + Send + 'static>(canvas: Canvas, recognizer: R) {
+    let Canvas { bounds, strokes, submission, ui_keyboard, .. } = canvas;
+    drawing::queue_redraw(ui_keyboard);
+
+    let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    receiver.attach(
+        Some(&MainContext::default()),
+        move |list: Vec<String>| {
+            let mut submission = submission.borrow_mut();
+            let committed = list.get(0)
+                .and_then(|text| CString::new(text.as_str()).ok())
+                .map(|text| submission.commit_text(&text).is_ok())
+                .unwrap_or(false);
+            let candidates = match committed {
+                true => Some(Candidates { list, current: 0 }),
+                false => None,
+            };
+            match &candidates {
+                Some(c) if c.list.len() > 1 => submission.show_popup(&c.describe()),
+                _ => submission.hide_popup(),
+            }
+            CANDIDATES.with(|c| *c.borrow_mut() = candidates);
+            Continue(false)
+        },
+    );
+
+    thread::spawn(move || {
+        let list = recognizer.recognize(&strokes, bounds.width, bounds.height)
+            .or_print(logging::Problem::Warning, "Handwriting not recognized")
+            .unwrap_or_else(Vec::new);
+        sender.send(list)
+            .or_print(logging::Problem::Bug, "Can't return recognized text");
+    });
+}
+
+/// Replaces the recognized text with the next guess.
+pub fn next_candidate(submission: &mut Submission) {
+    let replacement = CANDIDATES.with(|candidates| {
+        candidates.borrow_mut().as_mut()
+            .and_then(|c| c.advance().map(|r| (r, c.describe())))
+    });
+    if let Some(((old, new), description)) = replacement {
+        match CString::new(new) {
+            Ok(new) => {
+                if submission.replace_text(&old, &new).is_err() {
+                    log_print!(
+                        logging::Level::Warning,
+                        "Can't replace handwritten text: no text field",
+                    );
+                }
+                submission.show_popup(&description);
+            },
+            Err(e) => log_print!(logging::Level::Bug, "Bad candidate: {}", e),
+        }
+    }
+}
+
+/// Draws the strokes of the ongoing drawing.
+pub fn render(cr: &cairo::Context) {
+    CANVAS.with(|canvas| {
+        if let Some(canvas) = &*canvas.borrow() {
+            cr.save();
+            cr.set_line_width(3.0);
+            cr.set_line_cap(cairo::LineCap::Round);
+            cr.set_line_join(cairo::LineJoin::Round);
+            cr.set_source_rgba(0.2, 0.5, 1.0, 0.9);
+            for Stroke(points) in &canvas.strokes {
+                let mut points = points.iter();
+                if let Some((x, y)) = points.next() {
+                    cr.move_to(canvas.bounds.x + x, canvas.bounds.y + y);
+                    // A dot needs a line to show up
+                    cr.line_to(canvas.bounds.x + x, canvas.bounds.y + y);
+                }
+                for (x, y) in points {
+                    cr.line_to(canvas.bounds.x + x, canvas.bounds.y + y);
+                }
+            }
+            cr.stroke();
+            cr.restore();
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn candidates_cycle() {
+        let mut candidates = Candidates {
+            list: vec!["a".into(), "d".into(), "o".into()],
+            current: 0,
+        };
+        assert_eq!(candidates.advance(), Some(("a".into(), "d".into())));
+        assert_eq!(candidates.describe(), "a  [d]  o");
+        candidates.advance();
+        assert_eq!(candidates.advance(), Some(("o".into(), "a".into())));
+    }
+
+    #[test]
+    fn single_candidate() {
+        let mut candidates = Candidates {
+            list: vec!["a".into()],
+            current: 0,
+        };
+        assert_eq!(candidates.advance(), None);
+    }
+}
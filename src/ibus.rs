@@ -0,0 +1,522 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Composing text with an IBus engine, for languages needing an IME.
+ *
+ * With an engine chosen in the settings, like "pinyin" or "anthy",
+ * keys typing a single keysym, and erasing, go to the engine
+ * instead of the application.
+ * The engine's preedit and candidates get shown next to the cursor,
+ * and picked with its usual keys, like digits and space.
+ * What the engine commits goes to the text field,
+ * and keys it doesn't want get typed as usual.
+ *
+ * The connection goes to the IBus daemon of the session,
+ * found through `IBUS_ADDRESS` or the file the daemon leaves in the config dir.
+ * Fcitx 5 with its IBus frontend works the same way.
+ * Only daemons listening on a socket path are supported.
+ *
+ * The engine runs on its own thread,
+ * so presses return immediately and results come back to the main loop.
+ */
+
+use std::cell::RefCell;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{ Duration, Instant };
+use glib::{ Continue, MainContext, PRIORITY_DEFAULT };
+use xkbcommon::xkb;
+use zbus::Connection;
+use zvariant::{ OwnedObjectPath, OwnedValue, Value };
+
+use crate::action::{ Action, KeySym };
+use crate::keyboard::{ KeyCode, KeyStateId };
+use crate::logging;
+use crate::main::Commands;
+use crate::submission::{ Submission, SubmitData, Timestamp };
+use crate::xdg;
+
+// Traits
+use crate::logging::Warn;
+
+
+const DESTINATION: &str = "org.freedesktop.IBus";
+const BUS_PATH: &str = "/org/freedesktop/IBus";
+const CONTEXT_INTERFACE: &str = "org.freedesktop.IBus.InputContext";
+
+/// IBUS_CAP_PREEDIT_TEXT | IBUS_CAP_LOOKUP_TABLE
+const CAPABILITIES: u32 = 1 | 4;
+const RELEASE_MASK: u32 = 1 << 30;
+
+/// How long to wait for presses before looking for signals
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long to wait for further signals
+const SIGNAL_TIMEOUT: Duration = Duration::from_millis(10);
+/// Engines answer much faster, unless stuck
+const CALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Error = Box<dyn std::error::Error>;
+
+/// What to do with a key the engine doesn't take
+#[derive(Debug)]
+enum Fallback {
+    Text(CString),
+    Keycodes,
+    Erase,
+}
+
+#[derive(Debug)]
+struct KeyEvent {
+    keyval: u32,
+    key_id: KeyStateId,
+    keycodes: Vec<KeyCode>,
+    fallback: Fallback,
+}
+
+/// Sent to the main loop
+enum Message {
+    /// The session with this number takes keys
+    Started(u32, mpsc::Sender<KeyEvent>),
+    Stopped(u32),
+    Commit(String),
+    /// Preedit and candidates, or nothing to hide them
+    Show(Option<String>),
+    Unhandled(KeyEvent),
+}
+
+thread_local! {
+    /// The session taking presses, with its number
+    static SESSION: RefCell<Option<(u32, mpsc::Sender<KeyEvent>)>> = RefCell::new(None);
+}
+
+/// Hands the key to the engine, if there's one.
+/// Returns false if the key should get typed as usual.
+pub fn forward(action: &Action, key_id: KeyStateId, keycodes: &[KeyCode]) -> bool {
+    let (keysym, fallback) = match action {
+        Action::Submit { text, keys } => match keys.as_slice() {
+            [KeySym(name)] => (
+                name.as_str(),
+                match text {
+                    Some(text) => Fallback::Text(text.clone()),
+                    None => Fallback::Keycodes,
+                },
+            ),
+            _ => return false,
+        },
+        Action::Erase => ("BackSpace", Fallback::Erase),
+        _ => return false,
+    };
+    let keyval = xkb::keysym_from_name(keysym, xkb::KEYSYM_NO_FLAGS);
+    if keyval == xkb::KEY_NoSymbol {
+        return false;
+    }
+    SESSION.with(|session| {
+        let mut session = session.borrow_mut();
+        let event = KeyEvent {
+            keyval,
+            key_id,
+            keycodes: keycodes.to_vec(),
+            fallback,
+        };
+        let sent = match &*session {
+            Some((_, keys)) => keys.send(event).is_ok(),
+            None => false,
+        };
+        if !sent {
+            *session = None;
+        }
+        sent
+    })
+}
+
+fn type_unhandled(submission: &mut Submission, key: KeyEvent, time: Timestamp) {
+    let data = match &key.fallback {
+        Fallback::Text(text) => SubmitData::Text(text),
+        Fallback::Keycodes => SubmitData::Keycodes,
+        Fallback::Erase => SubmitData::Erase,
+    };
+    submission.handle_press(key.key_id, data, &key.keycodes, time);
+    submission.handle_release(key.key_id, time);
+}
+
+/// The candidates shown, like in a lookup table
+#[derive(Debug, Default, PartialEq)]
+struct Candidates {
+    /// Only the current page
+    items: Vec<String>,
+    /// Within the page
+    cursor: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Panel {
+    preedit: String,
+    candidates: Candidates,
+}
+
+impl Panel {
+    fn describe(&self) -> Option<String> {
+        let candidates = self.candidates.items.iter().enumerate()
+            .map(|(i, text)| match Some(i) == self.candidates.cursor {
+                true => format!("[{}.{}]", i + 1, text),
+                false => format!("{}.{}", i + 1, text),
+            });
+        let parts: Vec<String> = Some(self.preedit.clone())
+            .filter(|preedit| !preedit.is_empty())
+            .into_iter()
+            .chain(candidates)
+            .collect();
+        match parts.is_empty() {
+            true => None,
+            false => Some(parts.join("  ")),
+        }
+    }
+}
+
+/// IBusText is a structure holding the text in the third field.
+fn get_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Value(inner) => get_text(inner),
+        Value::Structure(structure) => match structure.fields() {
+            [_name, _attachments, Value::Str(text), ..] => Some(text.as_str().into()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// IBusLookupTable holds the page size, the cursor, whether it's visible,
+/// then after two more, the candidates.
+fn get_candidates(value: &Value) -> Option<Candidates> {
+    match value {
+        Value::Value(inner) => get_candidates(inner),
+        Value::Structure(structure) => match structure.fields() {
+            [
+                _name, _attachments,
+                Value::U32(page_size), Value::U32(cursor), Value::Bool(cursor_visible),
+                _round, _orientation,
+                Value::Array(items),
+                ..
+            ] => {
+                let page_size = (*page_size as usize).max(1);
+                let cursor = *cursor as usize;
+                let start = cursor - cursor % page_size;
+                Some(Candidates {
+                    items: items.get().iter()
+                        .skip(start)
+                        .take(page_size)
+                        .filter_map(get_text)
+                        .collect(),
+                    cursor: match cursor_visible {
+                        true => Some(cursor - start),
+                        false => None,
+                    },
+                })
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The display named like the daemon names it.
+fn get_display() -> String {
+    if let Ok(display) = env::var("WAYLAND_DISPLAY") {
+        return display;
+    }
+    env::var("DISPLAY").ok()
+        .and_then(|display| display.rsplit(':').next().map(String::from))
+        .and_then(|number| number.split('.').next().map(String::from))
+        .unwrap_or_else(|| "0".into())
+}
+
+fn get_address() -> Result<String, Error> {
+    if let Ok(address) = env::var("IBUS_ADDRESS") {
+        return Ok(address);
+    }
+    let machine_id = fs::read_to_string("/etc/machine-id")
+        .or_else(|_| fs::read_to_string("/var/lib/dbus/machine-id"))?;
+    let path = xdg::config_path(
+        PathBuf::from("ibus/bus")
+            .join(format!("{}-unix-{}", machine_id.trim(), get_display())),
+    ).ok_or("No config dir")?;
+    fs::read_to_string(&path)?
+        .lines()
+        .filter(|line| line.starts_with("IBUS_ADDRESS="))
+        .map(|line| line["IBUS_ADDRESS=".len()..].to_string())
+        .next()
+        .ok_or_else(|| format!("No address in {}", path.display()).into())
+}
+
+/// Takes the socket path out of an address like `unix:path=/run/...,guid=...`.
+fn get_socket_path(address: &str) -> Option<&str> {
+    address.split(';')
+        .filter(|address| address.starts_with("unix:"))
+        .flat_map(|address| address["unix:".len()..].split(','))
+        .filter(|option| option.starts_with("path="))
+        .map(|option| &option["path=".len()..])
+        .next()
+}
+
+fn is_timeout(error: &zbus::Error) -> bool {
+    match error {
+        zbus::Error::Io(e) => match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+struct Session {
+    connection: Connection,
+    /// Shares the connection's socket, to change its timeout
+    socket: UnixStream,
+    context: OwnedObjectPath,
+    panel: Panel,
+    messages: glib::Sender<Message>,
+}
+
+impl Session {
+    fn connect(engine: &str, messages: glib::Sender<Message>) -> Result<Session, Error> {
+        let address = get_address()?;
+        let path = get_socket_path(&address)
+            .ok_or_else(|| format!("Unsupported IBus address {}", address))?;
+        let stream = UnixStream::connect(path)?;
+        let socket = stream.try_clone()?;
+        let connection = Connection::new_unix_client(stream, true)?;
+        socket.set_read_timeout(Some(CALL_TIMEOUT))?;
+        let reply = connection.call_method(
+            Some(DESTINATION),
+            BUS_PATH,
+            Some(DESTINATION),
+            "CreateInputContext",
+            &("Squeekboard"),
+        )?;
+        let context: OwnedObjectPath = reply.body()?;
+        let session = Session {
+            connection,
+            socket,
+            context,
+            panel: Panel::default(),
+            messages,
+        };
+        session.call("SetCapabilities", &(CAPABILITIES))?;
+        session.call("SetEngine", &(engine))?;
+        session.call("FocusIn", &())?;
+        Ok(session)
+    }
+
+    fn call<B: serde::Serialize + zvariant::Type>(&self, method: &str, body: &B)
+        -> Result<zbus::Message, Error>
+    {
+        self.socket.set_read_timeout(Some(CALL_TIMEOUT))?;
+        Ok(self.connection.call_method(
+            Some(DESTINATION),
+            self.context.as_str(),
+            Some(CONTEXT_INTERFACE),
+            method,
+            body,
+        )?)
+    }
+
+    fn process(&mut self, key: KeyEvent) -> Result<(), Error> {
+        let reply = self.call("ProcessKeyEvent", &(key.keyval, 0u32, 0u32))?;
+        let handled: bool = reply.body()?;
+        // Engines expect the release too, but it makes no difference.
+        self.call("ProcessKeyEvent", &(key.keyval, 0u32, RELEASE_MASK))?;
+        // A commit may come before the key which caused it.
+        self.receive_signals()?;
+        if !handled {
+            self.messages.send(Message::Unhandled(key))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the signals which already arrived.
+    fn receive_signals(&mut self) -> Result<(), Error> {
+        self.socket.set_read_timeout(Some(SIGNAL_TIMEOUT))?;
+        loop {
+            let message = match self.connection.receive_message() {
+                Ok(message) => message,
+                Err(e) if is_timeout(&e) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let member = message.header()?.member()?.map(String::from);
+            match member.as_ref().map(String::as_str) {
+                Some("CommitText") => {
+                    let text: OwnedValue = message.body()?;
+                    if let Some(text) = get_text(&text) {
+                        self.messages.send(Message::Commit(text))?;
+                    }
+                },
+                Some("UpdatePreeditText") => {
+                    let (text, _cursor, visible): (OwnedValue, u32, bool) = message.body()?;
+                    self.panel.preedit = match visible {
+                        true => get_text(&text).unwrap_or_default(),
+                        false => String::new(),
+                    };
+                    self.show()?;
+                },
+                Some("HidePreeditText") => {
+                    self.panel.preedit = String::new();
+                    self.show()?;
+                },
+                Some("UpdateLookupTable") => {
+                    let (table, visible): (OwnedValue, bool) = message.body()?;
+                    self.panel.candidates = match visible {
+                        true => get_candidates(&table).unwrap_or_default(),
+                        false => Candidates::default(),
+                    };
+                    self.show()?;
+                },
+                Some("HideLookupTable") => {
+                    self.panel.candidates = Candidates::default();
+                    self.show()?;
+                },
+                // Keys passed on by the engine, typed as text
+                Some("ForwardKeyEvent") => {
+                    let (keyval, _keycode, state): (u32, u32, u32) = message.body()?;
+                    let text = xkb::keysym_to_utf8(keyval);
+                    let text = text.trim_end_matches('\0');
+                    if state & RELEASE_MASK == 0 && !text.is_empty() {
+                        self.messages.send(Message::Commit(text.into()))?;
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn show(&self) -> Result<(), Error> {
+        Ok(self.messages.send(Message::Show(self.panel.describe()))?)
+    }
+
+    /// Runs until the main loop stops sending keys.
+    fn run(mut self, keys: mpsc::Receiver<KeyEvent>) -> Result<(), Error> {
+        loop {
+            match keys.recv_timeout(POLL_INTERVAL) {
+                Ok(key) => self.process(key)?,
+                Err(mpsc::RecvTimeoutError::Timeout) => self.receive_signals()?,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Starts and stops sessions as the engine in the settings changes.
+fn watch(commands: mpsc::Receiver<Commands>, messages: glib::Sender<Message>) {
+    let mut current = 0;
+    for commands in commands.iter() {
+        if let Some(engine) = commands.ibus_engine {
+            // The main loop drops the old session's keys,
+            // and the session ends when it notices.
+            messages.send(Message::Stopped(current))
+                .or_print(logging::Problem::Bug, "Can't stop IBus session");
+            current += 1;
+            if let Some(engine) = engine {
+                let (sender, keys) = mpsc::channel();
+                let messages = messages.clone();
+                let number = current;
+                thread::spawn(move || {
+                    let session = Session::connect(&engine, messages.clone())
+                        .or_print(logging::Problem::Warning, "Can't connect to IBus");
+                    if let Some(session) = session {
+                        messages.send(Message::Started(number, sender))
+                            .or_print(logging::Problem::Bug, "Can't start IBus session");
+                        session.run(keys)
+                            .or_print(logging::Problem::Warning, "IBus session failed");
+                    }
+                    messages.send(Message::Stopped(number))
+                        .or_print(logging::Problem::Bug, "Can't stop IBus session");
+                });
+            }
+        }
+    }
+}
+
+fn handle(submission: &mut Submission, message: Message, time: Timestamp) {
+    match message {
+        Message::Started(number, keys) => {
+            SESSION.with(|session| *session.borrow_mut() = Some((number, keys)));
+        },
+        Message::Stopped(number) => {
+            let was_current = SESSION.with(|session| {
+                let mut session = session.borrow_mut();
+                let is_current = session.as_ref()
+                    .map(|(n, _)| *n == number)
+                    .unwrap_or(false);
+                if is_current {
+                    *session = None;
+                }
+                is_current
+            });
+            if was_current {
+                submission.hide_popup();
+            }
+        },
+        Message::Commit(text) => {
+            let result = CString::new(text)
+                .map_err(|e| e.to_string())
+                .and_then(|text| {
+                    submission.commit_text(&text)
+                        .map_err(|_| "No text field".to_string())
+                });
+            if let Err(e) = result {
+                log_print!(logging::Level::Warning, "Can't commit from IBus: {}", e);
+            }
+        },
+        Message::Show(Some(text)) => submission.show_popup(&text),
+        Message::Show(None) => submission.hide_popup(),
+        Message::Unhandled(key) => type_unhandled(submission, key, time),
+    }
+}
+
+/// Call on the main loop, before the state loop starts.
+pub fn init(commands: mpsc::Receiver<Commands>, submission: Rc<RefCell<Submission>>) {
+    let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    let start = Instant::now();
+    receiver.attach(
+        Some(&MainContext::default()),
+        move |message| {
+            let time = Timestamp(start.elapsed().as_millis() as u32);
+            handle(&mut submission.borrow_mut(), message, time);
+            Continue(true)
+        },
+    );
+    thread::spawn(move || watch(commands, sender));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn panel() {
+        let mut panel = Panel::default();
+        assert_eq!(panel.describe(), None);
+        panel.preedit = "ni".into();
+        panel.candidates = Candidates {
+            items: vec!["你".into(), "呢".into()],
+            cursor: Some(1),
+        };
+        assert_eq!(panel.describe(), Some("ni  1.你  [2.呢]".into()));
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(
+            get_socket_path("unix:path=/run/user/1000/ibus/bus,guid=0123"),
+            Some("/run/user/1000/ibus/bus"),
+        );
+        assert_eq!(get_socket_path("unix:abstract=/tmp/dbus-X,guid=0123"), None);
+    }
+}
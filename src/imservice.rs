@@ -6,11 +6,14 @@
 use std::boxed::Box;
 use std::ffi::CString;
 use std::fmt;
+use std::mem;
 use std::num::Wrapping;
 use std::string::String;
 use std::time::Instant;
 
 use crate::event_loop::driver;
+use crate::input_popup;
+use crate::spelling;
 use crate::state;
 use crate::state::Event;
 use ::logging;
@@ -155,6 +158,10 @@ pub mod c {
 
         imservice.current = imservice.pending.clone();
         imservice.serial += Wrapping(1u32);
+        if !imservice.current.active {
+            imservice.popup.hide();
+        }
+        imservice.check_spelling();
         imservice.send_event();
     }
     
@@ -170,8 +177,13 @@ pub mod c {
         // no need to care about proper double-buffering,
         // the keyboard is already decommissioned
         imservice.current.active = false;
+        imservice.popup.hide();
 
         imservice.send_event();
+
+        // The input method can't be brought back in this process.
+        // A fresh instance will try to get it again.
+        ::watchdog::request_restart("input method became unavailable");
     }    
 
     // FIXME: destroy and deallocate
@@ -226,7 +238,7 @@ bitflags!{
 /// use rs::imservice::ContentPurpose;
 /// assert_eq!(ContentPurpose::Alpha as u32, 1);
 /// ```
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ContentPurpose {
     Normal = 0,
     Alpha = 1,
@@ -319,6 +331,36 @@ impl Default for IMProtocolState {
     }
 }
 
+/// Changes held back until the end of the frame, to go out in one commit.
+///
+/// The protocol applies the deletion before the text within a commit,
+/// and a second string or deletion would replace the first.
+/// So strings get joined, and so do deletions,
+/// but a deletion after some text needs a commit of its own.
+#[derive(Default, Debug, PartialEq)]
+struct Batch {
+    delete: Option<(u32, u32)>,
+    text: Vec<u8>,
+    /// Something asked for a commit
+    committed: bool,
+}
+
+impl Batch {
+    fn add_text(&mut self, text: &CString) {
+        self.text.extend_from_slice(text.as_bytes());
+    }
+
+    /// Returns false if the batch must be sent before the deletion.
+    fn add_delete(&mut self, before: u32, after: u32) -> bool {
+        if !self.text.is_empty() {
+            return false;
+        }
+        let (b, a) = self.delete.unwrap_or((0, 0));
+        self.delete = Some((b + before, a + after));
+        true
+    }
+}
+
 pub struct IMService {
     /// Owned reference (still created and destroyed in C)
     pub im: c::InputMethod,
@@ -328,6 +370,13 @@ pub struct IMService {
     current: IMProtocolState, // turn current into an idiomatic representation?
     preedit_string: String,
     serial: Wrapping<u32>,
+    /// Shown next to the text cursor
+    popup: input_popup::Manager,
+    speller: spelling::Speller<spelling::WordList>,
+    /// The word whose corrections are in the popup
+    misspelling: Option<spelling::Misspelling>,
+    /// Present while a frame is being handled
+    batch: Option<Batch>,
 }
 
 pub enum SubmitError {
@@ -349,6 +398,10 @@ impl IMService {
             current: IMProtocolState::default(),
             preedit_string: String::new(),
             serial: Wrapping(0u32),
+            popup: input_popup::Manager::new(im),
+            speller: spelling::Speller::new(spelling::WordList::get),
+            misspelling: None,
+            batch: None,
         });
         unsafe {
             c::imservice_connect_listeners(
@@ -359,45 +412,105 @@ impl IMService {
         imservice
     }
 
-    pub fn commit_string(&self, text: &CString) -> Result<(), SubmitError> {
-        match self.current.active {
-            true => {
+    pub fn commit_string(&mut self, text: &CString) -> Result<(), SubmitError> {
+        match (self.current.active, &mut self.batch) {
+            (true, Some(batch)) => {
+                batch.add_text(text);
+                Ok(())
+            },
+            (true, None) => {
                 unsafe {
                     c::eek_input_method_commit_string(self.im, text.as_ptr())
                 }
                 Ok(())
             },
-            false => Err(SubmitError::NotActive),
+            (false, _) => Err(SubmitError::NotActive),
         }
     }
 
     pub fn delete_surrounding_text(
-        &self,
+        &mut self,
         before: u32, after: u32,
     ) -> Result<(), SubmitError> {
-        match self.current.active {
-            true => {
-                unsafe {
-                    c::eek_input_method_delete_surrounding_text(
-                        self.im,
-                        before, after,
-                    )
+        if !self.current.active {
+            return Err(SubmitError::NotActive);
+        }
+        let queued = self.batch.as_mut()
+            .map(|batch| batch.add_delete(before, after));
+        match queued {
+            Some(true) => {},
+            Some(false) => {
+                // The text before it goes out first.
+                self.send_batch();
+                if let Some(batch) = &mut self.batch {
+                    batch.add_delete(before, after);
                 }
-                Ok(())
             },
-            false => Err(SubmitError::NotActive),
+            None => unsafe {
+                c::eek_input_method_delete_surrounding_text(
+                    self.im,
+                    before, after,
+                )
+            },
         }
+        Ok(())
     }
 
     pub fn commit(&mut self) -> Result<(), SubmitError> {
-        match self.current.active {
-            true => {
+        match (self.current.active, &mut self.batch) {
+            (true, Some(batch)) => {
+                batch.committed = true;
+                Ok(())
+            },
+            (true, None) => {
                 unsafe {
                     c::eek_input_method_commit(self.im, self.serial.0)
                 }
                 Ok(())
             },
-            false => Err(SubmitError::NotActive),
+            (false, _) => Err(SubmitError::NotActive),
+        }
+    }
+
+    /// Holds back the changes to the text until `end_batch`,
+    /// so that the application gets them in a single commit.
+    pub fn begin_batch(&mut self) {
+        if self.batch.is_none() {
+            self.batch = Some(Batch::default());
+        }
+    }
+
+    pub fn end_batch(&mut self) {
+        self.send_batch();
+        self.batch = None;
+    }
+
+    /// Sends out what's held back, and starts an empty batch.
+    fn send_batch(&mut self) {
+        let batch = match &mut self.batch {
+            Some(batch) => mem::replace(batch, Batch::default()),
+            None => return,
+        };
+        // Anything held back was accepted while active,
+        // and the protocol state only changes between frames.
+        if !self.current.active || !batch.committed {
+            return;
+        }
+        if let Some((before, after)) = batch.delete {
+            unsafe {
+                c::eek_input_method_delete_surrounding_text(self.im, before, after)
+            }
+        }
+        if !batch.text.is_empty() {
+            // Joined from strings which had no NUL bytes
+            if let Ok(text) = CString::new(batch.text) {
+                unsafe {
+                    c::eek_input_method_commit_string(self.im, text.as_ptr())
+                }
+            }
+        }
+        unsafe {
+            c::eek_input_method_commit(self.im, self.serial.0)
         }
     }
 
@@ -405,6 +518,101 @@ impl IMService {
         self.current.active
     }
 
+    /// The text around the cursor, and the cursor's byte offset in it.
+    /// Only known while active.
+    pub fn get_surrounding_text(&self) -> Option<(&str, usize)> {
+        match self.current.active {
+            true => self.current.surrounding_text.to_str().ok()
+                .map(|text| (text, self.current.surrounding_cursor as usize)),
+            false => None,
+        }
+    }
+
+    /// Shows text next to the cursor, e.g. composition candidates.
+    pub fn show_popup(&mut self, text: &str) -> Result<(), SubmitError> {
+        match self.current.active {
+            true => {
+                // Someone else owns the popup now.
+                self.misspelling = None;
+                self.popup.show(text);
+                Ok(())
+            },
+            false => Err(SubmitError::NotActive),
+        }
+    }
+
+    pub fn hide_popup(&mut self) {
+        self.misspelling = None;
+        self.popup.hide();
+    }
+
+    /// Shows corrections if the cursor is after or in a misspelled word.
+    fn check_spelling(&mut self) {
+        let details = state::InputMethodDetails {
+            hint: self.current.content_hint,
+            purpose: self.current.content_purpose,
+        };
+        let found = match self.current.active
+            && spelling::is_enabled()
+            && !details.is_sensitive()
+        {
+            true => match self.current.surrounding_text.to_str() {
+                Ok(text) => self.speller.check(
+                    text,
+                    self.current.surrounding_cursor as usize,
+                ),
+                Err(_) => None,
+            },
+            false => None,
+        };
+        match (&self.misspelling, &found) {
+            (_, Some(misspelling)) => self.popup.show(&misspelling.describe()),
+            // Only take down the popup if it showed corrections.
+            (Some(_), None) => self.popup.hide(),
+            (None, None) => {},
+        }
+        self.misspelling = found;
+    }
+
+    /// Replaces the misspelled word with the best correction,
+    /// keeping whatever was typed after it.
+    pub fn accept_correction(&mut self) -> Result<(), SubmitError> {
+        let misspelling = match self.misspelling.clone() {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let correction = match misspelling.suggestions.first() {
+            Some(c) => c.clone(),
+            None => return Ok(()),
+        };
+        let (before, after, replacement) = {
+            let (text, cursor) = match self.get_surrounding_text() {
+                Some(t) => t,
+                None => return Err(SubmitError::NotActive),
+            };
+            match cursor < misspelling.end {
+                // In the word
+                true => (
+                    cursor - misspelling.start,
+                    misspelling.end - cursor,
+                    correction,
+                ),
+                false => (
+                    cursor - misspelling.start,
+                    0,
+                    format!("{}{}", correction, &text[misspelling.end..cursor]),
+                ),
+            }
+        };
+        let replacement = match CString::new(replacement) {
+            Ok(r) => r,
+            Err(_) => return Ok(()),
+        };
+        self.delete_surrounding_text(before as u32, after as u32)
+            .and_then(|()| self.commit_string(&replacement))
+            .and_then(|()| self.commit())
+    }
+
     fn send_event(&self) {
         let state = &self.current;
         let timestamp = Instant::now();
@@ -422,3 +630,21 @@ impl IMService {
             .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batch() {
+        let mut batch = Batch::default();
+        assert!(batch.add_delete(1, 0));
+        assert!(batch.add_delete(2, 0));
+        batch.add_text(&CString::new("a").unwrap());
+        batch.add_text(&CString::new("b").unwrap());
+        assert_eq!(batch.delete, Some((3, 0)));
+        assert_eq!(batch.text, b"ab".to_vec());
+        // Would get applied before the text
+        assert!(!batch.add_delete(1, 0));
+    }
+}
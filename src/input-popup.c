@@ -0,0 +1,107 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+#include <gdk/gdkwayland.h>
+#include <gtk/gtk.h>
+
+#include "input-popup.h"
+
+struct squeek_input_popup {
+    struct zwp_input_method_v2 *im; // unowned
+    struct squeek_input_popup_manager *manager; // unowned, calls back to Rust
+    GtkWidget *window;
+    GtkWidget *label;
+    struct zwp_input_popup_surface_v2 *popup; // only while mapped
+};
+
+/// Calls back into Rust
+void squeek_input_popup_handle_rectangle(struct squeek_input_popup_manager *manager,
+                                         int32_t x, int32_t y, int32_t width, int32_t height);
+
+static void
+handle_text_input_rectangle(void *data, struct zwp_input_popup_surface_v2 *popup,
+                            int32_t x, int32_t y, int32_t width, int32_t height)
+{
+    (void)popup;
+    struct squeek_input_popup *self = data;
+    squeek_input_popup_handle_rectangle(self->manager, x, y, width, height);
+}
+
+static const struct zwp_input_popup_surface_v2_listener popup_listener = {
+    .text_input_rectangle = handle_text_input_rectangle,
+};
+
+static void
+on_realize(struct squeek_input_popup *self, GtkWidget *widget)
+{
+    (void)self;
+    // The surface gets the input popup role instead of a toplevel one.
+    gdk_wayland_window_set_use_custom_surface(gtk_widget_get_window(widget));
+}
+
+static void
+on_map(struct squeek_input_popup *self, GtkWidget *widget)
+{
+    struct wl_surface *surface = gdk_wayland_window_get_wl_surface(gtk_widget_get_window(widget));
+    self->popup = zwp_input_method_v2_get_input_popup_surface(self->im, surface);
+    zwp_input_popup_surface_v2_add_listener(self->popup, &popup_listener, self);
+    wl_surface_commit(surface);
+}
+
+static void
+on_unmap(struct squeek_input_popup *self, GtkWidget *widget)
+{
+    (void)widget;
+    if (self->popup) {
+        zwp_input_popup_surface_v2_destroy(self->popup);
+        self->popup = NULL;
+    }
+}
+
+// Called from Rust
+struct squeek_input_popup *
+squeek_input_popup_new(struct zwp_input_method_v2 *im, struct squeek_input_popup_manager *manager)
+{
+    struct squeek_input_popup *self = calloc(1, sizeof(struct squeek_input_popup));
+    self->im = im;
+    self->manager = manager;
+    self->window = gtk_window_new(GTK_WINDOW_TOPLEVEL);
+    gtk_window_set_decorated(GTK_WINDOW(self->window), FALSE);
+    gtk_style_context_add_class(gtk_widget_get_style_context(self->window), "input-popup");
+    self->label = gtk_label_new(NULL);
+    gtk_widget_set_halign(self->label, GTK_ALIGN_START);
+    gtk_container_add(GTK_CONTAINER(self->window), self->label);
+
+    g_object_connect(self->window,
+        "swapped-signal::realize", G_CALLBACK(on_realize), self,
+        "swapped-signal::map", G_CALLBACK(on_map), self,
+        "swapped-signal::unmap", G_CALLBACK(on_unmap), self,
+        NULL);
+    return self;
+}
+
+// Called from Rust
+/// Shows the popup with the given contents.
+void
+squeek_input_popup_set_text(struct squeek_input_popup *self, const char *text)
+{
+    gtk_label_set_text(GTK_LABEL(self->label), text);
+    gtk_widget_show_all(self->window);
+}
+
+// Called from Rust
+/// Moves the contents relative to the popup surface.
+void
+squeek_input_popup_set_offset(struct squeek_input_popup *self, int32_t x, int32_t y)
+{
+    gtk_widget_set_margin_start(self->label, x);
+    gtk_widget_set_margin_top(self->label, y);
+}
+
+// Called from Rust
+void
+squeek_input_popup_hide(struct squeek_input_popup *self)
+{
+    gtk_widget_hide(self->window);
+}
@@ -0,0 +1,15 @@
+#pragma once
+
+#include <inttypes.h>
+#include "input-method-unstable-v2-client-protocol.h"
+
+/// input_popup::Manager
+struct squeek_input_popup_manager;
+
+/// A window shown as zwp_input_popup_surface_v2, next to the text cursor.
+struct squeek_input_popup;
+
+struct squeek_input_popup *squeek_input_popup_new(struct zwp_input_method_v2 *im, struct squeek_input_popup_manager *manager);
+void squeek_input_popup_set_text(struct squeek_input_popup *self, const char *text);
+void squeek_input_popup_set_offset(struct squeek_input_popup *self, int32_t x, int32_t y);
+void squeek_input_popup_hide(struct squeek_input_popup *self);
@@ -0,0 +1,147 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Popups inside the application, next to the text cursor.
+ *
+ * Used for things tied to the text being edited,
+ * like preedit and candidates in composition modes.
+ * The compositor places the popup surface,
+ * and reports where the text cursor is relative to it.
+ */
+
+use std::cmp;
+use std::ffi::CString;
+
+use crate::imservice::c::InputMethod;
+use crate::logging;
+
+// Traits
+use crate::logging::Warn;
+
+
+pub mod c {
+    use super::*;
+
+    use std::os::raw::{c_char, c_void};
+
+    /// struct squeek_input_popup*
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    pub struct InputPopup(*const c_void);
+
+    extern "C" {
+        #[allow(improper_ctypes)] // Manager will never be dereferenced in C
+        pub fn squeek_input_popup_new(
+            im: InputMethod,
+            manager: *const Manager,
+        ) -> InputPopup;
+        pub fn squeek_input_popup_set_text(popup: InputPopup, text: *const c_char);
+        pub fn squeek_input_popup_set_offset(popup: InputPopup, x: i32, y: i32);
+        pub fn squeek_input_popup_hide(popup: InputPopup);
+    }
+
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_input_popup_handle_rectangle(
+        manager: *mut Manager,
+        x: i32, y: i32, width: i32, height: i32,
+    ) {
+        if manager.is_null() {
+            panic!("Null input popup manager pointer");
+        }
+        let manager = unsafe { &mut *manager };
+        manager.set_cursor(Rectangle { x, y, width, height });
+    }
+}
+
+/// The text cursor, relative to the popup surface
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rectangle {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rectangle {
+    /// Where the contents start, to appear right below the cursor.
+    /// The compositor may have placed the popup elsewhere,
+    /// so the contents never go beyond its top-left edge.
+    fn get_contents_offset(&self) -> (i32, i32) {
+        (
+            cmp::max(self.x, 0),
+            cmp::max(self.y + self.height, 0),
+        )
+    }
+}
+
+/// Owns the popup surface of one input method.
+/// Must stay in one place in memory, because C refers to it.
+pub struct Manager {
+    im: InputMethod,
+    /// Created on first use, to stay away from GTK until needed
+    popup: Option<c::InputPopup>,
+}
+
+impl Manager {
+    pub fn new(im: InputMethod) -> Self {
+        Manager {
+            im,
+            popup: None,
+        }
+    }
+
+    /// Moves the contents below the cursor.
+    fn set_cursor(&mut self, cursor: Rectangle) {
+        if let Some(popup) = self.popup {
+            let (x, y) = cursor.get_contents_offset();
+            unsafe { c::squeek_input_popup_set_offset(popup, x, y) };
+        }
+    }
+
+    /// Shows the popup with the text, creating it as needed.
+    pub fn show(&mut self, text: &str) {
+        let text = match CString::new(text).or_print(
+            logging::Problem::Bug,
+            "Popup text contains a null byte",
+        ) {
+            Some(text) => text,
+            None => return,
+        };
+        let popup = match self.popup {
+            Some(popup) => popup,
+            None => {
+                let popup = unsafe {
+                    c::squeek_input_popup_new(self.im, self as *const Manager)
+                };
+                self.popup = Some(popup);
+                popup
+            },
+        };
+        unsafe { c::squeek_input_popup_set_text(popup, text.as_ptr()) };
+    }
+
+    pub fn hide(&mut self) {
+        if let Some(popup) = self.popup {
+            unsafe { c::squeek_input_popup_hide(popup) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn below_cursor() {
+        let cursor = Rectangle { x: 10, y: 4, width: 1, height: 20 };
+        assert_eq!(cursor.get_contents_offset(), (10, 24));
+    }
+
+    #[test]
+    fn stays_inside() {
+        let cursor = Rectangle { x: -5, y: -30, width: 1, height: 20 };
+        assert_eq!(cursor.get_contents_offset(), (0, 0));
+    }
+}
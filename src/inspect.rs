@@ -0,0 +1,145 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Everything Squeekboard knows at the moment, in one piece for bug reports.
+ *
+ * The state thread and the UI thread leave copies of their parts here,
+ * and `DumpState` on the debug D-Bus interface returns them as JSON.
+ * The application state has no stable format,
+ * so it's included the way Rust prints it.
+ *
+ * In password and PIN fields, the pressed buttons are only counted.
+ */
+
+use std::sync::{ Arc, Mutex };
+use std::time::Instant;
+
+use serde_yaml::{ Mapping, Value };
+
+use crate::data::export;
+use crate::imservice::ContentPurpose;
+use crate::layout::Layout;
+use crate::state::Application;
+
+
+/// What the UI thread holds, as of the last press, release, or layout change
+#[derive(Clone, Debug, Default)]
+pub struct Keys {
+    pub view: String,
+    /// Names of the buttons held down
+    pub pressed: Vec<String>,
+    /// How each pressed key got submitted
+    pub submitted: Vec<String>,
+    pub modifiers: Vec<String>,
+    pub keymap: Option<usize>,
+    /// Typing goes to the keyboard's own text field
+    pub redirected: bool,
+}
+
+impl Keys {
+    pub fn new(layout: &Layout) -> Keys {
+        let pressed = layout.get_pressed_button_names();
+        Keys {
+            view: layout.current_view.clone(),
+            pressed: match layout.purpose {
+                ContentPurpose::Password | ContentPurpose::Pin
+                    => pressed.iter().map(|_| "*".into()).collect(),
+                _ => pressed,
+            },
+            ..Keys::default()
+        }
+    }
+}
+
+/// Written by the threads owning the state, read by the debug interface.
+#[derive(Clone, Default)]
+pub struct Inspector {
+    application: Arc<Mutex<Option<(Application, Instant)>>>,
+    keys: Arc<Mutex<Keys>>,
+}
+
+impl Inspector {
+    pub fn new() -> Inspector {
+        Inspector::default()
+    }
+
+    pub fn set_application(&self, state: &Application, now: Instant) {
+        *self.application.lock().unwrap() = Some((state.clone(), now));
+    }
+
+    pub fn set_keys(&self, keys: Keys) {
+        *self.keys.lock().unwrap() = keys;
+    }
+
+    /// The layout is the one shown, if known.
+    pub fn dump(&self, layout: Option<&mut Layout>) -> String {
+        let application = self.application.lock().unwrap().clone();
+        let keys = self.keys.lock().unwrap().clone();
+        export::value_to_json(&describe(application, &keys, layout))
+    }
+}
+
+fn field(map: &mut Mapping, key: &str, value: Value) {
+    map.insert(Value::String(key.into()), value);
+}
+
+fn strings(items: &[String]) -> Value {
+    Value::Sequence(items.iter().cloned().map(Value::String).collect())
+}
+
+fn describe(
+    application: Option<(Application, Instant)>,
+    keys: &Keys,
+    layout: Option<&mut Layout>,
+) -> Value {
+    let mut out = Mapping::new();
+    let (application, outcome) = match application {
+        Some((state, now)) => (
+            Value::String(format!("{:#?}", state)),
+            Value::String(format!("{:#?}", state.get_outcome(now))),
+        ),
+        None => (Value::Null, Value::Null),
+    };
+    field(&mut out, "application", application);
+    field(&mut out, "outcome", outcome);
+    field(&mut out, "layout", layout.map(export::describe).unwrap_or(Value::Null));
+    field(&mut out, "view", Value::String(keys.view.clone()));
+    field(&mut out, "pressed", strings(&keys.pressed));
+
+    let mut submission = Mapping::new();
+    field(&mut submission, "submitted", strings(&keys.submitted));
+    field(&mut submission, "modifiers", strings(&keys.modifiers));
+    field(
+        &mut submission,
+        "keymap",
+        keys.keymap.map(|idx| Value::Number(idx.into())).unwrap_or(Value::Null),
+    );
+    field(&mut submission, "redirected", Value::Bool(keys.redirected));
+    field(&mut out, "submission", Value::Mapping(submission));
+    Value::Mapping(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let inspector = Inspector::new();
+        assert_eq!(
+            inspector.dump(None),
+            "{\"application\":null,\"outcome\":null,\"layout\":null,\"view\":\"\",\
+            \"pressed\":[],\"submission\":{\"submitted\":[],\"modifiers\":[],\
+            \"keymap\":null,\"redirected\":false}}",
+        );
+    }
+
+    #[test]
+    fn application() {
+        let inspector = Inspector::new();
+        let now = Instant::now();
+        inspector.set_application(&Application::new(now), now);
+        assert!(inspector.dump(None).starts_with("{\"application\":\"Application {"));
+    }
+}
@@ -1,30 +1,31 @@
 /*! State of the emulated keyboard and keys.
  * Regards the keyboard as if it was composed of switches. */
 
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::io;
 use std::mem;
 use std::ptr;
-use std::rc::Rc;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Duration;
 
 use ::action::Action;
 use ::util;
 
 // Traits
+use serde::{ Deserialize, Deserializer, Serialize, Serializer };
 use std::io::Write;
 use std::iter::{ FromIterator, IntoIterator };
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum PressType {
     Released = 0,
     Pressed = 1,
 }
 
 /// The extended, unambiguous layout-keycode
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyCode {
     pub code: u32,
     pub keymap_idx: usize,
@@ -49,40 +50,164 @@ bitflags!{
 }
 
 /// When the submitted actions of keys need to be tracked,
-/// they need a stable, comparable ID
-#[derive(Clone, PartialEq)]
-pub struct KeyStateId(*const KeyState);
+/// they need a stable, comparable ID.
+///
+/// IDs stay unique across layouts,
+/// so that a modifier latched on one layout
+/// doesn't get mixed up with a key on the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyStateId {
+    store: usize,
+    index: usize,
+}
+
+/// Only the index, which is enough to tell keys of one layout apart
+impl fmt::Display for KeyStateId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.index)
+    }
+}
+
+/// Store number of IDs read back, until `KeyStore::adopt` takes them
+const NO_STORE: usize = usize::MAX;
+
+/// Only the index gets stored.
+/// The store gets a new number when read back.
+impl Serialize for KeyStateId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.index.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyStateId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D)
+        -> Result<Self, D::Error>
+    {
+        usize::deserialize(deserializer)
+            .map(|index| KeyStateId { store: NO_STORE, index })
+    }
+}
+
+/// How the key acts when held, where the layout overrides the defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Behavior {
+    /// Whether typing repeats while held
+    pub repeatable: Option<bool>,
+    /// Text to choose instead, after holding the key
+    pub long_press: Vec<String>,
+    /// The view latched by the key goes back after this long without a press
+    pub latch_timeout: Option<Duration>,
+    /// Holding the key opens the layout chooser
+    #[serde(default)]
+    pub hold_for_preferences: bool,
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyState {
     pub pressed: PressType,
     /// A cache of raw keycodes derived from Action::Submit given a keymap
     pub keycodes: Vec<KeyCode>,
     /// Static description of what the key does when pressed or released
     pub action: Action,
+    #[serde(default)]
+    pub behavior: Behavior,
 }
 
 impl KeyState {
-    #[must_use]
-    pub fn into_released(self) -> KeyState {
-        KeyState {
-            pressed: PressType::Released,
-            ..self
+    /// Keys doing something else on hold don't repeat unless asked to.
+    pub fn repeats(&self) -> bool {
+        self.behavior.repeatable.unwrap_or(
+            self.behavior.long_press.is_empty()
+                && !self.behavior.hold_for_preferences
+        )
+    }
+
+    /// Changes the state in place,
+    /// so that pressing doesn't copy the keycodes and the action.
+    pub fn press(&mut self) {
+        self.pressed = PressType::Pressed;
+    }
+
+    pub fn release(&mut self) {
+        self.pressed = PressType::Released;
+    }
+}
+
+/// Source of store numbers
+static NEXT_STORE: AtomicUsize = AtomicUsize::new(0);
+
+/// Owns the states of all keys of a layout.
+/// Buttons refer to the states by ID,
+/// and buttons with the same name share one.
+#[derive(Debug)]
+pub struct KeyStore {
+    id: usize,
+    keys: Vec<KeyState>,
+}
+
+impl KeyStore {
+    pub fn new() -> KeyStore {
+        KeyStore {
+            id: NEXT_STORE.fetch_add(1, Ordering::Relaxed),
+            keys: Vec::new(),
         }
     }
 
-    #[must_use]
-    pub fn into_pressed(self) -> KeyState {
-        KeyState {
-            pressed: PressType::Pressed,
-            ..self
+    pub fn add(&mut self, key: KeyState) -> KeyStateId {
+        self.keys.push(key);
+        KeyStateId {
+            store: self.id,
+            index: self.keys.len() - 1,
+        }
+    }
+
+    fn get_index(&self, id: KeyStateId) -> usize {
+        if id.store != self.id {
+            panic!("Key {:?} belongs to another layout", id);
         }
+        id.index
     }
 
-    /// KeyStates instances are the unique identifiers of pressed keys,
-    /// and the actions submitted with them.
-    pub fn get_id(keystate: &Rc<RefCell<KeyState>>) -> KeyStateId {
-        KeyStateId(keystate.as_ptr() as *const KeyState)
+    pub fn get(&self, id: KeyStateId) -> &KeyState {
+        &self.keys[self.get_index(id)]
+    }
+
+    pub fn get_mut(&mut self, id: KeyStateId) -> &mut KeyState {
+        let index = self.get_index(id);
+        &mut self.keys[index]
+    }
+
+    /// All keys, with their IDs.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(KeyStateId, &'a KeyState)> + 'a {
+        let store = self.id;
+        self.keys.iter()
+            .enumerate()
+            .map(move |(index, key)| (KeyStateId { store, index }, key))
+    }
+
+    /// Turns an ID read back from storage into one of this store.
+    pub fn adopt(&self, id: KeyStateId) -> Option<KeyStateId> {
+        match id.store == NO_STORE && id.index < self.keys.len() {
+            true => Some(KeyStateId { store: self.id, index: id.index }),
+            false => None,
+        }
+    }
+}
+
+impl Serialize for KeyStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.keys.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D)
+        -> Result<Self, D::Error>
+    {
+        let keys = Vec::deserialize(deserializer)?;
+        let mut store = KeyStore::new();
+        store.keys = keys;
+        Ok(store)
     }
 }
 
@@ -333,4 +458,29 @@ mod tests {
         let code = keycodes.get("U1101").expect("Did not find the tested keysym");
         assert_eq!(code.keymap_idx, 1);
     }
+
+    fn make_key() -> KeyState {
+        KeyState {
+            pressed: PressType::Released,
+            keycodes: Vec::new(),
+            action: Action::Erase,
+            behavior: Behavior::default(),
+        }
+    }
+
+    #[test]
+    fn store_ids() {
+        let mut store = KeyStore::new();
+        let first = store.add(make_key());
+        let second = store.add(make_key());
+        assert_ne!(first, second);
+
+        store.get_mut(second).press();
+        assert_eq!(store.get(first).pressed, PressType::Released);
+        assert_eq!(store.get(second).pressed, PressType::Pressed);
+
+        // The same place in another layout is another key
+        let mut other = KeyStore::new();
+        assert_ne!(other.add(make_key()), first);
+    }
 }
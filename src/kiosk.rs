@@ -0,0 +1,106 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Locked-down mode for public terminals.
+ *
+ * Enabled by `--profile kiosk`, or by the presence of the config file.
+ * The config file may pin the layout:
+ *
+ * ```yaml
+ * layout: de
+ * ```
+ *
+ * In kiosk mode, the popover doesn't open,
+ * so the layout can't be changed and there are no preferences.
+ * D-Bus methods changing the state get refused,
+ * and the debug interface doesn't start.
+ * Saved state from earlier sessions is ignored.
+ */
+
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::sync::atomic::{ AtomicBool, Ordering };
+
+use crate::logging;
+
+
+/// Outside of the user's reach
+const CONFIG_PATH: &str = "/etc/squeekboard/kiosk.yaml";
+
+/// Set once on startup
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Kiosk {
+    /// Shown instead of the system layout
+    pub layout: Option<String>,
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn load_config() -> Option<Kiosk> {
+    match fs::read_to_string(CONFIG_PATH) {
+        // An empty file just turns kiosk mode on
+        Ok(ref data) if data.trim().is_empty() => Some(Kiosk::default()),
+        Ok(data) => Some(match serde_yaml::from_str(&data) {
+            Ok(kiosk) => kiosk,
+            Err(e) => {
+                // Better locked down with the wrong layout than not locked.
+                log_print!(
+                    logging::Level::Warning,
+                    "Bad kiosk config at {}: {}", CONFIG_PATH, e,
+                );
+                Kiosk::default()
+            },
+        }),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            log_print!(
+                logging::Level::Warning,
+                "Can't read kiosk config at {}: {}", CONFIG_PATH, e,
+            );
+            Some(Kiosk::default())
+        },
+    }
+}
+
+/// Returns the kiosk configuration if kiosk mode is on,
+/// and applies it to the whole process.
+pub fn init(forced: bool) -> Option<Kiosk> {
+    let kiosk = match (load_config(), forced) {
+        (Some(kiosk), _) => Some(kiosk),
+        (None, true) => Some(Kiosk::default()),
+        (None, false) => None,
+    };
+    ENABLED.store(kiosk.is_some(), Ordering::Relaxed);
+    kiosk
+}
+
+pub mod c {
+    use super::*;
+
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_kiosk_is_enabled() -> u32 {
+        is_enabled() as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        assert_eq!(
+            serde_yaml::from_str::<Kiosk>("layout: de").unwrap(),
+            Kiosk { layout: Some("de".into()) },
+        );
+        assert!(serde_yaml::from_str::<Kiosk>("layuot: de").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Labels which are words rather than the symbol the key types,
+ * like "ABC" on the key switching to letters.
+ *
+ * Layouts refer to them with `label_name`, and the builder puts in
+ * the English text, keeping the name on the button.
+ * That text gets translated when the layout is loaded,
+ * so that compiled layouts stay the same in every language.
+ * Labels which are spelled out in the layout stay as they are.
+ *
+ * The common views also have names which the user gets to hear.
+ * Other views are called what the layout calls them.
+ *
+ * xgettext finds the texts by the `N_` marker, reading this file as C.
+ */
+
+use std::ffi::{ CStr, CString };
+use std::os::raw::c_char;
+use glib_sys;
+
+use crate::layout::{ Button, Label, Layout };
+use crate::util::Interned;
+
+
+/// Marks the text for xgettext
+#[allow(non_snake_case)]
+const fn N_(msgid: &'static str) -> &'static str {
+    msgid
+}
+
+const DOMAIN: &[u8] = b"squeekboard\0";
+
+/// The names for layouts, and the English texts
+const NAMED: &[(&str, &str)] = &[
+    // translators: Key label, switches to the letters view
+    ("letters", N_("ABC")),
+    // translators: Key label, switches to the letters view, in lower case
+    ("letters_lower", N_("abc")),
+    // translators: Key label, switches to the numbers view
+    ("numbers", N_("123")),
+    // translators: Key label, switches to the numbers and symbols view
+    ("numbers_symbols", N_("?123")),
+    // translators: Key label, switches to the symbols view
+    ("symbols", N_("*/=")),
+    // translators: Key label
+    ("enter", N_("Enter")),
+    // translators: Key label
+    ("space", N_("Space")),
+    // translators: Key label
+    ("tab", N_("Tab")),
+    // translators: Key label
+    ("escape", N_("Esc")),
+];
+
+/// The views which the user hears about, and their English names
+const VIEWS: &[(&str, &str)] = &[
+    // translators: Name of the view, spoken when switching to it
+    ("base", N_("Small letters")),
+    // translators: Name of the view, spoken when switching to it
+    ("upper", N_("Capital letters")),
+    // translators: Spoken when Shift makes only the next letter capital
+    ("upper_once", N_("Capital letter")),
+    // translators: Name of the view, spoken when switching to it
+    ("numbers", N_("Numbers")),
+    // translators: Name of the view, spoken when switching to it
+    ("symbols", N_("Symbols")),
+];
+
+/// The English text for the name used in a layout
+pub fn get_text(name: &str) -> Option<&'static str> {
+    NAMED.iter()
+        .find(|(named, _)| *named == name)
+        .map(|(_, text)| *text)
+}
+
+/// The name of the view in the current language.
+/// Views without a name of their own keep the one from the layout.
+pub fn get_view_name(view: &str) -> String {
+    VIEWS.iter()
+        .find(|(named, _)| *named == view)
+        .map(|(_, text)| gettext(text))
+        .unwrap_or_else(|| view.into())
+}
+
+/// Without a translation, gives back the text.
+fn gettext(msgid: &str) -> String {
+    let msgid = match CString::new(msgid) {
+        Ok(msgid) => msgid,
+        Err(_) => return msgid.into(),
+    };
+    unsafe {
+        let translated = glib_sys::g_dgettext(
+            DOMAIN.as_ptr() as *const c_char,
+            msgid.as_ptr(),
+        );
+        CStr::from_ptr(translated).to_string_lossy().into_owned()
+    }
+}
+
+/// Returns None if the label stays as it is.
+fn translate_label<F: Fn(&str) -> String>(button: &Button, translate: F) -> Option<Label> {
+    let name = button.label_name.as_ref()?.to_str().ok()?;
+    let text = get_text(name)?;
+    let translated = translate(text);
+    if translated == text {
+        return None;
+    }
+    Interned::new(&translated).ok().map(Label::Text)
+}
+
+/// Puts in the texts for the current language.
+/// Views not built yet get translated when they are.
+pub fn translate(layout: &mut Layout) {
+    for (_offset, view) in layout.views.values_mut() {
+        view.modify(|view| view.replace_labels(|button| translate_label(button, gettext)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::layout_dsl::key;
+
+    #[test]
+    fn translated() {
+        let label = |text: &str| Label::Text(Interned::new(text).unwrap());
+        let button = |text: &str, name: Option<&str>| {
+            let view = view![row![key(text)]].into_view();
+            Button {
+                label_name: name.map(|name| Interned::new(name).unwrap()),
+                ..(*view.get_rows()[0].1.get_buttons()[0].1).clone()
+            }
+        };
+        let german = |text: &str| match text {
+            "Enter" => "Eingabe".to_string(),
+            other => other.into(),
+        };
+        assert_eq!(
+            translate_label(&button("Enter", Some("enter")), german),
+            Some(label("Eingabe")),
+        );
+        assert_eq!(translate_label(&button("ABC", Some("letters")), german), None);
+        // Only named labels get looked up, not the ones spelled out.
+        assert_eq!(translate_label(&button("Enter", None), german), None);
+        assert_eq!(get_text("numbers_symbols"), Some("?123"));
+    }
+
+    #[test]
+    fn view_names() {
+        // No translations get loaded in tests.
+        assert_eq!(get_view_name("numbers"), "Numbers");
+        assert_eq!(get_view_name("accents"), "accents");
+    }
+}
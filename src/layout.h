@@ -16,6 +16,7 @@
 enum squeek_arrangement_kind {
     ARRANGEMENT_KIND_BASE = 0,
     ARRANGEMENT_KIND_WIDE = 1,
+    ARRANGEMENT_KIND_COMPACT = 2,
 };
 
 struct squeek_layout_state {
@@ -30,12 +31,14 @@ struct squeek_layout;
 
 
 struct transformation squeek_layout_calculate_transformation(
-        const struct squeek_layout *layout,
+        struct squeek_layout *layout,
         double allocation_width, double allocation_size);
 
 struct squeek_layout *squeek_load_layout(const char *name, uint32_t type, uint32_t variant_type, const char *overlay_name);
 enum squeek_arrangement_kind squeek_layout_get_kind(const struct squeek_layout *);
 uint32_t squeek_layout_get_purpose(const struct squeek_layout *);
+uint32_t squeek_layout_is_floating(const struct squeek_layout *);
+double squeek_layout_get_opacity(const struct squeek_layout *);
 void squeek_layout_free(struct squeek_layout*);
 
 void squeek_layout_release(struct squeek_layout *layout,
@@ -45,14 +48,53 @@ void squeek_layout_release(struct squeek_layout *layout,
                            struct squeek_popover *popover,
                            struct squeek_state_manager *state,
                            EekGtkKeyboard *ui_keyboard);
+void squeek_layout_report_rows(const struct squeek_layout *layout,
+                               struct transformation widget_to_layout,
+                               struct squeek_state_manager *state);
+void squeek_layout_set_input_region(const struct squeek_layout *layout,
+                                    struct transformation widget_to_layout,
+                                    EekGtkKeyboard *ui_keyboard);
+void squeek_layout_move_touch(struct squeek_layout *layout,
+                              struct submission *submission,
+                              const void *touch,
+                              double x_widget, double y_widget,
+                              struct transformation widget_to_layout,
+                              uint32_t timestamp,
+                              struct squeek_popover *popover,
+                              struct squeek_state_manager *state,
+                              EekGtkKeyboard *ui_keyboard);
+void squeek_layout_release_touch(struct squeek_layout *layout,
+                                 struct submission *submission,
+                                 const void *touch,
+                                 struct transformation widget_to_layout,
+                                 uint32_t timestamp,
+                                 struct squeek_popover *popover,
+                                 struct squeek_state_manager *state,
+                                 EekGtkKeyboard *ui_keyboard);
 void squeek_layout_release_all_only(struct squeek_layout *layout,
                                     struct submission *submission,
                                     uint32_t timestamp);
+void squeek_layout_hand_over(struct squeek_layout *old_layout,
+                             struct squeek_layout *new_layout,
+                             struct submission *submission,
+                             uint32_t timestamp);
 void squeek_layout_depress(struct squeek_layout *layout,
                            struct submission *submission,
                            double x_widget, double y_widget,
                            struct transformation widget_to_layout,
-                           uint32_t timestamp, EekGtkKeyboard *ui_keyboard);
+                           uint32_t timestamp,
+                           struct squeek_popover *popover,
+                           struct squeek_state_manager *state,
+                           EekGtkKeyboard *ui_keyboard);
+uint32_t squeek_layout_begin_chord(struct squeek_layout *layout,
+                                   struct submission *submission,
+                                   uint32_t timestamp);
+void squeek_layout_end_chord(struct squeek_layout *layout,
+                             struct submission *submission,
+                             uint32_t timestamp, EekGtkKeyboard *ui_keyboard);
+void squeek_layout_set_view(struct squeek_layout *layout, const char *view,
+                            struct squeek_state_manager *state);
+uint32_t squeek_layout_is_animating(const struct squeek_layout *layout);
 void squeek_layout_drag(struct squeek_layout *layout,
                         struct submission *submission,
                         double x_widget, double y_widget,
@@ -22,25 +22,106 @@ use std::cmp;
 use std::collections::{ HashMap, HashSet };
 use std::ffi::CString;
 use std::fmt;
-use std::rc::Rc;
+use std::mem;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
 use std::vec::Vec;
 
-use crate::action::Action;
+use crate::action::{ Action, Modifier };
 use crate::actors;
+use crate::actors::feedback;
+use crate::announce;
+use crate::automation;
+use crate::braille;
+use crate::calculator;
+use crate::clock::{ Clock, System };
 use crate::drawing;
+use crate::erase;
+use crate::flash;
 use crate::float_ord::FloatOrd;
-use crate::keyboard::KeyState;
+use crate::gestures;
+use crate::handwriting;
+use crate::ibus;
+use crate::keyboard::{ KeyStateId, KeyStore };
+use crate::kiosk;
 use crate::logging;
+use crate::long_press;
+use crate::morse;
 use crate::popover;
+use crate::portals;
+use crate::rearrange;
 use crate::receiver;
+use crate::repeat;
+use crate::send_key;
+use crate::send_key::ReturnKey;
+use crate::sound::{ self, Sound };
+use crate::state;
+use crate::statistics;
+use crate::steno;
+use crate::stuck_keys;
 use crate::submission::{ Submission, SubmitData, Timestamp };
-use crate::util::find_max_double;
+use crate::t9;
+use crate::template;
+use crate::text_field;
+use crate::util::{ find_max_double, Interned };
+use crate::voice;
 
 use crate::imservice::ContentPurpose;
 
 // Traits
-use std::borrow::Borrow;
 use crate::logging::Warn;
+use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+use serde::ser;
+use std::convert::TryFrom;
+
+/// Whether presses trigger haptic and sound feedback.
+/// Follows the user's settings.
+static FEEDBACK: AtomicBool = AtomicBool::new(true);
+
+pub fn set_feedback(enabled: bool) {
+    FEEDBACK.store(enabled, Ordering::Relaxed);
+}
+
+/// Seconds left of the cleaning mode, during which touches are ignored.
+/// 0 when not cleaning.
+static CLEANING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_cleaning(seconds_left: Option<u64>) {
+    CLEANING.store(seconds_left.unwrap_or(0) as usize, Ordering::Relaxed);
+}
+
+pub fn get_cleaning() -> Option<u64> {
+    match CLEANING.load(Ordering::Relaxed) {
+        0 => None,
+        seconds => Some(seconds as u64),
+    }
+}
+
+/// Buzzes and plays the sound, unless the user turned that off.
+pub fn emit_feedback(ui_keyboard: c::EekGtkKeyboard, sound: Sound) {
+    if FEEDBACK.load(Ordering::Relaxed) {
+        feedback::trigger(feedback::Event::Press);
+        sound::play(ui_keyboard, sound);
+    }
+}
+
+/// When the long press takes over the key.
+/// The feedbackd event names have none for holding,
+/// so it buzzes like another press.
+pub fn emit_hold_feedback(ui_keyboard: c::EekGtkKeyboard) {
+    if FEEDBACK.load(Ordering::Relaxed) {
+        feedback::trigger(feedback::Event::Press);
+        sound::play(ui_keyboard, Sound::Key);
+    }
+}
+
+/// When a press does nothing, though the key looks like it would.
+pub fn emit_error_feedback() {
+    flash::error(System.now());
+    if FEEDBACK.load(Ordering::Relaxed) {
+        feedback::trigger(feedback::Event::Error);
+    }
+}
 
 /// Gathers stuff defined in C or called by C
 pub mod c {
@@ -51,7 +132,8 @@ pub mod c {
 
     use gtk_sys;
     use std::ops::{ Add, Sub };
-    use std::os::raw::c_void;
+    use std::os::raw::{ c_char, c_void };
+    use std::ptr;
     
     use crate::util::CloneOwned;
     
@@ -62,14 +144,22 @@ pub mod c {
 
     extern "C" {
         #[allow(improper_ctypes)]
-        pub fn eek_gtk_keyboard_emit_feedback(
+        pub fn eek_gtk_keyboard_set_view(
+            keyboard: EekGtkKeyboard,
+            view: *const c_char,
+        );
+        /// Without `bounds`, the whole widget takes touches.
+        #[allow(improper_ctypes)]
+        pub fn eek_gtk_keyboard_set_input_region(
             keyboard: EekGtkKeyboard,
+            bounds: *const Bounds,
+            count: u32,
         );
     }
 
     /// Defined in eek-types.h
     #[repr(C)]
-    #[derive(Clone, Debug, PartialEq)]
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
     pub struct Point {
         pub x: f64,
         pub y: f64,
@@ -138,7 +228,7 @@ pub mod c {
                 scale_y: self.scale_y * next.scale_y,
             }
         }
-        fn forward(&self, p: Point) -> Point {
+        pub fn forward(&self, p: Point) -> Point {
             Point {
                 x: (p.x - self.origin_x) / self.scale_x,
                 y: (p.y - self.origin_y) / self.scale_y,
@@ -177,15 +267,20 @@ pub mod c {
     #[no_mangle]
     pub extern "C"
     fn squeek_layout_calculate_transformation(
-        layout: *const Layout,
+        layout: *mut Layout,
         allocation_width: f64,
         allocation_height: f64,
     ) -> Transformation {
-        let layout = unsafe { &*layout };
-        layout.calculate_transformation(Size {
+        let layout = unsafe { &mut *layout };
+        let available = Size {
             width: allocation_width,
             height: allocation_height,
-        })
+        };
+        match layout.floating {
+            Some(_) => layout.fit_current_view(&available),
+            None => layout.grow_current_view(&available),
+        }
+        layout.calculate_transformation(available)
     }
 
     #[no_mangle]
@@ -202,6 +297,23 @@ pub mod c {
         layout.purpose.clone() as u32
     }
 
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_is_floating(layout: *const Layout) -> u32 {
+        let layout = unsafe { &*layout };
+        layout.floating.is_some() as u32
+    }
+
+    /// Of the buttons, in floating layouts
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_layout_get_opacity(layout: *const Layout) -> f64 {
+        let layout = unsafe { &*layout };
+        layout.floating.as_ref()
+            .map(|floating| floating.opacity)
+            .unwrap_or(1.0)
+    }
+
     #[no_mangle]
     pub extern "C"
     fn squeek_layout_free(layout: *mut Layout) {
@@ -226,29 +338,214 @@ pub mod c {
         ) {
             let time = Timestamp(time);
             let layout = unsafe { &mut *layout };
+            if automation::finish_stop() {
+                return;
+            }
+            if handwriting::is_drawing() {
+                handwriting::end_stroke();
+            }
+            if rearrange::is_editing() {
+                let target = layout.last_touch.clone()
+                    .and_then(|point| layout.find_button_by_position(point))
+                    .and_then(|place| place.button.name.to_str().ok().map(String::from));
+                rearrange::release(layout, target.as_ref().map(String::as_str));
+                drawing::queue_redraw(ui_keyboard);
+                return;
+            }
             let submission = submission.clone_ref();
             let mut submission = submission.borrow_mut();
             let app_state = app_state.clone_owned();
             let popover_state = popover.clone_owned();
+            // Lifting several fingers, or taking back a swipe's key,
+            // reaches the application as one change.
+            submission.begin_frame();
             
             let ui_backend = UIBackend {
                 widget_to_layout,
                 keyboard: ui_keyboard,
             };
 
-            // The list must be copied,
-            // because it will be mutated in the loop
-            for key in layout.pressed_keys.clone() {
-                let key: &Rc<RefCell<KeyState>> = key.borrow();
-                seat::handle_release_key(
+            let size = layout.get_current_view_position().1.get_size();
+            let swipe = match (layout.touch.take(), &layout.last_touch) {
+                (Some(touch), Some(end)) => touch.finish(end, &size),
+                _ => None,
+            };
+            if swipe.is_some() {
+                // The key where the swipe started was not meant to type.
+                let typed = layout.pressed_keys.iter()
+                    .filter(|key_id| submission.is_committed_as_text(key_id))
+                    .count();
+                for _ in 0..typed {
+                    submission.delete_before_cursor(erase::Unit::Character, Vec::new(), time);
+                }
+            }
+
+            seat::release_keys_except(
+                layout,
+                &mut submission,
+                Some(&ui_backend),
+                time,
+                Some((&popover_state, &app_state)),
+                None,
+            );
+            seat::end_peek(layout, time, Some(&app_state));
+            if let Some(command) = swipe {
+                seat::run_gesture(
                     layout,
                     &mut submission,
                     Some(&ui_backend),
                     time,
-                    Some((&popover_state, app_state.clone())),
-                    key,
+                    Some((&popover_state, &app_state)),
+                    command,
                 );
             }
+            submission.end_frame();
+            drawing::queue_redraw(ui_keyboard);
+        }
+
+        thread_local! {
+            /// Avoids flooding the state with the same rows.
+            static REPORTED_ROWS: RefCell<Vec<Bounds>> = RefCell::new(Vec::new());
+        }
+
+        /// Tells the state where the rows of the current view are in the widget.
+        /// Called whenever the widget geometry gets calculated.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_report_rows(
+            layout: *const Layout,
+            widget_to_layout: Transformation,
+            app_state: receiver::c::State,
+        ) {
+            let layout = unsafe { &*layout };
+            let (view_offset, view) = layout.get_current_view_position();
+            let rows: Vec<Bounds> = view.get_rows().iter()
+                .map(|(row_offset, row)| {
+                    let size = row.get_size();
+                    widget_to_layout.reverse_bounds(Bounds {
+                        x: view_offset.x + row_offset.x,
+                        y: view_offset.y + row_offset.y,
+                        width: size.width,
+                        height: size.height,
+                    })
+                })
+                .collect();
+            let changed = REPORTED_ROWS.with(|reported| {
+                let mut reported = reported.borrow_mut();
+                let changed = *reported != rows;
+                *reported = rows.clone();
+                changed
+            });
+            if changed {
+                app_state.clone_owned()
+                    .send(state::Event::RowBounds(rows))
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            }
+        }
+
+        /// Lets touches outside of the buttons of a floating layout
+        /// through to the application below.
+        /// Called whenever the widget geometry gets calculated.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_set_input_region(
+            layout: *const Layout,
+            widget_to_layout: Transformation,
+            ui_keyboard: EekGtkKeyboard,
+        ) {
+            let layout = unsafe { &*layout };
+            if layout.floating.is_none() {
+                unsafe { eek_gtk_keyboard_set_input_region(ui_keyboard, ptr::null(), 0) };
+                return;
+            }
+            let mut bounds = Vec::new();
+            layout.foreach_visible_button(|offset, button| {
+                bounds.push(widget_to_layout.reverse_bounds(Bounds {
+                    x: offset.x,
+                    y: offset.y,
+                    width: button.size.width,
+                    height: button.size.height,
+                }));
+            });
+            unsafe {
+                eek_gtk_keyboard_set_input_region(
+                    ui_keyboard,
+                    bounds.as_ptr(),
+                    bounds.len() as u32,
+                )
+            };
+        }
+
+        /// Moves a touch of a floating layout,
+        /// pressing the key it lands on.
+        /// Called for the beginning of the touch too.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_move_touch(
+            layout: *mut Layout,
+            submission: CSubmission,
+            touch: *const c_void,
+            x_widget: f64, y_widget: f64,
+            widget_to_layout: Transformation,
+            time: u32,
+            popover: actors::popover::c::Actor,
+            app_state: receiver::c::State,
+            ui_keyboard: EekGtkKeyboard,
+        ) {
+            let layout = unsafe { &mut *layout };
+            let submission = submission.clone_ref();
+            let mut submission = submission.borrow_mut();
+            let app_state = app_state.clone_owned();
+            let popover_state = popover.clone_owned();
+            let point = widget_to_layout.forward(
+                Point { x: x_widget, y: y_widget }
+            );
+            let ui_backend = UIBackend {
+                widget_to_layout,
+                keyboard: ui_keyboard,
+            };
+            seat::move_touch(
+                layout,
+                &mut submission,
+                &ui_backend,
+                Timestamp(time),
+                (&popover_state, &app_state),
+                touch as usize,
+                point,
+            );
+            drawing::queue_redraw(ui_keyboard);
+        }
+
+        /// Ends a touch of a floating layout.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_release_touch(
+            layout: *mut Layout,
+            submission: CSubmission,
+            touch: *const c_void,
+            widget_to_layout: Transformation,
+            time: u32,
+            popover: actors::popover::c::Actor,
+            app_state: receiver::c::State,
+            ui_keyboard: EekGtkKeyboard,
+        ) {
+            let layout = unsafe { &mut *layout };
+            let submission = submission.clone_ref();
+            let mut submission = submission.borrow_mut();
+            let app_state = app_state.clone_owned();
+            let popover_state = popover.clone_owned();
+            let ui_backend = UIBackend {
+                widget_to_layout,
+                keyboard: ui_keyboard,
+            };
+            seat::release_touch(
+                layout,
+                &mut submission,
+                Some(&ui_backend),
+                Timestamp(time),
+                Some((&popover_state, &app_state)),
+                touch as usize,
+            );
             drawing::queue_redraw(ui_keyboard);
         }
 
@@ -263,19 +560,68 @@ pub mod c {
             let layout = unsafe { &mut *layout };
             let submission = submission.clone_ref();
             let mut submission = submission.borrow_mut();
-            // The list must be copied,
-            // because it will be mutated in the loop
-            for key in layout.pressed_keys.clone() {
-                let key: &Rc<RefCell<KeyState>> = key.borrow();
-                seat::handle_release_key(
-                    layout,
-                    &mut submission,
-                    None, // don't update UI
-                    Timestamp(time),
-                    None, // don't switch layouts
-                    &mut key.clone(),
-                );
-            }
+            submission.begin_frame();
+            seat::release_keys_except(
+                layout,
+                &mut submission,
+                None, // don't update UI
+                Timestamp(time),
+                None, // don't switch layouts
+                None,
+            );
+            layout.held.clear();
+            seat::end_chord(layout, &mut submission, Timestamp(time));
+            submission.end_frame();
+            // Nothing to report the view change to
+            layout.peek = None;
+        }
+
+        /// Keeps a held modifier key down for the touch beginning now.
+        /// Returns 1 if there was one.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_begin_chord(
+            layout: *mut Layout,
+            submission: CSubmission,
+            time: u32,
+        ) -> u32 {
+            let layout = unsafe { &mut *layout };
+            let submission = submission.clone_ref();
+            let mut submission = submission.borrow_mut();
+            seat::begin_chord(layout, &mut submission, Timestamp(time)) as u32
+        }
+
+        /// Called when the touch holding the modifier ends.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_end_chord(
+            layout: *mut Layout,
+            submission: CSubmission,
+            time: u32,
+            ui_keyboard: EekGtkKeyboard,
+        ) {
+            let layout = unsafe { &mut *layout };
+            let submission = submission.clone_ref();
+            let mut submission = submission.borrow_mut();
+            seat::end_chord(layout, &mut submission, Timestamp(time));
+            drawing::queue_redraw(ui_keyboard);
+        }
+
+        /// Prepares `new` to replace `old`.
+        /// Must be called before the submission switches to the new keymaps.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_hand_over(
+            old: *mut Layout,
+            new: *mut Layout,
+            submission: CSubmission,
+            time: u32,
+        ) {
+            let old = unsafe { &mut *old };
+            let new = unsafe { &mut *new };
+            let submission = submission.clone_ref();
+            let mut submission = submission.borrow_mut();
+            seat::hand_over(old, new, &mut submission, Timestamp(time));
         }
 
         #[no_mangle]
@@ -286,33 +632,241 @@ pub mod c {
             x_widget: f64, y_widget: f64,
             widget_to_layout: Transformation,
             time: u32,
+            popover: actors::popover::c::Actor,
+            app_state: receiver::c::State,
             ui_keyboard: EekGtkKeyboard,
         ) {
+            if get_cleaning().is_some() {
+                return;
+            }
+            // The emergency stop for typing from elsewhere
+            if automation::is_typing(Instant::now()) {
+                automation::stop(Instant::now());
+                drawing::queue_redraw(ui_keyboard);
+                return;
+            }
             let layout = unsafe { &mut *layout };
-            let submission = submission.clone_ref();
-            let mut submission = submission.borrow_mut();
+            let app_state = app_state.clone_owned();
+            let submission_rc = submission.clone_ref();
+            let mut submission = submission_rc.borrow_mut();
             let point = widget_to_layout.forward(
                 Point { x: x_widget, y: y_widget }
             );
+            layout.last_touch = Some(point.clone());
+
+            // Buttons get moved instead of pressed.
+            if rearrange::is_editing() {
+                let view_offset = layout.get_current_view_position().0;
+                if let Some(place) = layout.find_button_by_position(point.clone()) {
+                    let offset = view_offset + place.offset;
+                    if let Ok(name) = place.button.name.to_str() {
+                        rearrange::press(
+                            name,
+                            Bounds {
+                                x: offset.x,
+                                y: offset.y,
+                                width: place.button.size.width,
+                                height: place.button.size.height,
+                            },
+                            point,
+                        );
+                    }
+                }
+                drawing::queue_redraw(ui_keyboard);
+                return;
+            }
 
-            let state = layout.find_button_by_position(point)
-                .map(|place| place.button.state.clone());
+            let place = {
+                let view_offset = layout.get_current_view_position().0;
+                layout.find_button_by_position(point.clone())
+                    .map(|place| {
+                        let offset = view_offset + place.offset;
+                        (
+                            place.button.key,
+                            Bounds {
+                                x: offset.x,
+                                y: offset.y,
+                                width: place.button.size.width,
+                                height: place.button.size.height,
+                            },
+                        )
+                    })
+            };
 
-            if let Some(state) = state {
+            if let Some((key_id, bounds)) = place {
+                let action = layout.keys.get(key_id).action.clone();
+                let repeats = layout.keys.get(key_id).repeats();
+                let long_press = layout.keys.get(key_id).behavior.long_press.clone();
+                let hold_for_preferences = layout.keys.get(key_id)
+                    .behavior.hold_for_preferences;
+                match action {
+                    Action::Handwriting | Action::VoiceInput if layout.sensitive => {
+                        log_print!(
+                            logging::Level::Info,
+                            "Recognizers are off for sensitive text fields",
+                        );
+                        emit_error_feedback();
+                        return;
+                    },
+                    _ => {},
+                }
+                // The canvas takes the touch away from buttons
+                if let Action::Handwriting = action {
+                    handwriting::begin_stroke(
+                        bounds,
+                        point,
+                        submission_rc.clone(),
+                        ui_keyboard,
+                    );
+                    return;
+                }
+                // A second finger is no swipe
+                layout.touch = match layout.pressed_keys.is_empty() {
+                    true => gestures::Touch::start(point.clone(), Instant::now()),
+                    false => None,
+                };
+                // The press may switch the view
+                let view = layout.current_view.clone();
                 seat::handle_press_key(
                     layout,
                     &mut submission,
                     Timestamp(time),
-                    &state,
+                    Some(&app_state),
+                    key_id,
                 );
+                let widget_bounds = widget_to_layout.reverse_bounds(bounds.clone());
+                layout.ripples.push(Ripple {
+                    center: point,
+                    bounds,
+                    view,
+                    started: System.now(),
+                });
                 // maybe TODO: draw on the display buffer here
                 drawing::queue_redraw(ui_keyboard);
-                unsafe {
-                    eek_gtk_keyboard_emit_feedback(ui_keyboard);
+                emit_feedback(ui_keyboard, Sound::for_action(&action));
+
+                match action {
+                    Action::Submit { .. }
+                        | Action::SubmitTemplate(_)
+                        | Action::SendOrNewline
+                        | Action::Erase
+                        | Action::Shortcut { .. }
+                    => {
+                        app_state.send(state::Event::KeyPressed)
+                            .or_print(logging::Problem::Bug, "Can't send to state");
+                    },
+                    _ => {},
+                }
+
+                // Shortcuts get released right away.
+                match action {
+                    Action::Submit { .. } | Action::Erase => stuck_keys::watch(
+                        submission_rc.clone(),
+                        key_id,
+                        Timestamp(time),
+                    ),
+                    _ => {},
+                }
+
+                match action {
+                    Action::Submit { .. } | Action::Erase if !repeats => {
+                        submission.stop_app_repeat(key_id, Timestamp(time));
+                    },
+                    _ => {},
+                }
+
+                match action {
+                    // The popover would let the user change the layout
+                    Action::Submit { text: Some(text), .. }
+                        if hold_for_preferences && !kiosk::is_enabled()
+                    => long_press::start_preferences(
+                        submission_rc.clone(),
+                        key_id,
+                        ui_keyboard,
+                        widget_bounds,
+                        text,
+                        popover.clone_owned(),
+                        app_state.clone(),
+                        layout.menu.clone(),
+                        Timestamp(time),
+                    ),
+                    Action::Submit { text: Some(text), .. } if !long_press.is_empty() => {
+                        long_press::start(
+                            submission_rc.clone(),
+                            key_id,
+                            ui_keyboard,
+                            widget_bounds,
+                            text.clone(),
+                            long_press,
+                            Timestamp(time),
+                        );
+                        if repeats {
+                            repeat::start(submission_rc.clone(), key_id, text);
+                        }
+                    },
+                    Action::Erase if repeats => erase::start_repeat(
+                        submission_rc.clone(),
+                        key_id,
+                        ui_keyboard,
+                        Timestamp(time),
+                    ),
+                    Action::SendOrNewline => send_key::start_hold(
+                        submission_rc.clone(),
+                        ui_keyboard,
+                    ),
+                    Action::VoiceInput => voice::toggle(submission_rc.clone(), ui_keyboard),
+                    Action::Morse(signal) => morse::signal(
+                        submission_rc.clone(),
+                        ui_keyboard,
+                        signal,
+                    ),
+                    Action::Submit { text: Some(text), .. } if repeats => repeat::start(
+                        submission_rc.clone(),
+                        key_id,
+                        text,
+                    ),
+                    _ => {},
                 }
             };
         }
 
+        /// Switches the view on request from outside of the keyboard,
+        /// like from the popover.
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_set_view(
+            layout: *mut Layout,
+            view: *const c_char,
+            app_state: receiver::c::State,
+        ) {
+            let layout = unsafe { &mut *layout };
+            let view = match crate::util::c::as_str(&view) {
+                Ok(Some(view)) => view,
+                _ => {
+                    log_print!(logging::Level::Bug, "Bad view name");
+                    return;
+                },
+            };
+            if layout.current_view != view {
+                try_set_view(layout, view);
+                layout.view_latched = LatchedState::Not;
+                app_state.clone_owned()
+                    .send(state::Event::ViewChanged(layout.current_view.clone()))
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            }
+        }
+
+        /// Tells whether there are animations to draw frames for
+        #[no_mangle]
+        pub extern "C"
+        fn squeek_layout_is_animating(layout: *const Layout) -> u32 {
+            let layout = unsafe { &*layout };
+            let now = System.now();
+            layout.ripples.iter()
+                .any(|ripple| ripple.get_progress(now).is_some())
+                as u32
+        }
+
         // FIXME: this will work funny
         // when 2 touch points are on buttons and moving one after another
         // Solution is to have separate pressed lists for each point
@@ -329,9 +883,20 @@ pub mod c {
             ui_keyboard: EekGtkKeyboard,
         ) {
             let time = Timestamp(time);
+            // Keys already pressed get released normally
+            if get_cleaning().is_some() || automation::is_stopping() {
+                return;
+            }
             let layout = unsafe { &mut *layout };
-            let submission = submission.clone_ref();
-            let mut submission = submission.borrow_mut();
+            if handwriting::is_drawing() {
+                let point = widget_to_layout.forward(
+                    Point { x: x_widget, y: y_widget }
+                );
+                handwriting::add_point(point);
+                return;
+            }
+            let submission_rc = submission.clone_ref();
+            let mut submission = submission_rc.borrow_mut();
             // We only need to query state here, not update.
             // A copy is enough.
             let popover_state = popover.clone_owned();
@@ -343,59 +908,76 @@ pub mod c {
             let point = ui_backend.widget_to_layout.forward(
                 Point { x: x_widget, y: y_widget }
             );
+            layout.last_touch = Some(point.clone());
 
-            let pressed = layout.pressed_keys.clone();
-            let button_info = {
-                let place = layout.find_button_by_position(point);
-                place.map(|place| {(
-                    place.button.state.clone(),
-                    place.button.clone(),
-                    place.offset,
-                )})
-            };
+            if rearrange::is_editing() {
+                rearrange::drag(point);
+                drawing::queue_redraw(ui_keyboard);
+                return;
+            }
 
-            if let Some((state, _button, _view_position)) = button_info {
-                let mut found = false;
-                for wrapped_key in pressed {
-                    let key: &Rc<RefCell<KeyState>> = wrapped_key.borrow();
-                    if Rc::ptr_eq(&state, &wrapped_key.0) {
-                        found = true;
-                    } else {
-                        seat::handle_release_key(
-                            layout,
-                            &mut submission,
-                            Some(&ui_backend),
-                            time,
-                            Some((&popover_state, app_state.clone())),
-                            key,
-                        );
-                    }
+            let size = layout.get_current_view_position().1.get_size();
+            if let Some(touch) = &mut layout.touch {
+                // Keys under a swipe don't get pressed.
+                if touch.update(&point, Instant::now(), &size) {
+                    return;
+                }
+            }
+
+            let button_key = layout.find_button_by_position(point)
+                .map(|place| place.button.key);
+
+            // The view switched on press,
+            // and jitter would land on the key switching back
+            let peek_held = layout.peek.as_ref()
+                .map(|peek| layout.pressed_keys.contains(&peek.key))
+                .unwrap_or(false);
+            if let Some(key_id) = button_key {
+                if peek_held && layout.keys.get(key_id).action.switches_view() {
+                    return;
                 }
+            }
+
+            // Sliding onto a key releases the one before in the same change.
+            submission.begin_frame();
+            if let Some(key_id) = button_key {
+                let found = layout.pressed_keys.contains(&key_id);
+                seat::release_keys_except(
+                    layout,
+                    &mut submission,
+                    Some(&ui_backend),
+                    time,
+                    Some((&popover_state, &app_state)),
+                    Some(key_id),
+                );
                 if !found {
                     seat::handle_press_key(
                         layout,
                         &mut submission,
                         time,
-                        &state,
+                        Some(&app_state),
+                        key_id,
                     );
-                    // maybe TODO: draw on the display buffer here
-                    unsafe {
-                        eek_gtk_keyboard_emit_feedback(ui_keyboard);
+                    let action = &layout.keys.get(key_id).action;
+                    match action {
+                        Action::Submit { .. } | Action::Erase
+                            => stuck_keys::watch(submission_rc.clone(), key_id, time),
+                        _ => {},
                     }
+                    // maybe TODO: draw on the display buffer here
+                    emit_feedback(ui_keyboard, Sound::for_action(action));
                 }
             } else {
-                for wrapped_key in pressed {
-                    let key: &Rc<RefCell<KeyState>> = wrapped_key.borrow();
-                    seat::handle_release_key(
-                        layout,
-                        &mut submission,
-                        Some(&ui_backend),
-                        time,
-                        Some((&popover_state, app_state.clone())),
-                        key,
-                    );
-                }
+                seat::release_keys_except(
+                    layout,
+                    &mut submission,
+                    Some(&ui_backend),
+                    time,
+                    Some((&popover_state, &app_state)),
+                    None,
+                );
             }
+            submission.end_frame();
             drawing::queue_redraw(ui_keyboard);
         }
 
@@ -429,32 +1011,40 @@ pub struct ButtonPlace<'a> {
     offset: c::Point,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Size {
     pub width: f64,
     pub height: f64,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Label {
     /// Text used to display the symbol
-    Text(CString),
+    Text(Interned),
     /// Icon name used to render the symbol
-    IconName(CString),
+    IconName(Interned),
 }
 
 /// The graphical representation of a button
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Button {
     /// ID string, e.g. for CSS
-    pub name: CString,
+    pub name: Interned,
     /// Label to display to the user
     pub label: Label,
+    /// The name of a label known to Squeekboard,
+    /// whose text is in `label` in the current language
+    pub label_name: Option<Interned>,
     pub size: Size,
     /// The name of the visual class applied
-    pub outline_name: CString,
-    /// current state, shared with other buttons
-    pub state: Rc<RefCell<KeyState>>,
+    pub outline_name: Interned,
+    /// Corner radii from the outline, clockwise from the top left.
+    /// Without them, the theme decides.
+    pub corners: Option<[f64; 4]>,
+    /// Share of the width the row gains when stretched
+    pub growth: f64,
+    /// Current state in the layout's `KeyStore`, shared with other buttons
+    pub key: KeyStateId,
 }
 
 impl Button {
@@ -467,7 +1057,7 @@ impl Button {
 }
 
 /// The graphical representation of a row of buttons
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Row {
     /// Buttons together with their offset from the left relative to the row.
     /// ie. the first button always start at 0.
@@ -475,6 +1065,8 @@ pub struct Row {
 
     /// Total size of the row
     size: Size,
+    /// Width before growing
+    natural_width: f64,
 }
 
 impl Row {
@@ -497,17 +1089,61 @@ impl Row {
             |(_offset, button)| button.size.height,
         );
 
-        Row { buttons, size: Size { width, height } }
+        Row { buttons, size: Size { width, height }, natural_width: width }
     }
 
     pub fn get_size(&self) -> Size {
         self.size.clone()
     }
 
+    fn get_growth(&self) -> f64 {
+        self.buttons.iter().map(|(_offset, button)| button.growth).sum()
+    }
+
+    /// Spreads the width beyond the natural one among the buttons,
+    /// according to their growth.
+    /// A row without growth stays as it is, and no row gets narrower
+    /// than the natural width.
+    fn grow_to(&mut self, width: f64) {
+        let growth = self.get_growth();
+        if growth <= 0.0 {
+            return;
+        }
+        let added = f64::max(width - self.natural_width, 0.0)
+            - (self.size.width - self.natural_width);
+        let mut offset = 0.0;
+        for (x_offset, button) in self.buttons.iter_mut() {
+            button.size.width += added * button.growth / growth;
+            *x_offset = offset;
+            offset += button.size.width;
+        }
+        self.size.width = offset;
+    }
+
     pub fn get_buttons(&self) -> &Vec<(f64, Box<Button>)> {
         &self.buttons
     }
 
+    /// Places the buttons next to each other again,
+    /// after some changed places or went away.
+    fn reflow(&mut self) {
+        let buttons = mem::replace(&mut self.buttons, Vec::new());
+        let mut offset = 0.0;
+        let buttons = buttons.into_iter()
+            .map(|(_offset, button)| {
+                let placed = (offset, button);
+                offset += placed.1.size.width;
+                placed
+            })
+            .collect();
+        *self = Row::new(buttons);
+    }
+
+    fn find_button_by_name(&self, name: &str) -> Option<usize> {
+        self.buttons.iter()
+            .position(|(_offset, button)| button.name.to_str() == Ok(name))
+    }
+
     /// Finds the first button that covers the specified point
     /// relative to row's position's origin
     fn find_button_by_position(&self, x: f64) -> &(f64, Box<Button>)
@@ -533,13 +1169,56 @@ pub struct Spacing {
     pub button: f64,
 }
 
-#[derive(Clone)]
-pub struct View {
-    /// Rows together with their offsets from the top left
+/// Corner of the screen which a cluster of buttons keeps to
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    fn is_left(&self) -> bool {
+        match self {
+            Corner::TopLeft | Corner::BottomLeft => true,
+            Corner::TopRight | Corner::BottomRight => false,
+        }
+    }
+
+    fn is_top(&self) -> bool {
+        match self {
+            Corner::TopLeft | Corner::TopRight => true,
+            Corner::BottomLeft | Corner::BottomRight => false,
+        }
+    }
+}
+
+/// Where a row of a floating view goes.
+/// Floating views have a row for every button.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Anchor {
+    pub corner: Corner,
+    /// Distance of the cluster from both edges at the corner
+    pub margin: f64,
+    /// Size of the whole cluster the row belongs to
+    pub cluster: Size,
+    /// Offset of the row within the cluster
+    pub offset: c::Point,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct View {
+    /// Rows together with their offsets from the top left
     rows: Vec<(c::Point, Row)>,
 
     /// Total size of the view
     size: Size,
+    /// Offsets from the left edge of the first rows.
+    /// Rows past the end get centered.
+    stagger: Vec<f64>,
+    /// Only in floating views, one for each row
+    anchors: Vec<Anchor>,
 }
 
 impl View {
@@ -553,25 +1232,139 @@ impl View {
                 == rows.iter().map(|(f, _)| *f).collect::<Vec<_>>()
         });
 
-        // No need to call `get_rows()`,
-        // as the biggest row is the most far reaching in both directions
-        // because they are all centered.
-        let width = find_max_double(rows.iter(), |(_offset, row)| row.size.width);
-
         let height = rows.iter().next_back()
             .map(|(y_offset, row)| row.size.height + y_offset)
             .unwrap_or(0.0);
 
-        // Center the rows
         let rows = rows.into_iter().map(|(y_offset, row)| {(
-                c::Point {
-                    x: (width - row.size.width) / 2.0,
-                    y: y_offset,
-                },
+                c::Point { x: 0.0, y: y_offset },
                 row,
             )}).collect::<Vec<_>>();
 
-        View { rows, size: Size { width, height } }
+        let mut view = View {
+            rows,
+            size: Size { width: 0.0, height },
+            stagger: Vec::new(),
+            anchors: Vec::new(),
+        };
+        view.place_rows();
+        view
+    }
+
+    /// A view whose rows keep to the corners of the screen
+    /// instead of following one another.
+    pub fn floating(rows: Vec<(Anchor, Row)>) -> View {
+        let (anchors, rows) = rows.into_iter()
+            .map(|(anchor, row)| (anchor, (c::Point { x: 0.0, y: 0.0 }, row)))
+            .unzip();
+        let mut view = View {
+            rows,
+            size: Size { width: 0.0, height: 0.0 },
+            stagger: Vec::new(),
+            anchors,
+        };
+        view.place_rows();
+        view
+    }
+
+    pub fn is_floating(&self) -> bool {
+        !self.anchors.is_empty()
+    }
+
+    /// The smallest size where clusters in opposite corners don't overlap
+    fn get_floating_minimum(&self) -> Size {
+        let reach = |left: bool, top: bool| {
+            let (width, height) = (
+                find_max_double(
+                    self.anchors.iter().filter(|anchor| anchor.corner.is_left() == left),
+                    |anchor| anchor.margin + anchor.cluster.width,
+                ),
+                find_max_double(
+                    self.anchors.iter().filter(|anchor| anchor.corner.is_top() == top),
+                    |anchor| anchor.margin + anchor.cluster.height,
+                ),
+            );
+            Size { width, height }
+        };
+        let (start, end) = (reach(true, true), reach(false, false));
+        Size {
+            width: start.width + end.width,
+            height: start.height + end.height,
+        }
+    }
+
+    /// Takes the available size and places the clusters of a floating view
+    /// in its corners. The view never gets smaller than its clusters need.
+    pub fn fit(&mut self, available: Size) {
+        if !self.is_floating() {
+            return;
+        }
+        let minimum = self.get_floating_minimum();
+        self.size = Size {
+            width: f64::max(available.width, minimum.width),
+            height: f64::max(available.height, minimum.height),
+        };
+        let size = &self.size;
+        for (anchor, (offset, _row)) in self.anchors.iter().zip(self.rows.iter_mut()) {
+            let x = match anchor.corner.is_left() {
+                true => anchor.margin,
+                false => size.width - anchor.margin - anchor.cluster.width,
+            };
+            let y = match anchor.corner.is_top() {
+                true => anchor.margin,
+                false => size.height - anchor.margin - anchor.cluster.height,
+            };
+            *offset = c::Point {
+                x: x + anchor.offset.x,
+                y: y + anchor.offset.y,
+            };
+        }
+    }
+
+    /// Shifts the rows from the left edge instead of centering them,
+    /// like the keys on a typewriter.
+    pub fn with_stagger(mut self, stagger: Vec<f64>) -> View {
+        self.stagger = stagger;
+        self.place_rows();
+        self
+    }
+
+    fn get_stagger(&self, row_index: usize) -> Option<f64> {
+        self.stagger.get(row_index).cloned()
+    }
+
+    /// Fits the width to the rows, and places them across.
+    /// Floating views keep their size instead.
+    fn place_rows(&mut self) {
+        if self.is_floating() {
+            let size = self.size.clone();
+            self.fit(size);
+            return;
+        }
+        let width = find_max_double(
+            self.rows.iter().enumerate(),
+            |(index, (_offset, row))| {
+                self.get_stagger(*index).unwrap_or(0.0) + row.size.width
+            },
+        );
+        let stagger = &self.stagger;
+        for (index, (offset, row)) in self.rows.iter_mut().enumerate() {
+            offset.x = match stagger.get(index) {
+                Some(stagger) => *stagger,
+                None => (width - row.size.width) / 2.0,
+            };
+        }
+        self.size.width = width;
+    }
+
+    /// Second pass over the rows, letting growing buttons fill the width.
+    /// Rows which don't grow get placed in the new width again.
+    pub fn grow_to(&mut self, width: f64) {
+        let stagger = &self.stagger;
+        for (index, (_offset, row)) in self.rows.iter_mut().enumerate() {
+            row.grow_to(width - stagger.get(index).cloned().unwrap_or(0.0));
+        }
+        self.place_rows();
     }
     /// Finds the first button that covers the specified point
     /// relative to view's position's origin
@@ -590,6 +1383,24 @@ impl View {
             return None;
         }
 
+        // Clusters leave gaps between them, and touches there hit nothing.
+        if self.is_floating() {
+            return self.rows.iter()
+                .flat_map(|(row_offset, row)| {
+                    row.buttons.iter().map(move |(x, button)| (row_offset, *x, button))
+                })
+                .find(|(row_offset, x, button)| c::Bounds {
+                    x: row_offset.x + x,
+                    y: row_offset.y,
+                    width: button.size.width,
+                    height: button.size.height,
+                }.contains(&point))
+                .map(|(row_offset, x, button)| ButtonPlace {
+                    button,
+                    offset: row_offset + c::Point { x, y: 0.0 },
+                });
+        }
+
         // Rows are sorted so we can use a binary search to find the row.
         let result = self.rows.binary_search_by(
             |(f, _)| f.y.partial_cmp(&point.y).unwrap()
@@ -616,30 +1427,200 @@ impl View {
         &self.rows
     }
 
+    /// Returns the row and the position in the row.
+    fn find_button_by_name(&self, name: &str) -> Option<(usize, usize)> {
+        self.rows.iter()
+            .enumerate()
+            .find_map(|(index, (_offset, row))|
+                row.find_button_by_name(name).map(|position| (index, position))
+            )
+    }
+
+    /// Makes the buttons trade places. Returns false if one is missing.
+    pub fn swap_buttons(&mut self, a: &str, b: &str) -> bool {
+        let (a, b) = match (self.find_button_by_name(a), self.find_button_by_name(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+        if a.0 == b.0 {
+            self.rows[a.0].1.buttons.swap(a.1, b.1);
+        } else {
+            let (first, second) = if a.0 < b.0 { (a, b) } else { (b, a) };
+            let (before, after) = self.rows.split_at_mut(second.0);
+            mem::swap(
+                &mut before[first.0].1.buttons[first.1].1,
+                &mut after[0].1.buttons[second.1].1,
+            );
+        }
+        self.rows[a.0].1.reflow();
+        self.rows[b.0].1.reflow();
+        self.place_rows();
+        true
+    }
+
+    /// Leaves the button out, and closes the gap.
+    /// Returns false if it's missing.
+    pub fn hide_button(&mut self, name: &str) -> bool {
+        match self.find_button_by_name(name) {
+            Some((row, position)) => {
+                self.rows[row].1.buttons.remove(position);
+                self.rows[row].1.reflow();
+                self.place_rows();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Gives the buttons of the keys another label
+    pub fn relabel(&mut self, keys: &[KeyStateId], label: &Label) {
+        for (_offset, row) in &mut self.rows {
+            for (_offset, button) in &mut row.buttons {
+                if keys.contains(&button.key) {
+                    button.label = label.clone();
+                    button.label_name = None;
+                }
+            }
+        }
+    }
+
+    /// Replaces the labels for which `replace` gives another.
+    /// The sizes stay.
+    pub fn replace_labels<F: Fn(&Button) -> Option<Label>>(&mut self, replace: F) {
+        for (_offset, row) in &mut self.rows {
+            for (_offset, button) in &mut row.buttons {
+                if let Some(label) = replace(button) {
+                    button.label = label;
+                }
+            }
+        }
+    }
+
     /// Returns a size which contains all the views
     /// if they are all centered on the same point.
-    pub fn calculate_super_size(views: Vec<&View>) -> Size {
+    pub fn calculate_super_size(sizes: Vec<Size>) -> Size {
         Size {
             height: find_max_double(
-                views.iter(),
-                |view| view.size.height,
+                sizes.iter(),
+                |size| size.height,
             ),
             width: find_max_double(
-                views.iter(),
-                |view| view.size.width,
+                sizes.iter(),
+                |size| size.width,
             ),
         }
     }
 }
 
+/// A view which may not have its buttons yet.
+/// Most views never get shown, so creating them all up front
+/// would only delay the keyboard's appearance.
+pub enum LazyView {
+    Built(View),
+    Pending {
+        /// Known ahead, because views get centered on the biggest one
+        size: Size,
+        row_count: usize,
+        build: Box<dyn FnOnce() -> View + Send>,
+    },
+}
+
+impl LazyView {
+    /// Returns None if the view was never shown.
+    pub fn get(&self) -> Option<&View> {
+        match self {
+            LazyView::Built(view) => Some(view),
+            LazyView::Pending { .. } => None,
+        }
+    }
+
+    /// Creates the buttons if needed.
+    pub fn finalize(&mut self) -> &mut View {
+        if let LazyView::Pending { .. } = self {
+            let pending = mem::replace(self, LazyView::Built(View::new(Vec::new())));
+            if let LazyView::Pending { build, .. } = pending {
+                *self = LazyView::Built(build());
+            }
+        }
+        match self {
+            LazyView::Built(view) => view,
+            LazyView::Pending { .. } => unreachable!(),
+        }
+    }
+
+    pub fn get_size(&self) -> Size {
+        match self {
+            LazyView::Built(view) => view.get_size(),
+            LazyView::Pending { size, .. } => size.clone(),
+        }
+    }
+
+    pub fn get_row_count(&self) -> usize {
+        match self {
+            LazyView::Built(view) => view.get_rows().len(),
+            LazyView::Pending { row_count, .. } => *row_count,
+        }
+    }
+
+    /// Changes the view now, or right after it gets built.
+    pub fn modify<F>(&mut self, change: F)
+        where F: FnOnce(&mut View) + Send + 'static
+    {
+        match self {
+            LazyView::Built(view) => change(view),
+            LazyView::Pending { build, .. } => {
+                let original = mem::replace(build, Box::new(|| View::new(Vec::new())));
+                *build = Box::new(move || {
+                    let mut view = original();
+                    change(&mut view);
+                    view
+                });
+            },
+        }
+    }
+}
+
+impl From<View> for LazyView {
+    fn from(view: View) -> Self {
+        LazyView::Built(view)
+    }
+}
+
+/// Only built views can be stored.
+impl Serialize for LazyView {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get()
+            .ok_or_else(|| ser::Error::custom("View not built yet"))?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LazyView {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D)
+        -> Result<Self, D::Error>
+    {
+        View::deserialize(deserializer).map(LazyView::Built)
+    }
+}
+
 /// The physical characteristic of layout for the purpose of styling
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum ArrangementKind {
     Base = 0,
     Wide = 1,
+    /// For landscape screens too short for the wide arrangement
+    Compact = 2,
 }
 
-#[derive(Debug, PartialEq)]
+/// How a layout over the whole screen gets drawn,
+/// with the application showing between the buttons
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Floating {
+    /// Of the buttons, from 0 to 1
+    pub opacity: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Margins {
     pub top: f64,
     pub bottom: f64,
@@ -647,6 +1628,15 @@ pub struct Margins {
     pub right: f64,
 }
 
+/// Letter case demanded by the text field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LetterCase {
+    Lower,
+    Upper,
+    /// Only the first letter is upper case
+    Title,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum LatchedState {
     /// Holds view to return to.
@@ -654,6 +1644,85 @@ pub enum LatchedState {
     Not,
 }
 
+/// Pressing longer than this on a view switching key
+/// only shows the view until the key is released.
+const PEEK_AFTER: Duration = Duration::from_millis(300);
+
+/// A view switched to on press of a view switching key.
+/// Whether it stays depends on how the touch ends.
+struct Peek {
+    key: KeyStateId,
+    /// To return to
+    from_view: String,
+    from_latched: LatchedState,
+    /// Switched to
+    view: String,
+    pressed_at: Timestamp,
+    /// Another key got pressed in the meantime
+    used: bool,
+}
+
+impl Peek {
+    fn should_return(&self, released_at: Timestamp) -> bool {
+        let held = released_at.0.wrapping_sub(self.pressed_at.0);
+        self.used || Duration::from_millis(held as u64) >= PEEK_AFTER
+    }
+}
+
+/// A modifier held down by one touch, while another touch types
+struct Chord {
+    key: KeyStateId,
+    modifier: Modifier,
+    /// The modifier was latched before the chord
+    was_active: bool,
+    /// Another key got pressed in the meantime
+    used: bool,
+}
+
+impl Chord {
+    /// Using the chord leaves the modifier as it was before.
+    /// Otherwise, it was a tap, which toggles the modifier.
+    fn keeps_modifier(&self) -> bool {
+        self.used == self.was_active
+    }
+}
+
+/// Popover entry added by the layout
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MenuEntry {
+    pub label: String,
+    /// `SetView` or `ShowOverlay`
+    pub action: Action,
+}
+
+/// Highlight spreading from a touch across the button
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ripple {
+    /// Where the button got touched
+    pub center: c::Point,
+    /// Of the button, with the view offset
+    pub bounds: c::Bounds,
+    /// Ripples only show on the view they started in
+    pub view: String,
+    pub started: Instant,
+}
+
+impl Ripple {
+    const DURATION: Duration = Duration::from_millis(250);
+
+    /// Returns how far along the animation is, from 0 to 1,
+    /// or None once it's over.
+    pub fn get_progress(&self, now: Instant) -> Option<f64> {
+        let elapsed = now.saturating_duration_since(self.started);
+        match elapsed < Ripple::DURATION {
+            true => Some(
+                elapsed.as_secs_f64() / Ripple::DURATION.as_secs_f64()
+            ),
+            false => None,
+        }
+    }
+}
+
 // TODO: split into sth like
 // Arrangement (views) + details (keymap) + State (keys)
 /// State of the UI, contains the backend as well
@@ -661,18 +1730,39 @@ pub struct Layout {
     pub margins: Margins,
     pub kind: ArrangementKind,
     pub purpose: ContentPurpose,
+    /// The text field holds secrets, so no text goes to outside services,
+    /// which might remember it.
+    pub sensitive: bool,
+    /// Typing goes into the keyboard's own text field
+    pub text_field: Option<text_field::Purpose>,
     pub current_view: String,
 
     // If current view is latched,
     // clicking any button that emits an action (erase, submit, set modifier)
     // will cause lock buttons to unlatch.
     view_latched: LatchedState,
-
-    // Views own the actual buttons which have state
-    // Maybe they should own UI only,
-    // and keys should be owned by a dedicated non-UI-State?
-    /// Point is the offset within the layout
-    pub views: HashMap<String, (c::Point, View)>,
+    /// The view was chosen for the text field,
+    /// so it doesn't get carried over from the previous layout.
+    view_preselected: bool,
+    /// Present from the press of a view switching key
+    /// until the touch ends.
+    peek: Option<Peek>,
+    /// Modifier key pressed by an earlier touch, still held.
+    /// It's not among `pressed_keys`,
+    /// so that releasing the other touch doesn't release it.
+    chord: Option<Chord>,
+    /// Keys of the steno stroke being pressed
+    steno: steno::Machine,
+    /// Dots of the Braille cell being pressed
+    braille: braille::Translator,
+    /// Letter groups of the word being typed
+    t9: t9::Composer,
+
+    /// Point is the offset within the layout.
+    /// The current view is always built.
+    pub views: HashMap<String, (c::Point, LazyView)>,
+    /// States of the keys the buttons in the views refer to
+    pub keys: KeyStore,
 
     // Non-UI stuff
     /// xkb keymaps applicable to the contained keys. Unchangeable
@@ -685,15 +1775,84 @@ pub struct Layout {
     // through all buttons of the current view anyway.
     // When the list tracks actual location,
     // it becomes possible to place popovers and other UI accurately.
-    pub pressed_keys: HashSet<::util::Pointer<RefCell<KeyState>>>,
+    pub pressed_keys: HashSet<KeyStateId>,
+    /// Where the last press or drag happened, in layout coordinates.
+    /// Only used for the debug overlay.
+    pub last_touch: Option<c::Point>,
+    /// The touch which may turn into a swipe
+    pub touch: Option<gestures::Touch>,
+    /// Press animations, pruned when drawn
+    pub ripples: Vec<Ripple>,
+    /// Extra entries for the popover
+    pub menu: Vec<MenuEntry>,
+    /// The views float over the screen instead of taking the bottom
+    pub floating: Option<Floating>,
+    /// The key under each touch, in floating layouts.
+    /// Touches there press keys independently of each other.
+    held: HashMap<usize, KeyStateId>,
 }
 
 /// A builder structure for picking up layout data from storage
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "StoredLayoutData")]
 pub struct LayoutData {
     /// Point is the offset within layout
-    pub views: HashMap<String, (c::Point, View)>,
+    pub views: HashMap<String, (c::Point, LazyView)>,
+    pub keys: KeyStore,
+    pub menu: Vec<MenuEntry>,
+    #[serde(with = "::util::cstring::vec")]
     pub keymaps: Vec<CString>,
     pub margins: Margins,
+    pub floating: Option<Floating>,
+}
+
+/// Layout data as read back,
+/// with buttons not yet connected to the keys.
+#[derive(Deserialize)]
+struct StoredLayoutData {
+    views: HashMap<String, (c::Point, LazyView)>,
+    keys: KeyStore,
+    menu: Vec<MenuEntry>,
+    #[serde(with = "::util::cstring::vec")]
+    keymaps: Vec<CString>,
+    margins: Margins,
+    floating: Option<Floating>,
+}
+
+impl TryFrom<StoredLayoutData> for LayoutData {
+    type Error = String;
+    fn try_from(data: StoredLayoutData) -> Result<Self, Self::Error> {
+        let keys = data.keys;
+        let mut views = data.views;
+        for (_offset, view) in views.values_mut() {
+            for (_offset, row) in view.finalize().rows.iter_mut() {
+                for (_offset, button) in row.buttons.iter_mut() {
+                    button.key = keys.adopt(button.key)
+                        .ok_or_else(|| format!(
+                            "Button {} has no key",
+                            button.name.to_string_lossy(),
+                        ))?;
+                }
+            }
+        }
+        Ok(LayoutData {
+            views,
+            keys,
+            menu: data.menu,
+            keymaps: data.keymaps,
+            margins: data.margins,
+            floating: data.floating,
+        })
+    }
+}
+
+impl LayoutData {
+    /// Creates the buttons of all views, e.g. before storing them.
+    pub fn finalize_views(&mut self) {
+        for (_offset, view) in self.views.values_mut() {
+            view.finalize();
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -707,37 +1866,95 @@ impl fmt::Display for NoSuchView {
 
 // Unfortunately, changes are not atomic due to mutability :(
 // An error will not be recoverable
-// The usage of &mut on Rc<RefCell<KeyState>> doesn't mean anything special.
-// Cloning could also be used.
 impl Layout {
     pub fn new(data: LayoutData, kind: ArrangementKind, purpose: ContentPurpose) -> Layout {
+        let mut views = data.views;
+        if let Some((_offset, view)) = views.get_mut("base") {
+            view.finalize();
+        }
         Layout {
             kind,
             current_view: "base".to_owned(),
             view_latched: LatchedState::Not,
-            views: data.views,
+            view_preselected: false,
+            peek: None,
+            chord: None,
+            steno: steno::Machine::default(),
+            braille: braille::Translator::default(),
+            t9: t9::Composer::default(),
+            sensitive: false,
+            text_field: None,
+            views,
+            keys: data.keys,
+            menu: data.menu,
             keymaps: data.keymaps,
             pressed_keys: HashSet::new(),
+            last_touch: None,
+            touch: None,
+            ripples: Vec::new(),
             margins: data.margins,
+            floating: data.floating,
+            held: HashMap::new(),
             purpose,
         }
     }
 
-    pub fn get_current_view_position(&self) -> &(c::Point, View) {
-        &self.views
-            .get(&self.current_view).expect("Selected nonexistent view")
+    pub fn get_current_view_position(&self) -> (&c::Point, &View) {
+        let (offset, view) = self.views.get(&self.current_view)
+            .expect("Selected nonexistent view");
+        (offset, view.get().expect("Current view not built"))
     }
 
     pub fn get_current_view(&self) -> &View {
-        &self.views.get(&self.current_view).expect("Selected nonexistent view").1
+        self.get_current_view_position().1
     }
 
     fn set_view(&mut self, view: String) -> Result<(), NoSuchView> {
-        if self.views.contains_key(&view) {
-            self.current_view = view;
-            Ok(())
-        } else {
-            Err(NoSuchView)
+        match self.views.get_mut(&view) {
+            Some((_offset, lazy)) => {
+                lazy.finalize();
+                self.current_view = view;
+                Ok(())
+            },
+            None => Err(NoSuchView),
+        }
+    }
+
+    /// Switches to the view matching the letter case.
+    /// Layouts without an "upper" view stay as they are.
+    pub fn preselect_case(&mut self, case: LetterCase) {
+        let (view, latched) = match case {
+            LetterCase::Lower => ("base", LatchedState::Not),
+            LetterCase::Upper => ("upper", LatchedState::Not),
+            // Back to lower case after the first letter
+            LetterCase::Title => ("upper", LatchedState::FromView("base".into())),
+        };
+        if self.set_view(view.into()).is_ok() {
+            self.view_latched = latched;
+            self.view_preselected = true;
+        }
+    }
+
+    /// Turns Return keys into Send keys, as far as the text field wants.
+    pub fn set_return_key(&mut self, return_key: ReturnKey) {
+        if return_key == ReturnKey::Newline {
+            return;
+        }
+        let keys: Vec<KeyStateId> = self.keys.iter()
+            .filter(|(_id, key)| key.action.is_return())
+            .map(|(id, _key)| id)
+            .collect();
+        if return_key == ReturnKey::SendHoldForNewline {
+            for id in &keys {
+                self.keys.get_mut(*id).action = Action::SendOrNewline;
+            }
+        }
+        let label = Label::IconName(
+            Interned::new(send_key::SEND_ICON).expect("Icon name contains a null byte")
+        );
+        for (_offset, view) in self.views.values_mut() {
+            let (keys, label) = (keys.clone(), label.clone());
+            view.modify(move |view| view.relabel(&keys, &label));
         }
     }
 
@@ -747,16 +1964,14 @@ impl Layout {
         &self.view_latched
     }
 
-    /// Calculates size without margins
+    /// Calculates size of the current view without margins.
+    /// The panel gets resized when views differ in size,
+    /// so the current view is the one to fill it.
     fn calculate_inner_size(&self) -> Size {
-        View::calculate_super_size(
-            self.views.iter().map(|(_, (_offset, v))| v).collect()
-        )
+        self.get_current_view().get_size()
     }
 
-    /// Size including margins
-    fn calculate_size(&self) -> Size {
-        let inner_size = self.calculate_inner_size();
+    fn add_margins(&self, inner_size: Size) -> Size {
         Size {
             width: self.margins.left + inner_size.width + self.margins.right,
             height: (
@@ -767,6 +1982,65 @@ impl Layout {
         }
     }
 
+    /// Size including margins
+    fn calculate_size(&self) -> Size {
+        self.add_margins(self.calculate_inner_size())
+    }
+
+    pub fn get_row_count(&self, view: &str) -> Option<usize> {
+        self.views.get(view)
+            .map(|(_, view)| view.get_row_count())
+    }
+
+    /// Height divided by width, margins included
+    pub fn get_aspect_ratio(&self, view: &str) -> Option<f64> {
+        self.views.get(view)
+            .map(|(_, view)| self.add_margins(view.get_size()))
+            .map(|size| size.height / size.width)
+    }
+
+    /// The shortest side among the buttons of the view,
+    /// divided by the view's height with margins.
+    /// Builds the view.
+    pub fn get_smallest_button(&mut self, view: &str) -> Option<f64> {
+        let margins = self.margins.top + self.margins.bottom;
+        let view = self.views.get_mut(view)?.1.finalize();
+        let height = view.get_size().height + margins;
+        view.get_rows().iter()
+            .flat_map(|(_offset, row)| row.get_buttons())
+            .map(|(_offset, button)| button.size.width.min(button.size.height))
+            .min_by(|a, b| a.partial_cmp(b).unwrap_or(cmp::Ordering::Equal))
+            .map(|side| side / height)
+    }
+
+    /// Stretches the current view to the width of the available space,
+    /// as far as its buttons can grow.
+    /// The height stays, so the same scale fits both directions.
+    pub fn grow_current_view(&mut self, available: &Size) {
+        if available.height <= 0.0 {
+            return;
+        }
+        let margins = &self.margins;
+        if let Some((_offset, LazyView::Built(view))) = self.views.get_mut(&self.current_view) {
+            let height = margins.top + view.get_size().height + margins.bottom;
+            let width = available.width * height / available.height
+                - margins.left - margins.right;
+            view.grow_to(width);
+        }
+    }
+
+    /// Spreads the clusters of a floating view over the available space,
+    /// so that the scale stays 1 and layout units are logical pixels.
+    pub fn fit_current_view(&mut self, available: &Size) {
+        let margins = &self.margins;
+        if let Some((_offset, LazyView::Built(view))) = self.views.get_mut(&self.current_view) {
+            view.fit(Size {
+                width: available.width - margins.left - margins.right,
+                height: available.height - margins.top - margins.bottom,
+            });
+        }
+    }
+
     pub fn calculate_transformation(
         &self,
         available: Size,
@@ -783,15 +2057,17 @@ impl Layout {
             scale_x: scale_x,
             scale_y: scale_y,
         };
+        // Views are placed centered within the largest one.
+        let (offset, _view) = self.get_current_view_position();
         outside_margins.chain(c::Transformation {
-            origin_x: self.margins.left,
-            origin_y: self.margins.top,
+            origin_x: self.margins.left - offset.x,
+            origin_y: self.margins.top - offset.y,
             scale_x: 1.0,
             scale_y: 1.0,
         })
     }
 
-    fn find_button_by_position(&self, point: c::Point) -> Option<ButtonPlace> {
+    pub fn find_button_by_position(&self, point: c::Point) -> Option<ButtonPlace> {
         let (offset, layout) = self.get_current_view_position();
         layout.find_button_by_position(point - offset)
     }
@@ -810,23 +2086,124 @@ impl Layout {
         }
     }
 
+    /// Names of the visible buttons whose keys are currently pressed.
+    /// A key placed on multiple buttons is listed once per button.
+    pub fn get_pressed_button_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.foreach_visible_button(|_offset, button| {
+            if self.pressed_keys.contains(&button.key) {
+                names.push(button.name.to_string_lossy().into_owned());
+            }
+        });
+        names
+    }
+
+    /// Returns whether the current view changed.
     fn apply_view_transition(
         &mut self,
         action: &Action,
-    ) {
+    ) -> bool {
         let (transition, new_latched) = Layout::process_action_for_view(
             action,
             &self.current_view,
             &self.view_latched,
         );
+        self.change_view(transition, new_latched)
+    }
+
+    /// Like `apply_view_transition`, with the action of one of the keys.
+    fn apply_key_view_transition(&mut self, key: KeyStateId) -> bool {
+        let (transition, new_latched) = Layout::process_action_for_view(
+            &self.keys.get(key).action,
+            &self.current_view,
+            &self.view_latched,
+        );
+        self.change_view(transition, new_latched)
+    }
 
-        match transition {
-            ViewTransition::UnlatchAll => self.unstick_locks(),
-            ViewTransition::ChangeTo(view) => try_set_view(self, view.into()),
-            ViewTransition::NoChange => {},
+    fn change_view(
+        &mut self,
+        transition: ViewTransition,
+        new_latched: LatchedState,
+    ) -> bool {
+        let changed = match transition {
+            ViewTransition::UnlatchAll => {
+                let changed = match &self.view_latched {
+                    LatchedState::FromView(view) => view != &self.current_view,
+                    LatchedState::Not => false,
+                };
+                self.unstick_locks();
+                changed
+            },
+            ViewTransition::ChangeTo(view) => {
+                let changed = self.current_view != view;
+                try_set_view(self, &view);
+                changed && self.current_view == view
+            },
+            ViewTransition::NoChange => false,
         };
 
+        // Shift latched and locked show the same view.
+        let relatched = (self.view_latched == LatchedState::Not)
+            != (new_latched == LatchedState::Not);
+        if changed || relatched {
+            announce::view_changed(&self.current_view, &new_latched);
+        }
         self.view_latched = new_latched;
+        changed
+    }
+
+    /// Switches to the key's view right away,
+    /// keeping track of the view to return to.
+    /// Returns whether the current view changed.
+    fn start_peek(&mut self, key: KeyStateId, time: Timestamp) -> bool {
+        let from_view = self.current_view.clone();
+        let from_latched = self.view_latched.clone();
+        let changed = self.apply_key_view_transition(key);
+        self.peek = Some(Peek {
+            key,
+            from_view,
+            from_latched,
+            view: self.current_view.clone(),
+            pressed_at: time,
+            used: false,
+        });
+        changed
+    }
+
+    /// A tap on the view switching key keeps the view.
+    /// After holding the key, or reaching another key from it,
+    /// the view from before comes back.
+    /// Returns whether the current view changed.
+    fn end_peek(&mut self, time: Timestamp) -> bool {
+        match self.peek.take() {
+            // Another key may have switched the view already
+            Some(peek) => if peek.should_return(time) && self.current_view == peek.view {
+                let changed = self.current_view != peek.from_view;
+                try_set_view(self, &peek.from_view);
+                self.view_latched = peek.from_latched;
+                changed
+            } else {
+                false
+            },
+            None => false,
+        }
+    }
+
+    /// Takes over the view of the layout being replaced,
+    /// as long as the same views exist here.
+    /// Otherwise, stays in the default view.
+    fn carry_view_from(&mut self, old: &Layout) {
+        if self.view_preselected {
+            return;
+        }
+        let latched_compatible = match &old.view_latched {
+            LatchedState::FromView(view) => self.views.contains_key(view),
+            LatchedState::Not => true,
+        };
+        if latched_compatible && self.set_view(old.current_view.clone()).is_ok() {
+            self.view_latched = old.view_latched.clone();
+        }
     }
 
     /// Unlatch all latched keys,
@@ -854,13 +2231,16 @@ impl Layout {
     /// keys go through the following stages when clicked repeatedly:
     /// unlocked+unlatched -> locked+latched -> locked+unlatched
     /// -> unlocked+unlatched
-    fn process_action_for_view<'a>(
-        action: &'a Action,
+    fn process_action_for_view(
+        action: &Action,
         current_view: &str,
         latched: &LatchedState,
-    ) -> (ViewTransition<'a>, LatchedState) {
+    ) -> (ViewTransition, LatchedState) {
         match action {
             Action::Submit { text: _, keys: _ }
+                | Action::SubmitTemplate(_)
+                | Action::SendOrNewline
+                | Action::Shortcut { modifiers: _, keys: _ }
                 | Action::Erase
                 | Action::ApplyModifier(_)
             => {
@@ -871,7 +2251,7 @@ impl Layout {
                 (t, LatchedState::Not)
             },
             Action::SetView(view) => (
-                ViewTransition::ChangeTo(view),
+                ViewTransition::ChangeTo(view.clone()),
                 LatchedState::Not,
             ),
             Action::LockView { lock, unlock, latches, looks_locked_from: _ } => {
@@ -880,12 +2260,12 @@ impl Layout {
                 match (locked, latched, latches) {
                     // Was unlocked, now make locked but latched.
                     (false, LatchedState::Not, true) => (
-                        VT::ChangeTo(lock),
+                        VT::ChangeTo(lock.clone()),
                         LatchedState::FromView(current_view.into()),
                     ),
                     // Layout is latched for reason other than this button.
                     (false, LatchedState::FromView(view), true) => (
-                        VT::ChangeTo(lock),
+                        VT::ChangeTo(lock.clone()),
                         LatchedState::FromView(view.clone()),
                     ),
                     // Was latched, now only locked.
@@ -893,10 +2273,10 @@ impl Layout {
                         => (VT::NoChange, LatchedState::Not),
                     // Was unlocked, can't latch so now make fully locked.
                     (false, _, false)
-                        => (VT::ChangeTo(lock), LatchedState::Not),
+                        => (VT::ChangeTo(lock.clone()), LatchedState::Not),
                     // Was locked, now make unlocked.
                     (true, _, _)
-                        => (VT::ChangeTo(unlock), LatchedState::Not),
+                        => (VT::ChangeTo(unlock.clone()), LatchedState::Not),
                 }
             },
             _ => (ViewTransition::NoChange, latched.clone()),
@@ -904,9 +2284,11 @@ impl Layout {
     }
 }
 
+/// Owns the view name,
+/// because the action it comes from belongs to the layout being changed.
 #[derive(Debug, PartialEq)]
-enum ViewTransition<'a> {
-    ChangeTo(&'a str),
+enum ViewTransition {
+    ChangeTo(String),
     UnlatchAll,
     NoChange,
 }
@@ -925,16 +2307,16 @@ mod procedures {
 
     type Place<'v> = (c::Point, &'v Box<Button>);
 
-    /// Finds all buttons referring to the key in `state`,
+    /// Finds all buttons referring to the key,
     /// together with their offsets within the view.
-    pub fn find_key_places<'v, 's>(
+    pub fn find_key_places<'v>(
         view: &'v View,
-        state: &'s Rc<RefCell<KeyState>>
+        key: KeyStateId,
     ) -> Vec<Place<'v>> {
         view.get_rows().iter().flat_map(|(row_offset, row)| {
             row.buttons.iter()
                 .filter_map(move |(x_offset, button)| {
-                    if Rc::ptr_eq(&button.state, state) {
+                    if button.key == key {
                         Some((
                             row_offset + c::Point { x: *x_offset, y: 0.0 },
                             button,
@@ -961,10 +2343,10 @@ mod procedures {
                 v.as_ref() as *const T
             }
 
-            let state = make_state();
-            let state_clone = state.clone();
+            let mut keys = KeyStore::new();
+            let key = make_state(&mut keys);
 
-            let button = make_button_with_state("1".into(), state);
+            let button = make_button_with_state("1".into(), key);
             let button_ptr = as_ptr(&button);
 
             let row = Row::new(vec!((0.1, button)));
@@ -972,7 +2354,7 @@ mod procedures {
             let view = View::new(vec!((1.2, row)));
 
             assert_eq!(
-                find_key_places(&view, &state_clone.clone()).into_iter()
+                find_key_places(&view, key).into_iter()
                     .map(|(place, button)| { (place, as_ptr(button)) })
                     .collect::<Vec<_>>(),
                 vec!(
@@ -982,7 +2364,7 @@ mod procedures {
 
             let view = View::new(vec![]);
             assert_eq!(
-                find_key_places(&view, &state_clone.clone()).is_empty(),
+                find_key_places(&view, key).is_empty(),
                 true
             );
         }
@@ -998,29 +2380,74 @@ pub struct UIBackend {
 mod seat {
     use super::*;
 
+    use std::cell::Cell;
+
+    thread_local! {
+        /// Changes on every press, so that a latch timer knows it's stale.
+        static PRESS_COUNT: Cell<u32> = Cell::new(0);
+    }
+
+    /// Tells the state about the new view,
+    /// which may need a different panel height.
+    fn report_view_change(
+        layout: &Layout,
+        app_state: Option<&receiver::State>,
+    ) {
+        if let Some(app_state) = app_state {
+            app_state
+                .send(state::Event::ViewChanged(layout.current_view.clone()))
+                .or_print(logging::Problem::Bug, "Can't send to state");
+        }
+    }
+
     pub fn handle_press_key(
         layout: &mut Layout,
         submission: &mut Submission,
         time: Timestamp,
-        rckey: &Rc<RefCell<KeyState>>,
+        app_state: Option<&receiver::State>,
+        key_id: KeyStateId,
     ) {
-        if !layout.pressed_keys.insert(::util::Pointer(rckey.clone())) {
+        if !layout.pressed_keys.insert(key_id) {
             log_print!(
                 logging::Level::Bug,
-                "Key {:?} was already pressed", rckey,
+                "Key {:?} was already pressed", key_id,
             );
         }
-        let key: KeyState = {
-            RefCell::borrow(rckey).clone()
-        };
-        let action = key.action.clone();
-        match action {
+        PRESS_COUNT.with(|count| count.set(count.get().wrapping_add(1)));
+        statistics::record_press(
+            &layout.keys.get(key_id).action,
+            layout.purpose,
+            layout.sensitive,
+        );
+        if let Some(chord) = &mut layout.chord {
+            chord.used = true;
+        }
+        if let Some(peek) = &mut layout.peek {
+            peek.used = true;
+        } else if layout.keys.get(key_id).action.switches_view() {
+            if layout.start_peek(key_id, time) {
+                report_view_change(layout, app_state);
+            }
+        }
+        let key = layout.keys.get(key_id);
+        match &key.action {
+            Action::T9(_) | Action::T9Next => {},
+            Action::Erase if layout.t9.is_composing() => {},
+            _ => layout.t9.finish(submission),
+        }
+        // The engine composes the text instead.
+        if !layout.t9.is_composing() && ibus::forward(&key.action, key_id, &key.keycodes) {
+            layout.keys.get_mut(key_id).press();
+            submission.report_keys(layout);
+            return;
+        }
+        match &key.action {
             Action::Submit {
                 text: Some(text),
                 keys: _,
             } => submission.handle_press(
-                KeyState::get_id(rckey),
-                SubmitData::Text(&text),
+                key_id,
+                SubmitData::Text(text),
                 &key.keycodes,
                 time,
             ),
@@ -1028,54 +2455,173 @@ mod seat {
                 text: None,
                 keys: _,
             } => submission.handle_press(
-                KeyState::get_id(rckey),
+                key_id,
                 SubmitData::Keycodes,
                 &key.keycodes,
                 time,
             ),
+            Action::Erase if layout.t9.is_composing() => layout.t9.erase(submission),
             Action::Erase => submission.handle_press(
-                KeyState::get_id(rckey),
+                key_id,
                 SubmitData::Erase,
                 &key.keycodes,
                 time,
             ),
+            Action::Shortcut { modifiers, keys: _ } => submission.handle_shortcut(
+                key_id,
+                modifiers,
+                &key.keycodes,
+                time,
+            ),
+            Action::SendOrNewline => send_key::press(),
+            Action::Steno(name) => layout.steno.press(name),
+            Action::Braille(dot) => layout.braille.press(*dot),
+            Action::T9(letters) => layout.t9.press(letters, submission),
+            Action::T9Next => layout.t9.next(submission),
             _ => {},
         };
-        RefCell::replace(rckey, key.into_pressed());
+        layout.keys.get_mut(key_id).press();
+        submission.report_keys(layout);
     }
 
-    pub fn handle_release_key(
+    /// Runs the command of a swipe which just ended,
+    /// mostly by tapping the key which does the same.
+    pub fn run_gesture(
         layout: &mut Layout,
         submission: &mut Submission,
         ui: Option<&UIBackend>,
         time: Timestamp,
-        // TODO: intermediate measure:
-        // passing state conditionally because it's only used for popover.
-        // Eventually, it should be used for sumitting button events,
-        // and passed always.
         manager: Option<(&actors::popover::State, receiver::State)>,
-        rckey: &Rc<RefCell<KeyState>>,
+        command: gestures::Command,
     ) {
-        let key: KeyState = {
-            RefCell::borrow(rckey).clone()
-        };
-        let action = key.action.clone();
+        if let gestures::Command::Hide = command {
+            if let Some((_, app_state)) = manager {
+                app_state.send(state::Event::Visibility(state::visibility::Event::ForceHidden))
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            }
+            return;
+        }
+        let key = layout.keys.iter()
+            .find(|(_id, key)| command.is_done_by(&key.action))
+            .map(|(id, key)| (id, key.keycodes.clone()));
+        match (command, key) {
+            (gestures::Command::EraseWord, Some((_id, keycodes))) => {
+                submission.delete_before_cursor(erase::Unit::Word, keycodes, time);
+            },
+            (_, Some((key_id, _keycodes))) => {
+                handle_press_key(
+                    layout,
+                    submission,
+                    time,
+                    manager.as_ref().map(|(_, app_state)| app_state),
+                    key_id,
+                );
+                handle_release_key(layout, submission, ui, time, manager, key_id);
+            },
+            (_, None) => {
+                log_print!(
+                    logging::Level::Info,
+                    "No key on the layout to swipe for {}", command.get_name(),
+                );
+                emit_error_feedback();
+            },
+        }
+    }
 
-        layout.apply_view_transition(&action);
+    /// Goes back to the view unless another key gets pressed in the meantime.
+    fn unlatch_after(keyboard: c::EekGtkKeyboard, view: String, timeout: Duration) {
+        unless_pressed(System, timeout, move || match CString::new(view.as_str()) {
+            Ok(view) => unsafe {
+                c::eek_gtk_keyboard_set_view(keyboard, view.as_ptr())
+            },
+            Err(e) => log_print!(logging::Level::Bug, "Bad view name: {}", e),
+        });
+    }
 
-        // update
-        let key = key.into_released();
+    /// Calls `f` after the timeout, if no key got pressed until then.
+    fn unless_pressed<C: Clock, F: FnOnce() + 'static>(clock: C, timeout: Duration, f: F) {
+        let presses = PRESS_COUNT.with(|count| count.get());
+        let mut f = Some(f);
+        clock.schedule(timeout, Box::new(move || {
+            if PRESS_COUNT.with(|count| count.get()) == presses {
+                if let Some(f) = f.take() {
+                    f();
+                }
+            }
+            false
+        }));
+    }
+
+    /// No other key of the chord is held, apart from the one being released.
+    fn is_chord_over<F: Fn(&Action) -> bool>(
+        layout: &Layout,
+        key_id: KeyStateId,
+        is_part: F,
+    ) -> bool {
+        !layout.pressed_keys.iter()
+            .filter(|id| **id != key_id)
+            .any(|id| is_part(&layout.keys.get(*id).action))
+    }
+
+    pub fn handle_release_key(
+        layout: &mut Layout,
+        submission: &mut Submission,
+        ui: Option<&UIBackend>,
+        time: Timestamp,
+        // TODO: intermediate measure:
+        // passing state conditionally because it's only used for popover.
+        // Eventually, it should be used for sumitting button events,
+        // and passed always.
+        manager: Option<(&actors::popover::State, receiver::State)>,
+        key_id: KeyStateId,
+    ) {
+        let owns_peek = layout.peek.as_ref()
+            .map(|peek| peek.key == key_id)
+            .unwrap_or(false);
+        // The peek switched the view on press already
+        if !owns_peek && layout.apply_key_view_transition(key_id) {
+            report_view_change(
+                layout,
+                manager.as_ref().map(|(_, app_state)| app_state),
+            );
+        }
+        if let (LatchedState::FromView(view), Some(timeout), Some(ui)) = (
+            &layout.view_latched,
+            layout.keys.get(key_id).behavior.latch_timeout,
+            ui,
+        ) {
+            unlatch_after(ui.keyboard, view.clone(), timeout);
+        }
 
         // process non-view switching
-        match action {
+        match &layout.keys.get(key_id).action {
+            // Return finishes typing the search query.
+            Action::Submit { text: None, keys }
+                if layout.text_field == Some(text_field::Purpose::Search)
+                    && keys.iter().any(|keysym| keysym.0 == "Return")
+            => {
+                submission.handle_release(key_id, time);
+                if let (Some(query), Some((_, app_state)))
+                    = (submission.get_redirected_text(), manager)
+                {
+                    let query = Some(query).filter(|query| !query.is_empty());
+                    app_state.send(state::Event::SymbolQuery(query))
+                        .or_print(logging::Problem::Bug, "Can't send to state");
+                }
+            },
             Action::Submit { text: _, keys: _ }
+                | Action::Shortcut { modifiers: _, keys: _ }
                 | Action::Erase
             => {
-                submission.handle_release(KeyState::get_id(rckey), time);
+                submission.handle_release(key_id, time);
+            },
+            Action::SendOrNewline => {
+                let keycodes = layout.keys.get(key_id).keycodes.clone();
+                send_key::release(submission, key_id, &keycodes, time);
             },
             Action::ApplyModifier(modifier) => {
+                let modifier = *modifier;
                 // FIXME: key id is unneeded with stateless locks
-                let key_id = KeyState::get_id(rckey);
                 let gets_locked = !submission.is_modifier_active(modifier);
                 match gets_locked {
                     true => submission.handle_add_modifier(
@@ -1085,13 +2631,34 @@ mod seat {
                     false => submission.handle_drop_modifier(key_id, time),
                 }
             }
+            // The stroke ends with the last steno key.
+            Action::Steno(_) => {
+                let is_steno = |action: &Action| match action {
+                    Action::Steno(_) => true,
+                    _ => false,
+                };
+                if is_chord_over(layout, key_id, is_steno) {
+                    layout.steno.finish(submission);
+                }
+            },
+            Action::Braille(_) => {
+                let is_braille = |action: &Action| match action {
+                    Action::Braille(_) => true,
+                    _ => false,
+                };
+                if is_chord_over(layout, key_id, is_braille) {
+                    layout.braille.finish(submission);
+                }
+            },
+            // The popover would let the user change the layout
+            Action::ShowPreferences if kiosk::is_enabled() => {},
             // only show when UI is present
             Action::ShowPreferences => if let Some(ui) = &ui {
                 // only show when layout manager is available
                 if let Some((manager, app_state)) = manager {
                     let view = layout.get_current_view();
                     let places = ::layout::procedures::find_key_places(
-                        view, &rckey,
+                        view, key_id,
                     );
                     // Getting first item will cause mispositioning
                     // with more than one button with the same key
@@ -1103,24 +2670,296 @@ mod seat {
                             width: button.size.width,
                             height: button.size.height,
                         };
-                        popover::show(
+                        actors::popover::show_at(
                             ui.keyboard,
                             ui.widget_to_layout.reverse_bounds(bounds),
                             manager,
                             app_state,
+                            &layout.menu,
                         );
                     }
                 }
             },
+            Action::NextCandidate => handwriting::next_candidate(submission),
+            Action::InsertResult => calculator::insert_result(submission),
+            Action::Screenshot => portals::screenshot(),
+            Action::Statistics => statistics::next_chart(),
+            Action::SubmitTemplate(text) => {
+                let expanded = template::expand(text, &template::System)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| CString::new(text).map_err(|e| e.to_string()));
+                match expanded {
+                    Ok(text) => if submission.commit_text(&text).is_err() {
+                        log_print!(
+                            logging::Level::Warning,
+                            "No text field to receive the template",
+                        );
+                    },
+                    Err(e) => log_print!(
+                        logging::Level::Warning,
+                        "Can't expand template {}: {}", text, e,
+                    ),
+                }
+            },
+            Action::AcceptCorrection => if submission.accept_correction().is_err() {
+                log_print!(
+                    logging::Level::Warning,
+                    "No text field to receive the correction",
+                );
+            },
+            Action::ComposeStep { sequence, result } => if let Some((_, app_state)) = manager {
+                let event = match result {
+                    Some(text) => {
+                        if submission.commit_text(text).is_err() {
+                            log_print!(
+                                logging::Level::Warning,
+                                "No text field to receive the composed text",
+                            );
+                        }
+                        state::Event::ComposeSequence(None)
+                    },
+                    None => state::Event::ComposeSequence(Some(sequence.clone())),
+                };
+                app_state.send(event)
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            },
+            Action::StartCleaning => if let Some((_, app_state)) = manager {
+                app_state.send(state::Event::StartCleaning)
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            },
+            Action::ComposeCancel => if let Some((_, app_state)) = manager {
+                app_state.send(state::Event::ComposeSequence(None))
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            },
+            // The word already typed gets searched right away.
+            Action::SearchSymbols => if let Some((_, app_state)) = manager {
+                let event = match submission.take_word() {
+                    Some(word) => state::Event::SymbolQuery(Some(word)),
+                    None => state::Event::EditSymbolQuery,
+                };
+                app_state.send(event)
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            },
+
+            Action::ShowOverlay(name) => if let Some((_, app_state)) = manager {
+                app_state
+                    .send(state::Event::OverlayChanged(
+                        popover::LayoutId::Local(name.clone()),
+                    ))
+                    .or_print(logging::Problem::Bug, "Can't send to state");
+            },
             // Other keys are handled in view switcher before.
             _ => {}
         };
 
-        let pointer = ::util::Pointer(rckey.clone());
         // Apply state changes
-        layout.pressed_keys.remove(&pointer);
+        layout.pressed_keys.remove(&key_id);
         // Commit activated button state changes
-        RefCell::replace(rckey, key);
+        layout.keys.get_mut(key_id).release();
+        submission.report_keys(layout);
+    }
+
+    /// Releases all pressed keys except for `kept`.
+    ///
+    /// Every release takes the key out of `pressed_keys`,
+    /// so the set gets searched again for the next one
+    /// instead of getting copied up front.
+    pub fn release_keys_except(
+        layout: &mut Layout,
+        submission: &mut Submission,
+        ui: Option<&UIBackend>,
+        time: Timestamp,
+        manager: Option<(&actors::popover::State, &receiver::State)>,
+        kept: Option<KeyStateId>,
+    ) {
+        loop {
+            let next = layout.pressed_keys.iter()
+                .find(|key_id| Some(**key_id) != kept)
+                .cloned();
+            match next {
+                Some(key_id) => handle_release_key(
+                    layout,
+                    submission,
+                    ui,
+                    time,
+                    manager.map(|(popover, app_state)| (popover, app_state.clone())),
+                    key_id,
+                ),
+                None => break,
+            }
+        }
+    }
+
+    /// Moves a touch of a floating layout onto the key at the point,
+    /// or off the keys.
+    /// Keys of other touches stay down, so that they can be pressed together.
+    pub fn move_touch(
+        layout: &mut Layout,
+        submission: &mut Submission,
+        ui: &UIBackend,
+        time: Timestamp,
+        manager: (&actors::popover::State, &receiver::State),
+        touch: usize,
+        point: c::Point,
+    ) {
+        let key_id = layout.find_button_by_position(point)
+            .map(|place| place.button.key);
+        if layout.held.get(&touch).cloned() == key_id {
+            return;
+        }
+        release_touch(layout, submission, Some(ui), time, Some(manager), touch);
+        if let Some(key_id) = key_id {
+            layout.held.insert(touch, key_id);
+            // Another touch may be holding the key already.
+            if !layout.pressed_keys.contains(&key_id) {
+                let (_popover, app_state) = manager;
+                handle_press_key(layout, submission, time, Some(app_state), key_id);
+                emit_feedback(
+                    ui.keyboard,
+                    Sound::for_action(&layout.keys.get(key_id).action),
+                );
+            }
+        }
+    }
+
+    /// Lets go of the key under the touch,
+    /// unless another touch still holds it.
+    pub fn release_touch(
+        layout: &mut Layout,
+        submission: &mut Submission,
+        ui: Option<&UIBackend>,
+        time: Timestamp,
+        manager: Option<(&actors::popover::State, &receiver::State)>,
+        touch: usize,
+    ) {
+        if let Some(key_id) = layout.held.remove(&touch) {
+            if !layout.held.values().any(|held| *held == key_id) {
+                handle_release_key(
+                    layout,
+                    submission,
+                    ui,
+                    time,
+                    manager.map(|(popover, app_state)| (popover, app_state.clone())),
+                    key_id,
+                );
+            }
+        }
+    }
+
+    /// Keeps the only pressed key down while another touch begins,
+    /// if it's a modifier.
+    /// The modifier applies to the keys pressed with that touch.
+    pub fn begin_chord(
+        layout: &mut Layout,
+        submission: &mut Submission,
+        time: Timestamp,
+    ) -> bool {
+        if layout.chord.is_some() || layout.pressed_keys.len() != 1 {
+            return false;
+        }
+        let key_id = *layout.pressed_keys.iter().next()
+            .expect("One key is pressed");
+        let modifier = match layout.keys.get(key_id).action {
+            Action::ApplyModifier(modifier) => modifier,
+            _ => return false,
+        };
+        layout.pressed_keys.remove(&key_id);
+        let was_active = submission.is_modifier_active(modifier);
+        if !was_active {
+            submission.handle_add_modifier(key_id, modifier, time);
+        }
+        layout.chord = Some(Chord {
+            key: key_id,
+            modifier,
+            was_active,
+            used: false,
+        });
+        true
+    }
+
+    /// Releases the modifier key held since `begin_chord`.
+    pub fn end_chord(
+        layout: &mut Layout,
+        submission: &mut Submission,
+        time: Timestamp,
+    ) {
+        if let Some(chord) = layout.chord.take() {
+            let active = submission.is_modifier_active(chord.modifier);
+            if active && !chord.keeps_modifier() {
+                submission.handle_drop_modifier(chord.key, time);
+            }
+            layout.keys.get_mut(chord.key).release();
+        }
+    }
+
+    /// Called once the touch is gone and all keys are released.
+    pub fn end_peek(
+        layout: &mut Layout,
+        time: Timestamp,
+        app_state: Option<&receiver::State>,
+    ) {
+        if layout.end_peek(time) {
+            report_view_change(layout, app_state);
+        }
+    }
+
+    /// Switches from the `old` layout to the `new` one
+    /// without leaving anything stuck.
+    ///
+    /// Keys still held on the old layout get released
+    /// while the submission still uses the old keymaps,
+    /// so that the release matches the press.
+    /// The release doesn't trigger the keys' actions:
+    /// neither modifiers nor views should change because of a layout switch.
+    /// The finger that held the key ends up on the new layout
+    /// with nothing pressed.
+    pub fn hand_over(
+        old: &mut Layout,
+        new: &mut Layout,
+        submission: &mut Submission,
+        time: Timestamp,
+    ) {
+        for key_id in old.pressed_keys.drain() {
+            let key = old.keys.get_mut(key_id);
+            match key.action {
+                Action::Submit { .. }
+                    | Action::Shortcut { .. }
+                    | Action::Erase
+                => {
+                    submission.handle_release(key_id, time);
+                },
+                _ => {},
+            }
+            key.release();
+        }
+        end_chord(old, submission, time);
+        new.carry_view_from(old);
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::clock::Manual;
+        use std::rc::Rc;
+
+        #[test]
+        fn latch_timeout() {
+            let clock = Manual::new();
+            let unlatched = Rc::new(Cell::new(0));
+            let count = unlatched.clone();
+            unless_pressed(clock.clone(), Duration::from_secs(1), move || count.set(count.get() + 1));
+            clock.advance(Duration::from_millis(999));
+            assert_eq!(unlatched.get(), 0);
+            clock.advance(Duration::from_millis(1));
+            assert_eq!(unlatched.get(), 1);
+
+            // A press in the meantime keeps the view.
+            let count = unlatched.clone();
+            unless_pressed(clock.clone(), Duration::from_secs(1), move || count.set(count.get() + 1));
+            PRESS_COUNT.with(|count| count.set(count.get().wrapping_add(1)));
+            clock.advance(Duration::from_secs(2));
+            assert_eq!(unlatched.get(), 1);
+        }
     }
 }
 
@@ -1128,33 +2967,39 @@ mod seat {
 mod test {
     use super::*;
 
-    use std::ffi::CString;
+    use ::action::KeySym;
     use ::keyboard::PressType;
+    use ::layout_dsl;
+    use ::layout_dsl::{ find_key, key };
 
-    pub fn make_state_with_action(action: Action)
-        -> Rc<RefCell<::keyboard::KeyState>>
+    pub fn make_state_with_action(keys: &mut KeyStore, action: Action)
+        -> KeyStateId
     {
-        Rc::new(RefCell::new(::keyboard::KeyState {
+        keys.add(::keyboard::KeyState {
             pressed: PressType::Released,
             keycodes: Vec::new(),
             action,
-        }))
+            behavior: Default::default(),
+        })
     }
 
-    pub fn make_state() -> Rc<RefCell<::keyboard::KeyState>> {
-        make_state_with_action(Action::SetView("default".into()))
+    pub fn make_state(keys: &mut KeyStore) -> KeyStateId {
+        make_state_with_action(keys, Action::SetView("default".into()))
     }
 
     pub fn make_button_with_state(
         name: String,
-        state: Rc<RefCell<::keyboard::KeyState>>,
+        key: KeyStateId,
     ) -> Box<Button> {
         Box::new(Button {
-            name: CString::new(name.clone()).unwrap(),
+            name: Interned::new(&name).unwrap(),
             size: Size { width: 0f64, height: 0f64 },
-            outline_name: CString::new("test").unwrap(),
-            label: Label::Text(CString::new(name).unwrap()),
-            state: state,
+            outline_name: Interned::new("test").unwrap(),
+            corners: None,
+            growth: 0.0,
+            label: Label::Text(Interned::new(&name).unwrap()),
+            label_name: None,
+            key,
         })
     }
 
@@ -1169,7 +3014,7 @@ mod test {
 
         assert_eq!(
             Layout::process_action_for_view(&action, "unlock", &LatchedState::Not),
-            (ViewTransition::ChangeTo("lock"), LatchedState::FromView("unlock".into())),
+            (ViewTransition::ChangeTo("lock".into()), LatchedState::FromView("unlock".into())),
         );
 
         assert_eq!(
@@ -1179,7 +3024,7 @@ mod test {
 
         assert_eq!(
             Layout::process_action_for_view(&action, "lock", &LatchedState::Not),
-            (ViewTransition::ChangeTo("unlock"), LatchedState::Not),
+            (ViewTransition::ChangeTo("unlock".into()), LatchedState::Not),
         );
 
         assert_eq!(
@@ -1199,47 +3044,17 @@ mod test {
 
         let submit = Action::Erase;
 
-        let view = View::new(vec![(
-            0.0,
-            Row::new(vec![
-                (
-                    0.0,
-                    make_button_with_state(
-                        "switch".into(),
-                        make_state_with_action(switch.clone())
-                    ),
-                ),
-                (
-                    1.0,
-                    make_button_with_state(
-                        "submit".into(),
-                        make_state_with_action(submit.clone())
-                    ),
-                ),
-            ]),
-        )]);
-
-        let mut layout = Layout {
-            current_view: "base".into(),
-            view_latched: LatchedState::Not,
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            pressed_keys: HashSet::new(),
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 0.0,
-            },
-            views: hashmap! {
-                // Both can use the same structure.
-                // Switching doesn't depend on the view shape
-                // as long as the switching button is present.
-                "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        // Both can use the same structure.
+        // Switching doesn't depend on the view shape
+        // as long as the switching button is present.
+        let view = || view![row![
+            key("switch").action(switch.clone()),
+            key("submit").action(submit.clone()),
+        ]];
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view()),
+            ("locked", view()),
+        ]);
 
         // Basic cycle
         layout.apply_view_transition(&switch);
@@ -1273,50 +3088,15 @@ mod test {
             looks_locked_from: vec![],
         };
 
-        let submit = Action::Erase;
-
-        let view = View::new(vec![(
-            0.0,
-            Row::new(vec![
-                (
-                    0.0,
-                    make_button_with_state(
-                        "switch".into(),
-                        make_state_with_action(switch.clone())
-                    ),
-                ),
-                (
-                    1.0,
-                    make_button_with_state(
-                        "submit".into(),
-                        make_state_with_action(submit.clone())
-                    ),
-                ),
-            ]),
-        )]);
-
-        let mut layout = Layout {
-            current_view: "base".into(),
-            view_latched: LatchedState::Not,
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            pressed_keys: HashSet::new(),
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 0.0,
-            },
-            views: hashmap! {
-                // Both can use the same structure.
-                // Switching doesn't depend on the view shape
-                // as long as the switching button is present.
-                "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                "unlocked".into() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        let view = || view![row![
+            key("switch").action(switch.clone()),
+            key("submit").action(Action::Erase),
+        ]];
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view()),
+            ("locked", view()),
+            ("unlocked", view()),
+        ]);
 
         layout.apply_view_transition(&switch);
         assert_eq!(&layout.current_view, "locked");
@@ -1342,48 +3122,15 @@ mod test {
 
         let submit = Action::Erase;
 
-        let view = View::new(vec![(
-            0.0,
-            Row::new(vec![
-                (
-                    0.0,
-                    make_button_with_state(
-                        "switch".into(),
-                        make_state_with_action(switch.clone())
-                    ),
-                ),
-                (
-                    1.0,
-                    make_button_with_state(
-                        "submit".into(),
-                        make_state_with_action(submit.clone())
-                    ),
-                ),
-            ]),
-        )]);
-
-        let mut layout = Layout {
-            current_view: "base".into(),
-            view_latched: LatchedState::Not,
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            pressed_keys: HashSet::new(),
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 0.0,
-            },
-            views: hashmap! {
-                // All can use the same structure.
-                // Switching doesn't depend on the view shape
-                // as long as the switching button is present.
-                "base".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                "locked".into() => (c::Point { x: 0.0, y: 0.0 }, view.clone()),
-                "ĄĘ".into() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        let view = || view![row![
+            key("switch").action(switch.clone()),
+            key("submit").action(submit.clone()),
+        ]];
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view()),
+            ("locked", view()),
+            ("ĄĘ", view()),
+        ]);
 
         // Latch twice, then Ąto-unlatch across 2 levels
         layout.apply_view_transition(&switch);
@@ -1397,43 +3144,43 @@ mod test {
         assert_eq!(&layout.current_view, "base");
     }
 
+    /// Shift latches for one letter, and locks on a second press.
+    #[test]
+    fn shift_latch_lock() {
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view![row![key("a"), key("Shift").lock("upper")]]),
+            ("upper", view![row![key("A"), key("Shift").lock("upper")]]),
+        ]);
+        let shift = find_key(&layout, "Shift");
+        let letter = find_key(&layout, "A");
+
+        assert_eq!(layout.apply_key_view_transition(shift), true);
+        assert_eq!(&layout.current_view, "upper");
+        assert_eq!(layout.view_latched, LatchedState::FromView("base".into()));
+        layout.apply_key_view_transition(letter);
+        assert_eq!(&layout.current_view, "base");
+        assert_eq!(layout.view_latched, LatchedState::Not);
+
+        layout.apply_key_view_transition(shift);
+        assert_eq!(layout.apply_key_view_transition(shift), false);
+        assert_eq!(&layout.current_view, "upper");
+        assert_eq!(layout.view_latched, LatchedState::Not);
+        layout.apply_key_view_transition(letter);
+        layout.apply_key_view_transition(letter);
+        assert_eq!(&layout.current_view, "upper");
+
+        layout.apply_key_view_transition(shift);
+        assert_eq!(&layout.current_view, "base");
+    }
+
     #[test]
     fn check_centering() {
         //    A B
         // ---bar---
-        let view = View::new(vec![
-            (
-                0.0,
-                Row::new(vec![
-                    (
-                        0.0,
-                        Box::new(Button {
-                            size: Size { width: 5.0, height: 10.0 },
-                            ..*make_button_with_state("A".into(), make_state())
-                        }),
-                    ),
-                    (
-                        5.0,
-                        Box::new(Button {
-                            size: Size { width: 5.0, height: 10.0 },
-                            ..*make_button_with_state("B".into(), make_state())
-                        }),
-                    ),
-                ]),
-            ),
-            (
-                10.0,
-                Row::new(vec![
-                    (
-                        0.0,
-                        Box::new(Button {
-                            size: Size { width: 30.0, height: 10.0 },
-                            ..*make_button_with_state("bar".into(), make_state())
-                        }),
-                    ),
-                ]),
-            )
-        ]);
+        let view = view![
+            row![key("A").size(5.0, 10.0), key("B").size(5.0, 10.0)],
+            row![key("bar").size(30.0, 10.0)],
+        ].into_view();
         assert!(
             view.find_button_by_position(c::Point { x: 5.0, y: 5.0 })
                 .unwrap().button.name.to_str().unwrap() == "A"
@@ -1452,39 +3199,80 @@ mod test {
         );
     }
 
+    fn find_name(view: &View, x: f64, y: f64) -> Option<String> {
+        view.find_button_by_position(c::Point { x, y })
+            .map(|place| place.button.name.to_string_lossy().into_owned())
+    }
+
+    /// Touches beside a short row reach its nearest key,
+    /// but touches outside of the view reach nothing.
+    #[test]
+    fn hit_edges() {
+        let view = view![
+            row![key("q"), key("w"), key("e")],
+            row![key("z")],
+        ].into_view();
+        assert_eq!(find_name(&view, 0.5, 0.5), Some("q".into()));
+        assert_eq!(find_name(&view, 2.5, 0.5), Some("e".into()));
+        assert_eq!(find_name(&view, 1.5, 1.5), Some("z".into()));
+        assert_eq!(find_name(&view, 0.2, 1.5), Some("z".into()));
+        assert_eq!(find_name(&view, 2.8, 1.5), Some("z".into()));
+        assert_eq!(find_name(&view, 3.5, 0.5), None);
+        assert_eq!(find_name(&view, 0.5, 2.5), None);
+        assert_eq!(find_name(&view, -0.1, 0.5), None);
+    }
+
+    /// Clusters keep to their corners, and touches between them hit nothing.
+    #[test]
+    fn hit_floating() {
+        let row = |name: &str| {
+            view![row![key(name).size(10.0, 10.0)]].into_view()
+                .get_rows()[0].1.clone()
+        };
+        let anchor = |corner| Anchor {
+            corner,
+            margin: 5.0,
+            cluster: Size { width: 10.0, height: 10.0 },
+            offset: c::Point { x: 0.0, y: 0.0 },
+        };
+        let mut view = View::floating(vec![
+            (anchor(Corner::TopLeft), row("L")),
+            (anchor(Corner::BottomRight), row("A")),
+        ]);
+        assert!(view.is_floating());
+        view.fit(Size { width: 100.0, height: 50.0 });
+        assert_eq!(view.get_size(), Size { width: 100.0, height: 50.0 });
+        assert_eq!(find_name(&view, 10.0, 10.0), Some("L".into()));
+        assert_eq!(find_name(&view, 90.0, 40.0), Some("A".into()));
+        assert_eq!(find_name(&view, 50.0, 25.0), None);
+        assert_eq!(find_name(&view, 2.0, 2.0), None);
+        // Too small for both clusters
+        view.fit(Size { width: 10.0, height: 10.0 });
+        assert_eq!(view.get_size(), Size { width: 30.0, height: 30.0 });
+        assert_eq!(find_name(&view, 20.0, 20.0), Some("A".into()));
+    }
+
+    /// Smaller views are centered within the layout.
+    #[test]
+    fn hit_smaller_view() {
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view![row![key("q"), key("w"), key("e")], row![key("z")]]),
+            ("small", view![row![key("x")]]),
+        ]);
+        layout.set_view("small".into()).unwrap();
+        let find = |x, y| layout.find_button_by_position(c::Point { x, y })
+            .map(|place| place.button.name.to_string_lossy().into_owned());
+        assert_eq!(find(1.5, 1.0), Some("x".into()));
+        assert_eq!(find(0.5, 1.0), None);
+        assert_eq!(find(1.5, 0.2), None);
+    }
+
     #[test]
     fn check_bottom_margin() {
         // just one button
-        let view = View::new(vec![
-            (
-                0.0,
-                Row::new(vec![(
-                    0.0,
-                    Box::new(Button {
-                        size: Size { width: 1.0, height: 1.0 },
-                        ..*make_button_with_state("foo".into(), make_state())
-                    }),
-                )]),
-            ),
-        ]);
-        let layout = Layout {
-            current_view: String::new(),
-            view_latched: LatchedState::Not,
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            pressed_keys: HashSet::new(),
-            // Lots of bottom margin
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 1.0,
-            },
-            views: hashmap! {
-                String::new() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        let mut layout = layout_dsl::layout(vec![("base", view![row![key("foo")]])]);
+        // Lots of bottom margin
+        layout.margins.bottom = 1.0;
         assert_eq!(
             layout.calculate_inner_size(),
             Size { width: 1.0, height: 1.0 }
@@ -1508,35 +3296,7 @@ mod test {
     #[test]
     fn check_stretching() {
         // just one button
-        let view = View::new(vec![
-            (
-                0.0,
-                Row::new(vec![(
-                    0.0,
-                    Box::new(Button {
-                        size: Size { width: 1.0, height: 1.0 },
-                        ..*make_button_with_state("foo".into(), make_state())
-                    }),
-                )]),
-            ),
-        ]);
-        let layout = Layout {
-            current_view: String::new(),
-            view_latched: LatchedState::Not,
-            keymaps: Vec::new(),
-            kind: ArrangementKind::Base,
-            pressed_keys: HashSet::new(),
-            margins: Margins {
-                top: 0.0,
-                left: 0.0,
-                right: 0.0,
-                bottom: 0.0,
-            },
-            views: hashmap! {
-                String::new() => (c::Point { x: 0.0, y: 0.0 }, view),
-            },
-            purpose: ContentPurpose::Normal,
-        };
+        let layout = layout_dsl::layout(vec![("base", view![row![key("foo")]])]);
         let transformation = layout.calculate_transformation(
             Size { width: 100.0, height: 100.0 }
         );
@@ -1558,4 +3318,276 @@ mod test {
         assert_eq!(transformation.scale_x, 100.0);
         assert_eq!(transformation.scale_y, 100.0);
     }
+
+    #[test]
+    fn check_growth() {
+        let mut layout = layout_dsl::layout(vec![("base", view![
+            row![key("a"), key("b"), key("c"), key("d")],
+            row![key("x"), key("space").grow(1.0), key("y")],
+        ])]);
+        // Twice as wide as the layout
+        layout.grow_current_view(&Size { width: 4.0, height: 1.0 });
+        let view = layout.get_current_view();
+        assert_eq!(view.get_size(), Size { width: 8.0, height: 2.0 });
+        let (offset, row) = &view.get_rows()[1];
+        assert_eq!(offset.x, 0.0);
+        let widths: Vec<f64> = row.get_buttons().iter()
+            .map(|(_x, button)| button.size.width)
+            .collect();
+        assert_eq!(widths, vec![1.0, 6.0, 1.0]);
+        // The row without growth stays centered
+        assert_eq!(view.get_rows()[0].0.x, 2.0);
+        let transformation = layout.calculate_transformation(
+            Size { width: 4.0, height: 1.0 }
+        );
+        assert_eq!(transformation.scale_x, transformation.scale_y);
+
+        // Narrower again
+        layout.grow_current_view(&Size { width: 1.0, height: 1.0 });
+        assert_eq!(layout.get_current_view().get_size().width, 4.0);
+    }
+
+    #[test]
+    fn check_stagger() {
+        let view = view![
+            row![key("q"), key("w"), key("e")],
+            row![key("a"), key("s"), key("d")],
+            row![key("space")],
+        ].into_view().with_stagger(vec![0.0, 0.5]);
+        assert_eq!(view.get_size().width, 3.5);
+        let offsets: Vec<f64> = view.get_rows().iter()
+            .map(|(offset, _row)| offset.x)
+            .collect();
+        assert_eq!(offsets, vec![0.0, 0.5, 1.25]);
+    }
+
+    #[test]
+    fn rearrange_buttons() {
+        let mut view = view![
+            row![key("q"), key("w"), key("e")],
+            row![key("space")],
+        ].into_view();
+        assert!(view.swap_buttons("q", "space"));
+        assert!(view.hide_button("w"));
+        assert!(!view.hide_button("x"));
+        let rows: Vec<Vec<(f64, String)>> = view.get_rows().iter()
+            .map(|(_offset, row)| row.get_buttons().iter()
+                .map(|(x, button)| (*x, button.name.to_str().unwrap().to_owned()))
+                .collect()
+            )
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![(0.0, "space".to_owned()), (1.0, "e".to_owned())],
+                vec![(0.0, "q".to_owned())],
+            ],
+        );
+        assert_eq!(view.get_size().width, 2.0);
+    }
+
+    fn make_layout_with_views(views: Vec<&str>) -> Layout {
+        let view = View::new(vec![]);
+        Layout {
+            current_view: "base".into(),
+            view_latched: LatchedState::Not,
+            view_preselected: false,
+            peek: None,
+            chord: None,
+            steno: steno::Machine::default(),
+            braille: braille::Translator::default(),
+            t9: t9::Composer::default(),
+            sensitive: false,
+            text_field: None,
+            keymaps: Vec::new(),
+            kind: ArrangementKind::Base,
+            keys: KeyStore::new(),
+            pressed_keys: HashSet::new(),
+            last_touch: None,
+            touch: None,
+            ripples: Vec::new(),
+            menu: Vec::new(),
+            margins: Margins {
+                top: 0.0,
+                left: 0.0,
+                right: 0.0,
+                bottom: 0.0,
+            },
+            views: views.into_iter()
+                .map(|name| (
+                    name.into(),
+                    (c::Point { x: 0.0, y: 0.0 }, view.clone().into()),
+                ))
+                .collect(),
+            purpose: ContentPurpose::Normal,
+        }
+    }
+
+    #[test]
+    fn carry_latched_view() {
+        let mut old = make_layout_with_views(vec!["base", "upper"]);
+        old.current_view = "upper".into();
+        old.view_latched = LatchedState::FromView("base".into());
+
+        let mut new = make_layout_with_views(vec!["base", "upper", "numbers"]);
+        new.carry_view_from(&old);
+        assert_eq!(&new.current_view, "upper");
+        assert_eq!(new.view_latched, LatchedState::FromView("base".into()));
+
+        let mut new = make_layout_with_views(vec!["base"]);
+        new.carry_view_from(&old);
+        assert_eq!(&new.current_view, "base");
+        assert_eq!(new.view_latched, LatchedState::Not);
+    }
+
+    #[test]
+    fn preselected_view_stays() {
+        let mut old = make_layout_with_views(vec!["base", "upper"]);
+        old.current_view = "upper".into();
+
+        let mut new = make_layout_with_views(vec!["base", "upper"]);
+        new.preselect_case(LetterCase::Lower);
+        new.carry_view_from(&old);
+        assert_eq!(&new.current_view, "base");
+
+        let mut new = make_layout_with_views(vec!["base", "upper"]);
+        new.preselect_case(LetterCase::Title);
+        assert_eq!(&new.current_view, "upper");
+        assert_eq!(new.view_latched, LatchedState::FromView("base".into()));
+
+        let mut new = make_layout_with_views(vec!["base"]);
+        new.preselect_case(LetterCase::Upper);
+        new.carry_view_from(&old);
+        assert_eq!(&new.current_view, "base");
+        assert_eq!(new.view_preselected, false);
+    }
+
+    #[test]
+    fn lazy_view_built_on_switch() {
+        let mut layout = make_layout_with_views(vec!["base"]);
+        layout.views.insert("upper".into(), (
+            c::Point { x: 0.0, y: 0.0 },
+            LazyView::Pending {
+                size: Size { width: 10.0, height: 10.0 },
+                row_count: 0,
+                build: Box::new(|| View::new(Vec::new())),
+            },
+        ));
+        assert_eq!(layout.get_row_count("upper"), Some(0));
+        assert!(layout.views["upper"].1.get().is_none());
+        layout.preselect_case(LetterCase::Upper);
+        assert_eq!(&layout.current_view, "upper");
+        assert!(layout.views["upper"].1.get().is_some());
+    }
+
+    #[test]
+    fn pressed_button_names() {
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view![row![key("a"), key("b")]]),
+        ]);
+        let pressed = find_key(&layout, "b");
+        assert_eq!(layout.get_pressed_button_names(), Vec::<String>::new());
+        layout.pressed_keys.insert(pressed);
+        assert_eq!(layout.get_pressed_button_names(), vec!["b".to_owned()]);
+    }
+
+    /// Layouts can get built away from the UI thread.
+    #[test]
+    fn layout_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Layout>();
+        assert_send::<LayoutData>();
+    }
+
+    #[test]
+    fn ripple_progress() {
+        let started = Instant::now();
+        let ripple = Ripple {
+            center: c::Point { x: 1.0, y: 1.0 },
+            bounds: c::Bounds { x: 0.0, y: 0.0, width: 2.0, height: 2.0 },
+            view: "base".into(),
+            started,
+        };
+        assert_eq!(ripple.get_progress(started), Some(0.0));
+        assert_eq!(
+            ripple.get_progress(started + Ripple::DURATION / 2),
+            Some(0.5),
+        );
+        assert_eq!(ripple.get_progress(started + Ripple::DURATION), None);
+    }
+
+    #[test]
+    fn peek_view() {
+        let view = || view![row![key("switch").set_view("numbers")]];
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view()),
+            ("numbers", view()),
+        ]);
+        let key = find_key(&layout, "switch");
+
+        // Tap
+        assert_eq!(layout.start_peek(key, Timestamp(1000)), true);
+        assert_eq!(&layout.current_view, "numbers");
+        assert_eq!(layout.end_peek(Timestamp(1100)), false);
+        assert_eq!(&layout.current_view, "numbers");
+
+        layout.current_view = "base".into();
+        // Hold
+        assert_eq!(layout.start_peek(key, Timestamp(2000)), true);
+        assert_eq!(&layout.current_view, "numbers");
+        assert_eq!(layout.end_peek(Timestamp(2500)), true);
+        assert_eq!(&layout.current_view, "base");
+
+        // Reach another key
+        layout.start_peek(key, Timestamp(3000));
+        layout.peek.as_mut().unwrap().used = true;
+        assert_eq!(layout.end_peek(Timestamp(3100)), true);
+        assert_eq!(&layout.current_view, "base");
+    }
+
+    #[test]
+    fn chord_modifier() {
+        let mut keys = KeyStore::new();
+        let key = make_state_with_action(
+            &mut keys,
+            Action::ApplyModifier(Modifier::Control),
+        );
+        let chord = |was_active, used| Chord {
+            key,
+            modifier: Modifier::Control,
+            was_active,
+            used,
+        };
+        // Ctrl held for Ctrl+C
+        assert_eq!(chord(false, true).keeps_modifier(), false);
+        assert_eq!(chord(true, true).keeps_modifier(), true);
+        // Nothing else pressed, so it's like a tap
+        assert_eq!(chord(false, false).keeps_modifier(), true);
+        assert_eq!(chord(true, false).keeps_modifier(), false);
+    }
+
+    #[test]
+    fn send_key() {
+        let enter = Action::Submit {
+            text: None,
+            keys: vec![KeySym("Return".into())],
+        };
+        let mut layout = layout_dsl::layout(vec![
+            ("base", view![row![key("a"), key("Return").action(enter)]]),
+        ]);
+        layout.set_return_key(ReturnKey::SendHoldForNewline);
+        let id = find_key(&layout, "Return");
+        assert_eq!(layout.keys.get(id).action, Action::SendOrNewline);
+        let (_offset, row) = &layout.get_current_view().get_rows()[0];
+        let labels: Vec<&Label> = row.get_buttons().iter()
+            .map(|(_offset, button)| &button.label)
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                &Label::Text(Interned::new("a").unwrap()),
+                &Label::IconName(Interned::new(send_key::SEND_ICON).unwrap()),
+            ],
+        );
+    }
 }
@@ -0,0 +1,174 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Short descriptions of layouts for tests.
+ *
+ * ```
+ * let layout = layout(vec![
+ *     ("base", view![
+ *         row![key("a"), key("Shift").lock("upper")],
+ *         row![key("space").size(2.0, 1.0)],
+ *     ]),
+ * ]);
+ * ```
+ *
+ * Keys are placed left to right, and rows top to bottom,
+ * like the layout files do.
+ * Keys with the same name share their state, also across views.
+ */
+
+use crate::action::Action;
+use crate::data::parsing::add_offsets;
+use crate::imservice::ContentPurpose;
+use crate::keyboard::{ KeyState, KeyStateId, KeyStore, PressType };
+use crate::layout::{
+    ArrangementKind, Button, Label, Layout, LayoutData, Margins, Row, Size, View,
+};
+use crate::layout::c::Point;
+use crate::util::Interned;
+use std::collections::HashMap;
+
+
+/// A row of keys, left to right
+macro_rules! row {
+    ($($key:expr),* $(,)*) => { vec![$($key),*] };
+}
+
+/// A view from its rows, top to bottom
+macro_rules! view {
+    ($($row:expr),* $(,)*) => {
+        $crate::layout_dsl::ViewSpec { rows: vec![$($row),*] }
+    };
+}
+
+#[derive(Clone)]
+pub struct KeySpec {
+    name: String,
+    action: Action,
+    size: Size,
+    growth: f64,
+}
+
+/// A 1x1 key typing nothing
+pub fn key(name: &str) -> KeySpec {
+    KeySpec {
+        name: name.into(),
+        action: Action::Submit { text: None, keys: Vec::new() },
+        size: Size { width: 1.0, height: 1.0 },
+        growth: 0.0,
+    }
+}
+
+impl KeySpec {
+    pub fn action(self, action: Action) -> KeySpec {
+        KeySpec { action, ..self }
+    }
+
+    /// Latches the view, and locks it on a second press.
+    /// Unlocks to "base".
+    pub fn lock(self, view: &str) -> KeySpec {
+        self.action(Action::LockView {
+            lock: view.into(),
+            unlock: "base".into(),
+            latches: true,
+            looks_locked_from: Vec::new(),
+        })
+    }
+
+    pub fn set_view(self, view: &str) -> KeySpec {
+        self.action(Action::SetView(view.into()))
+    }
+
+    pub fn size(self, width: f64, height: f64) -> KeySpec {
+        KeySpec { size: Size { width, height }, ..self }
+    }
+
+    pub fn grow(self, growth: f64) -> KeySpec {
+        KeySpec { growth, ..self }
+    }
+}
+
+pub struct ViewSpec {
+    pub rows: Vec<Vec<KeySpec>>,
+}
+
+impl ViewSpec {
+    /// The first key with the name decides the action.
+    fn build(self, keys: &mut KeyStore, ids: &mut HashMap<String, KeyStateId>) -> View {
+        let rows = self.rows.into_iter().map(|row| {
+            let buttons = row.into_iter().map(|spec| {
+                let key = *ids.entry(spec.name.clone())
+                    .or_insert_with(|| keys.add(KeyState {
+                        pressed: PressType::Released,
+                        keycodes: Vec::new(),
+                        action: spec.action.clone(),
+                        behavior: Default::default(),
+                    }));
+                let name = Interned::new(&spec.name).unwrap();
+                Box::new(Button {
+                    name: name.clone(),
+                    label: Label::Text(name),
+                    label_name: None,
+                    size: spec.size,
+                    outline_name: Interned::new("default").unwrap(),
+                    corners: None,
+                    growth: spec.growth,
+                    key,
+                })
+            });
+            Row::new(add_offsets(buttons, |button| button.size.width).collect())
+        });
+        View::new(add_offsets(rows, |row| row.get_size().height).collect())
+    }
+
+    /// For checking a view alone
+    pub fn into_view(self) -> View {
+        self.build(&mut KeyStore::new(), &mut HashMap::new())
+    }
+}
+
+/// Views are centered on each other, and "base" is shown first.
+pub fn layout(views: Vec<(&str, ViewSpec)>) -> Layout {
+    let mut keys = KeyStore::new();
+    let mut ids = HashMap::new();
+    let views: Vec<(String, View)> = views.into_iter()
+        .map(|(name, spec)| (name.into(), spec.build(&mut keys, &mut ids)))
+        .collect();
+    let total = View::calculate_super_size(
+        views.iter().map(|(_name, view)| view.get_size()).collect()
+    );
+    let views = views.into_iter()
+        .map(|(name, view)| {
+            let size = view.get_size();
+            let offset = Point {
+                x: (total.width - size.width) / 2.0,
+                y: (total.height - size.height) / 2.0,
+            };
+            (name, (offset, view.into()))
+        })
+        .collect();
+    Layout::new(
+        LayoutData {
+            views,
+            keys,
+            menu: Vec::new(),
+            keymaps: Vec::new(),
+            margins: Margins { top: 0.0, left: 0.0, right: 0.0, bottom: 0.0 },
+            floating: None,
+        },
+        ArrangementKind::Base,
+        ContentPurpose::Normal,
+    )
+}
+
+/// The key behind the first button with the name, in any built view
+pub fn find_key(layout: &Layout, name: &str) -> KeyStateId {
+    layout.views.values()
+        .filter_map(|(_offset, view)| view.get())
+        .flat_map(|view| view.get_rows())
+        .flat_map(|(_offset, row)| row.get_buttons())
+        .find(|(_offset, button)| button.name.to_str() == Ok(name))
+        .map(|(_offset, button)| button.key)
+        .expect("No such key")
+}
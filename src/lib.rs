@@ -19,31 +19,82 @@ extern crate zvariant;
 #[cfg(test)]
 #[macro_use]
 mod assert_matches;
+#[cfg(test)]
+#[macro_use]
+mod layout_dsl;
 #[macro_use]
 mod logging;
 
 mod action;
 mod actors;
 mod animation;
+mod announce;
+mod automation;
+#[cfg(test)]
+mod benches;
+mod braille;
+mod calculator;
+mod clock;
+mod command_socket;
+mod crash;
 pub mod data;
 mod debug;
 mod drawing;
+mod erase;
 mod event_loop;
+mod flash;
 pub mod float_ord;
+mod gestures;
+pub mod golden;
+mod handwriting;
+mod ibus;
 pub mod imservice;
+mod input_popup;
+mod inspect;
 mod keyboard;
+mod kiosk;
+mod labels;
 mod layout;
 mod locale;
+mod long_press;
 mod main;
+mod memory;
+mod morse;
+mod notification;
+mod options;
 mod outputs;
 mod panel;
+mod persist;
 mod popover;
+mod portals;
+mod preview;
+mod rearrange;
 mod receiver;
+mod remote;
+mod repeat;
 mod resources;
+mod send_key;
+mod sensors;
+mod settings;
+mod sound;
+mod spelling;
 mod state;
+#[cfg(test)]
+mod state_check;
+mod statistics;
+mod steno;
+mod stuck_keys;
 mod style;
 mod submission;
+mod t9;
+mod template;
 pub mod tests;
+mod text_field;
+pub mod trace;
 pub mod util;
 mod vkeyboard;
+mod voice;
+mod watchdog;
 mod xdg;
+#[cfg(feature = "xtest")]
+mod xtest;
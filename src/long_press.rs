@@ -0,0 +1,145 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Offering alternative text when a key is held.
+ *
+ * Buttons with `long_press` in the layout type their text on press as usual.
+ * Held for a little longer, they open a popover with the alternatives,
+ * and the one chosen replaces the text typed on press.
+ *
+ * Without a language key, holding the space bar opens the layout chooser
+ * in the same way.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::ffi::CString;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::actors;
+use crate::clock::{ Clock, System };
+use crate::keyboard::KeyStateId;
+use crate::layout;
+use crate::layout::MenuEntry;
+use crate::layout::c::{ Bounds, EekGtkKeyboard };
+use crate::logging;
+use crate::popover;
+use crate::receiver;
+use crate::submission::{ Submission, Timestamp };
+
+// Traits
+use crate::logging::Warn;
+
+
+const LONG_PRESS_AFTER: Duration = Duration::from_millis(500);
+
+thread_local! {
+    /// Changes on every press of a key with alternatives,
+    /// to tell stale timers apart.
+    static GENERATION: Cell<u32> = Cell::new(0);
+}
+
+fn choose(submission: &Rc<RefCell<Submission>>, typed: &CString, choice: &str) {
+    let choice = match CString::new(choice)
+        .or_print(logging::Problem::Warning, "Alternative contains a null byte")
+    {
+        Some(choice) => choice,
+        None => return,
+    };
+    let result = submission.borrow_mut()
+        .replace_text(&typed.to_string_lossy(), &choice);
+    if result.is_err() {
+        log_print!(
+            logging::Level::Warning,
+            "No text field to receive the alternative",
+        );
+    }
+}
+
+/// Releases the key and calls `then` if it's still held after a while.
+fn after_hold<F>(
+    submission: Rc<RefCell<Submission>>,
+    key_id: KeyStateId,
+    ui_keyboard: EekGtkKeyboard,
+    time: Timestamp,
+    then: F,
+)
+    where F: Fn(Rc<RefCell<Submission>>) + 'static
+{
+    let generation = GENERATION.with(|g| {
+        g.set(g.get().wrapping_add(1));
+        g.get()
+    });
+    let submission = Rc::downgrade(&submission);
+    let check = move || {
+        let is_current = GENERATION.with(|g| g.get()) == generation;
+        let held = submission.upgrade()
+            .filter(|submission| is_current && submission.borrow().is_pressed(&key_id));
+        if let Some(submission) = held {
+            // The popover takes over the touch, so the release may never come.
+            let elapsed_ms = LONG_PRESS_AFTER.as_millis() as u32;
+            submission.borrow_mut()
+                .handle_release(key_id, Timestamp(time.0.wrapping_add(elapsed_ms)));
+            layout::emit_hold_feedback(ui_keyboard);
+            then(submission);
+        }
+        false
+    };
+    System.schedule(LONG_PRESS_AFTER, Box::new(check));
+}
+
+/// Offers the alternatives if the key is still held after a while.
+/// The position is in widget coordinates.
+pub fn start(
+    submission: Rc<RefCell<Submission>>,
+    key_id: KeyStateId,
+    ui_keyboard: EekGtkKeyboard,
+    position: Bounds,
+    typed: CString,
+    alternatives: Vec<String>,
+    time: Timestamp,
+) {
+    after_hold(submission, key_id, ui_keyboard, time, move |submission| {
+        let typed = typed.clone();
+        popover::present_choices(
+            ui_keyboard,
+            position.clone(),
+            alternatives.clone(),
+            move |choice| choose(&submission, &typed, choice),
+        );
+    });
+}
+
+/// Opens the layout chooser instead of typing
+/// if the key is still held after a while.
+/// The position is in widget coordinates.
+pub fn start_preferences(
+    submission: Rc<RefCell<Submission>>,
+    key_id: KeyStateId,
+    ui_keyboard: EekGtkKeyboard,
+    position: Bounds,
+    typed: CString,
+    popover_state: actors::popover::State,
+    app_state: receiver::State,
+    menu: Vec<MenuEntry>,
+    time: Timestamp,
+) {
+    after_hold(submission, key_id, ui_keyboard, time, move |submission| {
+        let result = submission.borrow_mut()
+            .replace_text(&typed.to_string_lossy(), &CString::default());
+        if result.is_err() {
+            log_print!(
+                logging::Level::Surprise,
+                "No text field to take back the typed text from",
+            );
+        }
+        actors::popover::show_at(
+            ui_keyboard,
+            position.clone(),
+            &popover_state,
+            app_state.clone(),
+            &menu,
+        );
+    });
+}
@@ -29,10 +29,30 @@ struct rsobjects {
 
 void register_ui_loop_handler(struct receiver *receiver, struct panel_manager *panel, struct squeek_popover *popover, EekboardContextService *hint_manager, DBusHandler *dbus_handler);
 
-struct rsobjects squeek_init(void);
+/// Command line options. Strings may be NULL.
+struct squeek_options {
+    const char *profile;
+    const char *layout;
+    const char *output;
+    /// 0 for automatic
+    uint32_t height;
+    /// File to record state events to
+    const char *trace;
+};
+
+struct rsobjects squeek_init(struct squeek_options options);
+
+uint32_t squeek_kiosk_is_enabled(void);
+
+/// Returns only in the supervised process.
+void squeek_watchdog_supervise(void);
 
 void squeek_state_send_force_visible(struct squeek_state_manager *state);
 void squeek_state_send_force_hidden(struct squeek_state_manager *state);
+void squeek_state_send_app_visibility(struct squeek_state_manager *state, uint32_t visible);
+void squeek_state_send_start_cleaning(struct squeek_state_manager *state);
 
 void squeek_state_send_keyboard_present(struct squeek_state_manager *state, uint32_t keyboard_present);
+/// Rate in keys per second, 0 for no repeating. Delay in milliseconds.
+void squeek_state_send_repeat_info(struct squeek_state_manager *state, int32_t rate, int32_t delay);
 void squeek_state_send_layout_set(struct squeek_state_manager *state, char *name, char *layout, uint32_t timestamp);
@@ -5,29 +5,76 @@
 /*! Glue for the main loop. */
 use crate::actors;
 use crate::animation;
+use crate::announce;
 use crate::debug;
+use crate::data::compose;
 use crate::data::loading;
+use crate::data::symbols;
+use crate::drawing;
+use crate::flash;
+use crate::gestures;
+use crate::labels;
+use crate::layout;
+use crate::logging;
+use crate::memory;
+use crate::morse;
+use crate::notification;
+use crate::outputs::OutputId;
 use crate::panel;
-use glib::{Continue, MainContext, PRIORITY_DEFAULT, Receiver};
+use crate::portals;
+use crate::rearrange;
+use crate::remote;
+use crate::repeat;
+use crate::repeat::RepeatInfo;
+use crate::sound;
+use crate::spelling;
+use crate::statistics;
+use crate::style;
+use glib::{Continue, MainContext, Receiver};
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+// Traits
+use crate::logging::Warn;
 
 
 mod c {
     use super::*;
     use std::ffi::CString;
     use std::os::raw::{c_char, c_void};
+    use std::panic;
+    use std::path::Path;
     use std::ptr;
     use std::rc::Rc;
     use std::time::Instant;
 
+    use crate::command_socket;
+    use crate::crash;
+    use crate::event_loop::bus::Bus;
     use crate::event_loop::driver;
+    use crate::ibus;
+    use crate::inspect;
     use crate::imservice::IMService;
     use crate::imservice::c::InputMethod;
+    use crate::kiosk;
+    use crate::options::Profile;
+    use crate::options::c::COptions;
     use crate::layout;
     use crate::outputs::Outputs;
+    use crate::persist;
+    use crate::sensors;
+    use crate::settings;
     use crate::state;
-    use crate::submission::Submission;
+    use crate::submission;
+    use crate::submission::{ KeyboardBackend, Submission };
+    use crate::logging;
+    use crate::trace;
     use crate::util::c::Wrapped;
+    use crate::vkeyboard::VirtualKeyboard;
     use crate::vkeyboard::c::ZwpVirtualKeyboardV1;
+
+    // Traits
+    use crate::logging::Warn;
     
     /// DbusHandler*
     #[repr(transparent)]
@@ -60,6 +107,7 @@ mod c {
         layer_shell: *const c_void,
         virtual_keyboard_manager: *const c_void,
         input_method_manager: *const c_void,
+        text_input_manager: *const c_void,
         outputs: Wrapped<Outputs>,
         seat: *const c_void,
         input_method: InputMethod,
@@ -72,6 +120,7 @@ mod c {
                 layer_shell: ptr::null(),
                 virtual_keyboard_manager: ptr::null(),
                 input_method_manager: ptr::null(),
+                text_input_manager: ptr::null(),
                 outputs: Wrapped::new(outputs_manager),
                 seat: ptr::null(),
                 input_method: InputMethod::null(),
@@ -88,6 +137,28 @@ mod c {
         // This should probably only get called from the gtk main loop,
         // given that dbus handler is using glib.
         fn dbus_handler_set_visible(dbus: *const DBusHandler, visible: u8);
+        fn dbus_handler_set_geometry(
+            dbus: *const DBusHandler,
+            panel: *const panel::Rect,
+            rows: *const panel::Rect,
+            row_count: usize,
+        );
+    }
+
+    /// Used when the compositor doesn't offer virtual-keyboard.
+    #[cfg(feature = "xtest")]
+    fn get_fallback_keyboard() -> Option<Box<dyn KeyboardBackend>> {
+        log_print!(
+            logging::Level::Warning,
+            "No Wayland virtual keyboard. Trying X11 XTEST instead.",
+        );
+        crate::xtest::XTest::new()
+            .map(|k| Box::new(k) as Box<dyn KeyboardBackend>)
+    }
+
+    #[cfg(not(feature = "xtest"))]
+    fn get_fallback_keyboard() -> Option<Box<dyn KeyboardBackend>> {
+        None
     }
 
     /// Creates what's possible in Rust to eliminate as many FFI calls as possible,
@@ -95,30 +166,124 @@ mod c {
     /// and that leads to suffering.
     #[no_mangle]
     pub extern "C"
-    fn squeek_init() -> RsObjects {
-        // Set up channels
-        let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    fn squeek_init(options: COptions) -> RsObjects {
+        let options = match options.to_options() {
+            Ok(options) => options,
+            Err(e) => {
+                log_print!(logging::Level::Error, "{}", e);
+                std::process::exit(1);
+            },
+        };
+        // Set up channels. The UI subscribes first, to get the commands first.
+        let bus = Bus::new();
+        let receiver = bus.subscribe_main();
+        memory::watch(bus.subscribe_main());
+        // Before the loop starts, so that the first layout is not missed
+        let debug_commands = bus.subscribe();
+        let remote_commands = bus.subscribe();
+        let socket_commands = bus.subscribe();
+        let ibus_commands = bus.subscribe();
         let now = Instant::now();
-        let state_manager = driver::Threaded::new(sender, state::Application::new(now));
+        let kiosk = kiosk::init(options.profile == Profile::Kiosk);
+        // Like traces, recent events don't belong in reports from public terminals.
+        let journal = crash::install(kiosk.is_none());
+        let initial_state = state::Application::new(now);
+        let initial_state = match (&kiosk, persist::load()) {
+            (None, Some(snapshot)) => snapshot.apply_to(initial_state),
+            _ => initial_state,
+        };
+        let trace_path = options.trace.clone();
+        let initial_state = options.apply_to(kiosk.clone(), initial_state);
+        // Traces would reveal what's going on at a public terminal.
+        let recorder = match (&kiosk, trace_path) {
+            (None, Some(path)) => trace::Recorder::create(
+                Path::new(&path),
+                &initial_state,
+                now,
+            ).or_print(
+                logging::Problem::Warning,
+                &format!("Can't record trace to {}", path),
+            ),
+            _ => None,
+        };
+        let inspector = inspect::Inspector::new();
+        let state_manager = driver::Threaded::new(
+            bus,
+            initial_state,
+            recorder,
+            journal,
+            inspector.clone(),
+        );
+        let submission_journal = submission::Journal::new();
+        let statistics_clearing = statistics::init();
 
-        debug::init(state_manager.clone());
+        if kiosk.is_none() {
+            debug::init(
+                state_manager.clone(),
+                submission_journal.clone(),
+                statistics_clearing,
+                inspector.clone(),
+                debug_commands,
+            );
+        }
+        settings::watch(state_manager.clone());
+        sensors::init(state_manager.clone());
 
         let outputs = Outputs::new(state_manager.clone());
         let mut wayland = Box::new(Wayland::new(outputs));
         let wayland_raw = &mut *wayland as *mut _;
         unsafe { init_wayland(wayland_raw); }
 
-        let vk = wayland.virtual_keyboard;
+        let keyboard: Box<dyn KeyboardBackend> = if wayland.virtual_keyboard.is_null() {
+            match get_fallback_keyboard() {
+                Some(keyboard) => keyboard,
+                None => {
+                    log_print!(
+                        logging::Level::Error,
+                        "No virtual keyboard available, can't type.",
+                    );
+                    std::process::exit(1);
+                },
+            }
+        } else {
+            Box::new(VirtualKeyboard::new(wayland.virtual_keyboard))
+        };
 
         let imservice = if wayland.input_method.is_null() {
+            if wayland.text_input_manager.is_null() {
+                log_print!(
+                    logging::Level::Warning,
+                    "No input method. Typing through virtual keyboard only.",
+                );
+            } else {
+                log_print!(
+                    logging::Level::Warning,
+                    "The compositor offers text-input-v3 but no input method. \
+                    Typing through virtual keyboard only.",
+                );
+            }
+            // Nothing will report text fields getting focus,
+            // so the panel only appears when the user asks.
+            log_print!(
+                logging::Level::Info,
+                "Visibility is manual, e.g. through sm.puri.OSK0.SetVisible",
+            );
             None
         } else {
             Some(IMService::new(wayland.input_method, state_manager.clone()))
         };
-        let submission = Submission::new(vk, imservice);
-        
+        let submission = Wrapped::new(
+            Submission::new(keyboard, imservice, submission_journal, inspector)
+        );
+        // Strangers would be typing at a public terminal.
+        if kiosk.is_none() {
+            remote::init(remote_commands, submission.clone_ref());
+            command_socket::init(socket_commands, submission.clone_ref());
+        }
+        ibus::init(ibus_commands, submission.clone_ref());
+
         RsObjects {
-            submission: Wrapped::new(submission),
+            submission,
             state_manager: Wrapped::new(state_manager),
             receiver: Wrapped::new(receiver),
             wayland: Box::into_raw(wayland),
@@ -145,13 +310,21 @@ mod c {
         receiver.attach(
             Some(&ctx),
             move |msg| {
-                main_loop_handle_message(
-                    msg,
-                    panel_manager.clone(),
-                    &popover,
-                    hint_manager,
-                    dbus_handler,
-                );
+                let handled = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    main_loop_handle_message(
+                        msg,
+                        panel_manager.clone(),
+                        &popover,
+                        hint_manager,
+                        dbus_handler,
+                    )
+                }));
+                // Panicking through C would abort.
+                // Instead, the panel gets created anew.
+                if handled.is_err() {
+                    log_print!(logging::Level::Warning, "Restarting the panel after a crash");
+                    panel::Manager::restart(panel_manager.clone());
+                }
                 Continue(true)
             },
         );
@@ -170,6 +343,15 @@ mod c {
         hint_manager: HintManager,
         dbus_handler: *const DBusHandler,
     ) {
+        if let Some(seconds_left) = msg.cleaning {
+            layout::set_cleaning(seconds_left);
+            panel::Manager::queue_redraw(&panel_manager);
+        }
+
+        if let Some(handle) = msg.edge_handle {
+            panel::Manager::set_edge_handle(&panel_manager, handle);
+        }
+
         if let Some(visibility) = msg.panel_visibility {
             panel::Manager::update(panel_manager, visibility);
         }
@@ -179,19 +361,38 @@ mod c {
                 unsafe { dbus_handler_set_visible(dbus_handler, visible as u8) };
             }
         }
+
+        if let Some(geometry) = msg.dbus_geometry_set {
+            if dbus_handler != std::ptr::null() {
+                // Hidden takes up no space.
+                let geometry = geometry.unwrap_or(panel::Geometry {
+                    panel: panel::Rect { x: 0, y: 0, width: 0, height: 0 },
+                    rows: Vec::new(),
+                });
+                unsafe {
+                    dbus_handler_set_geometry(
+                        dbus_handler,
+                        &geometry.panel,
+                        geometry.rows.as_ptr(),
+                        geometry.rows.len(),
+                    )
+                };
+            }
+        }
         
         if let Some(commands::SetLayout { description }) = msg.layout_selection {
-            let animation::Contents {
-                name,
-                kind,
-                overlay_name,
-                purpose,
-            } = description;
-            actors::popover::set_overlay(popover, overlay_name.clone());
-            let layout = loading::load_layout(&name, kind, purpose, &overlay_name);
+            actors::popover::set_overlay(popover, description.overlay_name.clone());
+            let (layout, substitute) = load_layout(&description);
+            if let Some(loading::Substitute { requested }) = substitute {
+                notification::layout_missing(&requested, loading::get_layouts_dir());
+            }
             let layout = Box::into_raw(Box::new(layout));
+            let animation::Contents { name, overlay_name, .. } = description;
+            announce::layout_changed(&name, overlay_name.as_ref().map(String::as_str));
+            let name = overlay_name.unwrap_or(name);
+            statistics::set_layout(&name);
             // CSS can't express "+" in the class
-            let name = overlay_name.unwrap_or(name).replace('+', "_");
+            let name = name.replace('+', "_");
             let name = CString::new(name).unwrap_or(
                 CString::new("").unwrap()
             );
@@ -201,6 +402,146 @@ mod c {
                 eekboard_context_service_set_layout(hint_manager, name, layout, 0);
             }
         }
+
+        if let Some(enabled) = msg.debug_overlay {
+            drawing::set_debug_overlay(enabled);
+        }
+
+        if let Some(commands::SetTheme { name }) = msg.theme {
+            style::set_theme_override(name);
+        }
+
+        if let Some(enabled) = msg.feedback {
+            layout::set_feedback(enabled);
+        }
+
+        if let Some(percent) = msg.sound_volume {
+            sound::set_volume(percent);
+        }
+
+        if let Some(enabled) = msg.visual_feedback {
+            flash::set_enabled(enabled);
+        }
+
+        if let Some(enabled) = msg.spell_check {
+            spelling::set_enabled(enabled);
+        }
+
+        if let Some(mapping) = msg.gestures {
+            gestures::set_mapping(mapping);
+        }
+
+        if let Some(enabled) = msg.statistics {
+            statistics::set_enabled(enabled);
+        }
+
+        if let Some(enabled) = msg.announce {
+            announce::set_enabled(enabled);
+        }
+
+        if let Some(allowed) = msg.allow_screenshots {
+            portals::set_allowed(allowed);
+        }
+
+        if let Some(gap) = msg.morse_letter_gap {
+            morse::set_letter_gap(gap);
+        }
+
+        if let Some(info) = msg.repeat {
+            repeat::set(info);
+        }
+    }
+}
+
+/// Loads the layout as described by the state.
+/// Also reports if the layout had to be replaced by the default one.
+pub fn load_layout(description: &animation::Contents)
+    -> (layout::Layout, Option<loading::Substitute>)
+{
+    let animation::Contents {
+        name,
+        kind,
+        overlay_name,
+        purpose,
+        preview,
+        generation: _,
+        scramble,
+        bottom_row,
+        symbol_query,
+        text_field,
+        compose_sequence,
+        letter_case,
+        sensitive,
+        return_key,
+    } = description;
+    let (kind, purpose) = (*kind, *purpose);
+    let rearranged_name = match (preview, scramble, overlay_name) {
+        (None, false, None) => Some(name.as_str()),
+        _ => None,
+    };
+    let preview = preview.as_ref().and_then(|data|
+        loading::load_layout_from_str(data, kind, purpose, bottom_row)
+            .or_print(logging::Problem::Warning, "Can't preview layout")
+    );
+    let (mut layout, substitute) = match preview {
+        Some(layout) => (layout, None),
+        None if *scramble => (loading::load_scrambled_pin(kind), None),
+        None if overlay_name.as_ref().map(String::as_str)
+            == Some(symbols::OVERLAY_NAME)
+        => (
+            loading::load_symbols(
+                kind,
+                purpose,
+                symbol_query.as_ref().map(String::as_str),
+            ),
+            None,
+        ),
+        None if overlay_name.as_ref().map(String::as_str)
+            == Some(compose::OVERLAY_NAME)
+        => (loading::load_compose(kind, purpose, compose_sequence), None),
+        None => loading::load_layout(name, kind, purpose, overlay_name, bottom_row),
+    };
+    labels::translate(&mut layout);
+    rearrange::apply(&mut layout, rearranged_name);
+    if let Some(case) = letter_case {
+        layout.preselect_case(*case);
+    }
+    layout.sensitive = *sensitive;
+    layout.text_field = *text_field;
+    // The keyboard's own text fields have their own uses for Return.
+    if text_field.is_none() {
+        layout.set_return_key(*return_key);
+    }
+    (layout, substitute)
+}
+
+/// The layout shown on the panel, loaded again for a thread other than the UI.
+/// Scrambled PIN layouts are not given out, because the order must stay secret.
+pub struct LayoutCopy {
+    /// What the panel was last told to show
+    shown: Arc<Mutex<Option<animation::Contents>>>,
+    loaded: Option<(animation::Contents, layout::Layout)>,
+}
+
+impl LayoutCopy {
+    pub fn new(shown: Arc<Mutex<Option<animation::Contents>>>) -> LayoutCopy {
+        LayoutCopy { shown, loaded: None }
+    }
+
+    /// Loads the layout again only if it changed.
+    pub fn get(&mut self) -> Option<&mut layout::Layout> {
+        let shown = self.shown.lock().unwrap().clone()?;
+        if shown.scramble {
+            return None;
+        }
+        let is_loaded = self.loaded.as_ref()
+            .map(|(contents, _)| *contents == shown)
+            .unwrap_or(false);
+        if !is_loaded {
+            let (layout, _substitute) = load_layout(&shown);
+            self.loaded = Some((shown, layout));
+        }
+        self.loaded.as_mut().map(|(_, layout)| layout)
     }
 }
 
@@ -210,13 +551,47 @@ pub mod commands {
     pub struct SetLayout {
         pub description: animation::Contents,
     }
+
+    #[derive(Clone, Debug)]
+    pub struct SetTheme {
+        /// None follows the GTK theme
+        pub name: Option<String>,
+    }
 }
 
 /// The commands consumed by the main loop,
 /// to be sent out to external components.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Commands {
     pub panel_visibility: Option<panel::Command>,
     pub dbus_visible_set: Option<bool>,
+    /// None when there's no panel
+    pub dbus_geometry_set: Option<Option<panel::Geometry>>,
     pub layout_selection: Option<commands::SetLayout>,
+    /// Draw hit boxes and layout state over the keyboard
+    pub debug_overlay: Option<bool>,
+    pub theme: Option<commands::SetTheme>,
+    pub feedback: Option<bool>,
+    /// Percentage of the full volume of key sounds
+    pub sound_volume: Option<u32>,
+    /// Flash keys and the keyboard's border
+    pub visual_feedback: Option<bool>,
+    pub spell_check: Option<bool>,
+    pub allow_screenshots: Option<bool>,
+    pub morse_letter_gap: Option<Duration>,
+    /// Handled by the remote keyboard's own subscription
+    pub remote_keyboard: Option<remote::Access>,
+    /// Handled by the command socket's own subscription
+    pub command_socket: Option<bool>,
+    /// Handled by the IBus bridge's own subscription
+    pub ibus_engine: Option<Option<String>>,
+    pub statistics: Option<bool>,
+    /// Speak view and layout changes to screen readers
+    pub announce: Option<bool>,
+    pub gestures: Option<gestures::Mapping>,
+    /// Seconds left of ignoring input, or nothing to accept it again
+    pub cleaning: Option<Option<u64>>,
+    /// Output to show the edge handle on, or nothing to remove it
+    pub edge_handle: Option<Option<OutputId>>,
+    pub repeat: Option<RepeatInfo>,
 }
@@ -0,0 +1,138 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Keeping the memory use low while nobody types.
+ *
+ * Phones with little memory kill background programs
+ * to make space for the one in use.
+ * The compose sequences, the symbol database, the spell checking word list,
+ * and the steno dictionary take the most space, and are only needed while typing.
+ * When the panel stays hidden for a while, they get dropped,
+ * and they load again on next use.
+ *
+ * Layouts are not kept beyond the current one,
+ * and drawing happens without caching surfaces, so there's nothing to drop there.
+ *
+ * The sizes of the caches can be read over the debug D-Bus interface.
+ */
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Duration;
+
+use crate::data::{ compose, symbols };
+use crate::logging;
+use crate::main::Commands;
+use crate::panel;
+use crate::spelling;
+use crate::steno;
+
+
+/// How long the panel must stay hidden before the caches go
+const TRIM_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Cache {
+    Compose = 0,
+    Symbols = 1,
+    WordList = 2,
+    Steno = 3,
+}
+
+const CACHE_NAMES: [&str; 4] = ["Compose", "Symbols", "WordList", "Steno"];
+
+/// Entries in each cache.
+/// Written by the UI thread, and read by the debug interface thread.
+static SIZES: [AtomicUsize; 4] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Call whenever the cache gets filled or emptied.
+pub fn set_size(cache: Cache, entries: usize) {
+    SIZES[cache as usize].store(entries, Ordering::Relaxed);
+}
+
+/// Entries in each cache, by cache name.
+pub fn get_sizes() -> HashMap<String, u64> {
+    CACHE_NAMES.iter()
+        .zip(SIZES.iter())
+        .map(|(name, size)| (name.to_string(), size.load(Ordering::Relaxed) as u64))
+        .collect()
+}
+
+fn trim() {
+    compose::drop_cache();
+    symbols::drop_cache();
+    spelling::drop_word_list();
+    steno::drop_cache();
+    log_print!(logging::Level::Debug, "Dropped caches while hidden");
+}
+
+thread_local! {
+    static HIDDEN: Cell<bool> = Cell::new(true);
+    /// Changes on every hiding, to tell stale timers apart.
+    static GENERATION: Cell<u32> = Cell::new(0);
+}
+
+/// Repeated commands don't restart the countdown.
+fn update_visibility(command: &panel::Command) {
+    let hidden = match command {
+        panel::Command::Hide => true,
+        panel::Command::Show { .. } => false,
+    };
+    if HIDDEN.with(|h| h.replace(hidden)) == hidden {
+        return;
+    }
+    let generation = GENERATION.with(|g| {
+        let next = g.get().wrapping_add(1);
+        g.set(next);
+        next
+    });
+    if !hidden {
+        return;
+    }
+    let on_timeout = move || {
+        let is_current = GENERATION.with(|g| g.get()) == generation;
+        if is_current && HIDDEN.with(|h| h.get()) {
+            trim();
+        }
+        glib::Continue(false)
+    };
+
+    #[cfg(feature = "glib_v0_14")]
+    glib::timeout_add_local(TRIM_DELAY, on_timeout);
+    #[cfg(not(feature = "glib_v0_14"))]
+    glib::timeout_add_local(TRIM_DELAY.as_millis() as u32, on_timeout);
+}
+
+/// Follows the panel visibility from the command bus.
+pub fn watch(receiver: glib::Receiver<Commands>) {
+    let ctx = glib::MainContext::default();
+    let _acquired = ctx.acquire();
+    receiver.attach(Some(&ctx), |commands| {
+        if let Some(command) = commands.panel_visibility {
+            update_visibility(&command);
+        }
+        glib::Continue(true)
+    });
+    #[cfg(not(feature = "glib_v0_14"))]
+    ctx.release();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        set_size(Cache::Symbols, 12);
+        let sizes = get_sizes();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes["Symbols"], 12);
+    }
+}
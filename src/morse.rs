@@ -0,0 +1,193 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Typing with two keys, by Morse code.
+ *
+ * Meant for switch access, where pressing one of two big keys
+ * is easier than aiming at a small one.
+ * Keys with the `morse` action add a dot or a dash to the sequence.
+ * Once no key comes for the letter gap, the sequence gets decoded
+ * and the character committed.
+ * After three letter gaps, a space follows.
+ *
+ * Every signal buzzes, and dashes buzz twice, to tell them apart without looking.
+ * The letter gap comes from the settings.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::ffi::CString;
+use std::rc::Rc;
+use std::time::Duration;
+
+use serde::{ Deserialize, Serialize };
+
+use crate::layout;
+use crate::layout::c::EekGtkKeyboard;
+use crate::logging;
+use crate::sound::Sound;
+use crate::submission::Submission;
+
+
+pub const DEFAULT_LETTER_GAP: Duration = Duration::from_secs(1);
+
+/// Between the two buzzes of a dash
+const DASH_ECHO_DELAY: Duration = Duration::from_millis(150);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Signal {
+    Dot,
+    Dash,
+}
+
+const CODES: &[(&str, char)] = &[
+    (".-", 'a'), ("-...", 'b'), ("-.-.", 'c'), ("-..", 'd'), (".", 'e'),
+    ("..-.", 'f'), ("--.", 'g'), ("....", 'h'), ("..", 'i'), (".---", 'j'),
+    ("-.-", 'k'), (".-..", 'l'), ("--", 'm'), ("-.", 'n'), ("---", 'o'),
+    (".--.", 'p'), ("--.-", 'q'), (".-.", 'r'), ("...", 's'), ("-", 't'),
+    ("..-", 'u'), ("...-", 'v'), (".--", 'w'), ("-..-", 'x'), ("-.--", 'y'),
+    ("--..", 'z'),
+    ("-----", '0'), (".----", '1'), ("..---", '2'), ("...--", '3'), ("....-", '4'),
+    (".....", '5'), ("-....", '6'), ("--...", '7'), ("---..", '8'), ("----.", '9'),
+    (".-.-.-", '.'), ("--..--", ','), ("..--..", '?'), (".----.", '\''),
+    ("-.-.--", '!'), ("-..-.", '/'), ("-.--.", '('), ("-.--.-", ')'),
+    (".-...", '&'), ("---...", ':'), ("-.-.-.", ';'), ("-...-", '='),
+    (".-.-.", '+'), ("-....-", '-'), ("..--.-", '_'), (".-..-.", '"'),
+    ("...-..-", '$'), (".--.-.", '@'),
+];
+
+fn decode(sequence: &str) -> Option<char> {
+    CODES.iter()
+        .find(|(code, _)| *code == sequence)
+        .map(|(_, character)| *character)
+}
+
+#[derive(Default)]
+struct Decoder {
+    sequence: String,
+    /// Changes on every signal, to tell stale timers apart.
+    generation: u32,
+    /// Something was typed since the last space
+    in_word: bool,
+}
+
+thread_local! {
+    static DECODER: RefCell<Decoder> = RefCell::new(Decoder::default());
+    static LETTER_GAP: Cell<Duration> = Cell::new(DEFAULT_LETTER_GAP);
+}
+
+pub fn set_letter_gap(gap: Duration) {
+    LETTER_GAP.with(|g| g.set(gap));
+}
+
+fn schedule<F: FnMut() -> glib::Continue + 'static>(after: Duration, f: F) {
+    #[cfg(feature = "glib_v0_14")]
+    glib::timeout_add_local(after, f);
+    #[cfg(not(feature = "glib_v0_14"))]
+    glib::timeout_add_local(after.as_millis() as u32, f);
+}
+
+fn commit(submission: &Rc<RefCell<Submission>>, text: &str) {
+    let text = CString::new(text).expect("Morse table contains a null byte");
+    if submission.borrow_mut().commit_text(&text).is_err() {
+        log_print!(
+            logging::Level::Warning,
+            "No text field to receive the Morse character",
+        );
+    }
+}
+
+/// Called after a letter gap without signals.
+fn end_letter(submission: &Rc<RefCell<Submission>>, generation: u32) -> bool {
+    let sequence = DECODER.with(|decoder| {
+        let mut decoder = decoder.borrow_mut();
+        match decoder.generation == generation {
+            true => Some(std::mem::replace(&mut decoder.sequence, String::new())),
+            false => None,
+        }
+    });
+    let sequence = match sequence {
+        Some(sequence) => sequence,
+        None => return false,
+    };
+    match decode(&sequence) {
+        Some(character) => {
+            commit(submission, &character.to_string());
+            DECODER.with(|decoder| decoder.borrow_mut().in_word = true);
+            true
+        },
+        None => {
+            log_print!(logging::Level::Info, "Unknown Morse sequence {}", sequence);
+            false
+        },
+    }
+}
+
+/// Called after the remaining gap between words.
+fn end_word(submission: &Rc<RefCell<Submission>>, generation: u32) {
+    let needs_space = DECODER.with(|decoder| {
+        let mut decoder = decoder.borrow_mut();
+        let needs_space = decoder.generation == generation && decoder.in_word;
+        if needs_space {
+            decoder.in_word = false;
+        }
+        needs_space
+    });
+    if needs_space {
+        commit(submission, " ");
+    }
+}
+
+/// Adds the signal to the sequence, and decodes it when no more come.
+pub fn signal(
+    submission: Rc<RefCell<Submission>>,
+    ui_keyboard: EekGtkKeyboard,
+    signal: Signal,
+) {
+    // The press buzzed already.
+    if signal == Signal::Dash {
+        schedule(DASH_ECHO_DELAY, move || {
+            layout::emit_feedback(ui_keyboard, Sound::Key);
+            glib::Continue(false)
+        });
+    }
+    let generation = DECODER.with(|decoder| {
+        let mut decoder = decoder.borrow_mut();
+        decoder.sequence.push(match signal {
+            Signal::Dot => '.',
+            Signal::Dash => '-',
+        });
+        decoder.generation = decoder.generation.wrapping_add(1);
+        decoder.generation
+    });
+    let gap = LETTER_GAP.with(|g| g.get());
+    schedule(gap, move || {
+        if end_letter(&submission, generation) {
+            let submission = submission.clone();
+            schedule(gap * 2, move || {
+                end_word(&submission, generation);
+                glib::Continue(false)
+            });
+        }
+        glib::Continue(false)
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codes_unique() {
+        for (idx, (code, _)) in CODES.iter().enumerate() {
+            assert_eq!(CODES[idx + 1..].iter().find(|(c, _)| c == code), None);
+        }
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode("...."), Some('h'));
+        assert_eq!(decode(".-.-.-"), Some('.'));
+        assert_eq!(decode("......."), None);
+    }
+}
@@ -0,0 +1,167 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Hints for the user, shown as desktop notifications.
+ *
+ * Uses org.freedesktop.Notifications directly,
+ * on a separate thread, so that a missing or slow server
+ * doesn't hold up the keyboard.
+ */
+
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet };
+use std::path::PathBuf;
+use std::thread;
+use zbus::{ Connection, fdo };
+
+use crate::logging;
+use crate::portals;
+use crate::remote;
+
+// Traits
+use crate::logging::Warn;
+
+
+const APP_NAME: &str = "Squeekboard";
+const DESTINATION: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+const ACTION_OPEN_DIR: &str = "open-layouts-dir";
+
+thread_local! {
+    /// Layouts already reported in this session.
+    /// Nagging every time the layout gets loaded again wouldn't help.
+    static REPORTED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+type Error = Box<dyn std::error::Error>;
+
+/// Shows the notification and waits for the user to react.
+fn notify_layout_missing(name: &str, layouts_dir: Option<PathBuf>)
+    -> Result<(), Error>
+{
+    let connection = Connection::new_session()?;
+    let body = match &layouts_dir {
+        Some(dir) => format!(
+            "There's no on-screen layout for \"{}\", so the default one is shown. \
+            Custom layouts can be placed in {}.",
+            name, dir.display(),
+        ),
+        None => format!(
+            "There's no on-screen layout for \"{}\", so the default one is shown.",
+            name,
+        ),
+    };
+    let actions = match layouts_dir {
+        Some(_) => vec![ACTION_OPEN_DIR, "Open layouts folder"],
+        None => vec![],
+    };
+    let hints: HashMap<&str, zvariant::Value> = HashMap::new();
+    let reply = connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(DESTINATION),
+        "Notify",
+        &(
+            APP_NAME,
+            0u32, // replaces nothing
+            "input-keyboard-symbolic",
+            "Keyboard layout missing",
+            body.as_str(),
+            actions,
+            hints,
+            -1i32, // server's default timeout
+        ),
+    )?;
+    let id: u32 = reply.body()?;
+
+    let layouts_dir = match layouts_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+
+    fdo::DBusProxy::new(&connection)?.add_match(
+        "type='signal',interface='org.freedesktop.Notifications'",
+    )?;
+    loop {
+        let message = connection.receive_message()?;
+        let member = message.header()?.member()?.map(String::from);
+        match member.as_ref().map(String::as_str) {
+            Some("ActionInvoked") => {
+                let (action_id, action): (u32, String) = message.body()?;
+                if action_id == id && action == ACTION_OPEN_DIR {
+                    open_dir(layouts_dir);
+                    return Ok(());
+                }
+            },
+            Some("NotificationClosed") => {
+                let (closed_id, _reason): (u32, u32) = message.body()?;
+                if closed_id == id {
+                    return Ok(());
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+fn open_dir(dir: PathBuf) {
+    std::fs::create_dir_all(&dir)
+        .or_print(logging::Problem::Warning, "Can't create layouts folder");
+    portals::open_folder(&dir)
+        .or_print(logging::Problem::Warning, "Can't open layouts folder");
+}
+
+/// Tells the user that the default layout got used instead of the named one,
+/// once per session.
+pub fn layout_missing(name: &str, layouts_dir: Option<PathBuf>) {
+    let is_new = REPORTED.with(|reported|
+        reported.borrow_mut().insert(name.into())
+    );
+    if is_new {
+        let name = String::from(name);
+        thread::spawn(move || {
+            notify_layout_missing(&name, layouts_dir)
+                .or_print(logging::Problem::Warning, "Can't show notification");
+        });
+    }
+}
+
+fn notify_remote_keyboard(network: bool, code: &str) -> Result<(), Error> {
+    let connection = Connection::new_session()?;
+    let place = match network {
+        true => format!("port {} of this device", remote::PORT),
+        false => format!("http://localhost:{}/", remote::PORT),
+    };
+    let body = format!(
+        "Open {} in a web browser to type from there. The pairing code is {}.",
+        place, code,
+    );
+    let hints: HashMap<&str, zvariant::Value> = HashMap::new();
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(DESTINATION),
+        "Notify",
+        &(
+            APP_NAME,
+            0u32, // replaces nothing
+            "input-keyboard-symbolic",
+            "Remote keyboard enabled",
+            body.as_str(),
+            Vec::<&str>::new(),
+            hints,
+            0i32, // stays until dismissed
+        ),
+    )?;
+    Ok(())
+}
+
+/// Gives the pairing code to the user, who enabled the remote keyboard.
+pub fn remote_keyboard_started(network: bool, code: &str) {
+    let code = String::from(code);
+    thread::spawn(move || {
+        notify_remote_keyboard(network, &code)
+            .or_print(logging::Problem::Warning, "Can't show notification");
+    });
+}
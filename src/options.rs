@@ -0,0 +1,139 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Command line options.
+ *
+ * They get parsed in C, together with GTK's own,
+ * and seed the initial state of the application.
+ */
+
+use std::str::FromStr;
+
+use crate::kiosk::Kiosk;
+use crate::state::{ Application, output };
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    Default,
+    /// Locked down, see the kiosk module
+    Kiosk,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Default
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Profile::Default),
+            "kiosk" => Ok(Profile::Kiosk),
+            other => Err(format!(
+                "Unknown profile {}, expected \"default\" or \"kiosk\"", other,
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub profile: Profile,
+    /// Layout name, replacing the one chosen in the system
+    pub layout: Option<String>,
+    /// Output name, like "DSI-1"
+    pub output: Option<String>,
+    /// Panel height in logical pixels
+    pub height: Option<u32>,
+    /// File to record state events to
+    pub trace: Option<String>,
+}
+
+impl Options {
+    /// The layout from the command line wins over the one from the kiosk config.
+    pub fn apply_to(self, kiosk: Option<Kiosk>, state: Application) -> Application {
+        Application {
+            layout_override: self.layout
+                .or_else(|| kiosk.and_then(|kiosk| kiosk.layout)),
+            outputs: output::Policy {
+                pinned: self.output,
+                ..state.outputs
+            },
+            height_override: self.height,
+            ..state
+        }
+    }
+}
+
+pub mod c {
+    use super::*;
+
+    use std::os::raw::c_char;
+    use crate::util::c::as_str;
+
+    /// Mirrors `struct squeek_options`.
+    /// Strings may be NULL, and 0 means no height.
+    #[repr(C)]
+    pub struct COptions {
+        profile: *const c_char,
+        layout: *const c_char,
+        output: *const c_char,
+        height: u32,
+        trace: *const c_char,
+    }
+
+    fn get_string(s: &*const c_char) -> Result<Option<String>, String> {
+        as_str(s)
+            .map(|s| s.map(String::from))
+            .map_err(|e| format!("Option is not UTF-8: {}", e))
+    }
+
+    impl COptions {
+        pub fn to_options(&self) -> Result<Options, String> {
+            Ok(Options {
+                profile: match get_string(&self.profile)? {
+                    Some(profile) => profile.parse()?,
+                    None => Profile::Default,
+                },
+                layout: get_string(&self.layout)?,
+                output: get_string(&self.output)?,
+                height: match self.height {
+                    0 => None,
+                    height => Some(height),
+                },
+                trace: get_string(&self.trace)?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_profile() {
+        assert_eq!("kiosk".parse(), Ok(Profile::Kiosk));
+        assert!("public".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn kiosk_layout() {
+        let start = std::time::Instant::now();
+        let kiosk = Some(Kiosk { layout: Some("de".into()) });
+        let state = Options::default()
+            .apply_to(kiosk.clone(), Application::new(start));
+        assert_eq!(state.layout_override, Some("de".into()));
+
+        let options = Options {
+            layout: Some("fr".into()),
+            ..Options::default()
+        };
+        let state = options.apply_to(kiosk, Application::new(start));
+        assert_eq!(state.layout_override, Some("fr".into()));
+    }
+}
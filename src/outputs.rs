@@ -32,6 +32,16 @@ pub mod c {
         fn null() -> Self {
             Self(ptr::null())
         }
+
+        /// A number identifying the output in traces
+        pub fn get_id(&self) -> u64 {
+            self.0 as usize as u64
+        }
+
+        /// Not a real output, only a lookup key for replaying traces.
+        pub fn from_id(id: u64) -> Self {
+            Self(id as usize as *const c_void)
+        }
     }
 
     #[repr(C)]
@@ -65,6 +75,17 @@ pub mod c {
             WlOutput,
             i32, // factor
         ),
+        // Since version 4
+        name: extern fn(
+            T, // data
+            WlOutput,
+            *const c_char, // name
+        ),
+        description: extern fn(
+            T, // data
+            WlOutput,
+            *const c_char, // description
+        ),
     }
     
     bitflags!{
@@ -90,7 +111,7 @@ pub mod c {
     }
     
     impl Transform {
-        fn from_u32(v: u32) -> Option<Transform> {
+        pub fn from_u32(v: u32) -> Option<Transform> {
             use self::Transform::*;
             match v {
                 0 => Some(Normal),
@@ -130,7 +151,7 @@ pub mod c {
         _x: i32, _y: i32,
         phys_width: i32, phys_height: i32,
         _subpixel: i32,
-        _make: *const c_char, _model: *const c_char,
+        make: *const c_char, model: *const c_char,
         transform: i32,
     ) {
         let transform = Transform::from_u32(transform as u32)
@@ -158,6 +179,7 @@ pub mod c {
                     },
                     transform,
                 });
+                state.identity = get_identity(make, model);
             },
             None => log_print!(
                 logging::Level::Warning,
@@ -166,6 +188,19 @@ pub mod c {
         };
     }
 
+    /// Compositors send "unknown" or nothing when they don't know.
+    fn get_identity(make: *const c_char, model: *const c_char) -> Option<String> {
+        let known = |value: *const c_char| ::util::c::as_str(&value)
+            .or_print(logging::Problem::Warning, "Received invalid output make or model")
+            .and_then(|value| value)
+            .filter(|value| !value.is_empty() && !value.eq_ignore_ascii_case("unknown"))
+            .map(String::from);
+        match (known(make), known(model)) {
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            _ => None,
+        }
+    }
+
     extern fn outputs_handle_mode(
         outputs: COutputs,
         wl_output: WlOutput,
@@ -212,7 +247,7 @@ pub mod c {
                 output.current = output.pending.clone();
                 Some(Event {
                     output: OutputId(wl_output),
-                    change: ChangeType::Altered(output.current),
+                    change: ChangeType::Altered(output.current.clone()),
                 })
             },
             None => {
@@ -248,6 +283,35 @@ pub mod c {
         };
     }
 
+    extern fn outputs_handle_name(
+        outputs: COutputs,
+        wl_output: WlOutput,
+        name: *const c_char,
+    ) {
+        let name = ::util::c::as_str(&name)
+            .or_print(logging::Problem::Warning, "Received invalid output name")
+            .and_then(|name| name.map(String::from));
+        let outputs = outputs.clone_ref();
+        let mut collection = outputs.borrow_mut();
+        let output_state: Option<&mut OutputState>
+            = collection
+                .find_output_mut(wl_output)
+                .map(|o| &mut o.pending);
+        match output_state {
+            Some(state) => { state.name = name; }
+            None => log_print!(
+                logging::Level::Warning,
+                "Got name on unknown output",
+            ),
+        };
+    }
+
+    extern fn outputs_handle_description(
+        _outputs: COutputs,
+        _wl_output: WlOutput,
+        _description: *const c_char,
+    ) {}
+
     // End callbacks
 
     #[no_mangle]
@@ -277,6 +341,8 @@ pub mod c {
                 mode: outputs_handle_mode,
                 done: outputs_handle_done,
                 scale: outputs_handle_scale,
+                name: outputs_handle_name,
+                description: outputs_handle_description,
             } as *const WlOutputListener<COutputs>,
             raw_collection,
         )};
@@ -341,11 +407,17 @@ pub struct Geometry {
     pub phys_size: Size<Option<Millimeter>>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct OutputState {
     pub current_mode: Option<Mode>,
     pub geometry: Option<Geometry>,
     pub scale: i32,
+    /// Like "DSI-1". Not sent by compositors before wl_output version 4.
+    pub name: Option<String>,
+    /// Make and model, which stay the same on any connector.
+    /// wl_output doesn't tell serial numbers,
+    /// so identical monitors can't be told apart.
+    pub identity: Option<String>,
 }
 
 impl OutputState {
@@ -360,6 +432,8 @@ impl OutputState {
             current_mode: None,
             geometry: None,
             scale: 1,
+            name: None,
+            identity: None,
         }
     }
 
@@ -391,7 +465,7 @@ impl OutputState {
             OutputState {
                 current_mode: Some(Mode { width, height } ),
                 geometry: Some(Geometry { transform, .. } ),
-                scale: _,
+                ..
             } => Some(Self::transform_size(*width as u32, *height as u32, *transform)),
             OutputState {
                 current_mode: Some(Mode { width, height } ),
@@ -481,14 +555,14 @@ impl Outputs {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum ChangeType {
     /// Added or changed
     Altered(OutputState),
     Removed,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Event {
     pub output: OutputId,
     pub change: ChangeType,
@@ -55,26 +55,60 @@ make_widget (struct panel_manager *self)
 }
 
 
-// Called from rust
-/// Creates a new panel widget
-void
-panel_manager_request_widget (struct panel_manager *self, struct wl_output *output, uint32_t height, struct squeek_panel_manager *mgr)
+/// Floating panels cover the whole output, over fullscreen applications,
+/// and leave the space to them.
+static PhoshLayerSurface *
+make_window (struct wl_output *output, uint32_t height, gboolean floating)
 {
-    if (!self->window) {
-        self->window = g_object_new (
+    if (floating) {
+        return g_object_new (
             PHOSH_TYPE_LAYER_SURFACE,
             "layer-shell", squeek_wayland->layer_shell,
             "wl-output", output,
-            "height", height,
-            "anchor", ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM
+            "height", 0,
+            "anchor", ZWLR_LAYER_SURFACE_V1_ANCHOR_TOP
+            | ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM
             | ZWLR_LAYER_SURFACE_V1_ANCHOR_LEFT
             | ZWLR_LAYER_SURFACE_V1_ANCHOR_RIGHT,
-            "layer", ZWLR_LAYER_SHELL_V1_LAYER_TOP,
+            "layer", ZWLR_LAYER_SHELL_V1_LAYER_OVERLAY,
             "kbd-interactivity", FALSE,
-            "exclusive-zone", height,
+            "exclusive-zone", 0,
             "namespace", "osk",
             NULL
         );
+    }
+    return g_object_new (
+        PHOSH_TYPE_LAYER_SURFACE,
+        "layer-shell", squeek_wayland->layer_shell,
+        "wl-output", output,
+        "height", height,
+        "anchor", ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM
+        | ZWLR_LAYER_SURFACE_V1_ANCHOR_LEFT
+        | ZWLR_LAYER_SURFACE_V1_ANCHOR_RIGHT,
+        "layer", ZWLR_LAYER_SHELL_V1_LAYER_TOP,
+        "kbd-interactivity", FALSE,
+        "exclusive-zone", height,
+        "namespace", "osk",
+        NULL
+    );
+}
+
+// Called from rust
+/// Creates a new panel widget
+void
+panel_manager_request_widget (struct panel_manager *self, struct wl_output *output, uint32_t height, uint32_t floating, struct squeek_panel_manager *mgr)
+{
+    // Anchors and layers stay with the surface, so it gets replaced.
+    if (self->window && self->floating != (gboolean)floating) {
+        g_signal_handlers_disconnect_by_data (self->window, self);
+        gtk_widget_destroy (GTK_WIDGET (self->window));
+        self->window = NULL;
+        self->widget = NULL;
+    }
+
+    if (!self->window) {
+        self->window = make_window (output, height, floating);
+        self->floating = floating;
         g_object_connect (self->window,
             "swapped-signal::destroy", G_CALLBACK(on_destroy), self,
             "swapped-signal::configured", G_CALLBACK(on_surface_configure), mgr,
@@ -89,6 +123,8 @@ panel_manager_request_widget (struct panel_manager *self, struct wl_output *outp
         gtk_window_set_title (GTK_WINDOW(self->window), "Squeekboard");
         gtk_window_set_icon_name (GTK_WINDOW(self->window), "squeekboard");
         gtk_window_set_keep_above (GTK_WINDOW(self->window), TRUE);
+        // The application shows between the buttons.
+        gtk_widget_set_app_paintable (GTK_WIDGET(self->window), floating);
     }
 
     if (!self->widget) {
@@ -109,6 +145,108 @@ panel_manager_resize (struct panel_manager *self, uint32_t height)
 }
 
 
+// Called from rust
+/// Draws the keyboard again, to reflect changes outside of the layout
+void
+panel_manager_queue_draw (struct panel_manager *self)
+{
+    if (self->widget) {
+        gtk_widget_queue_draw (self->widget);
+    }
+}
+
+/// Tall enough to hit with a finger at the edge of the screen
+static const uint32_t HANDLE_HEIGHT = 8;
+/// Upwards, in pixels
+static const double HANDLE_SWIPE_DISTANCE = 40;
+
+/// Calls back into Rust
+void squeek_panel_handle_swiped(struct squeek_state_manager *state_manager);
+
+static gboolean
+on_handle_draw (GtkWidget *widget, cairo_t *cr, gpointer user_data)
+{
+    (void)user_data;
+    GtkStyleContext *ctx = gtk_widget_get_style_context (widget);
+    GdkRGBA color;
+    gtk_style_context_get_color (ctx, gtk_style_context_get_state (ctx), &color);
+    double width = gtk_widget_get_allocated_width (widget);
+    double height = gtk_widget_get_allocated_height (widget);
+    double bar_width = 48;
+    double bar_height = 3;
+    cairo_set_source_rgba (cr, color.red, color.green, color.blue, 0.5);
+    cairo_rectangle (cr, (width - bar_width) / 2, (height - bar_height) / 2,
+                     bar_width, bar_height);
+    cairo_fill (cr);
+    return FALSE;
+}
+
+static void
+on_handle_drag_update (struct panel_manager *self,
+                       gdouble offset_x, gdouble offset_y,
+                       GtkGestureDrag *gesture)
+{
+    (void)offset_x;
+    if (-offset_y >= HANDLE_SWIPE_DISTANCE) {
+        gtk_gesture_set_state (GTK_GESTURE (gesture), GTK_EVENT_SEQUENCE_CLAIMED);
+        gtk_event_controller_reset (GTK_EVENT_CONTROLLER (gesture));
+        squeek_panel_handle_swiped (self->state_manager);
+    }
+}
+
+// Called from rust
+/// Removes the edge handle
+void
+panel_manager_hide_handle (struct panel_manager *self)
+{
+    g_clear_object (&self->handle_gesture);
+    if (self->handle) {
+        gtk_widget_destroy (GTK_WIDGET (self->handle));
+        self->handle = NULL;
+    }
+    self->handle_output = NULL;
+}
+
+// Called from rust
+/// Places the edge handle at the bottom of the output
+void
+panel_manager_show_handle (struct panel_manager *self, struct wl_output *output)
+{
+    if (self->handle && self->handle_output == output) {
+        return;
+    }
+    panel_manager_hide_handle (self);
+
+    self->handle = g_object_new (
+        PHOSH_TYPE_LAYER_SURFACE,
+        "layer-shell", squeek_wayland->layer_shell,
+        "wl-output", output,
+        "height", HANDLE_HEIGHT,
+        "anchor", ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM
+        | ZWLR_LAYER_SURFACE_V1_ANCHOR_LEFT
+        | ZWLR_LAYER_SURFACE_V1_ANCHOR_RIGHT,
+        "layer", ZWLR_LAYER_SHELL_V1_LAYER_TOP,
+        "kbd-interactivity", FALSE,
+        // Applications keep the space
+        "exclusive-zone", 0,
+        "namespace", "osk-handle",
+        NULL
+    );
+    self->handle_output = output;
+    gtk_widget_set_can_focus (GTK_WIDGET(self->handle), FALSE);
+
+    GtkWidget *area = gtk_drawing_area_new ();
+    gtk_widget_add_events (area, GDK_BUTTON_PRESS_MASK | GDK_BUTTON_RELEASE_MASK
+                           | GDK_BUTTON_MOTION_MASK | GDK_TOUCH_MASK);
+    g_signal_connect (area, "draw", G_CALLBACK (on_handle_draw), NULL);
+    self->handle_gesture = gtk_gesture_drag_new (area);
+    g_signal_connect_swapped (self->handle_gesture, "drag-update",
+                              G_CALLBACK (on_handle_drag_update), self);
+
+    gtk_container_add (GTK_CONTAINER (self->handle), area);
+    gtk_widget_show_all (GTK_WIDGET (self->handle));
+}
+
 struct panel_manager panel_manager_new(EekboardContextService *state, struct submission *submission, struct squeek_state_manager *state_manager, struct squeek_popover *popover)
 {
     struct panel_manager mgr = {
@@ -116,7 +254,11 @@ struct panel_manager panel_manager_new(EekboardContextService *state, struct sub
         .submission = submission,
         .window = NULL,
         .widget = NULL,
+        .floating = FALSE,
         .current_output = NULL,
+        .handle = NULL,
+        .handle_gesture = NULL,
+        .handle_output = NULL,
         .state_manager = state_manager,
         .popover = popover,
     };
@@ -17,9 +17,16 @@ struct panel_manager {
     // https://gitlab.gnome.org/World/Phosh/squeekboard/-/issues/343
     PhoshLayerSurface *window;
     GtkWidget *widget;
+    /// The window covers the whole output, see-through
+    gboolean floating;
 
     // Those should be held in Rust
     struct wl_output *current_output;
+
+    /// Thin strip at the bottom edge, revealing the hidden panel
+    PhoshLayerSurface *handle; // owned, nullable
+    GtkGesture *handle_gesture; // owned, nullable
+    struct wl_output *handle_output;
 };
 
 struct panel_manager panel_manager_new(EekboardContextService *state, struct submission *submission, struct squeek_state_manager *state_manager, struct squeek_popover *popover);
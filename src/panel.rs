@@ -39,6 +39,10 @@ pub mod c {
     use std::os::raw::c_void;
 
     use crate::outputs::c::WlOutput;
+    use crate::receiver;
+    use crate::state;
+    use crate::util::CloneOwned;
+    use crate::logging::Warn;
 
     /// struct panel_manager*
     #[repr(transparent)]
@@ -51,11 +55,24 @@ pub mod c {
             service: PanelManager,
             output: WlOutput,
             height: u32,
+            floating: u32,
             // for callbacks
             panel: Wrapped<Manager>,
         );
         pub fn panel_manager_resize(service: PanelManager, height: u32);
         pub fn panel_manager_hide(service: PanelManager);
+        pub fn panel_manager_queue_draw(service: PanelManager);
+        pub fn panel_manager_show_handle(service: PanelManager, output: WlOutput);
+        pub fn panel_manager_hide_handle(service: PanelManager);
+    }
+
+    /// The edge handle got swiped up.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_panel_handle_swiped(app_state: receiver::c::State) {
+        app_state.clone_owned()
+            .send(state::Event::Visibility(state::visibility::Event::ForceVisible))
+            .or_print(logging::Problem::Bug, "Can't send to state");
     }
 
     #[no_mangle]
@@ -64,8 +81,7 @@ pub mod c {
         // This is why this needs to be moved into state::State:
         // it's getting too coupled to glib.
         glib::idle_add_local(move || {
-            let panel = panel.clone_ref();
-            panel.borrow_mut().set_configured(Size{width, height});
+            Manager::set_configured(panel.clone(), Size{width, height});
             glib::Continue(false)
         });
     }
@@ -94,7 +110,27 @@ impl PixelSize {
     }
 }
 
-#[derive(Clone, Debug)]
+/// A rectangle on the output, in logical pixels.
+/// Mirrors `struct squeek_rect`.
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Where the keyboard is on the output,
+/// so that shells can keep their edge gestures away from it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Geometry {
+    pub panel: Rect,
+    /// Rows of the current view
+    pub rows: Vec<Rect>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 struct Size {
     width: u32,
     height: u32,
@@ -104,26 +140,171 @@ struct Size {
 /// the application asks for some size,
 /// and then receives a size that the compositor thought appropriate.
 /// Stores raw values passed to Wayland, i.e. scaled dimensions.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 enum State {
     Hidden,
     SizeRequested {
         output: OutputId,
         height: u32,
+        floating: bool,
+        /// Another height wanted while the request was in flight,
+        /// asked for once the size arrives
+        pending: Option<u32>,
         //width: u32,
     },
     SizeAllocated {
         output: OutputId,
         wanted_height: u32,
+        floating: bool,
         allocated: Size,
     },
 }
 
+/// What the compositor gets asked for, in scaled dimensions
+#[derive(Clone, Debug, PartialEq)]
+enum Request {
+    Widget { output: OutputId, height: u32, floating: bool },
+    Resize(u32),
+    Hide,
+}
+
+impl State {
+    fn update(self, cmd: Command) -> (State, Vec<Request>) {
+        match (cmd, self) {
+            (Command::Hide, State::Hidden) => (State::Hidden, vec![]),
+            (Command::Hide, State::SizeAllocated{..}) => (State::Hidden, vec![Request::Hide]),
+            (Command::Hide, State::SizeRequested{..}) => (State::Hidden, vec![Request::Hide]),
+            (Command::Show{output, height, floating}, State::Hidden) => {
+                let height = height.as_scaled_ceiling();
+                (
+                    State::SizeRequested{output, height, floating, pending: None},
+                    vec![Request::Widget{output, height, floating}],
+                )
+            },
+            (
+                Command::Show{output, height, floating},
+                State::SizeRequested{
+                    output: req_output,
+                    height: req_height,
+                    floating: req_floating,
+                    ..
+                },
+            ) => {
+                let height = height.as_scaled_ceiling();
+                if output == req_output && floating == req_floating {
+                    // Asking again right away could cause a busy loop,
+                    // when two requests are being processed at the same time:
+                    // one message in the compositor to allocate size A,
+                    // causing the state to update to height A'
+                    // the other from the state wanting height B',
+                    // causing the compositor to change size to B.
+                    // So the new height waits until the request in flight is answered.
+                    // A floating panel covers the output whatever the height.
+                    let pending = if height == req_height || floating {
+                        None
+                    } else {
+                        Some(height)
+                    };
+                    (State::SizeRequested{output, height: req_height, floating, pending}, vec![])
+                } else {
+                    // This looks weird, but should be safe.
+                    // The stack seems to handle
+                    // configure events on a dead surface.
+                    // The panel can't start or stop floating
+                    // without being created anew either.
+                    (
+                        State::SizeRequested{output, height, floating, pending: None},
+                        vec![Request::Hide, Request::Widget{output, height, floating}],
+                    )
+                }
+            },
+            (
+                Command::Show{output, height, floating},
+                State::SizeAllocated{
+                    output: alloc_output,
+                    allocated,
+                    wanted_height,
+                    floating: alloc_floating,
+                },
+            ) => {
+                let height = height.as_scaled_ceiling();
+                let same = output == alloc_output && floating == alloc_floating;
+                if same && (height == wanted_height || floating) {
+                    (
+                        State::SizeAllocated{output: alloc_output, wanted_height, floating, allocated},
+                        vec![],
+                    )
+                } else if same && height == allocated.height {
+                    (
+                        State::SizeAllocated{
+                            output: alloc_output,
+                            wanted_height: height,
+                            floating,
+                            allocated,
+                        },
+                        vec![],
+                    )
+                } else if same {
+                    // Should *all* other heights cause a resize?
+                    // What about those between wanted and allocated?
+                    (
+                        State::SizeRequested{output, height, floating, pending: None},
+                        vec![Request::Resize(height)],
+                    )
+                } else {
+                    (
+                        State::SizeRequested{output, height, floating, pending: None},
+                        vec![Request::Hide, Request::Widget{output, height, floating}],
+                    )
+                }
+            },
+        }
+    }
+
+    fn configure(self, size: Size) -> (State, Vec<Request>) {
+        match self {
+            State::Hidden => {
+                // This may happen if a hide is scheduled immediately after a show.
+                log_print!(
+                    logging::Level::Surprise,
+                    "Panel has been configured, but no request is pending. Ignoring",
+                );
+                (State::Hidden, vec![])
+            },
+            State::SizeAllocated{output, wanted_height, floating, ..} => {
+                log_print!(
+                    logging::Level::Surprise,
+                    "Panel received new configuration without asking",
+                );
+                (State::SizeAllocated{output, wanted_height, floating, allocated: size}, vec![])
+            },
+            State::SizeRequested{output, height, floating, pending} => {
+                let allocated = State::SizeAllocated {
+                    output,
+                    wanted_height: height,
+                    floating,
+                    allocated: size,
+                };
+                match pending {
+                    None => (allocated, vec![]),
+                    Some(height) => allocated.update(Command::Show {
+                        output,
+                        height: PixelSize { pixels: height, scale_factor: 1 },
+                        floating,
+                    }),
+                }
+            },
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Command {
     Show {
         output: OutputId,
         height: PixelSize,
+        /// Over the whole output, see-through where there are no buttons
+        floating: bool,
     },
     Hide,
 }
@@ -141,31 +322,77 @@ impl Manager {
             state: State::Hidden,
         }
     }
+
+    /// `copied` is this manager, for the callbacks.
+    fn send(&self, requests: Vec<Request>, copied: &Wrapped<Manager>) {
+        for request in requests {
+            match request {
+                Request::Widget { output, height, floating } => unsafe {
+                    c::panel_manager_request_widget(
+                        self.panel,
+                        output.0,
+                        height,
+                        floating as u32,
+                        copied.clone(),
+                    )
+                },
+                Request::Resize(height) => unsafe {
+                    c::panel_manager_resize(self.panel, height)
+                },
+                Request::Hide => unsafe { c::panel_manager_hide(self.panel) },
+            }
+        }
+    }
+
     // TODO: mabe send the allocated size back to state::State,
     // to perform layout adjustments
-    fn set_configured(&mut self, size: Size) {
-        self.state = match self.state.clone() {
-            State::Hidden => {
-                // This may happen if a hide is scheduled immediately after a show.
-                log_print!(
-                    logging::Level::Surprise,
-                    "Panel has been configured, but no request is pending. Ignoring",
-                );
-                State::Hidden
+    fn set_configured(mgr: Wrapped<Manager>, size: Size) {
+        let copied = mgr.clone();
+        let mgr = mgr.clone_ref();
+        let mut mgr = mgr.borrow_mut();
+        let (state, requests) = mgr.state.clone().configure(size);
+        mgr.state = state;
+        mgr.send(requests, &copied);
+    }
+
+    /// Draws the keyboard again, if it's shown.
+    pub fn queue_redraw(mgr: &Wrapped<Manager>) {
+        let mgr = mgr.clone_ref();
+        let mgr = mgr.borrow();
+        unsafe { c::panel_manager_queue_draw(mgr.panel); }
+    }
+
+    /// Shows the handle at the bottom of the output, or removes it.
+    /// The handle is independent of the panel.
+    pub fn set_edge_handle(mgr: &Wrapped<Manager>, output: Option<OutputId>) {
+        let mgr = mgr.clone_ref();
+        let mgr = mgr.borrow();
+        match output {
+            Some(output) => unsafe {
+                c::panel_manager_show_handle(mgr.panel, output.0)
             },
-            State::SizeAllocated{output, wanted_height, ..} => {
-                log_print!(
-                    logging::Level::Surprise,
-                    "Panel received new configuration without asking",
+            None => unsafe { c::panel_manager_hide_handle(mgr.panel) },
+        }
+    }
+
+    /// Creates the panel anew if it's shown, e.g. after a crash left it broken.
+    pub fn restart(mgr: Wrapped<Manager>) {
+        let copied = mgr.clone();
+
+        let mgr = mgr.clone_ref();
+        let mut mgr = mgr.borrow_mut();
+
+        match mgr.state.clone() {
+            State::Hidden => {},
+            State::SizeRequested { output, height, floating, .. }
+            | State::SizeAllocated { output, wanted_height: height, floating, .. } => {
+                mgr.send(
+                    vec![Request::Hide, Request::Widget { output, height, floating }],
+                    &copied,
                 );
-                State::SizeAllocated{output, wanted_height, allocated: size}
-            },
-            State::SizeRequested{output, height} => State::SizeAllocated {
-                output,
-                wanted_height: height,
-                allocated: size,
+                mgr.state = State::SizeRequested { output, height, floating, pending: None };
             },
-        };
+        }
     }
 
     pub fn update(mgr: Wrapped<Manager>, cmd: Command) {
@@ -174,74 +401,88 @@ impl Manager {
         let mgr = mgr.clone_ref();
         let mut mgr = mgr.borrow_mut();
 
-        (*mgr).state = match (cmd, mgr.state.clone()) {
-            (Command::Hide, State::Hidden) => State::Hidden,
-            (Command::Hide, State::SizeAllocated{..}) => {
-                unsafe { c::panel_manager_hide(mgr.panel); }
-                State::Hidden
-            },
-            (Command::Hide, State::SizeRequested{..}) => {
-                unsafe { c::panel_manager_hide(mgr.panel); }
-                State::Hidden
-            },
-            (Command::Show{output, height}, State::Hidden) => {
-                let height = height.as_scaled_ceiling();
-                unsafe { c::panel_manager_request_widget(mgr.panel, output.0, height, copied); }
-                State::SizeRequested{output, height}
-            },
-            (
-                Command::Show{output, height},
-                State::SizeRequested{output: req_output, height: req_height},
-            ) => {
-                let height = height.as_scaled_ceiling();
-                if output == req_output && height == req_height {
-                    State::SizeRequested{output: req_output, height: req_height}
-                } else if output == req_output {
-                    // I'm not sure about that.
-                    // This could cause a busy loop,
-                    // when two requests are being processed at the same time:
-                    // one message in the compositor to allocate size A,
-                    // causing the state to update to height A'
-                    // the other from the state wanting height B',
-                    // causing the compositor to change size to B.
-                    // So better cut this short here, despite artifacts.
-                    // Out of simplicty, just ignore the new request.
-                    // If that causes problems, the request in flight could be stored
-                    // for the purpose of handling it better somehow.
-                    State::SizeRequested{output: req_output, height: req_height}
-                } else {
-                    // This looks weird, but should be safe.
-                    // The stack seems to handle
-                    // configure events on a dead surface.
-                    unsafe {
-                        c::panel_manager_hide(mgr.panel);
-                        c::panel_manager_request_widget(mgr.panel, output.0, height, copied);
-                    }
-                    State::SizeRequested{output, height}
-                }
-            },
-            (
-                Command::Show{output, height},
-                State::SizeAllocated{output: alloc_output, allocated, wanted_height},
-            ) => {
-                let height = height.as_scaled_ceiling();
-                if output == alloc_output && height == wanted_height {
-                    State::SizeAllocated{output: alloc_output, wanted_height, allocated}
-                } else if output == alloc_output && height == allocated.height {
-                    State::SizeAllocated{output: alloc_output, wanted_height: height, allocated}
-                } else if output == alloc_output {
-                    // Should *all* other heights cause a resize?
-                    // What about those between wanted and allocated?
-                    unsafe { c::panel_manager_resize(mgr.panel, height); }
-                    State::SizeRequested{output, height}
-                } else {
-                    unsafe {
-                        c::panel_manager_hide(mgr.panel);
-                        c::panel_manager_request_widget(mgr.panel, output.0, height, copied);
-                    }
-                    State::SizeRequested{output, height}
-                }
-            },
+        let (state, requests) = mgr.state.clone().update(cmd);
+        mgr.state = state;
+        mgr.send(requests, &copied);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::outputs::c::WlOutput;
+
+    fn output() -> OutputId {
+        OutputId(unsafe { std::mem::transmute::<_, WlOutput>(1usize) })
+    }
+
+    fn show(pixels: u32) -> Command {
+        Command::Show {
+            output: output(),
+            height: PixelSize { pixels, scale_factor: 1 },
+            floating: false,
+        }
+    }
+
+    fn show_floating(pixels: u32) -> Command {
+        Command::Show {
+            output: output(),
+            height: PixelSize { pixels, scale_factor: 1 },
+            floating: true,
         }
     }
+
+    #[test]
+    fn height_change_while_requested() {
+        let (state, requests) = State::Hidden.update(show(200));
+        assert_eq!(
+            requests,
+            vec![Request::Widget { output: output(), height: 200, floating: false }],
+        );
+        // The compositor didn't answer yet.
+        let (state, requests) = state.update(show(300));
+        assert_eq!(requests, vec![]);
+        let (state, requests) = state.configure(Size { width: 720, height: 200 });
+        assert_eq!(requests, vec![Request::Resize(300)]);
+        let (state, requests) = state.configure(Size { width: 720, height: 300 });
+        assert_eq!(requests, vec![]);
+        assert_eq!(
+            state,
+            State::SizeAllocated {
+                output: output(),
+                wanted_height: 300,
+                floating: false,
+                allocated: Size { width: 720, height: 300 },
+            },
+        );
+    }
+
+    #[test]
+    fn height_change_back_while_requested() {
+        let (state, _) = State::Hidden.update(show(200));
+        let (state, _) = state.update(show(300));
+        let (state, _) = state.update(show(200));
+        let (_, requests) = state.configure(Size { width: 720, height: 200 });
+        assert_eq!(requests, vec![]);
+    }
+
+    /// The surface gets made anew for the other mode.
+    #[test]
+    fn start_floating() {
+        let (state, _) = State::Hidden.update(show(200));
+        let (state, _) = state.configure(Size { width: 720, height: 200 });
+        let (state, requests) = state.update(show_floating(1440));
+        assert_eq!(
+            requests,
+            vec![
+                Request::Hide,
+                Request::Widget { output: output(), height: 1440, floating: true },
+            ],
+        );
+        // Whatever the compositor leaves for the floating panel is fine.
+        let (state, requests) = state.configure(Size { width: 720, height: 1400 });
+        assert_eq!(requests, vec![]);
+        let (_, requests) = state.update(show_floating(1440));
+        assert_eq!(requests, vec![]);
+    }
 }
@@ -0,0 +1,331 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Keeps the user's choices across restarts.
+ *
+ * Only the state which the user set up explicitly gets saved.
+ * Everything else is discovered again from the system on startup.
+ *
+ * The file carries a version number.
+ * A file from a different version, or one that can't be understood,
+ * gets ignored, and the application starts with defaults instead.
+ */
+
+use crate::logging;
+use crate::popover::LayoutId;
+use crate::settings::Settings;
+use crate::state::{ Application, LayoutChoice, LayoutSource, output, visibility };
+use crate::xdg;
+use serde::{ Deserialize, Serialize };
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+// Traits
+use crate::logging::Warn;
+
+
+/// Bump on every incompatible change to `Snapshot`.
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Yaml(serde_yaml::Error),
+    /// The file was written by an incompatible version
+    Version(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO: {}", e),
+            Error::Yaml(e) => write!(f, "YAML: {}", e),
+            Error::Version(v) => write!(f, "Unsupported version {}", v),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Self {
+        Error::Yaml(e)
+    }
+}
+
+/// Read before anything else, to reject unknown formats early.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Visibility {
+    NotForced,
+    ForcedVisible,
+    ForcedHidden,
+}
+
+impl From<visibility::State> for Visibility {
+    fn from(state: visibility::State) -> Self {
+        match state {
+            visibility::State::NotForced => Visibility::NotForced,
+            visibility::State::ForcedVisible => Visibility::ForcedVisible,
+            visibility::State::ForcedHidden => Visibility::ForcedHidden,
+        }
+    }
+}
+
+impl From<Visibility> for visibility::State {
+    fn from(v: Visibility) -> Self {
+        match v {
+            Visibility::NotForced => visibility::State::NotForced,
+            Visibility::ForcedVisible => visibility::State::ForcedVisible,
+            Visibility::ForcedHidden => visibility::State::ForcedHidden,
+        }
+    }
+}
+
+/// Mirrors `popover::LayoutId`,
+/// so that renaming things in the code doesn't break saved files.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    System { kind: String, name: String },
+    Local(String),
+}
+
+impl From<LayoutId> for Layout {
+    fn from(id: LayoutId) -> Self {
+        match id {
+            LayoutId::System { kind, name } => Layout::System { kind, name },
+            LayoutId::Local(name) => Layout::Local(name),
+        }
+    }
+}
+
+impl From<Layout> for LayoutId {
+    fn from(layout: Layout) -> Self {
+        match layout {
+            Layout::System { kind, name } => LayoutId::System { kind, name },
+            Layout::Local(name) => LayoutId::Local(name),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SystemLayout {
+    name: String,
+    source: String,
+}
+
+impl From<LayoutChoice> for SystemLayout {
+    fn from(choice: LayoutChoice) -> Self {
+        SystemLayout {
+            name: choice.name,
+            source: match choice.source {
+                LayoutSource::Xkb => "xkb".into(),
+                LayoutSource::Other(source) => source,
+            },
+        }
+    }
+}
+
+impl From<SystemLayout> for LayoutChoice {
+    fn from(layout: SystemLayout) -> Self {
+        LayoutChoice {
+            name: layout.name,
+            source: layout.source.into(),
+        }
+    }
+}
+
+/// The parts of `state::Application` worth saving
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    version: u32,
+    visibility: Visibility,
+    /// The system will report its own choice soon after startup.
+    /// Knowing the old one lets the overlay survive if it's unchanged.
+    system_layout: SystemLayout,
+    overlay: Option<Layout>,
+    /// Height percentages by output make and model
+    #[serde(default)]
+    output_heights: HashMap<String, u32>,
+    /// The height setting last seen.
+    /// Until the settings get read again, the panel keeps its height,
+    /// and reading them isn't mistaken for the user changing the height.
+    #[serde(default)]
+    height_percent: Option<u32>,
+}
+
+impl Snapshot {
+    pub fn new(state: &Application) -> Self {
+        Snapshot {
+            version: VERSION,
+            visibility: state.visibility.forced.into(),
+            system_layout: state.layout_choice.clone().into(),
+            overlay: state.overlay_layout.clone().map(Layout::from),
+            output_heights: state.outputs.heights.clone(),
+            height_percent: Some(state.settings.height_percent),
+        }
+    }
+
+    /// Restores the saved parts, leaving the rest untouched.
+    pub fn apply_to(self, state: Application) -> Application {
+        Application {
+            visibility: visibility::Policy {
+                forced: self.visibility.into(),
+                ..state.visibility
+            },
+            layout_choice: self.system_layout.into(),
+            overlay_layout: self.overlay.map(LayoutId::from),
+            outputs: output::Policy {
+                heights: self.output_heights,
+                ..state.outputs
+            },
+            settings: Settings {
+                height_percent: self.height_percent
+                    .unwrap_or(state.settings.height_percent),
+                ..state.settings
+            },
+            ..state
+        }
+    }
+
+    fn parse(data: &str) -> Result<Self, Error> {
+        let header: Header = serde_yaml::from_str(data)?;
+        if header.version != VERSION {
+            return Err(Error::Version(header.version));
+        }
+        Ok(serde_yaml::from_str(data)?)
+    }
+}
+
+fn get_path() -> Option<PathBuf> {
+    xdg::state_path("squeekboard/state.yaml")
+}
+
+/// Returns the previously saved state, if there is a usable one.
+pub fn load() -> Option<Snapshot> {
+    let path = get_path()?;
+    match fs::read_to_string(&path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            log_print!(
+                logging::Level::Warning,
+                "Can't read saved state from {:?}: {}", path, e,
+            );
+            None
+        },
+        Ok(data) => Snapshot::parse(&data).or_print(
+            logging::Problem::Warning,
+            &format!("Ignoring saved state in {:?}", path),
+        ),
+    }
+}
+
+/// Writes to a temporary file first,
+/// so that crashing in the middle doesn't leave a truncated file behind.
+fn write(path: &Path, snapshot: &Snapshot) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let data = serde_yaml::to_string(snapshot)?;
+    let temp_path = path.with_extension("yaml.tmp");
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+pub fn save(snapshot: &Snapshot) {
+    if let Some(path) = get_path() {
+        write(&path, snapshot)
+            .or_print(logging::Problem::Warning, "Can't save state");
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn round_trip() {
+        let state = Application {
+            visibility: visibility::Policy {
+                forced: visibility::State::ForcedHidden,
+                ..Default::default()
+            },
+            overlay_layout: Some(LayoutId::Local("emoji".into())),
+            settings: Settings {
+                height_percent: 80,
+                ..Settings::default()
+            },
+            ..Application::new(Instant::now())
+        };
+        let snapshot = Snapshot::new(&state);
+        let data = serde_yaml::to_string(&snapshot).unwrap();
+        assert_eq!(Snapshot::parse(&data).unwrap(), snapshot);
+
+        let restored = Snapshot::parse(&data).unwrap()
+            .apply_to(Application::new(Instant::now()));
+        assert_eq!(
+            restored.visibility.forced,
+            visibility::State::ForcedHidden,
+        );
+        assert_eq!(
+            restored.overlay_layout,
+            Some(LayoutId::Local("emoji".into())),
+        );
+        assert_eq!(restored.settings.height_percent, 80);
+    }
+
+    /// The system repeating its layout after a restart
+    /// must not throw away the restored overlay.
+    #[test]
+    fn overlay_survives_same_choice() {
+        use crate::state::Event;
+        let now = Instant::now();
+        let state = Application {
+            overlay_layout: Some(LayoutId::Local("emoji".into())),
+            ..Application::new(now)
+        };
+        let restored = Snapshot::new(&state)
+            .apply_to(Application::new(now));
+        let choice = restored.layout_choice.clone();
+        let restored = restored.apply_event(Event::LayoutChoice(choice), now);
+        assert_eq!(
+            restored.overlay_layout,
+            Some(LayoutId::Local("emoji".into())),
+        );
+    }
+
+    #[test]
+    fn other_version() {
+        assert_matches!(
+            Snapshot::parse("version: 9999\nsomething_else: true\n"),
+            Err(Error::Version(9999))
+        );
+    }
+
+    #[test]
+    fn corrupted() {
+        assert_matches!(
+            Snapshot::parse("version: 1\nvisibility: forc"),
+            Err(Error::Yaml(_))
+        );
+        assert_matches!(Snapshot::parse("\0\0\0"), Err(Error::Yaml(_)));
+    }
+}
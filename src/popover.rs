@@ -4,17 +4,28 @@ use gio;
 use gtk;
 use std::ffi::CString;
 use std::cmp::Ordering;
-use crate::actors;
+use crate::actors::popover::Menu;
+use crate::data::compose;
+use crate::action::Action;
+use crate::data::symbols;
+use crate::drawing;
+use crate::layout::MenuEntry;
 use crate::layout::c::{ Bounds, EekGtkKeyboard };
 use crate::locale::{ OwnedTranslation, compare_current_locale };
 use crate::logging;
+use crate::preview;
+use crate::rearrange;
 use crate::receiver;
 use crate::resources;
+use crate::settings;
+use crate::settings::get_settings;
 use crate::state;
 
 // Traits
 use gio::prelude::ActionMapExt;
 use gio::prelude::SettingsExt;
+#[cfg(feature = "glib_v0_14")]
+use gio::prelude::SettingsExtManual;
 use glib::translate::FromGlibPtrNone;
 use glib::variant::ToVariant;
 use gtk::prelude::*;
@@ -105,30 +116,6 @@ mod variants {
     }
 }
 
-fn get_settings(schema_name: &str) -> Option<gio::Settings> {
-    let mut error_handler = logging::Print{};
-
-    #[cfg(feature = "glib_v0_14")]
-    let ss = gio::SettingsSchemaSource::default();
-    #[cfg(not(feature = "glib_v0_14"))]
-    let ss = gio::SettingsSchemaSource::get_default();
-    
-    ss.or_warn(
-            &mut error_handler,
-            logging::Problem::Surprise,
-            "No gsettings schemas installed.",
-        )
-        .and_then(|sss| 
-            sss.lookup(schema_name, true)
-                .or_warn(
-                    &mut error_handler,
-                    logging::Problem::Surprise,
-                    &format!("Gsettings schema {} not installed", schema_name),
-                )
-        )
-        .map(|_sschema| gio::Settings::new(schema_name))
-}
-
 fn set_layout(kind: &str, name: &str) {
     let settings = get_settings("org.gnome.desktop.input-sources");
     if let Some(settings) = settings {
@@ -165,7 +152,7 @@ pub enum LayoutId {
 }
 
 impl LayoutId {
-    fn get_name(&self) -> &str {
+    pub fn get_name(&self) -> &str {
         match &self {
             LayoutId::System { kind: _, name } => name.as_str(),
             LayoutId::Local(name) => name.as_str(),
@@ -184,17 +171,6 @@ fn set_visible_layout(
     }
 }
 
-/// Takes into account first any overlays, then system layouts from the list
-fn get_current_layout(
-    popover: &actors::popover::State,
-    system_layouts: &Vec<LayoutId>,
-) -> Option<LayoutId> {
-    match &popover.overlay {
-        Some(name) => Some(LayoutId::Local(name.into())),
-        None => system_layouts.get(0).map(LayoutId::clone),
-    }
-}
-
 /// Translates all provided layout names according to current locale,
 /// for the purpose of display (i.e. errors will be caught and reported)
 fn translate_layout_names(layouts: &Vec<LayoutId>) -> Vec<OwnedTranslation> {
@@ -231,18 +207,196 @@ fn translate_layout_names(layouts: &Vec<LayoutId>) -> Vec<OwnedTranslation> {
         .collect()
 }
 
-pub fn show(
-    window: EekGtkKeyboard,
-    position: Bounds,
-    popover: &actors::popover::State,
-    app_state: receiver::State,
+/// Quick settings change Squeekboard's own settings keys directly.
+/// The keys' types decide whether the menu shows toggles or choices.
+fn add_quick_settings(action_group: &gio::SimpleActionGroup) {
+    let settings = match get_settings(settings::SCHEMA) {
+        Some(settings) => settings,
+        None => return,
+    };
+    for key in &["feedback", "visual-feedback", "theme"] {
+        #[cfg(feature = "glib_v0_14")]
+        let action = Some(settings.create_action(key));
+        #[cfg(not(feature = "glib_v0_14"))]
+        let action = settings.create_action(key);
+        match action {
+            Some(action) => action_group.add_action(&action),
+            None => log_print!(
+                logging::Level::Surprise,
+                "No action for setting {}", key,
+            ),
+        }
+    }
+}
+
+fn activate_entry(
+    keyboard: EekGtkKeyboard,
+    entry: &MenuEntry,
+    app_state: &receiver::State,
 ) {
-    unsafe { gtk::set_initialized() };
-    let window = unsafe { gtk::Widget::from_glib_none(window.0) };
+    match &entry.action {
+        Action::SetView(view) => match CString::new(view.as_str()) {
+            Ok(view) => unsafe {
+                ::layout::c::eek_gtk_keyboard_set_view(keyboard, view.as_ptr())
+            },
+            Err(e) => log_print!(logging::Level::Bug, "Bad view name: {}", e),
+        },
+        Action::ShowOverlay(name) => {
+            app_state
+                .send(state::Event::OverlayChanged(LayoutId::Local(name.clone())))
+                .or_print(logging::Problem::Bug, "Can't send to state");
+        },
+        other => log_print!(
+            logging::Level::Bug,
+            "Menu entry {} has unsupported action {:?}", entry.label, other,
+        ),
+    }
+}
 
-    let overlay_layouts = resources::get_overlays().into_iter()
-        .map(|name| LayoutId::Local(name.to_string()));
+fn choose_layout(app_state: &receiver::State, layout: &LayoutId) {
+    app_state
+        .send(state::Event::OverlayChanged(layout.clone()))
+        .or_print(logging::Problem::Bug, "Can't send to state");
+    set_visible_layout(layout)
+}
+
+/// A row of pictures of the system layouts, scrolling sideways.
+/// Returns None if there's nothing to show.
+fn make_carousel(
+    popover: &gtk::Popover,
+    layouts: &[(String, LayoutId)],
+    app_state: &receiver::State,
+) -> Option<gtk::ScrolledWindow> {
+    #[cfg(feature = "glib_v0_14")]
+    let scale_factor = popover.scale_factor();
+    #[cfg(not(feature = "glib_v0_14"))]
+    let scale_factor = popover.get_scale_factor();
+
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let mut shown = 0;
+    for (tr, layout) in layouts {
+        let path = match layout {
+            LayoutId::System { name, .. } => preview::get(name, scale_factor),
+            LayoutId::Local(_) => None,
+        };
+        let path = match path {
+            Some(path) => path,
+            None => continue,
+        };
+        #[cfg(feature = "glib_v0_14")]
+        let icon = gio::FileIcon::new(&gio::File::for_path(&path));
+        #[cfg(not(feature = "glib_v0_14"))]
+        let icon = gio::FileIcon::new(&gio::File::new_for_path(&path));
+        #[cfg(feature = "glib_v0_14")]
+        let image = gtk::Image::from_gicon(&icon, gtk::IconSize::Dialog);
+        #[cfg(not(feature = "glib_v0_14"))]
+        let image = gtk::Image::new_from_gicon(&icon, gtk::IconSize::Dialog);
+        // The picture fits within the square.
+        image.set_pixel_size(preview::WIDTH as i32);
+
+        let contents = gtk::Box::new(gtk::Orientation::Vertical, 2);
+        contents.pack_start(&image, false, false, 0);
+        contents.pack_start(&gtk::Label::new(Some(tr)), false, false, 0);
+        let button = gtk::Button::new();
+        button.set_relief(gtk::ReliefStyle::None);
+        button.add(&contents);
+
+        let (popover, app_state, layout) = (popover.clone(), app_state.clone(), layout.clone());
+        button.connect_clicked(move |_| {
+            choose_layout(&app_state, &layout);
+            popover.popdown();
+        });
+        row.pack_start(&button, false, false, 0);
+        shown += 1;
+    }
+    if shown == 0 {
+        return None;
+    }
+    let carousel = gtk::ScrolledWindow::new(
+        None::<&gtk::Adjustment>,
+        None::<&gtk::Adjustment>,
+    );
+    carousel.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
+    carousel.set_min_content_width(2 * preview::WIDTH as i32);
+    carousel.add(&row);
+    carousel.show_all();
+    Some(carousel)
+}
+
+/// The box which the popover made from the model
+fn get_main_box(popover: &gtk::Popover) -> Option<gtk::Box> {
+    #[cfg(feature = "glib_v0_14")]
+    let main = popover.child()
+        .and_then(|child| child.downcast::<gtk::Stack>().ok())
+        .and_then(|stack| stack.child_by_name("main"));
+    #[cfg(not(feature = "glib_v0_14"))]
+    let main = popover.get_child()
+        .and_then(|child| child.downcast::<gtk::Stack>().ok())
+        .and_then(|stack| stack.get_child_by_name("main"));
+    main.and_then(|main| main.downcast::<gtk::Box>().ok())
+}
+
+/// Puts the carousel above the menu items.
+fn add_carousel(popover: &gtk::Popover, carousel: &gtk::ScrolledWindow) {
+    match get_main_box(popover) {
+        Some(main) => {
+            main.pack_start(carousel, false, false, 0);
+            main.reorder_child(carousel, 0);
+        },
+        None => log_print!(
+            logging::Level::Surprise,
+            "The popover has no place for layout previews",
+        ),
+    }
+}
+
+/// Puts the slider from the builder below the menu items,
+/// and ties it to the height setting.
+fn add_height_slider(popover: &gtk::Popover, builder: &gtk::Builder) {
+    let settings = match get_settings(settings::SCHEMA) {
+        Some(settings) => settings,
+        None => return,
+    };
+    #[cfg(feature = "glib_v0_14")]
+    let (adjustment, slider) = (
+        builder.object::<gtk::Adjustment>("height-adjustment"),
+        builder.object::<gtk::Widget>("height-slider"),
+    );
+    #[cfg(not(feature = "glib_v0_14"))]
+    let (adjustment, slider) = (
+        builder.get_object::<gtk::Adjustment>("height-adjustment"),
+        builder.get_object::<gtk::Widget>("height-slider"),
+    );
+    match (get_main_box(popover), adjustment, slider) {
+        (Some(main), Some(adjustment), Some(slider)) => {
+            #[cfg(feature = "glib_v0_14")]
+            settings.bind("height-percent", &adjustment, "value").build();
+            #[cfg(not(feature = "glib_v0_14"))]
+            settings.bind(
+                "height-percent",
+                &adjustment,
+                "value",
+                gio::SettingsBindFlags::DEFAULT,
+            );
+            main.pack_end(&slider, false, false, 6);
+        },
+        _ => log_print!(
+            logging::Level::Surprise,
+            "The popover has no place for the height slider",
+        ),
+    }
+}
+
+/// Layouts built into Squeekboard, which don't affect the system
+pub fn get_overlay_names() -> Vec<String> {
+    resources::get_overlays().into_iter()
+        .chain(vec![symbols::OVERLAY_NAME, compose::OVERLAY_NAME])
+        .map(String::from)
+        .collect()
+}
 
+/// System layouts with their display names, in the order of preference
+pub fn get_system_layouts() -> Vec<(String, LayoutId)> {
     let settings = get_settings("org.gnome.desktop.input-sources");
     let inputs = settings
         .map(|settings| {
@@ -259,68 +413,82 @@ pub fn show(
         .map(|(kind, name)| LayoutId::System { kind, name })
         .collect();
 
-    let all_layouts: Vec<LayoutId> = system_layouts.clone()
-        .into_iter()
-        .chain(overlay_layouts)
-        .collect();
+    translate_layout_names(&system_layouts).into_iter()
+        .map(|tr| tr.0)
+        .zip(system_layouts.into_iter())
+        .collect()
+}
 
-    let translated_names = translate_layout_names(&system_layouts);
+/// Builds the GTK popover from the menu, and opens it.
+/// Choices go straight to the state.
+pub fn present(
+    window: EekGtkKeyboard,
+    position: Bounds,
+    menu: Menu,
+    app_state: receiver::State,
+) {
+    unsafe { gtk::set_initialized() };
+    let keyboard = window;
+    let window = unsafe { gtk::Widget::from_glib_none(window.0) };
 
     // sorted collection of language layouts
-    let mut human_names: Vec<(OwnedTranslation, LayoutId)> = translated_names
-        .into_iter()
-        .zip(system_layouts.clone().into_iter())
-        .collect();
+    let mut human_names: Vec<(String, LayoutId)> = menu.system_layouts.clone();
 
     human_names.sort_unstable_by(|(tr_a, layout_a), (tr_b, layout_b)| {
         // Sort first by layout then name
         match (layout_a, layout_b) {
             (LayoutId::Local(_), LayoutId::System { .. }) => Ordering::Greater,
             (LayoutId::System { .. }, LayoutId::Local(_)) => Ordering::Less,
-            _ => compare_current_locale(&tr_a.0, &tr_b.0)
+            _ => compare_current_locale(tr_a, tr_b)
         }
     });
 
-    let model: gio::Menu = {
-        #[cfg(feature = "glib_v0_14")]
-        {
-            let builder = gtk::Builder::from_resource("/sm/puri/squeekboard/popover.ui");
-            builder.object("app-menu").unwrap()
-        }
-        #[cfg(not(feature = "glib_v0_14"))]
-        {
-            let builder = gtk::Builder::new_from_resource("/sm/puri/squeekboard/popover.ui");
-            builder.get_object("app-menu").unwrap()
-        }
-    };
+    #[cfg(feature = "glib_v0_14")]
+    let builder = gtk::Builder::from_resource("/sm/puri/squeekboard/popover.ui");
+    #[cfg(not(feature = "glib_v0_14"))]
+    let builder = gtk::Builder::new_from_resource("/sm/puri/squeekboard/popover.ui");
+    #[cfg(feature = "glib_v0_14")]
+    let model: gio::Menu = builder.object("app-menu").unwrap();
+    #[cfg(not(feature = "glib_v0_14"))]
+    let model: gio::Menu = builder.get_object("app-menu").unwrap();
 
     for (tr, l) in human_names.iter().rev() {
         let detailed_action = format!("layout::{}", l.get_name());
-        let item = gio::MenuItem::new(Some(&tr.0), Some(detailed_action.as_str()));
+        let item = gio::MenuItem::new(Some(tr), Some(detailed_action.as_str()));
         model.prepend_item (&item);
     }
 
+    if !menu.entries.is_empty() {
+        let section = gio::Menu::new();
+        for (index, entry) in menu.entries.iter().enumerate() {
+            let detailed_action = format!("entry::{}", index);
+            section.append(Some(&entry.label), Some(detailed_action.as_str()));
+        }
+        model.prepend_section(None, &section);
+    }
+
     #[cfg(feature = "glib_v0_14")]
-    let menu = gtk::Popover::from_model(Some(&window), &model);
+    let popover_menu = gtk::Popover::from_model(Some(&window), &model);
     #[cfg(not(feature = "glib_v0_14"))]
-    let menu = gtk::Popover::new_from_model(Some(&window), &model);
+    let popover_menu = gtk::Popover::new_from_model(Some(&window), &model);
 
-    menu.set_pointing_to(&gtk::Rectangle {
+    popover_menu.set_pointing_to(&gtk::Rectangle {
         x: position.x.ceil() as i32,
         y: position.y.ceil() as i32,
         width: position.width.floor() as i32,
         height: position.width.floor() as i32,
     });
-    menu.set_constrain_to(gtk::PopoverConstraint::None);
+    popover_menu.set_constrain_to(gtk::PopoverConstraint::None);
+
+    let carousel = make_carousel(&popover_menu, &human_names, &app_state);
 
     let action_group = gio::SimpleActionGroup::new();
+    // The layout action takes the original
+    let hide_state = app_state.clone();
+    let entry_state = app_state.clone();
+    let restore_state = app_state.clone();
 
-    if let Some(current_layout) = get_current_layout(popover, &system_layouts) {
-        let current_layout_name = all_layouts.iter()
-            .find(
-                |l| l.get_name() == current_layout.get_name()
-            ).unwrap()
-            .get_name();
+    if let Some(current_layout_name) = menu.get_current_name() {
         log_print!(logging::Level::Debug, "Current Layout {}", current_layout_name);
 
         let layout_action = gio::SimpleAction::new_stateful(
@@ -329,7 +497,8 @@ pub fn show(
             &current_layout_name.to_variant()
         );
 
-        let menu_inner = menu.clone();
+        let menu_inner = popover_menu.clone();
+        let layouts = menu.clone();
         layout_action.connect_change_state(move |_action, state| {
             match state {
                 Some(v) => {
@@ -339,19 +508,8 @@ pub fn show(
                             logging::Problem::Bug,
                             &format!("Variant is not string: {:?}", v)
                         )
-                        .map(|state| {
-                            let layout = all_layouts.iter()
-                                .find(
-                                    |choices| state == choices.get_name()
-                                ).unwrap();
-                            app_state
-                                .send(state::Event::OverlayChanged(layout.clone()))
-                                .or_print(
-                                    logging::Problem::Bug,
-                                    &format!("Can't send to state"),
-                                );
-                            set_visible_layout(layout)
-                        });
+                        .and_then(|state| layouts.find_layout(&state))
+                        .map(|layout| choose_layout(&app_state, layout));
                 },
                 None => log_print!(
                     logging::Level::Debug,
@@ -363,6 +521,53 @@ pub fn show(
         action_group.add_action(&layout_action);
     };
 
+    let entry_action = gio::SimpleAction::new(
+        "entry",
+        Some(String::new().to_variant().type_()),
+    );
+    let entries = menu.entries;
+    entry_action.connect_activate(move |_, index| {
+        let entry = index
+            .and_then(|index| index.get::<String>())
+            .and_then(|index| index.parse::<usize>().ok())
+            .and_then(|index| entries.get(index));
+        match entry {
+            Some(entry) => activate_entry(keyboard, entry, &entry_state),
+            None => log_print!(logging::Level::Bug, "No such menu entry"),
+        }
+    });
+    action_group.add_action(&entry_action);
+
+    add_quick_settings(&action_group);
+
+    let hide_action = gio::SimpleAction::new("hide", None);
+    hide_action.connect_activate(move |_, _| {
+        hide_state
+            .send(state::Event::Visibility(state::visibility::Event::ForceHidden))
+            .or_print(logging::Problem::Bug, "Can't send to state");
+    });
+    action_group.add_action(&hide_action);
+
+    let rearrange_action = gio::SimpleAction::new_stateful(
+        "rearrange",
+        None,
+        &rearrange::is_editing().to_variant(),
+    );
+    rearrange_action.connect_change_state(move |action, state| {
+        if let Some(editing) = state.and_then(|state| state.get::<bool>()) {
+            rearrange::set_editing(editing);
+            action.set_state(&editing.to_variant());
+            drawing::queue_redraw(keyboard);
+        }
+    });
+    action_group.add_action(&rearrange_action);
+
+    let restore_action = gio::SimpleAction::new("restore-keys", None);
+    restore_action.connect_activate(move |_, _| {
+        rearrange::reset(&restore_state);
+    });
+    action_group.add_action(&restore_action);
+
     let settings_action = gio::SimpleAction::new("settings", None);
     settings_action.connect_activate(move |_, _| {
         let s = CString::new("region").unwrap();
@@ -370,11 +575,70 @@ pub fn show(
     });
     action_group.add_action(&settings_action);
 
-    menu.insert_action_group("popup", Some(&action_group));
+    popover_menu.insert_action_group("popup", Some(&action_group));
+
+    popover_menu.bind_model(Some(&model), Some("popup"));
+    if let Some(carousel) = carousel {
+        add_carousel(&popover_menu, &carousel);
+    }
+    add_height_slider(&popover_menu, &builder);
+    glib::idle_add_local(move || {
+        popover_menu.popup();
+        Continue(false)
+    });
+}
+
+/// Offers the texts in a popover pointing at the position,
+/// and passes the one chosen to the callback.
+pub fn present_choices<F: Fn(&str) + 'static>(
+    window: EekGtkKeyboard,
+    position: Bounds,
+    choices: Vec<String>,
+    on_choice: F,
+) {
+    unsafe { gtk::set_initialized() };
+    let window = unsafe { gtk::Widget::from_glib_none(window.0) };
+
+    let model = gio::Menu::new();
+    for (index, choice) in choices.iter().enumerate() {
+        let detailed_action = format!("choice::{}", index);
+        model.append(Some(choice), Some(detailed_action.as_str()));
+    }
+
+    #[cfg(feature = "glib_v0_14")]
+    let popover_menu = gtk::Popover::from_model(Some(&window), &model);
+    #[cfg(not(feature = "glib_v0_14"))]
+    let popover_menu = gtk::Popover::new_from_model(Some(&window), &model);
+
+    popover_menu.set_pointing_to(&gtk::Rectangle {
+        x: position.x.ceil() as i32,
+        y: position.y.ceil() as i32,
+        width: position.width.floor() as i32,
+        height: position.height.floor() as i32,
+    });
+    popover_menu.set_constrain_to(gtk::PopoverConstraint::None);
+
+    let action_group = gio::SimpleActionGroup::new();
+    let choice_action = gio::SimpleAction::new(
+        "choice",
+        Some(String::new().to_variant().type_()),
+    );
+    choice_action.connect_activate(move |_, index| {
+        let choice = index
+            .and_then(|index| index.get::<String>())
+            .and_then(|index| index.parse::<usize>().ok())
+            .and_then(|index| choices.get(index));
+        match choice {
+            Some(choice) => on_choice(choice),
+            None => log_print!(logging::Level::Bug, "No such choice"),
+        }
+    });
+    action_group.add_action(&choice_action);
 
-    menu.bind_model(Some(&model), Some("popup"));
+    popover_menu.insert_action_group("choices", Some(&action_group));
+    popover_menu.bind_model(Some(&model), Some("choices"));
     glib::idle_add_local(move || {
-        menu.popup();
+        popover_menu.popup();
         Continue(false)
     });
 }
@@ -0,0 +1,116 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Requests to the XDG desktop portal.
+ *
+ * The `screenshot` action asks the portal for a screenshot,
+ * which is useful on phones without a button combination for it.
+ * The portal decides where the picture goes, and tells its URI.
+ *
+ * A screenshot captures whatever other applications show,
+ * so the action does nothing unless the "allow-screenshots" setting is on.
+ * Calls happen on a separate thread, like for notifications.
+ *
+ * Folders get opened through the portal as well, instead of with a process.
+ * The portal lets the user confirm, and pick the application.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread;
+use zbus::{ Connection, fdo };
+
+use crate::logging;
+
+// Traits
+use crate::logging::Warn;
+
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREENSHOT: &str = "org.freedesktop.portal.Screenshot";
+const OPEN_URI: &str = "org.freedesktop.portal.OpenURI";
+const REQUEST: &str = "org.freedesktop.portal.Request";
+
+/// Follows the user's settings
+static ALLOWED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_allowed(allowed: bool) {
+    ALLOWED.store(allowed, Ordering::Relaxed);
+}
+
+type Error = Box<dyn std::error::Error>;
+
+/// Waits for the portal to answer, and returns the URI of the picture.
+/// None if the user or the portal refused.
+fn take_screenshot() -> Result<Option<String>, Error> {
+    let connection = Connection::new_session()?;
+    // Before the call, so that a quick response doesn't get lost.
+    fdo::DBusProxy::new(&connection)?.add_match(
+        &format!("type='signal',interface='{}',member='Response'", REQUEST),
+    )?;
+    let mut options: HashMap<&str, zvariant::Value> = HashMap::new();
+    options.insert("interactive", false.into());
+    let reply = connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(SCREENSHOT),
+        "Screenshot",
+        &("", options), // no parent window
+    )?;
+    let handle: zvariant::ObjectPath = reply.body()?;
+
+    loop {
+        let message = connection.receive_message()?;
+        let header = message.header()?;
+        let is_ours = header.member()? == Some("Response")
+            && header.path()?.map(|path| path.as_str()) == Some(handle.as_str());
+        if is_ours {
+            let (response, results): (u32, HashMap<&str, zvariant::Value>)
+                = message.body()?;
+            return Ok(match (response, results.get("uri")) {
+                (0, Some(zvariant::Value::Str(uri))) => Some(uri.as_str().into()),
+                _ => None,
+            });
+        }
+    }
+}
+
+/// Asks the portal to show the folder, after the user confirms.
+/// The portal takes the open folder, so that no path gets interpreted.
+pub fn open_folder(dir: &Path) -> Result<(), Error> {
+    let connection = Connection::new_session()?;
+    let folder = File::open(dir)?;
+    let mut options: HashMap<&str, zvariant::Value> = HashMap::new();
+    options.insert("ask", true.into());
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(OPEN_URI),
+        "OpenFile",
+        &("", zvariant::Fd::from(folder.as_raw_fd()), options), // no parent window
+    )?;
+    Ok(())
+}
+
+/// Takes a screenshot in the background, if the settings allow it.
+pub fn screenshot() {
+    if !ALLOWED.load(Ordering::Relaxed) {
+        log_print!(
+            logging::Level::Info,
+            "Screenshots are not allowed in the settings",
+        );
+        return;
+    }
+    thread::spawn(|| {
+        match take_screenshot().or_print(logging::Problem::Warning, "Can't take screenshot") {
+            Some(Some(uri)) => log_print!(logging::Level::Info, "Screenshot saved to {}", uri),
+            Some(None) => log_print!(logging::Level::Info, "Screenshot refused"),
+            None => {},
+        }
+    });
+}
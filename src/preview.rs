@@ -0,0 +1,135 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Miniature pictures of layouts, shown in the layout menu.
+ *
+ * Layouts get drawn without a theme or a window,
+ * into PNG files in the runtime dir, which the popover shows as images.
+ * Every layout gets drawn once per scale factor in a session.
+ *
+ * Only system layouts get pictures.
+ * Overlays are recognizable by name,
+ * and some of them don't exist until they get generated.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::data::loading;
+use crate::data::parsing::BottomRow;
+use crate::imservice::ContentPurpose;
+use crate::layout::{ ArrangementKind, Label, View };
+use crate::logging;
+use crate::xdg;
+
+// Traits
+use crate::logging::Warn;
+
+
+/// In logical pixels
+pub const WIDTH: f64 = 120.0;
+
+const BACKGROUND: (f64, f64, f64) = (0.18, 0.18, 0.2);
+const KEY: (f64, f64, f64) = (0.33, 0.33, 0.36);
+const TEXT: (f64, f64, f64) = (0.95, 0.95, 0.95);
+/// Between keys, as a share of the row height
+const GAP: f64 = 0.06;
+
+thread_local! {
+    /// Pictures drawn so far, with None for the layouts which failed
+    static CACHE: RefCell<HashMap<(String, i32), Option<PathBuf>>>
+        = RefCell::new(HashMap::new());
+}
+
+type Error = Box<dyn std::error::Error>;
+
+fn set_color(cr: &cairo::Context, (r, g, b): (f64, f64, f64)) {
+    cr.set_source_rgb(r, g, b);
+}
+
+/// Draws the view scaled by the factor, from the origin.
+fn draw(cr: &cairo::Context, view: &View, scale: f64) {
+    cr.scale(scale, scale);
+    set_color(cr, BACKGROUND);
+    cr.paint();
+    for (row_offset, row) in view.get_rows() {
+        for (x, button) in row.get_buttons() {
+            let (x, y) = (row_offset.x + x, row_offset.y);
+            let (width, height) = (button.size.width, button.size.height);
+            let gap = GAP * height;
+            cr.rectangle(x + gap, y + gap, width - 2.0 * gap, height - 2.0 * gap);
+            set_color(cr, KEY);
+            cr.fill();
+            // Icons are too small to make out anyway.
+            if let Label::Text(text) = &button.label {
+                if let Ok(text) = text.to_str() {
+                    let font_size = height * 0.45;
+                    // Close enough to centered without measuring the text
+                    let text_width = font_size * 0.55 * text.chars().count() as f64;
+                    set_color(cr, TEXT);
+                    cr.set_font_size(font_size);
+                    cr.move_to(x + (width - text_width) / 2.0, y + height * 0.65);
+                    cr.show_text(text);
+                }
+            }
+        }
+    }
+}
+
+fn render(name: &str, scale_factor: i32) -> Result<PathBuf, Error> {
+    let (layout, substitute) = loading::load_layout(
+        &name.to_string(),
+        ArrangementKind::Base,
+        ContentPurpose::Normal,
+        &None,
+        &BottomRow::default(),
+    );
+    // A picture of the fallback would mislead.
+    if substitute.is_some() {
+        return Err(format!("No layout named {}", name).into());
+    }
+    let view = layout.get_current_view();
+    let size = view.get_size();
+    let scale = WIDTH * scale_factor as f64 / size.width;
+    let surface = cairo::ImageSurface::create(
+        cairo::Format::ARgb32,
+        (size.width * scale).ceil() as i32,
+        (size.height * scale).ceil() as i32,
+    ).map_err(|e| format!("Can't create the surface: {:?}", e))?;
+    {
+        #[cfg(feature = "glib_v0_14")]
+        let cr = cairo::Context::new(&surface)?;
+        #[cfg(not(feature = "glib_v0_14"))]
+        let cr = cairo::Context::new(&surface);
+        draw(&cr, view, scale);
+    }
+
+    let file_name = format!("{}@{}.png", name.replace('/', "_"), scale_factor);
+    let path = xdg::runtime_path(format!("squeekboard/previews/{}", file_name))
+        .ok_or("No runtime dir")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    surface.write_to_png(&mut File::create(&path)?)
+        .map_err(|e| format!("Can't write the picture: {:?}", e))?;
+    Ok(path)
+}
+
+/// Returns the picture of the system layout, drawing it the first time.
+pub fn get(name: &str, scale_factor: i32) -> Option<PathBuf> {
+    let key = (name.to_string(), scale_factor);
+    if let Some(path) = CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+        return path;
+    }
+    let path = render(name, scale_factor)
+        .or_print(
+            logging::Problem::Warning,
+            &format!("Can't draw the preview of {}", name),
+        );
+    CACHE.with(|cache| cache.borrow_mut().insert(key, path.clone()));
+    path
+}
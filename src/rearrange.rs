@@ -0,0 +1,327 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Moving and hiding buttons, in a mode entered from the layout menu.
+ *
+ * While rearranging, buttons don't type.
+ * Holding a button lifts it. Dropping it on another button
+ * makes the two trade places, and dropping it above the keyboard hides it.
+ *
+ * The changes go into a file per layout, in the data dir,
+ * and get applied on top of the layout every time it's loaded,
+ * so that updates to the layout itself still come through.
+ * Only text layouts can be rearranged.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{ Duration, Instant };
+use cairo;
+use serde::{ Deserialize, Serialize };
+
+use crate::layout::{ Layout, View };
+use crate::layout::c::{ Bounds, Point };
+use crate::logging;
+use crate::persist::Error;
+use crate::receiver;
+use crate::state;
+use crate::xdg;
+
+// Traits
+use crate::logging::Warn;
+
+
+/// Shorter presses don't lift the button
+const HOLD: Duration = Duration::from_millis(400);
+
+/// The user's changes to one layout
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Changes {
+    /// Buttons trading places, by view, in the order they were moved
+    #[serde(default)]
+    swapped: HashMap<String, Vec<(String, String)>>,
+    /// Buttons left out, by view
+    #[serde(default)]
+    hidden: HashMap<String, Vec<String>>,
+}
+
+impl Changes {
+    fn parse(data: &str) -> Result<Changes, Error> {
+        Ok(serde_yaml::from_str(data)?)
+    }
+
+    /// Changes mentioning missing buttons do nothing,
+    /// in case the layout lost them since.
+    fn apply_to_view(&self, name: &str, view: &mut View) {
+        for (a, b) in self.swapped.get(name).into_iter().flatten() {
+            view.swap_buttons(a, b);
+        }
+        for button in self.hidden.get(name).into_iter().flatten() {
+            view.hide_button(button);
+        }
+    }
+
+    fn apply(&self, layout: &mut Layout) {
+        for (name, (_offset, view)) in layout.views.iter_mut() {
+            if !self.swapped.contains_key(name) && !self.hidden.contains_key(name) {
+                continue;
+            }
+            let changes = self.clone();
+            let name = name.clone();
+            view.modify(move |view| changes.apply_to_view(&name, view));
+        }
+    }
+}
+
+fn get_path(layout: &str) -> Option<PathBuf> {
+    xdg::data_path(format!(
+        "squeekboard/rearranged/{}.yaml",
+        layout.replace('/', "_"),
+    ))
+}
+
+fn load(layout: &str) -> Result<Changes, Error> {
+    let path = match get_path(layout) {
+        Some(path) => path,
+        None => return Ok(Changes::default()),
+    };
+    match fs::read_to_string(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Changes::default()),
+        Err(e) => Err(e.into()),
+        Ok(data) => Changes::parse(&data),
+    }
+}
+
+fn save(layout: &str, changes: &Changes) -> Result<(), Error> {
+    let path = match get_path(layout) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_yaml::to_string(changes)?)?;
+    Ok(())
+}
+
+/// A button held while rearranging
+struct Lifted {
+    name: String,
+    /// Where the button was, relative to the layout
+    bounds: Bounds,
+    start: Point,
+    point: Point,
+    since: Instant,
+}
+
+impl Lifted {
+    fn is_held(&self) -> bool {
+        self.since.elapsed() >= HOLD
+    }
+}
+
+thread_local! {
+    static EDITING: Cell<bool> = Cell::new(false);
+    /// The text layout shown, if any
+    static LAYOUT: RefCell<Option<String>> = RefCell::new(None);
+    static LIFTED: RefCell<Option<Lifted>> = RefCell::new(None);
+}
+
+/// Applies the saved changes to a freshly loaded layout.
+/// `name` is the text layout, or None for anything else.
+pub fn apply(layout: &mut Layout, name: Option<&str>) {
+    LAYOUT.with(|l| *l.borrow_mut() = name.map(String::from));
+    if let Some(name) = name {
+        if let Some(changes) = load(name).or_print(
+            logging::Problem::Warning,
+            &format!("Ignoring the rearranged buttons of {}", name),
+        ) {
+            changes.apply(layout);
+        }
+    }
+}
+
+pub fn is_editing() -> bool {
+    EDITING.with(|e| e.get())
+}
+
+pub fn set_editing(editing: bool) {
+    EDITING.with(|e| e.set(editing));
+    LIFTED.with(|l| *l.borrow_mut() = None);
+}
+
+/// Forgets the changes to the layout shown, and loads it again.
+pub fn reset(app_state: &receiver::State) {
+    let name = match LAYOUT.with(|l| l.borrow().clone()) {
+        Some(name) => name,
+        None => return,
+    };
+    if let Some(path) = get_path(&name) {
+        match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            other => {
+                other.or_print(
+                    logging::Problem::Warning,
+                    "Can't forget the rearranged buttons",
+                );
+            },
+        }
+    }
+    app_state.send(state::Event::ReloadLayout)
+        .or_print(logging::Problem::Bug, "Can't send to state");
+}
+
+/// Starts holding the button.
+pub fn press(name: &str, bounds: Bounds, point: Point) {
+    LIFTED.with(|l| *l.borrow_mut() = Some(Lifted {
+        name: name.into(),
+        bounds,
+        start: point.clone(),
+        point,
+        since: Instant::now(),
+    }));
+}
+
+pub fn drag(point: Point) {
+    LIFTED.with(|l| {
+        if let Some(lifted) = l.borrow_mut().as_mut() {
+            lifted.point = point;
+        }
+    });
+}
+
+/// Where a dropped button ends up
+#[derive(Debug, PartialEq)]
+enum Drop {
+    SwapWith(String),
+    Hide,
+    Stay,
+}
+
+/// `target` is the button under the point, if any.
+fn get_drop(lifted: &Lifted, target: Option<&str>, view_top: f64) -> Drop {
+    if !lifted.is_held() {
+        return Drop::Stay;
+    }
+    if lifted.point.y < view_top {
+        return Drop::Hide;
+    }
+    match target {
+        Some(target) if target != lifted.name => Drop::SwapWith(target.into()),
+        _ => Drop::Stay,
+    }
+}
+
+/// Drops the held button, and remembers where it went.
+/// `target` is the name of the button under the finger.
+pub fn release(layout: &mut Layout, target: Option<&str>) {
+    let lifted = match LIFTED.with(|l| l.borrow_mut().take()) {
+        Some(lifted) => lifted,
+        None => return,
+    };
+    let name = match LAYOUT.with(|l| l.borrow().clone()) {
+        Some(name) => name,
+        None => {
+            log_print!(logging::Level::Info, "Only text layouts can be rearranged");
+            return;
+        },
+    };
+    let view_top = layout.get_current_view_position().0.y;
+    let view_name = layout.current_view.clone();
+    let mut changes = match load(&name).or_print(
+        logging::Problem::Warning,
+        "Can't read the rearranged buttons, not changing them",
+    ) {
+        Some(changes) => changes,
+        None => return,
+    };
+    let view = layout.views.get_mut(&view_name)
+        .expect("Current view is missing")
+        .1.finalize();
+    match get_drop(&lifted, target, view_top) {
+        Drop::Stay => return,
+        Drop::Hide => {
+            view.hide_button(&lifted.name);
+            changes.hidden.entry(view_name).or_insert_with(Vec::new)
+                .push(lifted.name);
+        },
+        Drop::SwapWith(target) => {
+            view.swap_buttons(&lifted.name, &target);
+            changes.swapped.entry(view_name).or_insert_with(Vec::new)
+                .push((lifted.name, target));
+        },
+    }
+    save(&name, &changes)
+        .or_print(logging::Problem::Warning, "Can't save the rearranged buttons");
+}
+
+/// Outlines the buttons while rearranging,
+/// and draws the held button under the finger.
+pub fn render(cr: &cairo::Context, layout: &Layout) {
+    if !is_editing() {
+        return;
+    }
+    cr.save();
+    cr.set_line_width(2.0);
+    cr.set_dash(&[6.0, 4.0], 0.0);
+    cr.set_source_rgba(0.2, 0.5, 0.9, 0.8);
+    layout.foreach_visible_button(|offset, button| {
+        cr.rectangle(
+            offset.x + 2.0, offset.y + 2.0,
+            button.size.width - 4.0, button.size.height - 4.0,
+        );
+    });
+    cr.stroke();
+
+    LIFTED.with(|l| {
+        if let Some(lifted) = l.borrow().as_ref().filter(|lifted| lifted.is_held()) {
+            let Bounds { x, y, width, height } = lifted.bounds;
+            cr.rectangle(
+                x + lifted.point.x - lifted.start.x,
+                y + lifted.point.y - lifted.start.y,
+                width, height,
+            );
+            cr.set_source_rgba(0.2, 0.5, 0.9, 0.5);
+            cr.fill();
+        }
+    });
+    cr.restore();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lifted(y: f64, since: Instant) -> Lifted {
+        Lifted {
+            name: "a".into(),
+            bounds: Bounds { x: 0.0, y: 10.0, width: 10.0, height: 10.0 },
+            start: Point { x: 5.0, y: 15.0 },
+            point: Point { x: 5.0, y },
+            since,
+        }
+    }
+
+    #[test]
+    fn drops() {
+        let held = Instant::now() - HOLD;
+        assert_eq!(get_drop(&lifted(15.0, held), Some("b"), 0.0), Drop::SwapWith("b".into()));
+        assert_eq!(get_drop(&lifted(-5.0, held), None, 0.0), Drop::Hide);
+        assert_eq!(get_drop(&lifted(15.0, held), Some("a"), 0.0), Drop::Stay);
+        // A tap doesn't move anything.
+        assert_eq!(get_drop(&lifted(15.0, Instant::now()), Some("b"), 0.0), Drop::Stay);
+    }
+
+    #[test]
+    fn parse() {
+        let changes = Changes::parse("swapped:\n  base: [[q, w]]\nhidden:\n  base: [comma]\n")
+            .unwrap();
+        assert_eq!(changes.swapped["base"], vec![("q".to_string(), "w".to_string())]);
+        assert_eq!(changes.hidden["base"], vec!["comma".to_string()]);
+    }
+}
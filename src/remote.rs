@@ -0,0 +1,513 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Typing on the device from a web browser on another computer.
+ *
+ * While enabled in the settings, a small web server offers a page
+ * which draws the layout shown on the panel.
+ * Buttons clicked on the page get typed like touched ones,
+ * through `Submission`, on the main loop.
+ * The page switches views on its own,
+ * and buttons which don't type anything do nothing remotely.
+ *
+ * The server listens either on localhost, or on all interfaces.
+ * Every time it starts, it makes up a new pairing code,
+ * shown in a notification, without which the page gets nothing.
+ * PIN layouts with scrambled digits are never sent out.
+ * Clicks count against the speed limit in `automation`.
+ *
+ * The page is plain HTTP, without encryption.
+ * Anyone on the path can read the pairing code and the buttons clicked,
+ * so outside of trusted networks, use "local" access through a tunnel,
+ * like `ssh -L 8947:localhost:8947`.
+ *
+ * Requests are served one at a time, each with a short deadline,
+ * so that a slow client only holds up the others briefly.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{ BuildHasher, Hasher };
+use std::io;
+use std::io::{ BufRead, BufReader, Read, Write };
+use std::net::{ IpAddr, Ipv4Addr, TcpListener, TcpStream };
+use std::rc::Rc;
+use std::sync::{ mpsc, Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+use std::time::{ Duration, Instant };
+use glib::{ Continue, MainContext, PRIORITY_DEFAULT };
+
+use crate::action::Action;
+use crate::automation;
+use crate::data::export;
+use crate::keyboard::{ KeyCode, KeyStateId };
+use crate::logging;
+use crate::main;
+use crate::main::Commands;
+use crate::notification;
+use crate::submission::{ Submission, SubmitData, Timestamp };
+
+// Traits
+use crate::logging::Warn;
+
+
+pub const PORT: u16 = 8947;
+
+const PAGE: &str = include_str!("../data/remote.html");
+
+/// Slows down guessing the pairing code:
+/// after a wrong one, no code from the same address gets checked for this long.
+const WRONG_CODE_DELAY: Duration = Duration::from_secs(1);
+/// How often the server checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// For reading the whole request and writing the response.
+/// Requests are small, and come one at a time.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+/// Requests only carry a button's position
+const MAX_REQUEST: u64 = 8192;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Off,
+    /// Only from the device itself, e.g. through an SSH tunnel
+    Local,
+    /// From anywhere the network allows
+    Network,
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Access::Off
+    }
+}
+
+const ACCESS_NAMES: &[(&str, Access)] = &[
+    ("off", Access::Off),
+    ("local", Access::Local),
+    ("network", Access::Network),
+];
+
+impl Access {
+    /// Takes the names used in the settings.
+    pub fn from_name(name: &str) -> Option<Access> {
+        ACCESS_NAMES.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, access)| *access)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        ACCESS_NAMES.iter()
+            .find(|(_, access)| access == self)
+            .map(|(name, _)| *name)
+            .expect("Access missing from the names")
+    }
+}
+
+type Error = Box<dyn std::error::Error>;
+
+/// A button clicked on the page, sent to the main loop to get typed
+struct Tap {
+    key_id: KeyStateId,
+    action: Action,
+    keycodes: Vec<KeyCode>,
+}
+
+fn type_tap(submission: &mut Submission, tap: Tap, time: Timestamp) {
+    let data = match &tap.action {
+        Action::Submit { text: Some(text), keys: _ } => SubmitData::Text(text),
+        Action::Submit { text: None, keys: _ }
+            | Action::SendOrNewline
+        => SubmitData::Keycodes,
+        Action::Erase => SubmitData::Erase,
+        _ => return,
+    };
+    submission.handle_press(tap.key_id, data, &tap.keycodes, time);
+    submission.handle_release(tap.key_id, time);
+}
+
+/// Six digits, from the kernel if possible.
+fn make_code() -> String {
+    let mut bytes = [0u8; 8];
+    let seed = File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .map(|()| u64::from_ne_bytes(bytes))
+        .unwrap_or_else(|_| RandomState::new().build_hasher().finish());
+    format!("{:06}", seed % 1_000_000)
+}
+
+struct Request {
+    method: String,
+    path: String,
+    code: Option<String>,
+    /// Of the body, which is left to read
+    length: usize,
+}
+
+/// Reads up to the body, which gets read only once the pairing code is right.
+fn read_head<R: BufRead>(reader: &mut R) -> Result<Request, Error> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut code = None;
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(idx) = header.find(':') {
+            let value = header[idx + 1..].trim();
+            match header[..idx].trim().to_ascii_lowercase().as_str() {
+                "content-length" => length = value.parse()?,
+                "x-pairing-code" => code = Some(value.to_string()),
+                _ => {},
+            }
+        }
+    }
+    if length as u64 > MAX_REQUEST {
+        return Err(format!("Request body too long: {}", length).into());
+    }
+    Ok(Request { method, path, code, length })
+}
+
+fn read_body<R: BufRead>(reader: &mut R, request: &Request) -> Result<String, Error> {
+    let mut body = vec![0; request.length];
+    reader.read_exact(&mut body)?;
+    Ok(String::from_utf8(body)?)
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn empty(status: &'static str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: String::new(),
+        }
+    }
+}
+
+fn write_response(mut stream: &TcpStream, response: Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+        Content-Type: {}\r\n\
+        Content-Length: {}\r\n\
+        Cache-Control: no-store\r\n\
+        Connection: close\r\n\
+        \r\n\
+        {}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body,
+    )
+}
+
+/// Reads from the stream until the deadline,
+/// however slowly the client sends.
+struct UntilDeadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl<'a> Read for UntilDeadline<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        // A zero timeout would mean none.
+        if left == Duration::from_secs(0) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Request too slow"));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Checks the pairing code, without holding up the server.
+struct Pairing {
+    code: String,
+    /// When each address last gave a wrong code
+    wrong_at: HashMap<IpAddr, Instant>,
+}
+
+impl Pairing {
+    fn new(code: String) -> Pairing {
+        Pairing { code, wrong_at: HashMap::new() }
+    }
+
+    fn check(
+        &mut self,
+        given: Option<&str>,
+        peer: IpAddr,
+        now: Instant,
+    ) -> Result<(), Response> {
+        // Addresses past the delay don't need remembering.
+        self.wrong_at.retain(|_, wrong_at|
+            now.saturating_duration_since(*wrong_at) < WRONG_CODE_DELAY
+        );
+        if self.wrong_at.contains_key(&peer) {
+            return Err(Response::empty("429 Too Many Requests"));
+        }
+        if given == Some(self.code.as_str()) {
+            Ok(())
+        } else {
+            self.wrong_at.insert(peer, now);
+            Err(Response::empty("403 Forbidden"))
+        }
+    }
+}
+
+struct Server {
+    layout: main::LayoutCopy,
+    /// The server stops when this stops being `current`
+    generation: Arc<AtomicUsize>,
+    current: usize,
+    pairing: Pairing,
+    taps: glib::Sender<Tap>,
+    limiter: automation::Limiter,
+}
+
+impl Server {
+    fn run(mut self, access: Access) -> Result<(), Error> {
+        let address = match access {
+            Access::Network => Ipv4Addr::UNSPECIFIED,
+            _ => Ipv4Addr::LOCALHOST,
+        };
+        let listener = TcpListener::bind((address, PORT))?;
+        listener.set_nonblocking(true)?;
+        notification::remote_keyboard_started(access == Access::Network, &self.pairing.code);
+
+        while self.generation.load(Ordering::SeqCst) == self.current {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    self.serve(stream, address.ip())
+                        .or_print(logging::Problem::Warning, "Bad remote keyboard request");
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn serve(&mut self, stream: TcpStream, peer: IpAddr) -> Result<(), Error> {
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut reader = BufReader::new(
+            UntilDeadline { stream: &stream, deadline }.take(MAX_REQUEST * 2)
+        );
+        let request = read_head(&mut reader)?;
+        let response = match request.path.as_str() {
+            "/layout" | "/press" => {
+                let code = request.code.as_ref().map(String::as_str);
+                match self.pairing.check(code, peer, Instant::now()) {
+                    Ok(()) => {
+                        let body = read_body(&mut reader, &request)?;
+                        self.handle(&request, &body)
+                    },
+                    Err(response) => response,
+                }
+            },
+            _ => self.handle(&request, ""),
+        };
+        write_response(&stream, response)?;
+        Ok(())
+    }
+
+    /// Call only after checking the pairing code.
+    fn handle(&mut self, request: &Request, body: &str) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => Response {
+                status: "200 OK",
+                content_type: "text/html; charset=utf-8",
+                body: PAGE.into(),
+            },
+            ("GET", "/layout") => match self.layout.get() {
+                Some(layout) => Response {
+                    status: "200 OK",
+                    content_type: "application/json",
+                    body: export::to_json(layout),
+                },
+                None => Response::empty("404 Not Found"),
+            },
+            ("POST", "/press") => match self.limiter.take(1, Instant::now()) {
+                Err(wait) => Response {
+                    body: automation::describe_wait(wait),
+                    ..Response::empty("429 Too Many Requests")
+                },
+                Ok(()) => self.handle_press(body),
+            },
+            _ => Response::empty("404 Not Found"),
+        }
+    }
+
+    fn handle_press(&mut self, body: &str) -> Response {
+        match self.press(body) {
+            Ok(()) => Response::empty("204 No Content"),
+            Err(e) => Response {
+                body: e,
+                ..Response::empty("400 Bad Request")
+            },
+        }
+    }
+
+    /// Takes the view name, and the indices of the row and the button.
+    fn press(&mut self, body: &str) -> Result<(), String> {
+        let parts: Vec<&str> = body.split_whitespace().collect();
+        let (view, row, button) = match parts.as_slice() {
+            [view, row, button] => match (row.parse::<usize>(), button.parse::<usize>()) {
+                (Ok(row), Ok(button)) => (*view, row, button),
+                _ => return Err("Bad button position".into()),
+            },
+            _ => return Err("Expected view, row, and button".into()),
+        };
+        let tap = {
+            let layout = self.layout.get().ok_or("No layout shown")?;
+            let key_id = layout.views.get_mut(view)
+                .map(|(_offset, view)| view.finalize())
+                .and_then(|view| view.get_rows().get(row))
+                .and_then(|(_offset, row)| row.get_buttons().get(button))
+                .map(|(_offset, button)| button.key)
+                .ok_or("No such button")?;
+            let key = layout.keys.get(key_id);
+            Tap {
+                key_id,
+                action: key.action.clone(),
+                keycodes: key.keycodes.clone(),
+            }
+        };
+        self.taps.send(tap).map_err(|e| e.to_string())
+    }
+}
+
+/// Starts and stops the server as the settings change,
+/// and keeps track of the layout shown.
+fn watch(commands: mpsc::Receiver<Commands>, taps: glib::Sender<Tap>) {
+    let shown = Arc::new(Mutex::new(None));
+    let generation = Arc::new(AtomicUsize::new(0));
+    let mut server: Option<thread::JoinHandle<()>> = None;
+    for commands in commands.iter() {
+        if let Some(main::commands::SetLayout { description }) = commands.layout_selection {
+            *shown.lock().unwrap() = Some(description);
+        }
+        if let Some(access) = commands.remote_keyboard {
+            let current = generation.fetch_add(1, Ordering::SeqCst) + 1;
+            // The new server needs the port.
+            if let Some(handle) = server.take() {
+                if handle.join().is_err() {
+                    log_print!(logging::Level::Bug, "Remote keyboard server panicked");
+                }
+            }
+            if access != Access::Off {
+                let (shown, generation, taps) = (shown.clone(), generation.clone(), taps.clone());
+                server = Some(thread::spawn(move || {
+                    // Layouts stay on the thread which loaded them.
+                    let new = Server {
+                        layout: main::LayoutCopy::new(shown),
+                        generation,
+                        current,
+                        pairing: Pairing::new(make_code()),
+                        taps,
+                        limiter: automation::Limiter::new(),
+                    };
+                    new.run(access)
+                        .or_print(logging::Problem::Warning, "Remote keyboard stopped");
+                }));
+            }
+        }
+    }
+}
+
+/// Call on the main loop, before the state loop starts.
+pub fn init(commands: mpsc::Receiver<Commands>, submission: Rc<RefCell<Submission>>) {
+    let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    let start = Instant::now();
+    receiver.attach(
+        Some(&MainContext::default()),
+        move |tap| {
+            let time = Timestamp(start.elapsed().as_millis() as u32);
+            let now = Instant::now();
+            match automation::check(now) {
+                Ok(()) => {
+                    type_tap(&mut submission.borrow_mut(), tap, time);
+                    automation::typed(now);
+                },
+                Err(e) => log_print!(logging::Level::Info, "Remote tap ignored: {}", e),
+            }
+            Continue(true)
+        },
+    );
+    thread::spawn(move || watch(commands, sender));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn access_names() {
+        for (name, access) in ACCESS_NAMES {
+            assert_eq!(Access::from_name(name), Some(*access));
+            assert_eq!(access.get_name(), *name);
+        }
+        assert_eq!(Access::from_name("everyone"), None);
+    }
+
+    #[test]
+    fn code() {
+        let code = make_code();
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn wrong_code() {
+        let start = Instant::now();
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2));
+        let other = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 3));
+        let mut pairing = Pairing::new("123456".into());
+        assert!(pairing.check(Some("123456"), peer, start).is_ok());
+        let status = |r: Result<(), Response>| r.err().map(|r| r.status);
+        assert_eq!(status(pairing.check(Some("000000"), peer, start)), Some("403 Forbidden"));
+        let soon = start + Duration::from_millis(10);
+        // Even the right one waits.
+        assert_eq!(
+            status(pairing.check(Some("123456"), peer, soon)),
+            Some("429 Too Many Requests"),
+        );
+        // Others don't.
+        assert!(pairing.check(Some("123456"), other, soon).is_ok());
+        let later = start + WRONG_CODE_DELAY;
+        assert!(pairing.check(Some("123456"), peer, later).is_ok());
+        assert_eq!(status(pairing.check(None, peer, later)), Some("403 Forbidden"));
+    }
+
+    #[test]
+    fn long_body() {
+        let request = "POST /press HTTP/1.1\r\nContent-Length: 100000000\r\n\r\n";
+        assert!(read_head(&mut request.as_bytes()).is_err());
+        let request = "POST /press HTTP/1.1\r\nX-Pairing-Code: 1\r\nContent-Length: 5\r\n\r\nmain 0 0";
+        let mut reader = request.as_bytes();
+        let head = read_head(&mut reader).unwrap();
+        assert_eq!(head.code, Some("1".into()));
+        assert_eq!(read_body(&mut reader, &head).unwrap(), "main ");
+    }
+}
@@ -0,0 +1,174 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Repeating held keys which type text through the input method.
+ *
+ * Key events through the virtual keyboard get repeated by the application,
+ * following the seat's settings.
+ * Text committed through the input method has nobody to repeat it,
+ * so it's done here, with the same delay and rate as on the seat.
+ * The settings can replace the seat's values.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::ffi::CString;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::clock::{ Clock, System };
+use crate::keyboard::KeyStateId;
+use crate::submission::Submission;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RepeatInfo {
+    /// Characters per second. 0 turns repeating off.
+    pub rate: u32,
+    /// Before the first repetition
+    pub delay: Duration,
+}
+
+impl Default for RepeatInfo {
+    /// Like wlroots, until the compositor tells otherwise
+    fn default() -> Self {
+        RepeatInfo {
+            rate: 25,
+            delay: Duration::from_millis(600),
+        }
+    }
+}
+
+impl RepeatInfo {
+    /// From the numbers in `wl_keyboard.repeat_info`
+    pub fn from_wayland(rate: i32, delay: i32) -> RepeatInfo {
+        RepeatInfo {
+            rate: rate.max(0) as u32,
+            delay: Duration::from_millis(delay.max(0) as u64),
+        }
+    }
+
+    /// Replaces the values which are overridden
+    pub fn with_overrides(self, rate: Option<u32>, delay: Option<Duration>) -> RepeatInfo {
+        RepeatInfo {
+            rate: rate.unwrap_or(self.rate),
+            delay: delay.unwrap_or(self.delay),
+        }
+    }
+
+    /// Between repetitions, or None if there are none.
+    pub fn get_interval(&self) -> Option<Duration> {
+        match self.rate {
+            0 => None,
+            rate => Some(Duration::from_secs(1) / rate),
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<RepeatInfo> = Cell::new(RepeatInfo::default());
+}
+
+/// Applies to keys pressed from now on.
+pub fn set(info: RepeatInfo) {
+    CURRENT.with(|current| current.set(info));
+}
+
+/// Commits the text again and again until the key gets released,
+/// as long as it went through the input method in the first place.
+pub fn start(
+    submission: Rc<RefCell<Submission>>,
+    key_id: KeyStateId,
+    text: CString,
+) {
+    let info = CURRENT.with(|current| current.get());
+    let interval = match info.get_interval() {
+        Some(interval) => interval,
+        None => return,
+    };
+    let submission = Rc::downgrade(&submission);
+    let is_held = move || {
+        submission.upgrade()
+            .filter(|submission| submission.borrow().is_committed_as_text(&key_id))
+    };
+    let commit = {
+        let is_held = is_held.clone();
+        move || is_held()
+            .map(|submission| submission.borrow_mut().commit_text(&text).is_ok())
+            .unwrap_or(false)
+    };
+    repeat(System, info.delay, interval, move || is_held().is_some(), commit);
+}
+
+/// Calls `commit` every interval after the delay, while `is_held`,
+/// until `commit` fails.
+fn repeat<C, H, F>(clock: C, delay: Duration, interval: Duration, is_held: H, commit: F)
+    where
+        C: Clock + Clone + 'static,
+        H: Fn() -> bool + 'static,
+        F: FnMut() -> bool + Clone + 'static,
+{
+    let later = clock.clone();
+    clock.schedule(delay, Box::new(move || {
+        if is_held() {
+            // Stops when the text field goes away.
+            later.schedule(interval, Box::new(commit.clone()));
+        }
+        false
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::Manual;
+
+    #[test]
+    fn interval() {
+        assert_eq!(
+            RepeatInfo::from_wayland(25, 600).get_interval(),
+            Some(Duration::from_millis(40)),
+        );
+        assert_eq!(RepeatInfo::from_wayland(0, 600).get_interval(), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let info = RepeatInfo::from_wayland(25, 600)
+            .with_overrides(None, Some(Duration::from_millis(300)));
+        assert_eq!(info.rate, 25);
+        assert_eq!(info.delay, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn cadence() {
+        let clock = Manual::new();
+        let info = RepeatInfo::from_wayland(25, 600);
+        let held = Rc::new(Cell::new(true));
+        let commits = Rc::new(Cell::new(0));
+        let (is_held, still_held, counted) = (held.clone(), held.clone(), commits.clone());
+        repeat(
+            clock.clone(),
+            info.delay,
+            info.get_interval().unwrap(),
+            move || is_held.get(),
+            move || {
+                if still_held.get() {
+                    counted.set(counted.get() + 1);
+                }
+                still_held.get()
+            },
+        );
+        clock.advance(Duration::from_millis(639));
+        assert_eq!(commits.get(), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(commits.get(), 1);
+        // 25 per second
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(commits.get(), 26);
+        held.set(false);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(commits.get(), 26);
+    }
+}
+
@@ -11,6 +11,7 @@ static KEYBOARDS: &[(&'static str, &'static str)] = &[
     // fallback layout.
     ("us", include_str!("../data/keyboards/us.yaml")),
     ("us_wide", include_str!("../data/keyboards/us_wide.yaml")),
+    ("us_compact", include_str!("../data/keyboards/us_compact.yaml")),
 
     // Language layouts: keep alphabetical.
     ("am", include_str!("../data/keyboards/am.yaml")),
@@ -102,6 +103,7 @@ static KEYBOARDS: &[(&'static str, &'static str)] = &[
 
     // Others
     ("number/us", include_str!("../data/keyboards/number/us.yaml")),
+    ("phone/us", include_str!("../data/keyboards/phone/us.yaml")),
     ("pin/us", include_str!("../data/keyboards/pin/us.yaml")),
 
     // Terminal
@@ -114,15 +116,35 @@ static KEYBOARDS: &[(&'static str, &'static str)] = &[
     ("terminal/us+dvorak_wide",   include_str!("../data/keyboards/terminal/us+dvorak_wide.yaml")),
 
     // Overlays
+    ("braille/us", include_str!("../data/keyboards/braille/us.yaml")),
+    ("calculator/us", include_str!("../data/keyboards/calculator/us.yaml")),
     ("emoji/us", include_str!("../data/keyboards/emoji/us.yaml")),
+    ("gamepad/us", include_str!("../data/keyboards/gamepad/us.yaml")),
+    ("handwriting/us", include_str!("../data/keyboards/handwriting/us.yaml")),
+    ("morse/us", include_str!("../data/keyboards/morse/us.yaml")),
+    ("statistics/us", include_str!("../data/keyboards/statistics/us.yaml")),
+    ("steno/us", include_str!("../data/keyboards/steno/us.yaml")),
+    ("t9/us", include_str!("../data/keyboards/t9/us.yaml")),
 ];
 
 pub fn get_keyboard(needle: &str) -> Option<&'static str> {
     KEYBOARDS.iter().find(|(name, _)| *name == needle).map(|(_, layout)| *layout)
 }
 
+pub fn get_keyboard_names() -> Vec<&'static str> {
+    KEYBOARDS.iter().map(|(name, _)| *name).collect()
+}
+
 static OVERLAY_NAMES: &[&'static str] = &[
+    "braille",
+    "calculator",
     "emoji",
+    "gamepad",
+    "handwriting",
+    "morse",
+    "statistics",
+    "steno",
+    "t9",
     "terminal",
 ];
 
@@ -0,0 +1,144 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! The Return key in text fields where it sends the message, like in chats.
+ *
+ * Single-line fields have nowhere to put a new line,
+ * so Return submits them, and the key shows a Send icon instead.
+ * With `return-sends` in the settings, plain multi-line fields work the same,
+ * except that holding the key types a new line.
+ *
+ * The text field only tells what it is when focused,
+ * so the Return key gets changed whenever the layout is loaded.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::ffi::CString;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::imservice::{ ContentHint, ContentPurpose };
+use crate::keyboard::{ KeyCode, KeyStateId };
+use crate::layout;
+use crate::layout::c::EekGtkKeyboard;
+use crate::logging;
+use crate::sound::Sound;
+use crate::submission::{ Submission, SubmitData, Timestamp };
+
+
+pub const SEND_ICON: &str = "mail-send-symbolic";
+
+/// Same as for alternatives of other keys
+const HOLD_FOR_NEWLINE: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReturnKey {
+    /// As defined in the layout
+    Newline,
+    /// Shows the Send icon
+    Send,
+    /// Shows the Send icon, and types a new line when held
+    SendHoldForNewline,
+}
+
+/// Only text meant for people gets sent.
+/// Addresses, numbers, and the like, just get confirmed.
+pub fn get_return_key(
+    purpose: ContentPurpose,
+    hint: ContentHint,
+    return_sends: bool,
+) -> ReturnKey {
+    match purpose {
+        ContentPurpose::Normal | ContentPurpose::Alpha => {
+            if !hint.contains(ContentHint::MULTILINE) {
+                ReturnKey::Send
+            } else if return_sends {
+                ReturnKey::SendHoldForNewline
+            } else {
+                ReturnKey::Newline
+            }
+        },
+        _ => ReturnKey::Newline,
+    }
+}
+
+thread_local! {
+    /// The press which didn't turn into a new line yet.
+    /// Changes on every press, to tell stale timers apart.
+    static PENDING: Cell<Option<u32>> = Cell::new(None);
+    static GENERATION: Cell<u32> = Cell::new(0);
+}
+
+/// Nothing gets typed until it's known how long the key is held.
+pub fn press() {
+    let generation = GENERATION.with(|g| {
+        g.set(g.get().wrapping_add(1));
+        g.get()
+    });
+    PENDING.with(|p| p.set(Some(generation)));
+}
+
+/// Types a new line if the key is still held after a while.
+pub fn start_hold(submission: Rc<RefCell<Submission>>, ui_keyboard: EekGtkKeyboard) {
+    let generation = PENDING.with(|p| p.get());
+    let submission = Rc::downgrade(&submission);
+    let check = move || {
+        let held = generation.is_some() && PENDING.with(|p| p.get()) == generation;
+        if let (true, Some(submission)) = (held, submission.upgrade()) {
+            PENDING.with(|p| p.set(None));
+            layout::emit_feedback(ui_keyboard, Sound::Enter);
+            let newline = CString::new("\n").unwrap();
+            if submission.borrow_mut().commit_text(&newline).is_err() {
+                log_print!(
+                    logging::Level::Warning,
+                    "No text field to receive the new line",
+                );
+            }
+        }
+        glib::Continue(false)
+    };
+
+    #[cfg(feature = "glib_v0_14")]
+    glib::timeout_add_local(HOLD_FOR_NEWLINE, check);
+    #[cfg(not(feature = "glib_v0_14"))]
+    glib::timeout_add_local(HOLD_FOR_NEWLINE.as_millis() as u32, check);
+}
+
+/// Sends with a tap of Return, unless the hold typed a new line already.
+pub fn release(
+    submission: &mut Submission,
+    key_id: KeyStateId,
+    keycodes: &Vec<KeyCode>,
+    time: Timestamp,
+) {
+    if PENDING.with(|p| p.take()).is_some() {
+        submission.handle_press(key_id, SubmitData::Keycodes, keycodes, time);
+        submission.handle_release(key_id, time);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn return_key() {
+        assert_eq!(
+            get_return_key(ContentPurpose::Normal, ContentHint::NONE, false),
+            ReturnKey::Send,
+        );
+        assert_eq!(
+            get_return_key(ContentPurpose::Normal, ContentHint::MULTILINE, false),
+            ReturnKey::Newline,
+        );
+        assert_eq!(
+            get_return_key(ContentPurpose::Normal, ContentHint::MULTILINE, true),
+            ReturnKey::SendHoldForNewline,
+        );
+        assert_eq!(
+            get_return_key(ContentPurpose::Url, ContentHint::NONE, true),
+            ReturnKey::Newline,
+        );
+    }
+}
@@ -0,0 +1,112 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Hardware sensors telling whether anyone can be typing.
+ *
+ * Readings come from iio-sensor-proxy on the system bus.
+ * It doesn't report the device lying face down,
+ * but then the proximity sensor is covered, same as in a pocket,
+ * so proximity is all that's watched.
+ *
+ * Without the service or the sensor, nothing ever gets reported,
+ * and the panel behaves as if the sensor was uncovered.
+ */
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::thread;
+use zbus::{ Connection, fdo };
+use zvariant::OwnedValue;
+
+use crate::event_loop::driver;
+use crate::logging;
+use crate::state;
+
+// Traits
+use crate::logging::Warn;
+
+
+const DESTINATION: &str = "net.hadess.SensorProxy";
+const PATH: &str = "/net/hadess/SensorProxy";
+const INTERFACE: &str = "net.hadess.SensorProxy";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// Something is right in front of the screen
+    Covered,
+    Uncovered,
+}
+
+impl From<bool> for Event {
+    fn from(near: bool) -> Self {
+        if near { Event::Covered }
+        else { Event::Uncovered }
+    }
+}
+
+type Error = Box<dyn std::error::Error>;
+
+fn get_bool(connection: &Connection, name: &str) -> Result<bool, Error> {
+    let reply = connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(PROPERTIES),
+        "Get",
+        &(INTERFACE, name),
+    )?;
+    let value: OwnedValue = reply.body()?;
+    Ok(bool::try_from(value)?)
+}
+
+fn send(sender: &driver::Threaded, event: Event) -> Result<(), Error> {
+    Ok(sender.send(state::Event::Sensor(event))?)
+}
+
+/// Reports proximity until the connection breaks.
+fn watch_proximity(sender: &driver::Threaded) -> Result<(), Error> {
+    let connection = Connection::new_system()?;
+    if !get_bool(&connection, "HasProximity")? {
+        return Ok(());
+    }
+    fdo::DBusProxy::new(&connection)?.add_match(&format!(
+        "type='signal',interface='{}',member='PropertiesChanged',path='{}'",
+        PROPERTIES, PATH,
+    ))?;
+    // The sensor stays on for as long as the connection is open.
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(INTERFACE),
+        "ClaimProximity",
+        &(),
+    )?;
+    send(sender, get_bool(&connection, "ProximityNear")?.into())?;
+
+    loop {
+        let message = connection.receive_message()?;
+        let member = message.header()?.member()?.map(String::from);
+        if member.as_ref().map(String::as_str) != Some("PropertiesChanged") {
+            continue;
+        }
+        let (interface, changed, _invalidated): (String, HashMap<String, OwnedValue>, Vec<String>)
+            = message.body()?;
+        if interface != INTERFACE {
+            continue;
+        }
+        if let Some(near) = changed.get("ProximityNear") {
+            send(sender, bool::try_from(near.clone())?.into())?;
+        }
+    }
+}
+
+pub fn init(sender: driver::Threaded) {
+    thread::spawn(move || {
+        watch_proximity(&sender)
+            .or_print(logging::Problem::Surprise, "No proximity readings");
+        // Whatever the last reading was, it's not true any more.
+        send(&sender, Event::Uncovered)
+            .or_print(logging::Problem::Bug, "Can't send to state manager");
+    });
+}
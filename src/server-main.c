@@ -19,6 +19,7 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 #include <stdlib.h>
+#include <unistd.h>
 #include <gio/gio.h>
 #include <gtk/gtk.h>
 #include <glib/gi18n.h>
@@ -43,6 +44,7 @@ typedef enum _SqueekboardDebugFlags {
     SQUEEKBOARD_DEBUG_FLAG_NONE = 0,
     SQUEEKBOARD_DEBUG_FLAG_FORCE_SHOW    = 1 << 0,
     SQUEEKBOARD_DEBUG_FLAG_GTK_INSPECTOR = 1 << 1,
+    SQUEEKBOARD_DEBUG_FLAG_WATCHDOG = 1 << 2,
 } SqueekboardDebugFlags;
 
 
@@ -108,8 +110,9 @@ registry_handle_global (void *data,
     // currently only v1 supported for most interfaces,
     // so there's no reason to check for available versions.
     // Even when lower version would be served, it would not be supported,
-    // causing a hard exit
-    (void)version;
+    // causing a hard exit.
+    // wl_output is the exception: names come with version 4.
+    // wl_seat is another: repeat_info comes with version 4.
     struct squeek_wayland *wayland = data;
 
     if (!strcmp (interface, zwlr_layer_shell_v1_interface.name)) {
@@ -121,13 +124,18 @@ registry_handle_global (void *data,
     } else if (!strcmp (interface, zwp_input_method_manager_v2_interface.name)) {
         wayland->input_method_manager = wl_registry_bind(registry, name,
             &zwp_input_method_manager_v2_interface, 1);
+    } else if (!strcmp (interface, zwp_text_input_manager_v3_interface.name)) {
+        wayland->text_input_manager = wl_registry_bind(registry, name,
+            &zwp_text_input_manager_v3_interface, 1);
     } else if (!strcmp (interface, "wl_output")) {
+        uint32_t output_version = MIN(MAX(version, 2),
+            MIN((uint32_t)wl_output_interface.version, 4));
         struct wl_output *output = wl_registry_bind (registry, name,
-            &wl_output_interface, 2);
+            &wl_output_interface, output_version);
         squeek_outputs_register(wayland->outputs, output, name);
     } else if (!strcmp(interface, "wl_seat")) {
         wayland->seat = wl_registry_bind(registry, name,
-            &wl_seat_interface, 1);
+            &wl_seat_interface, MIN(version, 4));
     }
 }
 
@@ -150,6 +158,103 @@ static const struct wl_registry_listener registry_listener = {
 };
 
 
+// Key repeat settings of the seat.
+// Nothing else from the keyboard is of interest.
+
+static void
+keyboard_handle_keymap(void *data, struct wl_keyboard *keyboard,
+                       uint32_t format, int32_t fd, uint32_t size)
+{
+    (void)data; (void)keyboard; (void)format; (void)size;
+    close(fd);
+}
+
+static void
+keyboard_handle_enter(void *data, struct wl_keyboard *keyboard,
+                      uint32_t serial, struct wl_surface *surface,
+                      struct wl_array *keys)
+{
+    (void)data; (void)keyboard; (void)serial; (void)surface; (void)keys;
+}
+
+static void
+keyboard_handle_leave(void *data, struct wl_keyboard *keyboard,
+                      uint32_t serial, struct wl_surface *surface)
+{
+    (void)data; (void)keyboard; (void)serial; (void)surface;
+}
+
+static void
+keyboard_handle_key(void *data, struct wl_keyboard *keyboard,
+                    uint32_t serial, uint32_t time, uint32_t key,
+                    uint32_t state)
+{
+    (void)data; (void)keyboard; (void)serial; (void)time; (void)key; (void)state;
+}
+
+static void
+keyboard_handle_modifiers(void *data, struct wl_keyboard *keyboard,
+                          uint32_t serial, uint32_t mods_depressed,
+                          uint32_t mods_latched, uint32_t mods_locked,
+                          uint32_t group)
+{
+    (void)data; (void)keyboard; (void)serial;
+    (void)mods_depressed; (void)mods_latched; (void)mods_locked; (void)group;
+}
+
+static void
+keyboard_handle_repeat_info(void *data, struct wl_keyboard *keyboard,
+                            int32_t rate, int32_t delay)
+{
+    (void)keyboard;
+    squeek_state_send_repeat_info(data, rate, delay);
+}
+
+static const struct wl_keyboard_listener keyboard_listener = {
+    keyboard_handle_keymap,
+    keyboard_handle_enter,
+    keyboard_handle_leave,
+    keyboard_handle_key,
+    keyboard_handle_modifiers,
+    keyboard_handle_repeat_info,
+};
+
+static void
+seat_handle_capabilities(void *data, struct wl_seat *seat, uint32_t caps)
+{
+    static struct wl_keyboard *keyboard = NULL;
+    if ((caps & WL_SEAT_CAPABILITY_KEYBOARD) && !keyboard) {
+        keyboard = wl_seat_get_keyboard(seat);
+        wl_keyboard_add_listener(keyboard, &keyboard_listener, data);
+    } else if (!(caps & WL_SEAT_CAPABILITY_KEYBOARD) && keyboard) {
+        wl_keyboard_release(keyboard);
+        keyboard = NULL;
+    }
+}
+
+static void
+seat_handle_name(void *data, struct wl_seat *seat, const char *name)
+{
+    (void)data; (void)seat; (void)name;
+}
+
+static const struct wl_seat_listener seat_listener = {
+    seat_handle_capabilities,
+    seat_handle_name,
+};
+
+/// Follows the key repeat settings of the seat,
+/// if the compositor is new enough to report them.
+static void
+watch_repeat_info(struct squeek_wayland *wayland,
+                  struct squeek_state_manager *state_manager)
+{
+    if (wl_seat_get_version(wayland->seat) < WL_KEYBOARD_REPEAT_INFO_SINCE_VERSION) {
+        return;
+    }
+    wl_seat_add_listener(wayland->seat, &seat_listener, state_manager);
+}
+
 void init_wayland(struct squeek_wayland *wayland) {
     // Set up Wayland
     gdk_set_allowed_backends ("wayland");
@@ -170,8 +275,8 @@ void init_wayland(struct squeek_wayland *wayland) {
         exit(1);
     }
     if (!wayland->virtual_keyboard_manager) {
-        g_error("No virtual keyboard manager Wayland global available.");
-        exit(1);
+        // Typing may still work through a fallback backend.
+        g_warning("No virtual keyboard manager Wayland global available.");
     }
     if (!wayland->layer_shell) {
         g_error("No layer shell global available.");
@@ -342,6 +447,9 @@ static GDebugKey debug_keys[] =
         { .key = "gtk-inspector",
           .value = SQUEEKBOARD_DEBUG_FLAG_GTK_INSPECTOR,
         },
+        { .key = "watchdog",
+          .value = SQUEEKBOARD_DEBUG_FLAG_WATCHDOG,
+        },
 };
 
 
@@ -367,7 +475,30 @@ main (int argc, char **argv)
     g_autoptr (GError) err = NULL;
     g_autoptr(GOptionContext) opt_context = NULL;
 
+    debug_flags = parse_debug_env ();
+    // Must happen before anything connects to the display
+    // (including option parsing),
+    // so that the supervisor outlives the compositor.
+    if (debug_flags & SQUEEKBOARD_DEBUG_FLAG_WATCHDOG) {
+        squeek_watchdog_supervise ();
+    }
+
+    g_autofree gchar *profile = NULL;
+    g_autofree gchar *layout = NULL;
+    g_autofree gchar *output = NULL;
+    gint height = 0;
+    g_autofree gchar *trace = NULL;
     const GOptionEntry options [] = {
+        { "profile", 0, 0, G_OPTION_ARG_STRING, &profile,
+          "\"kiosk\" locks down for public terminals", "default|kiosk" },
+        { "layout", 0, 0, G_OPTION_ARG_STRING, &layout,
+          "Use this layout instead of the system one", "NAME" },
+        { "output", 0, 0, G_OPTION_ARG_STRING, &output,
+          "Prefer this output, like DSI-1", "NAME" },
+        { "height", 0, 0, G_OPTION_ARG_INT, &height,
+          "Panel height in logical pixels", "PX" },
+        { "trace", 0, 0, G_OPTION_ARG_FILENAME, &trace,
+          "Record state events to a file, for bug reports", "FILE" },
         { NULL, 0, 0, G_OPTION_ARG_NONE, NULL, NULL, NULL }
     };
     opt_context = g_option_context_new ("- A on screen keyboard");
@@ -378,6 +509,10 @@ main (int argc, char **argv)
         g_warning ("%s", err->message);
         return 1;
     }
+    if (height < 0) {
+        g_warning ("Height must not be negative");
+        return 1;
+    }
 
     textdomain (GETTEXT_PACKAGE);
     bind_textdomain_codeset (GETTEXT_PACKAGE, "UTF-8");
@@ -388,7 +523,6 @@ main (int argc, char **argv)
         exit (1);
     }
 
-    debug_flags = parse_debug_env ();
     eek_init ();
 
     phosh_theme_init ();
@@ -396,10 +530,19 @@ main (int argc, char **argv)
     struct squeekboard instance = {0};
 
     // Also initializes wayland
-    struct rsobjects rsobjects = squeek_init();
+    struct squeek_options squeek_options = {
+        .profile = profile,
+        .layout = layout,
+        .output = output,
+        .height = (uint32_t)height,
+        .trace = trace,
+    };
+    struct rsobjects rsobjects = squeek_init(squeek_options);
 
     instance.settings_context = eekboard_context_service_new(rsobjects.state_manager);
 
+    watch_repeat_info(rsobjects.wayland, rsobjects.state_manager);
+
     // set up dbus
 
     // TODO: make dbus errors non-always-fatal
@@ -422,6 +565,7 @@ main (int argc, char **argv)
             exit (1);
         }
         instance.dbus_handler = service;
+        dbus_handler_set_capabilities(service, rsobjects.wayland);
 
         owner_id = g_bus_own_name_on_connection (connection,
                                                  DBUS_SERVICE_INTERFACE,
@@ -0,0 +1,276 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Squeekboard's own options, stored in GSettings.
+ *
+ * All keys get read together into a typed `Settings` value,
+ * which enters the state machine as an event on every change.
+ * Nothing else reads those keys,
+ * so there's no option that only applies after a restart.
+ */
+
+use crate::animation;
+use crate::data::parsing::BottomRow;
+use crate::event_loop::driver;
+use crate::gestures;
+use crate::logging;
+use crate::morse;
+use crate::remote;
+use crate::state::Event;
+use std::time::Duration;
+
+// Traits
+use crate::logging::Warn;
+use gio::prelude::SettingsExt;
+
+
+pub const SCHEMA: &str = "sm.puri.Squeekboard";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// Replaces the GTK theme for the keyboard
+    pub theme: Option<String>,
+    /// Applied to the panel height calculated for the output
+    pub height_percent: u32,
+    /// Haptic and sound feedback on presses
+    pub feedback: bool,
+    /// Percentage of the full volume for key sounds
+    pub sound_volume: u32,
+    /// Flash keys and the keyboard's border
+    pub visual_feedback: bool,
+    /// How long the panel stays after the text field goes away
+    pub hide_delay: Duration,
+    /// Hide the panel while a hardware keyboard is connected
+    pub hide_with_hardware_keyboard: bool,
+    /// Hide the panel while the proximity sensor is covered
+    pub hide_when_covered: bool,
+    /// Show the panel when a text field gets focused
+    pub show_on_focus: bool,
+    /// Shuffle the digits of PIN layouts
+    pub scramble_pin: bool,
+    /// Suggest corrections for misspelled words
+    pub spell_check: bool,
+    /// A handle at the bottom edge reveals the panel hidden by the user
+    pub edge_handle: bool,
+    /// Utility keys around the space bar
+    pub bottom_row: BottomRow,
+    /// Replaces the seat's key repeat rate
+    pub repeat_rate: Option<u32>,
+    /// Replaces the seat's key repeat delay
+    pub repeat_delay: Option<Duration>,
+    /// Let the screenshot action capture the screen
+    pub allow_screenshots: bool,
+    /// Pause after which Morse code signals make a character
+    pub morse_letter_gap: Duration,
+    /// Who may type through the web page
+    pub remote_keyboard: remote::Access,
+    /// Accept typing commands from scripts
+    pub command_socket: bool,
+    /// Composes text from the keys pressed
+    pub ibus_engine: Option<String>,
+    /// Count key presses for the statistics overlay
+    pub statistics: bool,
+    /// Return sends in multi-line text fields, and types a new line when held
+    pub return_sends: bool,
+    /// Tell screen readers about view and layout changes
+    pub announce_changes: bool,
+    /// What swiping across the keyboard does
+    pub gestures: gestures::Mapping,
+}
+
+impl Default for Settings {
+    /// Must match the defaults in the schema
+    fn default() -> Self {
+        Settings {
+            theme: None,
+            height_percent: 100,
+            feedback: true,
+            sound_volume: 100,
+            visual_feedback: false,
+            hide_delay: animation::HIDING_TIMEOUT,
+            hide_with_hardware_keyboard: true,
+            hide_when_covered: true,
+            show_on_focus: true,
+            scramble_pin: false,
+            spell_check: false,
+            edge_handle: false,
+            bottom_row: BottomRow::default(),
+            repeat_rate: None,
+            repeat_delay: None,
+            allow_screenshots: false,
+            morse_letter_gap: morse::DEFAULT_LETTER_GAP,
+            remote_keyboard: remote::Access::Off,
+            command_socket: false,
+            ibus_engine: None,
+            statistics: false,
+            return_sends: false,
+            announce_changes: true,
+            gestures: gestures::Mapping::default(),
+        }
+    }
+}
+
+/// Returns None if the schema is not installed,
+/// instead of aborting like GSettings would.
+pub fn get_settings(schema_name: &str) -> Option<gio::Settings> {
+    let mut error_handler = logging::Print{};
+
+    #[cfg(feature = "glib_v0_14")]
+    let ss = gio::SettingsSchemaSource::default();
+    #[cfg(not(feature = "glib_v0_14"))]
+    let ss = gio::SettingsSchemaSource::get_default();
+
+    ss.or_warn(
+            &mut error_handler,
+            logging::Problem::Surprise,
+            "No gsettings schemas installed.",
+        )
+        .and_then(|sss|
+            sss.lookup(schema_name, true)
+                .or_warn(
+                    &mut error_handler,
+                    logging::Problem::Surprise,
+                    &format!("Gsettings schema {} not installed", schema_name),
+                )
+        )
+        .map(|_sschema| gio::Settings::new(schema_name))
+}
+
+/// 0 leaves the value to the system
+fn get_override(value: u32) -> Option<u32> {
+    match value {
+        0 => None,
+        v => Some(v),
+    }
+}
+
+#[cfg(feature = "glib_v0_14")]
+fn read(settings: &gio::Settings) -> Settings {
+    let theme = String::from(settings.string("theme"));
+    Settings {
+        theme: if theme.is_empty() { None } else { Some(theme) },
+        height_percent: settings.uint("height-percent"),
+        feedback: settings.boolean("feedback"),
+        sound_volume: settings.uint("sound-volume"),
+        visual_feedback: settings.boolean("visual-feedback"),
+        hide_delay: Duration::from_millis(settings.uint("hide-delay") as u64),
+        hide_with_hardware_keyboard: settings.boolean("hide-with-hardware-keyboard"),
+        hide_when_covered: settings.boolean("hide-when-covered"),
+        show_on_focus: settings.boolean("show-on-focus"),
+        scramble_pin: settings.boolean("scramble-pin"),
+        spell_check: settings.boolean("spell-check"),
+        edge_handle: settings.boolean("edge-handle"),
+        bottom_row: BottomRow {
+            emoji: settings.boolean("emoji-key"),
+            voice: settings.boolean("voice-key"),
+            language: settings.boolean("language-key"),
+            comma: settings.boolean("comma-key"),
+            period: settings.boolean("period-key"),
+            extra_keys: settings.strv("extra-keys").into_iter()
+                .map(String::from)
+                .collect(),
+        },
+        repeat_rate: get_override(settings.uint("repeat-rate")),
+        repeat_delay: get_override(settings.uint("repeat-delay"))
+            .map(|ms| Duration::from_millis(ms as u64)),
+        allow_screenshots: settings.boolean("allow-screenshots"),
+        morse_letter_gap: Duration::from_millis(settings.uint("morse-letter-gap") as u64),
+        remote_keyboard: remote::Access::from_name(&settings.string("remote-keyboard"))
+            .unwrap_or_default(),
+        command_socket: settings.boolean("command-socket"),
+        ibus_engine: Some(String::from(settings.string("ibus-engine")))
+            .filter(|engine| !engine.is_empty()),
+        statistics: settings.boolean("statistics"),
+        return_sends: settings.boolean("return-sends"),
+        announce_changes: settings.boolean("announce-changes"),
+        gestures: gestures::Mapping {
+            left: gestures::Command::from_name(&settings.string("swipe-left")),
+            right: gestures::Command::from_name(&settings.string("swipe-right")),
+            up: gestures::Command::from_name(&settings.string("swipe-up")),
+            down: gestures::Command::from_name(&settings.string("swipe-down")),
+        },
+    }
+}
+
+#[cfg(not(feature = "glib_v0_14"))]
+fn get_gesture(settings: &gio::Settings, key: &str) -> Option<gestures::Command> {
+    settings.get_string(key)
+        .and_then(|name| gestures::Command::from_name(&name))
+}
+
+#[cfg(not(feature = "glib_v0_14"))]
+fn read(settings: &gio::Settings) -> Settings {
+    let theme = settings.get_string("theme")
+        .map(String::from)
+        .unwrap_or_default();
+    Settings {
+        theme: if theme.is_empty() { None } else { Some(theme) },
+        height_percent: settings.get_uint("height-percent"),
+        feedback: settings.get_boolean("feedback"),
+        sound_volume: settings.get_uint("sound-volume"),
+        visual_feedback: settings.get_boolean("visual-feedback"),
+        hide_delay: Duration::from_millis(settings.get_uint("hide-delay") as u64),
+        hide_with_hardware_keyboard: settings.get_boolean("hide-with-hardware-keyboard"),
+        hide_when_covered: settings.get_boolean("hide-when-covered"),
+        show_on_focus: settings.get_boolean("show-on-focus"),
+        scramble_pin: settings.get_boolean("scramble-pin"),
+        spell_check: settings.get_boolean("spell-check"),
+        edge_handle: settings.get_boolean("edge-handle"),
+        bottom_row: BottomRow {
+            emoji: settings.get_boolean("emoji-key"),
+            voice: settings.get_boolean("voice-key"),
+            language: settings.get_boolean("language-key"),
+            comma: settings.get_boolean("comma-key"),
+            period: settings.get_boolean("period-key"),
+            extra_keys: settings.get_strv("extra-keys").into_iter()
+                .map(String::from)
+                .collect(),
+        },
+        repeat_rate: get_override(settings.get_uint("repeat-rate")),
+        repeat_delay: get_override(settings.get_uint("repeat-delay"))
+            .map(|ms| Duration::from_millis(ms as u64)),
+        allow_screenshots: settings.get_boolean("allow-screenshots"),
+        morse_letter_gap: Duration::from_millis(settings.get_uint("morse-letter-gap") as u64),
+        remote_keyboard: settings.get_string("remote-keyboard")
+            .and_then(|name| remote::Access::from_name(&name))
+            .unwrap_or_default(),
+        command_socket: settings.get_boolean("command-socket"),
+        ibus_engine: settings.get_string("ibus-engine")
+            .map(String::from)
+            .filter(|engine| !engine.is_empty()),
+        statistics: settings.get_boolean("statistics"),
+        return_sends: settings.get_boolean("return-sends"),
+        announce_changes: settings.get_boolean("announce-changes"),
+        gestures: gestures::Mapping {
+            left: get_gesture(settings, "swipe-left"),
+            right: get_gesture(settings, "swipe-right"),
+            up: get_gesture(settings, "swipe-up"),
+            down: get_gesture(settings, "swipe-down"),
+        },
+    }
+}
+
+fn send(sender: &driver::Threaded, settings: &gio::Settings) {
+    sender.send(Event::SettingsChanged(read(settings)))
+        .or_warn(&mut logging::Print, logging::Problem::Warning, "Can't send to state manager");
+}
+
+/// Sends the current settings, and again after every change.
+/// Without the schema installed, defaults stay in effect.
+pub fn watch(sender: driver::Threaded) {
+    let settings = match get_settings(SCHEMA) {
+        Some(settings) => settings,
+        None => return,
+    };
+    send(&sender, &settings);
+
+    let handler = move |settings: &gio::Settings, _key: &str| send(&sender, settings);
+    #[cfg(feature = "glib_v0_14")]
+    settings.connect_changed(None, handler);
+    #[cfg(not(feature = "glib_v0_14"))]
+    settings.connect_changed(handler);
+
+    // Watched for the lifetime of the process
+    std::mem::forget(settings);
+}
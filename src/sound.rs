@@ -0,0 +1,143 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Sounds of key presses, from the freedesktop sound theme.
+ *
+ * Deleting, Return, and switching views each have an event ID of their own,
+ * so that a theme can make them sound different.
+ * They all extend "button-pressed", and the sound theme spec falls back
+ * by dropping the last part of the ID, so themes without keyboard sounds
+ * play the same click for all of them.
+ *
+ * The sounds get played through libcanberra, on the C side,
+ * which skips them while event sounds are off in the desktop settings.
+ */
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+use crate::action::Action;
+use crate::layout::c::EekGtkKeyboard;
+
+
+/// What a press sounds like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sound {
+    Key,
+    Delete,
+    Enter,
+    /// Switching views, or to another layout
+    Switch,
+}
+
+impl Sound {
+    pub fn for_action(action: &Action) -> Sound {
+        match action {
+            Action::Erase => Sound::Delete,
+            action if action.is_return() => Sound::Enter,
+            Action::SetView(_)
+                | Action::LockView { .. }
+                | Action::ShowOverlay(_)
+                | Action::ShowPreferences
+            => Sound::Switch,
+            _ => Sound::Key,
+        }
+    }
+
+    fn get_event_id(&self) -> &'static str {
+        match self {
+            Sound::Key => "button-pressed",
+            Sound::Delete => "button-pressed-delete",
+            Sound::Enter => "button-pressed-enter",
+            Sound::Switch => "button-pressed-switch",
+        }
+    }
+
+    /// For the sound server, in case it lists what's playing
+    fn get_description(&self) -> &'static str {
+        match self {
+            Sound::Key => "Key pressed",
+            Sound::Delete => "Delete key pressed",
+            Sound::Enter => "Enter key pressed",
+            Sound::Switch => "Keyboard view switched",
+        }
+    }
+}
+
+extern "C" {
+    #[allow(improper_ctypes)]
+    fn eek_gtk_keyboard_play_sound(
+        keyboard: EekGtkKeyboard,
+        event_id: *const c_char,
+        description: *const c_char,
+        volume: *const c_char,
+    );
+}
+
+thread_local! {
+    /// Percentage of the full volume. Must match the default setting
+    static VOLUME: Cell<u32> = Cell::new(100);
+}
+
+/// Follows the user's settings.
+pub fn set_volume(percent: u32) {
+    VOLUME.with(|v| v.set(percent.min(100)));
+}
+
+/// libcanberra wants decibels, as text.
+/// None for silence.
+fn get_volume_db(percent: u32) -> Option<String> {
+    match percent {
+        0 => None,
+        percent => Some(format!("{:.2}", 20.0 * (percent as f64 / 100.0).log10())),
+    }
+}
+
+/// Call through `layout::emit_feedback`, which checks if feedback is on.
+pub fn play(ui_keyboard: EekGtkKeyboard, sound: Sound) {
+    let volume = match get_volume_db(VOLUME.with(|v| v.get())) {
+        Some(volume) => volume,
+        None => return,
+    };
+    // None of those have zeros inside.
+    let event_id = CString::new(sound.get_event_id()).unwrap();
+    let description = CString::new(sound.get_description()).unwrap();
+    let volume = CString::new(volume).unwrap();
+    unsafe {
+        eek_gtk_keyboard_play_sound(
+            ui_keyboard,
+            event_id.as_ptr(),
+            description.as_ptr(),
+            volume.as_ptr(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::KeySym;
+
+    #[test]
+    fn volume() {
+        assert_eq!(get_volume_db(100), Some("0.00".into()));
+        assert_eq!(get_volume_db(50), Some("-6.02".into()));
+        assert_eq!(get_volume_db(0), None);
+    }
+
+    #[test]
+    fn actions() {
+        assert_eq!(Sound::for_action(&Action::Erase), Sound::Delete);
+        assert_eq!(
+            Sound::for_action(&Action::Submit {
+                text: None,
+                keys: vec![KeySym("Return".into())],
+            }),
+            Sound::Enter,
+        );
+        assert_eq!(Sound::for_action(&Action::ShowOverlay("emoji".into())), Sound::Switch);
+        assert_eq!(Sound::for_action(&Action::Steno("S-".into())), Sound::Key);
+    }
+}
@@ -0,0 +1,362 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Spell checking of the text around the cursor.
+ *
+ * Once a word is finished, it gets checked,
+ * and if it's misspelled, corrections appear next to the cursor.
+ * Words found misspelled are remembered,
+ * so the corrections come back when the cursor enters them again.
+ * The `accept_correction` action replaces the word with the best correction.
+ *
+ * Words come from the system word list, which is typically English.
+ * Checkers hide behind the `Checker` trait.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{ BufRead, BufReader };
+use std::rc::Rc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+
+use crate::logging;
+use crate::memory;
+
+
+const WORD_LIST_PATHS: &[&str] = &[
+    "/usr/share/dict/words",
+];
+
+/// Corrections shown at a time
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Misspelled words remembered for when the cursor comes back
+const MAX_REMEMBERED: usize = 32;
+
+/// Follows the user's settings
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub trait Checker {
+    fn is_correct(&self, word: &str) -> bool;
+    /// Returns corrections, best first.
+    fn suggest(&self, word: &str) -> Vec<String>;
+}
+
+/// Knows the words from a list, and nothing more.
+pub struct WordList {
+    /// Lower case
+    words: HashSet<String>,
+}
+
+impl WordList {
+    fn from_lines<R: BufRead>(reader: R) -> WordList {
+        WordList {
+            words: reader.lines()
+                .filter_map(|line| line.ok())
+                .map(|line| line.trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect(),
+        }
+    }
+
+    fn load() -> Option<WordList> {
+        let file = WORD_LIST_PATHS.iter()
+            .filter_map(|path| File::open(path).ok())
+            .next();
+        if file.is_none() {
+            log_print!(
+                logging::Level::Warning,
+                "No word list, spell checking won't work",
+            );
+        }
+        file.map(|file| WordList::from_lines(BufReader::new(file)))
+    }
+
+    /// Returns the system word list, loading it on first use.
+    pub fn get() -> Option<Rc<WordList>> {
+        WORD_LIST.with(|list| {
+            list.borrow_mut()
+                .get_or_insert_with(|| {
+                    let loaded = WordList::load();
+                    memory::set_size(
+                        memory::Cache::WordList,
+                        loaded.as_ref().map(|l| l.words.len()).unwrap_or(0),
+                    );
+                    loaded.map(Rc::new)
+                })
+                .clone()
+        })
+    }
+
+    /// Words with a letter from each group in turn, like typed on a phone keypad.
+    /// Sorted alphabetically, for lack of anything better.
+    pub fn find_by_groups(&self, groups: &[String]) -> Vec<String> {
+        let mut found: Vec<String> = self.words.iter()
+            .filter(|word| word.chars().count() == groups.len())
+            .filter(|word| {
+                word.chars().zip(groups).all(|(c, group)| group.contains(c))
+            })
+            .cloned()
+            .collect();
+        found.sort();
+        found
+    }
+
+    /// Words one typo away: a letter missing, extra, wrong, or swapped.
+    fn get_edits(word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut letters: Vec<char> = ('a'..='z').collect();
+        letters.extend(chars.iter().filter(|c| !c.is_ascii_lowercase()));
+        let join = |parts: &[&[char]]| -> String {
+            parts.iter().flat_map(|part| part.iter()).collect()
+        };
+        let mut edits = Vec::new();
+        for i in 0..chars.len() {
+            edits.push(join(&[&chars[..i], &chars[i + 1..]]));
+        }
+        for i in 0..chars.len().saturating_sub(1) {
+            edits.push(join(&[&chars[..i], &[chars[i + 1], chars[i]], &chars[i + 2..]]));
+        }
+        for i in 0..chars.len() {
+            for letter in &letters {
+                edits.push(join(&[&chars[..i], &[*letter], &chars[i + 1..]]));
+            }
+        }
+        for i in 0..=chars.len() {
+            for letter in &letters {
+                edits.push(join(&[&chars[..i], &[*letter], &chars[i..]]));
+            }
+        }
+        edits
+    }
+}
+
+thread_local! {
+    /// The word list is big, so it's shared between spellers,
+    /// and can be dropped when not needed.
+    /// `Some(None)` when there's no list to load.
+    static WORD_LIST: RefCell<Option<Option<Rc<WordList>>>> = RefCell::new(None);
+}
+
+/// Frees the word list until next needed.
+pub fn drop_word_list() {
+    WORD_LIST.with(|list| list.borrow_mut().take());
+    memory::set_size(memory::Cache::WordList, 0);
+}
+
+/// Brings back the capital letter at the start, if there was one.
+fn match_case(original: &str, suggestion: &str) -> String {
+    match original.chars().next() {
+        Some(c) if c.is_uppercase() => {
+            let mut chars = suggestion.chars();
+            chars.next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        },
+        _ => suggestion.into(),
+    }
+}
+
+impl Checker for WordList {
+    fn is_correct(&self, word: &str) -> bool {
+        // Not a word, or something like a name or acronym
+        word.chars().any(|c| !c.is_alphabetic() && c != '\'')
+            || word.chars().skip(1).any(char::is_uppercase)
+            || self.words.contains(&word.to_lowercase())
+    }
+
+    fn suggest(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let mut found: Vec<String> = Vec::new();
+        for edit in WordList::get_edits(&lower) {
+            if found.len() >= MAX_SUGGESTIONS {
+                break;
+            }
+            if edit != lower && self.words.contains(&edit) && !found.contains(&edit) {
+                found.push(edit);
+            }
+        }
+        found.iter().map(|s| match_case(word, s)).collect()
+    }
+}
+
+/// A misspelled word in the surrounding text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Misspelling {
+    /// Byte offsets in the surrounding text
+    pub start: usize,
+    pub end: usize,
+    pub word: String,
+    pub suggestions: Vec<String>,
+}
+
+impl Misspelling {
+    /// Text for the popup
+    pub fn describe(&self) -> String {
+        match self.suggestions.is_empty() {
+            true => format!("{}?", self.word),
+            false => format!("{} → {}", self.word, self.suggestions.join("  ")),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\''
+}
+
+/// Returns the byte range of the word around the offset, if any.
+fn find_word(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let start = text[..offset].char_indices().rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(offset);
+    let end = text[offset..].char_indices()
+        .find(|(_, c)| !is_word_char(*c))
+        .map(|(i, _)| offset + i)
+        .unwrap_or(text.len());
+    match start < end {
+        true => Some((start, end)),
+        false => None,
+    }
+}
+
+/// What the cursor is at
+#[derive(Debug, PartialEq)]
+enum Place {
+    /// Right after a word and a separator, so the word is finished
+    AfterWord(usize, usize),
+    /// In the word, or at its end, maybe still typing
+    InWord(usize, usize),
+    Elsewhere,
+}
+
+fn find_place(text: &str, cursor: usize) -> Place {
+    if let Some((start, end)) = find_word(text, cursor) {
+        return Place::InWord(start, end);
+    }
+    match text[..cursor].chars().next_back() {
+        Some(separator) => {
+            let before = cursor - separator.len_utf8();
+            match find_word(text, before) {
+                Some((start, end)) if end == before => Place::AfterWord(start, end),
+                _ => Place::Elsewhere,
+            }
+        },
+        None => Place::Elsewhere,
+    }
+}
+
+/// Checks words as the text changes.
+pub struct Speller<C: Checker> {
+    /// Gets the checker on every use, so that it can be dropped in between
+    load: fn() -> Option<Rc<C>>,
+    /// Oldest first
+    remembered: Vec<String>,
+}
+
+impl<C: Checker> Speller<C> {
+    pub fn new(load: fn() -> Option<Rc<C>>) -> Self {
+        Speller {
+            load,
+            remembered: Vec::new(),
+        }
+    }
+
+    fn remember(&mut self, word: &str) {
+        if !self.remembered.iter().any(|w| w == word) {
+            if self.remembered.len() >= MAX_REMEMBERED {
+                self.remembered.remove(0);
+            }
+            self.remembered.push(word.into());
+        }
+    }
+
+    /// Returns the misspelling to show for the cursor position.
+    pub fn check(&mut self, text: &str, cursor: usize) -> Option<Misspelling> {
+        if !text.is_char_boundary(cursor) {
+            return None;
+        }
+        let place = find_place(text, cursor);
+        let (start, end) = match place {
+            Place::AfterWord(start, end) => (start, end),
+            Place::InWord(start, end)
+                if self.remembered.iter().any(|w| w == &text[start..end])
+            => (start, end),
+            _ => return None,
+        };
+        let word = &text[start..end];
+        let suggestions = {
+            let checker = (self.load)()?;
+            if checker.is_correct(word) {
+                return None;
+            }
+            checker.suggest(word)
+        };
+        self.remember(word);
+        Some(Misspelling {
+            start,
+            end,
+            word: word.into(),
+            suggestions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words() -> Option<Rc<WordList>> {
+        Some(Rc::new(WordList::from_lines("word\nwork\nthe\nhello\n".as_bytes())))
+    }
+
+    #[test]
+    fn suggestions() {
+        let list = words().unwrap();
+        assert!(list.is_correct("Hello"));
+        assert!(list.is_correct("GNOME"));
+        assert!(!list.is_correct("wrod"));
+        assert_eq!(list.suggest("wrod"), vec!["word".to_string()]);
+        assert_eq!(list.suggest("Teh"), vec!["The".to_string()]);
+    }
+
+    #[test]
+    fn groups() {
+        let list = words().unwrap();
+        let groups: Vec<String> = vec!["tuv".into(), "ghi".into(), "def".into()];
+        assert_eq!(list.find_by_groups(&groups), vec!["the".to_string()]);
+        let groups: Vec<String> = vec!["wxyz".into(), "mno".into(), "pqrs".into(), "jkl".into()];
+        assert_eq!(list.find_by_groups(&groups), vec!["work".to_string()]);
+    }
+
+    #[test]
+    fn places() {
+        assert_eq!(find_place("hi wrod ", 8), Place::AfterWord(3, 7));
+        assert_eq!(find_place("hi wrod, ", 8), Place::AfterWord(3, 7));
+        assert_eq!(find_place("hi wr", 5), Place::InWord(3, 5));
+        assert_eq!(find_place("hi wrod  ", 9), Place::Elsewhere);
+        assert_eq!(find_place("", 0), Place::Elsewhere);
+    }
+
+    /// Corrections come back when the cursor returns to the word
+    #[test]
+    fn reenter() {
+        let mut speller = Speller::new(words);
+        assert_eq!(speller.check("wro", 3), None);
+        let found = speller.check("wrod ", 5).unwrap();
+        assert_eq!(found.suggestions, vec!["word".to_string()]);
+        assert_eq!(speller.check("wrod the", 8), None);
+        assert_eq!(speller.check("wrod the", 2).map(|m| m.start), Some(0));
+    }
+}
@@ -0,0 +1,2250 @@
+/* Copyright (C) 2021,2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Application-wide state is stored here.
+ * It's driven by the loop defined in the loop module.
+ *
+ * Parts of the state with a policy of their own live in submodules,
+ * and `Application` passes events to them and combines their decisions. */
+
+pub mod output;
+pub mod visibility;
+
+use crate::animation;
+use crate::calculator;
+use crate::data::loading;
+use crate::data::symbols;
+use crate::debug;
+use crate::gestures;
+use crate::imservice::{ ContentHint, ContentPurpose };
+use crate::layout::ArrangementKind;
+use crate::layout::c::Bounds;
+use crate::main;
+use crate::main::Commands;
+use crate::outputs;
+use crate::outputs::{Millimeter, OutputId, OutputState};
+use crate::panel;
+use crate::panel::PixelSize;
+use crate::popover;
+use crate::remote;
+use crate::repeat::RepeatInfo;
+use crate::send_key;
+use crate::sensors;
+use crate::settings::Settings;
+use crate::text_field;
+use crate::util::Rational;
+use std::cmp;
+use std::time::{ Duration, Instant };
+
+
+/// Buttons smaller than this get missed too often, even while looking.
+/// The narrowest keys on the Librem 5 are about 6.5 mm wide.
+pub const MIN_TARGET_SIZE: Rational<Millimeter> = Rational {
+    numerator: Millimeter(5),
+    denominator: 1,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Presence {
+    Present,
+    Missing,
+}
+
+#[derive(Clone, Debug)]
+pub struct InputMethodDetails {
+    pub hint: ContentHint,
+    pub purpose: ContentPurpose,
+}
+
+impl InputMethodDetails {
+    /// Whether the text must not leave the device or be remembered.
+    /// Sensitive data isn't always hidden, like a credit card number.
+    pub fn is_sensitive(&self) -> bool {
+        self.purpose == ContentPurpose::Password
+            || self.hint.intersects(ContentHint::SENSITIVE_DATA | ContentHint::HIDDEN_TEXT)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum InputMethod {
+    Active(InputMethodDetails),
+    InactiveSince(Instant),
+}
+
+/// Text field focus moving in or out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FocusChange {
+    Gained,
+    Lost,
+}
+
+impl InputMethod {
+    /// Takes the new state from the input method,
+    /// and tells whether the active state changed.
+    fn track(self, new: InputMethod) -> (InputMethod, Option<FocusChange>) {
+        match (self, new) {
+            (InputMethod::Active(_old), InputMethod::Active(new))
+                => (InputMethod::Active(new), None),
+            (InputMethod::InactiveSince(_old), InputMethod::Active(new))
+                => (InputMethod::Active(new), Some(FocusChange::Gained)),
+            (InputMethod::Active(_old), InputMethod::InactiveSince(since))
+                => (InputMethod::InactiveSince(since), Some(FocusChange::Lost)),
+            // This is a weird case, there's no need to update an inactive state.
+            // But it's not wrong, just superfluous.
+            // New is going to be newer than old, so it can be ignored.
+            // It was already inactive at that moment.
+            (InputMethod::InactiveSince(old), InputMethod::InactiveSince(_new))
+                => (InputMethod::InactiveSince(old), None),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutSource {
+    Xkb,
+    Other(String),
+}
+
+impl From<String> for LayoutSource {
+    fn from(v: String) -> Self {
+        if v.as_str() == "xkb" {
+            LayoutSource::Xkb
+        } else {
+           LayoutSource::Other(v)
+        }
+    }
+}
+
+/// How long input is ignored in the cleaning mode
+pub const CLEANING_DURATION: Duration = Duration::from_secs(30);
+
+/// The user's preferred system layout
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutChoice {
+    pub name: String,
+    pub source: LayoutSource,
+}
+
+/// Incoming events.
+/// This contains events that cause a change to the internal state.
+#[derive(Clone, Debug)]
+pub enum Event {
+    InputMethod(InputMethod),
+    Visibility(visibility::Event),
+    PhysicalKeyboard(Presence),
+    Sensor(sensors::Event),
+    Output(outputs::Event),
+    LayoutChoice(LayoutChoice),
+    OverlayChanged(popover::LayoutId),
+    /// An application asked for the panel explicitly,
+    /// e.g. a canvas-based editor which doesn't use text fields.
+    AppRequestedVisibility(visibility::AppRequest),
+    SettingsChanged(Settings),
+    /// Key repeat settings of the seat
+    RepeatInfo(RepeatInfo),
+    /// Show this layout definition until further notice
+    PreviewLayout(String),
+    /// Read the layout files again, and stop any preview
+    ReloadLayout,
+    /// Show symbols matching the query first in the symbols picker,
+    /// or stop searching
+    SymbolQuery(Option<String>),
+    /// Type the query for the symbols picker on the text layout
+    EditSymbolQuery,
+    /// Keysyms pressed so far in the compose picker,
+    /// or none to leave the picker
+    ComposeSequence(Option<Vec<String>>),
+    /// Ignore touches for a while, to let the user wipe the screen
+    StartCleaning,
+    /// The user switched to another view of the layout
+    ViewChanged(String),
+    /// The user pressed a key which types
+    KeyPressed,
+    /// Rows of the view as placed in the panel, in logical pixels
+    RowBounds(Vec<Bounds>),
+    Debug(debug::Event),
+    /// Event triggered because a moment in time passed.
+    /// Use to animate state transitions.
+    /// The value is the ideal arrival time.
+    TimeoutReached(Instant),
+}
+
+impl From<InputMethod> for Event {
+    fn from(im: InputMethod) -> Self {
+        Self::InputMethod(im)
+    }
+}
+
+impl From<outputs::Event> for Event {
+    fn from(ev: outputs::Event) -> Self {
+        Self::Output(ev)
+    }
+}
+
+/// The outwardly visible state.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub panel: animation::Outcome,
+    pub im: InputMethod,
+    pub debug_mode: bool,
+    /// Replaces the GTK theme
+    pub theme: Option<String>,
+    pub feedback: bool,
+    pub sound_volume: u32,
+    pub visual_feedback: bool,
+    pub spell_check: bool,
+    pub allow_screenshots: bool,
+    pub morse_letter_gap: Duration,
+    pub remote_keyboard: remote::Access,
+    pub command_socket: bool,
+    pub ibus_engine: Option<String>,
+    pub statistics: bool,
+    pub announce: bool,
+    pub gestures: gestures::Mapping,
+    /// Only while visible
+    pub geometry: Option<panel::Geometry>,
+    /// Seconds left until input gets accepted again
+    pub cleaning: Option<u64>,
+    /// Output showing the handle to bring back the hidden panel
+    pub edge_handle: Option<OutputId>,
+    pub repeat: RepeatInfo,
+}
+
+impl Outcome {
+    /// Returns the commands needed to apply changes as required by the new state.
+    /// Only what changed gets a command,
+    /// so that events which don't affect the panel,
+    /// like most input method updates,
+    /// cause no traffic to the compositor or on D-Bus.
+    pub fn get_commands_to_reach(&self, new_state: &Self) -> Commands {
+        // Switching outputs is a single Show for the new one.
+        // The panel manager takes the surface off the old output first,
+        // and D-Bus doesn't hear about it, because the panel stays visible.
+        let (old_visible, old_panel) = get_panel_command(&self.panel);
+        let (new_visible, new_panel) = get_panel_command(&new_state.panel);
+
+        let panel_visibility = if old_panel != new_panel {
+            Some(new_panel)
+        } else {
+            None
+        };
+
+        let dbus_visible_set = if old_visible != new_visible {
+            Some(new_visible)
+        } else {
+            None
+        };
+
+        // Compare the old and new states as not to flood with updates,
+        // which may look up in the file system.
+        use animation::Outcome::*;
+        let layout_selection = match &new_state.panel {
+            Visible{ contents: new_contents, ..} => {
+                let same
+                    = if let Visible { contents, .. } = &self.panel {
+                        contents == new_contents
+                    } else {
+                        false
+                    };
+
+                if !same {
+                    Some(main::commands::SetLayout {
+                        description: new_contents.clone()
+                    })
+                } else {
+                    None
+                }
+            },
+            animation::Outcome::Hidden => None,
+        };        
+
+        let debug_overlay = if self.debug_mode != new_state.debug_mode {
+            Some(new_state.debug_mode)
+        } else {
+            None
+        };
+
+        let theme = if self.theme != new_state.theme {
+            Some(main::commands::SetTheme { name: new_state.theme.clone() })
+        } else {
+            None
+        };
+
+        let feedback = if self.feedback != new_state.feedback {
+            Some(new_state.feedback)
+        } else {
+            None
+        };
+
+        let sound_volume = if self.sound_volume != new_state.sound_volume {
+            Some(new_state.sound_volume)
+        } else {
+            None
+        };
+
+        let visual_feedback = if self.visual_feedback != new_state.visual_feedback {
+            Some(new_state.visual_feedback)
+        } else {
+            None
+        };
+
+        let spell_check = if self.spell_check != new_state.spell_check {
+            Some(new_state.spell_check)
+        } else {
+            None
+        };
+
+        let allow_screenshots = if self.allow_screenshots != new_state.allow_screenshots {
+            Some(new_state.allow_screenshots)
+        } else {
+            None
+        };
+
+        let morse_letter_gap = if self.morse_letter_gap != new_state.morse_letter_gap {
+            Some(new_state.morse_letter_gap)
+        } else {
+            None
+        };
+
+        let remote_keyboard = if self.remote_keyboard != new_state.remote_keyboard {
+            Some(new_state.remote_keyboard)
+        } else {
+            None
+        };
+
+        let command_socket = if self.command_socket != new_state.command_socket {
+            Some(new_state.command_socket)
+        } else {
+            None
+        };
+
+        let ibus_engine = if self.ibus_engine != new_state.ibus_engine {
+            Some(new_state.ibus_engine.clone())
+        } else {
+            None
+        };
+
+        let statistics = if self.statistics != new_state.statistics {
+            Some(new_state.statistics)
+        } else {
+            None
+        };
+
+        let announce = if self.announce != new_state.announce {
+            Some(new_state.announce)
+        } else {
+            None
+        };
+
+        let gestures = if self.gestures != new_state.gestures {
+            Some(new_state.gestures)
+        } else {
+            None
+        };
+
+        let dbus_geometry_set = if self.geometry != new_state.geometry {
+            Some(new_state.geometry.clone())
+        } else {
+            None
+        };
+
+        let cleaning = if self.cleaning != new_state.cleaning {
+            Some(new_state.cleaning)
+        } else {
+            None
+        };
+
+        let edge_handle = if self.edge_handle != new_state.edge_handle {
+            Some(new_state.edge_handle)
+        } else {
+            None
+        };
+
+        let repeat = if self.repeat != new_state.repeat {
+            Some(new_state.repeat)
+        } else {
+            None
+        };
+
+        Commands {
+            panel_visibility,
+            dbus_visible_set,
+            layout_selection,
+            debug_overlay,
+            theme,
+            feedback,
+            sound_volume,
+            visual_feedback,
+            spell_check,
+            allow_screenshots,
+            morse_letter_gap,
+            remote_keyboard,
+            command_socket,
+            ibus_engine,
+            statistics,
+            announce,
+            gestures,
+            dbus_geometry_set,
+            cleaning,
+            edge_handle,
+            repeat,
+        }
+    }
+}
+
+/// Returns whether the panel is visible, and the command to get it there.
+fn get_panel_command(panel: &animation::Outcome) -> (bool, panel::Command) {
+    match panel {
+        animation::Outcome::Visible{output, height, floating, ..}
+            => (true, panel::Command::Show{
+                output: *output,
+                height: *height,
+                floating: *floating,
+            }),
+        animation::Outcome::Hidden => (false, panel::Command::Hide),
+    }
+}
+
+/// Looks up the shape of a view, like `loading::get_shape`.
+pub type GetShape = fn(
+    name: &str,
+    kind: ArrangementKind,
+    purpose: ContentPurpose,
+    overlay: Option<&str>,
+    view: &str,
+) -> Option<loading::Shape>;
+
+/// The actual logic of the program.
+/// At this moment, limited to calculating visibility and IM hints.
+///
+/// It keeps the panel visible for a short time period after each hide request.
+/// This prevents flickering on quick successive enable/disable events.
+/// It does not treat user-driven hiding in a special way.
+///
+/// This is the "functional core".
+/// All state changes return the next state and the optimal time for the next check.
+///
+/// This state tracker can be driven by any event loop.
+#[derive(Clone, Debug)]
+pub struct Application {
+    pub im: InputMethod,
+    pub visibility: visibility::Policy,
+    pub debug_mode_enabled: bool,
+    pub outputs: output::Policy,
+    /// We presume that the system always has some preference,
+    /// even though we receive the preference after init,
+    /// and we might not receive one at all (gsettings missing).
+    /// Then a default is used.
+    pub layout_choice: LayoutChoice,
+    /// Manual override of the system layout
+    pub overlay_layout: Option<popover::LayoutId>,
+    pub settings: Settings,
+    /// Layout definition from an editor, replacing the chosen layout
+    pub layout_preview: Option<String>,
+    /// Increased on every request to load the layout again
+    pub layout_generation: u32,
+    /// Search in the symbols picker
+    pub symbol_query: Option<String>,
+    /// The query is being typed, and goes back to the symbols picker when done
+    pub editing_query: bool,
+    /// Keysyms pressed in the compose picker
+    pub compose_sequence: Vec<String>,
+    /// Input is ignored until then
+    pub cleaning_until: Option<Instant>,
+    /// Shown instead of the system layout
+    pub layout_override: Option<String>,
+    /// Panel height in logical pixels, instead of one matching the output
+    pub height_override: Option<u32>,
+    /// The view last switched to.
+    /// Layouts without it show their base view.
+    pub current_view: String,
+    /// As reported by the keyboard widget
+    pub row_bounds: Vec<Bounds>,
+    /// Keeps the panel up for longer when switching text fields while typing
+    pub last_keypress: Option<Instant>,
+    /// As reported by the seat, before overrides from settings
+    pub seat_repeat: RepeatInfo,
+    /// Where the panel height comes from. Tests don't read layout files.
+    pub get_shape: GetShape,
+}
+
+impl Application {
+    /// A conservative default, ignoring the actual state of things.
+    /// It will initially show the keyboard for a blink.
+    // The ignorance might actually be desired,
+    // as it allows for startup without waiting for a system check.
+    // The downside is that adding actual state should not cause transitions.
+    // Another acceptable alternative is to allow explicitly uninitialized parts.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            im: InputMethod::InactiveSince(now),
+            visibility: Default::default(),
+            debug_mode_enabled: false,
+            outputs: Default::default(),
+            layout_choice: LayoutChoice {
+                name: String::from("us"),
+                source: LayoutSource::Xkb,
+            },
+            overlay_layout: None,
+            settings: Settings::default(),
+            layout_preview: None,
+            layout_generation: 0,
+            symbol_query: None,
+            editing_query: false,
+            compose_sequence: Vec::new(),
+            cleaning_until: None,
+            layout_override: None,
+            height_override: None,
+            current_view: String::from("base"),
+            row_bounds: Vec::new(),
+            last_keypress: None,
+            seat_repeat: RepeatInfo::default(),
+            get_shape: loading::get_shape,
+        }
+    }
+
+    /// Applies events which arrived together, in order.
+    pub fn apply_events(self, events: Vec<Event>, now: Instant) -> Self {
+        events.into_iter()
+            .fold(self, |state, event| state.apply_event(event, now))
+    }
+
+    pub fn apply_event(self, event: Event, now: Instant) -> Self {
+        if self.debug_mode_enabled {
+            println!(
+                "Received event:
+{:#?}",
+                event,
+            );
+        }
+        // The resulting state is available from `DumpState` when needed.
+        match event {
+            Event::Debug(dbg) => Self {
+                debug_mode_enabled: match dbg {
+                    debug::Event::Enable => true,
+                    debug::Event::Disable => false,
+                },
+                ..self
+            },
+
+            Event::TimeoutReached(_) => Self {
+                cleaning_until: self.cleaning_until.filter(|until| now < *until),
+                ..self
+            },
+
+            // Starting again while cleaning gives more time
+            Event::StartCleaning => Self {
+                cleaning_until: Some(now + CLEANING_DURATION),
+                ..self
+            },
+
+            Event::ViewChanged(current_view) => Self {
+                current_view,
+                ..self
+            },
+
+            Event::RowBounds(row_bounds) => Self {
+                row_bounds,
+                ..self
+            },
+
+            Event::KeyPressed => Self {
+                last_keypress: Some(now),
+                ..self
+            },
+
+            Event::Visibility(event) => Self {
+                visibility: self.visibility.apply_user(event),
+                ..self
+            },
+
+            Event::AppRequestedVisibility(request) => Self {
+                visibility: self.visibility.apply_app_request(request),
+                ..self
+            },
+
+            Event::SettingsChanged(settings) => Self {
+                outputs: self.outputs.apply_height_percent(
+                    self.settings.height_percent,
+                    settings.height_percent,
+                ),
+                settings,
+                ..self
+            },
+
+            Event::RepeatInfo(seat_repeat) => Self {
+                seat_repeat,
+                ..self
+            },
+
+            // Sending the same definition again must still load it,
+            // in case the first attempt failed to apply.
+            Event::PreviewLayout(data) => Self {
+                layout_preview: Some(data),
+                layout_generation: self.layout_generation.wrapping_add(1),
+                ..self
+            },
+
+            Event::ReloadLayout => {
+                // The layout files may have changed their shapes too.
+                loading::forget_shapes();
+                Self {
+                    layout_preview: None,
+                    layout_generation: self.layout_generation.wrapping_add(1),
+                    ..self
+                }
+            },
+
+            Event::PhysicalKeyboard(presence) => Self {
+                visibility: self.visibility.apply_physical_keyboard(presence),
+                ..self
+            },
+
+            Event::Sensor(sensor) => Self {
+                visibility: self.visibility.apply_sensor(sensor),
+                ..self
+            },
+
+            Event::Output(event) => Self {
+                outputs: self.outputs.apply(event),
+                ..self
+            },
+
+            Event::InputMethod(new_im) => {
+                let (im, focus) = self.im.track(new_im);
+                let app = Self { im, ..self };
+                match focus {
+                    Some(focus) => app.apply_focus_change(focus, now),
+                    None => app,
+                }
+            },
+
+            Event::LayoutChoice(layout_choice) => Self {
+                // Repeating the same choice doesn't count as a change,
+                // e.g. when the system reports it after restoring saved state.
+                overlay_layout: if layout_choice == self.layout_choice {
+                    self.overlay_layout
+                } else {
+                    None
+                },
+                layout_choice,
+                ..self
+            },
+            
+            Event::OverlayChanged(overlay_layout) => Self {
+                overlay_layout: Some(overlay_layout),
+                symbol_query: None,
+                editing_query: false,
+                compose_sequence: Vec::new(),
+                ..self
+            },
+
+            Event::SymbolQuery(symbol_query) => Self {
+                overlay_layout: if self.editing_query {
+                    Some(popover::LayoutId::Local(symbols::OVERLAY_NAME.into()))
+                } else {
+                    self.overlay_layout
+                },
+                symbol_query,
+                editing_query: false,
+                ..self
+            },
+
+            // Letters are on the text layout
+            Event::EditSymbolQuery => Self {
+                overlay_layout: None,
+                symbol_query: None,
+                editing_query: true,
+                ..self
+            },
+
+            Event::ComposeSequence(Some(compose_sequence)) => Self {
+                compose_sequence,
+                ..self
+            },
+
+            // Back to whatever was there before the picker
+            Event::ComposeSequence(None) => Self {
+                overlay_layout: None,
+                compose_sequence: Vec::new(),
+                ..self
+            },
+        }
+    }
+
+    /// For changes in active state, remove user's visibility override.
+    /// Both directions are spelled out explicitly,
+    /// to not lose the notion that it's the opposition that matters.
+    fn apply_focus_change(self, focus: FocusChange, now: Instant) -> Self {
+        let hidden_by_user = self.visibility.forced == visibility::State::ForcedHidden;
+        let app = Self {
+            visibility: self.visibility.apply_focus_change(),
+            ..self
+        };
+        match focus {
+            FocusChange::Gained => Self {
+                // A scrambled layout gets shuffled again for every field.
+                layout_generation: match (&app.im, app.settings.scramble_pin) {
+                    (InputMethod::Active(details), true)
+                        if details.purpose == ContentPurpose::Pin
+                    => app.layout_generation.wrapping_add(1),
+                    _ => app.layout_generation,
+                },
+                ..app
+            },
+            // Avoid triggering animation when old state was forced hidden
+            FocusChange::Lost if hidden_by_user => Self {
+                im: InputMethod::InactiveSince(now - app.settings.hide_delay * 2),
+                // Typing doesn't bring back what the user hid
+                last_keypress: None,
+                ..app
+            },
+            FocusChange::Lost => app,
+        }
+    }
+
+    /// `get_shape` returns the shape of the layout in the given arrangement,
+    /// if the layout is known.
+    /// `optional_rows` come on top of the layout, like the extra keys.
+    fn get_preferred_height_and_arrangement<F>(
+        output: &OutputState,
+        optional_rows: u32,
+        get_shape: F,
+    ) -> Option<(PixelSize, ArrangementKind)>
+        where F: Fn(ArrangementKind) -> Option<loading::Shape>
+    {
+        output.get_pixel_size()
+            .map(|px_size| {
+                // Assume isotropy.
+                // Pixels/mm.
+                let density = output.get_physical_size()
+                    .and_then(|size| size.width)
+                    .map(|width| Rational {
+                        numerator: px_size.width as i32,
+                        denominator: width.0 as u32,
+                    })
+                    // Whatever the Librem 5 has,
+                    // as a good default.
+                    .unwrap_or(Rational {
+                        numerator: 720,
+                        denominator: 65,
+                    });
+
+                // Based on what works on the L5.
+                // Exceeding that probably wastes space. Reducing makes typing harder.
+                const IDEAL_TARGET_SIZE: Rational<Millimeter> = Rational {
+                    numerator: Millimeter(1084),
+                    denominator: 100,
+                };
+
+                // Still big enough to hit while looking.
+                const COMPACT_TARGET_SIZE: Rational<Millimeter> = Rational {
+                    numerator: Millimeter(813),
+                    denominator: 100,
+                };
+
+                // In logical pixels
+                const MIN_APP_HEIGHT: u32 = 240;
+
+                // For layouts which can't be looked up.
+                const ROW_COUNT: u32 = 4;
+
+                // Wide arrangements are preferred on wide screens,
+                // but the layout might not have one.
+                // This must match `eek_gtk_keyboard.c::get_type`.
+                let abstract_width
+                    = PixelSize {
+                        scale_factor: output.scale as u32,
+                        pixels: px_size.width,
+                    } 
+                    .as_scaled_ceiling();
+
+                let preferred = if abstract_width < 540 {
+                    ArrangementKind::Base
+                } else {
+                    ArrangementKind::Wide
+                };
+
+                let min_app_height = MIN_APP_HEIGHT * output.scale as u32;
+                let min_size_px = (MIN_TARGET_SIZE * density).ceil().0 as u32;
+
+                let get_height = |kind, target_size: Rational<Millimeter>| {
+                    let (arrangement, rows, height_as_widths, smallest_key) = match get_shape(kind) {
+                        Some(shape) => (shape.kind, shape.rows, shape.aspect_ratio, shape.smallest_key),
+                        // Roughly what the built-in layouts have
+                        None => match kind {
+                            ArrangementKind::Base => (kind, ROW_COUNT, 240.0 / 360.0, None),
+                            ArrangementKind::Wide => (kind, ROW_COUNT, 172.0 / 540.0, None),
+                            ArrangementKind::Compact => (kind, ROW_COUNT - 1, 108.0 / 540.0, None),
+                        },
+                    };
+
+                    // With `extra_rows` as tall as the layout's own
+                    let get_height_with = |extra_rows: u32| {
+                        let stretch = (rows + extra_rows) as f64 / cmp::max(rows, 1) as f64;
+                        let ideal_height = target_size * (rows + extra_rows) as i32;
+                        let ideal_height_px = (ideal_height * density).ceil().0 as u32;
+
+                        // Layouts with small buttons need more height for them.
+                        // Until the layout can't fill more, the height decides the button size.
+                        let min_height_px = smallest_key
+                            .filter(|share| *share > 0.0)
+                            .map(|share| (min_size_px as f64 * stretch / share).ceil() as u32)
+                            .unwrap_or(0);
+
+                        // Reduce height to match what the layout can fill.
+                        let height
+                            = cmp::min(
+                                cmp::max(ideal_height_px, min_height_px),
+                                (height_as_widths * stretch * px_size.width as f64).ceil() as u32,
+                            );
+                        (height, min_height_px)
+                    };
+
+                    let (height, min_height_px) = get_height_with(optional_rows);
+                    // Optional rows take space from the keys before the application,
+                    // down to what's still easy to hit.
+                    let height = match optional_rows {
+                        0 => height,
+                        _ => {
+                            let (layout_height, _) = get_height_with(0);
+                            let room = cmp::max(
+                                px_size.height.saturating_sub(min_app_height),
+                                layout_height,
+                            );
+                            let smallest = cmp::max(
+                                min_size_px * (rows + optional_rows),
+                                min_height_px,
+                            );
+                            cmp::min(height, cmp::max(room, smallest))
+                        },
+                    };
+                    (height, arrangement)
+                };
+
+                let (height, arrangement) = get_height(preferred, IDEAL_TARGET_SIZE);
+
+                // Landscape screens may be so short
+                // that the application would be left with a sliver.
+                // Then smaller buttons are better than no application.
+                let (height, arrangement) = match preferred {
+                    ArrangementKind::Wide
+                        if px_size.height < height + min_app_height
+                    => get_height(ArrangementKind::Compact, COMPACT_TARGET_SIZE),
+                    _ => (height, arrangement),
+                };
+
+                (
+                    PixelSize {
+                        scale_factor: output.scale as u32,
+                        pixels: cmp::min(height, px_size.height / 2),
+                    },
+                    arrangement,
+                )
+            })
+    }
+    
+    /// Returns layout name, overlay name
+    fn get_layout_names(&self) -> (String, Option<String>) {
+        (
+            String::from(match &self.overlay_layout {
+                Some(popover::LayoutId::System { name, .. }) => name,
+                _ => self.layout_override.as_ref()
+                    .unwrap_or(&self.layout_choice.name),
+            }),
+            match &self.overlay_layout {
+                Some(popover::LayoutId::Local(name)) => Some(name.clone()),
+                _ => None,
+            },
+        )
+    }
+
+    pub fn get_outcome(&self, now: Instant) -> Outcome {
+        // FIXME: include physical keyboard presence
+        let panel = match self.outputs.preferred {
+            None => animation::Outcome::Hidden,
+            Some(output) => {
+                let (layout_name, overlay) = self.get_layout_names();
+                let (purpose, hint, sensitive) = match &self.im {
+                    InputMethod::Active(details)
+                        => (details.purpose, details.hint, details.is_sensitive()),
+                    InputMethod::InactiveSince(_)
+                        => (ContentPurpose::Normal, ContentHint::NONE, false),
+                };
+                let layout_name = loading::get_hinted_name(&layout_name, hint)
+                    .to_string();
+                let overlay_str = overlay.as_ref().map(String::as_str);
+                let view = &self.current_view;
+                // Shapes come from layouts without the extra keys.
+                let optional_rows = match overlay.is_none()
+                    && !self.settings.bottom_row.extra_keys.is_empty()
+                {
+                    true => 1,
+                    false => 0,
+                };
+                let get_shape = |kind| {
+                    (self.get_shape)(&layout_name, kind, purpose, overlay_str, view)
+                        // Generated overlays go over the text layout.
+                        .or_else(|| (self.get_shape)(&layout_name, kind, purpose, None, view))
+                };
+                let (height, arrangement) = Self::get_preferred_height_and_arrangement(
+                    self.outputs.get(&output).unwrap(),
+                    optional_rows,
+                    get_shape,
+                )
+                    .unwrap_or((
+                        PixelSize{pixels: 0, scale_factor: 1},
+                        ArrangementKind::Base,
+                    ));
+                let floating = get_shape(arrangement)
+                    .map(|shape| shape.floating)
+                    .unwrap_or(false);
+                let output_height = self.outputs.get(&output)
+                    .and_then(|output| output.get_pixel_size())
+                    .map(|size| size.height);
+                let height = PixelSize {
+                    pixels: match (floating, output_height, self.height_override) {
+                        // Floating layouts take the whole output.
+                        (true, Some(pixels), _) => pixels,
+                        (_, _, Some(pixels)) => pixels * height.scale_factor,
+                        _ => height.pixels
+                            * self.outputs.get_height_percent(self.settings.height_percent)
+                            / 100,
+                    },
+                    ..height
+                };
+                let scramble = self.settings.scramble_pin
+                    && purpose == ContentPurpose::Pin
+                    && overlay.is_none();
+                // Without a text field, Return goes to whatever has the focus.
+                let return_key = match &self.im {
+                    InputMethod::Active(_) => send_key::get_return_key(
+                        purpose,
+                        hint,
+                        self.settings.return_sends,
+                    ),
+                    InputMethod::InactiveSince(_) => send_key::ReturnKey::Newline,
+                };
+                let text_field = if self.editing_query {
+                    Some(text_field::Purpose::Search)
+                } else if overlay_str == Some(calculator::OVERLAY_NAME) {
+                    Some(text_field::Purpose::Calculation)
+                } else {
+                    None
+                };
+    
+                // TODO: Instead of setting size to 0 when the output is invalid,
+                // simply go invisible.
+                let visible = animation::Outcome::Visible{
+                    output,
+                    height,
+                    floating,
+                    contents: animation::Contents {
+                        kind: arrangement,
+                        name: layout_name,
+                        overlay_name: overlay,
+                        purpose,
+                        preview: self.layout_preview.clone(),
+                        generation: self.layout_generation,
+                        scramble,
+                        bottom_row: self.settings.bottom_row.clone(),
+                        symbol_query: self.symbol_query.clone(),
+                        text_field,
+                        compose_sequence: self.compose_sequence.clone(),
+                        letter_case: loading::get_letter_case(hint),
+                        sensitive,
+                        return_key,
+                    }
+                };
+
+                let focused = match self.im {
+                    InputMethod::Active(_) => true,
+                    InputMethod::InactiveSince(since)
+                        => now < since + self.get_hide_delay(since),
+                };
+                match self.visibility.is_visible(&self.settings, focused) {
+                    true => visible,
+                    false => animation::Outcome::Hidden,
+                }
+            }
+        };
+        Outcome {
+            geometry: self.get_geometry(&panel),
+            edge_handle: self.get_edge_handle(&panel),
+            panel,
+            im: self.im.clone(),
+            debug_mode: self.debug_mode_enabled,
+            theme: self.settings.theme.clone(),
+            feedback: self.settings.feedback,
+            sound_volume: self.settings.sound_volume,
+            visual_feedback: self.settings.visual_feedback,
+            spell_check: self.settings.spell_check,
+            allow_screenshots: self.settings.allow_screenshots,
+            morse_letter_gap: self.settings.morse_letter_gap,
+            remote_keyboard: self.settings.remote_keyboard,
+            command_socket: self.settings.command_socket,
+            ibus_engine: self.settings.ibus_engine.clone(),
+            statistics: self.settings.statistics,
+            announce: self.settings.announce_changes,
+            gestures: self.settings.gestures,
+            cleaning: self.get_cleaning_seconds(now),
+            repeat: self.seat_repeat.with_overrides(
+                self.settings.repeat_rate,
+                self.settings.repeat_delay,
+            ),
+        }
+    }
+
+    /// Places the panel at the bottom of the output,
+    /// and the rows inside the panel.
+    fn get_geometry(&self, outcome: &animation::Outcome) -> Option<panel::Geometry> {
+        let (output, height) = match outcome {
+            animation::Outcome::Visible { output, height, .. } => (output, height),
+            animation::Outcome::Hidden => return None,
+        };
+        let output = self.outputs.get(output)?;
+        let size = output.get_pixel_size()?;
+        let scale = cmp::max(output.scale, 1);
+        let width = size.width as i32 / scale;
+        let height = height.as_scaled_ceiling() as i32;
+        let top = size.height as i32 / scale - height;
+        Some(panel::Geometry {
+            panel: panel::Rect { x: 0, y: top, width, height },
+            rows: self.row_bounds.iter()
+                .map(|bounds| panel::Rect {
+                    x: bounds.x.round() as i32,
+                    y: top + bounds.y.round() as i32,
+                    width: bounds.width.round() as i32,
+                    height: bounds.height.round() as i32,
+                })
+                .collect(),
+        })
+    }
+
+    /// Only the user's hiding leaves the handle behind,
+    /// for the user to bring the panel back.
+    fn get_edge_handle(&self, outcome: &animation::Outcome) -> Option<OutputId> {
+        let hidden_by_user = match (outcome, self.visibility.forced) {
+            (animation::Outcome::Hidden, visibility::State::ForcedHidden) => true,
+            _ => false,
+        };
+        if hidden_by_user
+            && self.settings.edge_handle
+            && !(self.visibility.covered && self.settings.hide_when_covered)
+        {
+            self.outputs.preferred
+        } else {
+            None
+        }
+    }
+
+    /// Rounded up, so that the countdown ends at 1.
+    fn get_cleaning_seconds(&self, now: Instant) -> Option<u64> {
+        self.cleaning_until
+            .filter(|until| now < *until)
+            .map(|until| {
+                let left = until - now;
+                left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 }
+            })
+    }
+
+    /// The panel stays up for longer if the text field was left
+    /// in the middle of typing.
+    fn get_hide_delay(&self, inactive_since: Instant) -> Duration {
+        match self.last_keypress {
+            Some(pressed)
+                if pressed <= inactive_since
+                    && inactive_since < pressed + animation::RECENT_TYPING
+            => cmp::max(self.settings.hide_delay, animation::TYPING_HIDING_TIMEOUT),
+            _ => self.settings.hide_delay,
+        }
+    }
+
+    /// Returns the next time to update the outcome.
+    pub fn get_next_wake(&self, now: Instant) -> Option<Instant> {
+        // The countdown changes every second.
+        let cleaning_wake = match (self.cleaning_until, self.get_cleaning_seconds(now)) {
+            (Some(until), Some(seconds)) => Some(until - Duration::from_secs(seconds - 1)),
+            _ => None,
+        };
+        let hiding_wake = match self {
+            Self {
+                visibility: visibility::Policy {
+                    forced: visibility::State::NotForced,
+                    ..
+                },
+                im: InputMethod::InactiveSince(since),
+                ..
+            } => {
+                let anim_end = *since + self.get_hide_delay(*since);
+                if now < anim_end { Some(anim_end) }
+                else { None }
+            }
+            _ => None,
+        };
+        match (cleaning_wake, hiding_wake) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::layout::LetterCase;
+    use crate::outputs::c::WlOutput;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn imdetails_new() -> InputMethodDetails {
+        InputMethodDetails {
+            purpose: ContentPurpose::Normal,
+            hint: ContentHint::NONE,
+        }
+    }
+
+    fn fake_output_id(id: usize) -> OutputId {
+        OutputId(unsafe {
+            std::mem::transmute::<_, WlOutput>(id)
+        })
+    }
+
+    /// Like most layouts, with one row fewer in the "numbers" view.
+    pub fn get_fake_shape(
+        _name: &str,
+        kind: ArrangementKind,
+        _purpose: ContentPurpose,
+        _overlay: Option<&str>,
+        view: &str,
+    ) -> Option<loading::Shape> {
+        Some(loading::Shape {
+            kind,
+            rows: match view {
+                "numbers" => 3,
+                _ => 4,
+            },
+            aspect_ratio: match kind {
+                ArrangementKind::Base => 240.0 / 360.0,
+                ArrangementKind::Wide => 172.0 / 540.0,
+                ArrangementKind::Compact => 108.0 / 540.0,
+            },
+            smallest_key: None,
+            floating: false,
+        })
+    }
+
+    pub fn application_with_fake_output(start: Instant) -> Application {
+        let id = fake_output_id(1);
+        let mut known = HashMap::new();
+        known.insert(
+            id,
+            OutputState {
+                current_mode: None,
+                geometry: None,
+                scale: 1,
+                name: None,
+                identity: None,
+            },
+        );
+        Application {
+            outputs: output::Policy {
+                preferred: Some(id),
+                known,
+                ..Default::default()
+            },
+            get_shape: get_fake_shape,
+            ..Application::new(start)
+        }
+    }
+
+    /// Test the original delay scenario: no flicker on quick switches.
+    #[test]
+    fn avoid_hide() {
+        let start = Instant::now(); // doesn't matter when. It would be better to have a reproducible value though
+        let mut now = start;
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+        // Check 100ms at 1ms intervals. It should remain visible.
+        for _i in 0..100 {
+            now += Duration::from_millis(1);
+            assert_matches!(
+                state.get_outcome(now).panel,
+                animation::Outcome::Visible{..},
+                "Hidden when it should remain visible: {:?}",
+                now.saturating_duration_since(start),
+            )
+        }
+
+        let state = state.apply_event(Event::InputMethod(InputMethod::Active(imdetails_new())), now);
+
+        assert_matches!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Visible{..}
+        );
+    }
+
+    /// Make sure that hiding works when input method goes away
+    #[test]
+    fn hide() {
+        let start = Instant::now(); // doesn't matter when. It would be better to have a reproducible value though
+        let mut now = start;
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+
+        while let animation::Outcome::Visible{..} = state.get_outcome(now).panel {
+            now += Duration::from_millis(1);
+            assert!(
+                now < start + Duration::from_millis(250),
+                "Hiding too slow: {:?}",
+                now.saturating_duration_since(start),
+            );
+        }
+    }
+    
+    /// Check against the false showing bug.
+    /// Expectation: it will get hidden and not appear again
+    #[test]
+    fn false_show() {
+        let start = Instant::now(); // doesn't matter when. It would be better to have a reproducible value though
+        let mut now = start;
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        // This reflects the sequence from Wayland:
+        // disable, disable, enable, disable
+        // all in a single batch.
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+        let state = state.apply_event(Event::InputMethod(InputMethod::Active(imdetails_new())), now);
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+
+        while let animation::Outcome::Visible{..} = state.get_outcome(now).panel {
+            now += Duration::from_millis(1);
+            assert!(
+                now < start + Duration::from_millis(250),
+                "Still not hidden: {:?}",
+                now.saturating_duration_since(start),
+            );
+        }
+        
+        // One second without appearing again
+        for _i in 0..1000 {
+            now += Duration::from_millis(1);
+            assert_eq!(
+                state.get_outcome(now).panel,
+                animation::Outcome::Hidden,
+                "Appeared unnecessarily: {:?}",
+                now.saturating_duration_since(start),
+            );
+        }
+    }
+
+    #[test]
+    fn force_visible() {
+        let start = Instant::now(); // doesn't matter when. It would be better to have a reproducible value though
+        let mut now = start;
+        let state = Application {
+            im: InputMethod::InactiveSince(now),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        now += Duration::from_secs(1);
+
+        let state = state.apply_event(Event::Visibility(visibility::Event::ForceVisible), now);
+        assert_matches!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Visible{..},
+            "Failed to show: {:?}",
+            now.saturating_duration_since(start),
+        );
+        
+        now += Duration::from_secs(1);
+        let state = state.apply_event(Event::InputMethod(InputMethod::Active(imdetails_new())), now);
+        now += Duration::from_secs(1);
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+        now += Duration::from_secs(1);
+
+        assert_eq!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Hidden,
+            "Failed to release forced visibility: {:?}",
+            now.saturating_duration_since(start),
+        );
+    }
+
+    #[test]
+    fn keyboard_present() {
+        let start = Instant::now(); // doesn't matter when. It would be better to have a reproducible value though
+        let mut now = start;
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        now += Duration::from_secs(1);
+
+        let state = state.apply_event(Event::PhysicalKeyboard(Presence::Present), now);
+        assert_eq!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Hidden,
+            "Failed to hide: {:?}",
+            now.saturating_duration_since(start),
+        );
+        
+        now += Duration::from_secs(1);
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(now)), now);
+        now += Duration::from_secs(1);
+        let state = state.apply_event(Event::InputMethod(InputMethod::Active(imdetails_new())), now);
+
+        assert_eq!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Hidden,
+            "Failed to remain hidden: {:?}",
+            now.saturating_duration_since(start),
+        );
+
+        now += Duration::from_secs(1);
+        let state = state.apply_event(Event::PhysicalKeyboard(Presence::Missing), now);
+
+        assert_matches!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Visible{..},
+            "Failed to appear: {:?}",
+            now.saturating_duration_since(start),
+        );
+
+    }
+
+    /// A canvas editor summons the panel without activating the input method.
+    #[test]
+    fn app_requested_visibility() {
+        let start = Instant::now();
+        let mut now = start;
+        let state = Application {
+            im: InputMethod::InactiveSince(now),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Missing,
+                forced: visibility::State::NotForced,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        now += Duration::from_secs(1);
+
+        let state = state.apply_event(
+            Event::AppRequestedVisibility(visibility::AppRequest::Show),
+            now,
+        );
+        assert_matches!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Visible{..}
+        );
+
+        // The user still has the last word
+        let hidden = state.clone()
+            .apply_event(Event::Visibility(visibility::Event::ForceHidden), now);
+        assert_eq!(hidden.get_outcome(now).panel, animation::Outcome::Hidden);
+
+        let state = state.apply_event(
+            Event::AppRequestedVisibility(visibility::AppRequest::Hide),
+            now,
+        );
+        assert_eq!(state.get_outcome(now).panel, animation::Outcome::Hidden);
+
+        // Focusing a text field makes the old request irrelevant
+        now += Duration::from_secs(1);
+        let state = state.apply_event(Event::InputMethod(InputMethod::Active(imdetails_new())), now);
+        assert_eq!(state.visibility.app_request, None);
+        assert_matches!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Visible{..}
+        );
+    }
+
+    #[test]
+    fn settings_hide_delay() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            ..application_with_fake_output(start)
+        };
+        let state = state.apply_event(
+            Event::SettingsChanged(Settings {
+                hide_delay: Duration::from_secs(2),
+                ..Settings::default()
+            }),
+            start,
+        );
+        let state = state.apply_event(Event::InputMethod(InputMethod::InactiveSince(start)), start);
+        let now = start + Duration::from_secs(1);
+        assert_matches!(
+            state.get_outcome(now).panel,
+            animation::Outcome::Visible{..}
+        );
+        assert_eq!(
+            state.get_next_wake(now),
+            Some(start + Duration::from_secs(2)),
+        );
+    }
+
+    /// Leaving a text field while typing keeps the panel up for longer
+    #[test]
+    fn typing_hide_delay() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            ..application_with_fake_output(start)
+        };
+        let typed = state.clone().apply_event(Event::KeyPressed, start);
+        let left = start + Duration::from_secs(1);
+        let typed = typed.apply_event(Event::InputMethod(InputMethod::InactiveSince(left)), left);
+        let now = left + animation::HIDING_TIMEOUT;
+        assert_matches!(
+            typed.get_outcome(now).panel,
+            animation::Outcome::Visible{..}
+        );
+        assert_eq!(
+            typed.get_next_wake(now),
+            Some(left + animation::TYPING_HIDING_TIMEOUT),
+        );
+
+        // Long after the last key, the usual delay applies
+        let left = start + animation::RECENT_TYPING + Duration::from_secs(1);
+        let idle = state.apply_event(Event::KeyPressed, start)
+            .apply_event(Event::InputMethod(InputMethod::InactiveSince(left)), left);
+        assert_eq!(
+            idle.get_outcome(left + animation::HIDING_TIMEOUT).panel,
+            animation::Outcome::Hidden,
+        );
+    }
+
+    #[test]
+    fn settings_policies() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            visibility: visibility::Policy {
+                physical_keyboard: Presence::Present,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        assert_eq!(state.get_outcome(start).panel, animation::Outcome::Hidden);
+
+        let state = state.apply_event(
+            Event::SettingsChanged(Settings {
+                hide_with_hardware_keyboard: false,
+                ..Settings::default()
+            }),
+            start,
+        );
+        assert_matches!(
+            state.get_outcome(start).panel,
+            animation::Outcome::Visible{..}
+        );
+
+        let state = state.apply_event(
+            Event::SettingsChanged(Settings {
+                hide_with_hardware_keyboard: false,
+                show_on_focus: false,
+                ..Settings::default()
+            }),
+            start,
+        );
+        assert_eq!(state.get_outcome(start).panel, animation::Outcome::Hidden);
+    }
+
+    #[test]
+    fn settings_commands() {
+        let start = Instant::now();
+        let state = application_with_fake_output(start);
+        let changed = state.clone().apply_event(
+            Event::SettingsChanged(Settings {
+                theme: Some("Adwaita:dark".into()),
+                ..Settings::default()
+            }),
+            start,
+        );
+        let commands = state.get_outcome(start)
+            .get_commands_to_reach(&changed.get_outcome(start));
+        assert_matches!(
+            commands.theme,
+            Some(main::commands::SetTheme { name: Some(_) })
+        );
+        assert_eq!(commands.feedback, None);
+    }
+
+    /// Only changes to the panel reach the compositor and D-Bus.
+    #[test]
+    fn minimal_panel_commands() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            ..application_with_fake_output(start)
+        };
+        let outcome = state.get_outcome(start);
+
+        let commands = outcome.get_commands_to_reach(&state.get_outcome(start));
+        assert_eq!(commands.panel_visibility, None);
+        assert_eq!(commands.dbus_visible_set, None);
+
+        let hidden = state.clone()
+            .apply_event(Event::Visibility(visibility::Event::ForceHidden), start);
+        let commands = outcome.get_commands_to_reach(&hidden.get_outcome(start));
+        assert_eq!(commands.panel_visibility, Some(panel::Command::Hide));
+        assert_eq!(commands.dbus_visible_set, Some(false));
+
+        // A new height needs the panel, but the panel stays visible.
+        let taller = match outcome.panel.clone() {
+            animation::Outcome::Visible { output, height, floating, contents } => Outcome {
+                panel: animation::Outcome::Visible {
+                    output,
+                    height: PixelSize { pixels: height.pixels + 10, ..height },
+                    floating,
+                    contents,
+                },
+                ..outcome.clone()
+            },
+            animation::Outcome::Hidden => panic!("Panel should be visible"),
+        };
+        let commands = outcome.get_commands_to_reach(&taller);
+        assert_matches!(commands.panel_visibility, Some(panel::Command::Show { .. }));
+        assert_eq!(commands.dbus_visible_set, None);
+    }
+
+    /// The visible panel moves when outputs come and go.
+    #[test]
+    fn output_hotplug() {
+        let start = Instant::now();
+        let base = application_with_fake_output(start);
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            outputs: output::Policy {
+                pinned: Some("HDMI-A-1".into()),
+                ..base.outputs
+            },
+            ..base
+        };
+        let first = fake_output_id(1);
+        let pinned = fake_output_id(2);
+        let get_output = |outcome: &Outcome| match outcome.panel {
+            animation::Outcome::Visible { output, .. } => Some(output),
+            animation::Outcome::Hidden => None,
+        };
+        let outcome = state.get_outcome(start);
+        assert_eq!(get_output(&outcome), Some(first));
+
+        let state = state.apply_event(
+            Event::Output(outputs::Event {
+                output: pinned,
+                change: outputs::ChangeType::Altered(OutputState {
+                    current_mode: None,
+                    geometry: None,
+                    scale: 1,
+                    name: Some("HDMI-A-1".into()),
+                    identity: None,
+                }),
+            }),
+            start,
+        );
+        let moved = state.get_outcome(start);
+        let commands = outcome.get_commands_to_reach(&moved);
+        assert_matches!(
+            commands.panel_visibility,
+            Some(panel::Command::Show { output, .. }) if output == pinned
+        );
+        assert_eq!(commands.dbus_visible_set, None);
+
+        // Unplugged, so back to the other one
+        let state = state.apply_event(
+            Event::Output(outputs::Event { output: pinned, change: outputs::ChangeType::Removed }),
+            start,
+        );
+        let back = state.get_outcome(start);
+        let commands = moved.get_commands_to_reach(&back);
+        assert_matches!(
+            commands.panel_visibility,
+            Some(panel::Command::Show { output, .. }) if output == first
+        );
+        assert_eq!(commands.dbus_visible_set, None);
+
+        // Nowhere left to show it
+        let state = state.apply_event(
+            Event::Output(outputs::Event { output: first, change: outputs::ChangeType::Removed }),
+            start,
+        );
+        let commands = back.get_commands_to_reach(&state.get_outcome(start));
+        assert_eq!(commands.panel_visibility, Some(panel::Command::Hide));
+        assert_eq!(commands.dbus_visible_set, Some(false));
+    }
+
+    /// The built-in display and a monitor keep their own heights.
+    #[test]
+    fn height_per_output() {
+        let start = Instant::now();
+        use crate::outputs::{ Geometry, Mode, Size, c };
+        let output = |name: &str, identity: &str| OutputState {
+            current_mode: Some(Mode { width: 720, height: 1440 }),
+            geometry: Some(Geometry {
+                transform: c::Transform::Normal,
+                phys_size: Size {
+                    width: Some(Millimeter(65)),
+                    height: Some(Millimeter(130)),
+                },
+            }),
+            scale: 1,
+            name: Some(name.into()),
+            identity: Some(identity.into()),
+        };
+        let (phone, monitor) = (fake_output_id(1), fake_output_id(2));
+        let base = application_with_fake_output(start);
+        let mut state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            outputs: output::Policy {
+                pinned: Some("HDMI-A-1".into()),
+                ..base.outputs
+            },
+            ..base
+        };
+        state.outputs.known.insert(phone, output("DSI-1", "Purism Librem 5"));
+        let set_height = |state: Application, height_percent| state.apply_event(
+            Event::SettingsChanged(Settings { height_percent, ..Settings::default() }),
+            start,
+        );
+        let plug = |state: Application, change| state.apply_event(
+            Event::Output(outputs::Event { output: monitor, change }),
+            start,
+        );
+        let get_height = |state: &Application| match state.get_outcome(start).panel {
+            animation::Outcome::Visible { height, .. } => height.pixels,
+            animation::Outcome::Hidden => panic!("Panel should be visible"),
+        };
+
+        let state = set_height(state, 120);
+        let phone_height = get_height(&state);
+        let state = plug(
+            state,
+            outputs::ChangeType::Altered(output("HDMI-A-1", "Dell U2720Q")),
+        );
+        assert_eq!(state.outputs.preferred, Some(monitor));
+        let state = set_height(state, 60);
+        let monitor_height = get_height(&state);
+        assert!(monitor_height < phone_height);
+
+        let state = plug(state, outputs::ChangeType::Removed);
+        assert_eq!(get_height(&state), phone_height);
+        let state = plug(
+            state,
+            outputs::ChangeType::Altered(output("HDMI-A-1", "Dell U2720Q")),
+        );
+        assert_eq!(get_height(&state), monitor_height);
+    }
+
+    /// The settings win over the seat.
+    #[test]
+    fn repeat_override() {
+        let start = Instant::now();
+        let state = Application::new(start)
+            .apply_event(Event::RepeatInfo(RepeatInfo::from_wayland(40, 300)), start);
+        let changed = state.clone().apply_event(
+            Event::SettingsChanged(Settings {
+                repeat_rate: Some(10),
+                ..Settings::default()
+            }),
+            start,
+        );
+        let commands = state.get_outcome(start)
+            .get_commands_to_reach(&changed.get_outcome(start));
+        assert_eq!(
+            commands.repeat,
+            Some(RepeatInfo { rate: 10, delay: Duration::from_millis(300) }),
+        );
+    }
+
+    /// The editor sends the same text after fixing a file it includes,
+    /// and the layout must be loaded again.
+    #[test]
+    fn preview_repeated() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            ..application_with_fake_output(start)
+        };
+        let first = state.apply_event(Event::PreviewLayout("views: {}".into()), start);
+        let second = first.clone().apply_event(Event::PreviewLayout("views: {}".into()), start);
+        let commands = first.get_outcome(start)
+            .get_commands_to_reach(&second.get_outcome(start));
+        assert_matches!(commands.layout_selection, Some(_));
+
+        let reloaded = second.clone().apply_event(Event::ReloadLayout, start);
+        assert_eq!(reloaded.layout_preview, None);
+        let commands = second.get_outcome(start)
+            .get_commands_to_reach(&reloaded.get_outcome(start));
+        assert_matches!(commands.layout_selection, Some(_));
+    }
+
+    /// A field for Latin text doesn't get Cyrillic
+    #[test]
+    fn latin_hint() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(InputMethodDetails {
+                hint: ContentHint::LATIN | ContentHint::UPPERCASE,
+                ..imdetails_new()
+            }),
+            layout_choice: LayoutChoice {
+                name: "ru".into(),
+                source: LayoutSource::Xkb,
+            },
+            ..application_with_fake_output(start)
+        };
+        assert_matches!(
+            state.get_outcome(start).panel,
+            animation::Outcome::Visible {
+                contents: animation::Contents {
+                    ref name,
+                    letter_case: Some(LetterCase::Upper),
+                    ..
+                },
+                ..
+            } if name == "us"
+        );
+    }
+
+    #[test]
+    fn sensitive_hint() {
+        let details = InputMethodDetails {
+            hint: ContentHint::SENSITIVE_DATA,
+            ..imdetails_new()
+        };
+        assert!(details.is_sensitive());
+        assert!(!imdetails_new().is_sensitive());
+        let password = InputMethodDetails {
+            purpose: ContentPurpose::Password,
+            ..imdetails_new()
+        };
+        assert!(password.is_sensitive());
+    }
+
+    /// Every PIN field gets a new arrangement of digits
+    #[test]
+    fn scramble_on_focus() {
+        let start = Instant::now();
+        let state = Application {
+            settings: Settings {
+                scramble_pin: true,
+                ..Settings::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        let pin = InputMethodDetails {
+            purpose: ContentPurpose::Pin,
+            ..imdetails_new()
+        };
+        let first = state.clone()
+            .apply_event(Event::InputMethod(InputMethod::Active(pin.clone())), start)
+            .apply_event(Event::InputMethod(InputMethod::InactiveSince(start)), start);
+        let second = first.clone()
+            .apply_event(Event::InputMethod(InputMethod::Active(pin)), start);
+        assert_ne!(first.layout_generation, second.layout_generation);
+        assert_matches!(
+            second.get_outcome(start).panel,
+            animation::Outcome::Visible {
+                contents: animation::Contents { scramble: true, .. },
+                ..
+            }
+        );
+
+        let text = state.clone()
+            .apply_event(Event::InputMethod(InputMethod::Active(imdetails_new())), start);
+        assert_eq!(text.layout_generation, state.layout_generation);
+    }
+
+    /// A search doesn't survive leaving the symbols picker
+    #[test]
+    fn symbol_query_reset() {
+        let start = Instant::now();
+        let state = application_with_fake_output(start)
+            .apply_event(Event::OverlayChanged(popover::LayoutId::Local("symbols".into())), start)
+            .apply_event(Event::SymbolQuery(Some("arrow".into())), start);
+        assert_eq!(state.symbol_query, Some("arrow".into()));
+        let state = state
+            .apply_event(Event::OverlayChanged(popover::LayoutId::Local("emoji".into())), start);
+        assert_eq!(state.symbol_query, None);
+    }
+
+    /// Typing the query happens on letters, and the results on the picker
+    #[test]
+    fn symbol_query_edit() {
+        let start = Instant::now();
+        let picker = Some(popover::LayoutId::Local("symbols".into()));
+        let state = application_with_fake_output(start)
+            .apply_event(Event::OverlayChanged(popover::LayoutId::Local("symbols".into())), start)
+            .apply_event(Event::EditSymbolQuery, start);
+        assert_eq!(state.overlay_layout, None);
+        assert!(state.editing_query);
+        let state = state.apply_event(Event::SymbolQuery(Some("arrow".into())), start);
+        assert_eq!(state.overlay_layout, picker);
+        assert_eq!(state.symbol_query, Some("arrow".into()));
+        assert!(!state.editing_query);
+    }
+
+    #[test]
+    fn compose_finish() {
+        let start = Instant::now();
+        let state = application_with_fake_output(start)
+            .apply_event(Event::OverlayChanged(popover::LayoutId::Local("compose".into())), start)
+            .apply_event(Event::ComposeSequence(Some(vec!["o".into()])), start);
+        assert_eq!(state.compose_sequence, vec!["o".to_string()]);
+        let state = state.apply_event(Event::ComposeSequence(None), start);
+        assert_eq!(state.overlay_layout, None);
+        assert_eq!(state.compose_sequence, Vec::<String>::new());
+    }
+
+    #[test]
+    fn cleaning_countdown() {
+        let start = Instant::now();
+        let state = application_with_fake_output(start)
+            .apply_event(Event::StartCleaning, start);
+        assert_eq!(state.get_outcome(start).cleaning, Some(30));
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(state.get_outcome(later).cleaning, Some(29));
+        assert_eq!(state.get_next_wake(later), Some(start + Duration::from_secs(2)));
+
+        let end = start + CLEANING_DURATION;
+        let state = state.apply_event(Event::TimeoutReached(end), end);
+        assert_eq!(state.cleaning_until, None);
+        assert_eq!(state.get_outcome(end).cleaning, None);
+    }
+
+    #[test]
+    fn covered_sensor() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            visibility: visibility::Policy {
+                forced: visibility::State::ForcedVisible,
+                ..Default::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        let covered = state.apply_event(Event::Sensor(sensors::Event::Covered), start);
+        assert_eq!(covered.get_outcome(start).panel, animation::Outcome::Hidden);
+
+        let ignored = covered.clone().apply_event(
+            Event::SettingsChanged(Settings {
+                hide_when_covered: false,
+                ..Settings::default()
+            }),
+            start,
+        );
+        assert_matches!(
+            ignored.get_outcome(start).panel,
+            animation::Outcome::Visible{..}
+        );
+
+        let uncovered = covered.apply_event(Event::Sensor(sensors::Event::Uncovered), start);
+        assert_matches!(
+            uncovered.get_outcome(start).panel,
+            animation::Outcome::Visible{..}
+        );
+    }
+
+    #[test]
+    fn layout_override() {
+        let start = Instant::now();
+        let state = Application {
+            layout_override: Some("de".into()),
+            ..application_with_fake_output(start)
+        };
+        let state = state.apply_event(
+            Event::LayoutChoice(LayoutChoice {
+                name: "fr".into(),
+                source: LayoutSource::Xkb,
+            }),
+            start,
+        );
+        assert_eq!(state.get_layout_names(), ("de".into(), None));
+    }
+
+    #[test]
+    fn height_override() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            height_override: Some(300),
+            ..application_with_fake_output(start)
+        };
+        assert_matches!(
+            state.get_outcome(start).panel,
+            animation::Outcome::Visible {
+                height: PixelSize { pixels: 300, scale_factor: 1 },
+                ..
+            }
+        );
+    }
+
+    /// Views with fewer rows get a shorter panel.
+    #[test]
+    fn view_height() {
+        let start = Instant::now();
+        let id = fake_output_id(1);
+        let mut known = HashMap::new();
+        known.insert(id, output_l5());
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            outputs: output::Policy {
+                preferred: Some(id),
+                known,
+                ..Default::default()
+            },
+            get_shape: get_fake_shape,
+            ..Application::new(start)
+        };
+        let get_height = |state: &Application| match state.get_outcome(start).panel {
+            animation::Outcome::Visible { height, .. } => height.pixels,
+            animation::Outcome::Hidden => 0,
+        };
+        let numbers = state.clone()
+            .apply_event(Event::ViewChanged("numbers".into()), start);
+        assert!(get_height(&numbers) < get_height(&state));
+    }
+
+    /// Shells learn where the rows are on the output.
+    #[test]
+    fn geometry() {
+        let start = Instant::now();
+        let id = fake_output_id(1);
+        let mut known = HashMap::new();
+        known.insert(id, output_l5());
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            outputs: output::Policy {
+                preferred: Some(id),
+                known,
+                ..Default::default()
+            },
+            height_override: Some(200),
+            row_bounds: vec![Bounds { x: 10.0, y: 5.0, width: 340.0, height: 40.0 }],
+            get_shape: get_fake_shape,
+            ..Application::new(start)
+        };
+        let geometry = state.get_outcome(start).geometry.unwrap();
+        assert_eq!(
+            geometry.panel,
+            panel::Rect { x: 0, y: 520, width: 360, height: 200 },
+        );
+        assert_eq!(
+            geometry.rows,
+            vec![panel::Rect { x: 10, y: 525, width: 340, height: 40 }],
+        );
+    }
+
+    /// The pinned output takes over when it appears, and stays.
+    #[test]
+    fn pinned_output() {
+        let start = Instant::now();
+        let base = application_with_fake_output(start);
+        let state = Application {
+            outputs: output::Policy {
+                pinned: Some("HDMI-A-1".into()),
+                ..base.outputs
+            },
+            ..base
+        };
+        let output = |name: &str| outputs::ChangeType::Altered(OutputState {
+            current_mode: None,
+            geometry: None,
+            scale: 1,
+            name: Some(name.into()),
+            identity: None,
+        });
+        let pinned = fake_output_id(2);
+        let state = state
+            .apply_event(
+                Event::Output(outputs::Event { output: pinned, change: output("HDMI-A-1") }),
+                start,
+            )
+            .apply_event(
+                Event::Output(outputs::Event { output: fake_output_id(3), change: output("DSI-1") }),
+                start,
+            );
+        assert_eq!(state.outputs.preferred, Some(pinned));
+
+        let state = state.apply_event(
+            Event::Output(outputs::Event { output: pinned, change: outputs::ChangeType::Removed }),
+            start,
+        );
+        assert_ne!(state.outputs.preferred, Some(pinned));
+        assert_ne!(state.outputs.preferred, None);
+    }
+
+    #[test]
+    fn debug_overlay_toggle() {
+        let start = Instant::now();
+        let state = application_with_fake_output(start);
+        let enabled = state.clone()
+            .apply_event(Event::Debug(debug::Event::Enable), start);
+        let commands = state.get_outcome(start)
+            .get_commands_to_reach(&enabled.get_outcome(start));
+        assert_eq!(commands.debug_overlay, Some(true));
+
+        let commands = enabled.get_outcome(start)
+            .get_commands_to_reach(&enabled.get_outcome(start));
+        assert_eq!(commands.debug_overlay, None);
+    }
+
+    fn output_l5() -> OutputState {
+        use crate::outputs::{Mode, Geometry, c, Size};
+        OutputState {
+            current_mode: Some(Mode {
+                width: 720,
+                height: 1440,
+            }),
+            geometry: Some(Geometry{
+                transform: c::Transform::Normal,
+                phys_size: Size {
+                    width: Some(Millimeter(65)),
+                    height: Some(Millimeter(130)),
+                },
+            }),
+            scale: 2,
+            name: None,
+            identity: None,
+        }
+    }
+
+    #[test]
+    fn size_l5() {
+        assert_eq!(
+            Application::get_preferred_height_and_arrangement(
+                &output_l5(),
+                0,
+                |_| None,
+            ),
+            Some((
+                PixelSize {
+                    scale_factor: 2,
+                    pixels: 480,
+                },
+                ArrangementKind::Base,
+            )),
+        );
+    }
+
+    /// The L5 turned sideways leaves too little space with the wide arrangement.
+    #[test]
+    fn size_compact() {
+        use crate::outputs::{Mode, Geometry, c, Size};
+        let output = OutputState {
+            current_mode: Some(Mode {
+                width: 1440,
+                height: 720,
+            }),
+            geometry: Some(Geometry{
+                transform: c::Transform::Normal,
+                phys_size: Size {
+                    width: Some(Millimeter(130)),
+                    height: Some(Millimeter(65)),
+                },
+            }),
+            scale: 2,
+            name: None,
+            identity: None,
+        };
+        assert_eq!(
+            Application::get_preferred_height_and_arrangement(
+                &output,
+                0,
+                |kind| Some(match kind {
+                    ArrangementKind::Compact => loading::Shape { kind, rows: 3, aspect_ratio: 0.2, smallest_key: None, floating: false },
+                    _ => loading::Shape { kind, rows: 4, aspect_ratio: 0.3, smallest_key: None, floating: false },
+                }),
+            ),
+            Some((
+                PixelSize {
+                    scale_factor: 2,
+                    pixels: 271,
+                },
+                ArrangementKind::Compact,
+            )),
+        );
+    }
+
+    /// Buttons too small to hit make the panel taller,
+    /// as far as the layout can fill it.
+    #[test]
+    fn size_small_keys() {
+        use crate::outputs::{Mode, Geometry, c, Size};
+        let output = OutputState {
+            current_mode: Some(Mode {
+                width: 1440,
+                height: 720,
+            }),
+            geometry: Some(Geometry{
+                transform: c::Transform::Normal,
+                phys_size: Size {
+                    width: Some(Millimeter(130)),
+                    height: Some(Millimeter(65)),
+                },
+            }),
+            scale: 2,
+            name: None,
+            identity: None,
+        };
+        assert_eq!(
+            Application::get_preferred_height_and_arrangement(
+                &output,
+                0,
+                |kind| Some(match kind {
+                    ArrangementKind::Compact => loading::Shape {
+                        kind,
+                        rows: 3,
+                        aspect_ratio: 0.2,
+                        smallest_key: Some(0.125),
+                        floating: false,
+                    },
+                    _ => loading::Shape { kind, rows: 4, aspect_ratio: 0.3, smallest_key: None, floating: false },
+                }),
+            ),
+            Some((
+                PixelSize {
+                    scale_factor: 2,
+                    pixels: 288,
+                },
+                ArrangementKind::Compact,
+            )),
+        );
+    }
+
+    /// A number row makes the panel taller.
+    #[test]
+    fn size_five_rows() {
+        assert_eq!(
+            Application::get_preferred_height_and_arrangement(
+                &output_l5(),
+                0,
+                |kind| Some(loading::Shape { kind, rows: 5, aspect_ratio: 1.0, smallest_key: None, floating: false }),
+            ),
+            Some((
+                PixelSize {
+                    scale_factor: 2,
+                    pixels: 601,
+                },
+                ArrangementKind::Base,
+            )),
+        );
+    }
+
+    /// The extra keys count even when the layout is not known.
+    #[test]
+    fn size_extra_row() {
+        assert_eq!(
+            Application::get_preferred_height_and_arrangement(&output_l5(), 1, |_| None),
+            Some((
+                PixelSize {
+                    scale_factor: 2,
+                    pixels: 600,
+                },
+                ArrangementKind::Base,
+            )),
+        );
+    }
+
+    /// On a short screen, the extra keys share the space of the layout.
+    #[test]
+    fn size_compact_extra_row() {
+        use crate::outputs::{Mode, Geometry, c, Size};
+        let output = OutputState {
+            current_mode: Some(Mode {
+                width: 1440,
+                height: 720,
+            }),
+            geometry: Some(Geometry{
+                transform: c::Transform::Normal,
+                phys_size: Size {
+                    width: Some(Millimeter(130)),
+                    height: Some(Millimeter(65)),
+                },
+            }),
+            scale: 2,
+            name: None,
+            identity: None,
+        };
+        assert_eq!(
+            Application::get_preferred_height_and_arrangement(
+                &output,
+                1,
+                |kind| Some(match kind {
+                    ArrangementKind::Compact => loading::Shape { kind, rows: 3, aspect_ratio: 0.2, smallest_key: None, floating: false },
+                    _ => loading::Shape { kind, rows: 4, aspect_ratio: 0.3, smallest_key: None, floating: false },
+                }),
+            ),
+            Some((
+                PixelSize {
+                    scale_factor: 2,
+                    pixels: 271,
+                },
+                ArrangementKind::Compact,
+            )),
+        );
+    }
+
+    #[test]
+    fn edge_handle() {
+        let start = Instant::now();
+        let state = Application {
+            im: InputMethod::Active(imdetails_new()),
+            settings: Settings {
+                edge_handle: true,
+                ..Settings::default()
+            },
+            ..application_with_fake_output(start)
+        };
+        assert_eq!(state.get_outcome(start).edge_handle, None);
+
+        let hidden = state.clone()
+            .apply_event(Event::Visibility(visibility::Event::ForceHidden), start);
+        assert_eq!(hidden.get_outcome(start).edge_handle, Some(fake_output_id(1)));
+
+        let shown = hidden
+            .apply_event(Event::Visibility(visibility::Event::ForceVisible), start);
+        assert_matches!(shown.get_outcome(start).panel, animation::Outcome::Visible{..});
+        assert_eq!(shown.get_outcome(start).edge_handle, None);
+    }
+}
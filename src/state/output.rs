@@ -0,0 +1,104 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! The policy choosing the output for the panel, and its height there.
+ *
+ * Apart from pinning, there's no policy to choose one output over another,
+ * so the first one to appear wins, and stays until it's gone.
+ */
+
+use crate::outputs;
+use crate::outputs::{ OutputId, OutputState };
+use std::collections::HashMap;
+
+
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    /// The output on which the panel should appear.
+    /// This is stored as part of the state
+    /// because it's not clear how to derive the output from the rest of the state.
+    /// It should probably follow the focused input,
+    /// but not sure about being allowed on non-touch displays.
+    pub preferred: Option<OutputId>,
+    pub known: HashMap<OutputId, OutputState>,
+    /// Name of the output to prefer when present
+    pub pinned: Option<String>,
+    /// Height percentages chosen for outputs, by their identity.
+    /// They win over the setting when the panel is on that output.
+    pub heights: HashMap<String, u32>,
+}
+
+impl Policy {
+    pub fn apply(self, event: outputs::Event) -> Self {
+        let outputs::Event { output, change } = event;
+        let mut policy = self;
+        match change {
+            outputs::ChangeType::Altered(state) => {
+                let is_pinned = policy.pinned.is_some()
+                    && state.name == policy.pinned;
+                policy.known.insert(output, state);
+                policy.preferred = match is_pinned {
+                    true => Some(output),
+                    false => policy.preferred.or(Some(output)),
+                };
+            },
+            outputs::ChangeType::Removed => {
+                policy.known.remove(&output);
+                if policy.preferred == Some(output) {
+                    // Take whichever comes first.
+                    policy.preferred = policy.find_pinned()
+                        .or_else(|| policy.known.keys().next().map(|output| *output));
+                }
+            },
+        };
+        policy
+    }
+
+    /// A new height is for the output showing the panel.
+    /// Other outputs keep the height they had.
+    pub fn apply_height_percent(self, old: u32, new: u32) -> Self {
+        if new == old {
+            return self;
+        }
+        let current = self.get_identity();
+        let mut policy = self;
+        for state in policy.known.values() {
+            if let Some(identity) = &state.identity {
+                let height = if Some(identity) == current.as_ref() {
+                    new
+                } else {
+                    *policy.heights.get(identity).unwrap_or(&old)
+                };
+                policy.heights.insert(identity.clone(), height);
+            }
+        }
+        policy
+    }
+
+    pub fn get(&self, output: &OutputId) -> Option<&OutputState> {
+        self.known.get(output)
+    }
+
+    /// The identity of the output for the panel
+    fn get_identity(&self) -> Option<String> {
+        self.preferred
+            .and_then(|output| self.known.get(&output))
+            .and_then(|state| state.identity.clone())
+    }
+
+    /// The percentage remembered for the output, or the one in the settings
+    pub fn get_height_percent(&self, setting: u32) -> u32 {
+        self.get_identity()
+            .and_then(|identity| self.heights.get(&identity).cloned())
+            .unwrap_or(setting)
+    }
+
+    fn find_pinned(&self) -> Option<OutputId> {
+        self.known.iter()
+            .find(|(_, state)| {
+                self.pinned.is_some() && state.name == self.pinned
+            })
+            .map(|(output, _)| *output)
+    }
+}
@@ -0,0 +1,187 @@
+/* Copyright (C) 2021,2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! The policy deciding whether the panel should be shown.
+ *
+ * Every input which may want the panel up or down gives an opinion,
+ * or stays out of it.
+ * The opinions are ordered by priority, and the first one decides.
+ * A new input, like a locked session, is one more opinion in the list.
+ */
+
+use super::Presence;
+use crate::sensors;
+use crate::settings::Settings;
+
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// User requested the panel to show
+    ForceVisible,
+    /// The user requested the panel to go down
+    ForceHidden,
+}
+
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub enum State {
+    /// Last interaction was user forcing the panel to go visible
+    ForcedVisible,
+    /// Last interaction was user forcing the panel to hide
+    ForcedHidden,
+    /// Last interaction was the input method changing active state
+    NotForced,
+}
+
+/// Show or hide request coming from an application
+/// rather than from the user.
+/// Like text-input's `show_input_panel`.
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub enum AppRequest {
+    Show,
+    Hide,
+}
+
+/// Whether to show the panel, or None to leave it to the next opinion.
+pub type Opinion = Option<bool>;
+
+/// The first opinion decides. Nobody wanting the panel keeps it hidden.
+pub fn merge(opinions: &[Opinion]) -> bool {
+    opinions.iter()
+        .filter_map(|opinion| *opinion)
+        .next()
+        .unwrap_or(false)
+}
+
+/// Inputs which decide about visibility, apart from the text field.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    pub forced: State,
+    /// The last explicit request from the focused application.
+    /// Forgotten when focus changes.
+    pub app_request: Option<AppRequest>,
+    pub physical_keyboard: Presence,
+    /// The proximity sensor is covered, e.g. in a pocket
+    pub covered: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            forced: State::NotForced,
+            app_request: None,
+            physical_keyboard: Presence::Missing,
+            covered: false,
+        }
+    }
+}
+
+impl Policy {
+    pub fn apply_user(self, event: Event) -> Self {
+        Self {
+            forced: match event {
+                Event::ForceHidden => State::ForcedHidden,
+                Event::ForceVisible => State::ForcedVisible,
+            },
+            ..self
+        }
+    }
+
+    /// The newest request wins over the user showing the panel,
+    /// which can be done again afterwards.
+    /// The user hiding the panel stays in place,
+    /// so that apps asking for it again and again don't bring it back.
+    pub fn apply_app_request(self, request: AppRequest) -> Self {
+        Self {
+            app_request: Some(request),
+            forced: match self.forced {
+                State::ForcedHidden => State::ForcedHidden,
+                _ => State::NotForced,
+            },
+            ..self
+        }
+    }
+
+    pub fn apply_physical_keyboard(self, presence: Presence) -> Self {
+        Self {
+            physical_keyboard: presence,
+            ..self
+        }
+    }
+
+    pub fn apply_sensor(self, event: sensors::Event) -> Self {
+        Self {
+            covered: event == sensors::Event::Covered,
+            ..self
+        }
+    }
+
+    /// Overrides and requests are about the text field which had the focus.
+    pub fn apply_focus_change(self) -> Self {
+        Self {
+            forced: State::NotForced,
+            app_request: None,
+            ..self
+        }
+    }
+
+    /// `focused` tells whether a text field wants the panel,
+    /// including the time it stays up after the focus goes away.
+    pub fn is_visible(&self, settings: &Settings, focused: bool) -> bool {
+        merge(&[
+            // Nobody types on a keyboard in a pocket,
+            // even if they asked for it before putting it there.
+            match self.covered && settings.hide_when_covered {
+                true => Some(false),
+                false => None,
+            },
+            match self.forced {
+                State::ForcedHidden => Some(false),
+                State::ForcedVisible => Some(true),
+                State::NotForced => None,
+            },
+            match self.physical_keyboard {
+                Presence::Present if settings.hide_with_hardware_keyboard
+                    => Some(false),
+                _ => None,
+            },
+            // Apps don't know about hardware keyboards,
+            // so their requests don't override the setting.
+            self.app_request.map(|request| request == AppRequest::Show),
+            Some(settings.show_on_focus && focused),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_opinion_wins() {
+        assert_eq!(merge(&[None, Some(true), Some(false)]), true);
+        assert_eq!(merge(&[None, None]), false);
+    }
+
+    #[test]
+    fn hidden_stays_hidden() {
+        let settings = Settings::default();
+        let policy = Policy::default()
+            .apply_user(Event::ForceHidden)
+            .apply_app_request(AppRequest::Show);
+        assert_eq!(policy.forced, State::ForcedHidden);
+        assert_eq!(policy.is_visible(&settings, true), false);
+        // Until the focus moves on
+        let policy = policy.apply_focus_change().apply_app_request(AppRequest::Show);
+        assert_eq!(policy.is_visible(&settings, true), true);
+    }
+
+    #[test]
+    fn app_request_beats_forced_visible() {
+        let policy = Policy::default()
+            .apply_user(Event::ForceVisible)
+            .apply_app_request(AppRequest::Hide);
+        assert_eq!(policy.forced, State::NotForced);
+        assert_eq!(policy.is_visible(&Settings::default(), true), false);
+    }
+}
@@ -0,0 +1,204 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Random event sequences thrown at the state machine.
+ *
+ * The handwritten tests in `state` check the scenarios someone thought of.
+ * Here, sequences of events get generated from fixed seeds,
+ * and after every event, properties which must always hold get checked.
+ * A failure prints the seed and the events leading up to it,
+ * so it can be turned into a handwritten test.
+ *
+ * The generator is a small xorshift,
+ * to avoid depending on a property testing crate
+ * which distributions don't package.
+ */
+
+use crate::animation;
+use crate::imservice::{ ContentHint, ContentPurpose };
+use crate::outputs;
+use crate::outputs::{ OutputId, OutputState };
+use crate::sensors;
+use crate::settings::Settings;
+use crate::state::{ Application, Event, InputMethod, InputMethodDetails, Presence, visibility };
+use crate::state::test::application_with_fake_output;
+use std::cmp;
+use std::time::{ Duration, Instant };
+
+
+const SEQUENCES: u64 = 300;
+const EVENTS_PER_SEQUENCE: usize = 60;
+
+/// Reproducible from the seed
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        // Zero would stay zero forever
+        Random((seed + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+}
+
+fn make_event(random: &mut Random, now: Instant) -> Event {
+    match random.below(12) {
+        0 => {
+            let purposes = [
+                ContentPurpose::Normal,
+                ContentPurpose::Pin,
+                ContentPurpose::Password,
+                ContentPurpose::Terminal,
+            ];
+            Event::InputMethod(InputMethod::Active(InputMethodDetails {
+                purpose: purposes[random.below(purposes.len() as u64) as usize],
+                hint: ContentHint::NONE,
+            }))
+        },
+        1 => Event::InputMethod(InputMethod::InactiveSince(now)),
+        2 => Event::Visibility(visibility::Event::ForceVisible),
+        3 => Event::Visibility(visibility::Event::ForceHidden),
+        4 => Event::PhysicalKeyboard(
+            if random.chance() { Presence::Present } else { Presence::Missing }
+        ),
+        5 => Event::Sensor(
+            if random.chance() { sensors::Event::Covered }
+            else { sensors::Event::Uncovered }
+        ),
+        6 => Event::AppRequestedVisibility(
+            if random.chance() { visibility::AppRequest::Show }
+            else { visibility::AppRequest::Hide }
+        ),
+        7 => Event::KeyPressed,
+        8 => Event::StartCleaning,
+        9 => Event::TimeoutReached(now),
+        10 => Event::SettingsChanged(Settings {
+            hide_delay: Duration::from_millis(random.below(3000)),
+            show_on_focus: random.chance(),
+            hide_with_hardware_keyboard: random.chance(),
+            hide_when_covered: random.chance(),
+            ..Settings::default()
+        }),
+        _ => {
+            let output = OutputId(outputs::c::WlOutput::from_id(1 + random.below(3)));
+            Event::Output(outputs::Event {
+                output,
+                change: match random.chance() {
+                    true => outputs::ChangeType::Altered(OutputState {
+                        current_mode: Some(outputs::Mode { width: 720, height: 1440 }),
+                        geometry: None,
+                        scale: 2,
+                        name: None,
+                        identity: None,
+                    }),
+                    false => outputs::ChangeType::Removed,
+                },
+            })
+        },
+    }
+}
+
+fn is_hidden(state: &Application, now: Instant) -> bool {
+    state.get_outcome(now).panel == animation::Outcome::Hidden
+}
+
+/// The user's choice to hide wins over everything else.
+fn check_forced_hidden(state: &Application, now: Instant) -> Result<(), String> {
+    match state.visibility.forced {
+        visibility::State::ForcedHidden if !is_hidden(state, now)
+            => Err("Visible despite being forced hidden".into()),
+        _ => Ok(()),
+    }
+}
+
+/// Wakes never go back in time,
+/// otherwise the loop would spin or miss updates.
+fn check_wakes(state: &Application, now: Instant) -> Result<(), String> {
+    let mut time = now;
+    for _ in 0..5 {
+        match state.get_next_wake(time) {
+            Some(wake) if wake <= time => return Err(format!(
+                "Wake at {:?} is not after {:?}", wake - now, time - now,
+            )),
+            Some(wake) => time = wake,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Without anyone asking for the panel,
+/// it goes away some time after the text field.
+/// The loop must wake up for that in time.
+fn check_eventually_hidden(state: &Application, now: Instant) -> Result<(), String> {
+    match (&state.im, state.visibility.forced, state.visibility.app_request) {
+        (
+            InputMethod::InactiveSince(since),
+            visibility::State::NotForced,
+            None,
+        ) | (
+            InputMethod::InactiveSince(since),
+            visibility::State::NotForced,
+            Some(visibility::AppRequest::Hide),
+        ) => {
+            let deadline = *since + cmp::max(
+                state.settings.hide_delay,
+                animation::TYPING_HIDING_TIMEOUT,
+            );
+            if !is_hidden(state, cmp::max(deadline, now)) {
+                return Err("Still visible after the hiding delay".into());
+            }
+            if !is_hidden(state, now) {
+                match state.get_next_wake(now) {
+                    Some(wake) if wake <= deadline => {},
+                    other => return Err(format!(
+                        "Visible, but the next wake is {:?}",
+                        other.map(|wake| wake - now),
+                    )),
+                }
+            }
+            Ok(())
+        },
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn random_sequences() {
+    for seed in 0..SEQUENCES {
+        let mut random = Random::new(seed);
+        let start = Instant::now();
+        let mut now = start;
+        let mut state = application_with_fake_output(start);
+        let mut history = Vec::new();
+        for _ in 0..EVENTS_PER_SEQUENCE {
+            now += Duration::from_millis(random.below(700));
+            let event = make_event(&mut random, now);
+            history.push((now - start, event.clone()));
+            state = state.apply_event(event, now);
+
+            let result = check_forced_hidden(&state, now)
+                .and_then(|()| check_wakes(&state, now))
+                .and_then(|()| check_eventually_hidden(&state, now));
+            if let Err(e) = result {
+                panic!("Seed {}: {}\nAfter events:\n{:#?}", seed, e, history);
+            }
+        }
+    }
+}
@@ -0,0 +1,474 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Counting how the keyboard gets used, for the user's own eyes.
+ *
+ * Only while the "statistics" setting is on,
+ * presses of typing keys get counted by what they type,
+ * and by the layout they're on.
+ * The characters typed, and the time spent typing,
+ * are summed up by day, to show the typing speed over time.
+ * Nothing gets counted in password and PIN fields.
+ *
+ * The counts stay in the state dir, and never leave the device.
+ * They are saved at most once a minute, so the last minute may get lost.
+ * The `statistics` overlay draws them as bar charts,
+ * and `ClearStatistics` on the debug D-Bus interface forgets them.
+ */
+
+use std::cell::{ Cell, RefCell };
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use cairo;
+use glib::{ Continue, MainContext, PRIORITY_DEFAULT };
+use serde::{ Deserialize, Serialize };
+
+use crate::action::{ Action, KeySym };
+use crate::imservice::ContentPurpose;
+use crate::layout::c::Bounds;
+use crate::logging;
+use crate::persist::Error;
+use crate::xdg;
+
+// Traits
+use crate::logging::Warn;
+
+
+/// Bump on every incompatible change to `Counts`.
+const VERSION: u32 = 1;
+
+/// Longer pauses between presses are not typing
+const TYPING_GAP: Duration = Duration::from_secs(5);
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Days of typing speed kept
+const KEPT_DAYS: u64 = 30;
+/// Bars in a chart
+const MAX_BARS: usize = 10;
+const SPEED_DAYS: u64 = 7;
+
+/// Read before anything else, to reject unknown formats early.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+struct Day {
+    characters: u64,
+    /// Summed up from presses close enough to each other
+    typing_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Counts {
+    version: u32,
+    /// Presses by what the key types
+    keys: BTreeMap<String, u64>,
+    /// Presses by layout name
+    layouts: BTreeMap<String, u64>,
+    /// By days since 1970, in UTC
+    days: BTreeMap<u64, Day>,
+}
+
+impl Counts {
+    fn new() -> Counts {
+        Counts {
+            version: VERSION,
+            keys: BTreeMap::new(),
+            layouts: BTreeMap::new(),
+            days: BTreeMap::new(),
+        }
+    }
+
+    fn parse(data: &str) -> Result<Counts, Error> {
+        let header: Header = serde_yaml::from_str(data)?;
+        if header.version != VERSION {
+            return Err(Error::Version(header.version));
+        }
+        Ok(serde_yaml::from_str(data)?)
+    }
+
+    /// `gap` is the time since the previous press.
+    fn record(
+        &mut self,
+        key: &str,
+        characters: usize,
+        layout: &str,
+        day: u64,
+        gap: Option<Duration>,
+    ) {
+        *self.keys.entry(key.into()).or_insert(0) += 1;
+        *self.layouts.entry(layout.into()).or_insert(0) += 1;
+        let entry = self.days.entry(day).or_insert_with(Day::default);
+        entry.characters += characters as u64;
+        if let Some(gap) = gap.filter(|gap| *gap < TYPING_GAP) {
+            entry.typing_ms += gap.as_millis() as u64;
+        }
+        let oldest = day.saturating_sub(KEPT_DAYS - 1);
+        self.days = self.days.split_off(&oldest);
+    }
+}
+
+/// What the key types, and how many characters that is
+fn describe_key(action: &Action) -> Option<(String, usize)> {
+    match action {
+        Action::Submit { text: Some(text), keys: _ } => {
+            let text = text.to_string_lossy();
+            Some((text.to_string(), text.chars().count()))
+        },
+        Action::Submit { text: None, keys } => match keys.as_slice() {
+            [KeySym(name)] => Some((name.clone(), 1)),
+            _ => None,
+        },
+        Action::Erase => Some(("BackSpace".into(), 0)),
+        Action::SendOrNewline => Some(("Return".into(), 1)),
+        _ => None,
+    }
+}
+
+fn get_day(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() / (24 * 60 * 60))
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Chart {
+    Keys,
+    Layouts,
+    Speed,
+}
+
+impl Chart {
+    fn next(self) -> Chart {
+        match self {
+            Chart::Keys => Chart::Layouts,
+            Chart::Layouts => Chart::Speed,
+            Chart::Speed => Chart::Keys,
+        }
+    }
+}
+
+fn get_most_used(counts: &BTreeMap<String, u64>) -> Vec<(String, f64)> {
+    let mut bars: Vec<(&String, &u64)> = counts.iter().collect();
+    // Ties stay in the order of names
+    bars.sort_by(|(_, a), (_, b)| b.cmp(a));
+    bars.into_iter()
+        .take(MAX_BARS)
+        .map(|(name, count)| {
+            let name = match name.trim().is_empty() {
+                true => "␣".into(),
+                false => name.clone(),
+            };
+            (name, *count as f64)
+        })
+        .collect()
+}
+
+/// The title, and the bars with their labels
+fn get_bars(counts: &Counts, chart: Chart, today: u64) -> (&'static str, Vec<(String, f64)>) {
+    match chart {
+        Chart::Keys => ("Most used keys", get_most_used(&counts.keys)),
+        Chart::Layouts => ("Most used layouts", get_most_used(&counts.layouts)),
+        Chart::Speed => (
+            "Characters per minute",
+            (0..SPEED_DAYS).rev()
+                .map(|ago| {
+                    let label = match ago {
+                        0 => "today".into(),
+                        ago => format!("-{}d", ago),
+                    };
+                    let speed = today.checked_sub(ago)
+                        .and_then(|day| counts.days.get(&day))
+                        .filter(|day| day.typing_ms > 0)
+                        .map(|day| day.characters as f64 * 60_000.0 / day.typing_ms as f64)
+                        .unwrap_or(0.0);
+                    (label, speed)
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn get_path() -> Option<PathBuf> {
+    xdg::state_path("squeekboard/statistics.yaml")
+}
+
+fn load(path: &Path) -> Result<Counts, Error> {
+    match fs::read_to_string(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Counts::new()),
+        Err(e) => Err(e.into()),
+        Ok(data) => Counts::parse(&data),
+    }
+}
+
+/// Writes to a temporary file first,
+/// so that crashing in the middle doesn't leave a truncated file behind.
+fn write(path: &Path, counts: &Counts) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let data = serde_yaml::to_string(counts)?;
+    let temp_path = path.with_extension("yaml.tmp");
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+struct Recorder {
+    counts: Counts,
+    last_press: Option<Instant>,
+    last_save: Instant,
+    unsaved: bool,
+}
+
+impl Recorder {
+    fn save(&mut self) {
+        if let Some(path) = get_path() {
+            write(&path, &self.counts)
+                .or_print(logging::Problem::Warning, "Can't save statistics");
+        }
+        self.last_save = Instant::now();
+        self.unsaved = false;
+    }
+}
+
+thread_local! {
+    /// Only while enabled
+    static RECORDER: RefCell<Option<Recorder>> = RefCell::new(None);
+    /// The layout shown, without the `+` mangling for CSS
+    static LAYOUT: RefCell<String> = RefCell::new(String::new());
+    static CHART: Cell<Chart> = Cell::new(Chart::Keys);
+}
+
+/// Follows the user's settings. Loads the counts so far when turned on.
+pub fn set_enabled(enabled: bool) {
+    RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        match (enabled, recorder.is_some()) {
+            (true, false) => {
+                let counts = get_path()
+                    .and_then(|path| load(&path).or_print(
+                        logging::Problem::Warning,
+                        &format!("Ignoring saved statistics in {:?}", path),
+                    ))
+                    .unwrap_or_else(Counts::new);
+                *recorder = Some(Recorder {
+                    counts,
+                    last_press: None,
+                    last_save: Instant::now(),
+                    unsaved: false,
+                });
+            },
+            (false, true) => {
+                if let Some(mut old) = recorder.take() {
+                    if old.unsaved {
+                        old.save();
+                    }
+                }
+            },
+            _ => {},
+        }
+    });
+}
+
+pub fn set_layout(name: &str) {
+    LAYOUT.with(|layout| *layout.borrow_mut() = name.into());
+}
+
+/// Presses in sensitive text fields, like passwords, are not counted.
+pub fn record_press(action: &Action, purpose: ContentPurpose, sensitive: bool) {
+    match purpose {
+        ContentPurpose::Password | ContentPurpose::Pin => return,
+        _ if sensitive => return,
+        _ => {},
+    }
+    let (key, characters) = match describe_key(action) {
+        Some(key) => key,
+        None => return,
+    };
+    RECORDER.with(|recorder| {
+        if let Some(recorder) = recorder.borrow_mut().as_mut() {
+            let now = Instant::now();
+            let gap = recorder.last_press.map(|last| now - last);
+            LAYOUT.with(|layout| recorder.counts.record(
+                &key,
+                characters,
+                &layout.borrow(),
+                get_day(SystemTime::now()),
+                gap,
+            ));
+            recorder.last_press = Some(now);
+            recorder.unsaved = true;
+            if now - recorder.last_save >= SAVE_INTERVAL {
+                recorder.save();
+            }
+        }
+    });
+}
+
+/// Forgets everything counted so far, also on the disk.
+fn clear() {
+    RECORDER.with(|recorder| {
+        if let Some(recorder) = recorder.borrow_mut().as_mut() {
+            recorder.counts = Counts::new();
+            recorder.last_press = None;
+            recorder.unsaved = false;
+        }
+    });
+    if let Some(path) = get_path() {
+        match fs::remove_file(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            other => {
+                other.or_print(logging::Problem::Warning, "Can't remove statistics");
+            },
+        }
+    }
+}
+
+/// Asks the main loop to clear the statistics, from any thread.
+#[derive(Clone)]
+pub struct Clearing(glib::Sender<()>);
+
+impl Clearing {
+    pub fn request(&self) -> Result<(), String> {
+        self.0.send(()).map_err(|e| e.to_string())
+    }
+}
+
+/// Call on the main loop.
+pub fn init() -> Clearing {
+    let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    receiver.attach(
+        Some(&MainContext::default()),
+        |()| {
+            clear();
+            Continue(true)
+        },
+    );
+    Clearing(sender)
+}
+
+/// Shows the next chart on the following redraw.
+pub fn next_chart() {
+    CHART.with(|chart| chart.set(chart.get().next()));
+}
+
+fn render_message(cr: &cairo::Context, bounds: &Bounds, text: &str) {
+    cr.set_font_size(bounds.height / 8.0);
+    cr.move_to(bounds.x + bounds.width * 0.05, bounds.y + bounds.height / 2.0);
+    cr.show_text(text);
+}
+
+/// Draws the chart over the button.
+pub fn render(cr: &cairo::Context, bounds: &Bounds) {
+    let bars = RECORDER.with(|recorder| {
+        recorder.borrow().as_ref().map(|recorder| get_bars(
+            &recorder.counts,
+            CHART.with(|chart| chart.get()),
+            get_day(SystemTime::now()),
+        ))
+    });
+    cr.save();
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.9);
+    match bars {
+        None => render_message(cr, bounds, "Statistics are off"),
+        Some((_, bars)) if bars.iter().all(|(_, value)| *value == 0.0) => {
+            render_message(cr, bounds, "Nothing counted yet")
+        },
+        Some((title, bars)) => {
+            // The title takes one line, like a bar
+            let line = bounds.height / (MAX_BARS + 1) as f64;
+            let font_size = line * 0.8;
+            let label_width = bounds.width * 0.25;
+            let value_width = bounds.width * 0.15;
+            let bar_width = bounds.width - label_width - value_width;
+            let max = bars.iter().map(|(_, value)| *value).fold(0.0, f64::max);
+            cr.set_font_size(font_size);
+            cr.move_to(bounds.x + label_width, bounds.y + font_size);
+            cr.show_text(title);
+            for (i, (label, value)) in bars.iter().enumerate() {
+                let top = bounds.y + line * (i + 1) as f64;
+                cr.move_to(bounds.x + line * 0.5, top + font_size);
+                cr.show_text(label);
+                cr.rectangle(
+                    bounds.x + label_width,
+                    top + line * 0.1,
+                    bar_width * value / max,
+                    line * 0.8,
+                );
+                cr.fill();
+                cr.move_to(
+                    bounds.x + label_width + bar_width * value / max + line * 0.3,
+                    top + font_size,
+                );
+                cr.show_text(&format!("{:.0}", value));
+            }
+        },
+    }
+    cr.restore();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn counting() {
+        let mut counts = Counts::new();
+        counts.record("a", 1, "us", 100, None);
+        counts.record("a", 1, "us", 100, Some(Duration::from_millis(300)));
+        counts.record("BackSpace", 0, "de", 100, Some(Duration::from_secs(60)));
+        assert_eq!(counts.keys.get("a"), Some(&2));
+        assert_eq!(counts.layouts.get("de"), Some(&1));
+        assert_eq!(
+            counts.days.get(&100),
+            Some(&Day { characters: 2, typing_ms: 300 }),
+        );
+        counts.record("a", 1, "us", 100 + KEPT_DAYS, None);
+        assert_eq!(counts.days.get(&100), None);
+    }
+
+    #[test]
+    fn keys() {
+        let text = Action::Submit {
+            text: Some(CString::new("ab").unwrap()),
+            keys: vec![],
+        };
+        assert_eq!(describe_key(&text), Some(("ab".into(), 2)));
+        assert_eq!(describe_key(&Action::Erase), Some(("BackSpace".into(), 0)));
+        assert_eq!(describe_key(&Action::ShowPreferences), None);
+    }
+
+    #[test]
+    fn bars() {
+        let mut counts = Counts::new();
+        counts.record(" ", 1, "us", 10, None);
+        counts.record("b", 1, "us", 10, Some(Duration::from_secs(1)));
+        counts.record("b", 1, "us", 10, Some(Duration::from_secs(1)));
+        let (_, keys) = get_bars(&counts, Chart::Keys, 10);
+        assert_eq!(keys, vec![("b".into(), 2.0), ("␣".into(), 1.0)]);
+        let (_, speed) = get_bars(&counts, Chart::Speed, 10);
+        assert_eq!(speed.len(), SPEED_DAYS as usize);
+        assert_eq!(speed.last(), Some(&("today".into(), 90.0)));
+        assert_eq!(speed[0].1, 0.0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut counts = Counts::new();
+        counts.record("a", 1, "us", 10, None);
+        let data = serde_yaml::to_string(&counts).unwrap();
+        assert_eq!(Counts::parse(&data).unwrap(), counts);
+        assert_matches!(
+            Counts::parse("version: 9999\n"),
+            Err(Error::Version(9999))
+        );
+    }
+}
@@ -0,0 +1,218 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Stenography, where chords of keys type whole words.
+ *
+ * Keys with the `steno` action don't type when pressed.
+ * All keys pressed until the last one goes up make a stroke,
+ * which gets looked up in the dictionary, and the translation committed.
+ * Strokes missing from the dictionary are committed as they are written.
+ * A stroke of the asterisk alone takes back the previous translation.
+ *
+ * The dictionary is a Plover dictionary in `squeekboard/steno.json`
+ * in the XDG data directory. Only entries of a single stroke are used.
+ */
+
+use std::cell::RefCell;
+use std::collections::{ BTreeSet, HashMap };
+use std::ffi::CString;
+use std::fs::File;
+use std::io::BufReader;
+use std::rc::Rc;
+
+use crate::logging;
+use crate::memory;
+use crate::submission::Submission;
+use crate::xdg;
+
+
+/// Keys in the order they are written in.
+/// Left hand keys end with a hyphen, right hand keys start with one.
+pub const ORDER: [&str; 23] = [
+    "#", "S-", "T-", "K-", "P-", "W-", "H-", "R-", "A-", "O-",
+    "*", "-E", "-U",
+    "-F", "-R", "-P", "-B", "-L", "-G", "-T", "-S", "-D", "-Z",
+];
+
+/// Index of the first key which isn't on the left or in the middle
+const RIGHT_START: usize = 13;
+const MIDDLE: [&str; 5] = ["A-", "O-", "*", "-E", "-U"];
+
+/// Translations which can still be taken back
+const HISTORY_SIZE: usize = 32;
+
+pub fn is_key(name: &str) -> bool {
+    ORDER.contains(&name)
+}
+
+/// The stroke the way dictionaries spell it, e.g. "KAT" or "S-S".
+fn write(keys: &BTreeSet<usize>) -> String {
+    let has_middle = keys.iter().any(|idx| MIDDLE.contains(&ORDER[*idx]));
+    let mut written = String::new();
+    for idx in keys {
+        if *idx >= RIGHT_START && !has_middle && !written.contains('-') {
+            written.push('-');
+        }
+        written.push_str(ORDER[*idx].trim_matches('-'));
+    }
+    written
+}
+
+#[derive(Debug, PartialEq)]
+enum Output {
+    Commit(String),
+    /// Remove the text committed before
+    Undo(String),
+    Nothing,
+}
+
+/// Collects strokes, and remembers what they typed.
+#[derive(Default)]
+pub struct Machine {
+    /// Indices into ORDER
+    stroke: BTreeSet<usize>,
+    history: Vec<String>,
+}
+
+impl Machine {
+    pub fn press(&mut self, key: &str) {
+        if let Some(idx) = ORDER.iter().position(|k| *k == key) {
+            self.stroke.insert(idx);
+        }
+    }
+
+    fn resolve(&mut self, dictionary: &HashMap<String, String>) -> Output {
+        let written = write(&self.stroke);
+        self.stroke.clear();
+        match written.as_str() {
+            "" => Output::Nothing,
+            "*" => match self.history.pop() {
+                Some(text) => Output::Undo(text),
+                None => Output::Nothing,
+            },
+            written => {
+                let text = format!(
+                    "{} ",
+                    dictionary.get(written).map(String::as_str).unwrap_or(written),
+                );
+                if self.history.len() == HISTORY_SIZE {
+                    self.history.remove(0);
+                }
+                self.history.push(text.clone());
+                Output::Commit(text)
+            },
+        }
+    }
+
+    /// Call when the last steno key goes up.
+    pub fn finish(&mut self, submission: &mut Submission) {
+        let result = match self.resolve(&get_dictionary()) {
+            Output::Commit(text) => {
+                let text = CString::new(text).expect("Translation contains a null byte");
+                submission.commit_text(&text)
+            },
+            Output::Undo(text) => submission.replace_text(&text, &CString::default()),
+            Output::Nothing => Ok(()),
+        };
+        if result.is_err() {
+            log_print!(
+                logging::Level::Warning,
+                "No text field to receive the stroke",
+            );
+        }
+    }
+}
+
+fn load_dictionary() -> HashMap<String, String> {
+    let path = match xdg::data_path("squeekboard/steno.json") {
+        Some(path) => path,
+        None => return HashMap::new(),
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(_) => {
+            log_print!(
+                logging::Level::Info,
+                "No steno dictionary in {}, strokes get typed as written",
+                path.display(),
+            );
+            return HashMap::new();
+        },
+    };
+    // JSON is close enough to YAML.
+    let dictionary: Result<HashMap<String, String>, _>
+        = serde_yaml::from_reader(BufReader::new(file));
+    match dictionary {
+        Ok(dictionary) => dictionary.into_iter()
+            .filter(|(stroke, _)| !stroke.contains('/'))
+            .collect(),
+        Err(e) => {
+            log_print!(
+                logging::Level::Warning,
+                "Steno dictionary {} is broken: {}", path.display(), e,
+            );
+            HashMap::new()
+        },
+    }
+}
+
+thread_local! {
+    static DICTIONARY: RefCell<Option<Rc<HashMap<String, String>>>> = RefCell::new(None);
+}
+
+fn get_dictionary() -> Rc<HashMap<String, String>> {
+    DICTIONARY.with(|dictionary| {
+        dictionary.borrow_mut()
+            .get_or_insert_with(|| {
+                let loaded = load_dictionary();
+                memory::set_size(memory::Cache::Steno, loaded.len());
+                Rc::new(loaded)
+            })
+            .clone()
+    })
+}
+
+/// Frees the dictionary until next needed.
+pub fn drop_cache() {
+    DICTIONARY.with(|dictionary| dictionary.replace(None));
+    memory::set_size(memory::Cache::Steno, 0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stroke(keys: &[&str]) -> Machine {
+        let mut machine = Machine::default();
+        for key in keys {
+            machine.press(key);
+        }
+        machine
+    }
+
+    #[test]
+    fn writing() {
+        assert_eq!(write(&stroke(&["-T", "A-", "K-"]).stroke), "KAT");
+        assert_eq!(write(&stroke(&["S-", "-S"]).stroke), "S-S");
+        assert_eq!(write(&stroke(&["-F", "-Z"]).stroke), "-FZ");
+        assert_eq!(write(&stroke(&["-E", "-U", "-F"]).stroke), "EUF");
+    }
+
+    #[test]
+    fn undo() {
+        let dictionary: HashMap<String, String>
+            = vec![("KAT".into(), "cat".into())].into_iter().collect();
+        let mut machine = stroke(&["K-", "A-", "-T"]);
+        assert_eq!(machine.resolve(&dictionary), Output::Commit("cat ".into()));
+        machine.press("S-");
+        machine.press("-S");
+        assert_eq!(machine.resolve(&dictionary), Output::Commit("S-S ".into()));
+        machine.press("*");
+        assert_eq!(machine.resolve(&dictionary), Output::Undo("S-S ".into()));
+        machine.press("*");
+        assert_eq!(machine.resolve(&dictionary), Output::Undo("cat ".into()));
+        machine.press("*");
+        assert_eq!(machine.resolve(&dictionary), Output::Nothing);
+    }
+}
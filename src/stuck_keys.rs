@@ -0,0 +1,65 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Letting go of keys which stayed pressed on the virtual keyboard.
+ *
+ * A release can get lost, e.g. when the layout changes under the finger.
+ * The application then keeps repeating the key until something releases it.
+ * Every press gets checked after a while,
+ * and a key still held down by then gets released.
+ *
+ * The number of keys released this way can be read
+ * over the debug D-Bus interface.
+ */
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+
+use crate::keyboard::KeyStateId;
+use crate::logging;
+use crate::submission::{ Submission, Timestamp };
+
+
+/// Nobody holds a key this long on purpose
+const STUCK_AFTER: Duration = Duration::from_secs(20);
+
+/// Written by the UI thread, and read by the debug interface thread.
+static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+/// Keys released because they were stuck, since start
+pub fn get_released_count() -> u64 {
+    RELEASED.load(Ordering::Relaxed) as u64
+}
+
+/// Releases the key if it's still pressed from the same press
+/// when the time is up.
+/// The time is the timestamp of the press.
+pub fn watch(
+    submission: Rc<RefCell<Submission>>,
+    key_id: KeyStateId,
+    time: Timestamp,
+) {
+    let check = move || {
+        let mut submission = submission.borrow_mut();
+        let held = submission.get_held_duration(&key_id, Instant::now());
+        // A newer press gets its own check.
+        if let Some(held) = held.filter(|held| *held >= STUCK_AFTER) {
+            let elapsed_ms = held.as_millis() as u32;
+            submission.handle_release(key_id, Timestamp(time.0.wrapping_add(elapsed_ms)));
+            RELEASED.fetch_add(1, Ordering::Relaxed);
+            log_print!(
+                logging::Level::Warning,
+                "Released key {:?}, stuck for {:?}", key_id, held,
+            );
+        }
+        glib::Continue(false)
+    };
+
+    #[cfg(feature = "glib_v0_14")]
+    glib::timeout_add_local(STUCK_AFTER, check);
+    #[cfg(not(feature = "glib_v0_14"))]
+    glib::timeout_add_local(STUCK_AFTER.as_millis() as u32, check);
+}
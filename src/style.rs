@@ -18,6 +18,7 @@
 
 /*! CSS data loading. */
 
+use std::cell::RefCell;
 use std::env;
 use ::logging;
 
@@ -33,6 +34,12 @@ pub mod c {
     
     use gtk::prelude::CssProviderExt;
     use glib::translate::ToGlibPtr;
+    use std::os::raw::{ c_char, c_void };
+
+    extern "C" {
+        // From GObject
+        pub fn g_object_notify(object: *mut c_void, property_name: *const c_char);
+    }
 
     /// Loads the layout style based on current theme
     /// without having to worry about string allocation
@@ -46,7 +53,10 @@ pub mod c {
         #[cfg(not(feature = "glib_v0_14"))]
         let theme = gtk::Settings::get_default();
         
-        let theme = theme.map(|settings| get_theme_name(&settings));
+        let theme = match get_theme_override() {
+            Some(name) => Some(parse_theme(&name)),
+            None => theme.map(|settings| get_theme_name(&settings)),
+        };
         
         let css_name = path_from_theme(theme);
 
@@ -68,6 +78,36 @@ pub mod c {
 // not Adwaita, but rather fall back to default
 const DEFAULT_THEME_NAME: &str = "";
 
+thread_local! {
+    /// Chosen in squeekboard's settings. Only used on the UI thread.
+    static THEME_OVERRIDE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+fn get_theme_override() -> Option<String> {
+    THEME_OVERRIDE.with(|theme| theme.borrow().clone())
+}
+
+/// Uses the named theme instead of the GTK one,
+/// and reloads the style right away.
+pub fn set_theme_override(name: Option<String>) {
+    THEME_OVERRIDE.with(|theme| *theme.borrow_mut() = name);
+
+    #[cfg(feature = "glib_v0_14")]
+    let settings = gtk::Settings::default();
+    #[cfg(not(feature = "glib_v0_14"))]
+    let settings = gtk::Settings::get_default();
+
+    // The renderer reloads the style whenever the GTK theme changes.
+    if let Some(settings) = settings {
+        use glib::translate::ToGlibPtr;
+        let settings: *mut gtk_sys::GtkSettings = settings.to_glib_none().0;
+        let property = b"gtk-theme-name\0";
+        unsafe {
+            c::g_object_notify(settings as *mut _, property.as_ptr() as *const _);
+        }
+    }
+}
+
 struct GtkTheme {
     name: String,
     variant: Option<String>,
@@ -77,15 +117,7 @@ struct GtkTheme {
 /// Ported from GTK's gtksettings.c
 fn get_theme_name(settings: &gtk::Settings) -> GtkTheme {
     let env_theme = env::var("GTK_THEME")
-        .map(|theme| {
-            let mut parts = theme.splitn(2, ":");
-            GtkTheme {
-                // guaranteed at least empty string
-                // as the first result from splitting a string
-                name: parts.next().unwrap().into(),
-                variant: parts.next().map(String::from)
-            }
-        })
+        .map(|theme| parse_theme(&theme))
         .map_err(|e| {
             match &e {
                 env::VarError::NotPresent => {},
@@ -132,6 +164,17 @@ fn get_theme_name(settings: &gtk::Settings) -> GtkTheme {
     }
 }
 
+/// Parses the "name:variant" format of GTK_THEME
+fn parse_theme(theme: &str) -> GtkTheme {
+    let mut parts = theme.splitn(2, ":");
+    GtkTheme {
+        // guaranteed at least empty string
+        // as the first result from splitting a string
+        name: parts.next().unwrap().into(),
+        variant: parts.next().map(String::from)
+    }
+}
+
 fn path_from_theme(theme: Option<GtkTheme>) -> String {
     format!(
         "/sm/puri/squeekboard/style{}.css",
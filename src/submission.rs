@@ -8,27 +8,41 @@
  * 
  * It must also not get tripped up by sudden disappearances of interfaces.
  * 
- * The virtual-keyboard interface is always present.
+ * When typing gets lost, the journal can show the last operations,
+ * and whether they went through. It's off unless turned on
+ * over the debug D-Bus interface. It holds text lengths, not text,
+ * but the keycodes still tell which keys got pressed.
+ * 
+ * The virtual-keyboard interface is always present,
+ * but it hides behind the `KeyboardBackend` trait,
+ * so that other ways of delivering key events can take its place.
  * 
  * The text-input interface may not be presented,
  * and, for simplicity, no further attempt to claim it is made.
+ * Then, everything gets submitted through the keyboard backend.
  * 
  * The text-input interface may be enabled and disabled at arbitrary times,
  * and those events SHOULD NOT cause any lost events.
+ * 
+ * Typing can also go into a `TextSink` instead of the application,
+ * when the layout asks for the keyboard's own text field.
+ * Nothing reaches the application then, not even keycodes.
  * */
 
-use std::collections::HashSet;
+use std::collections::{ HashSet, VecDeque };
 use std::ffi::CString;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
 
-use crate::vkeyboard::c::ZwpVirtualKeyboardV1;
 use ::action::Modifier;
+use ::erase;
 use ::imservice;
 use ::imservice::IMService;
+use ::inspect;
 use ::keyboard::{ KeyCode, KeyStateId, Modifiers, PressType };
 use ::layout;
+use ::text_field;
 use ::util::vec_remove;
-use ::vkeyboard;
-use ::vkeyboard::VirtualKeyboard;
 
 // traits
 use std::iter::FromIterator;
@@ -71,18 +85,149 @@ pub struct Timestamp(pub u32);
 
 #[derive(Clone)]
 enum SubmittedAction {
-    /// A collection of keycodes that were pressed
-    VirtualKeyboard(Vec<KeyCode>),
+    /// A collection of keycodes that were pressed, and when
+    VirtualKeyboard(Vec<KeyCode>, Instant),
+    /// Keycodes released before the button,
+    /// when something else took over submitting
+    Released(Vec<KeyCode>),
     IMService,
+    /// Went into the text sink
+    Sink,
+}
+
+fn get_raw_modifier(modifier: &Modifier) -> Modifiers {
+    match modifier {
+        Modifier::Control => Modifiers::CONTROL,
+        Modifier::Alt => Modifiers::MOD1,
+        Modifier::Mod4 => Modifiers::MOD4,
+        Modifier::Shift => Modifiers::SHIFT,
+    }
+}
+
+/// Delivers key events to applications.
+/// Used whenever text can't be committed through the input method.
+pub trait KeyboardBackend {
+    /// Replaces the keymaps. Later calls refer to them by index.
+    fn set_keymaps(&mut self, keymaps: &[CString]);
+    fn select_keymap(&mut self, idx: usize);
+    /// Keycodes are xkb keycodes.
+    fn switch(&mut self, keycode: u32, action: PressType, time: Timestamp);
+    fn set_modifiers_state(&mut self, modifiers: Modifiers);
+}
+
+/// Receives typing meant for a text field inside the keyboard
+pub trait TextSink {
+    fn commit(&mut self, text: &str);
+    /// Removes the last character
+    fn erase(&mut self);
+    fn get_text(&self) -> &str;
+    /// What to show to the user
+    fn describe(&self) -> String;
+}
+
+/// Operations kept in the journal
+const JOURNAL_ENTRIES: usize = 200;
+
+#[derive(Clone, Debug)]
+pub enum Operation {
+    /// Through the input method
+    Commit { bytes: usize, ok: bool },
+    /// Through the input method
+    Delete { bytes: usize, ok: bool },
+    /// Time as sent to the compositor
+    Keycode { code: u32, press: PressType, time: u32 },
+    Modifiers(Modifiers),
+    SelectKeymap(usize),
+    /// How many keymaps there are now
+    SetKeymaps(usize),
+}
+
+/// Recent operations of the submission, when enabled.
+/// Shared with the debug interface.
+#[derive(Clone)]
+pub struct Journal(Arc<Mutex<Option<VecDeque<(Instant, Operation)>>>>);
+
+impl Journal {
+    /// Starts disabled.
+    pub fn new() -> Journal {
+        Journal(Arc::new(Mutex::new(None)))
+    }
+
+    /// Disabling forgets what's been recorded.
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut entries = self.0.lock().unwrap();
+        match (enabled, entries.is_some()) {
+            (true, false) => *entries = Some(VecDeque::new()),
+            (false, true) => *entries = None,
+            _ => {},
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    fn record(&self, operation: Operation) {
+        if let Some(entries) = self.0.lock().unwrap().as_mut() {
+            if entries.len() >= JOURNAL_ENTRIES {
+                entries.pop_front();
+            }
+            entries.push_back((Instant::now(), operation));
+        }
+    }
+
+    /// Oldest first, with the time passed since
+    pub fn dump(&self) -> Vec<String> {
+        let now = Instant::now();
+        match self.0.lock().unwrap().as_ref() {
+            Some(entries) => entries.iter()
+                .map(|(when, operation)| format!("-{:?}: {:?}", now - *when, operation))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Records everything going to the keyboard backend.
+struct JournaledKeyboard {
+    inner: Box<dyn KeyboardBackend>,
+    journal: Journal,
+}
+
+impl KeyboardBackend for JournaledKeyboard {
+    fn set_keymaps(&mut self, keymaps: &[CString]) {
+        self.journal.record(Operation::SetKeymaps(keymaps.len()));
+        self.inner.set_keymaps(keymaps)
+    }
+
+    fn select_keymap(&mut self, idx: usize) {
+        self.journal.record(Operation::SelectKeymap(idx));
+        self.inner.select_keymap(idx)
+    }
+
+    fn switch(&mut self, keycode: u32, action: PressType, time: Timestamp) {
+        self.journal.record(Operation::Keycode { code: keycode, press: action, time: time.0 });
+        self.inner.switch(keycode, action, time)
+    }
+
+    fn set_modifiers_state(&mut self, modifiers: Modifiers) {
+        self.journal.record(Operation::Modifiers(modifiers));
+        self.inner.set_modifiers_state(modifiers)
+    }
 }
 
 pub struct Submission {
     imservice: Option<Box<IMService>>,
-    virtual_keyboard: VirtualKeyboard,
+    keyboard: Box<dyn KeyboardBackend>,
+    journal: Journal,
+    inspector: inspect::Inspector,
     modifiers_active: Vec<(KeyStateId, Modifier)>,
     pressed: Vec<(KeyStateId, SubmittedAction)>,
-    keymap_fds: Vec<vkeyboard::c::KeyMap>,
+    keymap_count: usize,
     keymap_idx: Option<usize>,
+    sink: Option<Box<dyn TextSink>>,
+    /// Of the field created for the current layout
+    field_purpose: Option<text_field::Purpose>,
 }
 
 pub enum SubmitData<'a> {
@@ -92,14 +237,26 @@ pub enum SubmitData<'a> {
 }
 
 impl Submission {
-    pub fn new(vk: ZwpVirtualKeyboardV1, imservice: Option<Box<IMService>>) -> Self {
+    pub fn new(
+        keyboard: Box<dyn KeyboardBackend>,
+        imservice: Option<Box<IMService>>,
+        journal: Journal,
+        inspector: inspect::Inspector,
+    ) -> Self {
         Submission {
             imservice,
+            inspector,
             modifiers_active: Vec::new(),
-            virtual_keyboard: VirtualKeyboard(vk),
+            keyboard: Box::new(JournaledKeyboard {
+                inner: keyboard,
+                journal: journal.clone(),
+            }),
+            journal,
             pressed: Vec::new(),
-            keymap_fds: Vec::new(),
+            keymap_count: 0,
             keymap_idx: None,
+            sink: None,
+            field_purpose: None,
         }
     }
 
@@ -112,18 +269,32 @@ impl Submission {
         keycodes: &Vec<KeyCode>,
         time: Timestamp,
     ) {
+        if let Some(sink) = &mut self.sink {
+            match data {
+                SubmitData::Text(text) => sink.commit(&text.to_string_lossy()),
+                SubmitData::Erase => sink.erase(),
+                // Keys without text mean nothing to the field.
+                SubmitData::Keycodes => {},
+            }
+            let description = sink.describe();
+            self.show_popup(&description);
+            self.pressed.push((key_id, SubmittedAction::Sink));
+            return;
+        }
+
         let mods_are_on = !self.modifiers_active.is_empty();
 
         let was_committed_as_text = match (&mut self.imservice, mods_are_on) {
             (Some(imservice), false) => {
                 enum Outcome {
-                    Submitted(Result<(), imservice::SubmitError>),
+                    /// Bytes and result
+                    Submitted(usize, Result<(), imservice::SubmitError>),
                     NotSubmitted,
                 }
 
                 let submit_outcome = match data {
                     SubmitData::Text(text) => {
-                        Outcome::Submitted(imservice.commit_string(text))
+                        Outcome::Submitted(text.as_bytes().len(), imservice.commit_string(text))
                     },
                     SubmitData::Erase => {
                         /* Delete_surrounding_text takes byte offsets,
@@ -137,11 +308,13 @@ impl Submission {
                 };
 
                 match submit_outcome {
-                    Outcome::Submitted(result) => {
-                        match result.and_then(|()| imservice.commit()) {
+                    Outcome::Submitted(bytes, result) => {
+                        let ok = match result.and_then(|()| imservice.commit()) {
                             Ok(()) => true,
                             Err(imservice::SubmitError::NotActive) => false,
-                        }
+                        };
+                        self.journal.record(Operation::Commit { bytes, ok });
+                        ok
                     },
                     Outcome::NotSubmitted => false,
                 }
@@ -159,7 +332,7 @@ impl Submission {
                     match keycodes_count {
                         // Pressing a key made out of a single keycode is simple:
                         // press on press, release on release.
-                        1 => self.virtual_keyboard.switch(
+                        1 => self.keyboard.switch(
                             keycode,
                             PressType::Pressed,
                             time,
@@ -167,12 +340,12 @@ impl Submission {
                         // A key made of multiple keycodes
                         // has to submit them one after the other.
                         _ => {
-                            self.virtual_keyboard.switch(
+                            self.keyboard.switch(
                                 keycode.clone(),
                                 PressType::Pressed,
                                 time,
                             );
-                            self.virtual_keyboard.switch(
+                            self.keyboard.switch(
                                 keycode.clone(),
                                 PressType::Released,
                                 time,
@@ -180,13 +353,38 @@ impl Submission {
                         },
                     };
                 }
-                SubmittedAction::VirtualKeyboard(keycodes.clone())
+                SubmittedAction::VirtualKeyboard(keycodes.clone(), Instant::now())
             },
         };
         
         self.pressed.push((key_id, submit_action));
     }
     
+    /// Taps the keys with the extra modifiers held,
+    /// on top of the ones already active.
+    /// Never goes through the input method,
+    /// because the application must see the modifiers.
+    pub fn handle_shortcut(
+        &mut self,
+        key_id: KeyStateId,
+        modifiers: &[Modifier],
+        keycodes: &Vec<KeyCode>,
+        time: Timestamp,
+    ) {
+        for keycode in keycodes.iter() {
+            self.select_keymap(keycode.keymap_idx, time);
+            let raw_modifiers = self.get_raw_modifiers();
+            let extra = modifiers.iter()
+                .map(get_raw_modifier)
+                .fold(raw_modifiers, |m, n| m | n);
+            self.keyboard.set_modifiers_state(extra);
+            self.keyboard.switch(keycode.code, PressType::Pressed, time);
+            self.keyboard.switch(keycode.code, PressType::Released, time);
+            self.keyboard.set_modifiers_state(raw_modifiers);
+        }
+        self.pressed.push((key_id, SubmittedAction::Released(keycodes.clone())));
+    }
+
     pub fn handle_release(&mut self, key_id: KeyStateId, time: Timestamp) {
         let index = self.pressed.iter().position(|(id, _)| *id == key_id);
         if let Some(index) = index {
@@ -194,15 +392,18 @@ impl Submission {
             match action {
                 // string already sent, nothing to do
                 SubmittedAction::IMService => {},
+                // already released
+                SubmittedAction::Released(_) => {},
+                SubmittedAction::Sink => {},
                 // no matter if the imservice got activated,
                 // keys must be released
-                SubmittedAction::VirtualKeyboard(keycodes) => {
+                SubmittedAction::VirtualKeyboard(keycodes, _since) => {
                     let keycodes_count = keycodes.len();
                     match keycodes_count {
                         1 => {
                             let keycode = &keycodes[0];
                             self.select_keymap(keycode.keymap_idx, time);
-                            self.virtual_keyboard.switch(
+                            self.keyboard.switch(
                                 keycode.code,
                                 PressType::Released,
                                 time,
@@ -217,6 +418,21 @@ impl Submission {
         };
     }
     
+    /// Lets go of the keycodes of a held key, so that the application doesn't repeat them.
+    /// The key counts as pressed until released.
+    pub fn stop_app_repeat(&mut self, key_id: KeyStateId, time: Timestamp) {
+        let index = self.pressed.iter().position(|(id, _)| *id == key_id);
+        if let Some(index) = index {
+            if let SubmittedAction::VirtualKeyboard(keycodes, _since) = self.pressed[index].1.clone() {
+                self.handle_release(key_id, time);
+                self.pressed.insert(
+                    index,
+                    (key_id, SubmittedAction::Released(keycodes)),
+                );
+            }
+        }
+    }
+
     pub fn handle_add_modifier(
         &mut self,
         key_id: KeyStateId,
@@ -235,15 +451,215 @@ impl Submission {
         self.update_modifiers();
     }
 
+    fn get_raw_modifiers(&self) -> Modifiers {
+        self.modifiers_active.iter()
+            .map(|(_id, m)| get_raw_modifier(m))
+            .fold(Modifiers::empty(), |m, n| m | n)
+    }
+
     fn update_modifiers(&mut self) {
-        let raw_modifiers = self.modifiers_active.iter()
-            .map(|(_id, m)| match m {
-                Modifier::Control => Modifiers::CONTROL,
-                Modifier::Alt => Modifiers::MOD1,
-                Modifier::Mod4 => Modifiers::MOD4,
+        let raw_modifiers = self.get_raw_modifiers();
+        self.keyboard.set_modifiers_state(raw_modifiers);
+    }
+
+    /// Holds back the changes to the text field until `end_frame`,
+    /// so that everything one touch event does arrives in one commit.
+    /// Key events need nothing like that:
+    /// they only get flushed once the main loop is idle.
+    pub fn begin_frame(&mut self) {
+        if let Some(imservice) = &mut self.imservice {
+            imservice.begin_batch();
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        if let Some(imservice) = &mut self.imservice {
+            imservice.end_batch();
+        }
+    }
+
+    /// Submits text which doesn't come from any key,
+    /// e.g. from speech recognition.
+    /// There are no keys to fall back on, so it needs the input method.
+    pub fn commit_text(&mut self, text: &CString) -> Result<(), imservice::SubmitError> {
+        if let Some(sink) = &mut self.sink {
+            sink.commit(&text.to_string_lossy());
+            let description = sink.describe();
+            self.show_popup(&description);
+            return Ok(());
+        }
+        self.commit_to_application(text)
+    }
+
+    /// Like `commit_text`, but passes by the sink.
+    pub fn commit_to_application(&mut self, text: &CString)
+        -> Result<(), imservice::SubmitError>
+    {
+        let result = match &mut self.imservice {
+            Some(imservice) => imservice.commit_string(text)
+                .and_then(|()| imservice.commit()),
+            None => Err(imservice::SubmitError::NotActive),
+        };
+        self.journal.record(Operation::Commit {
+            bytes: text.as_bytes().len(),
+            ok: result.is_ok(),
+        });
+        result
+    }
+
+    /// Deletes the text right before the cursor, and commits new text instead.
+    pub fn replace_text(&mut self, old: &str, new: &CString)
+        -> Result<(), imservice::SubmitError>
+    {
+        let result = match &mut self.imservice {
+            Some(imservice) => imservice.delete_surrounding_text(old.len() as u32, 0)
+                .and_then(|()| imservice.commit_string(new))
+                .and_then(|()| imservice.commit()),
+            None => Err(imservice::SubmitError::NotActive),
+        };
+        let ok = result.is_ok();
+        self.journal.record(Operation::Delete { bytes: old.len(), ok });
+        self.journal.record(Operation::Commit { bytes: new.as_bytes().len(), ok });
+        result
+    }
+
+    /// Removes the word in front of the cursor, and returns it.
+    pub fn take_word(&mut self) -> Option<String> {
+        let imservice = self.imservice.as_mut()?;
+        let (word, length) = {
+            let (text, cursor) = imservice.get_surrounding_text()?;
+            if !text.is_char_boundary(cursor) {
+                return None;
+            }
+            let start = erase::find_start(text, cursor, erase::Unit::Word);
+            let before = &text[start..cursor];
+            (before.trim().to_string(), before.len())
+        };
+        if word.is_empty() {
+            return None;
+        }
+        let result = imservice.delete_surrounding_text(length as u32, 0)
+            .and_then(|()| imservice.commit());
+        self.journal.record(Operation::Delete { bytes: length, ok: result.is_ok() });
+        result.ok().map(|()| word)
+    }
+
+    /// Shows text next to the cursor, if there's a text field.
+    pub fn show_popup(&mut self, text: &str) {
+        if let Some(imservice) = &mut self.imservice {
+            // Without a text field, there's nowhere to show it.
+            let _ = imservice.show_popup(text);
+        }
+    }
+
+    pub fn hide_popup(&mut self) {
+        if let Some(imservice) = &mut self.imservice {
+            imservice.hide_popup();
+        }
+    }
+
+    /// Puts the first spelling correction in place of the misspelled word.
+    pub fn accept_correction(&mut self) -> Result<(), imservice::SubmitError> {
+        match &mut self.imservice {
+            Some(imservice) => imservice.accept_correction(),
+            None => Err(imservice::SubmitError::NotActive),
+        }
+    }
+
+    pub fn is_pressed(&self, key_id: &KeyStateId) -> bool {
+        self.pressed.iter().any(|(id, _)| id == key_id)
+    }
+
+    /// How long the key has been held on the virtual keyboard,
+    /// or None if it's not held there.
+    pub fn get_held_duration(&self, key_id: &KeyStateId, now: Instant) -> Option<Duration> {
+        self.pressed.iter()
+            .find(|(id, _)| id == key_id)
+            .and_then(|(_, action)| match action {
+                SubmittedAction::VirtualKeyboard(_, since) => Some(now.saturating_duration_since(*since)),
+                _ => None,
             })
-            .fold(Modifiers::empty(), |m, n| m | n);
-        self.virtual_keyboard.set_modifiers_state(raw_modifiers);
+    }
+
+    /// The key is held, and its text went through the input method.
+    pub fn is_committed_as_text(&self, key_id: &KeyStateId) -> bool {
+        self.pressed.iter().any(|(id, action)| {
+            id == key_id
+            && match action {
+                SubmittedAction::IMService => true,
+                _ => false,
+            }
+        })
+    }
+
+    /// Deletes a larger piece of text on behalf of a held erase key.
+    ///
+    /// The first call stops the application from repeating the erase key.
+    /// With the surrounding text known, the exact amount gets deleted.
+    /// Otherwise, Ctrl+BackSpace is the best guess for a word,
+    /// and also for a line, given that nothing better is standard.
+    pub fn erase(&mut self, key_id: &KeyStateId, unit: erase::Unit, time: Timestamp) {
+        let index = match self.pressed.iter().position(|(id, _)| id == key_id) {
+            Some(index) => index,
+            None => return,
+        };
+        let keycodes = match self.pressed[index].1.clone() {
+            SubmittedAction::VirtualKeyboard(keycodes, _since) => {
+                self.handle_release(*key_id, time);
+                self.pressed.insert(
+                    index,
+                    (*key_id, SubmittedAction::Released(keycodes.clone())),
+                );
+                keycodes
+            },
+            SubmittedAction::Released(keycodes) => keycodes,
+            SubmittedAction::IMService => Vec::new(),
+            // The field erases one character per press.
+            SubmittedAction::Sink => return,
+        };
+        self.delete_before_cursor(unit, keycodes, time);
+    }
+
+    /// Deletes before the cursor like a held erase key, without one.
+    /// The keycodes are the erase key's, for pressing with Ctrl
+    /// when the surrounding text is not known.
+    pub fn delete_before_cursor(
+        &mut self,
+        unit: erase::Unit,
+        keycodes: Vec<KeyCode>,
+        time: Timestamp,
+    ) {
+        let deleted = match &mut self.imservice {
+            Some(imservice) => {
+                let count = imservice.get_surrounding_text()
+                    .map(|(text, cursor)| {
+                        cursor - erase::find_start(text, cursor, unit)
+                    });
+                match count {
+                    Some(count) => {
+                        let ok = imservice
+                            .delete_surrounding_text(count as u32, 0)
+                            .and_then(|()| imservice.commit())
+                            .is_ok();
+                        self.journal.record(Operation::Delete { bytes: count, ok });
+                        ok
+                    },
+                    None => false,
+                }
+            },
+            None => false,
+        };
+
+        if !deleted {
+            let modifiers = self.get_raw_modifiers();
+            self.keyboard.set_modifiers_state(modifiers | Modifiers::CONTROL);
+            for keycode in keycodes {
+                self.select_keymap(keycode.keymap_idx, time);
+                self.keyboard.switch(keycode.code, PressType::Pressed, time);
+                self.keyboard.switch(keycode.code, PressType::Released, time);
+            }
+            self.keyboard.set_modifiers_state(modifiers);
+        }
     }
 
     pub fn is_modifier_active(&self, modifier: Modifier) -> bool {
@@ -266,7 +682,7 @@ impl Submission {
             return;
         }
         self.modifiers_active = Vec::new();
-        self.virtual_keyboard.set_modifiers_state(Modifiers::empty())
+        self.keyboard.set_modifiers_state(Modifiers::empty())
     }
 
     fn release_all_virtual_keys(&mut self, time: Timestamp) {
@@ -274,7 +690,7 @@ impl Submission {
             .clone().into_iter()
             .filter_map(|(id, action)| {
                 match action {
-                    SubmittedAction::VirtualKeyboard(_) => Some(id),
+                    SubmittedAction::VirtualKeyboard(_, _) => Some(id),
                     _ => None,
                 }
             });
@@ -298,17 +714,58 @@ impl Submission {
             self.keymap_idx = Some(idx);
             self.clear_all_modifiers();
             self.release_all_virtual_keys(time);
-            let keymap = &self.keymap_fds[idx];
-            self.virtual_keyboard.update_keymap(keymap);
+            if idx < self.keymap_count {
+                self.keyboard.select_keymap(idx);
+            }
         }
     }
     
+    /// Sends typing to the sink instead of the application,
+    /// or back to the application with None.
+    pub fn redirect(&mut self, sink: Option<Box<dyn TextSink>>) {
+        match &sink {
+            Some(sink) => self.show_popup(&sink.describe()),
+            None => self.hide_popup(),
+        }
+        self.sink = sink;
+    }
+
+    /// Contents of the sink receiving typing, if there is one.
+    pub fn get_redirected_text(&self) -> Option<String> {
+        self.sink.as_ref().map(|sink| sink.get_text().to_string())
+    }
+
+    /// Leaves a copy of the keys held down for the debug interface.
+    pub fn report_keys(&self, layout: &layout::Layout) {
+        self.inspector.set_keys(inspect::Keys {
+            submitted: self.pressed.iter()
+                .map(|(id, action)| format!("{:?}: {}", id, match action {
+                    SubmittedAction::VirtualKeyboard(..) => "virtual keyboard",
+                    SubmittedAction::Released(_) => "released",
+                    SubmittedAction::IMService => "input method",
+                    SubmittedAction::Sink => "text field",
+                }))
+                .collect(),
+            modifiers: self.modifiers_active.iter()
+                .map(|(_id, modifier)| format!("{:?}", modifier))
+                .collect(),
+            keymap: self.keymap_idx,
+            redirected: self.sink.is_some(),
+            ..inspect::Keys::new(layout)
+        });
+    }
+
     pub fn use_layout(&mut self, layout: &layout::Layout, time: Timestamp) {
-        self.keymap_fds = layout.keymaps.iter()
-            .map(|keymap_str| vkeyboard::c::KeyMap::from_cstr(
-                keymap_str.as_c_str()
-            ))
-            .collect();
+        // A field survives layout changes as long as it's wanted,
+        // e.g. when switching to the numbers view.
+        if layout.text_field != self.field_purpose {
+            self.field_purpose = layout.text_field;
+            self.redirect(layout.text_field.map(|purpose| {
+                Box::new(text_field::Field::new(purpose)) as Box<dyn TextSink>
+            }));
+        }
+        self.keyboard.set_keymaps(&layout.keymaps);
+        self.keymap_count = layout.keymaps.len();
         self.keymap_idx = None;
 
         // This can probably be eliminated,
@@ -316,5 +773,34 @@ impl Submission {
         // However, self.keymap_idx needs to become Option<>
         // in order to force update on new layouts.
         self.select_keymap(0, time);
+        self.report_keys(layout);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn journal_bounded() {
+        let journal = Journal::new();
+        journal.set_enabled(true);
+        for idx in 0..JOURNAL_ENTRIES + 5 {
+            journal.record(Operation::SelectKeymap(idx));
+        }
+        let entries = journal.dump();
+        assert_eq!(entries.len(), JOURNAL_ENTRIES);
+        assert!(entries[0].ends_with("SelectKeymap(5)"));
+    }
+
+    #[test]
+    fn journal_off() {
+        let journal = Journal::new();
+        journal.record(Operation::SelectKeymap(0));
+        journal.set_enabled(true);
+        assert_eq!(journal.dump().len(), 0);
+        journal.record(Operation::SelectKeymap(0));
+        journal.set_enabled(false);
+        assert_eq!(journal.dump().len(), 0);
     }
 }
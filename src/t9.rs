@@ -0,0 +1,158 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Typing words with one key per group of letters, like on old phones.
+ *
+ * Keys with the `t9` action stand for a group of letters, e.g. "abc".
+ * Every press looks for the words in the word list
+ * which have a letter from each pressed group in turn.
+ * The first match gets typed right away, and replaced as more keys come.
+ * All matches are shown next to the cursor,
+ * and the `t9_next` action switches to the next one.
+ * Erasing takes back the last group, and any other key finishes the word.
+ *
+ * When nothing matches, the first letter of each group stands in.
+ * After a single press, the letters of the group are offered too,
+ * so that a group of punctuation works as well.
+ */
+
+use std::ffi::CString;
+
+use crate::logging;
+use crate::spelling::WordList;
+use crate::submission::Submission;
+
+
+/// Words offered at a time
+const MAX_CANDIDATES: usize = 8;
+
+/// The word list is in lower case, so capitals would never match.
+pub fn is_group(letters: &str) -> bool {
+    !letters.is_empty() && !letters.chars().any(char::is_uppercase)
+}
+
+/// Adds the fallbacks to the matching words.
+fn find_candidates(groups: &[String], mut words: Vec<String>) -> Vec<String> {
+    words.truncate(MAX_CANDIDATES);
+    if let [group] = groups {
+        for letter in group.chars().map(String::from) {
+            if !words.contains(&letter) {
+                words.push(letter);
+            }
+        }
+    }
+    if words.is_empty() {
+        words.push(groups.iter().filter_map(|group| group.chars().next()).collect());
+    }
+    words
+}
+
+/// The word being typed, and the words it could be.
+#[derive(Default)]
+pub struct Composer {
+    groups: Vec<String>,
+    candidates: Vec<String>,
+    current: usize,
+}
+
+impl Composer {
+    fn get_typed(&self) -> &str {
+        self.candidates.get(self.current)
+            .map(String::as_str)
+            .unwrap_or("")
+    }
+
+    fn describe(&self) -> String {
+        self.candidates.iter().enumerate()
+            .map(|(i, text)| match i == self.current {
+                true => format!("[{}]", text),
+                false => text.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    fn update_candidates(&mut self) {
+        let words = WordList::get()
+            .map(|list| list.find_by_groups(&self.groups))
+            .unwrap_or_default();
+        self.candidates = match self.groups.is_empty() {
+            true => Vec::new(),
+            false => find_candidates(&self.groups, words),
+        };
+        self.current = 0;
+    }
+
+    /// Puts the current candidate in place of the old one.
+    fn retype(&self, old: &str, submission: &mut Submission) {
+        let new = CString::new(self.get_typed())
+            .expect("Candidate contains a null byte");
+        if submission.replace_text(old, &new).is_err() {
+            log_print!(
+                logging::Level::Warning,
+                "No text field to receive the word",
+            );
+        }
+        match self.candidates.is_empty() {
+            true => submission.hide_popup(),
+            false => submission.show_popup(&self.describe()),
+        }
+    }
+
+    pub fn is_composing(&self) -> bool {
+        !self.groups.is_empty()
+    }
+
+    pub fn press(&mut self, letters: &str, submission: &mut Submission) {
+        let old = self.get_typed().to_owned();
+        self.groups.push(letters.into());
+        self.update_candidates();
+        self.retype(&old, submission);
+    }
+
+    pub fn next(&mut self, submission: &mut Submission) {
+        if self.candidates.len() > 1 {
+            let old = self.get_typed().to_owned();
+            self.current = (self.current + 1) % self.candidates.len();
+            self.retype(&old, submission);
+        }
+    }
+
+    /// Takes back the last group pressed.
+    pub fn erase(&mut self, submission: &mut Submission) {
+        let old = self.get_typed().to_owned();
+        self.groups.pop();
+        self.update_candidates();
+        self.retype(&old, submission);
+    }
+
+    /// Leaves the typed word in the text. Call before any other key acts.
+    pub fn finish(&mut self, submission: &mut Submission) {
+        if self.is_composing() {
+            *self = Composer::default();
+            submission.hide_popup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn groups(groups: &[&str]) -> Vec<String> {
+        groups.iter().map(|group| group.to_string()).collect()
+    }
+
+    #[test]
+    fn fallbacks() {
+        assert_eq!(
+            find_candidates(&groups(&["ghi", "def"]), Vec::new()),
+            vec!["gd".to_string()],
+        );
+        assert_eq!(
+            find_candidates(&groups(&["ghi"]), vec!["i".into()]),
+            vec!["i".to_string(), "g".into(), "h".into()],
+        );
+    }
+}
@@ -0,0 +1,170 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Text generated at the time the key gets pressed.
+ *
+ * Templates are text with placeholders in braces:
+ * - `{date}` and `{time}` for the current local date and time,
+ * - `{date:PATTERN}` and `{time:PATTERN}` for a `g_date_time_format` pattern,
+ * - `{clipboard}` for the text on the clipboard.
+ *
+ * `{{` and `}}` stand for the braces themselves.
+ *
+ * Whatever a placeholder expands to is not expanded again,
+ * so clipboard contents can't smuggle in further placeholders.
+ */
+
+use std::fmt;
+
+
+const DATE_PATTERN: &str = "%Y-%m-%d";
+const TIME_PATTERN: &str = "%H:%M";
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Position in bytes of a brace without its partner
+    Unmatched(usize),
+    UnknownPlaceholder(String),
+    /// The source couldn't provide the text
+    Unavailable(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Unmatched(position) => write!(f, "Unmatched brace at {}", position),
+            Error::UnknownPlaceholder(name) => write!(f, "Unknown placeholder {}", name),
+            Error::Unavailable(name) => write!(f, "No text for placeholder {}", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Piece<'a> {
+    Text(&'a str),
+    /// Date and time formatted with the pattern
+    Now(&'a str),
+    Clipboard,
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Piece, Error> {
+    let mut parts = placeholder.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("date"), None) => Ok(Piece::Now(DATE_PATTERN)),
+        (Some("time"), None) => Ok(Piece::Now(TIME_PATTERN)),
+        (Some("date"), Some(pattern)) | (Some("time"), Some(pattern))
+            => Ok(Piece::Now(pattern)),
+        (Some("clipboard"), None) => Ok(Piece::Clipboard),
+        _ => Err(Error::UnknownPlaceholder(placeholder.into())),
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Piece>, Error> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while !rest.is_empty() {
+        let position = template.len() - rest.len();
+        let brace = rest.find(|c| c == '{' || c == '}');
+        let (text, after) = match brace {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        rest = if after.starts_with("{{") {
+            pieces.push(Piece::Text("{"));
+            &after[2..]
+        } else if after.starts_with("}}") {
+            pieces.push(Piece::Text("}"));
+            &after[2..]
+        } else if after.starts_with('{') {
+            let end = after.find('}')
+                .ok_or(Error::Unmatched(position + text.len()))?;
+            pieces.push(parse_placeholder(&after[1..end])?);
+            &after[end + 1..]
+        } else if after.starts_with('}') {
+            return Err(Error::Unmatched(position + text.len()));
+        } else {
+            after
+        };
+    }
+    Ok(pieces)
+}
+
+/// Where the placeholders take their text from
+pub trait Source {
+    fn format_now(&self, pattern: &str) -> Option<String>;
+    fn get_clipboard(&self) -> Option<String>;
+}
+
+/// The clock and clipboard of the system
+pub struct System;
+
+impl Source for System {
+    fn format_now(&self, pattern: &str) -> Option<String> {
+        #[cfg(feature = "glib_v0_14")]
+        let formatted = glib::DateTime::new_now_local().ok()
+            .and_then(|now| now.format(pattern).ok());
+        #[cfg(not(feature = "glib_v0_14"))]
+        let formatted = glib::DateTime::new_now_local().format(pattern);
+
+        formatted.map(|text| text.to_string())
+    }
+
+    fn get_clipboard(&self) -> Option<String> {
+        gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+            .wait_for_text()
+            .map(|text| text.to_string())
+    }
+}
+
+/// Finds problems which don't depend on the time of expansion.
+pub fn check(template: &str) -> Result<(), Error> {
+    parse(template).map(|_| ())
+}
+
+pub fn expand<S: Source>(template: &str, source: &S) -> Result<String, Error> {
+    parse(template)?.into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => Ok(text.into()),
+            Piece::Now(pattern) => source.format_now(pattern)
+                .ok_or_else(|| Error::Unavailable(pattern.into())),
+            Piece::Clipboard => source.get_clipboard()
+                .ok_or_else(|| Error::Unavailable("clipboard".into())),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Fake;
+
+    impl Source for Fake {
+        fn format_now(&self, pattern: &str) -> Option<String> {
+            Some(format!("<{}>", pattern))
+        }
+        fn get_clipboard(&self) -> Option<String> {
+            Some("{date}".into())
+        }
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            expand("On {date} at {time:%H}: {clipboard}", &Fake),
+            Ok("On <%Y-%m-%d> at <%H>: {date}".into()),
+        );
+    }
+
+    #[test]
+    fn braces() {
+        assert_eq!(expand("{{date}}", &Fake), Ok("{date}".into()));
+        assert_eq!(check("a}b"), Err(Error::Unmatched(1)));
+        assert_eq!(check("ab{date"), Err(Error::Unmatched(2)));
+        assert_eq!(check("{weather}"), Err(Error::UnknownPlaceholder("weather".into())));
+    }
+}
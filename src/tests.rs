@@ -1,7 +1,15 @@
 /*! Testing functionality */
 
+use ::action::Action;
+use ::data::expansion::Context;
 use ::data::parsing::Layout;
+use ::imservice::ContentPurpose;
+use ::layout;
+use ::layout::{ ArrangementKind, LayoutData };
 use ::logging;
+use ::outputs::Millimeter;
+use ::state::MIN_TARGET_SIZE;
+use std::ffi::CString;
 use xkbcommon::xkb;
 
 
@@ -26,18 +34,31 @@ impl CountAndPrint {
     }
 }
 
+/// Layouts with conditions get checked in every context,
+/// so that mistakes in rarely seen variants show up too.
+fn get_contexts(layout: &Layout) -> Vec<Context> {
+    match layout.is_conditional() {
+        true => Context::all(),
+        false => vec![Context::default()],
+    }
+}
+
 pub fn check_builtin_layout(name: &str, missing_return: bool) {
-    check_layout(
-        Layout::from_resource(name).expect("Invalid layout data"),
-        missing_return,
-    )
+    let layout = Layout::from_resource(name).expect("Invalid layout data");
+    for context in get_contexts(&layout) {
+        let layout = Layout::from_resource_for(name, &context)
+            .unwrap_or_else(|e| panic!("Invalid layout data for {}: {}", context, e));
+        check_layout(layout, missing_return);
+    }
 }
 
 pub fn check_layout_file(path: &str) {
-    check_layout(
-        Layout::from_file(path.into()).expect("Invalid layout file"),
-        false,
-    )
+    let layout = Layout::from_file(path.into()).expect("Invalid layout file");
+    for context in get_contexts(&layout) {
+        let layout = Layout::from_file_for(path.into(), &context)
+            .unwrap_or_else(|e| panic!("Invalid layout file for {}: {}", context, e));
+        check_layout(layout, false);
+    }
 }
 
 fn check_sym_in_keymap(state: &xkb::State, sym_name: &str) -> bool {
@@ -80,21 +101,7 @@ fn check_layout(layout: Layout, allow_missing_return: bool) {
 
     let layout = layout.expect("layout broken");
     
-    let xkb_states: Vec<xkb::State> = layout.keymaps.iter()
-        .map(|keymap_str| {
-            let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
-            let keymap_str = keymap_str
-                .clone()
-                .into_string().expect("Failed to decode keymap string");
-            let keymap = xkb::Keymap::new_from_string(
-                &context,
-                keymap_str.clone(),
-                xkb::KEYMAP_FORMAT_TEXT_V1,
-                xkb::KEYMAP_COMPILE_NO_FLAGS,
-            ).expect("Failed to create keymap");
-            xkb::State::new(&keymap)
-        })
-        .collect();
+    let xkb_states = make_states(&layout.keymaps);
 
     check_sym_presence(&xkb_states, "BackSpace", &mut handler);
     let mut printer = logging::Print;
@@ -107,9 +114,10 @@ fn check_layout(layout: Layout, allow_missing_return: bool) {
 
     // "Press" each button with keysyms
     for (_pos, view) in layout.views.values() {
+        let view = view.get().expect("View not built");
         for (_y, row) in view.get_rows() {
             for (_x, button) in row.get_buttons() {
-                let keystate = button.state.borrow();
+                let keystate = layout.keys.get(button.key);
                 for keycode in &keystate.keycodes {
                     match xkb_states[keycode.keymap_idx].key_get_one_sym(keycode.code) {
                         xkb::KEY_NoSymbol => {
@@ -132,7 +140,167 @@ fn check_layout(layout: Layout, allow_missing_return: bool) {
         }
     }
 
+    for problem in check_keymap_round_trip(&layout, &xkb_states) {
+        handler.handle(logging::Level::Error, &problem);
+    }
+
+    // Wider screens have room for bigger buttons, so this is only a hint.
+    let mut layout = layout::Layout::new(layout, ArrangementKind::Base, ContentPurpose::Normal);
+    check_target_sizes(&mut layout, &mut printer);
+
     if handler.0 > 0 {
         panic!("Layout contains mistakes");
     }
 }
+
+/// The narrowest screens showing the keyboard, like the Librem 5's
+const NARROWEST_SCREEN: Millimeter = Millimeter(65);
+
+/// Finds views whose smallest buttons stay under the minimal size
+/// on the narrowest screen, where the width limits the panel.
+fn check_target_sizes(layout: &mut layout::Layout, handler: &mut dyn logging::Handler) {
+    let min_size = MIN_TARGET_SIZE.numerator.0 as f64 / MIN_TARGET_SIZE.denominator as f64;
+    let mut views: Vec<String> = layout.views.keys().cloned().collect();
+    views.sort();
+    for view in views {
+        let share_of_width = layout.get_aspect_ratio(&view)
+            .and_then(|ratio| layout.get_smallest_button(&view).map(|share| share * ratio));
+        if let Some(share) = share_of_width {
+            let size = share * NARROWEST_SCREEN.0 as f64;
+            if size < min_size {
+                handler.handle(
+                    logging::Level::Warning,
+                    &format!(
+                        "Buttons in view {} are {:.1} mm on a {} mm wide screen, less than {} mm",
+                        view, size, NARROWEST_SCREEN.0, min_size,
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn make_states(keymaps: &[CString]) -> Vec<xkb::State> {
+    keymaps.iter()
+        .map(|keymap_str| {
+            let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+            let keymap_str = keymap_str
+                .clone()
+                .into_string().expect("Failed to decode keymap string");
+            let keymap = xkb::Keymap::new_from_string(
+                &context,
+                keymap_str.clone(),
+                xkb::KEYMAP_FORMAT_TEXT_V1,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            ).expect("Failed to create keymap");
+            xkb::State::new(&keymap)
+        })
+        .collect()
+}
+
+/// Sends the keycodes of each key through the keymaps,
+/// and compares the keysyms coming out with what the key declares.
+/// When the key submits text, the keysyms must also type that text.
+/// Returns the mismatches.
+pub fn check_keymap_round_trip(layout: &LayoutData, states: &[xkb::State])
+    -> Vec<String>
+{
+    let mut problems = Vec::new();
+    for (_pos, view) in layout.views.values() {
+        let view = match view.get() {
+            Some(view) => view,
+            None => continue,
+        };
+        for (_y, row) in view.get_rows() {
+            for (_x, button) in row.get_buttons() {
+                let name = button.name.to_string_lossy();
+                let keystate = layout.keys.get(button.key);
+                let (text, declared) = match &keystate.action {
+                    Action::Submit { text, keys } => (text.as_ref(), keys.clone()),
+                    Action::Shortcut { keys, .. } => (None, keys.clone()),
+                    Action::Erase => (None, vec![::action::KeySym("BackSpace".into())]),
+                    _ => continue,
+                };
+                if declared.len() != keystate.keycodes.len() {
+                    problems.push(format!(
+                        "Key {} declares {} keysyms, but has {} keycodes",
+                        name, declared.len(), keystate.keycodes.len(),
+                    ));
+                    continue;
+                }
+                let resolved: Vec<xkb::Keysym> = keystate.keycodes.iter()
+                    .map(|keycode| match states.get(keycode.keymap_idx) {
+                        Some(state) => state.key_get_one_sym(keycode.code),
+                        None => xkb::KEY_NoSymbol,
+                    })
+                    .collect();
+                for (sym, expected) in resolved.iter().zip(declared.iter()) {
+                    let expected_sym = xkb::keysym_from_name(
+                        &expected.0,
+                        xkb::KEYSYM_NO_FLAGS,
+                    );
+                    if *sym != expected_sym {
+                        problems.push(format!(
+                            "Key {} should send {}, but sends {}",
+                            name, expected.0, xkb::keysym_get_name(*sym),
+                        ));
+                    }
+                }
+                if let Some(text) = text {
+                    let typed: String = resolved.iter()
+                        .map(|sym| xkb::keysym_to_utf8(*sym))
+                        .map(|utf8| utf8.trim_end_matches('\0').to_owned())
+                        .collect();
+                    if typed.as_bytes() != text.as_bytes() {
+                        problems.push(format!(
+                            "Key {} submits {:?}, but its keys type {:?}",
+                            name, text, typed,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::data::parsing::BottomRow;
+
+    fn build(layout: Layout) -> LayoutData {
+        layout.with_bottom_row(&BottomRow::default())
+            .build(logging::Print).0
+            .expect("Layout broken")
+    }
+
+    #[test]
+    fn round_trip_builtin() {
+        let layout = build(Layout::from_resource("us").unwrap());
+        let states = make_states(&layout.keymaps);
+        assert_eq!(check_keymap_round_trip(&layout, &states), Vec::<String>::new());
+    }
+
+    /// Keycodes pointing to the wrong keysyms get caught
+    #[test]
+    fn round_trip_swapped() {
+        let mut layout = build(Layout::from_resource("us").unwrap());
+        let states = make_states(&layout.keymaps);
+        let ids: Vec<_> = ["a", "b"].iter()
+            .map(|name| {
+                layout.views["base"].1.get().unwrap()
+                    .get_rows().iter()
+                    .flat_map(|(_y, row)| row.get_buttons())
+                    .find(|(_x, button)| button.name.to_str() == Ok(*name))
+                    .map(|(_x, button)| button.key)
+                    .unwrap()
+            })
+            .collect();
+        let a = layout.keys.get(ids[0]).keycodes.clone();
+        let b = layout.keys.get(ids[1]).keycodes.clone();
+        layout.keys.get_mut(ids[0]).keycodes = b;
+        layout.keys.get_mut(ids[1]).keycodes = a;
+        assert_eq!(check_keymap_round_trip(&layout, &states).len(), 4);
+    }
+}
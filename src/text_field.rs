@@ -0,0 +1,86 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! A text field belonging to the keyboard itself.
+ *
+ * While it's in use, typing goes into the field instead of the application,
+ * and the field's contents are shown in the popup next to the cursor.
+ * The symbols picker uses it to type the search query,
+ * and the calculator to type expressions.
+ */
+
+use crate::calculator;
+use crate::submission::TextSink;
+
+
+/// What the typed text is for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Purpose {
+    /// Query for the symbols picker
+    Search,
+    /// Expression for the calculator
+    Calculation,
+}
+
+pub struct Field {
+    purpose: Purpose,
+    text: String,
+}
+
+impl Field {
+    pub fn new(purpose: Purpose) -> Field {
+        Field { purpose, text: String::new() }
+    }
+}
+
+impl TextSink for Field {
+    fn commit(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    fn erase(&mut self) {
+        self.text.pop();
+    }
+
+    fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    fn describe(&self) -> String {
+        match self.purpose {
+            Purpose::Search => format!("🔍 {}", self.text),
+            Purpose::Calculation => match calculator::evaluate(&self.text) {
+                Ok(value) => format!("{} = {}", self.text, calculator::format(value)),
+                Err(_) => self.text.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn erase_multibyte() {
+        let mut field = Field::new(Purpose::Search);
+        field.commit("ab");
+        field.commit("ć");
+        field.erase();
+        assert_eq!(field.get_text(), "ab");
+        field.erase();
+        field.erase();
+        field.erase();
+        assert_eq!(field.get_text(), "");
+    }
+
+    #[test]
+    fn calculation_result() {
+        let mut field = Field::new(Purpose::Calculation);
+        field.commit("6×7");
+        assert_eq!(field.describe(), "6×7 = 42");
+        field.commit("+");
+        assert_eq!(field.describe(), "6×7+");
+    }
+}
@@ -0,0 +1,687 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Recording and replaying the events reaching the state machine.
+ *
+ * With `--trace FILE`, every `state::Event` gets appended to the file,
+ * together with the time when it got applied.
+ * Feeding the events back through `Application::apply_event`
+ * goes through the same states as the original session,
+ * so a trace attached to a bug report reproduces the bug exactly.
+ * `squeekboard-replay-trace FILE` prints what happened.
+ *
+ * The file is a stream of YAML documents:
+ * a header with the starting state, and then one document per event.
+ * The header carries a version number,
+ * and traces from other versions get refused.
+ * Like in `persist`, the types are mirrored here,
+ * so that changes in the code don't silently change the format.
+ */
+
+use crate::animation;
+use crate::data::parsing::BottomRow;
+use crate::debug;
+use crate::gestures;
+use crate::imservice::{ ContentHint, ContentPurpose };
+use crate::layout::c::Bounds;
+use crate::logging;
+use crate::morse;
+use crate::outputs;
+use crate::outputs::{ Millimeter, OutputId, OutputState };
+use crate::persist;
+use crate::remote;
+use crate::repeat::RepeatInfo;
+use crate::sensors;
+use crate::settings;
+use crate::state;
+use crate::state::{ Application, InputMethod, InputMethodDetails, Presence, output, visibility };
+use serde::{ Deserialize, Serialize };
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+// Traits
+use crate::logging::Warn;
+
+
+/// Bump on every incompatible change to the types in this file.
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Yaml(serde_yaml::Error),
+    /// The file was written by an incompatible version
+    Version(u32),
+    /// Parsed, but doesn't make sense
+    Format(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO: {}", e),
+            Error::Yaml(e) => write!(f, "YAML: {}", e),
+            Error::Version(v) => write!(f, "Unsupported version {}", v),
+            Error::Format(e) => write!(f, "Bad trace: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Self {
+        Error::Yaml(e)
+    }
+}
+
+/// Read before anything else, to reject unknown formats early.
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    version: u32,
+    /// Restored from the saved state
+    initial: persist::Snapshot,
+    /// From the command line
+    layout_override: Option<String>,
+    pinned_output: Option<String>,
+    height_override: Option<u32>,
+}
+
+impl Header {
+    fn new(state: &Application) -> Self {
+        Header {
+            version: VERSION,
+            initial: persist::Snapshot::new(state),
+            layout_override: state.layout_override.clone(),
+            pinned_output: state.outputs.pinned.clone(),
+            height_override: state.height_override,
+        }
+    }
+
+    fn apply_to(self, state: Application) -> Application {
+        let state = self.initial.apply_to(state);
+        Application {
+            layout_override: self.layout_override,
+            outputs: output::Policy {
+                pinned: self.pinned_output,
+                ..state.outputs
+            },
+            height_override: self.height_override,
+            ..state
+        }
+    }
+}
+
+/// Microseconds since the start of recording
+type Micros = u64;
+
+fn to_micros(start: Instant, when: Instant) -> Micros {
+    match when > start {
+        true => {
+            let since = when - start;
+            since.as_secs() * 1_000_000 + since.subsec_micros() as u64
+        },
+        false => 0,
+    }
+}
+
+fn from_micros(start: Instant, micros: Micros) -> Instant {
+    start + Duration::from_micros(micros)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Geometry {
+    /// `wl_output.transform`
+    transform: u32,
+    width_mm: Option<i32>,
+    height_mm: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Output {
+    mode: Option<(i32, i32)>,
+    geometry: Option<Geometry>,
+    scale: i32,
+    name: Option<String>,
+    #[serde(default)]
+    identity: Option<String>,
+}
+
+impl From<&OutputState> for Output {
+    fn from(state: &OutputState) -> Self {
+        Output {
+            mode: state.current_mode.as_ref().map(|mode| (mode.width, mode.height)),
+            geometry: state.geometry.as_ref().map(|geometry| Geometry {
+                transform: geometry.transform as u32,
+                width_mm: geometry.phys_size.width.map(|mm| mm.0),
+                height_mm: geometry.phys_size.height.map(|mm| mm.0),
+            }),
+            scale: state.scale,
+            name: state.name.clone(),
+            identity: state.identity.clone(),
+        }
+    }
+}
+
+impl Output {
+    fn into_state(self) -> Result<OutputState, Error> {
+        let geometry = match self.geometry {
+            Some(geometry) => Some(outputs::Geometry {
+                transform: outputs::c::Transform::from_u32(geometry.transform)
+                    .ok_or_else(|| Error::Format(format!(
+                        "Unknown transform {}", geometry.transform,
+                    )))?,
+                phys_size: outputs::Size {
+                    width: geometry.width_mm.map(Millimeter),
+                    height: geometry.height_mm.map(Millimeter),
+                },
+            }),
+            None => None,
+        };
+        Ok(OutputState {
+            current_mode: self.mode.map(|(width, height)| outputs::Mode { width, height }),
+            geometry,
+            scale: self.scale,
+            name: self.name,
+            identity: self.identity,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Settings {
+    theme: Option<String>,
+    height_percent: u32,
+    feedback: bool,
+    hide_delay_ms: u64,
+    hide_with_hardware_keyboard: bool,
+    hide_when_covered: bool,
+    show_on_focus: bool,
+    scramble_pin: bool,
+    spell_check: bool,
+    edge_handle: bool,
+    /// Emoji, voice, language, comma, period
+    bottom_row: (bool, bool, bool, bool, bool),
+    #[serde(default)]
+    repeat_rate: Option<u32>,
+    #[serde(default)]
+    repeat_delay_ms: Option<u64>,
+    #[serde(default)]
+    allow_screenshots: bool,
+    /// None in traces from before the setting
+    #[serde(default)]
+    morse_letter_gap_ms: Option<u64>,
+    /// None in traces from before the setting
+    #[serde(default)]
+    remote_keyboard: Option<String>,
+    #[serde(default)]
+    command_socket: bool,
+    #[serde(default)]
+    ibus_engine: Option<String>,
+    #[serde(default)]
+    statistics: bool,
+    /// Commands for swiping left, right, up, down
+    #[serde(default)]
+    swipes: (Option<String>, Option<String>, Option<String>, Option<String>),
+    #[serde(default)]
+    return_sends: bool,
+    #[serde(default)]
+    extra_keys: Vec<String>,
+    /// None in traces from before the setting
+    #[serde(default)]
+    announce_changes: Option<bool>,
+    /// None in traces from before the setting
+    #[serde(default)]
+    sound_volume: Option<u32>,
+    #[serde(default)]
+    visual_feedback: bool,
+}
+
+impl From<&settings::Settings> for Settings {
+    fn from(s: &settings::Settings) -> Self {
+        let row = &s.bottom_row;
+        Settings {
+            theme: s.theme.clone(),
+            height_percent: s.height_percent,
+            feedback: s.feedback,
+            hide_delay_ms: s.hide_delay.as_millis() as u64,
+            hide_with_hardware_keyboard: s.hide_with_hardware_keyboard,
+            hide_when_covered: s.hide_when_covered,
+            show_on_focus: s.show_on_focus,
+            scramble_pin: s.scramble_pin,
+            spell_check: s.spell_check,
+            edge_handle: s.edge_handle,
+            bottom_row: (row.emoji, row.voice, row.language, row.comma, row.period),
+            extra_keys: row.extra_keys.clone(),
+            repeat_rate: s.repeat_rate,
+            repeat_delay_ms: s.repeat_delay.map(|d| d.as_millis() as u64),
+            allow_screenshots: s.allow_screenshots,
+            morse_letter_gap_ms: Some(s.morse_letter_gap.as_millis() as u64),
+            remote_keyboard: Some(s.remote_keyboard.get_name().into()),
+            command_socket: s.command_socket,
+            ibus_engine: s.ibus_engine.clone(),
+            statistics: s.statistics,
+            swipes: (
+                get_command_name(s.gestures.left),
+                get_command_name(s.gestures.right),
+                get_command_name(s.gestures.up),
+                get_command_name(s.gestures.down),
+            ),
+            return_sends: s.return_sends,
+            announce_changes: Some(s.announce_changes),
+            sound_volume: Some(s.sound_volume),
+            visual_feedback: s.visual_feedback,
+        }
+    }
+}
+
+fn get_command_name(command: Option<gestures::Command>) -> Option<String> {
+    command.map(|command| command.get_name().into())
+}
+
+fn get_command(name: Option<String>) -> Option<gestures::Command> {
+    name.and_then(|name| gestures::Command::from_name(&name))
+}
+
+impl From<Settings> for settings::Settings {
+    fn from(s: Settings) -> Self {
+        let (emoji, voice, language, comma, period) = s.bottom_row;
+        let (left, right, up, down) = s.swipes;
+        settings::Settings {
+            theme: s.theme,
+            height_percent: s.height_percent,
+            feedback: s.feedback,
+            sound_volume: s.sound_volume.unwrap_or(100),
+            visual_feedback: s.visual_feedback,
+            hide_delay: Duration::from_millis(s.hide_delay_ms),
+            hide_with_hardware_keyboard: s.hide_with_hardware_keyboard,
+            hide_when_covered: s.hide_when_covered,
+            show_on_focus: s.show_on_focus,
+            scramble_pin: s.scramble_pin,
+            spell_check: s.spell_check,
+            edge_handle: s.edge_handle,
+            bottom_row: BottomRow {
+                emoji, voice, language, comma, period,
+                extra_keys: s.extra_keys,
+            },
+            repeat_rate: s.repeat_rate,
+            repeat_delay: s.repeat_delay_ms.map(Duration::from_millis),
+            allow_screenshots: s.allow_screenshots,
+            morse_letter_gap: s.morse_letter_gap_ms
+                .map(Duration::from_millis)
+                .unwrap_or(morse::DEFAULT_LETTER_GAP),
+            remote_keyboard: s.remote_keyboard
+                .and_then(|name| remote::Access::from_name(&name))
+                .unwrap_or_default(),
+            command_socket: s.command_socket,
+            ibus_engine: s.ibus_engine,
+            statistics: s.statistics,
+            return_sends: s.return_sends,
+            // Nothing was announced back then.
+            announce_changes: s.announce_changes.unwrap_or(false),
+            gestures: gestures::Mapping {
+                left: get_command(left),
+                right: get_command(right),
+                up: get_command(up),
+                down: get_command(down),
+            },
+        }
+    }
+}
+
+/// Mirrors `state::Event`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    InputMethodActive { purpose: u32, hint: u32 },
+    InputMethodInactive { since: Micros },
+    ForceVisible,
+    ForceHidden,
+    PhysicalKeyboard { present: bool },
+    Sensor { covered: bool },
+    OutputAltered { id: u64, output: Output },
+    OutputRemoved { id: u64 },
+    LayoutChoice(persist::SystemLayout),
+    OverlayChanged(persist::Layout),
+    AppRequestedVisibility { show: bool },
+    SettingsChanged(Settings),
+    RepeatInfo { rate: u32, delay_ms: u64 },
+    PreviewLayout(String),
+    ReloadLayout,
+    SymbolQuery(Option<String>),
+    EditSymbolQuery,
+    ComposeSequence(Option<Vec<String>>),
+    StartCleaning,
+    ViewChanged(String),
+    KeyPressed,
+    /// x, y, width, height
+    RowBounds(Vec<(f64, f64, f64, f64)>),
+    Debug { enabled: bool },
+    TimeoutReached { at: Micros },
+}
+
+impl Event {
+    fn new(event: &state::Event, start: Instant) -> Self {
+        match event.clone() {
+            state::Event::InputMethod(InputMethod::Active(details)) => Event::InputMethodActive {
+                purpose: details.purpose as u32,
+                hint: details.hint.bits(),
+            },
+            state::Event::InputMethod(InputMethod::InactiveSince(since)) => Event::InputMethodInactive {
+                since: to_micros(start, since),
+            },
+            state::Event::Visibility(visibility::Event::ForceVisible) => Event::ForceVisible,
+            state::Event::Visibility(visibility::Event::ForceHidden) => Event::ForceHidden,
+            state::Event::PhysicalKeyboard(presence) => Event::PhysicalKeyboard {
+                present: match presence {
+                    Presence::Present => true,
+                    Presence::Missing => false,
+                },
+            },
+            state::Event::Sensor(sensor) => Event::Sensor {
+                covered: sensor == sensors::Event::Covered,
+            },
+            state::Event::Output(outputs::Event { output, change }) => match change {
+                outputs::ChangeType::Altered(state) => Event::OutputAltered {
+                    id: output.0.get_id(),
+                    output: (&state).into(),
+                },
+                outputs::ChangeType::Removed => Event::OutputRemoved {
+                    id: output.0.get_id(),
+                },
+            },
+            state::Event::LayoutChoice(choice) => Event::LayoutChoice(choice.into()),
+            state::Event::OverlayChanged(layout) => Event::OverlayChanged(layout.into()),
+            state::Event::AppRequestedVisibility(request) => Event::AppRequestedVisibility {
+                show: request == visibility::AppRequest::Show,
+            },
+            state::Event::SettingsChanged(settings) => Event::SettingsChanged((&settings).into()),
+            state::Event::RepeatInfo(info) => Event::RepeatInfo {
+                rate: info.rate,
+                delay_ms: info.delay.as_millis() as u64,
+            },
+            state::Event::PreviewLayout(yaml) => Event::PreviewLayout(yaml),
+            state::Event::ReloadLayout => Event::ReloadLayout,
+            state::Event::SymbolQuery(query) => Event::SymbolQuery(query),
+            state::Event::EditSymbolQuery => Event::EditSymbolQuery,
+            state::Event::ComposeSequence(sequence) => Event::ComposeSequence(sequence),
+            state::Event::StartCleaning => Event::StartCleaning,
+            state::Event::ViewChanged(view) => Event::ViewChanged(view),
+            state::Event::KeyPressed => Event::KeyPressed,
+            state::Event::RowBounds(rows) => Event::RowBounds(
+                rows.iter()
+                    .map(|b| (b.x, b.y, b.width, b.height))
+                    .collect()
+            ),
+            state::Event::Debug(debug::Event::Enable) => Event::Debug { enabled: true },
+            state::Event::Debug(debug::Event::Disable) => Event::Debug { enabled: false },
+            state::Event::TimeoutReached(when) => Event::TimeoutReached {
+                at: to_micros(start, when),
+            },
+        }
+    }
+
+    fn into_event(self, start: Instant) -> Result<state::Event, Error> {
+        Ok(match self {
+            Event::InputMethodActive { purpose, hint } => state::Event::InputMethod(
+                InputMethod::Active(InputMethodDetails {
+                    purpose: ContentPurpose::try_from(purpose)
+                        .map_err(|_| Error::Format(format!("Unknown purpose {}", purpose)))?,
+                    hint: ContentHint::from_bits(hint)
+                        .ok_or_else(|| Error::Format(format!("Unknown hints {}", hint)))?,
+                })
+            ),
+            Event::InputMethodInactive { since } => state::Event::InputMethod(
+                InputMethod::InactiveSince(from_micros(start, since))
+            ),
+            Event::ForceVisible => state::Event::Visibility(visibility::Event::ForceVisible),
+            Event::ForceHidden => state::Event::Visibility(visibility::Event::ForceHidden),
+            Event::PhysicalKeyboard { present } => state::Event::PhysicalKeyboard(
+                if present { Presence::Present } else { Presence::Missing }
+            ),
+            Event::Sensor { covered } => state::Event::Sensor(covered.into()),
+            Event::OutputAltered { id, output } => state::Event::Output(outputs::Event {
+                output: OutputId(outputs::c::WlOutput::from_id(id)),
+                change: outputs::ChangeType::Altered(output.into_state()?),
+            }),
+            Event::OutputRemoved { id } => state::Event::Output(outputs::Event {
+                output: OutputId(outputs::c::WlOutput::from_id(id)),
+                change: outputs::ChangeType::Removed,
+            }),
+            Event::LayoutChoice(choice) => state::Event::LayoutChoice(choice.into()),
+            Event::OverlayChanged(layout) => state::Event::OverlayChanged(layout.into()),
+            Event::AppRequestedVisibility { show } => state::Event::AppRequestedVisibility(
+                if show { visibility::AppRequest::Show }
+                else { visibility::AppRequest::Hide }
+            ),
+            Event::SettingsChanged(settings) => state::Event::SettingsChanged(settings.into()),
+            Event::RepeatInfo { rate, delay_ms } => state::Event::RepeatInfo(RepeatInfo {
+                rate,
+                delay: Duration::from_millis(delay_ms),
+            }),
+            Event::PreviewLayout(yaml) => state::Event::PreviewLayout(yaml),
+            Event::ReloadLayout => state::Event::ReloadLayout,
+            Event::SymbolQuery(query) => state::Event::SymbolQuery(query),
+            Event::EditSymbolQuery => state::Event::EditSymbolQuery,
+            Event::ComposeSequence(sequence) => state::Event::ComposeSequence(sequence),
+            Event::StartCleaning => state::Event::StartCleaning,
+            Event::ViewChanged(view) => state::Event::ViewChanged(view),
+            Event::KeyPressed => state::Event::KeyPressed,
+            Event::RowBounds(rows) => state::Event::RowBounds(
+                rows.into_iter()
+                    .map(|(x, y, width, height)| Bounds { x, y, width, height })
+                    .collect()
+            ),
+            Event::Debug { enabled } => state::Event::Debug(
+                if enabled { debug::Event::Enable } else { debug::Event::Disable }
+            ),
+            Event::TimeoutReached { at } => state::Event::TimeoutReached(from_micros(start, at)),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    /// When the event got applied
+    at: Micros,
+    event: Event,
+}
+
+/// Separates documents explicitly,
+/// because not all versions of serde_yaml do that.
+fn write_document<W: Write, T: Serialize>(out: &mut W, value: &T) -> Result<(), Error> {
+    let data = serde_yaml::to_string(value)?;
+    let data = data.trim_start_matches("---\n");
+    write!(out, "---\n{}\n", data.trim_end())?;
+    Ok(())
+}
+
+/// Separators are never indented,
+/// even if there's YAML inside a string.
+fn split_documents(data: &str) -> Vec<String> {
+    let mut documents = Vec::new();
+    for line in data.lines() {
+        if line == "---" {
+            documents.push(String::new());
+        } else if let Some(document) = documents.last_mut() {
+            document.push_str(line);
+            document.push('\n');
+        }
+    }
+    documents
+}
+
+/// Appends events to the trace as they come.
+pub struct Recorder<W: Write = fs::File> {
+    out: W,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, state: &Application, now: Instant)
+        -> Result<Recorder, Error>
+    {
+        Recorder::new(fs::File::create(path)?, state, now)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    fn new(mut out: W, state: &Application, now: Instant) -> Result<Self, Error> {
+        write_document(&mut out, &Header::new(state))?;
+        Ok(Recorder { out, start: now })
+    }
+
+    /// Events applied together share the time.
+    pub fn record(&mut self, events: &[state::Event], now: Instant) {
+        for event in events {
+            let entry = Entry {
+                at: to_micros(self.start, now),
+                event: Event::new(event, self.start),
+            };
+            write_document(&mut self.out, &entry)
+                .or_print(logging::Problem::Warning, "Can't record event");
+        }
+        self.out.flush()
+            .or_print(logging::Problem::Warning, "Can't write trace");
+    }
+}
+
+#[derive(Debug)]
+pub struct Trace {
+    header: Header,
+    entries: Vec<Entry>,
+}
+
+impl Trace {
+    fn parse(data: &str) -> Result<Self, Error> {
+        let mut documents = split_documents(data).into_iter();
+        let header = documents.next()
+            .ok_or_else(|| Error::Format("Empty trace".into()))?;
+        let version: Version = serde_yaml::from_str(&header)?;
+        if version.version != VERSION {
+            return Err(Error::Version(version.version));
+        }
+        Ok(Trace {
+            header: serde_yaml::from_str(&header)?,
+            entries: documents
+                .map(|document| serde_yaml::from_str(&document))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Trace::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Applies the events in order as if they were happening since `start`,
+    /// and lets `on_step` see every resulting state.
+    pub fn replay<F>(self, start: Instant, mut on_step: F) -> Result<Application, Error>
+        where F: FnMut(Instant, &state::Event, &Application)
+    {
+        let state = self.header.apply_to(Application::new(start));
+        self.entries.into_iter().try_fold(state, |state, entry| {
+            let now = from_micros(start, entry.at);
+            let event = entry.event.into_event(start)?;
+            let state = state.apply_event(event.clone(), now);
+            on_step(now, &event, &state);
+            Ok(state)
+        })
+    }
+}
+
+/// Prints the events and how the panel changed.
+pub fn print_replay(path: &Path) -> Result<(), Error> {
+    let trace = Trace::load(path)?;
+    let start = Instant::now();
+    let mut last_panel: Option<animation::Outcome> = None;
+    trace.replay(start, |now, event, state| {
+        println!("{:?}: {:?}", now - start, event);
+        let panel = state.get_outcome(now).panel;
+        if last_panel.as_ref() != Some(&panel) {
+            println!("    panel: {:?}", panel);
+            last_panel = Some(panel);
+        }
+    })?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_and_replay() {
+        let start = Instant::now();
+        let events = vec![
+            state::Event::Output(outputs::Event {
+                output: OutputId(outputs::c::WlOutput::from_id(1)),
+                change: outputs::ChangeType::Altered(OutputState {
+                    current_mode: Some(outputs::Mode { width: 720, height: 1440 }),
+                    geometry: None,
+                    scale: 2,
+                    name: Some("DSI-1".into()),
+                    identity: None,
+                }),
+            }),
+            state::Event::SettingsChanged(settings::Settings {
+                hide_delay: Duration::from_secs(1),
+                ..settings::Settings::default()
+            }),
+            state::Event::InputMethod(InputMethod::Active(InputMethodDetails {
+                purpose: ContentPurpose::Email,
+                hint: ContentHint::SPELLCHECK,
+            })),
+            state::Event::InputMethod(InputMethod::InactiveSince(start)),
+        ];
+        let initial = Application {
+            layout_override: Some("de".into()),
+            ..Application::new(start)
+        };
+
+        let mut recorder = Recorder::new(Vec::new(), &initial, start).unwrap();
+        recorder.record(&events[..2], start);
+        recorder.record(&events[2..], start + Duration::from_millis(10));
+        let data = String::from_utf8(recorder.out).unwrap();
+
+        let direct = initial.clone()
+            .apply_events(events[..2].to_vec(), start)
+            .apply_events(events[2..].to_vec(), start + Duration::from_millis(10));
+
+        let mut steps = 0;
+        let replayed = Trace::parse(&data).unwrap()
+            .replay(start, |_, _, _| steps += 1)
+            .unwrap();
+        assert_eq!(steps, 4);
+        assert_eq!(replayed.layout_override, Some("de".into()));
+        assert_eq!(replayed.settings, direct.settings);
+
+        let now = start + Duration::from_millis(500);
+        let outcome = replayed.get_outcome(now).panel;
+        assert_eq!(outcome, direct.get_outcome(now).panel);
+        assert_matches!(outcome, animation::Outcome::Visible { .. });
+    }
+
+    #[test]
+    fn refuse_version() {
+        let data = "---\nversion: 0\n";
+        assert_matches!(Trace::parse(data), Err(Error::Version(0)));
+    }
+}
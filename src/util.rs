@@ -1,13 +1,14 @@
 /*! Assorted helpers */
 use std::collections::HashMap;
-use std::rc::Rc;
 
 use ::float_ord::FloatOrd;
+use serde::{ de, ser, Deserialize, Deserializer, Serialize, Serializer };
 
-use std::borrow::Borrow;
+use std::ffi::{ CStr, CString, NulError };
 use std::hash::{ Hash, Hasher };
 use std::iter::FromIterator;
-use std::ops::Mul;
+use std::ops::{ Deref, Mul };
+use std::sync::Arc;
 
 pub mod c {
     use super::*;
@@ -148,6 +149,140 @@ pub fn hash_map_map<K, V, F, K1, V1>(map: HashMap<K, V>, mut f: F)
     )
 }
 
+/// Serde helpers storing `CString`s as text rather than as bytes,
+/// so that stored layouts stay readable.
+pub mod cstring {
+    use serde::{ de, ser, Deserialize, Deserializer, Serialize, Serializer };
+    use std::ffi::CString;
+
+    pub fn serialize<S: Serializer>(value: &CString, serializer: S)
+        -> Result<S::Ok, S::Error>
+    {
+        value.to_str().map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+        -> Result<CString, D::Error>
+    {
+        CString::new(String::deserialize(deserializer)?)
+            .map_err(de::Error::custom)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(value: &Option<CString>, serializer: S)
+            -> Result<S::Ok, S::Error>
+        {
+            value.as_ref()
+                .map(|value| value.to_str())
+                .transpose()
+                .map_err(ser::Error::custom)?
+                .serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+            -> Result<Option<CString>, D::Error>
+        {
+            Option::<String>::deserialize(deserializer)?
+                .map(CString::new)
+                .transpose()
+                .map_err(de::Error::custom)
+        }
+    }
+
+    pub mod vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(value: &[CString], serializer: S)
+            -> Result<S::Ok, S::Error>
+        {
+            value.iter()
+                .map(|value| value.to_str())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(ser::Error::custom)?
+                .serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+            -> Result<Vec<CString>, D::Error>
+        {
+            Vec::<String>::deserialize(deserializer)?
+                .into_iter()
+                .map(CString::new)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+/// A C string shared between all its users.
+/// Layouts repeat the same names, labels and outlines over and over,
+/// so each distinct string gets stored once, see `Interner`.
+#[derive(Clone, Debug, Eq, PartialOrd, Ord)]
+pub struct Interned(Arc<CStr>);
+
+impl Interned {
+    pub fn new(s: &str) -> Result<Interned, NulError> {
+        CString::new(s).map(|s| Interned(s.into()))
+    }
+}
+
+impl PartialEq for Interned {
+    fn eq(&self, other: &Interned) -> bool {
+        // Strings from the same interner are equal only when they're the same
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl Deref for Interned {
+    type Target = CStr;
+    fn deref(&self) -> &CStr {
+        &self.0
+    }
+}
+
+/// Stored as text, like `cstring`
+impl Serialize for Interned {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_str().map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Interned {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Interned::new(&String::deserialize(deserializer)?)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Hands out the same `Interned` for equal strings.
+#[derive(Default)]
+pub struct Interner(HashMap<String, Interned>);
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    pub fn get(&mut self, s: &str) -> Result<Interned, NulError> {
+        if let Some(interned) = self.0.get(s) {
+            return Ok(interned.clone());
+        }
+        let interned = Interned::new(s)?;
+        self.0.insert(s.into(), interned.clone());
+        Ok(interned)
+    }
+}
+
 pub fn find_max_double<T, I, F>(iterator: I, get: F)
     -> f64
     where I: Iterator<Item=T>,
@@ -206,41 +341,6 @@ impl<U, T: Mul<U, Output=T>> Mul<Rational<U>> for Rational<T> {
     }
 }
 
-/// Compares pointers but not internal values of Rc
-pub struct Pointer<T>(pub Rc<T>);
-
-impl<T> Pointer<T> {
-    pub fn new(value: T) -> Self {
-        Pointer(Rc::new(value))
-    }
-}
-
-impl<T> Hash for Pointer<T> {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        (&*self.0 as *const T).hash(state);
-    }
-}
-
-impl<T> PartialEq for Pointer<T> {
-    fn eq(&self, other: &Pointer<T>) -> bool {
-        Rc::ptr_eq(&self.0, &other.0)
-    }
-}
-
-impl<T> Eq for Pointer<T> {}
-
-impl<T> Clone for Pointer<T> {
-    fn clone(&self) -> Self {
-        Pointer(self.0.clone())
-    }
-}
-
-impl<T> Borrow<Rc<T>> for Pointer<T> {
-    fn borrow(&self) -> &Rc<T> {
-        &self.0
-    }
-}
-
 pub trait WarningHandler {
     /// Handle a warning
     fn handle(&mut self, warning: &str);
@@ -273,17 +373,6 @@ pub fn cycle_count<T, I: Clone + Iterator<Item=T>>(iter: I)
 mod tests {
     use super::*;
 
-    use std::collections::HashSet;
-
-    #[test]
-    fn check_set() {
-        let mut s = HashSet::new();
-        let first = Rc::new(1u32);
-        s.insert(Pointer(first.clone()));
-        assert_eq!(s.insert(Pointer(Rc::new(2u32))), true);
-        assert_eq!(s.remove(&Pointer(first)), true);
-    }
-
     #[test]
     fn check_count() {
         assert_eq!(
@@ -291,4 +380,14 @@ mod tests {
             vec![(5, 0), (6, 0), (7, 0), (5, 1), (6, 1), (7, 1), (5, 2)]
         );
     }
+
+    #[test]
+    fn interned_once() {
+        let mut interner = Interner::new();
+        let a = interner.get("default").unwrap();
+        let b = interner.get("default").unwrap();
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, Interned::new("default").unwrap());
+        assert_ne!(a, interner.get("wide").unwrap());
+    }
 }
@@ -1,7 +1,9 @@
 /*! Managing the events belonging to virtual-keyboard interface. */
 
+use std::ffi::CString;
+
 use ::keyboard::{ Modifiers, PressType };
-use ::submission::Timestamp;
+use ::submission::{ KeyboardBackend, Timestamp };
 
 /// Standard xkb keycode
 type KeyCode = u32;
@@ -20,6 +22,10 @@ pub mod c {
         pub fn null() -> Self {
             Self(ptr::null())
         }
+
+        pub fn is_null(&self) -> bool {
+            self.0.is_null()
+        }
     }
 
     #[repr(C)]
@@ -69,13 +75,43 @@ pub mod c {
     }
 }
 
-/// Layout-independent backend. TODO: Have one instance per program or seat
-pub struct VirtualKeyboard(pub c::ZwpVirtualKeyboardV1);
+/// The Wayland virtual-keyboard backend.
+/// TODO: Have one instance per program or seat
+pub struct VirtualKeyboard {
+    vk: c::ZwpVirtualKeyboardV1,
+    /// The keymaps of the current layout, ready to be sent over
+    keymaps: Vec<c::KeyMap>,
+}
 
 impl VirtualKeyboard {
+    pub fn new(vk: c::ZwpVirtualKeyboardV1) -> Self {
+        VirtualKeyboard {
+            vk,
+            keymaps: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardBackend for VirtualKeyboard {
+    fn set_keymaps(&mut self, keymaps: &[CString]) {
+        self.keymaps = keymaps.iter()
+            .map(|keymap_str| c::KeyMap::from_cstr(keymap_str.as_c_str()))
+            .collect();
+    }
+
+    fn select_keymap(&mut self, idx: usize) {
+        let keymap = &self.keymaps[idx];
+        unsafe {
+            c::eek_virtual_keyboard_update_keymap(
+                self.vk,
+                keymap as *const c::KeyMap,
+            );
+        }
+    }
+
     // TODO: error out if keymap not set
-    pub fn switch(
-        &self,
+    fn switch(
+        &mut self,
         keycode: KeyCode,
         action: PressType,
         timestamp: Timestamp,
@@ -83,24 +119,15 @@ impl VirtualKeyboard {
         let keycode = keycode - 8;
         unsafe {
             c::eek_virtual_keyboard_v1_key(
-                self.0, timestamp.0, keycode, action.clone() as u32
+                self.vk, timestamp.0, keycode, action.clone() as u32
             );
         }
     }
     
-    pub fn set_modifiers_state(&self, modifiers: Modifiers) {
+    fn set_modifiers_state(&mut self, modifiers: Modifiers) {
         let modifiers = modifiers.bits() as u32;
         unsafe {
-            c::eek_virtual_keyboard_set_modifiers(self.0, modifiers);
-        }
-    }
-    
-    pub fn update_keymap(&self, keymap: &c::KeyMap) {
-        unsafe {
-            c::eek_virtual_keyboard_update_keymap(
-                self.0,
-                keymap as *const c::KeyMap,
-            );
+            c::eek_virtual_keyboard_set_modifiers(self.vk, modifiers);
         }
     }
 }
@@ -0,0 +1,143 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Entering text by speaking, with the help of a speech recognition service.
+ *
+ * The service is any program owning the `sm.puri.Speech1` bus name
+ * on the session bus, at the `/sm/puri/Speech1` path,
+ * with the `sm.puri.Speech1` interface:
+ *
+ * - method `Start()` begins listening,
+ * - method `Stop()` ends listening,
+ * - signal `Recognized(s text)` carries a piece of recognized text,
+ * - signal `Stopped()` comes after listening ended for any reason.
+ *
+ * A wrapper around a local recognizer like whisper.cpp is enough.
+ * D-Bus runs on a separate thread,
+ * and recognized text comes back to the main loop to get submitted.
+ */
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::rc::Rc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread;
+use glib::{ Continue, MainContext, PRIORITY_DEFAULT };
+use zbus::{ Connection, fdo };
+
+use crate::drawing;
+use crate::layout::c::EekGtkKeyboard;
+use crate::logging;
+use crate::submission::Submission;
+
+// Traits
+use crate::logging::Warn;
+
+
+const DESTINATION: &str = "sm.puri.Speech1";
+const PATH: &str = "/sm/puri/Speech1";
+const INTERFACE: &str = "sm.puri.Speech1";
+
+/// Whether a session is ongoing. Shown on the key.
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_listening() -> bool {
+    LISTENING.load(Ordering::Relaxed)
+}
+
+/// Sent from the D-Bus thread to the main loop
+enum Message {
+    Recognized(String),
+    Stopped,
+}
+
+type Error = Box<dyn std::error::Error>;
+
+fn call(connection: &Connection, method: &str) -> Result<(), Error> {
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(INTERFACE),
+        method,
+        &(),
+    )?;
+    Ok(())
+}
+
+/// Listens until the service stops.
+fn run_session(sender: &glib::Sender<Message>) -> Result<(), Error> {
+    let connection = Connection::new_session()?;
+    fdo::DBusProxy::new(&connection)?.add_match(
+        &format!("type='signal',interface='{}'", INTERFACE),
+    )?;
+    call(&connection, "Start")?;
+    loop {
+        let message = connection.receive_message()?;
+        let member = message.header()?.member()?.map(String::from);
+        match member.as_ref().map(String::as_str) {
+            Some("Recognized") => {
+                let text: String = message.body()?;
+                sender.send(Message::Recognized(text))?;
+            },
+            Some("Stopped") => return Ok(()),
+            _ => {},
+        }
+    }
+}
+
+fn stop() {
+    thread::spawn(|| {
+        Connection::new_session()
+            .map_err(Error::from)
+            .and_then(|connection| call(&connection, "Stop"))
+            .or_print(logging::Problem::Warning, "Can't stop speech recognition");
+    });
+}
+
+fn start(submission: Rc<RefCell<Submission>>, ui_keyboard: EekGtkKeyboard) {
+    let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+    receiver.attach(
+        Some(&MainContext::default()),
+        move |message| match message {
+            Message::Recognized(text) => {
+                let text = match CString::new(text) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        log_print!(logging::Level::Warning, "Bad recognized text: {}", e);
+                        return Continue(true);
+                    },
+                };
+                if submission.borrow_mut().commit_text(&text).is_err() {
+                    log_print!(
+                        logging::Level::Warning,
+                        "No text field to receive recognized text",
+                    );
+                }
+                Continue(true)
+            },
+            Message::Stopped => {
+                LISTENING.store(false, Ordering::Relaxed);
+                drawing::queue_redraw(ui_keyboard);
+                Continue(false)
+            },
+        },
+    );
+
+    LISTENING.store(true, Ordering::Relaxed);
+    thread::spawn(move || {
+        run_session(&sender)
+            .or_print(logging::Problem::Warning, "Speech recognition failed");
+        sender.send(Message::Stopped)
+            .or_print(logging::Problem::Bug, "Can't report end of speech recognition");
+    });
+}
+
+/// Starts listening, or stops if already listening.
+pub fn toggle(submission: Rc<RefCell<Submission>>, ui_keyboard: EekGtkKeyboard) {
+    if is_listening() {
+        stop();
+    } else {
+        start(submission, ui_keyboard);
+    }
+}
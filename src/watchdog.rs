@@ -0,0 +1,155 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Keeps squeekboard running when the compositor goes away.
+ *
+ * GTK terminates the process as soon as the Wayland connection is lost,
+ * so there's nothing left to recover from inside the process.
+ * Instead, a supervising process starts squeekboard as its child,
+ * and starts it again whenever the child dies unexpectedly,
+ * waiting longer after each quick failure.
+ * The user's choices come back thanks to the `persist` module.
+ *
+ * The supervisor doesn't touch GTK or Wayland at all,
+ * so it survives compositor restarts.
+ */
+
+use crate::logging;
+use std::env;
+use std::process;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+// Traits
+use crate::logging::Warn;
+
+
+/// Set in the environment of the supervised child.
+const SUPERVISED_ENV: &str = "SQUEEKBOARD_SUPERVISED";
+
+/// Asks the supervisor to start a fresh instance.
+const EXIT_RECONNECT: i32 = 75;
+
+/// How long to wait before starting the child again
+#[derive(Clone, Debug, PartialEq)]
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    const INITIAL: Duration = Duration::from_millis(500);
+    const MAX: Duration = Duration::from_secs(30);
+    /// After running for this long, the child is considered healthy,
+    /// and the next failure gets a quick restart again.
+    const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+
+    fn new() -> Self {
+        Backoff { delay: Self::INITIAL }
+    }
+
+    /// Returns the delay before the next start,
+    /// given how long the last child was running.
+    fn next(self, uptime: Duration) -> (Duration, Self) {
+        if uptime >= Self::HEALTHY_UPTIME {
+            (Self::INITIAL, Backoff { delay: Self::INITIAL * 2 })
+        } else {
+            let delay = self.delay;
+            (
+                delay,
+                Backoff { delay: std::cmp::min(delay * 2, Self::MAX) },
+            )
+        }
+    }
+}
+
+pub fn is_supervised() -> bool {
+    env::var_os(SUPERVISED_ENV).is_some()
+}
+
+/// Ends this instance, so that the supervisor starts a new one.
+/// Does nothing when not supervised.
+pub fn request_restart(reason: &str) {
+    if is_supervised() {
+        log_print!(logging::Level::Warning, "Restarting: {}", reason);
+        process::exit(EXIT_RECONNECT);
+    }
+}
+
+/// Runs the child over and over until it exits cleanly.
+/// Never returns.
+fn supervise() -> ! {
+    let exe = env::current_exe()
+        .or_print(logging::Problem::Error, "Can't find own executable");
+    let exe = match exe {
+        Some(exe) => exe,
+        None => process::exit(1),
+    };
+    let mut backoff = Backoff::new();
+    loop {
+        let start = Instant::now();
+        let status = process::Command::new(&exe)
+            .args(env::args_os().skip(1))
+            .env(SUPERVISED_ENV, "1")
+            .status()
+            .or_print(logging::Problem::Error, "Can't start squeekboard");
+
+        match status {
+            Some(status) if status.success() => process::exit(0),
+            Some(status) => log_print!(
+                logging::Level::Warning,
+                "Squeekboard quit unexpectedly ({})",
+                status,
+            ),
+            None => {},
+        }
+
+        let (delay, next) = backoff.next(start.elapsed());
+        backoff = next;
+        log_print!(logging::Level::Info, "Starting again in {:?}", delay);
+        thread::sleep(delay);
+    }
+}
+
+pub mod c {
+    use super::*;
+
+    /// Returns only inside the supervised child.
+    /// The original process stays behind as the supervisor.
+    #[no_mangle]
+    pub extern "C"
+    fn squeek_watchdog_supervise() {
+        if !is_supervised() {
+            supervise();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn grows_until_max() {
+        let mut backoff = Backoff::new();
+        let mut delays = Vec::new();
+        for _ in 0..10 {
+            let (delay, next) = backoff.next(Duration::from_secs(1));
+            delays.push(delay);
+            backoff = next;
+        }
+        assert_eq!(delays[0], Backoff::INITIAL);
+        assert_eq!(delays[1], Backoff::INITIAL * 2);
+        assert_eq!(delays[9], Backoff::MAX);
+    }
+
+    #[test]
+    fn resets_after_healthy_run() {
+        let mut backoff = Backoff::new();
+        for _ in 0..5 {
+            backoff = backoff.next(Duration::from_secs(1)).1;
+        }
+        let (delay, _) = backoff.next(Backoff::HEALTHY_UPTIME);
+        assert_eq!(delay, Backoff::INITIAL);
+    }
+}
@@ -6,6 +6,7 @@
 #include "wlr-layer-shell-unstable-v1-client-protocol.h"
 #include "virtual-keyboard-unstable-v1-client-protocol.h"
 #include "input-method-unstable-v2-client-protocol.h"
+#include "text-input-unstable-v3-client-protocol.h"
 
 #include "outputs.h"
 
@@ -14,6 +15,8 @@ struct squeek_wayland {
     struct zwlr_layer_shell_v1 *layer_shell;
     struct zwp_virtual_keyboard_manager_v1 *virtual_keyboard_manager;
     struct zwp_input_method_manager_v2 *input_method_manager;
+    // Only checked for presence
+    struct zwp_text_input_manager_v3 *text_input_manager;
     struct squeek_outputs *outputs;
     struct wl_seat *seat;
     // objects
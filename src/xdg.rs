@@ -41,7 +41,7 @@ fn is_absolute_path(path: OsString) -> Option<PathBuf> {
     }
 }
 
-fn home_dir() -> Option<PathBuf> {
+pub fn home_dir() -> Option<PathBuf> {
     return env::var_os("HOME")
         .and_then(|h| if h.is_empty() { None } else { Some(h) })
         .map(PathBuf::from);
@@ -61,3 +61,43 @@ pub fn data_path<P>(path: P) -> Option<PathBuf>
         dir.join(path.as_ref())
     })
 }
+
+fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .and_then(is_absolute_path)
+        .or_else(|| home_dir().map(|h| h.join(".config")))
+}
+
+/// Returns the path to the directory within the config dir
+pub fn config_path<P>(path: P) -> Option<PathBuf>
+    where P: AsRef<Path>
+{
+    config_dir().map(|dir| {
+        dir.join(path.as_ref())
+    })
+}
+
+fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .and_then(is_absolute_path)
+        .or_else(|| home_dir().map(|h| h.join(".local/state")))
+}
+
+/// Returns the path to the directory within the state dir
+pub fn state_path<P>(path: P) -> Option<PathBuf>
+    where P: AsRef<Path>
+{
+    state_dir().map(|dir| {
+        dir.join(path.as_ref())
+    })
+}
+
+/// Returns the path within the runtime dir.
+/// There's no fallback, because the dir must be private to the user.
+pub fn runtime_path<P>(path: P) -> Option<PathBuf>
+    where P: AsRef<Path>
+{
+    env::var_os("XDG_RUNTIME_DIR")
+        .and_then(is_absolute_path)
+        .map(|dir| dir.join(path.as_ref()))
+}
@@ -0,0 +1,141 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/* Typing into X11 applications using the XTEST extension.
+ * Only used when the compositor offers no virtual keyboard. */
+
+#include <inttypes.h>
+#include <stdlib.h>
+#include <X11/Xlib.h>
+#include <X11/extensions/XTest.h>
+
+#include "xtest.h"
+
+/// Keysyms missing from the X keymap get keycodes out of this many.
+#define SPARE_COUNT 8
+
+/// A keycode with no symbols of its own,
+/// lent to one keysym at a time.
+struct spare {
+    KeyCode keycode;
+    /// NoSymbol while nothing was put there yet
+    KeySym keysym;
+    /// Held keys keep their keycode until released
+    int pressed;
+};
+
+struct squeek_xtest {
+    Display *display;
+    struct spare spares[SPARE_COUNT];
+    int spare_count;
+};
+
+/// Takes the highest keycodes which produce nothing,
+/// so that keysyms don't have to replace each other on one keycode.
+static void find_spares(struct squeek_xtest *xtest) {
+    int min_keycode, max_keycode, syms_per_code;
+    XDisplayKeycodes(xtest->display, &min_keycode, &max_keycode);
+    KeySym *syms = XGetKeyboardMapping(xtest->display, min_keycode,
+                                       max_keycode - min_keycode + 1,
+                                       &syms_per_code);
+    if (!syms) {
+        return;
+    }
+    for (int keycode = max_keycode;
+         keycode >= min_keycode && xtest->spare_count < SPARE_COUNT;
+         keycode--) {
+        KeySym *code_syms = &syms[(keycode - min_keycode) * syms_per_code];
+        int used = 0;
+        for (int i = 0; i < syms_per_code; i++) {
+            if (code_syms[i] != NoSymbol) {
+                used = 1;
+                break;
+            }
+        }
+        if (!used) {
+            xtest->spares[xtest->spare_count++] = (struct spare) {
+                .keycode = keycode,
+                .keysym = NoSymbol,
+                .pressed = 0,
+            };
+        }
+    }
+    XFree(syms);
+}
+
+struct squeek_xtest *squeek_xtest_open(void) {
+    Display *display = XOpenDisplay(NULL);
+    if (!display) {
+        return NULL;
+    }
+    int event_base, error_base, major, minor;
+    if (!XTestQueryExtension(display, &event_base, &error_base, &major, &minor)) {
+        XCloseDisplay(display);
+        return NULL;
+    }
+    struct squeek_xtest *xtest = calloc(1, sizeof(*xtest));
+    xtest->display = display;
+    find_spares(xtest);
+    return xtest;
+}
+
+/// Returns the spare lent to the keysym, or one to lend it.
+/// Spares which were never used go first,
+/// and then the ones which were released.
+static struct spare *find_spare(struct squeek_xtest *xtest, KeySym keysym) {
+    struct spare *unused = NULL;
+    struct spare *released = NULL;
+    for (int i = 0; i < xtest->spare_count; i++) {
+        struct spare *spare = &xtest->spares[i];
+        if (spare->keysym == keysym) {
+            return spare;
+        }
+        if (spare->pressed) {
+            continue;
+        }
+        if (spare->keysym == NoSymbol) {
+            unused = unused ? unused : spare;
+        } else {
+            released = released ? released : spare;
+        }
+    }
+    return unused ? unused : released;
+}
+
+/// Returns a keycode which produces the keysym,
+/// putting the keysym on a spare keycode if none does yet.
+/// Returns 0 when all spares are held.
+static KeyCode get_keycode(struct squeek_xtest *xtest, KeySym keysym) {
+    struct spare *spare = find_spare(xtest, keysym);
+    if (spare && spare->keysym == keysym) {
+        return spare->keycode;
+    }
+    KeyCode keycode = XKeysymToKeycode(xtest->display, keysym);
+    if (keycode != 0 || !spare) {
+        return keycode;
+    }
+    KeySym syms[] = { keysym, keysym };
+    XChangeKeyboardMapping(xtest->display, spare->keycode, 2, syms, 1);
+    XSync(xtest->display, False);
+    spare->keysym = keysym;
+    return spare->keycode;
+}
+
+/// Returns 0 if there was no keycode to send.
+uint32_t squeek_xtest_key(struct squeek_xtest *xtest, uint32_t keysym, uint32_t pressed) {
+    KeyCode keycode = get_keycode(xtest, keysym);
+    if (keycode == 0) {
+        return 0;
+    }
+    // The symbol stays on a released spare until it's lent again,
+    // in case the application looks at it late.
+    for (int i = 0; i < xtest->spare_count; i++) {
+        if (xtest->spares[i].keycode == keycode) {
+            xtest->spares[i].pressed = pressed ? 1 : 0;
+        }
+    }
+    XTestFakeKeyEvent(xtest->display, keycode, pressed ? True : False, CurrentTime);
+    XFlush(xtest->display);
+    return 1;
+}
@@ -0,0 +1,12 @@
+#ifndef XTEST_H
+#define XTEST_H
+
+#include <inttypes.h>
+#include <X11/Xlib.h>
+
+struct squeek_xtest;
+
+struct squeek_xtest *squeek_xtest_open(void);
+uint32_t squeek_xtest_key(struct squeek_xtest *xtest, uint32_t keysym, uint32_t pressed);
+
+#endif // XTEST_H
@@ -0,0 +1,199 @@
+/* Copyright (C) 2022 Purism SPC
+ * SPDX-License-Identifier: GPL-3.0+
+ */
+
+/*! Typing into X11 applications through the XTEST extension.
+ *
+ * Used when the compositor doesn't offer virtual-keyboard,
+ * e.g. in a nested X session, or with XWayland-only apps.
+ *
+ * X servers don't accept custom keymaps the way virtual-keyboard does,
+ * so keycodes are translated to keysyms using the layout's own keymap,
+ * and the X server finds a keycode for each keysym.
+ * Keysyms missing from the X keymap get lent one of a few unused keycodes,
+ * each to one keysym at a time, until it's released.
+ */
+
+use std::ffi::CString;
+use xkbcommon::xkb;
+
+use crate::keyboard::{ Modifiers, PressType };
+use crate::logging;
+use crate::submission::{ KeyboardBackend, Timestamp };
+
+// Traits
+use crate::logging::Warn;
+
+
+mod c {
+    use std::os::raw::c_void;
+
+    /// struct squeek_xtest*
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    pub struct Connection(*const c_void);
+
+    impl Connection {
+        pub fn is_null(&self) -> bool {
+            self.0.is_null()
+        }
+    }
+
+    extern "C" {
+        pub fn squeek_xtest_open() -> Connection;
+        /// Returns 0 when no keycode was free for the keysym
+        pub fn squeek_xtest_key(xtest: Connection, keysym: u32, pressed: u32) -> u32;
+    }
+}
+
+/// Keysyms pressed to emulate modifiers
+const MODIFIER_KEYSYMS: &[(Modifiers, u32)] = &[
+    (Modifiers::SHIFT, xkb::KEY_Shift_L),
+    (Modifiers::CONTROL, xkb::KEY_Control_L),
+    (Modifiers::MOD1, xkb::KEY_Alt_L),
+    (Modifiers::MOD4, xkb::KEY_Super_L),
+    (Modifiers::MOD5, xkb::KEY_ISO_Level3_Shift),
+];
+
+/// Translates keycodes to keysyms using the layout's keymaps
+struct Keymaps {
+    /// Keymaps which failed stay as None, to keep the indices
+    states: Vec<Option<xkb::State>>,
+    current: usize,
+}
+
+impl Keymaps {
+    fn new(keymaps: &[CString]) -> Self {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let states = keymaps.iter()
+            .map(|keymap| xkb::Keymap::new_from_string(
+                &context,
+                keymap.to_string_lossy().into_owned(),
+                xkb::KEYMAP_FORMAT_TEXT_V1,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            ).or_print(
+                logging::Problem::Bug,
+                "Generated keymap not understood",
+            ))
+            .map(|keymap| keymap.map(|keymap| xkb::State::new(&keymap)))
+            .collect();
+        Keymaps { states, current: 0 }
+    }
+
+    fn get_keysym(&self, keycode: u32) -> Option<u32> {
+        let state = match self.states.get(self.current)? {
+            Some(state) => state,
+            None => {
+                log_print!(
+                    logging::Level::Bug,
+                    "Keymap {} unavailable, dropping key",
+                    self.current,
+                );
+                return None;
+            },
+        };
+        let sym = state.key_get_one_sym(keycode);
+        if sym == xkb::KEY_NoSymbol {
+            None
+        } else {
+            Some(sym)
+        }
+    }
+}
+
+pub struct XTest {
+    connection: c::Connection,
+    keymaps: Keymaps,
+    modifiers: Modifiers,
+}
+
+impl XTest {
+    /// Returns None when there's no X server, or it lacks XTEST.
+    pub fn new() -> Option<Self> {
+        let connection = unsafe { c::squeek_xtest_open() };
+        if connection.is_null() {
+            None
+        } else {
+            Some(XTest {
+                connection,
+                keymaps: Keymaps::new(&[]),
+                modifiers: Modifiers::empty(),
+            })
+        }
+    }
+
+    fn send(&self, keysym: u32, press: PressType) {
+        let sent = unsafe {
+            c::squeek_xtest_key(self.connection, keysym, press as u32)
+        };
+        if sent == 0 {
+            log_print!(
+                logging::Level::Warning,
+                "No keycode free for keysym {}, too many keys held",
+                xkb::keysym_get_name(keysym),
+            );
+        }
+    }
+}
+
+impl KeyboardBackend for XTest {
+    fn set_keymaps(&mut self, keymaps: &[CString]) {
+        self.keymaps = Keymaps::new(keymaps);
+    }
+
+    fn select_keymap(&mut self, idx: usize) {
+        self.keymaps.current = idx;
+    }
+
+    fn switch(
+        &mut self,
+        keycode: u32,
+        action: PressType,
+        _timestamp: Timestamp,
+    ) {
+        match self.keymaps.get_keysym(keycode) {
+            Some(keysym) => self.send(keysym, action),
+            None => log_print!(
+                logging::Level::Bug,
+                "No keysym for keycode {} in the current keymap",
+                keycode,
+            ),
+        }
+    }
+
+    fn set_modifiers_state(&mut self, modifiers: Modifiers) {
+        for &(modifier, keysym) in MODIFIER_KEYSYMS {
+            let was = self.modifiers.contains(modifier);
+            let is = modifiers.contains(modifier);
+            if was != is {
+                self.send(
+                    keysym,
+                    if is { PressType::Pressed } else { PressType::Released },
+                );
+            }
+        }
+        self.modifiers = modifiers;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keyboard::{ generate_keymaps, KeyCode };
+
+    #[test]
+    fn keysym_from_second_keymap() {
+        let keymaps = generate_keymaps(hashmap!(
+            "a".into() => KeyCode { keymap_idx: 0, code: 9 },
+            "c".into() => KeyCode { keymap_idx: 1, code: 9 },
+        )).unwrap();
+        let keymaps: Vec<CString> = keymaps.into_iter()
+            .map(|k| CString::new(k).unwrap())
+            .collect();
+        let mut keymaps = Keymaps::new(&keymaps);
+        assert_eq!(keymaps.get_keysym(9), Some(xkb::KEY_a));
+        keymaps.current = 1;
+        assert_eq!(keymaps.get_keysym(9), Some(xkb::KEY_c));
+        assert_eq!(keymaps.get_keysym(10), None);
+    }
+}